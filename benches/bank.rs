@@ -9,6 +9,7 @@ use criterion::{Bencher, Criterion};
 use rayon::prelude::*;
 use solana::bank::*;
 use solana::hash::hash;
+use solana::ledger::next_entries;
 use solana::mint::Mint;
 use solana::signature::{Keypair, KeypairUtil};
 use solana::transaction::Transaction;
@@ -46,16 +47,72 @@ fn bench_process_transaction(bencher: &mut Bencher) {
             transactions.clone()
         },
         |transactions| {
-            let results = bank.process_transactions(transactions);
+            let results = bank.process_transactions(&transactions);
             assert!(results.iter().all(Result::is_ok));
         },
     )
 }
 
+fn bench_process_entries(bencher: &mut Bencher) {
+    let mint = Mint::new(100_000_000);
+
+    // Replay a ledger of payments from the mint to unrelated parties.
+    let transactions: Vec<_> = (0..4096)
+        .into_par_iter()
+        .map(|_| Transaction::new(&mint.keypair(), Keypair::new().pubkey(), 1, mint.last_id()))
+        .collect();
+    let entries = next_entries(&mint.last_id(), 1, transactions);
+
+    bencher.iter_with_setup(
+        || (Bank::new(&mint), entries.clone()),
+        |(bank, entries)| {
+            assert!(bank.process_entries(entries).is_ok());
+        },
+    )
+}
+
+fn bench_account_lookup(bencher: &mut Bencher, num_accounts: u64) {
+    let bank = Bank::default();
+    bank.add_synthetic_accounts(num_accounts, 1);
+    let pubkeys: Vec<_> = (0..1024)
+        .map(|i| Bank::synthetic_pubkey(i * 7_919 % num_accounts))
+        .collect();
+
+    bencher.iter(|| {
+        for pubkey in &pubkeys {
+            assert_eq!(bank.get_balance(pubkey), 1);
+        }
+    })
+}
+
+fn bench_accounts_snapshot(bencher: &mut Bencher) {
+    let bank = Bank::default();
+    bank.add_synthetic_accounts(100_000, 1);
+
+    bencher.iter(|| {
+        let (_, snapshot) = bank.accounts_snapshot();
+        assert_eq!(snapshot.len(), 100_000);
+    })
+}
+
 fn bench(criterion: &mut Criterion) {
     criterion.bench_function("bench_process_transaction", |bencher| {
         bench_process_transaction(bencher);
     });
+    criterion.bench_function("bench_process_entries", |bencher| {
+        bench_process_entries(bencher);
+    });
+    for num_accounts in &[10_000, 1_000_000] {
+        criterion.bench_function(
+            &format!("bench_account_lookup_{}", num_accounts),
+            move |bencher| {
+                bench_account_lookup(bencher, *num_accounts);
+            },
+        );
+    }
+    criterion.bench_function("bench_accounts_snapshot", |bencher| {
+        bench_accounts_snapshot(bencher);
+    });
 }
 
 criterion_group!(
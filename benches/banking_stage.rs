@@ -8,9 +8,11 @@ use criterion::{Bencher, Criterion};
 use rayon::prelude::*;
 use solana::bank::Bank;
 use solana::banking_stage::BankingStage;
+use solana::latency::StageLatency;
 use solana::mint::Mint;
 use solana::packet::{to_packets_chunked, PacketRecycler};
-use solana::record_stage::Signal;
+use solana::record_stage::{CommitLock, Signal};
+use solana::replay_stats::ReplayStats;
 use solana::signature::{Keypair, KeypairUtil};
 use solana::transaction::Transaction;
 use std::iter;
@@ -83,7 +85,7 @@ fn check_txs(receiver: &Receiver<Signal>, ref_tx_count: usize) {
     let mut total = 0;
     loop {
         let signal = receiver.recv().unwrap();
-        if let Signal::Transactions(transactions) = signal {
+        if let Signal::Transactions(transactions, _) = signal {
             total += transactions.len();
             if total >= ref_tx_count {
                 break;
@@ -121,6 +123,8 @@ fn bench_banking_stage_multi_accounts(bencher: &mut Bencher) {
     let (verified_sender, verified_receiver) = channel();
     let (signal_sender, signal_receiver) = channel();
     let packet_recycler = PacketRecycler::default();
+    let mut latency = StageLatency::new("banking_stage-latency");
+    let mut replay_stats = ReplayStats::default();
 
     let setup_transactions: Vec<_> = (0..num_src_accounts)
         .map(|i| {
@@ -147,8 +151,15 @@ fn bench_banking_stage_multi_accounts(bencher: &mut Bencher) {
 
         let verified_setup_len = verified_setup.len();
         verified_sender.send(verified_setup).unwrap();
-        BankingStage::process_packets(&bank, &verified_receiver, &signal_sender, &packet_recycler)
-            .unwrap();
+        BankingStage::process_packets(
+            &bank,
+            &verified_receiver,
+            &signal_sender,
+            &CommitLock::unrecorded(),
+            &packet_recycler,
+            &mut latency,
+            &mut replay_stats,
+        ).unwrap();
 
         check_txs(&signal_receiver, num_src_accounts);
 
@@ -162,8 +173,15 @@ fn bench_banking_stage_multi_accounts(bencher: &mut Bencher) {
 
         let verified_len = verified.len();
         verified_sender.send(verified).unwrap();
-        BankingStage::process_packets(&bank, &verified_receiver, &signal_sender, &packet_recycler)
-            .unwrap();
+        BankingStage::process_packets(
+            &bank,
+            &verified_receiver,
+            &signal_sender,
+            &CommitLock::unrecorded(),
+            &packet_recycler,
+            &mut latency,
+            &mut replay_stats,
+        ).unwrap();
 
         check_txs(&signal_receiver, tx);
     });
@@ -193,6 +211,8 @@ fn bench_banking_stage_single_from(bencher: &mut Bencher) {
     let (verified_sender, verified_receiver) = channel();
     let (signal_sender, signal_receiver) = channel();
     let packet_recycler = PacketRecycler::default();
+    let mut latency = StageLatency::new("banking_stage-latency");
+    let mut replay_stats = ReplayStats::default();
 
     bencher.iter(move || {
         let bank = Arc::new(Bank::new(&mint));
@@ -205,8 +225,15 @@ fn bench_banking_stage_single_from(bencher: &mut Bencher) {
             .collect();
         let verified_len = verified.len();
         verified_sender.send(verified).unwrap();
-        BankingStage::process_packets(&bank, &verified_receiver, &signal_sender, &packet_recycler)
-            .unwrap();
+        BankingStage::process_packets(
+            &bank,
+            &verified_receiver,
+            &signal_sender,
+            &CommitLock::unrecorded(),
+            &packet_recycler,
+            &mut latency,
+            &mut replay_stats,
+        ).unwrap();
 
         check_txs(&signal_receiver, tx);
     });
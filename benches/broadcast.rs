@@ -0,0 +1,64 @@
+#[macro_use]
+extern crate criterion;
+extern crate solana;
+
+use criterion::{Bencher, Criterion};
+use solana::packet::{BlobRecycler, BLOB_DATA_SIZE};
+use solana::sharded_sender::ShardedSender;
+use solana::signature::{Keypair, KeypairUtil};
+use std::net::UdpSocket;
+
+const NUM_PEERS: usize = 16;
+const NUM_BLOBS: usize = 1024;
+
+/// Broadcast a batch of full blobs to local peers that never read them, so only
+/// the sending side is measured.
+fn bench_broadcast_egress(bencher: &mut Bencher, num_shards: usize) {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let (sender, _thread_hdls) = ShardedSender::new(sock, num_shards).unwrap();
+    let peers: Vec<_> = (0..NUM_PEERS)
+        .map(|_| {
+            let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+            (sock.local_addr().unwrap(), Keypair::new().pubkey(), sock)
+        })
+        .collect();
+
+    let recycler = BlobRecycler::default();
+    let blobs: Vec<_> = (0..NUM_BLOBS)
+        .map(|_| {
+            let blob = recycler.allocate();
+            blob.write().unwrap().meta.size = BLOB_DATA_SIZE;
+            blob
+        })
+        .collect();
+
+    bencher.iter(|| {
+        let orders = blobs
+            .iter()
+            .enumerate()
+            .map(|(i, blob)| {
+                let (addr, id, _) = &peers[i % NUM_PEERS];
+                (blob.clone(), *addr, *id)
+            })
+            .collect();
+        for result in sender.send(orders) {
+            result.unwrap();
+        }
+    });
+}
+
+fn bench(criterion: &mut Criterion) {
+    criterion.bench_function("bench_broadcast_egress_1_shard", |bencher| {
+        bench_broadcast_egress(bencher, 1);
+    });
+    criterion.bench_function("bench_broadcast_egress_4_shards", |bencher| {
+        bench_broadcast_egress(bencher, 4);
+    });
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().sample_size(2);
+    targets = bench
+);
+criterion_main!(benches);
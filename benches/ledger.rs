@@ -3,7 +3,7 @@ extern crate criterion;
 extern crate solana;
 
 use criterion::{Bencher, Criterion};
-use solana::hash::{hash, Hash};
+use solana::hash::{hash, Hash, PohHash};
 use solana::ledger::{next_entries, reconstruct_entries_from_blobs, Block};
 use solana::packet::BlobRecycler;
 use solana::signature::{Keypair, KeypairUtil};
@@ -26,10 +26,26 @@ fn bench_block_to_blobs_to_block(bencher: &mut Bencher) {
     });
 }
 
+fn bench_poh_hash(bencher: &mut Bencher, poh_hash: PohHash) {
+    let hasher = poh_hash.hasher();
+    let mut id = Hash::default();
+    bencher.iter(|| {
+        for _ in 0..1000 {
+            id = hasher.hash(&id.as_ref());
+        }
+    });
+}
+
 fn bench(criterion: &mut Criterion) {
     criterion.bench_function("bench_block_to_blobs_to_block", |bencher| {
         bench_block_to_blobs_to_block(bencher);
     });
+    criterion.bench_function("bench_poh_hash_sha256", |bencher| {
+        bench_poh_hash(bencher, PohHash::Sha256);
+    });
+    criterion.bench_function("bench_poh_hash_sha256_simd", |bencher| {
+        bench_poh_hash(bencher, PohHash::Sha256Simd);
+    });
 }
 
 criterion_group!(
@@ -0,0 +1,42 @@
+//! The `account` module defines the on-chain state of a single account. It is
+//! shared by the bank and the thin client, so it carries no server-side dependencies.
+
+use bincode::{deserialize, serialized_size};
+use hash::Hash;
+use signature::Pubkey;
+
+/// An Account with userdata that is stored on chain
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Account {
+    /// tokens in the account
+    pub tokens: i64,
+    /// user data
+    /// A transaction can write to its userdata
+    pub userdata: Vec<u8>,
+    /// The key allowed to assign the account to another owner, such as the
+    /// program that interprets its userdata. `Pubkey::default()` until the
+    /// account is first assigned, meaning the account's own key.
+    pub owner: Pubkey,
+}
+
+impl Account {
+    /// The key that has to sign to assign the account at `pubkey`.
+    pub fn authority(&self, pubkey: &Pubkey) -> Pubkey {
+        if self.owner == Pubkey::default() {
+            *pubkey
+        } else {
+            self.owner
+        }
+    }
+
+    /// The durable nonce an `AdvanceNonce` instruction stored in the account's
+    /// userdata, if that's all the userdata holds.
+    pub fn nonce(&self) -> Option<Hash> {
+        let nonce: Hash = deserialize(&self.userdata).ok()?;
+        if serialized_size(&nonce).ok()? == self.userdata.len() as u64 {
+            Some(nonce)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! The `accounts_db` module keeps accounts on disk, so a bank needn't hold every
+//! account in memory and a restarting node can pick up from the accounts it
+//! saved rather than replay the whole ledger. Account records are appended to a
+//! data file, which is memory mapped for reading, and an in-memory index points
+//! at the latest record of each account. A record that replaces or removes an
+//! account leaves the old one behind until the file is compacted.
+//!
+//! Appended records only count once they're committed, together with a state
+//! blob of the caller's, by replacing the state file in one step. Records past
+//! the committed length, say from a crash partway through a save, are dropped
+//! when the db is opened again.
+
+use account::Account;
+use bincode::{self, deserialize, deserialize_from, serialize, serialize_into};
+use memmap::Mmap;
+use signature::Pubkey;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+
+const STATE_FILE: &str = "state";
+
+/// Compact the data file once old records take up more of it than live ones,
+/// and it's at least this big.
+pub const MIN_COMPACT_BYTES: u64 = 64 * 1024 * 1024;
+
+const SIZEOF_U64: u64 = size_of::<u64>() as u64;
+
+fn err_bincode_to_io(e: Box<bincode::ErrorKind>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// What the state file records.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DbState {
+    /// The data file the committed records are in
+    data_file: String,
+    /// The length of the committed records
+    len: u64,
+    /// The caller's state as of the commit
+    state: Option<Vec<u8>>,
+}
+
+pub struct AccountsDb {
+    dir: PathBuf,
+    db_state: DbState,
+    data: File,
+    /// The data file, or `None` while it's empty, which can't be mapped.
+    map: Option<Mmap>,
+    /// The length of the records in the data file, committed or not
+    len: u64,
+    /// The offset and length of each account's latest record
+    index: HashMap<Pubkey, (u64, u64)>,
+    /// The length of the records `index` points at
+    live: u64,
+}
+
+impl AccountsDb {
+    /// Open the db in the directory at `path`, creating it if there isn't one.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let dir = PathBuf::from(path);
+        fs::create_dir_all(&dir)?;
+        let db_state = match File::open(dir.join(STATE_FILE)) {
+            Ok(file) => deserialize_from(file).map_err(err_bincode_to_io)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => DbState {
+                data_file: "accounts.0.dat".to_string(),
+                ..DbState::default()
+            },
+            Err(e) => return Err(e),
+        };
+
+        // files left behind by a compaction that didn't commit
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if name.ends_with(".dat") && name != db_state.data_file {
+                fs::remove_file(dir.join(&*name))?;
+            }
+        }
+
+        let data = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(dir.join(&db_state.data_file))?;
+        data.set_len(db_state.len)?;
+        let mut db = AccountsDb {
+            dir,
+            len: db_state.len,
+            db_state,
+            data,
+            map: None,
+            index: HashMap::new(),
+            live: 0,
+        };
+        db.remap()?;
+        db.build_index()?;
+        Ok(db)
+    }
+
+    fn remap(&mut self) -> io::Result<()> {
+        self.map = if self.len == 0 {
+            None
+        } else {
+            // the file is only ever appended to, or replaced, while it's mapped
+            Some(unsafe { Mmap::map(&self.data)? })
+        };
+        Ok(())
+    }
+
+    /// The account in the record at `offset` that's `len` long, all told.
+    fn record(&self, offset: u64, len: u64) -> io::Result<(Pubkey, Option<Account>)> {
+        let map = self.map.as_ref().expect("mapped accounts");
+        let start = (offset + SIZEOF_U64) as usize;
+        deserialize(&map[start..(offset + len) as usize]).map_err(err_bincode_to_io)
+    }
+
+    /// The length, all told, of the record at `offset`.
+    fn record_len(&self, offset: u64) -> io::Result<u64> {
+        let map = self.map.as_ref().expect("mapped accounts");
+        if offset + SIZEOF_U64 > self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated accounts record",
+            ));
+        }
+        let at = offset as usize;
+        let len: u64 =
+            deserialize(&map[at..at + SIZEOF_U64 as usize]).map_err(err_bincode_to_io)?;
+        if offset + SIZEOF_U64 + len > self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated accounts record",
+            ));
+        }
+        Ok(SIZEOF_U64 + len)
+    }
+
+    fn build_index(&mut self) -> io::Result<()> {
+        let mut offset = 0;
+        while offset < self.len {
+            let len = self.record_len(offset)?;
+            match self.record(offset, len)? {
+                (pubkey, Some(_)) => self.index.insert(pubkey, (offset, len)),
+                (pubkey, None) => self.index.remove(&pubkey),
+            };
+            offset += len;
+        }
+        self.live = self.index.values().map(|&(_, len)| len).sum();
+        Ok(())
+    }
+
+    /// The latest account stored at `pubkey`, committed or not.
+    pub fn load(&self, pubkey: &Pubkey) -> Option<Account> {
+        self.index.get(pubkey).map(|&(offset, len)| {
+            self.record(offset, len)
+                .expect("reading an indexed accounts record")
+                .1
+                .expect("indexed account")
+        })
+    }
+
+    /// Whether an account is stored at `pubkey`.
+    pub fn contains(&self, pubkey: &Pubkey) -> bool {
+        self.index.contains_key(pubkey)
+    }
+
+    /// The pubkeys of the accounts stored.
+    pub fn pubkeys(&self) -> impl Iterator<Item = &Pubkey> {
+        self.index.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Append `accounts`, where `None` removes the account at a pubkey. They
+    /// won't survive a restart until they're committed.
+    pub fn store<I>(&mut self, accounts: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (Pubkey, Option<Account>)>,
+    {
+        self.data.seek(SeekFrom::Start(self.len))?;
+        let mut updates = vec![];
+        {
+            let mut writer = BufWriter::new(&self.data);
+            let mut offset = self.len;
+            for (pubkey, account) in accounts {
+                if account.is_none() && !self.index.contains_key(&pubkey) {
+                    continue;
+                }
+                let record = serialize(&(pubkey, &account)).map_err(err_bincode_to_io)?;
+                let len = record.len() as u64;
+                writer.write_all(&serialize(&len).map_err(err_bincode_to_io)?)?;
+                writer.write_all(&record)?;
+                updates.push((pubkey, account.map(|_| (offset, SIZEOF_U64 + len))));
+                offset += SIZEOF_U64 + len;
+            }
+            writer.flush()?;
+            self.len = offset;
+        }
+        for (pubkey, location) in updates {
+            let old = match location {
+                Some(location) => {
+                    self.live += location.1;
+                    self.index.insert(pubkey, location)
+                }
+                None => self.index.remove(&pubkey),
+            };
+            if let Some((_, len)) = old {
+                self.live -= len;
+            }
+        }
+        self.remap()
+    }
+
+    /// The state committed along with the accounts, if any has been.
+    pub fn state(&self) -> Option<&[u8]> {
+        self.db_state.state.as_ref().map(|state| &state[..])
+    }
+
+    /// Make the stored accounts, and `state` along with them, survive a restart.
+    pub fn commit(&mut self, state: Vec<u8>) -> io::Result<()> {
+        self.data.sync_all()?;
+        let db_state = DbState {
+            data_file: self.db_state.data_file.clone(),
+            len: self.len,
+            state: Some(state),
+        };
+        self.write_state(db_state)?;
+        if self.len >= MIN_COMPACT_BYTES && self.live < self.len - self.live {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Replace the state file in one step, so a crash can't leave it half written.
+    fn write_state(&mut self, db_state: DbState) -> io::Result<()> {
+        let path = self.dir.join(STATE_FILE);
+        let tmp_path = self.dir.join(format!("{}.tmp", STATE_FILE));
+        {
+            let file = File::create(&tmp_path)?;
+            serialize_into(&file, &db_state).map_err(err_bincode_to_io)?;
+            file.sync_all()?;
+        }
+        fs::rename(tmp_path, path)?;
+        self.db_state = db_state;
+        Ok(())
+    }
+
+    /// Copy the live records to a new data file, dropping the ones replaced
+    /// or removed since, and commit it. The last committed state carries over.
+    pub fn compact(&mut self) -> io::Result<()> {
+        // data files are named accounts.<generation>.dat
+        let generation: u64 = self
+            .db_state
+            .data_file
+            .split('.')
+            .nth(1)
+            .and_then(|generation| generation.parse().ok())
+            .unwrap_or(0);
+        let data_file = format!("accounts.{}.dat", generation + 1);
+        let data = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.dir.join(&data_file))?;
+
+        let mut locations: Vec<_> = self.index.iter().map(|(k, v)| (*k, *v)).collect();
+        locations.sort_by_key(|&(_, (offset, _))| offset);
+        let mut index = HashMap::new();
+        let mut len = 0;
+        {
+            let map = self.map.as_ref();
+            let mut writer = BufWriter::new(&data);
+            for (pubkey, (offset, record_len)) in locations {
+                let map = map.expect("mapped accounts");
+                writer.write_all(&map[offset as usize..(offset + record_len) as usize])?;
+                index.insert(pubkey, (len, record_len));
+                len += record_len;
+            }
+            writer.flush()?;
+        }
+        data.sync_all()?;
+
+        let old_data_file = self.db_state.data_file.clone();
+        let db_state = DbState {
+            data_file,
+            len,
+            state: self.db_state.state.clone(),
+        };
+        self.write_state(db_state)?;
+        self.data = data;
+        self.len = len;
+        self.live = len;
+        self.index = index;
+        self.remap()?;
+        fs::remove_file(self.dir.join(old_data_file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use signature::{Keypair, KeypairUtil};
+    use std::env;
+
+    fn tmp_accounts_path(name: &str) -> String {
+        let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
+        let keypair = Keypair::new();
+        format!("{}/tmp-accounts-{}-{}", out_dir, name, keypair.pubkey())
+    }
+
+    fn account(tokens: i64) -> Account {
+        Account {
+            tokens,
+            userdata: vec![1, 2, 3],
+            owner: Pubkey::default(),
+        }
+    }
+
+    #[test]
+    fn test_store_load() {
+        let path = tmp_accounts_path("test_store_load");
+        let alice = Keypair::new().pubkey();
+        let bob = Keypair::new().pubkey();
+        let mut db = AccountsDb::open(&path).unwrap();
+        assert!(db.is_empty());
+        assert_eq!(db.load(&alice), None);
+
+        db.store(vec![(alice, Some(account(1))), (bob, Some(account(2)))])
+            .unwrap();
+        db.store(vec![(alice, Some(account(3))), (bob, None)])
+            .unwrap();
+        assert_eq!(db.load(&alice), Some(account(3)));
+        assert_eq!(db.load(&bob), None);
+        assert_eq!(db.pubkeys().collect::<Vec<_>>(), vec![&alice]);
+
+        // removing an account that was never stored writes nothing
+        let len = db.len;
+        db.store(vec![(bob, None)]).unwrap();
+        assert_eq!(db.len, len);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_commit_reopen() {
+        let path = tmp_accounts_path("test_commit_reopen");
+        let alice = Keypair::new().pubkey();
+        let bob = Keypair::new().pubkey();
+        {
+            let mut db = AccountsDb::open(&path).unwrap();
+            assert_eq!(db.state(), None);
+            db.store(vec![(alice, Some(account(1))), (bob, Some(account(2)))])
+                .unwrap();
+            db.commit(vec![7]).unwrap();
+
+            // stored but not committed
+            db.store(vec![(alice, Some(account(5))), (bob, None)])
+                .unwrap();
+        }
+        let db = AccountsDb::open(&path).unwrap();
+        assert_eq!(db.state(), Some(&[7u8][..]));
+        assert_eq!(db.load(&alice), Some(account(1)));
+        assert_eq!(db.load(&bob), Some(account(2)));
+        assert_eq!(db.len(), 2);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_compact() {
+        let path = tmp_accounts_path("test_compact");
+        let pubkeys: Vec<_> = (0..10).map(|_| Keypair::new().pubkey()).collect();
+        let mut db = AccountsDb::open(&path).unwrap();
+        for tokens in 0..10 {
+            db.store(
+                pubkeys
+                    .iter()
+                    .map(|pubkey| (*pubkey, Some(account(tokens)))),
+            )
+            .unwrap();
+        }
+        db.store(vec![(pubkeys[0], None)]).unwrap();
+        db.commit(vec![1]).unwrap();
+        let len = db.len;
+
+        db.compact().unwrap();
+        assert!(db.len < len / 9);
+        assert_eq!(db.live, db.len);
+        assert_eq!(db.load(&pubkeys[0]), None);
+        assert_eq!(db.load(&pubkeys[1]), Some(account(9)));
+
+        let db = AccountsDb::open(&path).unwrap();
+        assert_eq!(db.state(), Some(&[1u8][..]));
+        assert_eq!(db.len(), 9);
+        assert_eq!(db.load(&pubkeys[9]), Some(account(9)));
+        let files: Vec<_> = fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".dat"))
+            .collect();
+        assert_eq!(files, vec!["accounts.1.dat".to_string()]);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+//! The `accounts_hash` module keeps a Merkle root over the balance of every
+//! account in the bank. Nodes that processed the same entries agree on it, so
+//! comparing roots shows when their account state diverged even though their
+//! entry ids match. It's updated as balances change, rather than rehashing
+//! every account each time it's asked for.
+
+use bank::MAX_ENTRY_IDS;
+use bincode::serialize;
+use hash::{hash, Hash};
+use signature::Pubkey;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// Accounts are spread over `1 << BUCKET_BITS` buckets by the leading bits of
+/// their pubkey, so a change only rehashes its own bucket and the path from it
+/// to the root.
+pub const BUCKET_BITS: usize = 12;
+
+const NUM_BUCKETS: usize = 1 << BUCKET_BITS;
+
+/// The hash of one account's leaf, committing to its pubkey and balance.
+pub fn leaf_hash(pubkey: &Pubkey, tokens: i64) -> Hash {
+    let mut leaf = pubkey.as_ref().to_vec();
+    leaf.extend_from_slice(&serialize(&tokens).expect("serialize tokens"));
+    hash(&leaf)
+}
+
+/// The parent of two nodes. Empty subtrees hash to `Hash::default()`, and so
+/// does a node with two of them, so an empty tree needs no hashing at all.
+fn parent_hash(left: &Hash, right: &Hash) -> Hash {
+    if *left == Hash::default() && *right == Hash::default() {
+        return Hash::default();
+    }
+    let mut node = left.as_ref().to_vec();
+    node.extend_from_slice(right.as_ref());
+    hash(&node)
+}
+
+/// The Merkle root of `hashes`, pairing them up level by level. An odd one
+/// out moves up a level unhashed.
+pub fn merkle_root(mut hashes: Vec<Hash>) -> Hash {
+    if hashes.is_empty() {
+        return Hash::default();
+    }
+    while hashes.len() > 1 {
+        hashes = hashes
+            .chunks(2)
+            .map(|pair| {
+                if pair.len() == 2 {
+                    parent_hash(&pair[0], &pair[1])
+                } else {
+                    pair[0]
+                }
+            })
+            .collect();
+    }
+    hashes[0]
+}
+
+fn bucket_index(pubkey: &Pubkey) -> usize {
+    let bytes = pubkey.as_ref();
+    ((bytes[0] as usize) << 8 | bytes[1] as usize) >> (16 - BUCKET_BITS)
+}
+
+/// The balances the root is over, and the hashes of the tree above them.
+pub struct AccountsHash {
+    /// Each bucket's balances, in pubkey order.
+    buckets: Vec<BTreeMap<Pubkey, i64>>,
+    /// A binary tree over the bucket roots, stored as a heap: the root is at
+    /// 1, the children of node `i` at `2 * i` and `2 * i + 1`, and the root of
+    /// bucket `b` at `NUM_BUCKETS + b`.
+    nodes: Vec<Hash>,
+    /// Buckets changed since the root was last computed.
+    dirty_buckets: HashSet<usize>,
+    /// Accounts whose balance may have changed since the root was last
+    /// computed, which the bank has to look up again.
+    stale: HashSet<Pubkey>,
+    /// Whether every balance has to be looked up again, for instance after the
+    /// bank's accounts were replaced wholesale.
+    rebuild: bool,
+    /// The root as of each recent entry id that was recorded, and the order
+    /// they were recorded in, oldest first.
+    recorded: HashMap<Hash, Hash>,
+    record_order: VecDeque<Hash>,
+}
+
+impl Default for AccountsHash {
+    fn default() -> Self {
+        AccountsHash {
+            buckets: vec![BTreeMap::new(); NUM_BUCKETS],
+            nodes: vec![Hash::default(); 2 * NUM_BUCKETS],
+            dirty_buckets: HashSet::new(),
+            stale: HashSet::new(),
+            rebuild: false,
+            recorded: HashMap::new(),
+            record_order: VecDeque::new(),
+        }
+    }
+}
+
+impl AccountsHash {
+    /// Note that the balance at `pubkey` may have changed.
+    pub fn mark(&mut self, pubkey: &Pubkey) {
+        if !self.rebuild {
+            self.stale.insert(*pubkey);
+        }
+    }
+
+    /// Note that any balance may have changed.
+    pub fn mark_all(&mut self) {
+        self.rebuild = true;
+        self.stale.clear();
+    }
+
+    /// Whether `mark_all` was called since the balances were last cleared.
+    pub fn needs_rebuild(&self) -> bool {
+        self.rebuild
+    }
+
+    /// Forget every balance, to have them all set again.
+    pub fn clear(&mut self) {
+        for (index, bucket) in self.buckets.iter_mut().enumerate() {
+            if !bucket.is_empty() {
+                bucket.clear();
+                self.dirty_buckets.insert(index);
+            }
+        }
+        self.stale.clear();
+        self.rebuild = false;
+    }
+
+    /// Take the accounts marked since they were last taken.
+    pub fn take_stale(&mut self) -> Vec<Pubkey> {
+        self.stale.drain().collect()
+    }
+
+    /// Set the balance at `pubkey`, or drop it if there's no account there.
+    pub fn update(&mut self, pubkey: &Pubkey, tokens: Option<i64>) {
+        let index = bucket_index(pubkey);
+        let bucket = &mut self.buckets[index];
+        let changed = match tokens {
+            Some(tokens) => bucket.insert(*pubkey, tokens) != Some(tokens),
+            None => bucket.remove(pubkey).is_some(),
+        };
+        if changed {
+            self.dirty_buckets.insert(index);
+        }
+    }
+
+    /// The root over the balances set so far. Only the changed buckets and
+    /// the paths above them are hashed again.
+    pub fn root(&mut self) -> Hash {
+        let mut dirty_nodes: HashSet<usize> = HashSet::new();
+        for index in self.dirty_buckets.drain() {
+            let leaves = self.buckets[index]
+                .iter()
+                .map(|(pubkey, tokens)| leaf_hash(pubkey, *tokens))
+                .collect();
+            self.nodes[NUM_BUCKETS + index] = merkle_root(leaves);
+            dirty_nodes.insert((NUM_BUCKETS + index) / 2);
+        }
+        while !dirty_nodes.is_empty() {
+            let mut parents = HashSet::new();
+            for node in dirty_nodes {
+                self.nodes[node] = parent_hash(&self.nodes[2 * node], &self.nodes[2 * node + 1]);
+                if node > 1 {
+                    parents.insert(node / 2);
+                }
+            }
+            dirty_nodes = parents;
+        }
+        self.nodes[1]
+    }
+
+    /// Remember `root` as the root as of entry id `last_id`. Only the
+    /// `MAX_ENTRY_IDS` most recently recorded are kept.
+    pub fn record(&mut self, last_id: &Hash, root: Hash) {
+        if self.recorded.insert(*last_id, root).is_none() {
+            self.record_order.push_back(*last_id);
+        }
+        while self.record_order.len() > MAX_ENTRY_IDS {
+            let oldest = self.record_order.pop_front().unwrap();
+            self.recorded.remove(&oldest);
+        }
+    }
+
+    /// The root recorded as of entry id `last_id`, if there is one.
+    pub fn recorded_root(&self, last_id: &Hash) -> Option<Hash> {
+        self.recorded.get(last_id).cloned()
+    }
+
+    /// Drop the root recorded as of `last_id` if it was the last one
+    /// recorded, as when the entry id is rolled back.
+    pub fn forget(&mut self, last_id: &Hash) {
+        if self.record_order.back() == Some(last_id) {
+            self.record_order.pop_back();
+            self.recorded.remove(last_id);
+        }
+    }
+
+    /// Keep only the roots recorded as of entry ids `keep` accepts.
+    pub fn retain_recorded<F>(&mut self, keep: F)
+    where
+        F: Fn(&Hash) -> bool,
+    {
+        self.record_order.retain(|last_id| keep(last_id));
+        self.recorded.retain(|last_id, _| keep(last_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use signature::{Keypair, KeypairUtil};
+
+    /// The root over `balances` computed from scratch.
+    fn full_root(balances: &[(Pubkey, i64)]) -> Hash {
+        let mut buckets = vec![BTreeMap::new(); NUM_BUCKETS];
+        for (pubkey, tokens) in balances {
+            buckets[bucket_index(pubkey)].insert(*pubkey, *tokens);
+        }
+        let mut level: Vec<Hash> = buckets
+            .iter()
+            .map(|bucket| {
+                merkle_root(
+                    bucket
+                        .iter()
+                        .map(|(pubkey, tokens)| leaf_hash(pubkey, *tokens))
+                        .collect(),
+                )
+            })
+            .collect();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| parent_hash(&pair[0], &pair[1]))
+                .collect();
+        }
+        level[0]
+    }
+
+    #[test]
+    fn test_empty_root() {
+        let mut accounts_hash = AccountsHash::default();
+        assert_eq!(accounts_hash.root(), Hash::default());
+        assert_eq!(merkle_root(vec![]), Hash::default());
+    }
+
+    #[test]
+    fn test_incremental_root() {
+        let pubkeys: Vec<_> = (0..10).map(|_| Keypair::new().pubkey()).collect();
+        let mut accounts_hash = AccountsHash::default();
+        let mut balances = vec![];
+        for (i, pubkey) in pubkeys.iter().enumerate() {
+            accounts_hash.update(pubkey, Some(i as i64));
+            balances.push((*pubkey, i as i64));
+        }
+        let root = accounts_hash.root();
+        assert_eq!(root, full_root(&balances));
+
+        // changing a balance changes the root
+        accounts_hash.update(&pubkeys[3], Some(100));
+        balances[3].1 = 100;
+        let changed = accounts_hash.root();
+        assert_ne!(changed, root);
+        assert_eq!(changed, full_root(&balances));
+
+        // and changing it back restores it
+        accounts_hash.update(&pubkeys[3], Some(3));
+        assert_eq!(accounts_hash.root(), root);
+
+        accounts_hash.update(&pubkeys[0], None);
+        balances.remove(0);
+        assert_eq!(accounts_hash.root(), full_root(&balances));
+    }
+
+    #[test]
+    fn test_order_independent() {
+        let pubkeys: Vec<_> = (0..5).map(|_| Keypair::new().pubkey()).collect();
+        let mut forward = AccountsHash::default();
+        let mut backward = AccountsHash::default();
+        for pubkey in &pubkeys {
+            forward.update(pubkey, Some(1));
+        }
+        for pubkey in pubkeys.iter().rev() {
+            backward.update(pubkey, Some(1));
+        }
+        assert_eq!(forward.root(), backward.root());
+    }
+
+    #[test]
+    fn test_mark_and_clear() {
+        let pubkey = Keypair::new().pubkey();
+        let mut accounts_hash = AccountsHash::default();
+        accounts_hash.mark(&pubkey);
+        assert_eq!(accounts_hash.take_stale(), vec![pubkey]);
+        assert!(accounts_hash.take_stale().is_empty());
+
+        accounts_hash.update(&pubkey, Some(1));
+        accounts_hash.mark_all();
+        accounts_hash.mark(&pubkey);
+        assert!(accounts_hash.needs_rebuild());
+        assert!(accounts_hash.take_stale().is_empty());
+        accounts_hash.clear();
+        assert!(!accounts_hash.needs_rebuild());
+        assert_eq!(accounts_hash.root(), Hash::default());
+    }
+
+    #[test]
+    fn test_record_and_forget() {
+        let mut accounts_hash = AccountsHash::default();
+        let id0 = hash(b"0");
+        let id1 = hash(b"1");
+        accounts_hash.record(&id0, hash(b"root0"));
+        accounts_hash.record(&id1, hash(b"root1"));
+        assert_eq!(accounts_hash.recorded_root(&id1), Some(hash(b"root1")));
+
+        // only the last one recorded can be forgotten
+        accounts_hash.forget(&id0);
+        assert_eq!(accounts_hash.recorded_root(&id0), Some(hash(b"root0")));
+        accounts_hash.forget(&id1);
+        assert_eq!(accounts_hash.recorded_root(&id1), None);
+
+        accounts_hash.retain_recorded(|last_id| *last_id != id0);
+        assert_eq!(accounts_hash.recorded_root(&id0), None);
+
+        for i in 0..=MAX_ENTRY_IDS {
+            accounts_hash.record(&hash(&serialize(&i).unwrap()), Hash::default());
+        }
+        assert_eq!(
+            accounts_hash.recorded_root(&hash(&serialize(&0).unwrap())),
+            None
+        );
+        assert!(accounts_hash
+            .recorded_root(&hash(&serialize(&1).unwrap()))
+            .is_some());
+    }
+}
@@ -3,25 +3,38 @@
 //! on behalf of the caller, and a low-level API for when they have
 //! already been signed and verified.
 
+use account::Account;
+use accounts_db::AccountsDb;
+use accounts_hash::AccountsHash;
+use bincode::{self, deserialize, deserialize_from, serialize, serialize_into};
+use block_cost::SlotCost;
 use chrono::prelude::*;
 use counter::Counter;
-use entry::Entry;
-use hash::Hash;
+use entry::{Entry, PohDomain};
+use feature::{Feature, FeatureStatus, Features};
+use hash::{hash, Hash, PohHash};
 use itertools::Itertools;
 use ledger::Block;
 use log::Level;
 use mint::Mint;
+use native_program::NativeProgram;
 use payment_plan::{Payment, PaymentPlan, Witness};
+use rayon::prelude::*;
+use rent;
 use signature::{Keypair, Pubkey, Signature};
 use std;
 use std::collections::hash_map::Entry::Occupied;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
 use std::result;
+use std::slice;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::RwLock;
-use std::time::Instant;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use timing::{duration_as_us, timestamp};
-use transaction::{Instruction, Plan, Transaction};
+use transaction::{Instruction, Plan, Transaction, Vote};
+use voting;
 use window::WINDOW_SIZE;
 
 /// The number of most recent `last_id` values that the bank will track the signatures
@@ -34,8 +47,33 @@ pub const MAX_ENTRY_IDS: usize = 1024 * 16;
 
 pub const VERIFY_BLOCK_SIZE: usize = 16;
 
+/// The number of most recently banked transaction fees kept for fee estimates.
+pub const MAX_RECENT_FEES: usize = 1024;
+
+/// The number of most recently written slots whose costs are kept.
+pub const MAX_RECENT_SLOT_COSTS: usize = 64;
+
+/// The yearly growth of the token supply, in percent. The bank never creates
+/// tokens after the genesis block.
+pub const INFLATION_PERCENT: u64 = 0;
+
+/// The share of each transaction fee, in percent, that's destroyed rather than
+/// paid out. No node collects fees, so all of each fee is burned.
+pub const FEE_BURN_PERCENT: u64 = 100;
+
+/// The most userdata an account may hold, in bytes.
+pub const MAX_ACCOUNT_DATA_LEN: usize = 10 * 1024;
+
+/// The most a single transaction may grow an account's userdata by, in bytes,
+/// so that one transaction can't take much of a node's memory.
+pub const MAX_DATA_GROWTH_PER_TRANSACTION: usize = 1024;
+
+/// The number of entries a node writes to its ledger between saves of its
+/// accounts, which keeps the accounts held in memory to those changed since.
+pub const ACCOUNTS_SAVE_INTERVAL: u64 = 4096;
+
 /// Reasons a transaction might be rejected.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum BankError {
     /// Attempt to debit from `Pubkey`, but no found no record of a prior credit.
     AccountNotFound(Pubkey),
@@ -58,50 +96,417 @@ pub enum BankError {
     /// tokens.
     NegativeTokens,
 
+    /// The transaction's memo is longer than `MAX_MEMO_LEN`.
+    MemoTooLong,
+
+    /// The transaction tried to assign the account at `Pubkey` without being
+    /// signed by its owner.
+    NotOwner(Pubkey),
+
+    /// The transaction tried to assign the account at `Pubkey` away from its own
+    /// key while it still holds userdata. Only an owner other than the account's
+    /// own key may hand over an account's userdata.
+    AccountDataNotEmpty(Pubkey),
+
+    /// The transaction voted for the identity `Pubkey` without being signed by
+    /// the node that identity authorized to vote for it.
+    UnauthorizedVoter(Pubkey),
+
     /// Proof of History verification failed.
     LedgerVerificationFailed,
+
+    /// The checkpoint was never taken, or was already rolled back or confirmed.
+    CheckpointNotFound(CheckpointId),
+
+    /// The transaction would leave the account at `Pubkey` holding more than
+    /// `MAX_ACCOUNT_DATA_LEN` bytes of userdata.
+    AccountDataTooLarge(Pubkey),
+
+    /// The transaction would grow the userdata of the account at `Pubkey` by
+    /// more than `MAX_DATA_GROWTH_PER_TRANSACTION` bytes.
+    AllocationTooLarge(Pubkey),
+
+    /// The account at `Pubkey` holds userdata other than a durable nonce, or the
+    /// transaction would overwrite its nonce with the signer's userdata.
+    InvalidNonceAccount(Pubkey),
+
+    /// A transaction earlier in the batch locked the account at `Pubkey`, so
+    /// this one was deferred to a later batch.
+    AccountInUse(Pubkey),
+
+    /// The transaction tried to activate `Feature` without being signed by the
+    /// mint's key.
+    UnauthorizedFeatureActivation(Feature),
+
+    /// The transaction relies on `Feature`, which the cluster hasn't activated.
+    FeatureNotActive(Feature),
+
+    /// The transaction tried to cancel the contract `Signature` declared, but no
+    /// plan of it is waiting on a witness, or the signer isn't its cancel
+    /// authority.
+    NotCancelable(Signature),
+
+    /// The transaction tried to write past the end of the userdata allocated to
+    /// the account at `Pubkey`.
+    DataOutOfBounds(Pubkey),
+
+    /// The transaction has an instruction of the program with ID `Pubkey`,
+    /// which the bank has no handler for.
+    UnknownProgram(Pubkey),
+
+    /// The transaction declared the account at `Pubkey` read-only, but would
+    /// change it.
+    ReadOnlyAccountWritten(Pubkey),
 }
 
 pub type Result<T> = result::Result<T, BankError>;
-/// An Account with userdata that is stored on chain
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
-pub struct Account {
-    /// tokens in the account
+
+/// The status of every signature the bank has processed, `Ok` or the
+/// `BankError` it failed with, keyed by the `last_id` it was signed against
+/// and stamped with when that `last_id` was registered. A `last_id`'s
+/// statuses are evicted along with it once `MAX_ENTRY_IDS` newer ones have
+/// been registered.
+pub type StatusCache = HashMap<Hash, (HashMap<Signature, Result<()>>, u64)>;
+
+/// Runs one of a transaction's instructions for the program it's registered
+/// under, once the transaction's debits are made, returning the payments it
+/// made. Any account it changes must be journaled first.
+pub type ProgramHandler =
+    fn(&Bank, &Transaction, &Instruction, &mut HashMap<Pubkey, Account>) -> Vec<Payment>;
+
+/// How much a committed transaction changed the balance of `pubkey`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BalanceDelta {
+    pub pubkey: Pubkey,
     pub tokens: i64,
-    /// user data
-    /// A transaction can write to its userdata
-    pub userdata: Vec<u8>,
+}
+
+/// Told about the transactions the bank processes, such as to push changes to
+/// clients that subscribed to them or to keep an index of them. The bank's
+/// locks are held during each call, so an observer must not call back into the
+/// bank, and should return quickly.
+pub trait BankObserver: Send + Sync {
+    /// Called with the result of each transaction in `txs`, and with a lookup
+    /// of the accounts as the batch left them, the saved ones included.
+    fn transactions_processed(
+        &self,
+        _txs: &[Transaction],
+        _results: &[Result<()>],
+        _accounts: &Fn(&Pubkey) -> Option<Account>,
+    ) {
+    }
+
+    /// Called with each transaction the bank commits, in order, and the
+    /// balances it changed. Tokens a transaction pays into a contract still
+    /// pending show up as a debit only, and are credited by the transaction
+    /// that completes the contract.
+    fn transaction_committed(&self, _tx: &Transaction, _deltas: &[BalanceDelta]) {}
+
+    /// Called with the ids of entries as the bank registers them, in order,
+    /// and the tick height after the last of them.
+    fn entries_registered(&self, _ids: &[Hash], _tick_height: u64) {}
 }
 
 /// The state of all accounts and contracts after processing its entries.
 pub struct Bank {
-    /// A map of account public keys to the balance in that account.
+    /// A map of account public keys to the balance in that account. With an
+    /// accounts db, only the accounts changed or looked up since it was last
+    /// saved to are kept here, and the rest are read from it.
     accounts: RwLock<HashMap<Pubkey, Account>>,
 
     /// A map of smart contract transaction signatures to what remains of its payment
     /// plan. Each transaction that targets the plan should cause it to be reduced.
     /// Once it cannot be reduced, final payments are made and it is discarded.
-    pending: RwLock<HashMap<Signature, Plan>>,
+    pending: RwLock<HashMap<Signature, PendingPayment>>,
+
+    /// A map of identities to the node each authorized to vote for it. Locked
+    /// after all the other maps but `features`.
+    authorized_voters: RwLock<HashMap<Pubkey, Pubkey>>,
+
+    /// The features activated so far. Locked after all the other maps.
+    features: RwLock<Features>,
+
+    /// The handler of each program the bank runs instructions of, by program ID.
+    programs: HashMap<Pubkey, ProgramHandler>,
 
     /// A FIFO queue of `last_id` items, where each item is a set of signatures
     /// that have been processed using that `last_id`. Rejected `last_id`
     /// values are so old that the `last_id` has been pulled out of the queue.
     last_ids: RwLock<VecDeque<Hash>>,
 
-    /// Mapping of hashes to signature sets along with timestamp. The bank uses this data to
-    /// reject transactions with signatures its seen before
-    last_ids_sigs: RwLock<HashMap<Hash, (HashSet<Signature>, u64)>>,
+    /// Mapping of hashes to the status of each signature processed with that hash, along
+    /// with timestamp. The bank uses this data to reject transactions with signatures it
+    /// has processed before, and to report why others failed
+    last_ids_sigs: RwLock<StatusCache>,
 
     /// The number of transactions the bank has processed without error since the
     /// start of the ledger.
     transaction_count: AtomicUsize,
 
+    /// The number of ticks, entries without transactions, the bank has processed
+    /// since the start of the ledger.
+    tick_height: AtomicUsize,
+
+    /// The number of entry IDs the bank has registered since the start of the
+    /// ledger. Rent is collected each time it reaches a multiple of
+    /// `RENT_EPOCH_ENTRY_IDS`.
+    entry_id_count: AtomicUsize,
+
     /// This bool allows us to submit metrics that are specific for leaders or validators
     /// It is set to `true` by fullnode before creating the bank.
     pub is_leader: bool,
 
     // The latest finality time for the network
     finality_time: AtomicUsize,
+
+    /// Fees of recently banked transactions, recorded by the banking stage.
+    recent_fees: RwLock<RecentFees>,
+
+    /// Costs of the slots this node recently wrote as leader, oldest first,
+    /// recorded by the write stage.
+    recent_slot_costs: RwLock<VecDeque<SlotCost>>,
+
+    /// The PoH domain of the ledger, which every entry after the genesis
+    /// entries must be recorded in.
+    poh_domain: RwLock<PohDomain>,
+
+    /// Told about every processed batch of transactions.
+    observers: RwLock<Vec<Arc<BankObserver>>>,
+
+    /// The accounts saved to disk, if the bank was given an accounts db.
+    /// Locked after all the other maps but `journals`.
+    store: RwLock<AccountsStore>,
+
+    /// What changed since each checkpoint that may still be rolled back to.
+    /// Locked after all the other maps but `accounts_hash`.
+    journals: RwLock<Journals>,
+
+    /// The number of checkpoints in `journals`, so that changes made while
+    /// none are open, as on a leader, don't take its lock.
+    open_checkpoints: AtomicUsize,
+
+    /// The Merkle root over every balance, and the roots as of recently
+    /// replayed entry ids. Locked after all the other maps.
+    accounts_hash: RwLock<AccountsHash>,
+}
+
+/// Where the bank reads the accounts it doesn't hold in memory from.
+#[derive(Default)]
+struct AccountsStore {
+    db: Option<AccountsDb>,
+    /// Accounts emptied since the last save, which are still in the db.
+    purged: HashSet<Pubkey>,
+    /// The number of times the accounts were saved to the db.
+    saves: usize,
+}
+
+impl AccountsStore {
+    /// The account at `pubkey` as of the last save, unless it's been emptied since.
+    fn load(&self, pubkey: &Pubkey) -> Option<Account> {
+        if self.purged.contains(pubkey) {
+            return None;
+        }
+        self.db.as_ref().and_then(|db| db.load(pubkey))
+    }
+
+    /// The accounts saved to the db that aren't in `accounts` or emptied since.
+    fn unloaded<'a>(
+        &'a self,
+        accounts: &'a HashMap<Pubkey, Account>,
+    ) -> impl Iterator<Item = &'a Pubkey> {
+        self.db
+            .iter()
+            .flat_map(|db| db.pubkeys())
+            .filter(move |pubkey| !accounts.contains_key(pubkey) && !self.purged.contains(pubkey))
+    }
+}
+
+/// Fees paid by the transactions a leader recently banked, and how busy its
+/// banking stage was.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecentFees {
+    /// Fees of the last `MAX_RECENT_FEES` banked transactions, oldest first.
+    pub fees: VecDeque<i64>,
+
+    /// The number of verified transactions in the last batch handed to the bank.
+    pub queue_depth: usize,
+}
+
+impl RecentFees {
+    /// The fee at `percentile`, from 0 to 100, of the recent fees, or `None` if
+    /// no transactions were banked recently.
+    pub fn percentile(&self, percentile: usize) -> Option<i64> {
+        if self.fees.is_empty() {
+            return None;
+        }
+        let mut fees: Vec<_> = self.fees.iter().cloned().collect();
+        fees.sort();
+        let ix = (fees.len() - 1) * percentile.min(100) / 100;
+        Some(fees[ix])
+    }
+}
+
+/// A contract's payment plan still waiting on a witness.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct PendingPayment {
+    plan: Plan,
+    /// The account that funded the contract, which gets the tokens back if
+    /// it's canceled.
+    payer: Pubkey,
+    /// The key that may cancel the contract, if any.
+    cancel_authority: Option<Pubkey>,
+}
+
+impl PendingPayment {
+    /// The payment back to the payer if `signer` cancels the contract, or
+    /// `None` if `signer` may not.
+    fn refund(&self, signer: &Pubkey) -> Option<Payment> {
+        if self.cancel_authority != Some(*signer) {
+            return None;
+        }
+        Some(Payment {
+            tokens: self.plan.tokens(),
+            to: self.payer,
+        })
+    }
+}
+
+/// A copy of a bank's state, which the bank can later be restored to.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    accounts: HashMap<Pubkey, Account>,
+    pending: HashMap<Signature, PendingPayment>,
+    authorized_voters: HashMap<Pubkey, Pubkey>,
+    features: Features,
+    last_ids: VecDeque<Hash>,
+    last_ids_sigs: StatusCache,
+    transaction_count: usize,
+    tick_height: usize,
+    entry_id_count: usize,
+    purged: HashSet<Pubkey>,
+    saves: usize,
+}
+
+impl Snapshot {
+    /// Whether `last_id` was registered with the bank when the snapshot was taken.
+    pub fn has_entry_id(&self, last_id: &Hash) -> bool {
+        self.last_ids_sigs.contains_key(last_id)
+    }
+}
+
+/// What's saved along with the accounts, both to the accounts db and to a
+/// snapshot: the entry height and the id of the last entry they're the state
+/// as of, the `last_id` queue and its signatures, the pending plans, the
+/// authorized voters, the transaction count, the tick height, the number of
+/// entry IDs registered and the features activated.
+type SavedState = (
+    u64,
+    Hash,
+    VecDeque<Hash>,
+    StatusCache,
+    HashMap<Signature, PendingPayment>,
+    HashMap<Pubkey, Pubkey>,
+    usize,
+    usize,
+    usize,
+    Features,
+);
+
+fn err_bincode_to_io(e: Box<bincode::ErrorKind>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Identifies a checkpoint taken with `Bank::checkpoint`.
+pub type CheckpointId = u64;
+
+/// A change to the `last_id` queue or the signatures recorded under it.
+#[derive(Debug)]
+enum LastIdChange {
+    /// An id was pushed onto the back of the queue, replacing these
+    /// signatures if it was already registered.
+    Registered(Option<(HashMap<Signature, Result<()>>, u64)>),
+    /// The id at the front of the queue was dropped, along with its signatures.
+    Evicted(Hash, Option<(HashMap<Signature, Result<()>>, u64)>),
+    /// The status of a signature under an id changed from this one.
+    Signature(Hash, Signature, Option<Result<()>>),
+}
+
+/// Enough to undo everything that changed since a checkpoint. A keyed entry
+/// is saved the first time it changes, so it costs nothing until then.
+#[derive(Debug)]
+struct Journal {
+    id: CheckpointId,
+    accounts: HashMap<Pubkey, Option<Account>>,
+    pending: HashMap<Signature, Option<PendingPayment>>,
+    authorized_voters: HashMap<Pubkey, Option<Pubkey>>,
+    features: HashMap<Feature, Option<FeatureStatus>>,
+    /// Accounts emptied while still in the accounts db.
+    purged: Vec<Pubkey>,
+    /// Changes to the `last_id` queue, in the order they were made.
+    last_ids: Vec<LastIdChange>,
+    transaction_count: usize,
+    tick_height: usize,
+    entry_id_count: usize,
+}
+
+/// Save the value `key` has in `map` unless it was saved already.
+fn save_entry<K, V>(saved: &mut HashMap<K, Option<V>>, key: &K, map: &HashMap<K, V>)
+where
+    K: std::hash::Hash + Eq + Copy,
+    V: Clone,
+{
+    if !saved.contains_key(key) {
+        saved.insert(*key, map.get(key).cloned());
+    }
+}
+
+/// Put back the values `save_entry` saved.
+fn restore_entries<K, V>(saved: HashMap<K, Option<V>>, map: &mut HashMap<K, V>)
+where
+    K: std::hash::Hash + Eq,
+{
+    for (key, value) in saved {
+        match value {
+            Some(value) => map.insert(key, value),
+            None => map.remove(&key),
+        };
+    }
+}
+
+/// The open checkpoints, oldest first. Changes are saved to the newest one.
+#[derive(Debug, Default)]
+struct Journals {
+    next_id: CheckpointId,
+    stack: Vec<Journal>,
+}
+
+impl Journals {
+    fn position(&self, id: CheckpointId) -> Result<usize> {
+        self.stack
+            .iter()
+            .position(|journal| journal.id == id)
+            .ok_or(BankError::CheckpointNotFound(id))
+    }
+}
+
+/// What debiting one transaction of a batch reads and writes, moved out of the
+/// bank's maps while it's debited in parallel with the rest of the batch.
+struct DebitState {
+    /// The entry ID the transaction's signature is kept under.
+    last_id: Hash,
+    accounts: HashMap<Pubkey, Account>,
+    last_ids_sigs: StatusCache,
+}
+
+/// Put `entry` in the ring buffer `tail`, which holds the last `WINDOW_SIZE`
+/// entries, at `tail_idx`, and move `tail_idx` along.
+fn push_tail(entry: Entry, tail: &mut Vec<Entry>, tail_idx: &mut usize) {
+    if tail.len() > *tail_idx {
+        tail[*tail_idx] = entry;
+    } else {
+        tail.push(entry);
+    }
+    *tail_idx = (*tail_idx + 1) % WINDOW_SIZE as usize;
 }
 
 impl Default for Bank {
@@ -109,16 +514,50 @@ impl Default for Bank {
         Bank {
             accounts: RwLock::new(HashMap::new()),
             pending: RwLock::new(HashMap::new()),
+            authorized_voters: RwLock::new(HashMap::new()),
+            features: RwLock::new(Features::default()),
+            programs: Bank::native_programs(),
             last_ids: RwLock::new(VecDeque::new()),
             last_ids_sigs: RwLock::new(HashMap::new()),
             transaction_count: AtomicUsize::new(0),
+            tick_height: AtomicUsize::new(0),
+            entry_id_count: AtomicUsize::new(0),
             is_leader: true,
             finality_time: AtomicUsize::new(std::usize::MAX),
+            recent_fees: RwLock::new(RecentFees::default()),
+            recent_slot_costs: RwLock::new(VecDeque::new()),
+            poh_domain: RwLock::new(PohDomain::default()),
+            observers: RwLock::new(vec![]),
+            store: RwLock::new(AccountsStore::default()),
+            journals: RwLock::new(Journals::default()),
+            open_checkpoints: AtomicUsize::new(0),
+            accounts_hash: RwLock::new(AccountsHash::default()),
         }
     }
 }
 
 impl Bank {
+    /// The native programs' handlers, by program ID.
+    fn native_programs() -> HashMap<Pubkey, ProgramHandler> {
+        let handlers: [(NativeProgram, ProgramHandler); 3] = [
+            (NativeProgram::System, Bank::system_program),
+            (NativeProgram::Budget, Bank::budget_program),
+            (NativeProgram::Vote, Bank::vote_program),
+        ];
+        handlers
+            .iter()
+            .map(|(program, handler)| (program.id(), *handler))
+            .collect()
+    }
+
+    /// Run the instructions of the program with ID `program_id` with `handler`,
+    /// in place of any handler registered for it before. Every node has to
+    /// register the same handlers before processing entries, or they'll
+    /// disagree about the ledger.
+    pub fn register_program(&mut self, program_id: Pubkey, handler: ProgramHandler) {
+        self.programs.insert(program_id, handler);
+    }
+
     /// Create a default Bank
     pub fn new_default(is_leader: bool) -> Self {
         let mut bank = Bank::default();
@@ -134,23 +573,133 @@ impl Bank {
 
     /// Create an Bank with only a Mint. Typically used by unit tests.
     pub fn new(mint: &Mint) -> Self {
-        let deposit = Payment {
-            to: mint.pubkey(),
-            tokens: mint.tokens,
-        };
-        let bank = Self::new_from_deposit(&deposit);
+        let bank = Self::default();
+        bank.apply_genesis(&mint.create_transactions());
         bank.register_entry_id(&mint.last_id());
+        // the mint's first entry is the ledger's first tick
+        bank.tick_height.store(1, Ordering::Relaxed);
+        *bank.poh_domain.write().unwrap() = mint.poh_domain();
         bank
     }
 
+    /// Apply the genesis transactions. The first deposits all the tokens to the
+    /// mint, whose key may then activate features, and the others grant some
+    /// of them to other accounts or activate features from the start.
+    fn apply_genesis(&self, transactions: &[Transaction]) {
+        let accounts = &mut self
+            .accounts
+            .write()
+            .expect("'accounts' write lock in apply_genesis");
+        for (i, tx) in transactions.iter().enumerate() {
+            if i == 0 {
+                self.features
+                    .write()
+                    .expect("'features' write lock in apply_genesis")
+                    .authority = Some(tx.from);
+            }
+            if let Some(Instruction::ActivateFeature(feature)) = tx.instructions.first() {
+                let mut features = self
+                    .features
+                    .write()
+                    .expect("'features' write lock in apply_genesis");
+                self.journal(|journal| {
+                    save_entry(&mut journal.features, feature, &features.statuses)
+                });
+                features.statuses.insert(*feature, FeatureStatus::Active);
+                continue;
+            }
+            let payment = tx
+                .final_payments()
+                .into_iter()
+                .next()
+                .expect("invalid ledger, needs to start with a contract");
+            self.load_account(&tx.from, accounts);
+            self.journal(|journal| {
+                save_entry(&mut journal.accounts, &tx.from, accounts);
+                save_entry(&mut journal.accounts, &payment.to, accounts);
+            });
+            self.touch_account(&tx.from);
+            if i > 0 {
+                accounts
+                    .entry(tx.from)
+                    .or_insert_with(Account::default)
+                    .tokens -= payment.tokens;
+            }
+            self.apply_payment(&payment, accounts);
+        }
+    }
+
     /// Commit funds to the `payment.to` party.
     fn apply_payment(&self, payment: &Payment, accounts: &mut HashMap<Pubkey, Account>) {
+        self.load_account(&payment.to, accounts);
+        self.touch_account(&payment.to);
         accounts
             .entry(payment.to)
             .or_insert_with(Account::default)
             .tokens += payment.tokens;
     }
 
+    /// Copy the account at `pubkey` into `accounts` from the accounts db, unless
+    /// it's there already, so it can be changed.
+    fn load_account(&self, pubkey: &Pubkey, accounts: &mut HashMap<Pubkey, Account>) {
+        if accounts.contains_key(pubkey) {
+            return;
+        }
+        let store = self
+            .store
+            .read()
+            .expect("'store' read lock in load_account");
+        if let Some(account) = store.load(pubkey) {
+            accounts.insert(*pubkey, account);
+        }
+    }
+
+    /// The account at `pubkey`, from `accounts` or else from the accounts db.
+    fn lookup_account(
+        &self,
+        pubkey: &Pubkey,
+        accounts: &HashMap<Pubkey, Account>,
+    ) -> Option<Account> {
+        accounts.get(pubkey).cloned().or_else(|| {
+            self.store
+                .read()
+                .expect("'store' read lock in lookup_account")
+                .load(pubkey)
+        })
+    }
+
+    /// Note that the balance at `pubkey` is about to change, so that
+    /// `accounts_hash` hashes it again.
+    fn touch_account(&self, pubkey: &Pubkey) {
+        self.accounts_hash
+            .write()
+            .expect("'accounts_hash' write lock in touch_account")
+            .mark(pubkey);
+    }
+
+    /// Stop reading the account at `pubkey`, which a debit emptied, from the
+    /// accounts db.
+    fn purge_account(&self, pubkey: &Pubkey) {
+        let mut store = self
+            .store
+            .write()
+            .expect("'store' write lock in purge_account");
+        let stored = store.db.as_ref().map_or(false, |db| db.contains(pubkey));
+        if stored && store.purged.insert(*pubkey) {
+            self.journal(|journal| journal.purged.push(*pubkey));
+        }
+    }
+
+    /// Return the PoH domain new entries are recorded in.
+    pub fn poh_domain(&self) -> PohDomain {
+        *self.poh_domain.read().unwrap()
+    }
+
+    /// Compute the PoH with `poh_hash`, both to verify entries and to record them.
+    pub fn set_poh_hash(&self, poh_hash: PohHash) {
+        self.poh_domain.write().unwrap().hash = poh_hash;
+    }
+
     /// Return the last entry ID registered.
     pub fn last_id(&self) -> Hash {
         let last_ids = self.last_ids.read().expect("'last_ids' read lock");
@@ -161,28 +710,47 @@ impl Bank {
         *last_item
     }
 
-    /// Store the given signature. The bank will reject any transaction with the same signature.
-    fn reserve_signature(signatures: &mut HashSet<Signature>, signature: &Signature) -> Result<()> {
-        if let Some(signature) = signatures.get(signature) {
+    /// Store the given signature. The bank will reject any transaction with the same signature,
+    /// unless the one before it failed.
+    fn reserve_signature(
+        signatures: &mut HashMap<Signature, Result<()>>,
+        signature: &Signature,
+    ) -> Result<()> {
+        if let Some(Ok(())) = signatures.get(signature) {
             return Err(BankError::DuplicateSignature(*signature));
         }
-        signatures.insert(*signature);
+        signatures.insert(*signature, Ok(()));
         Ok(())
     }
 
     /// Forget the given `signature` because its transaction was rejected.
-    fn forget_signature(signatures: &mut HashSet<Signature>, signature: &Signature) {
+    fn forget_signature(signatures: &mut HashMap<Signature, Result<()>>, signature: &Signature) {
         signatures.remove(signature);
     }
 
+    /// Record why the transaction with `signature` and `last_id` failed. A duplicate
+    /// leaves the status of the transaction it duplicates alone.
+    fn record_signature_error(
+        signature: &Signature,
+        last_id: &Hash,
+        err: &BankError,
+        last_ids_sigs: &mut StatusCache,
+    ) {
+        if let BankError::DuplicateSignature(_) = err {
+            return;
+        }
+        if let Some(entry) = last_ids_sigs.get_mut(last_id) {
+            entry.0.insert(*signature, Err(err.clone()));
+        }
+    }
+
     /// Forget the given `signature` with `last_id` because the transaction was rejected.
-    fn forget_signature_with_last_id(&self, signature: &Signature, last_id: &Hash) {
-        if let Some(entry) = self
-            .last_ids_sigs
-            .write()
-            .expect("'last_ids' read lock in forget_signature_with_last_id")
-            .get_mut(last_id)
-        {
+    fn forget_signature_with_last_id(
+        signature: &Signature,
+        last_id: &Hash,
+        last_ids_sigs: &mut StatusCache,
+    ) {
+        if let Some(entry) = last_ids_sigs.get_mut(last_id) {
             Self::forget_signature(&mut entry.0, signature);
         }
     }
@@ -194,13 +762,12 @@ impl Bank {
         }
     }
 
-    fn reserve_signature_with_last_id(&self, signature: &Signature, last_id: &Hash) -> Result<()> {
-        if let Some(entry) = self
-            .last_ids_sigs
-            .write()
-            .expect("'last_ids' read lock in reserve_signature_with_last_id")
-            .get_mut(last_id)
-        {
+    fn reserve_signature_with_last_id(
+        signature: &Signature,
+        last_id: &Hash,
+        last_ids_sigs: &mut StatusCache,
+    ) -> Result<()> {
+        if let Some(entry) = last_ids_sigs.get_mut(last_id) {
             return Self::reserve_signature(&mut entry.0, signature);
         }
         Err(BankError::LastIdNotFound(*last_id))
@@ -222,431 +789,2193 @@ impl Bank {
         ret
     }
 
+    /// The `count` most recently registered entry IDs, newest first, with the
+    /// timestamp each was registered at. An ID stays usable until
+    /// `MAX_ENTRY_IDS` newer ones are registered.
+    pub fn recent_last_ids(&self, count: usize) -> Vec<(Hash, u64)> {
+        let last_ids = self
+            .last_ids
+            .read()
+            .expect("'last_ids' read lock in recent_last_ids");
+        let last_ids_sigs = self
+            .last_ids_sigs
+            .read()
+            .expect("'last_ids_sigs' read lock in recent_last_ids");
+        last_ids
+            .iter()
+            .rev()
+            .take(count)
+            .map(|id| (*id, last_ids_sigs[id].1))
+            .collect()
+    }
+
     /// Tell the bank which Entry IDs exist on the ledger. This function
     /// assumes subsequent calls correspond to later entries, and will boot
     /// the oldest ones once its internal cache is full. Once boot, the
     /// bank will reject transactions using that `last_id`.
     pub fn register_entry_id(&self, last_id: &Hash) {
-        let mut last_ids = self
-            .last_ids
-            .write()
-            .expect("'last_ids' write lock in register_entry_id");
-        let mut last_ids_sigs = self
-            .last_ids_sigs
-            .write()
-            .expect("last_ids_sigs write lock");
-        if last_ids.len() >= MAX_ENTRY_IDS {
-            let id = last_ids.pop_front().unwrap();
-            last_ids_sigs.remove(&id);
+        let rent_due = {
+            let mut last_ids = self
+                .last_ids
+                .write()
+                .expect("'last_ids' write lock in register_entry_id");
+            let mut last_ids_sigs = self
+                .last_ids_sigs
+                .write()
+                .expect("last_ids_sigs write lock");
+            self.register_entry_ids(&[*last_id], &mut last_ids, &mut last_ids_sigs)
+        };
+        self.notify_entries(&[*last_id]);
+        if !self.is_leader {
+            let mut accounts = self
+                .accounts
+                .write()
+                .expect("'accounts' write lock in register_entry_id");
+            self.settle_entry_ids(&[*last_id], rent_due, &mut accounts);
+        } else if rent_due.is_some() {
+            // a leader's record stage registers each entry ID as it records
+            // the entry, so the epoch ends at the same entry as on validators
+            let mut accounts = self
+                .accounts
+                .write()
+                .expect("'accounts' write lock in register_entry_id");
+            self.end_epoch(&mut accounts);
         }
-        last_ids_sigs.insert(*last_id, (HashSet::new(), timestamp()));
-        last_ids.push_back(*last_id);
     }
 
-    /// Deduct tokens from the 'from' address the account has sufficient
-    /// funds and isn't a duplicate.
-    fn apply_debits(
+    /// Register a run of entry IDs, in order, with the locks already held.
+    /// Returns the index of the one that ended a rent epoch, if any did, in
+    /// which case the caller has to `end_epoch` once it holds the accounts.
+    fn register_entry_ids(
         &self,
-        tx: &Transaction,
-        accounts: &mut HashMap<Pubkey, Account>,
-    ) -> Result<()> {
-        let mut purge = false;
-        {
-            let option = accounts.get_mut(&tx.from);
-            if option.is_none() {
-                // TODO: this is gnarly because the counters are static atomics
-                if !self.is_leader {
-                    inc_new_counter_info!("bank-appy_debits-account_not_found-validator", 1);
-                } else if let Instruction::NewVote(_) = &tx.instruction {
-                    inc_new_counter_info!("bank-appy_debits-vote_account_not_found", 1);
-                } else {
-                    inc_new_counter_info!("bank-appy_debits-generic_account_not_found", 1);
+        ids: &[Hash],
+        last_ids: &mut VecDeque<Hash>,
+        last_ids_sigs: &mut StatusCache,
+    ) -> Option<usize> {
+        let now = timestamp();
+        let mut journals = if self.open_checkpoints.load(Ordering::Relaxed) > 0 {
+            Some(
+                self.journals
+                    .write()
+                    .expect("'journals' write lock in register_entry_ids"),
+            )
+        } else {
+            None
+        };
+        let mut journal = journals
+            .as_mut()
+            .and_then(|journals| journals.stack.last_mut());
+        for last_id in ids {
+            if last_ids.len() >= MAX_ENTRY_IDS {
+                let id = last_ids.pop_front().unwrap();
+                let sigs = last_ids_sigs.remove(&id);
+                if let Some(ref mut journal) = journal {
+                    journal.last_ids.push(LastIdChange::Evicted(id, sigs));
                 }
-                return Err(BankError::AccountNotFound(tx.from));
             }
-            let bal = option.unwrap();
-
-            self.reserve_signature_with_last_id(&tx.signature, &tx.last_id)?;
+            let sigs = last_ids_sigs.insert(*last_id, (HashMap::new(), now));
+            if let Some(ref mut journal) = journal {
+                journal.last_ids.push(LastIdChange::Registered(sigs));
+            }
+            last_ids.push_back(*last_id);
+        }
+        let count = self.entry_id_count.fetch_add(ids.len(), Ordering::Relaxed);
+        let epoch_end = rent::RENT_EPOCH_ENTRY_IDS - count % rent::RENT_EPOCH_ENTRY_IDS;
+        if epoch_end <= ids.len() {
+            Some(epoch_end - 1)
+        } else {
+            None
+        }
+    }
 
-            if let Instruction::NewContract(contract) = &tx.instruction {
-                if contract.tokens < 0 {
-                    return Err(BankError::NegativeTokens);
-                }
+    /// End the rent epoch at `ids[rent_due]`, if one ended, and record the
+    /// accounts hash as of each of the replayed entry IDs `ids`, which were
+    /// just registered. The ones before the epoch ended are as of before the
+    /// rent was collected.
+    fn settle_entry_ids(
+        &self,
+        ids: &[Hash],
+        rent_due: Option<usize>,
+        accounts: &mut HashMap<Pubkey, Account>,
+    ) {
+        let (before_rent, after_rent) = ids.split_at(rent_due.unwrap_or_else(|| ids.len()));
+        self.record_accounts_hashes(before_rent, accounts);
+        if rent_due.is_some() {
+            self.end_epoch(accounts);
+        }
+        self.record_accounts_hashes(after_rent, accounts);
+    }
 
-                if bal.tokens < contract.tokens {
-                    self.forget_signature_with_last_id(&tx.signature, &tx.last_id);
-                    return Err(BankError::InsufficientFunds(tx.from));
-                } else if bal.tokens == contract.tokens {
-                    purge = true;
-                } else {
-                    bal.tokens -= contract.tokens;
-                }
-            };
+    /// Record the current accounts hash as the one as of each of `ids`.
+    fn record_accounts_hashes(&self, ids: &[Hash], accounts: &HashMap<Pubkey, Account>) {
+        if ids.is_empty() {
+            return;
         }
+        let store = self
+            .store
+            .read()
+            .expect("'store' read lock in record_accounts_hashes");
+        let mut accounts_hash = self
+            .accounts_hash
+            .write()
+            .expect("'accounts_hash' write lock in record_accounts_hashes");
+        let root = Self::update_accounts_hash(&mut accounts_hash, accounts, &store);
+        for last_id in ids {
+            accounts_hash.record(last_id, root);
+        }
+    }
 
-        if purge {
-            accounts.remove(&tx.from);
+    /// Set the balances that changed since the root was last computed, and
+    /// return the new root.
+    fn update_accounts_hash(
+        accounts_hash: &mut AccountsHash,
+        accounts: &HashMap<Pubkey, Account>,
+        store: &AccountsStore,
+    ) -> Hash {
+        let stale: Vec<Pubkey> = if accounts_hash.needs_rebuild() {
+            accounts_hash.clear();
+            accounts
+                .keys()
+                .chain(store.unloaded(accounts))
+                .cloned()
+                .collect()
+        } else {
+            accounts_hash.take_stale()
+        };
+        for pubkey in &stale {
+            let tokens = accounts
+                .get(pubkey)
+                .cloned()
+                .or_else(|| store.load(pubkey))
+                .map(|account| account.tokens);
+            accounts_hash.update(pubkey, tokens);
         }
+        accounts_hash.root()
+    }
 
-        Ok(())
+    /// The Merkle root over the balance of every account. Nodes that have
+    /// processed the same entries agree on it, so it shows when their state
+    /// diverged even where their entry IDs didn't.
+    pub fn accounts_hash(&self) -> Hash {
+        let accounts = self
+            .accounts
+            .read()
+            .expect("'accounts' read lock in accounts_hash");
+        let store = self
+            .store
+            .read()
+            .expect("'store' read lock in accounts_hash");
+        let mut accounts_hash = self
+            .accounts_hash
+            .write()
+            .expect("'accounts_hash' write lock in accounts_hash");
+        Self::update_accounts_hash(&mut accounts_hash, &accounts, &store)
     }
 
-    /// Apply only a transaction's credits.
-    /// Note: It is safe to apply credits from multiple transactions in parallel.
-    fn apply_credits(&self, tx: &Transaction, accounts: &mut HashMap<Pubkey, Account>) {
-        match &tx.instruction {
-            Instruction::NewContract(contract) => {
-                let plan = contract.plan.clone();
-                if let Some(payment) = plan.final_payment() {
-                    self.apply_payment(&payment, accounts);
-                } else {
-                    let mut pending = self
-                        .pending
-                        .write()
-                        .expect("'pending' write lock in apply_credits");
-                    pending.insert(tx.signature, plan);
-                }
-            }
-            Instruction::ApplyTimestamp(dt) => {
-                let _ = self.apply_timestamp(tx.from, *dt);
-            }
-            Instruction::ApplySignature(signature) => {
-                let _ = self.apply_signature(tx.from, *signature);
+    /// The accounts hash as of the entry ID `last_id`, if it's one of the
+    /// last `MAX_ENTRY_IDS` this bank replayed. A leader records none for the
+    /// entries it writes itself.
+    pub fn accounts_hash_at(&self, last_id: &Hash) -> Option<Hash> {
+        self.accounts_hash
+            .read()
+            .expect("'accounts_hash' read lock in accounts_hash_at")
+            .recorded_root(last_id)
+    }
+
+    /// Turn on the features activated during the rent epoch that just ended,
+    /// then collect the rent due for it.
+    fn end_epoch(&self, accounts: &mut HashMap<Pubkey, Account>) {
+        {
+            let mut features = self
+                .features
+                .write()
+                .expect("'features' write lock in end_epoch");
+            for feature in features.pending() {
+                info!("feature {} is now active", feature.name());
+                self.journal(|journal| {
+                    save_entry(&mut journal.features, &feature, &features.statuses)
+                });
+                features.statuses.insert(feature, FeatureStatus::Active);
             }
-            Instruction::NewVote(_vote) => {
-                trace!("GOT VOTE! last_id={:?}", &tx.last_id.as_ref()[..8]);
-                // TODO: record the vote in the stake table...
+        }
+        self.collect_rent(accounts);
+    }
+
+    /// Charge every account below its rent-exempt balance the rent it owes for
+    /// the epoch, and drop those that leaves empty. The rent isn't paid to
+    /// anyone; the tokens leave circulation.
+    fn collect_rent(&self, accounts: &mut HashMap<Pubkey, Account>) {
+        let stored: Vec<_> = {
+            let store = self
+                .store
+                .read()
+                .expect("'store' read lock in collect_rent");
+            store
+                .unloaded(accounts)
+                .filter_map(|pubkey| {
+                    let account = store.load(pubkey)?;
+                    if rent::rent_due(&account) > 0 {
+                        Some((*pubkey, account))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        };
+        accounts.extend(stored);
+
+        let due: Vec<_> = accounts
+            .iter()
+            .map(|(pubkey, account)| (*pubkey, rent::rent_due(account)))
+            .filter(|&(_, rent)| rent > 0)
+            .collect();
+        let mut reaped = 0;
+        for &(pubkey, rent) in &due {
+            self.journal(|journal| save_entry(&mut journal.accounts, &pubkey, accounts));
+            self.touch_account(&pubkey);
+            let empty = {
+                let account = accounts.get_mut(&pubkey).unwrap();
+                account.tokens -= rent;
+                account.tokens == 0
+            };
+            if empty {
+                accounts.remove(&pubkey);
+                self.purge_account(&pubkey);
+                reaped += 1;
             }
         }
+        inc_new_counter_info!("bank-collect_rent-charged", due.len());
+        inc_new_counter_info!("bank-collect_rent-reaped", reaped);
     }
-    fn save_data(&self, tx: &Transaction, accounts: &mut HashMap<Pubkey, Account>) {
-        //TODO This is a temporary implementation until the full rules on memory management for
-        //smart contracts are implemented. See github issue #953
-        if !tx.userdata.is_empty() {
-            if let Some(ref mut account) = accounts.get_mut(&tx.from) {
-                if account.userdata.len() != tx.userdata.len() {
-                    account.userdata.resize(tx.userdata.len(), 0);
+
+    /// Let the newest checkpoint, if there is one, save what's about to change.
+    /// Callers hold one of the maps' write locks, so no checkpoint can be taken
+    /// between looking at `open_checkpoints` and saving the change.
+    fn journal<F>(&self, save: F)
+    where
+        F: FnOnce(&mut Journal),
+    {
+        if self.open_checkpoints.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut journals = self
+            .journals
+            .write()
+            .expect("'journals' write lock in journal");
+        if let Some(journal) = journals.stack.last_mut() {
+            save(journal);
+        }
+    }
+
+    /// The accounts whose tokens or userdata debiting `tx` may change: those
+    /// it draws tokens from and those whose nonce it advances.
+    fn debited_pubkeys(tx: &Transaction) -> Vec<Pubkey> {
+        let mut pubkeys = tx.funding_pubkeys();
+        for instruction in &tx.instructions {
+            if let Instruction::AdvanceNonce(pubkey) = instruction {
+                if !pubkeys.contains(pubkey) {
+                    pubkeys.push(*pubkey);
                 }
-                account.userdata.copy_from_slice(&tx.userdata);
             }
         }
+        pubkeys
     }
 
-    /// Process a Transaction. If it contains a payment plan that requires a witness
-    /// to progress, the payment plan will be stored in the bank.
-    pub fn process_transaction(&self, tx: &Transaction) -> Result<()> {
-        let accounts = &mut self.accounts.write().unwrap();
-        self.apply_debits(tx, accounts)?;
-        self.apply_credits(tx, accounts);
-        self.save_data(tx, accounts);
-        self.transaction_count.fetch_add(1, Ordering::Relaxed);
-        Ok(())
+    /// The entry ID to keep `tx`'s signature status under. That's its own
+    /// `last_id`, unless it uses a durable nonce in place of one, in which case
+    /// it's the most recently registered ID, as the nonce never is one.
+    fn signature_last_id(
+        tx: &Transaction,
+        accounts: &HashMap<Pubkey, Account>,
+        last_ids_sigs: &StatusCache,
+    ) -> Hash {
+        if last_ids_sigs.contains_key(&tx.last_id) {
+            return tx.last_id;
+        }
+        let nonce = tx
+            .nonce_pubkey()
+            .and_then(|pubkey| accounts.get(&pubkey))
+            .and_then(Account::nonce);
+        if nonce != Some(tx.last_id) {
+            return tx.last_id;
+        }
+        last_ids_sigs
+            .iter()
+            .max_by_key(|(_, entry)| entry.1)
+            .map_or(tx.last_id, |(last_id, _)| *last_id)
     }
 
-    /// Process a batch of transactions.
-    #[must_use]
-    pub fn process_transactions(&self, txs: Vec<Transaction>) -> Vec<Result<Transaction>> {
-        let accounts = &mut self.accounts.write().unwrap();
-        debug!("processing Transactions {}", txs.len());
-        let txs_len = txs.len();
-        let now = Instant::now();
-        let results: Vec<_> = txs
-            .into_iter()
-            .map(|tx| self.apply_debits(&tx, accounts).map(|_| tx))
-            .collect(); // Calling collect() here forces all debits to complete before moving on.
+    /// The accounts debiting `tx` reads: those it may change, its signer's and
+    /// those whose owner or userdata it sets.
+    fn loaded_pubkeys(tx: &Transaction) -> Vec<Pubkey> {
+        let mut pubkeys = Self::debited_pubkeys(tx);
+        pubkeys.push(tx.from);
+        pubkeys.extend(tx.instructions.iter().filter_map(Instruction::data_pubkey));
+        pubkeys
+    }
 
-        let debits = now.elapsed();
-        let now = Instant::now();
+    /// The accounts processing `tx` may change, short of those a witness or
+    /// cancellation pays: those debiting it loads and those its final payments
+    /// go to.
+    fn written_pubkeys(tx: &Transaction) -> Vec<Pubkey> {
+        let mut pubkeys = Self::loaded_pubkeys(tx);
+        pubkeys.extend(tx.final_payments().iter().map(|payment| payment.to));
+        pubkeys
+    }
 
-        let res: Vec<_> = results
-            .into_iter()
-            .map(|result| {
-                result.map(|tx| {
-                    self.apply_credits(&tx, accounts);
-                    tx
-                })
-            })
-            .collect();
+    /// The accounts `tx` declared read-only, leaving out any it would change,
+    /// which are locked and loaded as written ones.
+    fn read_only_pubkeys(tx: &Transaction) -> Vec<Pubkey> {
+        let written = Self::written_pubkeys(tx);
+        tx.read_only
+            .iter()
+            .filter(|pubkey| !written.contains(pubkey))
+            .cloned()
+            .collect()
+    }
 
-        debug!(
-            "debits: {} us credits: {:?} us tx: {}",
-            duration_as_us(&debits),
-            duration_as_us(&now.elapsed()),
-            txs_len
-        );
+    /// The accounts processing `tx` may change, or `None` if it applies a
+    /// witness or cancels a contract, which may pay any account.
+    fn locked_pubkeys(tx: &Transaction) -> Option<Vec<Pubkey>> {
+        let reduces_plans = tx.instructions.iter().any(|instruction| match instruction {
+            Instruction::ApplyTimestamp(_)
+            | Instruction::ApplySignature(_)
+            | Instruction::Cancel(_) => true,
+            _ => false,
+        });
+        if reduces_plans {
+            None
+        } else {
+            Some(Self::written_pubkeys(tx))
+        }
+    }
 
-        let mut tx_count = 0;
-        let mut err_count = 0;
-        for r in &res {
-            if r.is_ok() {
-                tx_count += 1;
-            } else {
-                if err_count == 0 {
-                    info!("tx error: {:?}", r);
+    /// Lock the accounts of each of `txs` in turn, those it declared read-only
+    /// shared with other readers and the rest exclusively. A transaction that
+    /// writes an account locked before it, or reads one written before it, is
+    /// deferred with `AccountInUse`, as is every transaction after one that
+    /// applies a witness. A deferred transaction still holds its accounts, so
+    /// none after it can overtake it.
+    fn lock_accounts(txs: &[Transaction]) -> Vec<Result<()>> {
+        let mut written = HashSet::new();
+        let mut read = HashSet::new();
+        let mut exclusive = false;
+        txs.iter()
+            .map(|tx| {
+                if exclusive {
+                    return Err(BankError::AccountInUse(tx.from));
                 }
-                err_count += 1;
-            }
+                match Self::locked_pubkeys(tx) {
+                    Some(pubkeys) => {
+                        let read_only = Self::read_only_pubkeys(tx);
+                        let in_use = pubkeys
+                            .iter()
+                            .find(|pubkey| written.contains(*pubkey) || read.contains(*pubkey))
+                            .or_else(|| read_only.iter().find(|pubkey| written.contains(*pubkey)));
+                        let result =
+                            in_use.map_or(Ok(()), |pubkey| Err(BankError::AccountInUse(*pubkey)));
+                        written.extend(pubkeys.iter().cloned());
+                        read.extend(read_only);
+                        result
+                    }
+                    None => {
+                        exclusive = true;
+                        if written.is_empty() && read.is_empty() {
+                            Ok(())
+                        } else {
+                            Err(BankError::AccountInUse(tx.from))
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Deduct tokens from the funding address if the account has sufficient
+    /// funds and isn't a duplicate, recording the error if it fails.
+    fn apply_debits(
+        &self,
+        tx: &Transaction,
+        accounts: &mut HashMap<Pubkey, Account>,
+        last_ids_sigs: &mut StatusCache,
+    ) -> Result<()> {
+        let last_id = self.begin_debit(tx, accounts, last_ids_sigs);
+        let result = self.debit(tx, &last_id, accounts, last_ids_sigs);
+        self.end_debit(tx, &last_id, &result, accounts, last_ids_sigs);
+        result
+    }
+
+    /// Load the accounts debiting `tx` reads and journal what it may change,
+    /// returning the entry ID its signature is kept under.
+    fn begin_debit(
+        &self,
+        tx: &Transaction,
+        accounts: &mut HashMap<Pubkey, Account>,
+        last_ids_sigs: &StatusCache,
+    ) -> Hash {
+        for pubkey in Self::loaded_pubkeys(tx).iter().chain(&tx.read_only) {
+            self.load_account(pubkey, accounts);
         }
-        if err_count > 0 {
-            info!("{} errors of {} txs", err_count, err_count + tx_count);
-            if !self.is_leader {
-                inc_new_counter_info!("bank-proccess_transactions_err-validator", err_count);
-            } else {
-                inc_new_counter_info!("bank-proccess_transactions_err-leader", err_count);
+        let last_id = Self::signature_last_id(tx, accounts, last_ids_sigs);
+        let debited = Self::debited_pubkeys(tx);
+        self.journal(|journal| {
+            for pubkey in &debited {
+                save_entry(&mut journal.accounts, pubkey, accounts);
+            }
+            if let Some(entry) = last_ids_sigs.get(&last_id) {
+                let status = entry.0.get(&tx.signature).cloned();
+                let change = LastIdChange::Signature(last_id, tx.signature, status);
+                journal.last_ids.push(change);
             }
+        });
+        for pubkey in &debited {
+            self.touch_account(pubkey);
         }
-        self.transaction_count
-            .fetch_add(tx_count, Ordering::Relaxed);
-        res
+        last_id
     }
 
-    pub fn process_entry(&self, entry: Entry) -> Result<()> {
-        if !entry.transactions.is_empty() {
-            for result in self.process_transactions(entry.transactions) {
-                result?;
+    /// Purge the accounts `tx`'s debit emptied, or record why it failed.
+    fn end_debit(
+        &self,
+        tx: &Transaction,
+        last_id: &Hash,
+        result: &Result<()>,
+        accounts: &HashMap<Pubkey, Account>,
+        last_ids_sigs: &mut StatusCache,
+    ) {
+        match result {
+            Ok(()) => {
+                for pubkey in &Self::debited_pubkeys(tx) {
+                    if !accounts.contains_key(pubkey) {
+                        self.purge_account(pubkey);
+                    }
+                }
             }
+            Err(err) => Self::record_signature_error(&tx.signature, last_id, err, last_ids_sigs),
         }
-        if !entry.has_more {
-            self.register_entry_id(&entry.id);
-        }
-        Ok(())
     }
 
-    /// Process an ordered list of entries, populating a circular buffer "tail"
-    ///   as we go.
-    fn process_entries_tail(
+    /// Move what debiting `tx` reads and writes out of the bank's maps, so it
+    /// can be debited alongside transactions that share no accounts with it.
+    fn take_debit_state(
         &self,
-        entries: Vec<Entry>,
-        tail: &mut Vec<Entry>,
-        tail_idx: &mut usize,
-    ) -> Result<u64> {
-        let mut entry_count = 0;
-
-        for entry in entries {
-            if tail.len() > *tail_idx {
-                tail[*tail_idx] = entry.clone();
-            } else {
-                tail.push(entry.clone());
+        tx: &Transaction,
+        accounts: &mut HashMap<Pubkey, Account>,
+        last_ids_sigs: &mut StatusCache,
+    ) -> DebitState {
+        let last_id = self.begin_debit(tx, accounts, last_ids_sigs);
+        let mut state = DebitState {
+            last_id,
+            accounts: HashMap::new(),
+            last_ids_sigs: HashMap::new(),
+        };
+        for pubkey in Self::loaded_pubkeys(tx) {
+            if let Some(account) = accounts.remove(&pubkey) {
+                state.accounts.insert(pubkey, account);
             }
-            *tail_idx = (*tail_idx + 1) % WINDOW_SIZE as usize;
-
-            entry_count += 1;
-            self.process_entry(entry)?;
         }
-
-        Ok(entry_count)
-    }
-
-    /// Process an ordered list of entries.
-    pub fn process_entries(&self, entries: Vec<Entry>) -> Result<u64> {
-        let mut entry_count = 0;
-        for entry in entries {
-            entry_count += 1;
-            self.process_entry(entry)?;
+        // other transactions may be reading these too, so each gets a copy
+        for pubkey in Self::read_only_pubkeys(tx) {
+            if let Some(account) = accounts.get(&pubkey) {
+                state.accounts.insert(pubkey, account.clone());
+            }
         }
-        Ok(entry_count)
+        if let Some(entry) = last_ids_sigs.get_mut(&last_id) {
+            let mut signatures = HashMap::new();
+            if let Some(status) = entry.0.remove(&tx.signature) {
+                signatures.insert(tx.signature, status);
+            }
+            state.last_ids_sigs.insert(last_id, (signatures, entry.1));
+        }
+        state
     }
 
-    /// Append entry blocks to the ledger, verifying them along the way.
-    fn process_blocks<I>(
+    /// Move `state` back into the bank's maps once `tx` was debited with `result`.
+    fn return_debit_state(
         &self,
-        start_hash: Hash,
-        entries: I,
-        tail: &mut Vec<Entry>,
-        tail_idx: &mut usize,
-    ) -> Result<u64>
-    where
-        I: IntoIterator<Item = Entry>,
-    {
-        // Ledger verification needs to be parallelized, but we can't pull the whole
-        // thing into memory. We therefore chunk it.
-        let mut entry_count = *tail_idx as u64;
-        let mut id = start_hash;
-        for block in &entries.into_iter().chunks(VERIFY_BLOCK_SIZE) {
-            let block: Vec<_> = block.collect();
-            if !block.verify(&id) {
-                warn!("Ledger proof of history failed at entry: {}", entry_count);
-                return Err(BankError::LedgerVerificationFailed);
+        tx: &Transaction,
+        mut state: DebitState,
+        result: &Result<()>,
+        accounts: &mut HashMap<Pubkey, Account>,
+        last_ids_sigs: &mut StatusCache,
+    ) {
+        for pubkey in Self::read_only_pubkeys(tx) {
+            state.accounts.remove(&pubkey);
+        }
+        accounts.extend(state.accounts);
+        if let Some((signatures, _)) = state.last_ids_sigs.remove(&state.last_id) {
+            if let Some(entry) = last_ids_sigs.get_mut(&state.last_id) {
+                entry.0.extend(signatures);
             }
-            id = block.last().unwrap().id;
-            entry_count += self.process_entries_tail(block, tail, tail_idx)?;
         }
-        Ok(entry_count)
+        self.end_debit(tx, &state.last_id, result, accounts, last_ids_sigs);
     }
 
-    /// Process a full ledger.
-    pub fn process_ledger<I>(&self, entries: I) -> Result<(u64, Vec<Entry>)>
-    where
-        I: IntoIterator<Item = Entry>,
-    {
-        let mut entries = entries.into_iter();
-
-        // The first item in the ledger is required to be an entry with zero num_hashes,
-        // which implies its id can be used as the ledger's seed.
-        let entry0 = entries.next().expect("invalid ledger: empty");
-
-        // The second item in the ledger is a special transaction where the to and from
-        // fields are the same. That entry should be treated as a deposit, not a
-        // transfer to oneself.
-        let entry1 = entries
-            .next()
-            .expect("invalid ledger: need at least 2 entries");
-        {
-            let tx = &entry1.transactions[0];
-            let deposit = if let Instruction::NewContract(contract) = &tx.instruction {
-                contract.plan.final_payment()
+    /// Debit `tx`, keeping its signature under `last_id`.
+    fn debit(
+        &self,
+        tx: &Transaction,
+        last_id: &Hash,
+        accounts: &mut HashMap<Pubkey, Account>,
+        last_ids_sigs: &mut StatusCache,
+    ) -> Result<()> {
+        if !tx.verify_memo() {
+            return Err(BankError::MemoTooLong);
+        }
+        let written = Self::written_pubkeys(tx);
+        if let Some(pubkey) = tx.read_only.iter().find(|pubkey| written.contains(pubkey)) {
+            return Err(BankError::ReadOnlyAccountWritten(*pubkey));
+        }
+        Self::check_data_len(tx, accounts)?;
+        Self::check_loader(tx, accounts)?;
+        for instruction in &tx.instructions {
+            let program_id = instruction.program_id();
+            if !self.programs.contains_key(&program_id) {
+                return Err(BankError::UnknownProgram(program_id));
+            }
+            match instruction {
+                Instruction::Assign(pubkey, _) => Self::check_assign(&tx.from, pubkey, accounts)?,
+                Instruction::NewVote(vote) => self.check_voter(&tx.from, vote)?,
+                Instruction::AdvanceNonce(pubkey) => Self::check_nonce(tx, pubkey, accounts)?,
+                Instruction::ActivateFeature(feature) => {
+                    self.check_feature_authority(&tx.from, *feature)?
+                }
+                Instruction::Cancel(signature) => self.check_cancel(&tx.from, signature)?,
+                _ => (),
+            }
+        }
+        if tx.has_fee_payer() && !self.is_feature_active(Feature::FeePayer) {
+            return Err(BankError::FeatureNotActive(Feature::FeePayer));
+        }
+        let funding = tx.funding_pubkeys();
+        if let Some(from) = funding.iter().find(|pubkey| !accounts.contains_key(pubkey)) {
+            // TODO: this is gnarly because the counters are static atomics
+            if !self.is_leader {
+                inc_new_counter_info!("bank-appy_debits-account_not_found-validator", 1);
+            } else if voting::transaction_to_vote(tx).is_some() {
+                inc_new_counter_info!("bank-appy_debits-vote_account_not_found", 1);
             } else {
-                None
-            }.expect("invalid ledger, needs to start with a contract");
-
-            self.apply_payment(&deposit, &mut self.accounts.write().unwrap());
+                inc_new_counter_info!("bank-appy_debits-generic_account_not_found", 1);
+            }
+            return Err(BankError::AccountNotFound(*from));
         }
-        self.register_entry_id(&entry0.id);
-        self.register_entry_id(&entry1.id);
-        let entry1_id = entry1.id;
 
-        let mut tail = Vec::with_capacity(WINDOW_SIZE as usize);
-        tail.push(entry0);
-        tail.push(entry1);
-        let mut tail_idx = 2;
-        let entry_count = self.process_blocks(entry1_id, entries, &mut tail, &mut tail_idx)?;
+        Self::reserve_signature_with_last_id(&tx.signature, last_id, last_ids_sigs)?;
 
-        // check f we need to rotate tail
-        if tail.len() == WINDOW_SIZE as usize {
-            tail.rotate_left(tail_idx)
+        // check every debit before making any, so that the transaction's
+        // instructions are applied all together or not at all
+        let mut debits: Vec<(Pubkey, i64)> = vec![];
+        for (pubkey, tokens) in tx.debits() {
+            if tokens < 0 {
+                return Err(BankError::NegativeTokens);
+            }
+            match debits.iter_mut().find(|debit| debit.0 == pubkey) {
+                Some(debit) => debit.1 = debit.1.saturating_add(tokens),
+                None => debits.push((pubkey, tokens)),
+            }
+        }
+        for &(pubkey, tokens) in &debits {
+            if accounts[&pubkey].tokens < tokens {
+                Self::forget_signature_with_last_id(&tx.signature, last_id, last_ids_sigs);
+                return Err(BankError::InsufficientFunds(pubkey));
+            }
+        }
+        for (pubkey, tokens) in debits {
+            let purge = {
+                let account = accounts.get_mut(&pubkey).unwrap();
+                if account.tokens == tokens {
+                    true
+                } else {
+                    account.tokens -= tokens;
+                    false
+                }
+            };
+            if purge {
+                accounts.remove(&pubkey);
+            }
         }
 
-        Ok((entry_count, tail))
-    }
-
-    /// Process a Witness Signature. Any payment plans waiting on this signature
-    /// will progress one step.
-    fn apply_signature(&self, from: Pubkey, signature: Signature) -> Result<()> {
-        if let Occupied(mut e) = self
-            .pending
-            .write()
-            .expect("write() in apply_signature")
-            .entry(signature)
-        {
-            e.get_mut().apply_witness(&Witness::Signature, &from);
-            if let Some(payment) = e.get().final_payment() {
-                self.apply_payment(&payment, &mut self.accounts.write().unwrap());
-                e.remove_entry();
+        // the old nonce can't be used again once the transaction is in
+        let nonce = hash(tx.signature.as_ref());
+        for instruction in &tx.instructions {
+            if let Instruction::AdvanceNonce(pubkey) = instruction {
+                if let Some(account) = accounts.get_mut(pubkey) {
+                    account.userdata = serialize(&nonce).expect("serialize nonce");
+                }
             }
-        };
+        }
 
         Ok(())
     }
 
-    /// Process a Witness Timestamp. Any payment plans waiting on this timestamp
-    /// will progress one step.
-    fn apply_timestamp(&self, from: Pubkey, dt: DateTime<Utc>) -> Result<()> {
-        // Check to see if any timelocked transactions can be completed.
-        let mut completed = vec![];
+    /// Check that saving `tx`'s userdata to the signer's account keeps it within
+    /// the data size limits.
+    fn check_data_len(tx: &Transaction, accounts: &HashMap<Pubkey, Account>) -> Result<()> {
+        let len = tx.userdata.len();
+        if len > MAX_ACCOUNT_DATA_LEN {
+            return Err(BankError::AccountDataTooLarge(tx.from));
+        }
+        let old_len = accounts
+            .get(&tx.from)
+            .map_or(0, |account| account.userdata.len());
+        if len > old_len + MAX_DATA_GROWTH_PER_TRANSACTION {
+            return Err(BankError::AllocationTooLarge(tx.from));
+        }
+        Ok(())
+    }
 
-        // Hold 'pending' write lock until the end of this function. Otherwise another thread can
-        // double-spend if it enters before the modified plan is removed from 'pending'.
-        let mut pending = self
-            .pending
-            .write()
-            .expect("'pending' write lock in apply_timestamp");
-        for (key, plan) in pending.iter_mut() {
-            plan.apply_witness(&Witness::Timestamp(dt), &from);
-            if let Some(payment) = plan.final_payment() {
-                self.apply_payment(&payment, &mut self.accounts.write().unwrap());
-                completed.push(key.clone());
+    /// Check that `tx`'s signer owns each account it allocates or writes
+    /// userdata to, that no allocation breaks the data size limits, and that
+    /// each write fits within what's allocated by the time it's applied.
+    fn check_loader(tx: &Transaction, accounts: &HashMap<Pubkey, Account>) -> Result<()> {
+        let mut lens: HashMap<Pubkey, u64> = HashMap::new();
+        for instruction in &tx.instructions {
+            let pubkey = match instruction {
+                Instruction::Allocate(pubkey, _) | Instruction::Write(pubkey, _, _) => pubkey,
+                _ => continue,
+            };
+            let account = accounts
+                .get(pubkey)
+                .ok_or(BankError::AccountNotFound(*pubkey))?;
+            if account.authority(pubkey) != tx.from {
+                return Err(BankError::NotOwner(*pubkey));
+            }
+            let old_len = account.userdata.len() as u64;
+            let len = lens.entry(*pubkey).or_insert(old_len);
+            match instruction {
+                Instruction::Allocate(_, new_len) => {
+                    if *new_len > MAX_ACCOUNT_DATA_LEN as u64 {
+                        return Err(BankError::AccountDataTooLarge(*pubkey));
+                    }
+                    if *new_len > old_len + MAX_DATA_GROWTH_PER_TRANSACTION as u64 {
+                        return Err(BankError::AllocationTooLarge(*pubkey));
+                    }
+                    *len = *new_len;
+                }
+                Instruction::Write(_, offset, bytes) => {
+                    let end = offset.checked_add(bytes.len() as u64);
+                    if end.map_or(true, |end| end > *len) {
+                        return Err(BankError::DataOutOfBounds(*pubkey));
+                    }
+                }
+                _ => (),
             }
         }
+        Ok(())
+    }
 
-        for key in completed {
-            pending.remove(&key);
+    /// Check that `signer` may assign the account at `pubkey` to a new owner.
+    fn check_assign(
+        signer: &Pubkey,
+        pubkey: &Pubkey,
+        accounts: &HashMap<Pubkey, Account>,
+    ) -> Result<()> {
+        let account = accounts
+            .get(pubkey)
+            .ok_or(BankError::AccountNotFound(*pubkey))?;
+        if account.authority(pubkey) != *signer {
+            return Err(BankError::NotOwner(*pubkey));
+        }
+        // an owning program consents to handing over its userdata by signing
+        if !account.userdata.is_empty() && account.owner == Pubkey::default() {
+            return Err(BankError::AccountDataNotEmpty(*pubkey));
         }
-
         Ok(())
     }
 
-    /// Create, sign, and process a Transaction from `keypair` to `to` of
-    /// `n` tokens where `last_id` is the last Entry ID observed by the client.
-    pub fn transfer(
-        &self,
-        n: i64,
-        keypair: &Keypair,
-        to: Pubkey,
-        last_id: Hash,
-    ) -> Result<Signature> {
-        let tx = Transaction::new(keypair, to, n, last_id);
-        let signature = tx.signature;
-        self.process_transaction(&tx).map(|_| signature)
+    /// Check that `tx`'s signer may advance the nonce kept at `pubkey`.
+    fn check_nonce(
+        tx: &Transaction,
+        pubkey: &Pubkey,
+        accounts: &HashMap<Pubkey, Account>,
+    ) -> Result<()> {
+        let account = accounts
+            .get(pubkey)
+            .ok_or(BankError::AccountNotFound(*pubkey))?;
+        if account.authority(pubkey) != tx.from {
+            return Err(BankError::NotOwner(*pubkey));
+        }
+        let holds_other_data = !account.userdata.is_empty() && account.nonce().is_none();
+        if holds_other_data || (*pubkey == tx.from && !tx.userdata.is_empty()) {
+            return Err(BankError::InvalidNonceAccount(*pubkey));
+        }
+        Ok(())
     }
 
-    /// Create, sign, and process a postdated Transaction from `keypair`
-    /// to `to` of `n` tokens on `dt` where `last_id` is the last Entry ID
-    /// observed by the client.
-    pub fn transfer_on_date(
-        &self,
-        n: i64,
-        keypair: &Keypair,
-        to: Pubkey,
-        dt: DateTime<Utc>,
-        last_id: Hash,
-    ) -> Result<Signature> {
-        let tx = Transaction::new_on_date(keypair, to, dt, n, last_id);
-        let signature = tx.signature;
-        self.process_transaction(&tx).map(|_| signature)
+    /// Check that `signer` may cast `vote`, either for itself or for an identity
+    /// that authorized it.
+    fn check_voter(&self, signer: &Pubkey, vote: &Vote) -> Result<()> {
+        match vote.identity {
+            Some(identity) if identity != *signer => {
+                if self.authorized_voter(&identity) != Some(*signer) {
+                    return Err(BankError::UnauthorizedVoter(identity));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
     }
 
-    pub fn get_balance(&self, pubkey: &Pubkey) -> i64 {
-        self.get_account(pubkey).map(|a| a.tokens).unwrap_or(0)
+    /// Check that `signer` may cancel the contract `signature` declared, whose
+    /// plan has to be still waiting on a witness.
+    fn check_cancel(&self, signer: &Pubkey, signature: &Signature) -> Result<()> {
+        let pending = self
+            .pending
+            .read()
+            .expect("'pending' read lock in check_cancel");
+        let refund = pending
+            .get(signature)
+            .and_then(|payment| payment.refund(signer));
+        match refund {
+            Some(_) => Ok(()),
+            None => Err(BankError::NotCancelable(*signature)),
+        }
     }
 
-    pub fn get_account(&self, pubkey: &Pubkey) -> Option<Account> {
-        let accounts = self
-            .accounts
+    /// Check that `signer` is the mint's key, the only one that may activate
+    /// `feature`.
+    fn check_feature_authority(&self, signer: &Pubkey, feature: Feature) -> Result<()> {
+        let features = self
+            .features
             .read()
-            .expect("'accounts' read lock in get_balance");
-        accounts.get(pubkey).cloned()
+            .expect("'features' read lock in check_feature_authority");
+        if features.authority != Some(*signer) {
+            return Err(BankError::UnauthorizedFeatureActivation(feature));
+        }
+        Ok(())
     }
 
-    pub fn transaction_count(&self) -> usize {
-        self.transaction_count.load(Ordering::Relaxed)
+    /// Whether the cluster has activated `feature`. One activated after
+    /// genesis is only active once the rent epoch it was activated in ends.
+    pub fn is_feature_active(&self, feature: Feature) -> bool {
+        self.features
+            .read()
+            .expect("'features' read lock in is_feature_active")
+            .is_active(feature)
     }
 
-    pub fn has_signature(&self, signature: &Signature) -> bool {
-        let last_ids_sigs = self
-            .last_ids_sigs
+    /// The node `identity` authorized to vote for it, if any.
+    pub fn authorized_voter(&self, identity: &Pubkey) -> Option<Pubkey> {
+        self.authorized_voters
             .read()
-            .expect("'last_ids_sigs' read lock");
-        for (_hash, signatures) in last_ids_sigs.iter() {
-            if signatures.0.contains(signature) {
-                return true;
-            }
-        }
-        false
+            .expect("'authorized_voters' read lock in authorized_voter")
+            .get(identity)
+            .cloned()
     }
 
-    pub fn finality(&self) -> usize {
-        self.finality_time.load(Ordering::Relaxed)
+    /// Apply only a transaction's credits, returning the payments it made.
+    /// Note: It is safe to apply credits from multiple transactions in parallel.
+    fn apply_credits(
+        &self,
+        tx: &Transaction,
+        accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Vec<Payment> {
+        let mut payments = vec![];
+        for instruction in &tx.instructions {
+            payments.extend(self.apply_instruction(tx, instruction, accounts));
+        }
+        payments
     }
 
-    pub fn set_finality(&self, finality: usize) {
-        self.finality_time.store(finality, Ordering::Relaxed);
+    /// Apply one of `tx`'s instructions once its debits are made, with the
+    /// handler of the program it belongs to, returning the payments it made.
+    fn apply_instruction(
+        &self,
+        tx: &Transaction,
+        instruction: &Instruction,
+        accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Vec<Payment> {
+        match self.programs.get(&instruction.program_id()) {
+            Some(handler) => handler(self, tx, instruction, accounts),
+            // debiting checked that the program was registered
+            None => vec![],
+        }
     }
-}
 
-#[cfg(test)]
+    /// Run an instruction of the budget program, which moves tokens by
+    /// payment plans.
+    fn budget_program(
+        &self,
+        tx: &Transaction,
+        instruction: &Instruction,
+        accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Vec<Payment> {
+        match instruction {
+            Instruction::NewContract(contract)
+            | Instruction::NewContractFromSeed(_, _, contract) => {
+                let plan = contract.plan.clone();
+                if let Some(payment) = plan.final_payment() {
+                    self.journal(|journal| {
+                        save_entry(&mut journal.accounts, &payment.to, accounts)
+                    });
+                    self.apply_payment(&payment, accounts);
+                    vec![payment]
+                } else {
+                    let mut pending = self
+                        .pending
+                        .write()
+                        .expect("'pending' write lock in apply_credits");
+                    self.journal(|journal| {
+                        save_entry(&mut journal.pending, &tx.signature, &pending)
+                    });
+                    let payment = PendingPayment {
+                        plan,
+                        payer: instruction.funding_pubkey(&tx.from),
+                        cancel_authority: contract.cancel_authority,
+                    };
+                    pending.insert(tx.signature, payment);
+                    vec![]
+                }
+            }
+            Instruction::ApplyTimestamp(dt) => self
+                .apply_timestamp(tx.from, *dt, accounts)
+                .unwrap_or_default(),
+            Instruction::ApplySignature(signature) => self
+                .apply_signature(tx.from, *signature, accounts)
+                .unwrap_or_default(),
+            Instruction::Cancel(signature) => self
+                .cancel(&tx.from, signature, accounts)
+                .into_iter()
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Run an instruction of the system program, which manages accounts and
+    /// the cluster's features.
+    fn system_program(
+        &self,
+        tx: &Transaction,
+        instruction: &Instruction,
+        accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Vec<Payment> {
+        match instruction {
+            Instruction::Assign(pubkey, owner) => {
+                self.journal(|journal| save_entry(&mut journal.accounts, pubkey, accounts));
+                if let Some(account) = accounts.get_mut(pubkey) {
+                    account.owner = *owner;
+                }
+            }
+            Instruction::Allocate(pubkey, len) => {
+                self.journal(|journal| save_entry(&mut journal.accounts, pubkey, accounts));
+                if let Some(account) = accounts.get_mut(pubkey) {
+                    let len = *len as usize;
+                    if account.userdata.len() < len {
+                        let growth = len - account.userdata.len();
+                        inc_new_counter_info!("bank-allocate-allocated_bytes", growth);
+                    }
+                    account.userdata.resize(len, 0);
+                }
+            }
+            Instruction::Write(pubkey, offset, bytes) => {
+                self.journal(|journal| save_entry(&mut journal.accounts, pubkey, accounts));
+                if let Some(account) = accounts.get_mut(pubkey) {
+                    let offset = *offset as usize;
+                    account.userdata[offset..offset + bytes.len()].copy_from_slice(bytes);
+                }
+            }
+            Instruction::AdvanceNonce(_) => {
+                // the nonce was advanced along with the debits
+            }
+            Instruction::ActivateFeature(feature) => {
+                let mut features = self
+                    .features
+                    .write()
+                    .expect("'features' write lock in apply_credits");
+                if !features.statuses.contains_key(feature) {
+                    self.journal(|journal| {
+                        save_entry(&mut journal.features, feature, &features.statuses)
+                    });
+                    features.statuses.insert(*feature, FeatureStatus::Pending);
+                }
+            }
+            _ => (),
+        }
+        vec![]
+    }
+
+    /// Run an instruction of the vote program.
+    fn vote_program(
+        &self,
+        tx: &Transaction,
+        instruction: &Instruction,
+        _accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Vec<Payment> {
+        match instruction {
+            Instruction::NewVote(_vote) => {
+                trace!("GOT VOTE! last_id={:?}", &tx.last_id.as_ref()[..8]);
+                // TODO: record the vote in the stake table...
+            }
+            Instruction::AuthorizeVoter(voter) => {
+                let mut authorized_voters = self
+                    .authorized_voters
+                    .write()
+                    .expect("'authorized_voters' write lock in apply_credits");
+                self.journal(|journal| {
+                    save_entry(&mut journal.authorized_voters, &tx.from, &authorized_voters)
+                });
+                authorized_voters.insert(tx.from, *voter);
+            }
+            _ => (),
+        }
+        vec![]
+    }
+
+    /// The net change to each balance `tx` made by its debits and `payments`.
+    fn balance_deltas(tx: &Transaction, payments: &[Payment]) -> Vec<BalanceDelta> {
+        let debits = tx
+            .debits()
+            .into_iter()
+            .map(|(pubkey, tokens)| (pubkey, -tokens));
+        let credits = payments.iter().map(|payment| (payment.to, payment.tokens));
+        let mut deltas: Vec<BalanceDelta> = vec![];
+        for (pubkey, tokens) in debits.chain(credits) {
+            if let Some(delta) = deltas.iter_mut().find(|delta| delta.pubkey == pubkey) {
+                delta.tokens += tokens;
+                continue;
+            }
+            deltas.push(BalanceDelta { pubkey, tokens });
+        }
+        deltas.retain(|delta| delta.tokens != 0);
+        deltas
+    }
+    fn save_data(&self, tx: &Transaction, accounts: &mut HashMap<Pubkey, Account>) {
+        //TODO This is a temporary implementation until the full rules on memory management for
+        //smart contracts are implemented. See github issue #953
+        if !tx.userdata.is_empty() {
+            self.journal(|journal| save_entry(&mut journal.accounts, &tx.from, accounts));
+            if let Some(ref mut account) = accounts.get_mut(&tx.from) {
+                if account.userdata.len() < tx.userdata.len() {
+                    let growth = tx.userdata.len() - account.userdata.len();
+                    inc_new_counter_info!("bank-save_data-allocated_bytes", growth);
+                }
+                if account.userdata.len() != tx.userdata.len() {
+                    account.userdata.resize(tx.userdata.len(), 0);
+                }
+                account.userdata.copy_from_slice(&tx.userdata);
+            }
+        }
+    }
+
+    /// Tell `observer` about every batch of transactions from now on.
+    pub fn add_observer(&self, observer: Arc<BankObserver>) {
+        self.observers
+            .write()
+            .expect("'observers' write lock in add_observer")
+            .push(observer);
+    }
+
+    /// Tell the observers, if any, that `tx` was committed and made `payments`.
+    fn notify_committed(&self, tx: &Transaction, payments: &[Payment]) {
+        let observers = self
+            .observers
+            .read()
+            .expect("'observers' read lock in notify_committed");
+        if observers.is_empty() {
+            return;
+        }
+        let deltas = Self::balance_deltas(tx, payments);
+        for observer in observers.iter() {
+            observer.transaction_committed(tx, &deltas);
+        }
+    }
+
+    fn notify_entries(&self, ids: &[Hash]) {
+        if ids.is_empty() {
+            return;
+        }
+        let observers = self
+            .observers
+            .read()
+            .expect("'observers' read lock in notify_entries");
+        for observer in observers.iter() {
+            observer.entries_registered(ids, self.tick_height());
+        }
+    }
+
+    fn notify_observers(
+        &self,
+        txs: &[Transaction],
+        results: &[Result<()>],
+        accounts: &HashMap<Pubkey, Account>,
+    ) {
+        let observers = self
+            .observers
+            .read()
+            .expect("'observers' read lock in notify_observers");
+        let lookup = |pubkey: &Pubkey| self.lookup_account(pubkey, accounts);
+        for observer in observers.iter() {
+            observer.transactions_processed(txs, results, &lookup);
+        }
+    }
+
+    /// Run `tx` against a copy of the accounts it touches without committing
+    /// anything. Returns how it would fare and, if it would succeed, the balance
+    /// each of those accounts would be left with.
+    pub fn simulate_transaction(&self, tx: &Transaction) -> (Result<()>, Vec<(Pubkey, i64)>) {
+        let accounts = self
+            .accounts
+            .read()
+            .expect("'accounts' read lock in simulate_transaction");
+        let mut scratch: HashMap<Pubkey, Account> = HashMap::new();
+        let mut touched = Self::debited_pubkeys(tx);
+        touched.extend(tx.instructions.iter().filter_map(Instruction::data_pubkey));
+        for pubkey in touched.iter().chain(Some(&tx.from)) {
+            if let Some(account) = self.lookup_account(pubkey, &accounts) {
+                scratch.insert(*pubkey, account);
+            }
+        }
+
+        // only the transaction's own signature matters to the debit
+        let mut last_ids_sigs = HashMap::new();
+        let last_id = {
+            let all_sigs = self
+                .last_ids_sigs
+                .read()
+                .expect("'last_ids_sigs' read lock in simulate_transaction");
+            let last_id = Self::signature_last_id(tx, &scratch, &all_sigs);
+            if let Some(&(ref sigs, timestamp)) = all_sigs.get(&last_id) {
+                let sigs = sigs
+                    .get(&tx.signature)
+                    .map(|status| (tx.signature, status.clone()))
+                    .into_iter()
+                    .collect();
+                last_ids_sigs.insert(last_id, (sigs, timestamp));
+            }
+            last_id
+        };
+        if let Err(err) = self.debit(tx, &last_id, &mut scratch, &mut last_ids_sigs) {
+            return (Err(err), vec![]);
+        }
+
+        for payment in self.simulate_payments(tx) {
+            if !scratch.contains_key(&payment.to) {
+                let account = self
+                    .lookup_account(&payment.to, &accounts)
+                    .unwrap_or_default();
+                scratch.insert(payment.to, account);
+            }
+            self.apply_payment(&payment, &mut scratch);
+            if !touched.contains(&payment.to) {
+                touched.push(payment.to);
+            }
+        }
+        let balances = touched
+            .into_iter()
+            .map(|pubkey| {
+                let tokens = scratch.get(&pubkey).map_or(0, |account| account.tokens);
+                (pubkey, tokens)
+            })
+            .collect();
+        (Ok(()), balances)
+    }
+
+    /// The payments `tx` would make if it were committed now, leaving the
+    /// pending plans alone.
+    fn simulate_payments(&self, tx: &Transaction) -> Vec<Payment> {
+        let pending = self
+            .pending
+            .read()
+            .expect("'pending' read lock in simulate_payments");
+        let mut payments = vec![];
+        for instruction in &tx.instructions {
+            let witness = match instruction {
+                Instruction::ApplyTimestamp(dt) => Witness::Timestamp(*dt),
+                Instruction::ApplySignature(signature) => {
+                    let payment = pending.get(signature).and_then(|payment| {
+                        let mut plan = payment.plan.clone();
+                        plan.apply_witness(&Witness::Signature, &tx.from);
+                        plan.final_payment()
+                    });
+                    payments.extend(payment);
+                    continue;
+                }
+                Instruction::Cancel(signature) => {
+                    let refund = pending
+                        .get(signature)
+                        .and_then(|payment| payment.refund(&tx.from));
+                    payments.extend(refund);
+                    continue;
+                }
+                _ => {
+                    let contract = instruction.contract();
+                    payments.extend(contract.and_then(|contract| contract.plan.final_payment()));
+                    continue;
+                }
+            };
+            payments.extend(pending.values().filter_map(|payment| {
+                let mut plan = payment.plan.clone();
+                plan.apply_witness(&witness, &tx.from);
+                plan.final_payment()
+            }));
+        }
+        payments
+    }
+
+    /// Process a Transaction. If it contains a payment plan that requires a witness
+    /// to progress, the payment plan will be stored in the bank.
+    pub fn process_transaction(&self, tx: &Transaction) -> Result<()> {
+        let accounts = &mut self.accounts.write().unwrap();
+        let result = {
+            let last_ids_sigs = &mut self.last_ids_sigs.write().unwrap();
+            self.apply_debits(tx, accounts, last_ids_sigs)
+        };
+        if result.is_ok() {
+            let payments = self.apply_credits(tx, accounts);
+            self.save_data(tx, accounts);
+            self.transaction_count.fetch_add(1, Ordering::Relaxed);
+            self.notify_committed(tx, &payments);
+        }
+        self.notify_observers(slice::from_ref(tx), slice::from_ref(&result), accounts);
+        result
+    }
+
+    /// Process a batch of transactions and return a result for each of them. The
+    /// accounts and signature locks are acquired once for the whole batch rather
+    /// than once per transaction. Transactions that share no accounts are
+    /// debited in parallel, and those that conflict with one earlier in the
+    /// batch fail with `AccountInUse`, to be retried in a later batch.
+    #[must_use]
+    pub fn process_transactions(&self, txs: &[Transaction]) -> Vec<Result<()>> {
+        let accounts = &mut self.accounts.write().unwrap();
+        let debits = {
+            let last_ids_sigs = &mut self
+                .last_ids_sigs
+                .write()
+                .expect("'last_ids_sigs' write lock in process_transactions");
+            let locks = Self::lock_accounts(txs);
+            self.apply_locked_debits(txs, locks, accounts, last_ids_sigs)
+        };
+        self.apply_all_credits(txs, debits, accounts)
+    }
+
+    /// Process the transactions of an entry being replayed. A leader only puts
+    /// transactions that share no accounts in an entry, so they're debited in
+    /// parallel as they were by the leader, unless they conflict, in which case
+    /// they're debited one at a time. Either way, all before any are credited.
+    fn replay_transactions(
+        &self,
+        txs: &[Transaction],
+        accounts: &mut HashMap<Pubkey, Account>,
+        last_ids_sigs: &mut StatusCache,
+    ) -> Vec<Result<()>> {
+        let locks = Self::lock_accounts(txs);
+        let debits = if locks.iter().all(Result::is_ok) {
+            self.apply_locked_debits(txs, locks, accounts, last_ids_sigs)
+        } else {
+            self.apply_all_debits(txs, accounts, last_ids_sigs)
+        };
+        self.apply_all_credits(txs, debits, accounts)
+    }
+
+    /// Debit the transactions in `txs` whose accounts were locked across a rayon
+    /// thread pool, returning the results, with the lock error for the rest,
+    /// and the time it took. The caller holds the accounts and signature locks.
+    fn apply_locked_debits(
+        &self,
+        txs: &[Transaction],
+        locks: Vec<Result<()>>,
+        accounts: &mut HashMap<Pubkey, Account>,
+        last_ids_sigs: &mut StatusCache,
+    ) -> (Vec<Result<()>>, Duration) {
+        debug!("processing Transactions {}", txs.len());
+        let now = Instant::now();
+        let mut states: Vec<_> = txs
+            .iter()
+            .zip(locks)
+            .map(|(tx, lock)| lock.map(|_| self.take_debit_state(tx, accounts, last_ids_sigs)))
+            .collect();
+        let results: Vec<_> = txs
+            .par_iter()
+            .zip(states.par_iter_mut())
+            .map(|(tx, state)| match state {
+                Ok(state) => self.debit(
+                    tx,
+                    &state.last_id,
+                    &mut state.accounts,
+                    &mut state.last_ids_sigs,
+                ),
+                Err(err) => Err(err.clone()),
+            })
+            .collect();
+        for ((tx, state), result) in txs.iter().zip(states).zip(&results) {
+            if let Ok(state) = state {
+                self.return_debit_state(tx, state, result, accounts, last_ids_sigs);
+            }
+        }
+        (results, now.elapsed())
+    }
+
+    /// Debit every transaction in `txs` one at a time, returning the results and
+    /// the time it took. The caller holds the accounts and signature locks.
+    fn apply_all_debits(
+        &self,
+        txs: &[Transaction],
+        accounts: &mut HashMap<Pubkey, Account>,
+        last_ids_sigs: &mut StatusCache,
+    ) -> (Vec<Result<()>>, Duration) {
+        debug!("processing Transactions {}", txs.len());
+        let now = Instant::now();
+        let results = txs
+            .iter()
+            .map(|tx| self.apply_debits(tx, accounts, last_ids_sigs))
+            .collect(); // Calling collect() here forces all debits to complete before moving on.
+        (results, now.elapsed())
+    }
+
+    /// Credit every transaction whose debit succeeded and tally the results.
+    fn apply_all_credits(
+        &self,
+        txs: &[Transaction],
+        (results, debits): (Vec<Result<()>>, Duration),
+        accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Vec<Result<()>> {
+        let txs_len = txs.len();
+        let now = Instant::now();
+
+        let res: Vec<_> = txs
+            .iter()
+            .zip(results)
+            .map(|(tx, result)| {
+                result.map(|_| {
+                    let payments = self.apply_credits(tx, accounts);
+                    self.save_data(tx, accounts);
+                    self.notify_committed(tx, &payments);
+                })
+            })
+            .collect();
+
+        debug!(
+            "debits: {} us credits: {:?} us tx: {}",
+            duration_as_us(&debits),
+            duration_as_us(&now.elapsed()),
+            txs_len
+        );
+
+        let mut tx_count = 0;
+        let mut err_count = 0;
+        let mut deferred_count = 0;
+        for r in &res {
+            match r {
+                Ok(()) => tx_count += 1,
+                Err(BankError::AccountInUse(_)) => deferred_count += 1,
+                Err(_) => {
+                    if err_count == 0 {
+                        info!("tx error: {:?}", r);
+                    }
+                    err_count += 1;
+                }
+            }
+        }
+        if deferred_count > 0 {
+            inc_new_counter_info!("bank-process_transactions-account_in_use", deferred_count);
+        }
+        if err_count > 0 {
+            info!("{} errors of {} txs", err_count, err_count + tx_count);
+            if !self.is_leader {
+                inc_new_counter_info!("bank-proccess_transactions_err-validator", err_count);
+            } else {
+                inc_new_counter_info!("bank-proccess_transactions_err-leader", err_count);
+            }
+        }
+        self.transaction_count
+            .fetch_add(tx_count, Ordering::Relaxed);
+        if deferred_count > 0 {
+            // observers hear about deferred transactions once they're retried
+            let (txs, results): (Vec<_>, Vec<_>) = txs
+                .iter()
+                .cloned()
+                .zip(res.iter().cloned())
+                .filter(|(_, result)| match result {
+                    Err(BankError::AccountInUse(_)) => false,
+                    _ => true,
+                })
+                .unzip();
+            self.notify_observers(&txs, &results, accounts);
+        } else {
+            self.notify_observers(txs, &res, accounts);
+        }
+        res
+    }
+
+    pub fn process_entry(&self, entry: Entry) -> Result<()> {
+        if !entry.transactions.is_empty() {
+            let accounts = &mut self
+                .accounts
+                .write()
+                .expect("'accounts' write lock in process_entry");
+            let last_ids_sigs = &mut self
+                .last_ids_sigs
+                .write()
+                .expect("'last_ids_sigs' write lock in process_entry");
+            for result in self.replay_transactions(&entry.transactions, accounts, last_ids_sigs) {
+                result?;
+            }
+        } else {
+            self.tick_height.fetch_add(1, Ordering::Relaxed);
+        }
+        if !entry.has_more {
+            self.register_entry_id(&entry.id);
+        }
+        Ok(())
+    }
+
+    /// Process an ordered list of entries, populating a circular buffer "tail"
+    ///   as we go.
+    fn process_entries_tail(
+        &self,
+        entries: Vec<Entry>,
+        tail: &mut Vec<Entry>,
+        tail_idx: &mut usize,
+    ) -> Result<u64> {
+        let mut entry_count = 0;
+
+        for entry in entries {
+            push_tail(entry.clone(), tail, tail_idx);
+            entry_count += 1;
+            self.process_entry(entry)?;
+        }
+
+        Ok(entry_count)
+    }
+
+    /// Process an ordered list of entries. The bank locks are acquired once for
+    /// the whole list, and runs of entry IDs are registered together just before
+    /// the next transactions that could refer to them.
+    pub fn process_entries(&self, entries: Vec<Entry>) -> Result<u64> {
+        let accounts = &mut self
+            .accounts
+            .write()
+            .expect("'accounts' write lock in process_entries");
+        let last_ids = &mut self
+            .last_ids
+            .write()
+            .expect("'last_ids' write lock in process_entries");
+        let last_ids_sigs = &mut self
+            .last_ids_sigs
+            .write()
+            .expect("'last_ids_sigs' write lock in process_entries");
+
+        let mut entry_count = 0;
+        let mut ids = Vec::new();
+        for entry in entries {
+            entry_count += 1;
+            if !entry.transactions.is_empty() {
+                let rent_due = self.register_entry_ids(&ids, last_ids, last_ids_sigs);
+                self.settle_entry_ids(&ids, rent_due, accounts);
+                self.notify_entries(&ids);
+                ids.clear();
+                let results =
+                    self.replay_transactions(&entry.transactions, accounts, last_ids_sigs);
+                for result in results {
+                    result?;
+                }
+            } else {
+                self.tick_height.fetch_add(1, Ordering::Relaxed);
+            }
+            if !entry.has_more {
+                ids.push(entry.id);
+            }
+        }
+        let rent_due = self.register_entry_ids(&ids, last_ids, last_ids_sigs);
+        self.settle_entry_ids(&ids, rent_due, accounts);
+        self.notify_entries(&ids);
+        Ok(entry_count)
+    }
+
+    /// Append entry blocks to the ledger, verifying them along the way, and
+    /// return the entry height after them.
+    fn process_blocks<I>(
+        &self,
+        start_hash: Hash,
+        entries: I,
+        tail: &mut Vec<Entry>,
+        tail_idx: &mut usize,
+        mut entry_count: u64,
+    ) -> Result<u64>
+    where
+        I: IntoIterator<Item = Entry>,
+    {
+        // Ledger verification needs to be parallelized, but we can't pull the whole
+        // thing into memory. We therefore chunk it.
+        let mut id = start_hash;
+        let domain = self.poh_domain();
+        for block in &entries.into_iter().chunks(VERIFY_BLOCK_SIZE) {
+            let block: Vec<_> = block.collect();
+            if !block.verify_in_domain(&id, &domain) {
+                warn!("Ledger proof of history failed at entry: {}", entry_count);
+                return Err(BankError::LedgerVerificationFailed);
+            }
+            id = block.last().unwrap().id;
+            entry_count += self.process_entries_tail(block, tail, tail_idx)?;
+        }
+        Ok(entry_count)
+    }
+
+    /// Process a full ledger.
+    pub fn process_ledger<I>(&self, entries: I) -> Result<(u64, Vec<Entry>)>
+    where
+        I: IntoIterator<Item = Entry>,
+    {
+        let mut entries = entries.into_iter();
+
+        // The first item in the ledger is required to be an entry with zero num_hashes,
+        // which implies its id can be used as the ledger's seed.
+        let entry0 = entries.next().expect("invalid ledger: empty");
+
+        // The second item in the ledger starts with a special transaction where the to
+        // and from fields are the same. That transaction should be treated as a deposit,
+        // not a transfer to oneself. Any grants from the mint follow it.
+        let entry1 = entries
+            .next()
+            .expect("invalid ledger: need at least 2 entries");
+        self.apply_genesis(&entry1.transactions);
+        self.register_entry_id(&entry0.id);
+        self.register_entry_id(&entry1.id);
+        self.tick_height.fetch_add(1, Ordering::Relaxed);
+        let entry1_id = entry1.id;
+        self.set_poh_seed(entry0.id);
+
+        let mut tail = Vec::with_capacity(WINDOW_SIZE as usize);
+        tail.push(entry0);
+        tail.push(entry1);
+        let mut tail_idx = 2;
+        let entry_count = self.process_blocks(entry1_id, entries, &mut tail, &mut tail_idx, 2)?;
+
+        // check f we need to rotate tail
+        if tail.len() == WINDOW_SIZE as usize {
+            tail.rotate_left(tail_idx)
+        }
+
+        Ok((entry_count, tail))
+    }
+
+    /// Process the rest of a ledger whose first `entry_height` entries the bank
+    /// already holds the state after, as it does once `use_accounts_db` loads a
+    /// saved state. Those entries aren't verified again, only checked to end
+    /// with `last_entry_id`.
+    pub fn process_ledger_from<I>(
+        &self,
+        entries: I,
+        entry_height: u64,
+        last_entry_id: &Hash,
+    ) -> Result<(u64, Vec<Entry>)>
+    where
+        I: IntoIterator<Item = Entry>,
+    {
+        let mut entries = entries.into_iter();
+        let entry0 = entries.next().expect("invalid ledger: empty");
+        self.set_poh_seed(entry0.id);
+
+        let mut tail = Vec::with_capacity(WINDOW_SIZE as usize);
+        let mut tail_idx = 0;
+        let mut last_id = entry0.id;
+        push_tail(entry0, &mut tail, &mut tail_idx);
+        for _ in 1..entry_height {
+            let entry = entries.next().ok_or(BankError::LedgerVerificationFailed)?;
+            last_id = entry.id;
+            push_tail(entry, &mut tail, &mut tail_idx);
+        }
+        if last_id != *last_entry_id {
+            warn!("Ledger differs from saved accounts at: {}", entry_height);
+            return Err(BankError::LedgerVerificationFailed);
+        }
+        let entry_count =
+            self.process_blocks(last_id, entries, &mut tail, &mut tail_idx, entry_height)?;
+
+        if tail.len() == WINDOW_SIZE as usize {
+            tail.rotate_left(tail_idx)
+        }
+
+        Ok((entry_count, tail))
+    }
+
+    /// Take the domain the ledger starting with the entry `seed` names.
+    fn set_poh_seed(&self, seed: Hash) {
+        // the ledger names its domain, but the hash function is the node's choice
+        let mut poh_domain = self.poh_domain.write().unwrap();
+        *poh_domain = PohDomain {
+            hash: poh_domain.hash,
+            ..PohDomain::new(seed, 0)
+        };
+    }
+
+    /// Keep the accounts in `db` from now on, taking up the state it was last
+    /// saved with, if any. Call it on a new bank, before processing the
+    /// ledger. Returns the entry height and last entry id of the saved state,
+    /// which the ledger can be processed from.
+    pub fn use_accounts_db(&self, db: AccountsDb) -> io::Result<Option<(u64, Hash)>> {
+        let saved = match db.state() {
+            Some(state) => {
+                let saved: SavedState = deserialize(state).map_err(err_bincode_to_io)?;
+                Some(saved)
+            }
+            None => None,
+        };
+        self.store.write().unwrap().db = Some(db);
+        Ok(saved.map(|saved| {
+            self.accounts.write().unwrap().clear();
+            self.accounts_hash.write().unwrap().mark_all();
+            self.restore_state(saved)
+        }))
+    }
+
+    /// Take up the state in `saved`, but for the accounts, and return the entry
+    /// height and last entry id it's as of.
+    fn restore_state(&self, saved: SavedState) -> (u64, Hash) {
+        let (entry_height, last_entry_id, ids, sigs, plans, voters, count, ticks, id_count, active) =
+            saved;
+        let mut last_ids = self.last_ids.write().unwrap();
+        let mut last_ids_sigs = self.last_ids_sigs.write().unwrap();
+        let mut pending = self.pending.write().unwrap();
+        let mut authorized_voters = self.authorized_voters.write().unwrap();
+        let mut features = self.features.write().unwrap();
+        *last_ids = ids;
+        *last_ids_sigs = sigs;
+        *pending = plans;
+        *authorized_voters = voters;
+        *features = active;
+        self.transaction_count.store(count, Ordering::Relaxed);
+        self.tick_height.store(ticks, Ordering::Relaxed);
+        self.entry_id_count.store(id_count, Ordering::Relaxed);
+        (entry_height, last_entry_id)
+    }
+
+    /// Write the bank's whole state as of the entry `last_entry_id` at
+    /// `entry_height` to the file at `path`, which `from_snapshot` can start a
+    /// bank from. Accounts kept in an accounts db are written too.
+    pub fn serialize_snapshot(
+        &self,
+        path: &str,
+        entry_height: u64,
+        last_entry_id: &Hash,
+    ) -> io::Result<()> {
+        let accounts = self.accounts.read().unwrap();
+        let last_ids = self.last_ids.read().unwrap();
+        let last_ids_sigs = self.last_ids_sigs.read().unwrap();
+        let pending = self.pending.read().unwrap();
+        let authorized_voters = self.authorized_voters.read().unwrap();
+        let features = self.features.read().unwrap();
+        let store = self.store.read().unwrap();
+        let stored = store
+            .unloaded(&accounts)
+            .map(|pubkey| (*pubkey, store.load(pubkey).unwrap()));
+        let all_accounts: Vec<_> = accounts
+            .iter()
+            .map(|(pubkey, account)| (*pubkey, account.clone()))
+            .chain(stored)
+            .collect();
+        let state = (
+            entry_height,
+            last_entry_id,
+            &*last_ids,
+            &*last_ids_sigs,
+            &*pending,
+            &*authorized_voters,
+            self.transaction_count(),
+            self.tick_height() as usize,
+            self.entry_id_count(),
+            &*features,
+        );
+
+        // a crash while writing leaves any earlier snapshot at `path` alone
+        let tmp_path = format!("{}.tmp", path);
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serialize_into(&mut writer, &(&all_accounts, &state)).map_err(err_bincode_to_io)?;
+            writer.flush()?;
+        }
+        fs::rename(tmp_path, path)
+    }
+
+    /// Start a bank from the snapshot `serialize_snapshot` wrote to the file at
+    /// `path`. Returns it along with the entry height and last entry id the
+    /// snapshot is as of, which the rest of the ledger can be processed from.
+    pub fn from_snapshot(path: &str) -> io::Result<(Self, u64, Hash)> {
+        let reader = BufReader::new(File::open(path)?);
+        let (accounts, saved): (Vec<(Pubkey, Account)>, SavedState) =
+            deserialize_from(reader).map_err(err_bincode_to_io)?;
+        let bank = Bank::default();
+        *bank.accounts.write().unwrap() = accounts.into_iter().collect();
+        bank.accounts_hash.write().unwrap().mark_all();
+        let (entry_height, last_entry_id) = bank.restore_state(saved);
+        Ok((bank, entry_height, last_entry_id))
+    }
+
+    /// Write the accounts changed since the last save to the accounts db, and
+    /// commit them along with the rest of the bank's state as of the entry
+    /// `last_entry_id` at `entry_height`. The changed accounts are dropped from
+    /// memory, and so are the open checkpoints, since they can't be undone on
+    /// disk. Does nothing without an accounts db, and returns whether there
+    /// was one.
+    pub fn save_accounts(&self, entry_height: u64, last_entry_id: &Hash) -> io::Result<bool> {
+        let mut accounts = self.accounts.write().unwrap();
+        let last_ids = self.last_ids.read().unwrap();
+        let last_ids_sigs = self.last_ids_sigs.read().unwrap();
+        let pending = self.pending.read().unwrap();
+        let authorized_voters = self.authorized_voters.read().unwrap();
+        let features = self.features.read().unwrap();
+        let mut store = self.store.write().unwrap();
+        let mut journals = self
+            .journals
+            .write()
+            .expect("'journals' write lock in save_accounts");
+        if store.db.is_none() {
+            return Ok(false);
+        }
+        let state = (
+            entry_height,
+            last_entry_id,
+            &*last_ids,
+            &*last_ids_sigs,
+            &*pending,
+            &*authorized_voters,
+            self.transaction_count(),
+            self.tick_height() as usize,
+            self.entry_id_count(),
+            &*features,
+        );
+        let state = serialize(&state).map_err(err_bincode_to_io)?;
+
+        let store = &mut *store;
+        let db = store.db.as_mut().unwrap();
+        let changed = accounts
+            .iter()
+            .map(|(pubkey, account)| (*pubkey, Some(account.clone())));
+        let purged = store
+            .purged
+            .iter()
+            .filter(|pubkey| !accounts.contains_key(pubkey))
+            .map(|pubkey| (*pubkey, None));
+        db.store(changed.chain(purged))?;
+        db.commit(state)?;
+
+        inc_new_counter_info!("bank-save_accounts", accounts.len());
+        accounts.clear();
+        store.purged.clear();
+        store.saves += 1;
+        journals.stack.clear();
+        self.open_checkpoints.store(0, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// Process a Witness Signature. Any payment plans waiting on this signature
+    /// will progress one step. Returns the payments made.
+    fn apply_signature(
+        &self,
+        from: Pubkey,
+        signature: Signature,
+        accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Result<Vec<Payment>> {
+        let mut payments = vec![];
+        let mut pending = self.pending.write().expect("write() in apply_signature");
+        self.journal(|journal| save_entry(&mut journal.pending, &signature, &pending));
+        if let Occupied(mut e) = pending.entry(signature) {
+            e.get_mut().plan.apply_witness(&Witness::Signature, &from);
+            if let Some(payment) = e.get().plan.final_payment() {
+                self.journal(|journal| save_entry(&mut journal.accounts, &payment.to, accounts));
+                self.apply_payment(&payment, accounts);
+                payments.push(payment);
+                e.remove_entry();
+            }
+        };
+
+        Ok(payments)
+    }
+
+    /// Process a Witness Timestamp. Any payment plans waiting on this timestamp
+    /// will progress one step. Returns the payments made.
+    fn apply_timestamp(
+        &self,
+        from: Pubkey,
+        dt: DateTime<Utc>,
+        accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Result<Vec<Payment>> {
+        // Check to see if any timelocked transactions can be completed.
+        let mut completed = vec![];
+        let mut payments = vec![];
+
+        // Hold 'pending' write lock until the end of this function. Otherwise another thread can
+        // double-spend if it enters before the modified plan is removed from 'pending'.
+        let mut pending = self
+            .pending
+            .write()
+            .expect("'pending' write lock in apply_timestamp");
+        self.journal(|journal| {
+            for signature in pending.keys() {
+                save_entry(&mut journal.pending, signature, &pending);
+            }
+        });
+        for (key, pending_payment) in pending.iter_mut() {
+            let plan = &mut pending_payment.plan;
+            plan.apply_witness(&Witness::Timestamp(dt), &from);
+            if let Some(payment) = plan.final_payment() {
+                self.journal(|journal| save_entry(&mut journal.accounts, &payment.to, accounts));
+                self.apply_payment(&payment, accounts);
+                payments.push(payment);
+                completed.push(key.clone());
+            }
+        }
+
+        for key in completed {
+            pending.remove(&key);
+        }
+
+        Ok(payments)
+    }
+
+    /// Cancel the contract `signature` declared on behalf of `from`, if it's
+    /// the contract's cancel authority, and return its tokens to the account
+    /// that funded it. Returns the payment made.
+    fn cancel(
+        &self,
+        from: &Pubkey,
+        signature: &Signature,
+        accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Option<Payment> {
+        let mut pending = self
+            .pending
+            .write()
+            .expect("'pending' write lock in cancel");
+        let refund = pending.get(signature)?.refund(from)?;
+        self.journal(|journal| {
+            save_entry(&mut journal.pending, signature, &pending);
+            save_entry(&mut journal.accounts, &refund.to, accounts);
+        });
+        pending.remove(signature);
+        self.apply_payment(&refund, accounts);
+        Some(refund)
+    }
+
+    /// Create, sign, and process a Transaction from `keypair` to `to` of
+    /// `n` tokens where `last_id` is the last Entry ID observed by the client.
+    pub fn transfer(
+        &self,
+        n: i64,
+        keypair: &Keypair,
+        to: Pubkey,
+        last_id: Hash,
+    ) -> Result<Signature> {
+        let tx = Transaction::new(keypair, to, n, last_id);
+        let signature = tx.signature;
+        self.process_transaction(&tx).map(|_| signature)
+    }
+
+    /// Create, sign, and process a postdated Transaction from `keypair`
+    /// to `to` of `n` tokens on `dt` where `last_id` is the last Entry ID
+    /// observed by the client.
+    pub fn transfer_on_date(
+        &self,
+        n: i64,
+        keypair: &Keypair,
+        to: Pubkey,
+        dt: DateTime<Utc>,
+        last_id: Hash,
+    ) -> Result<Signature> {
+        let tx = Transaction::new_on_date(keypair, to, dt, n, last_id);
+        let signature = tx.signature;
+        self.process_transaction(&tx).map(|_| signature)
+    }
+
+    pub fn get_balance(&self, pubkey: &Pubkey) -> i64 {
+        self.get_account(pubkey).map(|a| a.tokens).unwrap_or(0)
+    }
+
+    pub fn get_account(&self, pubkey: &Pubkey) -> Option<Account> {
+        let accounts = self
+            .accounts
+            .read()
+            .expect("'accounts' read lock in get_balance");
+        self.lookup_account(pubkey, &accounts)
+    }
+
+    /// Every account, sorted by address, and the last id they're the state as of.
+    pub fn accounts_snapshot(&self) -> (Hash, Vec<(Pubkey, Account)>) {
+        let accounts = self
+            .accounts
+            .read()
+            .expect("'accounts' read lock in accounts_snapshot");
+        // no transaction lands while the accounts lock is held
+        let last_id = self.last_id();
+        let store = self
+            .store
+            .read()
+            .expect("'store' read lock in accounts_snapshot");
+        let stored = store
+            .unloaded(&accounts)
+            .map(|pubkey| (*pubkey, store.load(pubkey).unwrap()));
+        let mut snapshot: Vec<_> = accounts
+            .iter()
+            .map(|(pubkey, account)| (*pubkey, account.clone()))
+            .chain(stored)
+            .collect();
+        snapshot.sort_by_key(|(pubkey, _)| *pubkey);
+        (last_id, snapshot)
+    }
+
+    pub fn account_count(&self) -> usize {
+        let accounts = self
+            .accounts
+            .read()
+            .expect("'accounts' read lock in account_count");
+        let store = self
+            .store
+            .read()
+            .expect("'store' read lock in account_count");
+        accounts.len() + store.unloaded(&accounts).count()
+    }
+
+    /// The address of the `i`th synthetic account.
+    pub fn synthetic_pubkey(i: u64) -> Pubkey {
+        let mut seed = [0u8; 8];
+        for (k, byte) in seed.iter_mut().enumerate() {
+            *byte = (i >> (8 * k)) as u8;
+        }
+        Pubkey::new(hash(&seed).as_ref())
+    }
+
+    /// Add `num` accounts of `tokens` each at `synthetic_pubkey(0..num)`,
+    /// without any transactions, to see how the bank holds up at sizes a test
+    /// ledger would take far too long to reach. The tokens come from nowhere,
+    /// so never call this on a bank that's part of a cluster.
+    pub fn add_synthetic_accounts(&self, num: u64, tokens: i64) {
+        let mut accounts = self
+            .accounts
+            .write()
+            .expect("'accounts' write lock in add_synthetic_accounts");
+        accounts.reserve(num as usize);
+        for i in 0..num {
+            let pubkey = Self::synthetic_pubkey(i);
+            self.load_account(&pubkey, &mut accounts);
+            self.journal(|journal| save_entry(&mut journal.accounts, &pubkey, &accounts));
+            self.touch_account(&pubkey);
+            accounts
+                .entry(pubkey)
+                .or_insert_with(Account::default)
+                .tokens += tokens;
+        }
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.transaction_count.load(Ordering::Relaxed)
+    }
+
+    pub fn tick_height(&self) -> u64 {
+        self.tick_height.load(Ordering::Relaxed) as u64
+    }
+
+    /// The number of entry IDs registered since the start of the ledger.
+    pub fn entry_id_count(&self) -> usize {
+        self.entry_id_count.load(Ordering::Relaxed)
+    }
+
+    /// Whether a transaction with `signature` was processed successfully.
+    pub fn has_signature(&self, signature: &Signature) -> bool {
+        self.get_signature_status(signature) == Some(Ok(()))
+    }
+
+    /// How the transaction with `signature` fared, or `None` if the bank hasn't
+    /// seen it or its `last_id` has expired.
+    pub fn get_signature_status(&self, signature: &Signature) -> Option<Result<()>> {
+        self.get_signature_statuses(slice::from_ref(signature))
+            .pop()
+            .unwrap()
+    }
+
+    /// The status of each of `signatures`, looked up under one lock.
+    pub fn get_signature_statuses(&self, signatures: &[Signature]) -> Vec<Option<Result<()>>> {
+        let last_ids_sigs = self
+            .last_ids_sigs
+            .read()
+            .expect("'last_ids_sigs' read lock");
+        signatures
+            .iter()
+            .map(|signature| {
+                // a failed attempt under one last_id may have been retried
+                // successfully under another
+                let mut status = None;
+                for (_hash, signatures) in last_ids_sigs.iter() {
+                    match signatures.0.get(signature) {
+                        Some(Ok(())) => return Some(Ok(())),
+                        Some(err) => status = Some(err.clone()),
+                        None => (),
+                    }
+                }
+                status
+            })
+            .collect()
+    }
+
+    pub fn finality(&self) -> usize {
+        self.finality_time.load(Ordering::Relaxed)
+    }
+
+    pub fn set_finality(&self, finality: usize) {
+        self.finality_time.store(finality, Ordering::Relaxed);
+    }
+
+    pub fn recent_fees(&self) -> RecentFees {
+        self.recent_fees
+            .read()
+            .expect("'recent_fees' read lock in recent_fees")
+            .clone()
+    }
+
+    /// Record the `fees` of the transactions banked from a batch of `queue_depth`
+    /// verified transactions.
+    pub fn record_fees(&self, fees: &[i64], queue_depth: usize) {
+        let mut recent_fees = self
+            .recent_fees
+            .write()
+            .expect("'recent_fees' write lock in record_fees");
+        recent_fees.fees.extend(fees);
+        while recent_fees.fees.len() > MAX_RECENT_FEES {
+            recent_fees.fees.pop_front();
+        }
+        recent_fees.queue_depth = queue_depth;
+    }
+
+    /// The costs of the last `MAX_RECENT_SLOT_COSTS` slots this node wrote as
+    /// leader, oldest first.
+    pub fn recent_slot_costs(&self) -> Vec<SlotCost> {
+        self.recent_slot_costs
+            .read()
+            .expect("'recent_slot_costs' read lock in recent_slot_costs")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Record the costs of slots the write stage finished writing.
+    pub fn record_slot_costs(&self, costs: &[SlotCost]) {
+        let mut recent_slot_costs = self
+            .recent_slot_costs
+            .write()
+            .expect("'recent_slot_costs' write lock in record_slot_costs");
+        recent_slot_costs.extend(costs);
+        while recent_slot_costs.len() > MAX_RECENT_SLOT_COSTS {
+            recent_slot_costs.pop_front();
+        }
+    }
+
+    /// Copy the bank's state.
+    pub fn snapshot(&self) -> Snapshot {
+        let accounts = self.accounts.read().unwrap();
+        let last_ids = self.last_ids.read().unwrap();
+        let last_ids_sigs = self.last_ids_sigs.read().unwrap();
+        let pending = self.pending.read().unwrap();
+        let authorized_voters = self.authorized_voters.read().unwrap();
+        let features = self.features.read().unwrap();
+        let store = self.store.read().unwrap();
+        Snapshot {
+            accounts: accounts.clone(),
+            pending: pending.clone(),
+            authorized_voters: authorized_voters.clone(),
+            features: features.clone(),
+            last_ids: last_ids.clone(),
+            last_ids_sigs: last_ids_sigs.clone(),
+            transaction_count: self.transaction_count(),
+            tick_height: self.tick_height() as usize,
+            entry_id_count: self.entry_id_count(),
+            purged: store.purged.clone(),
+            saves: store.saves,
+        }
+    }
+
+    /// Restore the state the bank had when `snapshot` was taken. The accounts
+    /// must not have been saved to the accounts db since.
+    pub fn restore(&self, snapshot: &Snapshot) {
+        let mut accounts = self.accounts.write().unwrap();
+        let mut last_ids = self.last_ids.write().unwrap();
+        let mut last_ids_sigs = self.last_ids_sigs.write().unwrap();
+        let mut pending = self.pending.write().unwrap();
+        let mut authorized_voters = self.authorized_voters.write().unwrap();
+        let mut features = self.features.write().unwrap();
+        let mut store = self.store.write().unwrap();
+        assert_eq!(
+            store.saves, snapshot.saves,
+            "the accounts were saved since the snapshot"
+        );
+        let changed: HashSet<Pubkey> = accounts
+            .keys()
+            .chain(snapshot.accounts.keys())
+            .chain(store.purged.symmetric_difference(&snapshot.purged))
+            .cloned()
+            .filter(|pubkey| {
+                let tokens = |accounts: &HashMap<Pubkey, Account>| {
+                    accounts.get(pubkey).map(|account| account.tokens)
+                };
+                tokens(&accounts) != tokens(&snapshot.accounts)
+                    || store.purged.contains(pubkey) != snapshot.purged.contains(pubkey)
+            })
+            .collect();
+        *accounts = snapshot.accounts.clone();
+        *last_ids = snapshot.last_ids.clone();
+        *last_ids_sigs = snapshot.last_ids_sigs.clone();
+        *pending = snapshot.pending.clone();
+        *authorized_voters = snapshot.authorized_voters.clone();
+        *features = snapshot.features.clone();
+        store.purged = snapshot.purged.clone();
+        self.transaction_count
+            .store(snapshot.transaction_count, Ordering::Relaxed);
+        self.tick_height
+            .store(snapshot.tick_height, Ordering::Relaxed);
+        self.entry_id_count
+            .store(snapshot.entry_id_count, Ordering::Relaxed);
+        // the changes the open checkpoints saved no longer lead to this state
+        self.journals
+            .write()
+            .expect("'journals' write lock in restore")
+            .stack
+            .clear();
+        self.open_checkpoints.store(0, Ordering::Relaxed);
+        let mut accounts_hash = self.accounts_hash.write().unwrap();
+        for pubkey in &changed {
+            accounts_hash.mark(pubkey);
+        }
+        // roots recorded on a fork the bank has left behind no longer apply
+        accounts_hash.retain_recorded(|last_id| last_ids_sigs.contains_key(last_id));
+    }
+
+    /// Start saving changes so they can be undone by `rollback_to`, for
+    /// instance to apply entries from a leader that may turn out invalid.
+    /// Nothing is copied until it changes, but from then on it's kept until
+    /// the checkpoint is rolled back or confirmed.
+    pub fn checkpoint(&self) -> CheckpointId {
+        // no batch may be half applied when the checkpoint is taken
+        let _accounts = self.accounts.read().unwrap();
+        let _last_ids = self.last_ids.read().unwrap();
+        let _last_ids_sigs = self.last_ids_sigs.read().unwrap();
+        let _pending = self.pending.read().unwrap();
+        let _authorized_voters = self.authorized_voters.read().unwrap();
+        let _features = self.features.read().unwrap();
+        let _store = self.store.read().unwrap();
+        let mut journals = self
+            .journals
+            .write()
+            .expect("'journals' write lock in checkpoint");
+        let id = journals.next_id;
+        journals.next_id += 1;
+        journals.stack.push(Journal {
+            id,
+            accounts: HashMap::new(),
+            pending: HashMap::new(),
+            authorized_voters: HashMap::new(),
+            features: HashMap::new(),
+            purged: vec![],
+            last_ids: vec![],
+            transaction_count: self.transaction_count(),
+            tick_height: self.tick_height() as usize,
+            entry_id_count: self.entry_id_count(),
+        });
+        self.open_checkpoints
+            .store(journals.stack.len(), Ordering::Relaxed);
+        id
+    }
+
+    /// Undo every change since checkpoint `id` was taken. It and the
+    /// checkpoints taken after it are closed.
+    pub fn rollback_to(&self, id: CheckpointId) -> Result<()> {
+        let mut accounts = self.accounts.write().unwrap();
+        let mut last_ids = self.last_ids.write().unwrap();
+        let mut last_ids_sigs = self.last_ids_sigs.write().unwrap();
+        let mut pending = self.pending.write().unwrap();
+        let mut authorized_voters = self.authorized_voters.write().unwrap();
+        let mut features = self.features.write().unwrap();
+        let mut store = self.store.write().unwrap();
+        let mut journals = self
+            .journals
+            .write()
+            .expect("'journals' write lock in rollback_to");
+        let mut accounts_hash = self
+            .accounts_hash
+            .write()
+            .expect("'accounts_hash' write lock in rollback_to");
+        let position = journals.position(id)?;
+        self.open_checkpoints.store(position, Ordering::Relaxed);
+        for journal in journals.stack.drain(position..).rev() {
+            for pubkey in journal.accounts.keys().chain(&journal.purged) {
+                accounts_hash.mark(pubkey);
+            }
+            restore_entries(journal.accounts, &mut accounts);
+            restore_entries(journal.pending, &mut pending);
+            restore_entries(journal.authorized_voters, &mut authorized_voters);
+            restore_entries(journal.features, &mut features.statuses);
+            for pubkey in journal.purged {
+                store.purged.remove(&pubkey);
+            }
+            for change in journal.last_ids.into_iter().rev() {
+                match change {
+                    LastIdChange::Registered(sigs) => {
+                        let id = last_ids.pop_back().expect("registered last_id");
+                        accounts_hash.forget(&id);
+                        match sigs {
+                            Some(sigs) => last_ids_sigs.insert(id, sigs),
+                            None => last_ids_sigs.remove(&id),
+                        };
+                    }
+                    LastIdChange::Evicted(id, sigs) => {
+                        last_ids.push_front(id);
+                        if let Some(sigs) = sigs {
+                            last_ids_sigs.insert(id, sigs);
+                        }
+                    }
+                    LastIdChange::Signature(id, signature, status) => {
+                        if let Some(entry) = last_ids_sigs.get_mut(&id) {
+                            match status {
+                                Some(status) => entry.0.insert(signature, status),
+                                None => entry.0.remove(&signature),
+                            };
+                        }
+                    }
+                }
+            }
+            self.transaction_count
+                .store(journal.transaction_count, Ordering::Relaxed);
+            self.tick_height
+                .store(journal.tick_height, Ordering::Relaxed);
+            self.entry_id_count
+                .store(journal.entry_id_count, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Keep the changes since checkpoint `id` was taken. It and the
+    /// checkpoints taken before it are closed, and the changes they saved
+    /// are dropped.
+    pub fn confirm_checkpoint(&self, id: CheckpointId) -> Result<()> {
+        let mut journals = self
+            .journals
+            .write()
+            .expect("'journals' write lock in confirm_checkpoint");
+        let position = journals.position(id)?;
+        journals.stack.drain(..=position);
+        self.open_checkpoints
+            .store(journals.stack.len(), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
     use bincode::serialize;
+    use budget::Budget;
     use entry::next_entry;
     use entry::Entry;
     use entry_writer::{self, EntryWriter};
     use hash::hash;
     use ledger;
+    use mint::Grant;
     use packet::BLOB_DATA_SIZE;
     use signature::KeypairUtil;
     use std;
+    use std::env;
+    use std::fs;
     use std::io::{BufReader, Cursor, Seek, SeekFrom};
     use std::mem::size_of;
+    use std::sync::Mutex;
+    use transaction::{Contract, MAX_MEMO_LEN};
 
     #[test]
     fn test_two_payments_to_one_party() {
@@ -671,66 +3000,596 @@ mod tests {
         let pubkey = Keypair::new().pubkey();
         let bank = Bank::new(&mint);
         assert_eq!(
-            bank.transfer(-1, &mint.keypair(), pubkey, mint.last_id()),
-            Err(BankError::NegativeTokens)
+            bank.transfer(-1, &mint.keypair(), pubkey, mint.last_id()),
+            Err(BankError::NegativeTokens)
+        );
+        assert_eq!(bank.transaction_count(), 0);
+    }
+
+    #[test]
+    fn test_fees_are_burned() {
+        let mint = Mint::new(10);
+        let pubkey = Keypair::new().pubkey();
+        let bank = Bank::new(&mint);
+        let tx = Transaction::new_taxed(&mint.keypair(), pubkey, 5, 2, mint.last_id());
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(bank.get_balance(&pubkey), 3);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 5);
+        let burned = 10 - bank.get_balance(&pubkey) - bank.get_balance(&mint.pubkey());
+        assert_eq!(burned, 2 * FEE_BURN_PERCENT as i64 / 100);
+    }
+
+    #[test]
+    fn test_fee_payer() {
+        let mint = Mint::new_with_features(10, vec![Feature::FeePayer]);
+        let bank = Bank::new(&mint);
+        let fee_payer = Keypair::new();
+        let pubkey = Keypair::new().pubkey();
+        bank.transfer(3, &mint.keypair(), fee_payer.pubkey(), mint.last_id())
+            .unwrap();
+
+        // the fee comes on top of the payment, from the fee payer
+        let tx = Transaction::new_with_fee_payer(
+            &mint.keypair(),
+            &fee_payer,
+            pubkey,
+            5,
+            2,
+            mint.last_id(),
+        );
+        assert_eq!(bank.process_transaction(&tx), Ok(()));
+        assert_eq!(bank.get_balance(&pubkey), 5);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 2);
+        assert_eq!(bank.get_balance(&fee_payer.pubkey()), 1);
+
+        // a fee payer that can't cover the fee fails the whole transaction
+        let tx = Transaction::new_with_fee_payer(
+            &mint.keypair(),
+            &fee_payer,
+            pubkey,
+            1,
+            2,
+            mint.last_id(),
+        );
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::InsufficientFunds(fee_payer.pubkey()))
+        );
+        assert_eq!(bank.get_balance(&mint.pubkey()), 2);
+        assert_eq!(bank.get_balance(&fee_payer.pubkey()), 1);
+
+        let tx = Transaction::new_with_fee_payer(
+            &mint.keypair(),
+            &Keypair::new(),
+            pubkey,
+            1,
+            1,
+            mint.last_id(),
+        );
+        assert_matches!(
+            bank.process_transaction(&tx),
+            Err(BankError::AccountNotFound(_))
+        );
+    }
+
+    #[test]
+    fn test_feature_activation() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let fee_payer = Keypair::new();
+        bank.transfer(3, &mint.keypair(), fee_payer.pubkey(), mint.last_id())
+            .unwrap();
+        let pay_with_fee_payer = |bank: &Bank| {
+            let tx = Transaction::new_with_fee_payer(
+                &mint.keypair(),
+                &fee_payer,
+                fee_payer.pubkey(),
+                1,
+                1,
+                bank.last_id(),
+            );
+            bank.process_transaction(&tx)
+        };
+        assert_eq!(
+            pay_with_fee_payer(&bank),
+            Err(BankError::FeatureNotActive(Feature::FeePayer))
+        );
+
+        // only the mint may activate a feature
+        let tx =
+            Transaction::new_activate_feature(&fee_payer, Feature::FeePayer, mint.last_id(), 0);
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::UnauthorizedFeatureActivation(Feature::FeePayer))
+        );
+
+        // and it takes effect once the rent epoch ends
+        let checkpoint = bank.checkpoint();
+        let tx = Transaction::new_activate_feature(
+            &mint.keypair(),
+            Feature::FeePayer,
+            mint.last_id(),
+            0,
+        );
+        assert_eq!(bank.process_transaction(&tx), Ok(()));
+        assert!(!bank.is_feature_active(Feature::FeePayer));
+        register_rent_epoch(&bank);
+        assert!(bank.is_feature_active(Feature::FeePayer));
+        assert_eq!(pay_with_fee_payer(&bank), Ok(()));
+
+        bank.rollback_to(checkpoint).unwrap();
+        assert!(!bank.is_feature_active(Feature::FeePayer));
+    }
+
+    #[test]
+    fn test_memo_too_long() {
+        let mint = Mint::new(10);
+        let pubkey = Keypair::new().pubkey();
+        let bank = Bank::new(&mint);
+        let memo = "x".repeat(MAX_MEMO_LEN);
+        let tx = Transaction::new_with_memo(&mint.keypair(), pubkey, 1, mint.last_id(), &memo);
+        assert_eq!(bank.process_transaction(&tx), Ok(()));
+        assert_eq!(bank.get_balance(&pubkey), 1);
+
+        let memo = format!("{}x", memo);
+        let tx = Transaction::new_with_memo(&mint.keypair(), pubkey, 1, mint.last_id(), &memo);
+        assert_eq!(bank.process_transaction(&tx), Err(BankError::MemoTooLong));
+        assert_eq!(bank.get_balance(&pubkey), 1);
+        assert_eq!(bank.transaction_count(), 1);
+    }
+
+    #[test]
+    fn test_account_not_found() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let keypair = Keypair::new();
+        assert_eq!(
+            bank.transfer(1, &keypair, mint.pubkey(), mint.last_id()),
+            Err(BankError::AccountNotFound(keypair.pubkey()))
+        );
+        assert_eq!(bank.transaction_count(), 0);
+    }
+
+    #[test]
+    fn test_insufficient_funds() {
+        let mint = Mint::new(11_000);
+        let bank = Bank::new(&mint);
+        let pubkey = Keypair::new().pubkey();
+        bank.transfer(1_000, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+        assert_eq!(bank.transaction_count(), 1);
+        assert_eq!(
+            bank.transfer(10_001, &mint.keypair(), pubkey, mint.last_id()),
+            Err(BankError::InsufficientFunds(mint.pubkey()))
+        );
+        assert_eq!(bank.transaction_count(), 1);
+
+        let mint_pubkey = mint.keypair().pubkey();
+        assert_eq!(bank.get_balance(&mint_pubkey), 10_000);
+        assert_eq!(bank.get_balance(&pubkey), 1_000);
+    }
+
+    #[test]
+    fn test_payments_are_atomic() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let pubkey0 = Keypair::new().pubkey();
+        let pubkey1 = Keypair::new().pubkey();
+        let payments = vec![
+            Payment {
+                tokens: 3,
+                to: pubkey0,
+            },
+            Payment {
+                tokens: 4,
+                to: pubkey1,
+            },
+        ];
+        let tx = Transaction::new_payments(&mint.keypair(), &payments, mint.last_id());
+        assert_eq!(bank.process_transaction(&tx), Ok(()));
+        assert_eq!(bank.get_balance(&mint.pubkey()), 3);
+        assert_eq!(bank.get_balance(&pubkey0), 3);
+        assert_eq!(bank.get_balance(&pubkey1), 4);
+        assert_eq!(bank.transaction_count(), 1);
+
+        // each payment alone is affordable, but not both together
+        let payments: Vec<_> = payments
+            .into_iter()
+            .map(|payment| Payment {
+                tokens: 2,
+                to: payment.to,
+            })
+            .collect();
+        let tx = Transaction::new_payments(&mint.keypair(), &payments, mint.last_id());
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::InsufficientFunds(mint.pubkey()))
+        );
+        assert_eq!(bank.get_balance(&mint.pubkey()), 3);
+        assert_eq!(bank.get_balance(&pubkey0), 3);
+        assert_eq!(bank.get_balance(&pubkey1), 4);
+        assert_eq!(bank.transaction_count(), 1);
+    }
+
+    #[test]
+    fn test_transfer_to_newb() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        let pubkey = Keypair::new().pubkey();
+        bank.transfer(500, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+        assert_eq!(bank.get_balance(&pubkey), 500);
+    }
+
+    #[test]
+    fn test_transfer_from_seed() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        let base = Keypair::new();
+        let owner = Keypair::new().pubkey();
+        let deposit = Pubkey::create_with_seed(&base.pubkey(), "deposit", &owner);
+        bank.transfer(500, &mint.keypair(), deposit, mint.last_id())
+            .unwrap();
+
+        // the base key spends from the derived address without holding tokens itself
+        let pubkey = Keypair::new().pubkey();
+        let tx = Transaction::new_from_seed(&base, "deposit", owner, pubkey, 200, bank.last_id());
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(bank.get_balance(&deposit), 300);
+        assert_eq!(bank.get_balance(&pubkey), 200);
+        assert_eq!(bank.get_balance(&base.pubkey()), 0);
+
+        // another seed names another address
+        let tx = Transaction::new_from_seed(&base, "other", owner, pubkey, 200, bank.last_id());
+        let other = Pubkey::create_with_seed(&base.pubkey(), "other", &owner);
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::AccountNotFound(other))
+        );
+
+        // and a key other than the base can't spend from the address
+        let thief = Keypair::new();
+        let tx = Transaction::new_from_seed(&thief, "deposit", owner, pubkey, 200, bank.last_id());
+        assert!(bank.process_transaction(&tx).is_err());
+        assert_eq!(bank.get_balance(&deposit), 300);
+    }
+
+    #[test]
+    fn test_userdata() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        let pubkey = mint.keypair().pubkey();
+
+        let mut tx = Transaction::new(&mint.keypair(), pubkey, 0, bank.last_id());
+        tx.userdata = vec![1, 2, 3];
+        let rv = bank.process_transaction(&tx);
+        assert!(rv.is_ok());
+        let account = bank.get_account(&pubkey);
+        assert!(account.is_some());
+        assert_eq!(account.unwrap().userdata, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_userdata_limits() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        let pubkey = mint.keypair().pubkey();
+        let tx_with = |len: usize| {
+            let mut tx = Transaction::new(&mint.keypair(), pubkey, 0, bank.last_id());
+            tx.userdata = vec![1; len];
+            tx
+        };
+        let save = |len: usize| bank.process_transaction(&tx_with(len));
+
+        let too_much = MAX_DATA_GROWTH_PER_TRANSACTION + 1;
+        assert_eq!(save(too_much), Err(BankError::AllocationTooLarge(pubkey)));
+        assert_eq!(bank.get_account(&pubkey).unwrap().userdata, vec![]);
+
+        // an account grows to the limit over several transactions
+        let mut len = 0;
+        while len < MAX_ACCOUNT_DATA_LEN {
+            len = (len + MAX_DATA_GROWTH_PER_TRANSACTION).min(MAX_ACCOUNT_DATA_LEN);
+            assert_eq!(save(len), Ok(()));
+        }
+        let too_large = MAX_ACCOUNT_DATA_LEN + 1;
+        assert_eq!(save(too_large), Err(BankError::AccountDataTooLarge(pubkey)));
+        let (result, _) = bank.simulate_transaction(&tx_with(too_large));
+        assert_eq!(result, Err(BankError::AccountDataTooLarge(pubkey)));
+        let account = bank.get_account(&pubkey).unwrap();
+        assert_eq!(account.userdata.len(), MAX_ACCOUNT_DATA_LEN);
+    }
+
+    #[test]
+    fn test_load_userdata() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        let program = Keypair::new();
+        let pubkey = program.pubkey();
+        let tx = Transaction::new(&mint.keypair(), pubkey, 1, bank.last_id());
+        bank.process_transaction(&tx).unwrap();
+
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        for tx in Transaction::new_load(&program, pubkey, &data, bank.last_id()) {
+            assert_eq!(bank.process_transaction(&tx), Ok(()));
+        }
+        assert_eq!(bank.get_account(&pubkey).unwrap().userdata, data);
+
+        let write = |keypair: &Keypair, pubkey: Pubkey, offset: u64| {
+            let tx = Transaction::new_write(keypair, pubkey, offset, vec![0; 8], bank.last_id());
+            bank.process_transaction(&tx)
+        };
+        assert_eq!(write(&program, pubkey, 192), Ok(()));
+        assert_eq!(
+            write(&program, pubkey, 193),
+            Err(BankError::DataOutOfBounds(pubkey))
+        );
+        assert_eq!(
+            write(&program, pubkey, u64::max_value()),
+            Err(BankError::DataOutOfBounds(pubkey))
+        );
+        assert_eq!(
+            write(&mint.keypair(), pubkey, 0),
+            Err(BankError::NotOwner(pubkey))
+        );
+        let missing = Keypair::new().pubkey();
+        assert_eq!(
+            write(&program, missing, 0),
+            Err(BankError::AccountNotFound(missing))
+        );
+
+        // a write is checked against what the transaction allocated before it,
+        // and one past the end fails the allocation along with it
+        let too_far = vec![
+            Instruction::Allocate(pubkey, 300),
+            Instruction::Write(pubkey, 296, vec![1; 8]),
+        ];
+        let tx = Transaction::new_with_instructions(&program, too_far, bank.last_id(), 0);
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::DataOutOfBounds(pubkey))
+        );
+        assert_eq!(bank.get_account(&pubkey).unwrap().userdata.len(), 200);
+
+        let too_much = 200 + MAX_DATA_GROWTH_PER_TRANSACTION as u64 + 1;
+        let tx = Transaction::new_allocate(&program, pubkey, too_much, bank.last_id());
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::AllocationTooLarge(pubkey))
+        );
+        let tx = Transaction::new_allocate(&program, pubkey, 0, bank.last_id());
+        assert_eq!(bank.process_transaction(&tx), Ok(()));
+        assert_eq!(bank.get_account(&pubkey).unwrap().userdata, vec![]);
+    }
+
+    #[test]
+    fn test_accounts_snapshot() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        let pubkey = Keypair::new().pubkey();
+        bank.transfer(500, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+        let (last_id, accounts) = bank.accounts_snapshot();
+        assert_eq!(last_id, bank.last_id());
+        assert_eq!(accounts.len(), 2);
+        assert!(accounts[0].0 < accounts[1].0);
+        for (pubkey, account) in accounts {
+            assert_eq!(account.tokens, bank.get_balance(&pubkey));
+        }
+    }
+
+    #[test]
+    fn test_add_synthetic_accounts() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        bank.add_synthetic_accounts(100, 5);
+        assert_eq!(bank.account_count(), 101);
+        assert_ne!(Bank::synthetic_pubkey(0), Bank::synthetic_pubkey(1));
+        assert_eq!(bank.get_balance(&Bank::synthetic_pubkey(99)), 5);
+        assert_eq!(bank.get_balance(&Bank::synthetic_pubkey(100)), 0);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 10_000);
+    }
+
+    #[test]
+    fn test_assign() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        let keypair = Keypair::new();
+        let program = Keypair::new();
+        bank.transfer(500, &mint.keypair(), keypair.pubkey(), mint.last_id())
+            .unwrap();
+        bank.transfer(1, &mint.keypair(), program.pubkey(), mint.last_id())
+            .unwrap();
+
+        // only the account's own key may assign it at first
+        let tx =
+            Transaction::new_assign(&program, keypair.pubkey(), program.pubkey(), bank.last_id());
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::NotOwner(keypair.pubkey()))
+        );
+        let tx =
+            Transaction::new_assign(&keypair, keypair.pubkey(), program.pubkey(), bank.last_id());
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(
+            bank.get_account(&keypair.pubkey()).unwrap().owner,
+            program.pubkey()
+        );
+
+        // from then on only the owner may, and it may hand over userdata
+        let tx = Transaction::new_assign(
+            &keypair,
+            keypair.pubkey(),
+            Pubkey::default(),
+            bank.last_id(),
+        );
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::NotOwner(keypair.pubkey()))
+        );
+        bank.accounts
+            .write()
+            .unwrap()
+            .get_mut(&keypair.pubkey())
+            .unwrap()
+            .userdata = vec![1, 2, 3];
+        let tx = Transaction::new_assign(
+            &program,
+            keypair.pubkey(),
+            Pubkey::default(),
+            bank.last_id(),
+        );
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(
+            bank.get_account(&keypair.pubkey()).unwrap().owner,
+            Pubkey::default()
         );
-        assert_eq!(bank.transaction_count(), 0);
-    }
 
-    #[test]
-    fn test_account_not_found() {
-        let mint = Mint::new(1);
-        let bank = Bank::new(&mint);
-        let keypair = Keypair::new();
+        // but an account's own key can't give away its userdata
+        let tx =
+            Transaction::new_assign(&keypair, keypair.pubkey(), program.pubkey(), bank.last_id());
         assert_eq!(
-            bank.transfer(1, &keypair, mint.pubkey(), mint.last_id()),
-            Err(BankError::AccountNotFound(keypair.pubkey()))
+            bank.process_transaction(&tx),
+            Err(BankError::AccountDataNotEmpty(keypair.pubkey()))
+        );
+
+        let pubkey = Keypair::new().pubkey();
+        let tx = Transaction::new_assign(&keypair, pubkey, program.pubkey(), bank.last_id());
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::AccountNotFound(pubkey))
         );
-        assert_eq!(bank.transaction_count(), 0);
     }
 
     #[test]
-    fn test_insufficient_funds() {
-        let mint = Mint::new(11_000);
+    fn test_durable_nonce() {
+        let mint = Mint::new(10_000);
         let bank = Bank::new(&mint);
+        let nonce_keypair = Keypair::new();
+        let nonce_pubkey = nonce_keypair.pubkey();
         let pubkey = Keypair::new().pubkey();
-        bank.transfer(1_000, &mint.keypair(), pubkey, mint.last_id())
+        bank.transfer(1, &mint.keypair(), nonce_pubkey, mint.last_id())
             .unwrap();
-        assert_eq!(bank.transaction_count(), 1);
+
+        // hand the nonce account to the mint, then store a nonce in it
+        let tx =
+            Transaction::new_assign(&nonce_keypair, nonce_pubkey, mint.pubkey(), bank.last_id());
+        bank.process_transaction(&tx).unwrap();
+        let tx = Transaction::new_advance_nonce(&nonce_keypair, nonce_pubkey, bank.last_id());
         assert_eq!(
-            bank.transfer(10_001, &mint.keypair(), pubkey, mint.last_id()),
-            Err(BankError::InsufficientFunds(mint.pubkey()))
+            bank.process_transaction(&tx),
+            Err(BankError::NotOwner(nonce_pubkey))
         );
-        assert_eq!(bank.transaction_count(), 1);
+        let tx = Transaction::new_advance_nonce(&mint.keypair(), nonce_pubkey, bank.last_id());
+        bank.process_transaction(&tx).unwrap();
+        let nonce = bank.get_account(&nonce_pubkey).unwrap().nonce().unwrap();
+        assert!(bank.count_valid_ids(&[nonce]).is_empty());
 
-        let mint_pubkey = mint.keypair().pubkey();
-        assert_eq!(bank.get_balance(&mint_pubkey), 10_000);
-        assert_eq!(bank.get_balance(&pubkey), 1_000);
+        // a transaction signed with the nonce in place of a recent entry ID
+        let plan = Plan::Budget(Budget::new_payment(10, pubkey));
+        let payment = Instruction::NewContract(Contract {
+            tokens: 10,
+            plan,
+            cancel_authority: None,
+        });
+        let tx =
+            Transaction::new_with_nonce(&mint.keypair(), nonce_pubkey, nonce, vec![payment], 0);
+        assert_eq!(bank.process_transaction(&tx), Ok(()));
+        assert_eq!(bank.get_balance(&pubkey), 10);
+        assert!(bank.has_signature(&tx.signature));
+
+        // the nonce moved on, so the transaction can't be replayed
+        let account = bank.get_account(&nonce_pubkey).unwrap();
+        assert_eq!(account.nonce(), Some(hash(tx.signature.as_ref())));
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::LastIdNotFound(nonce))
+        );
+        assert_eq!(bank.get_balance(&pubkey), 10);
+    }
+
+    /// A budget program that takes the tokens of every contract and pays none.
+    fn burn(
+        _bank: &Bank,
+        _tx: &Transaction,
+        _instruction: &Instruction,
+        _accounts: &mut HashMap<Pubkey, Account>,
+    ) -> Vec<Payment> {
+        vec![]
     }
 
     #[test]
-    fn test_transfer_to_newb() {
+    fn test_register_program() {
         let mint = Mint::new(10_000);
-        let bank = Bank::new(&mint);
+        let mut bank = Bank::new(&mint);
         let pubkey = Keypair::new().pubkey();
+        let budget_id = NativeProgram::Budget.id();
+        bank.register_program(budget_id, burn);
         bank.transfer(500, &mint.keypair(), pubkey, mint.last_id())
             .unwrap();
-        assert_eq!(bank.get_balance(&pubkey), 500);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 9_500);
+        assert_eq!(bank.get_balance(&pubkey), 0);
+
+        bank.programs.remove(&budget_id);
+        assert_eq!(
+            bank.transfer(400, &mint.keypair(), pubkey, mint.last_id()),
+            Err(BankError::UnknownProgram(budget_id))
+        );
+        assert_eq!(bank.get_balance(&mint.pubkey()), 9_500);
     }
 
     #[test]
-    fn test_userdata() {
+    fn test_authorize_voter() {
         let mint = Mint::new(10_000);
         let bank = Bank::new(&mint);
-        let pubkey = mint.keypair().pubkey();
+        let identity = Keypair::new();
+        let node = Keypair::new();
+        bank.transfer(500, &mint.keypair(), identity.pubkey(), mint.last_id())
+            .unwrap();
+        bank.transfer(500, &mint.keypair(), node.pubkey(), mint.last_id())
+            .unwrap();
+        let vote = |version| Vote {
+            version,
+            contact_info_version: 0,
+            identity: Some(identity.pubkey()),
+            accounts_hash: None,
+        };
 
-        let mut tx = Transaction::new(&mint.keypair(), pubkey, 0, bank.last_id());
-        tx.userdata = vec![1, 2, 3];
-        let rv = bank.process_transaction(&tx);
-        assert!(rv.is_ok());
-        let account = bank.get_account(&pubkey);
-        assert!(account.is_some());
-        assert_eq!(account.unwrap().userdata, vec![1, 2, 3]);
+        // a node may always vote for its own key
+        let own_vote = Vote {
+            identity: Some(node.pubkey()),
+            ..vote(1)
+        };
+        let tx = Transaction::new_vote(&node, own_vote, bank.last_id(), 0);
+        bank.process_transaction(&tx).unwrap();
+
+        // but only for an identity that authorized it
+        let tx = Transaction::new_vote(&node, vote(2), bank.last_id(), 0);
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::UnauthorizedVoter(identity.pubkey()))
+        );
+        let snapshot = bank.snapshot();
+        let tx = Transaction::new_authorize_voter(&identity, node.pubkey(), bank.last_id(), 0);
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(
+            bank.authorized_voter(&identity.pubkey()),
+            Some(node.pubkey())
+        );
+        let tx = Transaction::new_vote(&node, vote(3), bank.last_id(), 0);
+        bank.process_transaction(&tx).unwrap();
+
+        // authorizing another node rotates the first one out
+        let other = Keypair::new();
+        let tx = Transaction::new_authorize_voter(&identity, other.pubkey(), bank.last_id(), 0);
+        bank.process_transaction(&tx).unwrap();
+        let tx = Transaction::new_vote(&node, vote(4), bank.last_id(), 0);
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::UnauthorizedVoter(identity.pubkey()))
+        );
+
+        bank.restore(&snapshot);
+        assert_eq!(bank.authorized_voter(&identity.pubkey()), None);
     }
 
     #[test]
@@ -754,14 +3613,16 @@ mod tests {
 
         // Now, acknowledge the time in the condition occurred and
         // that pubkey's funds are now available.
-        bank.apply_timestamp(mint.pubkey(), dt).unwrap();
+        bank.apply_timestamp(mint.pubkey(), dt, &mut bank.accounts.write().unwrap())
+            .unwrap();
         assert_eq!(bank.get_balance(&pubkey), 1);
 
         // tx count is still 1, because we chose not to count timestamp transactions
         // tx count.
         assert_eq!(bank.transaction_count(), 1);
 
-        bank.apply_timestamp(mint.pubkey(), dt).unwrap(); // <-- Attack! Attempt to process completed transaction.
+        bank.apply_timestamp(mint.pubkey(), dt, &mut bank.accounts.write().unwrap())
+            .unwrap(); // <-- Attack! Attempt to process completed transaction.
         assert_ne!(bank.get_balance(&pubkey), 2);
     }
 
@@ -786,103 +3647,510 @@ mod tests {
         assert_eq!(bank.get_balance(&pubkey), 0);
 
         // Now, cancel the trancaction. Mint gets her funds back, pubkey never sees them.
-        bank.apply_signature(mint.pubkey(), signature).unwrap();
+        bank.apply_signature(mint.pubkey(), signature, &mut bank.accounts.write().unwrap())
+            .unwrap();
         assert_eq!(bank.get_balance(&mint.pubkey()), 1);
         assert_eq!(bank.get_balance(&pubkey), 0);
 
         // Assert cancel doesn't cause count to go backward.
         assert_eq!(bank.transaction_count(), 1);
 
-        bank.apply_signature(mint.pubkey(), signature).unwrap(); // <-- Attack! Attempt to cancel completed transaction.
+        bank.apply_signature(mint.pubkey(), signature, &mut bank.accounts.write().unwrap())
+            .unwrap(); // <-- Attack! Attempt to cancel completed transaction.
         assert_ne!(bank.get_balance(&mint.pubkey()), 2);
     }
 
-    #[test]
-    fn test_duplicate_transaction_signature() {
-        let mint = Mint::new(1);
-        let bank = Bank::new(&mint);
-        let signature = Signature::default();
-        assert!(
-            bank.reserve_signature_with_last_id(&signature, &mint.last_id())
-                .is_ok()
+    #[test]
+    fn test_cancel_escrow() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let pubkey = Keypair::new().pubkey();
+        let dt = Utc.ymd(2100, 1, 1).and_hms(0, 0, 0);
+        let escrow = Transaction::new_escrow(&mint.keypair(), pubkey, dt, 4, mint.last_id());
+        assert_eq!(bank.process_transaction(&escrow), Ok(()));
+        let on_date = Transaction::new_on_date(&mint.keypair(), pubkey, dt, 1, mint.last_id());
+        assert_eq!(bank.process_transaction(&on_date), Ok(()));
+        assert_eq!(bank.get_balance(&mint.pubkey()), 5);
+
+        // only a contract's cancel authority may cancel it
+        let tx = Transaction::new_cancel(&Keypair::new(), escrow.signature, mint.last_id());
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::NotCancelable(escrow.signature))
+        );
+        let tx = Transaction::new_cancel(&mint.keypair(), on_date.signature, mint.last_id());
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::NotCancelable(on_date.signature))
+        );
+
+        let tx = Transaction::new_cancel(&mint.keypair(), escrow.signature, mint.last_id());
+        assert_eq!(
+            bank.simulate_transaction(&tx),
+            (Ok(()), vec![(mint.pubkey(), 9)])
+        );
+        assert_eq!(bank.process_transaction(&tx), Ok(()));
+        assert_eq!(bank.get_balance(&mint.pubkey()), 9);
+        assert_eq!(bank.pending.read().unwrap().len(), 1);
+
+        // a canceled contract is gone, so the timestamp only pays the other
+        let tx = Transaction::new_timestamp(&mint.keypair(), dt, mint.last_id());
+        assert_eq!(bank.process_transaction(&tx), Ok(()));
+        assert_eq!(bank.get_balance(&pubkey), 1);
+        let tx = Transaction::new_cancel(&mint.keypair(), escrow.signature, bank.last_id());
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::NotCancelable(escrow.signature))
+        );
+    }
+
+    #[test]
+    fn test_simulate_transaction() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let pubkey = Keypair::new().pubkey();
+        let tx = Transaction::new(&mint.keypair(), pubkey, 400, mint.last_id());
+        assert_eq!(
+            bank.simulate_transaction(&tx),
+            (Ok(()), vec![(mint.pubkey(), 600), (pubkey, 400)])
+        );
+
+        // nothing was committed, so the same transaction still goes through
+        assert_eq!(bank.get_balance(&mint.pubkey()), 1_000);
+        assert_eq!(bank.get_balance(&pubkey), 0);
+        assert_eq!(bank.transaction_count(), 0);
+        assert_eq!(bank.get_signature_status(&tx.signature), None);
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(
+            bank.simulate_transaction(&tx).0,
+            Err(BankError::DuplicateSignature(tx.signature))
+        );
+
+        let tx = Transaction::new(&mint.keypair(), pubkey, 601, mint.last_id());
+        assert_eq!(
+            bank.simulate_transaction(&tx),
+            (Err(BankError::InsufficientFunds(mint.pubkey())), vec![])
+        );
+        let tx = Transaction::new(&mint.keypair(), pubkey, 1, Hash::default());
+        assert_eq!(
+            bank.simulate_transaction(&tx).0,
+            Err(BankError::LastIdNotFound(Hash::default()))
+        );
+
+        // a witness would release a pending payment, which stays pending
+        let dt = Utc::now();
+        bank.transfer_on_date(100, &mint.keypair(), pubkey, dt, mint.last_id())
+            .unwrap();
+        let tx = Transaction::new_timestamp(&mint.keypair(), dt, mint.last_id());
+        assert_eq!(
+            bank.simulate_transaction(&tx),
+            (Ok(()), vec![(mint.pubkey(), 500), (pubkey, 500)])
+        );
+        assert_eq!(bank.get_balance(&pubkey), 400);
+        assert_eq!(bank.pending.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_transaction_signature() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let signature = Signature::default();
+        assert!(
+            Bank::reserve_signature_with_last_id(
+                &signature,
+                &mint.last_id(),
+                &mut bank.last_ids_sigs.write().unwrap()
+            ).is_ok()
+        );
+        assert_eq!(
+            Bank::reserve_signature_with_last_id(
+                &signature,
+                &mint.last_id(),
+                &mut bank.last_ids_sigs.write().unwrap()
+            ),
+            Err(BankError::DuplicateSignature(signature))
+        );
+    }
+
+    #[test]
+    fn test_forget_signature() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let signature = Signature::default();
+        Bank::reserve_signature_with_last_id(
+            &signature,
+            &mint.last_id(),
+            &mut bank.last_ids_sigs.write().unwrap(),
+        ).unwrap();
+        Bank::forget_signature_with_last_id(
+            &signature,
+            &mint.last_id(),
+            &mut bank.last_ids_sigs.write().unwrap(),
+        );
+        assert!(
+            Bank::reserve_signature_with_last_id(
+                &signature,
+                &mint.last_id(),
+                &mut bank.last_ids_sigs.write().unwrap()
+            ).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_has_signature() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let signature = Signature::default();
+        Bank::reserve_signature_with_last_id(
+            &signature,
+            &mint.last_id(),
+            &mut bank.last_ids_sigs.write().unwrap(),
+        ).expect("reserve signature");
+        assert!(bank.has_signature(&signature));
+    }
+
+    #[test]
+    fn test_get_signature_statuses() {
+        let mint = Mint::new(2);
+        let bank = Bank::new(&mint);
+        let keypair = Keypair::new();
+        let tx0 = Transaction::new(&mint.keypair(), keypair.pubkey(), 1, mint.last_id());
+        let tx1 = Transaction::new(&keypair, mint.pubkey(), 2, mint.last_id());
+        let unknown = Signature::default();
+        assert!(bank.process_transaction(&tx0).is_ok());
+        assert_eq!(
+            bank.process_transaction(&tx1),
+            Err(BankError::InsufficientFunds(keypair.pubkey()))
+        );
+        assert_eq!(
+            bank.process_transaction(&tx0),
+            Err(BankError::DuplicateSignature(tx0.signature))
+        );
+
+        assert_eq!(
+            bank.get_signature_statuses(&[tx0.signature, tx1.signature, unknown]),
+            vec![
+                Some(Ok(())),
+                Some(Err(BankError::InsufficientFunds(keypair.pubkey()))),
+                None,
+            ]
+        );
+        assert!(bank.has_signature(&tx0.signature));
+        assert!(!bank.has_signature(&tx1.signature));
+
+        // a failed transaction can still land once it's funded
+        bank.transfer(1, &mint.keypair(), keypair.pubkey(), mint.last_id())
+            .unwrap();
+        assert!(bank.process_transaction(&tx1).is_ok());
+        assert_eq!(bank.get_signature_status(&tx1.signature), Some(Ok(())));
+    }
+
+    #[test]
+    fn test_reject_old_last_id() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let signature = Signature::default();
+        for i in 0..MAX_ENTRY_IDS {
+            let last_id = hash(&serialize(&i).unwrap()); // Unique hash
+            bank.register_entry_id(&last_id);
+        }
+        // Assert we're no longer able to use the oldest entry ID.
+        assert_eq!(
+            Bank::reserve_signature_with_last_id(
+                &signature,
+                &mint.last_id(),
+                &mut bank.last_ids_sigs.write().unwrap()
+            ),
+            Err(BankError::LastIdNotFound(mint.last_id()))
+        );
+    }
+
+    #[test]
+    fn test_signature_status_evicted_with_last_id() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let tx = Transaction::new(&mint.keypair(), Keypair::new().pubkey(), 2, mint.last_id());
+        let insufficient_funds = Err(BankError::InsufficientFunds(mint.pubkey()));
+        assert_eq!(bank.process_transaction(&tx), insufficient_funds);
+        assert_eq!(
+            bank.get_signature_status(&tx.signature),
+            Some(insufficient_funds)
+        );
+        for i in 0..MAX_ENTRY_IDS {
+            let last_id = hash(&serialize(&i).unwrap()); // Unique hash
+            bank.register_entry_id(&last_id);
+        }
+        assert_eq!(bank.get_signature_status(&tx.signature), None);
+    }
+
+    #[test]
+    fn test_count_valid_ids() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let ids: Vec<_> = (0..MAX_ENTRY_IDS)
+            .map(|i| {
+                let last_id = hash(&serialize(&i).unwrap()); // Unique hash
+                bank.register_entry_id(&last_id);
+                last_id
+            })
+            .collect();
+        assert_eq!(bank.count_valid_ids(&[]).len(), 0);
+        assert_eq!(bank.count_valid_ids(&[mint.last_id()]).len(), 0);
+        for (i, id) in bank.count_valid_ids(&ids).iter().enumerate() {
+            assert_eq!(id.0, i);
+        }
+    }
+
+    #[test]
+    fn test_recent_last_ids() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let ids: Vec<_> = (0..3)
+            .map(|i| {
+                let last_id = hash(&serialize(&i).unwrap()); // Unique hash
+                bank.register_entry_id(&last_id);
+                last_id
+            })
+            .collect();
+        let recent = bank.recent_last_ids(2);
+        assert_eq!(
+            recent.iter().map(|id| id.0).collect::<Vec<_>>(),
+            vec![ids[2], ids[1]]
+        );
+        assert!(recent[0].1 >= recent[1].1);
+        assert_eq!(bank.recent_last_ids(10).len(), 4);
+        assert_eq!(bank.recent_last_ids(10)[3].0, mint.last_id());
+    }
+
+    #[test]
+    fn test_debits_before_credits() {
+        let mint = Mint::new(2);
+        let bank = Bank::new(&mint);
+        let keypair = Keypair::new();
+        let tx0 = Transaction::new(&mint.keypair(), keypair.pubkey(), 2, mint.last_id());
+        let tx1 = Transaction::new(&keypair, mint.pubkey(), 1, mint.last_id());
+        let txs = vec![tx0, tx1];
+        let results = replay_transactions(&bank, &txs);
+        assert!(results[1].is_err());
+
+        // Assert bad transactions aren't counted.
+        assert_eq!(bank.transaction_count(), 1);
+    }
+
+    /// Process `txs` the way a replayed entry is, which debits them one at a
+    /// time when they conflict.
+    fn replay_transactions(bank: &Bank, txs: &[Transaction]) -> Vec<Result<()>> {
+        let accounts = &mut bank.accounts.write().unwrap();
+        let last_ids_sigs = &mut bank.last_ids_sigs.write().unwrap();
+        bank.replay_transactions(txs, accounts, last_ids_sigs)
+    }
+
+    #[test]
+    fn test_process_transactions_account_in_use() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let keypair0 = Keypair::new();
+        let keypair1 = Keypair::new();
+        bank.transfer(4, &mint.keypair(), keypair0.pubkey(), mint.last_id())
+            .unwrap();
+        let pubkey = Keypair::new().pubkey();
+        let tx0 = Transaction::new(&mint.keypair(), keypair1.pubkey(), 2, mint.last_id());
+        let tx1 = Transaction::new(&keypair0, pubkey, 1, mint.last_id());
+        let tx2 = Transaction::new(&keypair1, pubkey, 1, mint.last_id());
+        let tx3 = Transaction::new(&mint.keypair(), pubkey, 1, mint.last_id());
+
+        // tx0 and tx1 share no accounts, but tx2 spends what tx0 pays and tx3
+        // spends from the mint too.
+        let results = bank.process_transactions(&[tx0, tx1, tx2.clone(), tx3.clone()]);
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Ok(()),
+                Err(BankError::AccountInUse(keypair1.pubkey())),
+                Err(BankError::AccountInUse(mint.pubkey())),
+            ]
+        );
+        assert_eq!(bank.transaction_count(), 3);
+        assert!(!bank.has_signature(&tx2.signature));
+
+        // The deferred transactions go through in the next batch, the first
+        // still holding the accounts they share.
+        let results = bank.process_transactions(&[tx2.clone(), tx3.clone()]);
+        assert_eq!(results, vec![Ok(()), Err(BankError::AccountInUse(pubkey))]);
+        assert_eq!(bank.process_transactions(&[tx3]), vec![Ok(())]);
+        assert_eq!(bank.get_balance(&pubkey), 3);
+        assert_eq!(bank.get_balance(&keypair1.pubkey()), 1);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 3);
+        assert_eq!(bank.transaction_count(), 5);
+    }
+
+    #[test]
+    fn test_lock_accounts_witness() {
+        let mint = Mint::new(2);
+        let pubkey = Keypair::new().pubkey();
+        let dt = Utc::now();
+        let keypair = Keypair::new();
+        let tx0 = Transaction::new(&keypair, pubkey, 1, mint.last_id());
+        let tx1 = Transaction::new_timestamp(&mint.keypair(), dt, mint.last_id());
+        let tx2 = Transaction::new(&mint.keypair(), pubkey, 1, mint.last_id());
+
+        // A witness may pay any account, so it only runs alone.
+        assert_eq!(
+            Bank::lock_accounts(&[tx1.clone(), tx2.clone()]),
+            vec![Ok(()), Err(BankError::AccountInUse(mint.pubkey()))]
+        );
+        assert_eq!(
+            Bank::lock_accounts(&[tx0, tx1, tx2]),
+            vec![
+                Ok(()),
+                Err(BankError::AccountInUse(mint.pubkey())),
+                Err(BankError::AccountInUse(mint.pubkey())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lock_accounts_read_only() {
+        let mint = Mint::new(2);
+        let oracle = Keypair::new().pubkey();
+        let read = |keypair: &Keypair| {
+            let mut tx = Transaction::new(keypair, Keypair::new().pubkey(), 1, mint.last_id());
+            tx.read_only = vec![oracle];
+            tx
+        };
+        let keypair0 = Keypair::new();
+        let keypair1 = Keypair::new();
+        let write = Transaction::new(&mint.keypair(), oracle, 1, mint.last_id());
+
+        // Any number of transactions may read an account, but none write it alongside them.
+        assert_eq!(
+            Bank::lock_accounts(&[read(&keypair0), read(&keypair1), write.clone()]),
+            vec![Ok(()), Ok(()), Err(BankError::AccountInUse(oracle))]
         );
         assert_eq!(
-            bank.reserve_signature_with_last_id(&signature, &mint.last_id()),
-            Err(BankError::DuplicateSignature(signature))
+            Bank::lock_accounts(&[write, read(&keypair0)]),
+            vec![Ok(()), Err(BankError::AccountInUse(oracle))]
         );
     }
 
     #[test]
-    fn test_forget_signature() {
-        let mint = Mint::new(1);
+    fn test_process_transactions_read_only() {
+        let mint = Mint::new(10);
         let bank = Bank::new(&mint);
-        let signature = Signature::default();
-        bank.reserve_signature_with_last_id(&signature, &mint.last_id())
+        let oracle = Keypair::new().pubkey();
+        let keypair0 = Keypair::new();
+        let keypair1 = Keypair::new();
+        bank.transfer(1, &mint.keypair(), oracle, mint.last_id())
             .unwrap();
-        bank.forget_signature_with_last_id(&signature, &mint.last_id());
-        assert!(
-            bank.reserve_signature_with_last_id(&signature, &mint.last_id())
-                .is_ok()
+        bank.transfer(2, &mint.keypair(), keypair0.pubkey(), mint.last_id())
+            .unwrap();
+        bank.transfer(2, &mint.keypair(), keypair1.pubkey(), mint.last_id())
+            .unwrap();
+
+        let to = Keypair::new().pubkey();
+        let read = |keypair: &Keypair| {
+            let mut tx = Transaction::new(keypair, to, 1, mint.last_id());
+            tx.read_only = vec![oracle];
+            tx.sign(keypair);
+            tx
+        };
+        assert_eq!(
+            bank.process_transactions(&[read(&keypair0), read(&keypair1)]),
+            vec![Ok(()), Ok(())]
         );
-    }
+        assert_eq!(bank.get_balance(&to), 2);
 
-    #[test]
-    fn test_has_signature() {
-        let mint = Mint::new(1);
-        let bank = Bank::new(&mint);
-        let signature = Signature::default();
-        bank.reserve_signature_with_last_id(&signature, &mint.last_id())
-            .expect("reserve signature");
-        assert!(bank.has_signature(&signature));
+        let mut write = Transaction::new(&mint.keypair(), oracle, 1, mint.last_id());
+        write.read_only = vec![oracle];
+        write.sign(&mint.keypair());
+        assert_eq!(
+            bank.process_transaction(&write),
+            Err(BankError::ReadOnlyAccountWritten(oracle))
+        );
+        assert_eq!(bank.get_balance(&oracle), 1);
     }
 
     #[test]
-    fn test_reject_old_last_id() {
-        let mint = Mint::new(1);
+    fn test_process_transactions_results() {
+        let mint = Mint::new(10);
         let bank = Bank::new(&mint);
-        let signature = Signature::default();
-        for i in 0..MAX_ENTRY_IDS {
-            let last_id = hash(&serialize(&i).unwrap()); // Unique hash
-            bank.register_entry_id(&last_id);
-        }
-        // Assert we're no longer able to use the oldest entry ID.
+        let keypair = Keypair::new();
+        let tx0 = Transaction::new(&mint.keypair(), keypair.pubkey(), 2, mint.last_id());
+        let tx1 = Transaction::new(&mint.keypair(), keypair.pubkey(), 20, mint.last_id());
+        let mut tx2 = Transaction::new(&mint.keypair(), keypair.pubkey(), 1, mint.last_id());
+        tx2.userdata = vec![1, 2, 3];
+        tx2.sign(&mint.keypair());
+        let results = replay_transactions(&bank, &[tx0.clone(), tx1, tx2, tx0.clone()]);
         assert_eq!(
-            bank.reserve_signature_with_last_id(&signature, &mint.last_id()),
-            Err(BankError::LastIdNotFound(mint.last_id()))
+            results,
+            vec![
+                Ok(()),
+                Err(BankError::InsufficientFunds(mint.pubkey())),
+                Ok(()),
+                Err(BankError::DuplicateSignature(tx0.signature)),
+            ]
         );
+        assert_eq!(bank.transaction_count(), 2);
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 3);
+        let account = bank.get_account(&mint.pubkey()).unwrap();
+        assert_eq!(account.tokens, 7);
+        assert_eq!(account.userdata, vec![1, 2, 3]);
     }
 
     #[test]
-    fn test_count_valid_ids() {
-        let mint = Mint::new(1);
+    fn test_process_transactions_witness() {
+        let mint = Mint::new(2);
         let bank = Bank::new(&mint);
-        let ids: Vec<_> = (0..MAX_ENTRY_IDS)
-            .map(|i| {
-                let last_id = hash(&serialize(&i).unwrap()); // Unique hash
-                bank.register_entry_id(&last_id);
-                last_id
-            })
-            .collect();
-        assert_eq!(bank.count_valid_ids(&[]).len(), 0);
-        assert_eq!(bank.count_valid_ids(&[mint.last_id()]).len(), 0);
-        for (i, id) in bank.count_valid_ids(&ids).iter().enumerate() {
-            assert_eq!(id.0, i);
+        let pubkey = Keypair::new().pubkey();
+        let dt = Utc::now();
+        let tx0 = Transaction::new_on_date(&mint.keypair(), pubkey, dt, 1, mint.last_id());
+        let tx1 = Transaction::new_timestamp(&mint.keypair(), dt, mint.last_id());
+
+        // The witness completes the payment plan within the same batch.
+        let results = replay_transactions(&bank, &[tx0, tx1]);
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(bank.get_balance(&pubkey), 1);
+    }
+
+    /// Records what each committed transaction changed.
+    struct CommitLog(Mutex<Vec<(Signature, Vec<BalanceDelta>)>>);
+
+    impl BankObserver for CommitLog {
+        fn transaction_committed(&self, tx: &Transaction, deltas: &[BalanceDelta]) {
+            self.0.lock().unwrap().push((tx.signature, deltas.to_vec()));
         }
     }
 
     #[test]
-    fn test_debits_before_credits() {
+    fn test_transaction_committed_observer() {
         let mint = Mint::new(2);
         let bank = Bank::new(&mint);
-        let keypair = Keypair::new();
-        let tx0 = Transaction::new(&mint.keypair(), keypair.pubkey(), 2, mint.last_id());
-        let tx1 = Transaction::new(&keypair, mint.pubkey(), 1, mint.last_id());
-        let txs = vec![tx0, tx1];
-        let results = bank.process_transactions(txs);
-        assert!(results[1].is_err());
+        let log = Arc::new(CommitLog(Mutex::new(vec![])));
+        bank.add_observer(log.clone());
+        let pubkey = Keypair::new().pubkey();
+        let dt = Utc::now();
+        let tx0 = Transaction::new_on_date(&mint.keypair(), pubkey, dt, 1, mint.last_id());
+        let tx1 = Transaction::new_timestamp(&mint.keypair(), dt, mint.last_id());
+        let tx2 = Transaction::new(&Keypair::new(), pubkey, 1, mint.last_id());
+        let tx3 = Transaction::new(&mint.keypair(), pubkey, 1, mint.last_id());
+        let results = replay_transactions(&bank, &[tx0.clone(), tx1.clone(), tx2]);
+        assert!(results[2].is_err());
+        bank.process_transaction(&tx3).unwrap();
 
-        // Assert bad transactions aren't counted.
-        assert_eq!(bank.transaction_count(), 1);
+        let debit = |tokens| BalanceDelta {
+            pubkey: mint.pubkey(),
+            tokens: -tokens,
+        };
+        let credit = |tokens| BalanceDelta { pubkey, tokens };
+        assert_eq!(
+            *log.0.lock().unwrap(),
+            vec![
+                (tx0.signature, vec![debit(1)]),
+                (tx1.signature, vec![credit(1)]),
+                (tx3.signature, vec![debit(1), credit(1)]),
+            ]
+        );
     }
 
     #[test]
@@ -904,6 +4172,28 @@ mod tests {
         assert!(bank.process_transaction(&tx).is_ok());
     }
 
+    #[test]
+    fn test_process_entries_refer_to_earlier_entry() {
+        let mint = Mint::new(2);
+        let bank = Bank::new(&mint);
+        let keypair = Keypair::new();
+        let tick = next_entry(&mint.last_id(), 1, vec![]);
+        let tx = Transaction::new(&mint.keypair(), keypair.pubkey(), 1, tick.id);
+        let entry = next_entry(&tick.id, 1, vec![tx]);
+
+        // The tick's ID is registered before the transaction that refers to it.
+        assert_eq!(bank.process_entries(vec![tick.clone(), entry.clone()]), Ok(2));
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 1);
+        assert_eq!(bank.last_id(), entry.id);
+
+        // A failing entry leaves the IDs that preceded it registered.
+        let tick2 = next_entry(&entry.id, 1, vec![]);
+        let bad_tx = Transaction::new(&keypair, mint.pubkey(), 2, tick2.id);
+        let bad_entry = next_entry(&tick2.id, 1, vec![bad_tx]);
+        assert!(bank.process_entries(vec![tick2.clone(), bad_entry]).is_err());
+        assert_eq!(bank.last_id(), tick2.id);
+    }
+
     #[test]
     fn test_process_genesis() {
         let mint = Mint::new(1);
@@ -928,23 +4218,31 @@ mod tests {
                 hash,
             ));
         }
-        let entries = ledger::next_entries(&hash, 0, txs);
+        let entries = ledger::next_entries_in_domain(&hash, 0, txs, &mint.poh_domain());
         entries.into_iter()
     }
 
-    fn create_sample_block(mint: &Mint, length: usize) -> impl Iterator<Item = Entry> {
+    fn create_sample_block_in_domain(
+        mint: &Mint,
+        domain: &PohDomain,
+        length: usize,
+    ) -> impl Iterator<Item = Entry> {
         let mut entries = Vec::with_capacity(length);
         let mut hash = mint.last_id();
-        let mut num_hashes = 0;
         for _ in 0..length {
             let keypair = Keypair::new();
             let tx = Transaction::new(&mint.keypair(), keypair.pubkey(), 1, hash);
-            let entry = Entry::new_mut(&mut hash, &mut num_hashes, vec![tx], false);
+            let entry = Entry::new(&domain.seed(&hash), 0, vec![tx], false);
+            hash = entry.id;
             entries.push(entry);
         }
         entries.into_iter()
     }
 
+    fn create_sample_block(mint: &Mint, length: usize) -> impl Iterator<Item = Entry> {
+        create_sample_block_in_domain(mint, &mint.poh_domain(), length)
+    }
+
     fn create_sample_ledger_with_next_entries(
         length: usize,
     ) -> (impl Iterator<Item = Entry>, Pubkey) {
@@ -969,12 +4267,38 @@ mod tests {
         let (ledger_height, tail) = bank.process_ledger(ledger).unwrap();
         assert_eq!(bank.get_balance(&pubkey), 1);
         assert_eq!(ledger_height, 3);
+        // only the genesis entry is a tick
+        assert_eq!(bank.tick_height(), 1);
         assert_eq!(tail.len(), 3);
         assert_eq!(tail, dup.collect_vec());
         let last_entry = &tail[tail.len() - 1];
         assert_eq!(bank.last_id(), last_entry.id);
     }
 
+    #[test]
+    fn test_process_ledger_with_grants() {
+        let grants = vec![
+            Grant {
+                pubkey: Keypair::new().pubkey(),
+                tokens: 10,
+            },
+            Grant {
+                pubkey: Keypair::new().pubkey(),
+                tokens: 20,
+            },
+        ];
+        let mint = Mint::new_with_grants(100, grants.clone());
+        let bank = Bank::default();
+        bank.process_ledger(mint.create_entries()).unwrap();
+        let test_bank = Bank::new(&mint);
+        for bank in &[bank, test_bank] {
+            assert_eq!(bank.get_balance(&mint.pubkey()), 70);
+            assert_eq!(bank.get_balance(&grants[0].pubkey), 10);
+            assert_eq!(bank.get_balance(&grants[1].pubkey), 20);
+            assert_eq!(bank.last_id(), mint.last_id());
+        }
+    }
+
     #[test]
     fn test_process_ledger_around_window_size() {
         // TODO: put me back in when Criterion is up
@@ -1028,6 +4352,31 @@ mod tests {
         assert_eq!(bank.get_balance(&mint.pubkey()), 1);
     }
 
+    #[test]
+    fn test_process_ledger_rejects_other_poh_domain() {
+        let mint = Mint::new(2);
+        let bank = Bank::default();
+        let genesis = mint.create_entries().into_iter();
+        let block = create_sample_block(&mint, 1);
+        bank.process_ledger(genesis.chain(block)).unwrap();
+        assert_eq!(bank.poh_domain(), mint.poh_domain());
+
+        // the same entries recorded in a later epoch, or with no domain at all
+        let epoch1 = PohDomain::new(mint.poh_domain().genesis_id, 1);
+        let genesis = mint.create_entries().into_iter();
+        let block = create_sample_block_in_domain(&mint, &epoch1, 1);
+        assert_eq!(
+            Bank::default().process_ledger(genesis.chain(block)),
+            Err(BankError::LedgerVerificationFailed)
+        );
+        let genesis = mint.create_entries().into_iter();
+        let tick = next_entry(&mint.last_id(), 1, vec![]);
+        assert_eq!(
+            Bank::default().process_ledger(genesis.chain(Some(tick))),
+            Err(BankError::LedgerVerificationFailed)
+        );
+    }
+
     #[test]
     fn test_process_ledger_has_more_cross_block() {
         // size_of<Transaction> is quite large for serialized size, so
@@ -1038,6 +4387,366 @@ mod tests {
         let bank = Bank::default();
         assert!(bank.process_ledger(ledger).is_ok());
     }
+    #[test]
+    fn test_snapshot_restore() {
+        let mint = Mint::new(2);
+        let bank = Bank::new(&mint);
+        let snapshot = bank.snapshot();
+        assert!(snapshot.has_entry_id(&mint.last_id()));
+
+        let keypair = Keypair::new();
+        let entry = next_entry(&mint.last_id(), 1, vec![]);
+        let tx = Transaction::new(&mint.keypair(), keypair.pubkey(), 1, mint.last_id());
+        bank.process_entries(vec![entry.clone()]).unwrap();
+        bank.process_transaction(&tx).unwrap();
+        assert!(!snapshot.has_entry_id(&entry.id));
+
+        bank.restore(&snapshot);
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 0);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 2);
+        assert_eq!(bank.last_id(), mint.last_id());
+        assert_eq!(bank.transaction_count(), 0);
+        assert!(!bank.has_signature(&tx.signature));
+    }
+
+    #[test]
+    fn test_checkpoint_rollback_to() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        let alice = Keypair::new();
+        let bob = Keypair::new().pubkey();
+        let checkpoint = bank.checkpoint();
+
+        let tx = Transaction::new(&mint.keypair(), alice.pubkey(), 1_000, mint.last_id());
+        let entry = next_entry(&mint.last_id(), 1, vec![tx.clone()]);
+        let tick = next_entry(&entry.id, 1, vec![]);
+        bank.process_entries(vec![entry.clone(), tick.clone()])
+            .unwrap();
+        let dt = Utc::now();
+        bank.transfer_on_date(100, &alice, bob, dt, tick.id)
+            .unwrap();
+        let tx = Transaction::new_authorize_voter(&alice, bob, tick.id, 0);
+        bank.process_transaction(&tx).unwrap();
+
+        let nested = bank.checkpoint();
+        bank.transfer(200, &alice, bob, tick.id).unwrap();
+        bank.apply_timestamp(alice.pubkey(), dt, &mut bank.accounts.write().unwrap())
+            .unwrap();
+        assert_eq!(bank.get_balance(&bob), 300);
+
+        bank.rollback_to(nested).unwrap();
+        assert_eq!(bank.get_balance(&alice.pubkey()), 900);
+        assert_eq!(bank.get_balance(&bob), 0);
+        assert_eq!(bank.pending.read().unwrap().len(), 1);
+        assert_eq!(
+            bank.rollback_to(nested),
+            Err(BankError::CheckpointNotFound(nested))
+        );
+
+        bank.rollback_to(checkpoint).unwrap();
+        assert_eq!(bank.get_balance(&mint.pubkey()), 10_000);
+        assert_eq!(bank.get_account(&alice.pubkey()), None);
+        assert_eq!(bank.last_id(), mint.last_id());
+        let signature = entry.transactions[0].signature;
+        assert_eq!(bank.get_signature_status(&signature), None);
+        assert_eq!(bank.transaction_count(), 0);
+        assert_eq!(bank.tick_height(), 1);
+        assert!(bank.pending.read().unwrap().is_empty());
+        assert_eq!(bank.authorized_voter(&alice.pubkey()), None);
+
+        // the rolled back entries apply again just the same
+        bank.process_entries(vec![entry, tick]).unwrap();
+        assert_eq!(bank.get_balance(&alice.pubkey()), 1_000);
+    }
+
+    #[test]
+    fn test_confirm_checkpoint() {
+        let mint = Mint::new(10_000);
+        let bank = Bank::new(&mint);
+        let pubkey = Keypair::new().pubkey();
+        let first = bank.checkpoint();
+        bank.transfer(1, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+        let second = bank.checkpoint();
+        bank.transfer(2, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+
+        bank.confirm_checkpoint(first).unwrap();
+        assert_eq!(bank.open_checkpoints.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            bank.rollback_to(first),
+            Err(BankError::CheckpointNotFound(first))
+        );
+        bank.rollback_to(second).unwrap();
+        assert_eq!(bank.get_balance(&pubkey), 1);
+        assert!(bank.journals.read().unwrap().stack.is_empty());
+        assert_eq!(bank.open_checkpoints.load(Ordering::Relaxed), 0);
+
+        // with no checkpoint open, changes aren't saved anywhere
+        bank.transfer(3, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+        assert_eq!(bank.get_balance(&pubkey), 4);
+        assert_eq!(
+            bank.confirm_checkpoint(second),
+            Err(BankError::CheckpointNotFound(second))
+        );
+    }
+
+    #[test]
+    fn test_rollback_to_evicted_last_ids() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        for i in 0..MAX_ENTRY_IDS as u64 - 1 {
+            bank.register_entry_id(&hash(&serialize(&i).unwrap()));
+        }
+        let last_ids = bank.last_ids.read().unwrap().clone();
+        let has_id = |id: &Hash| bank.last_ids_sigs.read().unwrap().contains_key(id);
+        let checkpoint = bank.checkpoint();
+        bank.register_entry_id(&Hash::default());
+        assert!(!has_id(&mint.last_id()));
+
+        bank.rollback_to(checkpoint).unwrap();
+        assert_eq!(*bank.last_ids.read().unwrap(), last_ids);
+        assert!(has_id(&mint.last_id()));
+        assert!(!has_id(&Hash::default()));
+    }
+
+    /// Register entry IDs until the bank's `entry_id_count` ends a rent epoch.
+    fn register_rent_epoch(bank: &Bank) {
+        while bank.entry_id_count() % rent::RENT_EPOCH_ENTRY_IDS != 0 {
+            let count = bank.entry_id_count() as u64;
+            bank.register_entry_id(&hash(&serialize(&count).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_collect_rent() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let dust = Keypair::new().pubkey();
+        let exempt = Keypair::new().pubkey();
+        bank.transfer(1, &mint.keypair(), dust, mint.last_id())
+            .unwrap();
+        bank.transfer(2, &mint.keypair(), exempt, mint.last_id())
+            .unwrap();
+
+        register_rent_epoch(&bank);
+        assert_eq!(bank.get_account(&dust), None);
+        assert_eq!(bank.get_balance(&exempt), 2);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 7);
+    }
+
+    #[test]
+    fn test_rollback_collected_rent() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let dust = Keypair::new().pubkey();
+        bank.transfer(1, &mint.keypair(), dust, mint.last_id())
+            .unwrap();
+
+        let checkpoint = bank.checkpoint();
+        register_rent_epoch(&bank);
+        assert_eq!(bank.get_account(&dust), None);
+        bank.rollback_to(checkpoint).unwrap();
+        assert_eq!(bank.get_balance(&dust), 1);
+        assert_eq!(bank.entry_id_count(), 1);
+    }
+
+    #[test]
+    fn test_accounts_hash() {
+        let mint = Mint::new(10);
+        let bank = Bank::new(&mint);
+        let other = Bank::new(&mint);
+        let genesis_hash = bank.accounts_hash();
+        assert_eq!(other.accounts_hash(), genesis_hash);
+
+        let pubkey = Keypair::new().pubkey();
+        bank.transfer(1, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+        assert_ne!(bank.accounts_hash(), genesis_hash);
+        other
+            .transfer(2, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+        assert_ne!(other.accounts_hash(), bank.accounts_hash());
+
+        // a bank started from the same accounts agrees
+        let snapshot = bank.snapshot();
+        other.restore(&snapshot);
+        assert_eq!(other.accounts_hash(), bank.accounts_hash());
+        let restored = Bank::default();
+        *restored.accounts.write().unwrap() = snapshot.accounts.clone();
+        restored.accounts_hash.write().unwrap().mark_all();
+        assert_eq!(restored.accounts_hash(), bank.accounts_hash());
+    }
+
+    #[test]
+    fn test_accounts_hash_at() {
+        let mint = Mint::new(2);
+        let bank = Bank::new(&mint);
+        let keypair = Keypair::new();
+        let tick = next_entry(&mint.last_id(), 1, vec![]);
+        let tx = Transaction::new(&mint.keypair(), keypair.pubkey(), 1, tick.id);
+        let entry = next_entry(&tick.id, 1, vec![tx]);
+        let genesis_hash = bank.accounts_hash();
+        assert_eq!(
+            bank.process_entries(vec![tick.clone(), entry.clone()]),
+            Ok(2)
+        );
+        assert_eq!(bank.accounts_hash_at(&tick.id), Some(genesis_hash));
+        assert_eq!(bank.accounts_hash_at(&entry.id), Some(bank.accounts_hash()));
+        assert_ne!(bank.accounts_hash(), genesis_hash);
+
+        // a leader records none for the entries it writes
+        bank.register_entry_id(&Hash::default());
+        assert_eq!(bank.accounts_hash_at(&Hash::default()), None);
+    }
+
+    #[test]
+    fn test_rollback_accounts_hash() {
+        let mint = Mint::new(2);
+        let bank = Bank::new(&mint);
+        let keypair = Keypair::new();
+        let genesis_hash = bank.accounts_hash();
+
+        let checkpoint = bank.checkpoint();
+        let tx = Transaction::new(&mint.keypair(), keypair.pubkey(), 1, mint.last_id());
+        let entry = next_entry(&mint.last_id(), 1, vec![tx]);
+        assert_eq!(bank.process_entries(vec![entry.clone()]), Ok(1));
+        assert!(bank.accounts_hash_at(&entry.id).is_some());
+        assert_ne!(bank.accounts_hash(), genesis_hash);
+
+        bank.rollback_to(checkpoint).unwrap();
+        assert_eq!(bank.accounts_hash(), genesis_hash);
+        assert_eq!(bank.accounts_hash_at(&entry.id), None);
+    }
+
+    fn tmp_accounts_path(name: &str) -> String {
+        let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
+        let keypair = Keypair::new();
+        format!("{}/tmp-bank-{}-{}", out_dir, name, keypair.pubkey())
+    }
+
+    #[test]
+    fn test_save_accounts_resume() {
+        let path = tmp_accounts_path("test_save_accounts_resume");
+        let (ledger, _) = create_sample_ledger(10);
+        let ledger: Vec<_> = ledger.collect();
+        let (entry_height, last_entry_id, accounts) = {
+            let bank = Bank::default();
+            let db = AccountsDb::open(&path).unwrap();
+            assert_eq!(bank.use_accounts_db(db).unwrap(), None);
+            let (entry_height, tail) = bank.process_ledger(ledger[..6].to_vec()).unwrap();
+            let last_entry_id = tail.last().unwrap().id;
+            bank.save_accounts(entry_height, &last_entry_id).unwrap();
+            assert!(bank.accounts.read().unwrap().is_empty());
+            (entry_height, last_entry_id, bank.accounts_snapshot())
+        };
+
+        let bank = Bank::default();
+        let db = AccountsDb::open(&path).unwrap();
+        let saved = bank.use_accounts_db(db).unwrap();
+        assert_eq!(saved, Some((entry_height, last_entry_id)));
+        assert_eq!(bank.accounts_snapshot(), accounts);
+        let (height, tail) = bank
+            .process_ledger_from(ledger.clone(), entry_height, &last_entry_id)
+            .unwrap();
+
+        let replayed = Bank::default();
+        let (replayed_height, replayed_tail) = replayed.process_ledger(ledger).unwrap();
+        assert_eq!(height, replayed_height);
+        assert_eq!(tail, replayed_tail);
+        assert_eq!(bank.accounts_snapshot(), replayed.accounts_snapshot());
+        assert_eq!(bank.account_count(), replayed.account_count());
+        assert_eq!(bank.transaction_count(), replayed.transaction_count());
+        assert_eq!(bank.tick_height(), replayed.tick_height());
+
+        // a ledger that doesn't extend the one saved from
+        let bank = Bank::default();
+        bank.use_accounts_db(AccountsDb::open(&path).unwrap())
+            .unwrap();
+        let (other_ledger, _) = create_sample_ledger(10);
+        let result = bank.process_ledger_from(other_ledger, entry_height, &last_entry_id);
+        assert!(result.is_err());
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_save_accounts_purged() {
+        let path = tmp_accounts_path("test_save_accounts_purged");
+        let mint = Mint::new(2);
+        let keypair = Keypair::new();
+        let bank = Bank::new(&mint);
+        bank.use_accounts_db(AccountsDb::open(&path).unwrap())
+            .unwrap();
+        bank.transfer(1, &mint.keypair(), keypair.pubkey(), mint.last_id())
+            .unwrap();
+        bank.save_accounts(3, &mint.last_id()).unwrap();
+
+        // emptying an account that's only on disk hides it until rolled back
+        let checkpoint = bank.checkpoint();
+        bank.transfer(1, &keypair, mint.pubkey(), mint.last_id())
+            .unwrap();
+        assert_eq!(bank.get_account(&keypair.pubkey()), None);
+        assert_eq!(bank.account_count(), 1);
+        bank.rollback_to(checkpoint).unwrap();
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 1);
+        assert_eq!(bank.account_count(), 2);
+
+        bank.transfer(1, &keypair, mint.pubkey(), mint.last_id())
+            .unwrap();
+        bank.save_accounts(4, &mint.last_id()).unwrap();
+        assert_eq!(
+            bank.rollback_to(checkpoint),
+            Err(BankError::CheckpointNotFound(checkpoint))
+        );
+
+        let bank = Bank::default();
+        bank.use_accounts_db(AccountsDb::open(&path).unwrap())
+            .unwrap();
+        assert_eq!(bank.get_account(&keypair.pubkey()), None);
+        assert_eq!(bank.get_balance(&mint.pubkey()), 2);
+        assert_eq!(bank.account_count(), 1);
+        assert_eq!(bank.transaction_count(), 2);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_serialize_snapshot() {
+        let accounts_path = tmp_accounts_path("test_serialize_snapshot");
+        let snapshot_path = format!("{}.snapshot", accounts_path);
+        let (ledger, _) = create_sample_ledger(10);
+        let ledger: Vec<_> = ledger.collect();
+        let bank = Bank::default();
+        bank.use_accounts_db(AccountsDb::open(&accounts_path).unwrap())
+            .unwrap();
+        let (entry_height, tail) = bank.process_ledger(ledger[..4].to_vec()).unwrap();
+        let last_entry_id = tail.last().unwrap().id;
+        // some accounts on disk, the rest in memory
+        bank.save_accounts(entry_height, &last_entry_id).unwrap();
+        bank.process_entries(ledger[4..6].to_vec()).unwrap();
+        let last_entry_id = ledger[5].id;
+        bank.serialize_snapshot(&snapshot_path, 6, &last_entry_id)
+            .unwrap();
+
+        let (loaded, height, id) = Bank::from_snapshot(&snapshot_path).unwrap();
+        assert_eq!((height, id), (6, last_entry_id));
+        assert_eq!(loaded.accounts_snapshot(), bank.accounts_snapshot());
+        assert_eq!(loaded.transaction_count(), bank.transaction_count());
+        assert_eq!(loaded.tick_height(), bank.tick_height());
+
+        let (height, tail) = loaded
+            .process_ledger_from(ledger.clone(), height, &id)
+            .unwrap();
+        let replayed = Bank::default();
+        assert_eq!(replayed.process_ledger(ledger).unwrap(), (height, tail));
+        assert_eq!(loaded.accounts_snapshot(), replayed.accounts_snapshot());
+        assert_eq!(loaded.transaction_count(), replayed.transaction_count());
+
+        assert!(Bank::from_snapshot(&accounts_path).is_err());
+        fs::remove_file(snapshot_path).unwrap();
+        fs::remove_dir_all(accounts_path).unwrap();
+    }
+
     #[test]
     fn test_new_default() {
         let def_bank = Bank::default();
@@ -1055,4 +4764,45 @@ mod tests {
         assert_eq!(def_bank.finality(), 90);
     }
 
+    #[test]
+    fn test_recent_fees() {
+        let bank = Bank::default();
+        assert_eq!(bank.recent_fees().percentile(50), None);
+
+        let fees: Vec<_> = (0..MAX_RECENT_FEES as i64 + 10).collect();
+        bank.record_fees(&fees, 3);
+        let recent_fees = bank.recent_fees();
+        assert_eq!(recent_fees.fees.len(), MAX_RECENT_FEES);
+        assert_eq!(recent_fees.queue_depth, 3);
+        assert_eq!(recent_fees.percentile(0), Some(10));
+        assert_eq!(
+            recent_fees.percentile(100),
+            Some(MAX_RECENT_FEES as i64 + 9)
+        );
+
+        bank.record_fees(&[5, 1, 3], 0);
+        let mut recent_fees = bank.recent_fees();
+        recent_fees.fees = recent_fees.fees.split_off(MAX_RECENT_FEES - 3);
+        assert_eq!(recent_fees.percentile(50), Some(3));
+        assert_eq!(recent_fees.queue_depth, 0);
+    }
+
+    #[test]
+    fn test_recent_slot_costs() {
+        let bank = Bank::default();
+        assert_eq!(bank.recent_slot_costs(), vec![]);
+
+        let costs: Vec<_> = (0..MAX_RECENT_SLOT_COSTS as u64 + 2)
+            .map(SlotCost::new)
+            .collect();
+        bank.record_slot_costs(&costs);
+        let recent_slot_costs = bank.recent_slot_costs();
+        assert_eq!(recent_slot_costs.len(), MAX_RECENT_SLOT_COSTS);
+        assert_eq!(recent_slot_costs[0].slot, 2);
+        assert_eq!(
+            recent_slot_costs[MAX_RECENT_SLOT_COSTS - 1].slot,
+            MAX_RECENT_SLOT_COSTS as u64 + 1
+        );
+    }
+
 }
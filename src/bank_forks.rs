@@ -0,0 +1,468 @@
+//! The `bank_forks` module tracks the forks a validator sees when leaders
+//! produce conflicting entries. Each fork keeps only the batch of entries it
+//! adds to its parent, and the bank keeps a checkpoint of its state before each
+//! batch on the fork it follows, so a batch can be applied on top of any recent
+//! batch, not just the latest one. Of the forks' tips, the bank follows the one
+//! holding the most of the network's latest votes. Moving to another fork rolls
+//! the bank back to where the forks meet and replays the other fork's batches
+//! from there. Batches that fall more than `MAX_FORK_DEPTH` behind the chosen
+//! tip are pruned, which abandons the forks built on them, and their
+//! checkpoints are confirmed. While a single fork remains, the bank's accounts
+//! can be saved, after which only batches built on its tip are accepted.
+
+use bank::{Bank, BankError, CheckpointId};
+use counter::Counter;
+use entry::Entry;
+use hash::Hash;
+use log::Level;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+/// The number of batches a fork can branch off behind the chosen tip.
+pub const MAX_FORK_DEPTH: u64 = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ForkError {
+    /// The batch doesn't extend any recent batch.
+    UnknownParent(Hash),
+    /// The batch extends a recent batch, but the bank rejected it.
+    BankError(BankError),
+}
+
+struct Fork {
+    /// The number of batches since the first one tracked.
+    depth: u64,
+    /// The id of the batch this one extends, `None` for the state the forks
+    /// started from.
+    parent: Option<Hash>,
+    entries: Vec<Entry>,
+    /// The bank's checkpoint of the state after this batch, while the bank
+    /// follows a fork through it and has applied a batch on top of it.
+    checkpoint: Option<CheckpointId>,
+}
+
+pub struct BankForks {
+    bank: Arc<Bank>,
+    /// Batches keyed by the id of their last entry.
+    forks: HashMap<Hash, Fork>,
+    /// Batches no batch has been applied on top of yet.
+    tips: HashSet<Hash>,
+    /// The tip the bank is at.
+    head: Hash,
+}
+
+impl BankForks {
+    /// Track the forks built on the bank's current state.
+    pub fn new(bank: Arc<Bank>) -> Self {
+        let head = bank.last_id();
+        let mut forks = HashMap::new();
+        forks.insert(
+            head,
+            Fork {
+                depth: 0,
+                parent: None,
+                entries: vec![],
+                checkpoint: None,
+            },
+        );
+        let mut tips = HashSet::new();
+        tips.insert(head);
+        BankForks {
+            bank,
+            forks,
+            tips,
+            head,
+        }
+    }
+
+    /// The id of the last entry on the fork the bank follows.
+    pub fn head(&self) -> Hash {
+        self.head
+    }
+
+    /// The bank the forks are applied to.
+    pub fn bank(&self) -> &Arc<Bank> {
+        &self.bank
+    }
+
+    /// Find the batch the first of `entries` is recorded on, trying the head
+    /// first.
+    fn find_parent(&self, entries: &[Entry]) -> Option<Hash> {
+        let domain = self.bank.poh_domain();
+        let first = &entries[0];
+        if first.verify_in_domain(&self.head, &domain) {
+            return Some(self.head);
+        }
+        self.forks
+            .keys()
+            .find(|id| first.verify_in_domain(id, &domain))
+            .cloned()
+    }
+
+    /// The batches from the first one tracked up to `id`, oldest first.
+    fn path(&self, id: Hash) -> Vec<Hash> {
+        let mut path = vec![id];
+        while let Some(parent) = self.forks[&path[path.len() - 1]].parent {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    /// The tip holding the most of `votes`, the latest entry id each validator
+    /// voted on. Ties go to the deepest tip, then to `current`. Votes on
+    /// entries from before the first batch tracked count for every tip alike,
+    /// so only the tracked batches are looked at.
+    fn heaviest_tip(&self, current: Hash, votes: &[Hash]) -> Hash {
+        let weight = |id: &Hash| {
+            let entry_ids: HashSet<Hash> = self
+                .path(*id)
+                .iter()
+                .flat_map(|id| self.forks[id].entries.iter().map(|entry| entry.id))
+                .collect();
+            let votes = votes.iter().filter(|v| entry_ids.contains(v)).count();
+            (votes, self.forks[id].depth)
+        };
+        let mut best = current;
+        let mut best_weight = weight(&best);
+        for id in &self.tips {
+            let w = weight(id);
+            if w > best_weight {
+                best = *id;
+                best_weight = w;
+            }
+        }
+        best
+    }
+
+    /// Move the bank to the state after batch `id`: roll it back to the last
+    /// batch that fork shares with the head's, then replay the rest.
+    fn switch_to(&mut self, id: Hash) {
+        if id == self.head {
+            return;
+        }
+        let head_path = self.path(self.head);
+        let path = self.path(id);
+        let shared = head_path
+            .iter()
+            .zip(&path)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let base = path[shared - 1];
+        if base != self.head {
+            let checkpoint = self.forks[&base].checkpoint.expect("checkpoint of a fork");
+            self.bank
+                .rollback_to(checkpoint)
+                .expect("rollback_to a fork's checkpoint");
+            for id in &head_path[shared - 1..] {
+                self.forks.get_mut(id).unwrap().checkpoint = None;
+            }
+        }
+        for pair in path[shared - 1..].windows(2) {
+            self.forks.get_mut(&pair[0]).unwrap().checkpoint = Some(self.bank.checkpoint());
+            // it was applied the same way before, failures included
+            let _ = self
+                .bank
+                .process_entries(self.forks[&pair[1]].entries.clone());
+        }
+        self.head = id;
+    }
+
+    /// Drop the batches more than `MAX_FORK_DEPTH` behind the head, along with
+    /// the forks that branch off before them, and confirm the checkpoints
+    /// they no longer need.
+    fn prune(&mut self) {
+        let path = self.path(self.head);
+        let excess = (self.forks[&self.head].depth - self.forks[&path[0]].depth)
+            .saturating_sub(MAX_FORK_DEPTH) as usize;
+        if excess == 0 {
+            return;
+        }
+        let root = path[excess];
+        if let Some(checkpoint) = self.forks[&path[excess - 1]].checkpoint {
+            self.bank
+                .confirm_checkpoint(checkpoint)
+                .expect("confirm_checkpoint of a pruned fork");
+        }
+        let root_depth = self.forks[&root].depth;
+        let keep: HashSet<Hash> = self
+            .forks
+            .keys()
+            .filter(|id| {
+                let mut id = **id;
+                while self.forks[&id].depth > root_depth {
+                    id = self.forks[&id].parent.unwrap();
+                }
+                id == root
+            })
+            .cloned()
+            .collect();
+        let before = self.forks.len();
+        self.forks.retain(|id, _| keep.contains(id));
+        self.forks.get_mut(&root).unwrap().parent = None;
+        self.forks.get_mut(&root).unwrap().entries.clear();
+        self.tips.retain(|id| keep.contains(id));
+        inc_new_counter_info!("bank_forks-pruned", before - self.forks.len());
+    }
+
+    /// Apply a batch of entries on the batch it extends, then move the bank to
+    /// the heaviest fork given `votes`. Returns whether the batch simply
+    /// extended the fork the bank was following and is still followed.
+    pub fn process_entries(
+        &mut self,
+        entries: Vec<Entry>,
+        votes: &[Hash],
+    ) -> Result<bool, ForkError> {
+        if entries.is_empty() {
+            return Ok(true);
+        }
+        let parent = match self.find_parent(&entries) {
+            Some(parent) => parent,
+            None => {
+                inc_new_counter_info!("bank_forks-unknown_parent", 1);
+                return Err(ForkError::UnknownParent(entries[0].id));
+            }
+        };
+        let old_head = self.head;
+        if parent != old_head {
+            inc_new_counter_info!("bank_forks-fork", 1);
+            self.switch_to(parent);
+        }
+
+        let tip = entries[entries.len() - 1].id;
+        self.forks.get_mut(&parent).unwrap().checkpoint = Some(self.bank.checkpoint());
+        // like a bank without forks, the entries before a failed one stay applied
+        let res = self.bank.process_entries(entries.clone());
+        let fork = Fork {
+            depth: self.forks[&parent].depth + 1,
+            parent: Some(parent),
+            entries,
+            checkpoint: None,
+        };
+        self.forks.insert(tip, fork);
+        self.tips.remove(&parent);
+        self.tips.insert(tip);
+        self.head = tip;
+
+        // a batch that extends the head keeps the bank on its fork unless outvoted
+        let current = if parent == old_head { tip } else { old_head };
+        let best = self.heaviest_tip(current, votes);
+        self.switch_to(best);
+        if parent != old_head && best != old_head {
+            info!("switched fork from {:?} to {:?}", old_head, best);
+            inc_new_counter_info!("bank_forks-switch", 1);
+        }
+        self.prune();
+
+        res.map_err(ForkError::BankError)?;
+        Ok(parent == old_head && best == tip)
+    }
+
+    /// Save the bank's accounts at `entry_height` if the head is the only tip
+    /// and `last_entry_id` is the head, the last entry in the ledger. Saving
+    /// drops every checkpoint, so the head becomes the first batch tracked
+    /// and batches built on the ones before it are dropped like pruned ones.
+    /// Returns whether the accounts were saved.
+    pub fn save_accounts(&mut self, entry_height: u64, last_entry_id: &Hash) -> io::Result<bool> {
+        if self.tips.len() != 1 || self.head != *last_entry_id {
+            return Ok(false);
+        }
+        if !self.bank.save_accounts(entry_height, last_entry_id)? {
+            return Ok(false);
+        }
+        let depth = self.forks[&self.head].depth;
+        self.forks.clear();
+        self.forks.insert(
+            self.head,
+            Fork {
+                depth,
+                parent: None,
+                entries: vec![],
+                checkpoint: None,
+            },
+        );
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use accounts_db::AccountsDb;
+    use bank::Bank;
+    use bank_forks::{BankForks, ForkError, MAX_FORK_DEPTH};
+    use entry::{next_entry, Entry, PohDomain};
+    use hash::Hash;
+    use mint::Mint;
+    use signature::{Keypair, KeypairUtil};
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+    use transaction::Transaction;
+
+    fn next_tick(domain: &PohDomain, last_id: &Hash, num_hashes: u64) -> Entry {
+        next_entry(&domain.seed(last_id), num_hashes, vec![])
+    }
+
+    fn tmp_accounts_path(name: &str) -> String {
+        let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
+        let keypair = Keypair::new();
+        format!("{}/tmp-bank-forks-{}-{}", out_dir, name, keypair.pubkey())
+    }
+
+    #[test]
+    fn test_heaviest_fork() {
+        let mint = Mint::new(10);
+        let bank = Arc::new(Bank::new(&mint));
+        let domain = bank.poh_domain();
+        let mut forks = BankForks::new(bank.clone());
+        let root = mint.last_id();
+
+        // fork a pays 1, fork b pays 2, from the same parent
+        let keypair = Keypair::new();
+        let tx_a = Transaction::new(&mint.keypair(), keypair.pubkey(), 1, root);
+        let tx_b = Transaction::new(&mint.keypair(), keypair.pubkey(), 2, root);
+        let a = next_entry(&domain.seed(&root), 1, vec![tx_a]);
+        let b = next_entry(&domain.seed(&root), 2, vec![tx_b]);
+
+        assert_eq!(forks.process_entries(vec![a.clone()], &[]), Ok(true));
+        assert_eq!(forks.head(), a.id);
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 1);
+
+        // without votes, the bank stays where it is
+        assert_eq!(forks.process_entries(vec![b.clone()], &[]), Ok(false));
+        assert_eq!(forks.head(), a.id);
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 1);
+
+        // votes on fork b move the bank over
+        let b2 = next_tick(&domain, &b.id, 1);
+        assert_eq!(forks.process_entries(vec![b2.clone()], &[b.id]), Ok(false));
+        assert_eq!(forks.head(), b2.id);
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 2);
+        assert_eq!(bank.last_id(), b2.id);
+
+        // fork a is still there to go back to
+        let a2 = next_tick(&domain, &a.id, 1);
+        let votes = [a.id, a.id];
+        assert_eq!(forks.process_entries(vec![a2.clone()], &votes), Ok(false));
+        assert_eq!(forks.head(), a2.id);
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 1);
+    }
+
+    #[test]
+    fn test_switch_replays_batches() {
+        let mint = Mint::new(10);
+        let bank = Arc::new(Bank::new(&mint));
+        let domain = bank.poh_domain();
+        let mut forks = BankForks::new(bank.clone());
+        let root = mint.last_id();
+        let keypair = Keypair::new();
+        let pay = |tokens| Transaction::new(&mint.keypair(), keypair.pubkey(), tokens, root);
+
+        // fork a pays 1 then 2, fork b pays 5
+        let a1 = next_entry(&domain.seed(&root), 1, vec![pay(1)]);
+        let a2 = next_entry(&domain.seed(&a1.id), 1, vec![pay(2)]);
+        let b1 = next_entry(&domain.seed(&root), 2, vec![pay(5)]);
+        assert_eq!(forks.process_entries(vec![a1.clone()], &[]), Ok(true));
+        assert_eq!(forks.process_entries(vec![a2.clone()], &[]), Ok(true));
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 3);
+
+        // moving to fork b rolls both of fork a's batches back
+        let votes = [b1.id, b1.id];
+        assert_eq!(forks.process_entries(vec![b1.clone()], &votes), Ok(false));
+        assert_eq!(forks.head(), b1.id);
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 5);
+
+        // and moving back replays them
+        let a3 = next_tick(&domain, &a2.id, 1);
+        let votes = [a1.id, a2.id, a2.id];
+        assert_eq!(forks.process_entries(vec![a3.clone()], &votes), Ok(false));
+        assert_eq!(forks.head(), a3.id);
+        assert_eq!(bank.get_balance(&keypair.pubkey()), 3);
+        assert_eq!(bank.last_id(), a3.id);
+    }
+
+    #[test]
+    fn test_unknown_parent() {
+        let mint = Mint::new(10);
+        let bank = Arc::new(Bank::new(&mint));
+        let mut forks = BankForks::new(bank.clone());
+        let other = PohDomain::new(Hash::default(), 0);
+        let tick = next_tick(&other, &mint.last_id(), 1);
+        assert_eq!(
+            forks.process_entries(vec![tick.clone()], &[]),
+            Err(ForkError::UnknownParent(tick.id))
+        );
+        assert_eq!(bank.last_id(), mint.last_id());
+    }
+
+    #[test]
+    fn test_prune_abandoned_fork() {
+        let mint = Mint::new(10);
+        let bank = Arc::new(Bank::new(&mint));
+        let domain = bank.poh_domain();
+        let mut forks = BankForks::new(bank.clone());
+
+        let abandoned = next_tick(&domain, &mint.last_id(), 1);
+        let mut last_id = mint.last_id();
+        for i in 0..MAX_FORK_DEPTH + 2 {
+            let tick = next_tick(&domain, &last_id, 2);
+            assert_eq!(forks.process_entries(vec![tick.clone()], &[]), Ok(true));
+            last_id = tick.id;
+            if i == 0 {
+                assert_eq!(
+                    forks.process_entries(vec![abandoned.clone()], &[]),
+                    Ok(false)
+                );
+            }
+        }
+        let orphan = next_tick(&domain, &abandoned.id, 1);
+        assert_eq!(
+            forks.process_entries(vec![orphan.clone()], &[]),
+            Err(ForkError::UnknownParent(orphan.id))
+        );
+    }
+
+    #[test]
+    fn test_save_accounts() {
+        let path = tmp_accounts_path("test_save_accounts");
+        let mint = Mint::new(10);
+        let bank = Arc::new(Bank::new(&mint));
+        bank.use_accounts_db(AccountsDb::open(&path).unwrap())
+            .unwrap();
+        let domain = bank.poh_domain();
+        let mut forks = BankForks::new(bank.clone());
+
+        let a = next_tick(&domain, &mint.last_id(), 1);
+        let b = next_tick(&domain, &mint.last_id(), 2);
+        assert_eq!(forks.process_entries(vec![a.clone()], &[]), Ok(true));
+        assert_eq!(forks.process_entries(vec![b.clone()], &[]), Ok(false));
+
+        // fork b could still take over
+        assert!(!forks.save_accounts(1, &a.id).unwrap());
+
+        let mut last_id = a.id;
+        let mut entry_height = 1;
+        for _ in 0..MAX_FORK_DEPTH {
+            let tick = next_tick(&domain, &last_id, 2);
+            assert_eq!(forks.process_entries(vec![tick.clone()], &[]), Ok(true));
+            last_id = tick.id;
+            entry_height += 1;
+        }
+        // the ledger has to end at the head
+        assert!(!forks.save_accounts(entry_height - 1, &a.id).unwrap());
+        assert!(forks.save_accounts(entry_height, &last_id).unwrap());
+
+        // only batches on the head are accepted after a save
+        let fork = next_tick(&domain, &a.id, 3);
+        assert_eq!(
+            forks.process_entries(vec![fork.clone()], &[]),
+            Err(ForkError::UnknownParent(fork.id))
+        );
+        let tick = next_tick(&domain, &last_id, 1);
+        assert_eq!(forks.process_entries(vec![tick.clone()], &[]), Ok(true));
+        assert_eq!(bank.last_id(), tick.id);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
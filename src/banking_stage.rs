@@ -2,13 +2,16 @@
 //! to contruct a software pipeline. The stage uses all available CPU cores and
 //! can do its processing in parallel with signature verification on the GPU.
 
-use bank::Bank;
+use bank::{Bank, BankError};
 use bincode::deserialize;
 use counter::Counter;
+use cpu_time;
+use latency::StageLatency;
 use log::Level;
 use packet::{PacketRecycler, Packets, SharedPackets};
 use rayon::prelude::*;
-use record_stage::Signal;
+use record_stage::{CommitLock, Signal};
+use replay_stats::ReplayStats;
 use result::{Error, Result};
 use service::Service;
 use std::net::SocketAddr;
@@ -21,6 +24,11 @@ use std::time::Instant;
 use timing;
 use transaction::Transaction;
 
+/// The most passes taken over one batch of transactions. Those deferred over an
+/// account in use on the last pass are dropped, so a batch spending one account
+/// over and over can't keep the stage on it for a pass per transaction.
+pub const MAX_BATCH_PASSES: usize = 4;
+
 /// Stores the stage's thread handle and output receiver.
 pub struct BankingStage {
     /// Handle to the stage's thread.
@@ -37,24 +45,52 @@ impl BankingStage {
         packet_recycler: PacketRecycler,
     ) -> (Self, Receiver<Signal>) {
         let (signal_sender, signal_receiver) = channel();
+        let banking_stage = Self::new_with_signal_sender(
+            bank,
+            verified_receiver,
+            packet_recycler,
+            signal_sender,
+            CommitLock::unrecorded(),
+        );
+        (banking_stage, signal_receiver)
+    }
+
+    /// Same as `new`, but sends the signals with `signal_sender`, which other
+    /// stages feeding the record stage may share, taking turns with them and
+    /// the record stage on `commit_lock`.
+    pub fn new_with_signal_sender(
+        bank: Arc<Bank>,
+        verified_receiver: Receiver<Vec<(SharedPackets, Vec<u8>)>>,
+        packet_recycler: PacketRecycler,
+        signal_sender: Sender<Signal>,
+        commit_lock: CommitLock,
+    ) -> Self {
         let thread_hdl = Builder::new()
             .name("solana-banking-stage".to_string())
-            .spawn(move || loop {
-                if let Err(e) = Self::process_packets(
-                    &bank,
-                    &verified_receiver,
-                    &signal_sender,
-                    &packet_recycler,
-                ) {
-                    match e {
-                        Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
-                        Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
-                        _ => error!("{:?}", e),
+            .spawn(move || {
+                cpu_time::register_thread("banking_stage");
+                let mut latency = StageLatency::new("banking_stage-latency");
+                let mut replay_stats = ReplayStats::default();
+                loop {
+                    if let Err(e) = Self::process_packets(
+                        &bank,
+                        &verified_receiver,
+                        &signal_sender,
+                        &commit_lock,
+                        &packet_recycler,
+                        &mut latency,
+                        &mut replay_stats,
+                    ) {
+                        match e {
+                            Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
+                            Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
+                            _ => error!("{:?}", e),
+                        }
                     }
                 }
             })
             .unwrap();
-        (BankingStage { thread_hdl }, signal_receiver)
+        BankingStage { thread_hdl }
     }
 
     /// Convert the transactions from a blob of binary data to a vector of transactions and
@@ -70,13 +106,18 @@ impl BankingStage {
             .collect()
     }
 
-    /// Process the incoming packets and send output `Signal` messages to `signal_sender`.
-    /// Discard packets via `packet_recycler`.
+    /// Process the incoming packets and send output `Signal` messages to `signal_sender`,
+    /// applying and sending each batch on its turn of `commit_lock`. Discard packets via
+    /// `packet_recycler`, record each batch's latency in `latency`, and count the
+    /// transactions rejected as replays in `replay_stats`.
     pub fn process_packets(
         bank: &Arc<Bank>,
         verified_receiver: &Receiver<Vec<(SharedPackets, Vec<u8>)>>,
         signal_sender: &Sender<Signal>,
+        commit_lock: &CommitLock,
         packet_recycler: &PacketRecycler,
+        latency: &mut StageLatency,
+        replay_stats: &mut ReplayStats,
     ) -> Result<()> {
         let timer = Duration::new(1, 0);
         let recv_start = Instant::now();
@@ -92,30 +133,66 @@ impl BankingStage {
         let bank_starting_tx_count = bank.transaction_count();
         let count = mms.iter().map(|x| x.1.len()).sum();
         let proc_start = Instant::now();
+        let mut fees = vec![];
         for (msgs, vers) in mms {
+            let batch_start = Instant::now();
+            let recv_time = msgs.read().unwrap().recv_time;
+            latency.record_since_recv(recv_time);
             let transactions = Self::deserialize_transactions(&msgs.read().unwrap());
             reqs_len += transactions.len();
-            let transactions = transactions
+            let mut transactions: Vec<(Transaction, SocketAddr)> = transactions
                 .into_iter()
                 .zip(vers)
                 .filter_map(|(tx, ver)| match tx {
                     None => None,
-                    Some((tx, _addr)) => if tx.verify_plan() && ver != 0 {
-                        Some(tx)
+                    Some((tx, addr)) => if tx.verify_plan() && ver != 0 {
+                        Some((tx, addr))
                     } else {
                         None
                     },
                 })
                 .collect();
+            // highest fees first, so they win any conflicts over an account
+            transactions.sort_by(|a, b| b.0.fee.cmp(&a.0.fee));
+            let (mut transactions, mut addrs): (Vec<_>, Vec<_>) = transactions.into_iter().unzip();
 
             debug!("process_transactions");
-            let results = bank.process_transactions(transactions);
-            let transactions = results.into_iter().filter_map(|x| x.ok()).collect();
-            signal_sender.send(Signal::Transactions(transactions))?;
+            // transactions deferred over an account one before them held are
+            // retried as a batch of their own, for up to `MAX_BATCH_PASSES`
+            for _ in 0..MAX_BATCH_PASSES {
+                if transactions.is_empty() {
+                    break;
+                }
+                let mut deferred = vec![];
+                commit_lock.commit(signal_sender, recv_time, || {
+                    let results = bank.process_transactions(&transactions);
+                    let mut processed = vec![];
+                    for ((tx, result), addr) in transactions.into_iter().zip(results).zip(addrs) {
+                        match result {
+                            Ok(()) => processed.push(tx),
+                            Err(BankError::AccountInUse(_)) => deferred.push((tx, addr)),
+                            Err(err) => replay_stats.record(addr.ip(), &err),
+                        }
+                    }
+                    fees.extend(processed.iter().map(|tx| tx.fee));
+                    processed
+                })?;
+                if !deferred.is_empty() {
+                    inc_new_counter_info!("banking_stage-account_in_use", deferred.len());
+                }
+                let (deferred_transactions, deferred_addrs) = deferred.into_iter().unzip();
+                transactions = deferred_transactions;
+                addrs = deferred_addrs;
+            }
+            if !transactions.is_empty() {
+                inc_new_counter_info!("banking_stage-account_in_use-dropped", transactions.len());
+            }
+            latency.process.record(&batch_start.elapsed());
             debug!("done process_transactions");
 
             packet_recycler.recycle(msgs);
         }
+        bank.record_fees(&fees, count);
         let total_time_s = timing::duration_as_s(&proc_start.elapsed());
         let total_time_ms = timing::duration_as_ms(&proc_start.elapsed());
         info!(
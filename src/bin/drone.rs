@@ -7,15 +7,19 @@ extern crate tokio;
 extern crate tokio_codec;
 
 use bincode::deserialize;
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use solana::crdt::NodeInfo;
-use solana::drone::{Drone, DroneRequest, DRONE_PORT};
+use solana::drone::{AirdropCoupon, Drone, DroneRequest, DRONE_PORT};
 use solana::fullnode::Config;
 use solana::logger;
 use solana::metrics::set_panic_hook;
-use solana::signature::read_keypair;
+use solana::signature::{read_keypair, Keypair, Pubkey};
+use solana::timing::timestamp;
 use std::fs::File;
+use std::io::Write;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::process::exit;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use tokio::net::TcpListener;
@@ -60,6 +64,49 @@ fn main() {
                 .takes_value(true)
                 .help("request limit for time slice"),
         )
+        .arg(
+            Arg::with_name("redeemed-coupons")
+                .long("redeemed-coupons")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("keep redeemed coupons in PATH, defaults to redeemed-coupons.json next to the keypair"),
+        )
+        .subcommand(
+            SubCommand::with_name("issue-coupon")
+                .about("Sign an airdrop coupon and exit, without starting the drone")
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The pubkey that redeeming the coupon pays"),
+                )
+                .arg(
+                    Arg::with_name("tokens")
+                        .long("tokens")
+                        .value_name("NUMBER")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The number of tokens the coupon is worth"),
+                )
+                .arg(
+                    Arg::with_name("expires-in")
+                        .long("expires-in")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .default_value("86400")
+                        .help("How long the coupon can be redeemed for"),
+                )
+                .arg(
+                    Arg::with_name("outfile")
+                        .short("o")
+                        .long("outfile")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .help("Write the coupon to PATH instead of stdout"),
+                ),
+        )
         .get_matches();
 
     let leader: NodeInfo;
@@ -70,8 +117,13 @@ fn main() {
         leader = NodeInfo::new_leader(&server_addr);
     };
 
-    let mint_keypair =
-        read_keypair(matches.value_of("keypair").expect("keypair")).expect("client keypair");
+    let keypair_path = matches.value_of("keypair").expect("keypair");
+    let mint_keypair = read_keypair(keypair_path).expect("client keypair");
+
+    if let Some(coupon_matches) = matches.subcommand_matches("issue-coupon") {
+        issue_coupon(&mint_keypair, coupon_matches);
+        return;
+    }
 
     let time_slice: Option<u64>;
     if let Some(t) = matches.value_of("time") {
@@ -86,16 +138,32 @@ fn main() {
         request_cap = None;
     }
 
+    let redeemed_coupons_path = match matches.value_of("redeemed-coupons") {
+        Some(path) => path.to_string(),
+        None => Path::new(keypair_path)
+            .with_file_name("redeemed-coupons.json")
+            .to_string_lossy()
+            .into_owned(),
+    };
+
     let drone_addr: SocketAddr = format!("0.0.0.0:{}", DRONE_PORT).parse().unwrap();
 
-    let drone = Arc::new(Mutex::new(Drone::new(
+    let mut drone = Drone::new(
         mint_keypair,
         drone_addr,
         leader.contact_info.tpu,
         leader.contact_info.rpu,
         time_slice,
         request_cap,
-    )));
+    );
+    if let Err(err) = drone.load_redeemed_coupons(&redeemed_coupons_path) {
+        eprintln!(
+            "failed to load redeemed coupons from {}: {}",
+            redeemed_coupons_path, err
+        );
+        exit(1);
+    }
+    let drone = Arc::new(Mutex::new(drone));
 
     let drone1 = drone.clone();
     thread::spawn(move || loop {
@@ -130,7 +198,7 @@ fn main() {
                     let res1 = drone2.lock().unwrap().send_airdrop(req);
                     match res1 {
                         Ok(_) => println!("Airdrop sent!"),
-                        Err(_) => println!("Request limit reached for this time slice"),
+                        Err(err) => println!("Airdrop failed: {}", err),
                     }
                     Ok(())
                 })
@@ -142,6 +210,36 @@ fn main() {
         });
     tokio::run(done);
 }
+fn issue_coupon(mint_keypair: &Keypair, matches: &ArgMatches) {
+    let to: Pubkey = matches
+        .value_of("to")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|err| {
+            eprintln!("invalid public key: {}", err);
+            exit(1);
+        });
+    let tokens = matches
+        .value_of("tokens")
+        .unwrap()
+        .parse()
+        .expect("integer");
+    let expires_in: u64 = matches
+        .value_of("expires-in")
+        .unwrap()
+        .parse()
+        .expect("integer");
+    let coupon = AirdropCoupon::new(mint_keypair, to, tokens, timestamp() + expires_in * 1000);
+    let serialized = serde_json::to_string(&coupon).expect("serialize coupon");
+    if let Some(outfile) = matches.value_of("outfile") {
+        let mut f =
+            File::create(outfile).unwrap_or_else(|_| panic!("failed to create {}", outfile));
+        f.write_all(serialized.as_bytes()).expect("write coupon");
+    } else {
+        println!("{}", serialized);
+    }
+}
+
 fn read_leader(path: &str) -> Config {
     let file = File::open(path).unwrap_or_else(|_| panic!("file not found: {}", path));
     serde_json::from_reader(file).unwrap_or_else(|_| panic!("failed to parse {}", path))
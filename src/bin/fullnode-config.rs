@@ -5,10 +5,15 @@ extern crate serde_json;
 extern crate solana;
 
 use clap::{App, Arg};
+use solana::broadcast_stage::BroadcastPacing;
 use solana::crdt::{get_ip_addr, parse_port_or_addr};
 use solana::fullnode::Config;
+use solana::ledger::{StoragePaths, TICKS_PER_SLOT};
 use solana::nat::get_public_ip_addr;
+use solana::rpc::JsonRpcConfig;
+use solana::rpc_tls::RpcTlsIdentity;
 use solana::signature::read_pkcs8;
+use solana::sigverify_stage::SigVerifyConfig;
 use std::io;
 use std::net::SocketAddr;
 
@@ -45,6 +50,160 @@ fn main() {
                 .takes_value(true)
                 .help("bind to port or address"),
         )
+        .arg(
+            Arg::with_name("ledger")
+                .long("ledger")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("store the ledger in DIR"),
+        )
+        .arg(
+            Arg::with_name("snapshots")
+                .long("snapshots")
+                .value_name("DIR")
+                .takes_value(true)
+                .requires("ledger")
+                .help("use DIR for snapshots, defaults to a directory in the ledger"),
+        )
+        .arg(
+            Arg::with_name("accounts")
+                .long("accounts")
+                .value_name("DIR")
+                .takes_value(true)
+                .requires("ledger")
+                .help("use DIR for the accounts database, defaults to a directory in the ledger"),
+        )
+        .arg(
+            Arg::with_name("broadcast-blobs-per-tick")
+                .long("broadcast-blobs-per-tick")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("as the leader, broadcast at most NUM blobs per tick on average"),
+        )
+        .arg(
+            Arg::with_name("broadcast-burst")
+                .long("broadcast-burst")
+                .value_name("NUM")
+                .takes_value(true)
+                .requires("broadcast-blobs-per-tick")
+                .help("broadcast up to NUM blobs at once, defaults to a slot's worth"),
+        )
+        .arg(
+            Arg::with_name("tick-ms")
+                .long("tick-ms")
+                .value_name("MS")
+                .takes_value(true)
+                .requires("broadcast-blobs-per-tick")
+                .help("pace broadcasts for ticks of MS milliseconds, defaults to 100"),
+        )
+        .arg(
+            Arg::with_name("broadcast-send-threads")
+                .long("broadcast-send-threads")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("as the leader, spread broadcasts over NUM sockets and threads"),
+        )
+        .arg(
+            Arg::with_name("sigverify-workers")
+                .long("sigverify-workers")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("verify signatures on NUM threads, defaults to half the cores"),
+        )
+        .arg(
+            Arg::with_name("sigverify-batch-size")
+                .long("sigverify-batch-size")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("verify at most about NUM packets per batch"),
+        )
+        .arg(
+            Arg::with_name("sigverify-max-in-flight")
+                .long("sigverify-max-in-flight")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("queue at most NUM verified batches for the bank"),
+        )
+        .arg(
+            Arg::with_name("rpc-ip-rate")
+                .long("rpc-ip-rate")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("serve each client IP at most NUM RPC requests per second"),
+        )
+        .arg(
+            Arg::with_name("rpc-ip-burst")
+                .long("rpc-ip-burst")
+                .value_name("NUM")
+                .takes_value(true)
+                .requires("rpc-ip-rate")
+                .help("let a client IP make NUM RPC requests at once, defaults to 100"),
+        )
+        .arg(
+            Arg::with_name("rpc-rate")
+                .long("rpc-rate")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("serve at most NUM RPC requests per second for all clients together"),
+        )
+        .arg(
+            Arg::with_name("rpc-api-keys")
+                .long("rpc-api-keys")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("serve RPC API keys with the methods and rates in the JSON file PATH"),
+        )
+        .arg(
+            Arg::with_name("rpc-tls-cert")
+                .long("rpc-tls-cert")
+                .value_name("PATH")
+                .takes_value(true)
+                .requires("rpc-tls-key")
+                .help("serve RPC over HTTPS with the PEM certificate chain in PATH"),
+        )
+        .arg(
+            Arg::with_name("rpc-tls-key")
+                .long("rpc-tls-key")
+                .value_name("PATH")
+                .takes_value(true)
+                .requires("rpc-tls-cert")
+                .help("the PEM private key for --rpc-tls-cert"),
+        )
+        .arg(
+            Arg::with_name("rpc-health-max-behind")
+                .long("rpc-health-max-behind")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("have getHealth fail past NUM entries behind the leader, defaults to 1000"),
+        )
+        .arg(
+            Arg::with_name("plugin-socket")
+                .long("plugin-socket")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("stream committed accounts and entries to plugins connecting to PATH"),
+        )
+        .arg(
+            Arg::with_name("entry-stream-addr")
+                .long("entry-stream-addr")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .help("publish the ledger to read replicas on HOST:PORT"),
+        )
+        .arg(
+            Arg::with_name("admin-rpc")
+                .long("admin-rpc")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .help("serve failover requests on HOST:PORT, only bind a trusted interface"),
+        )
+        .arg(
+            Arg::with_name("pubsub")
+                .long("pubsub")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .help("serve account and signature subscriptions on HOST:PORT"),
+        )
         .get_matches();
 
     let bind_addr: SocketAddr = {
@@ -77,7 +236,129 @@ fn main() {
 
     // we need all the receiving sockets to be bound within the expected
     // port range that we open on aws
-    let config = Config::new(&bind_addr, pkcs8);
+    let mut config = Config::new(&bind_addr, pkcs8);
+    if let Some(ledger_path) = matches.value_of("ledger") {
+        let mut storage = StoragePaths::new(ledger_path);
+        if let Some(snapshots) = matches.value_of("snapshots") {
+            storage.snapshots = snapshots.to_string();
+        }
+        if let Some(accounts) = matches.value_of("accounts") {
+            storage.accounts = accounts.to_string();
+        }
+        config.storage = Some(storage);
+    }
+    if let Some(blobs_per_tick) = matches.value_of("broadcast-blobs-per-tick") {
+        let blobs_per_tick = blobs_per_tick
+            .parse()
+            .expect("please pass a number for --broadcast-blobs-per-tick");
+        let burst = match matches.value_of("broadcast-burst") {
+            Some(burst) => burst
+                .parse()
+                .expect("please pass a number for --broadcast-burst"),
+            None => blobs_per_tick * TICKS_PER_SLOT,
+        };
+        let tick_ms = match matches.value_of("tick-ms") {
+            Some(tick_ms) => tick_ms.parse().expect("please pass a number for --tick-ms"),
+            None => 100,
+        };
+        config.broadcast_pacing = Some(BroadcastPacing {
+            blobs_per_tick,
+            burst,
+            tick_ms,
+        });
+    }
+    if let Some(send_threads) = matches.value_of("broadcast-send-threads") {
+        config.broadcast_send_threads = Some(
+            send_threads
+                .parse()
+                .expect("please pass a number for --broadcast-send-threads"),
+        );
+    }
+    if matches.is_present("sigverify-workers")
+        || matches.is_present("sigverify-batch-size")
+        || matches.is_present("sigverify-max-in-flight")
+    {
+        let mut sigverify = SigVerifyConfig::default();
+        if let Some(workers) = matches.value_of("sigverify-workers") {
+            sigverify.workers = workers
+                .parse()
+                .expect("please pass a number for --sigverify-workers");
+        }
+        if let Some(batch_size) = matches.value_of("sigverify-batch-size") {
+            sigverify.batch_size = batch_size
+                .parse()
+                .expect("please pass a number for --sigverify-batch-size");
+        }
+        if let Some(max_in_flight) = matches.value_of("sigverify-max-in-flight") {
+            sigverify.max_in_flight = max_in_flight
+                .parse()
+                .expect("please pass a number for --sigverify-max-in-flight");
+        }
+        config.sigverify = Some(sigverify);
+    }
+    if matches.is_present("rpc-ip-rate")
+        || matches.is_present("rpc-rate")
+        || matches.is_present("rpc-api-keys")
+        || matches.is_present("rpc-health-max-behind")
+        || matches.is_present("rpc-tls-cert")
+    {
+        let mut rpc = JsonRpcConfig::default();
+        if let Some(ip_rate) = matches.value_of("rpc-ip-rate") {
+            rpc.requests_per_second_per_ip = ip_rate
+                .parse()
+                .expect("please pass a number for --rpc-ip-rate");
+        }
+        if let Some(ip_burst) = matches.value_of("rpc-ip-burst") {
+            rpc.burst_per_ip = ip_burst
+                .parse()
+                .expect("please pass a number for --rpc-ip-burst");
+        }
+        if let Some(rate) = matches.value_of("rpc-rate") {
+            rpc.requests_per_second = rate.parse().expect("please pass a number for --rpc-rate");
+        }
+        if let Some(api_keys) = matches.value_of("rpc-api-keys") {
+            rpc.api_keys = Some(api_keys.to_string());
+        }
+        if let Some(max_behind) = matches.value_of("rpc-health-max-behind") {
+            rpc.health_max_entries_behind = max_behind
+                .parse()
+                .expect("please pass a number for --rpc-health-max-behind");
+        }
+        if let (Some(cert_path), Some(key_path)) = (
+            matches.value_of("rpc-tls-cert"),
+            matches.value_of("rpc-tls-key"),
+        ) {
+            rpc.tls = Some(RpcTlsIdentity {
+                cert_path: cert_path.to_string(),
+                key_path: key_path.to_string(),
+            });
+        }
+        config.rpc = Some(rpc);
+    }
+    if let Some(plugin_socket) = matches.value_of("plugin-socket") {
+        config.plugin_socket = Some(plugin_socket.to_string());
+    }
+    if let Some(entry_stream_addr) = matches.value_of("entry-stream-addr") {
+        config.entry_stream_addr = Some(
+            entry_stream_addr
+                .parse()
+                .expect("please pass an address for --entry-stream-addr"),
+        );
+    }
+    if let Some(admin_rpc_addr) = matches.value_of("admin-rpc") {
+        config.admin_rpc_addr = Some(
+            admin_rpc_addr
+                .parse()
+                .expect("please pass an address for --admin-rpc"),
+        );
+    }
+    if let Some(pubsub_addr) = matches.value_of("pubsub") {
+        config.pubsub_addr = Some(
+            pubsub_addr
+                .parse()
+                .expect("please pass an address for --pubsub"),
+        );
+    }
     let stdout = io::stdout();
     serde_json::to_writer(stdout, &config).expect("serialize");
 }
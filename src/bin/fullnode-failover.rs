@@ -0,0 +1,131 @@
+#[macro_use]
+extern crate clap;
+extern crate reqwest;
+#[macro_use]
+extern crate serde_json;
+extern crate solana;
+
+use clap::{App, Arg};
+use serde_json::Value;
+use solana::fullnode::Config;
+use solana::rpc::default_admin_rpc_addr;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::process::exit;
+
+fn admin_request(addr: &str, method: &str, params: Value) -> Result<Value, String> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+    let mut response = reqwest::Client::new()
+        .post(&format!("http://{}", addr))
+        .json(&request)
+        .send()
+        .map_err(|err| format!("{} to {} failed: {}", method, addr, err))?;
+    let response: Value = response
+        .json()
+        .map_err(|err| format!("{} to {} returned garbage: {}", method, addr, err))?;
+    if let Some(error) = response.get("error") {
+        return Err(format!(
+            "{} to {} failed: {}",
+            method, addr, error["message"]
+        ));
+    }
+    Ok(response["result"].clone())
+}
+
+/// The admin RPC address the fullnode configured by `path` serves on.
+fn config_admin_rpc_addr(path: &str) -> Result<SocketAddr, String> {
+    let file = File::open(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    let config: Config = serde_json::from_reader(file)
+        .map_err(|err| format!("failed to parse {}: {}", path, err))?;
+    Ok(config.admin_rpc_addr.unwrap_or_else(default_admin_rpc_addr))
+}
+
+fn main() {
+    let matches = App::new("fullnode-failover")
+        .version(crate_version!())
+        .about("Hands the identity lease of a warm standby pair to the standby")
+        .after_help(
+            "The standby may run on another host if its admin RPC is bound to an \
+             address this host can reach, set with solana-fullnode-config --admin-rpc. \
+             Only bind the admin RPC to a private or otherwise trusted interface.",
+        )
+        .arg(
+            Arg::with_name("active")
+                .short("a")
+                .long("active")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .conflicts_with("identity")
+                .help("admin RPC address of the node holding the identity, defaults to localhost"),
+        )
+        .arg(
+            Arg::with_name("identity")
+                .short("i")
+                .long("identity")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("take the address of the node holding the identity from its config FILE"),
+        )
+        .arg(
+            Arg::with_name("standby")
+                .short("s")
+                .long("standby")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .required(true)
+                .help("admin RPC address of the standby node"),
+        )
+        .arg(
+            Arg::with_name("term")
+                .long("term")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("skip the release and hand over a term already released by the active node"),
+        )
+        .get_matches();
+
+    let active = match (matches.value_of("active"), matches.value_of("identity")) {
+        (Some(active), _) => active.to_string(),
+        (None, Some(path)) => config_admin_rpc_addr(path)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                exit(1);
+            })
+            .to_string(),
+        (None, None) => default_admin_rpc_addr().to_string(),
+    };
+    let active = &active;
+    let standby = matches.value_of("standby").unwrap();
+
+    let term = match matches.value_of("term") {
+        Some(term) => term.parse().unwrap_or_else(|_| {
+            eprintln!("invalid term {}", term);
+            exit(1);
+        }),
+        None => {
+            let result =
+                admin_request(active, "releaseIdentity", json!([])).unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    exit(1);
+                });
+            let term = result.as_u64().expect("term");
+            eprintln!("{} released the identity at term {}", active, term);
+            term
+        }
+    };
+
+    if let Err(err) = admin_request(standby, "acquireIdentity", json!([term])) {
+        eprintln!("{}", err);
+        eprintln!(
+            "no node holds the identity, retry with: --standby {} --term {}",
+            standby, term
+        );
+        exit(1);
+    }
+    println!("{} now holds the identity at term {}", standby, term);
+}
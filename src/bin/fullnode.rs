@@ -5,19 +5,35 @@ extern crate log;
 extern crate serde_json;
 extern crate solana;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgGroup};
 use solana::client::mk_client;
 use solana::crdt::{NodeInfo, TestNode};
 use solana::drone::DRONE_PORT;
 use solana::fullnode::{Config, Fullnode};
+use solana::handshake::handshake;
+use solana::identity_lease::IdentityLease;
+use solana::ledger::StoragePaths;
 use solana::logger;
 use solana::metrics::set_panic_hook;
+use solana::port_check::check_ports;
+use solana::result::Error;
+use solana::rpc::default_admin_rpc_addr;
+use solana::rpc_pubsub::PUBSUB_PORT;
 use solana::service::Service;
 use solana::signature::{Keypair, KeypairUtil};
 use solana::wallet::request_airdrop;
 use std::fs::File;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::process::exit;
+use std::sync::Arc;
+
+/// What went wrong, worded for the operator where the error says enough.
+fn describe(err: &Error) -> String {
+    match err {
+        Error::StartupError(err) => err.to_string(),
+        err => format!("{:?}", err),
+    }
+}
 
 fn main() -> () {
     logger::setup();
@@ -46,21 +62,89 @@ fn main() -> () {
                 .long("ledger")
                 .value_name("DIR")
                 .takes_value(true)
-                .required(true)
                 .help("use DIR as persistent ledger location"),
         )
+        .arg(
+            Arg::with_name("snapshots")
+                .long("snapshots")
+                .value_name("DIR")
+                .takes_value(true)
+                .requires("storage")
+                .help("use DIR for snapshots, defaults to a directory in the ledger"),
+        )
+        .arg(
+            Arg::with_name("accounts")
+                .long("accounts")
+                .value_name("DIR")
+                .takes_value(true)
+                .requires("storage")
+                .help("use DIR for the accounts database, defaults to a directory in the ledger"),
+        )
+        // the identity may say where the ledger is instead of --ledger
+        .group(
+            ArgGroup::with_name("storage")
+                .args(&["ledger", "identity"])
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .long("snapshot")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("start from the bank snapshot in FILE, replaying only the ledger after it"),
+        )
+        .arg(
+            Arg::with_name("standby")
+                .long("standby")
+                .takes_value(false)
+                .help("start as a warm standby that doesn't sign until it acquires the identity"),
+        )
+        .arg(
+            Arg::with_name("no-port-check")
+                .long("no-port-check")
+                .takes_value(false)
+                .help("skip checking that the testnet entry point can reach this node's ports"),
+        )
         .get_matches();
 
     let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8000);
     let mut keypair = Keypair::new();
     let mut repl_data = NodeInfo::new_leader_with_pubkey(keypair.pubkey(), &bind_addr);
+    let mut storage = None;
+    let mut broadcast_pacing = None;
+    let mut broadcast_send_threads = None;
+    let mut sigverify_config = None;
+    let mut rpc_config = None;
+    let mut vote_identity = None;
+    let mut poh_hash = None;
+    let mut plugin_socket = None;
+    let mut entry_stream_addr = None;
+    let mut admin_rpc_addr = default_admin_rpc_addr();
+    let mut pubsub_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), PUBSUB_PORT);
     if let Some(i) = matches.value_of("identity") {
         let path = i.to_string();
         if let Ok(file) = File::open(path.clone()) {
             let parse: serde_json::Result<Config> = serde_json::from_reader(file);
             if let Ok(data) = parse {
-                keypair = data.keypair();
+                keypair = match data.keypair() {
+                    Ok(keypair) => keypair,
+                    Err(err) => {
+                        eprintln!("can't use the identity in {}: {}", path, describe(&err));
+                        exit(1);
+                    }
+                };
                 repl_data = data.node_info;
+                storage = data.storage;
+                broadcast_pacing = data.broadcast_pacing;
+                broadcast_send_threads = data.broadcast_send_threads;
+                sigverify_config = data.sigverify;
+                rpc_config = data.rpc;
+                vote_identity = data.vote_identity;
+                poh_hash = data.poh_hash;
+                plugin_socket = data.plugin_socket;
+                entry_stream_addr = data.entry_stream_addr;
+                admin_rpc_addr = data.admin_rpc_addr.unwrap_or(admin_rpc_addr);
+                pubsub_addr = data.pubsub_addr.unwrap_or(pubsub_addr);
             } else {
                 eprintln!("failed to parse {}", path);
                 exit(1);
@@ -74,7 +158,28 @@ fn main() -> () {
     let leader_pubkey = keypair.pubkey();
     let repl_clone = repl_data.clone();
 
-    let ledger_path = matches.value_of("ledger").unwrap();
+    let mut storage = match (matches.value_of("ledger"), storage) {
+        (Some(ledger_path), None) => StoragePaths::new(ledger_path),
+        (Some(ledger_path), Some(mut storage)) => {
+            storage.ledger = ledger_path.to_string();
+            storage
+        }
+        (None, Some(storage)) => storage,
+        (None, None) => {
+            eprintln!("no ledger location, use --ledger or an identity with storage paths");
+            exit(1);
+        }
+    };
+    if let Some(snapshots) = matches.value_of("snapshots") {
+        storage.snapshots = snapshots.to_string();
+    }
+    if let Some(accounts) = matches.value_of("accounts") {
+        storage.accounts = accounts.to_string();
+    }
+
+    let snapshot = matches.value_of("snapshot").map(|s| s.to_string());
+
+    let lease = Arc::new(IdentityLease::new(!matches.is_present("standby")));
 
     let mut node = TestNode::new_with_bind_addr(repl_data, bind_addr);
     let mut drone_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), DRONE_PORT);
@@ -83,13 +188,89 @@ fn main() -> () {
         let testnet_addr: SocketAddr = testnet_address_string.parse().unwrap();
         drone_addr.set_ip(testnet_addr.ip());
 
-        Fullnode::new(node, false, ledger_path, keypair, Some(testnet_addr))
+        match handshake(&node, &testnet_addr) {
+            Ok(Some(capabilities)) => eprintln!(
+                "handshake with {} agreed on {:?}",
+                testnet_addr, capabilities
+            ),
+            Ok(None) => eprintln!(
+                "{} didn't answer the handshake, it may predate it",
+                testnet_addr
+            ),
+            Err(err) => {
+                eprintln!(
+                    "can't join the network at {}: {:?}, upgrade this node or the network",
+                    testnet_addr, err
+                );
+                exit(1);
+            }
+        }
+
+        if !matches.is_present("no-port-check") {
+            match check_ports(&node, &testnet_addr) {
+                Ok(ref unreachable) if unreachable.is_empty() => {
+                    eprintln!("all ports are reachable from {}", testnet_addr)
+                }
+                Ok(unreachable) => {
+                    for (name, addr) in unreachable {
+                        eprintln!(
+                            "{} port {} is unreachable from {}, check your firewall",
+                            name, addr, testnet_addr
+                        );
+                    }
+                }
+                Err(err) => eprintln!("port check failed: {:?}", err),
+            }
+        }
+
+        Fullnode::new_with_storage(
+            node,
+            false,
+            &storage,
+            keypair,
+            lease,
+            Some(testnet_addr),
+            broadcast_pacing,
+            broadcast_send_threads,
+            sigverify_config,
+            rpc_config,
+            vote_identity,
+            poh_hash,
+            plugin_socket,
+            entry_stream_addr,
+            Some(admin_rpc_addr),
+            Some(pubsub_addr),
+            snapshot,
+        )
     } else {
         node.data.leader_id = node.data.id;
 
-        Fullnode::new(node, true, ledger_path, keypair, None)
+        Fullnode::new_with_storage(
+            node,
+            true,
+            &storage,
+            keypair,
+            lease,
+            None,
+            broadcast_pacing,
+            broadcast_send_threads,
+            sigverify_config,
+            rpc_config,
+            vote_identity,
+            poh_hash,
+            plugin_socket,
+            entry_stream_addr,
+            Some(admin_rpc_addr),
+            Some(pubsub_addr),
+            snapshot,
+        )
     };
 
+    let fullnode = fullnode.unwrap_or_else(|err| {
+        eprintln!("failed to start the fullnode: {}", describe(&err));
+        exit(1);
+    });
+
     let mut client = mk_client(&repl_clone);
     let previous_balance = client.poll_get_balance(&leader_pubkey).unwrap_or(0);
     eprintln!("balance is {}", previous_balance);
@@ -8,12 +8,43 @@ extern crate solana;
 
 use atty::{is, Stream};
 use clap::{App, Arg};
-use solana::ledger::LedgerWriter;
-use solana::mint::Mint;
+use solana::feature::{Feature, FEATURES};
+use solana::ledger::FileLedgerWriter;
+use solana::mint::{Grant, Mint};
+use solana::signature::Pubkey;
 use std::error;
 use std::io::{stdin, Read};
 use std::process::exit;
 
+fn parse_grant(grant: &str) -> Grant {
+    let mut parts = grant.splitn(2, '=');
+    let pubkey = parts.next().unwrap().parse::<Pubkey>();
+    let tokens = parts.next().map(|tokens| tokens.parse());
+    match (pubkey, tokens) {
+        (Ok(pubkey), Some(Ok(tokens))) if tokens > 0 => Grant { pubkey, tokens },
+        (Err(err), _) => {
+            eprintln!("invalid grant {}, its public key {}", grant, err);
+            exit(1);
+        }
+        _ => {
+            eprintln!("invalid grant {}, expected PUBKEY=NUMBER", grant);
+            exit(1);
+        }
+    }
+}
+
+fn parse_feature(name: &str) -> Feature {
+    Feature::from_name(name).unwrap_or_else(|| {
+        let names: Vec<_> = FEATURES.iter().map(|feature| feature.name()).collect();
+        eprintln!(
+            "unknown feature {}, expected one of {}",
+            name,
+            names.join(", ")
+        );
+        exit(1);
+    })
+}
+
 fn main() -> Result<(), Box<error::Error>> {
     let matches = App::new("solana-genesis")
         .version(crate_version!())
@@ -35,10 +66,38 @@ fn main() -> Result<(), Box<error::Error>> {
                 .required(true)
                 .help("use DIR as persistent ledger location"),
         )
+        .arg(
+            Arg::with_name("grant")
+                .short("g")
+                .long("grant")
+                .value_name("PUBKEY=NUMBER")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Grant NUMBER of the mint's tokens to PUBKEY, can be repeated"),
+        )
+        .arg(
+            Arg::with_name("feature")
+                .short("f")
+                .long("feature")
+                .value_name("NAME")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Activate the feature NAME from genesis on, can be repeated"),
+        )
         .get_matches();
 
     let tokens = value_t_or_exit!(matches, "tokens", i64);
     let ledger_path = matches.value_of("ledger").unwrap();
+    let grants: Vec<_> = matches
+        .values_of("grant")
+        .map(|grants| grants.map(parse_grant).collect())
+        .unwrap_or_default();
+    let features: Vec<_> = matches
+        .values_of("feature")
+        .map(|features| features.map(parse_feature).collect())
+        .unwrap_or_default();
 
     if is(Stream::Stdin) {
         eprintln!("nothing found on stdin, expected a json file");
@@ -53,9 +112,15 @@ fn main() -> Result<(), Box<error::Error>> {
     }
 
     let pkcs8: Vec<u8> = serde_json::from_str(&buffer)?;
-    let mint = Mint::new_with_pkcs8(tokens, pkcs8);
+    let mut mint = Mint::new_with_pkcs8(tokens, pkcs8);
+    mint.grants = grants;
+    mint.features = features;
+    if mint.remaining_tokens() < 0 {
+        eprintln!("grants add up to more than {} tokens", tokens);
+        exit(1);
+    }
 
-    let mut ledger_writer = LedgerWriter::open(&ledger_path, true)?;
+    let mut ledger_writer = FileLedgerWriter::open(&ledger_path, true)?;
     ledger_writer.write_entries(mint.create_entries())?;
 
     Ok(())
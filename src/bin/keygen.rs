@@ -3,16 +3,52 @@ extern crate clap;
 extern crate dirs;
 extern crate ring;
 extern crate serde_json;
+extern crate solana;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use ring::rand::SystemRandom;
 use ring::signature::Ed25519KeyPair;
+use solana::signature::{keypair_signs_for, read_pubkey, Pubkey};
 use std::error;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::Path;
+use std::process::exit;
+
+fn default_keypair_path() -> String {
+    let mut path = dirs::home_dir().expect("home directory");
+    path.extend(&[".config", "solana", "id.json"]);
+    path.to_str().unwrap().to_string()
+}
+
+fn generate(matches: &ArgMatches) -> Result<(), Box<error::Error>> {
+    let rnd = SystemRandom::new();
+    let pkcs8_bytes = Ed25519KeyPair::generate_pkcs8(&rnd)?;
+    let serialized = serde_json::to_string(&pkcs8_bytes.to_vec())?;
+
+    let outfile = matches
+        .value_of("outfile")
+        .map(|s| s.to_string())
+        .unwrap_or_else(default_keypair_path);
+
+    if outfile == "-" {
+        println!("{}", serialized);
+    } else {
+        if let Some(outdir) = Path::new(&outfile).parent() {
+            fs::create_dir_all(outdir)?;
+        }
+        let mut f = File::create(outfile)?;
+        f.write_all(&serialized.into_bytes())?;
+    }
+
+    Ok(())
+}
 
 fn main() -> Result<(), Box<error::Error>> {
+    let keypair_arg = Arg::with_name("keypair")
+        .value_name("PATH")
+        .takes_value(true)
+        .help("keypair file, defaults to ~/.config/solana/id.json");
     let matches = App::new("solana-keygen")
         .version(crate_version!())
         .arg(
@@ -23,28 +59,52 @@ fn main() -> Result<(), Box<error::Error>> {
                 .takes_value(true)
                 .help("path to generated file"),
         )
+        .subcommand(
+            SubCommand::with_name("pubkey")
+                .about("Print the public key of a keypair file")
+                .arg(keypair_arg.clone().index(1)),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check that a keypair file signs for a public key")
+                .arg(
+                    Arg::with_name("pubkey")
+                        .index(1)
+                        .value_name("PUBKEY")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the claimed public key"),
+                )
+                .arg(keypair_arg.index(2)),
+        )
         .get_matches();
 
-    let rnd = SystemRandom::new();
-    let pkcs8_bytes = Ed25519KeyPair::generate_pkcs8(&rnd)?;
-    let serialized = serde_json::to_string(&pkcs8_bytes.to_vec())?;
-
-    let mut path = dirs::home_dir().expect("home directory");
-    let outfile = if matches.is_present("outfile") {
-        matches.value_of("outfile").unwrap()
-    } else {
-        path.extend(&[".config", "solana", "id.json"]);
-        path.to_str().unwrap()
-    };
-
-    if outfile == "-" {
-        println!("{}", serialized);
-    } else {
-        if let Some(outdir) = Path::new(outfile).parent() {
-            fs::create_dir_all(outdir)?;
+    match matches.subcommand() {
+        ("pubkey", Some(pubkey_matches)) => {
+            let path = pubkey_matches
+                .value_of("keypair")
+                .map(|s| s.to_string())
+                .unwrap_or_else(default_keypair_path);
+            println!("{}", read_pubkey(&path)?);
         }
-        let mut f = File::create(outfile)?;
-        f.write_all(&serialized.into_bytes())?;
+        ("verify", Some(verify_matches)) => {
+            let path = verify_matches
+                .value_of("keypair")
+                .map(|s| s.to_string())
+                .unwrap_or_else(default_keypair_path);
+            let claimed = verify_matches.value_of("pubkey").unwrap();
+            let pubkey: Pubkey = claimed.parse().unwrap_or_else(|err| {
+                eprintln!("{} is not a valid public key: {}", claimed, err);
+                exit(1);
+            });
+            if keypair_signs_for(&path, &pubkey)? {
+                println!("{} signs for {}", path, pubkey);
+            } else {
+                eprintln!("{} does not sign for {}", path, pubkey);
+                exit(1);
+            }
+        }
+        _ => generate(&matches)?,
     }
 
     Ok(())
@@ -3,12 +3,74 @@ extern crate clap;
 extern crate serde_json;
 extern crate solana;
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use solana::bank::Bank;
 use solana::ledger::{read_ledger, verify_ledger};
 use solana::logger;
+use solana::timing::{duration_as_ms, duration_as_ns};
+use std::fs;
 use std::io::{stdout, Write};
 use std::process::exit;
+use std::time::{Duration, Instant};
+
+/// This process's resident memory in kB, where `/proc` has it.
+fn resident_kb() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Fill a bank with synthetic accounts and report what holding that many
+/// costs, to plan for account counts no test ledger reaches.
+fn capacity(matches: &ArgMatches) {
+    let num_accounts = value_t_or_exit!(matches, "accounts", u64);
+    let num_lookups = value_t_or_exit!(matches, "lookups", u64);
+    let bank = Bank::default();
+
+    let rss_before = resident_kb();
+    let now = Instant::now();
+    bank.add_synthetic_accounts(num_accounts, 1);
+    println!(
+        "created {} accounts in {}ms",
+        bank.account_count(),
+        duration_as_ms(&now.elapsed())
+    );
+    if let (Some(before), Some(after)) = (rss_before, resident_kb()) {
+        let kb = after.saturating_sub(before);
+        println!(
+            "resident memory grew {}kB, {} bytes per account",
+            kb,
+            kb * 1024 / num_accounts.max(1)
+        );
+    }
+
+    if num_accounts > 0 {
+        let mut slowest = Duration::default();
+        let now = Instant::now();
+        for i in 0..num_lookups {
+            // stride through the accounts in an order the map can't predict
+            let pubkey = Bank::synthetic_pubkey(i.wrapping_mul(7_919) % num_accounts);
+            let lookup = Instant::now();
+            assert_eq!(bank.get_balance(&pubkey), 1);
+            slowest = slowest.max(lookup.elapsed());
+        }
+        let elapsed = now.elapsed();
+        println!(
+            "{} lookups averaged {}ns, slowest {}ns",
+            num_lookups,
+            duration_as_ns(&elapsed) / num_lookups.max(1),
+            duration_as_ns(&slowest)
+        );
+    }
+
+    let now = Instant::now();
+    let (_, snapshot) = bank.accounts_snapshot();
+    println!(
+        "snapshot of {} accounts took {}ms",
+        snapshot.len(),
+        duration_as_ms(&now.elapsed())
+    );
+}
 
 fn main() {
     logger::setup();
@@ -20,7 +82,6 @@ fn main() {
                 .long("ledger")
                 .value_name("DIR")
                 .takes_value(true)
-                .required(true)
                 .help("use DIR for ledger location"),
         )
         .arg(
@@ -29,7 +90,7 @@ fn main() {
                 .long("head")
                 .value_name("NUM")
                 .takes_value(true)
-                .help("at most the first NUM entries in ledger\n  (only applies to verify, print, json commands)"),
+                .help("at most the first NUM entries in ledger\n  (only applies to verify, print, json, snapshot commands)"),
         )
         .arg(
             Arg::with_name("precheck")
@@ -40,9 +101,48 @@ fn main() {
         .subcommand(SubCommand::with_name("print").about("Print the ledger"))
         .subcommand(SubCommand::with_name("json").about("Print the ledger in JSON format"))
         .subcommand(SubCommand::with_name("verify").about("Verify the ledger's PoH"))
+        .subcommand(
+            SubCommand::with_name("snapshot")
+                .about("Process the ledger and write a bank snapshot a fullnode can start from")
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("write the snapshot to FILE"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("capacity")
+                .about("Measure a bank holding many synthetic accounts; ignores the ledger")
+                .arg(
+                    Arg::with_name("accounts")
+                        .long("accounts")
+                        .value_name("NUM")
+                        .takes_value(true)
+                        .default_value("1000000")
+                        .help("create NUM accounts"),
+                )
+                .arg(
+                    Arg::with_name("lookups")
+                        .long("lookups")
+                        .value_name("NUM")
+                        .takes_value(true)
+                        .default_value("100000")
+                        .help("time NUM account lookups"),
+                ),
+        )
         .get_matches();
 
-    let ledger_path = matches.value_of("ledger").unwrap();
+    if let ("capacity", Some(capacity_matches)) = matches.subcommand() {
+        capacity(capacity_matches);
+        return;
+    }
+
+    let ledger_path = matches.value_of("ledger").unwrap_or_else(|| {
+        eprintln!("{}", matches.usage());
+        exit(1);
+    });
 
     if matches.is_present("precheck") {
         if let Err(e) = verify_ledger(&ledger_path) {
@@ -128,6 +228,21 @@ fn main() {
                 }
             }
         }
+        ("snapshot", Some(snapshot_matches)) => {
+            let path = snapshot_matches.value_of("file").unwrap();
+            let bank = Bank::default();
+            let entries = entries.take(head).map(|e| e.unwrap());
+            let (entry_height, tail) = bank.process_ledger(entries).unwrap_or_else(|e| {
+                eprintln!("processing the ledger failed, err: {:?}", e);
+                exit(1);
+            });
+            let last_entry_id = tail.last().unwrap().id;
+            if let Err(e) = bank.serialize_snapshot(path, entry_height, &last_entry_id) {
+                eprintln!("writing the snapshot to {} failed, err: {}", path, e);
+                exit(1);
+            }
+            println!("wrote a snapshot at entry {} to {}", entry_height, path);
+        }
         ("", _) => {
             eprintln!("{}", matches.usage());
             exit(1);
@@ -0,0 +1,67 @@
+#[macro_use]
+extern crate clap;
+extern crate solana;
+
+use clap::{App, Arg};
+use solana::entry_stream::ENTRY_STREAM_PORT;
+use solana::logger;
+use solana::metrics::set_panic_hook;
+use solana::replica::Replica;
+use solana::rpc::RPC_PORT;
+use solana::service::Service;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::process::exit;
+
+fn main() {
+    logger::setup();
+    set_panic_hook("replica");
+    let matches = App::new("replica")
+        .version(crate_version!())
+        .arg(
+            Arg::with_name("ledger")
+                .short("l")
+                .long("ledger")
+                .value_name("DIR")
+                .takes_value(true)
+                .required(true)
+                .help(
+                    "use DIR as persistent ledger location, it needs at least the genesis entries",
+                ),
+        )
+        .arg(
+            Arg::with_name("validator")
+                .short("v")
+                .long("validator")
+                .value_name("HOST")
+                .takes_value(true)
+                .required(true)
+                .help("follow the entry stream of the trusted validator at HOST"),
+        )
+        .arg(
+            Arg::with_name("rpc-port")
+                .long("rpc-port")
+                .value_name("PORT")
+                .takes_value(true)
+                .help("serve JSON RPC requests on PORT"),
+        )
+        .get_matches();
+
+    let ledger_path = matches.value_of("ledger").unwrap();
+    let validator: IpAddr = matches
+        .value_of("validator")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|e| {
+            eprintln!("failed to parse validator address: {:?}", e);
+            exit(1);
+        });
+    let stream_addr = SocketAddr::new(validator, ENTRY_STREAM_PORT);
+    let rpc_port = match matches.value_of("rpc-port") {
+        Some(port) => port.parse().expect("please pass a number for --rpc-port"),
+        None => RPC_PORT,
+    };
+    let rpc_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), rpc_port);
+
+    let replica = Replica::new(ledger_path, stream_addr, rpc_addr);
+    replica.join().expect("join");
+}
@@ -0,0 +1,153 @@
+extern crate bs58;
+#[macro_use]
+extern crate clap;
+extern crate reqwest;
+#[macro_use]
+extern crate serde_json;
+extern crate solana;
+
+use clap::{App, Arg};
+use serde_json::Value;
+use solana::rpc::{RpcAccountHash, RpcAccountsHashes};
+use std::collections::BTreeMap;
+use std::process::exit;
+use std::thread::sleep;
+use std::time::Duration;
+
+fn rpc_request(addr: &str, method: &str, params: Value) -> Result<Value, String> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+    let mut response = reqwest::Client::new()
+        .post(&format!("http://{}", addr))
+        .json(&request)
+        .send()
+        .map_err(|err| format!("{} to {} failed: {}", method, addr, err))?;
+    let response: Value = response
+        .json()
+        .map_err(|err| format!("{} to {} returned garbage: {}", method, addr, err))?;
+    if let Some(error) = response.get("error") {
+        return Err(format!("{} to {} failed: {}", method, addr, error["message"]));
+    }
+    Ok(response["result"].clone())
+}
+
+fn accounts_hashes(addr: &str) -> RpcAccountsHashes {
+    rpc_request(addr, "getAccountsHashes", json!([]))
+        .and_then(|result| {
+            serde_json::from_value(result)
+                .map_err(|err| format!("getAccountsHashes to {} returned garbage: {}", addr, err))
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(1);
+        })
+}
+
+/// The accounts keyed by address, in the order the nodes sorted them.
+fn by_address(hashes: &RpcAccountsHashes) -> BTreeMap<Vec<u8>, &RpcAccountHash> {
+    hashes
+        .accounts
+        .iter()
+        .map(|account| {
+            let pubkey = bs58::decode(&account.pubkey).into_vec().unwrap_or_default();
+            (pubkey, account)
+        })
+        .collect()
+}
+
+fn describe(account: Option<&&RpcAccountHash>) -> String {
+    match account {
+        Some(account) => format!("{} tokens, hash {}", account.tokens, account.hash),
+        None => "missing".to_string(),
+    }
+}
+
+fn main() {
+    let matches = App::new("state-diff")
+        .version(crate_version!())
+        .about("Compares the accounts of two nodes as of the same last id")
+        .arg(
+            Arg::with_name("left")
+                .index(1)
+                .value_name("HOST:PORT")
+                .required(true)
+                .help("RPC address of the first node"),
+        )
+        .arg(
+            Arg::with_name("right")
+                .index(2)
+                .value_name("HOST:PORT")
+                .required(true)
+                .help("RPC address of the second node"),
+        )
+        .arg(
+            Arg::with_name("tries")
+                .long("tries")
+                .value_name("NUM")
+                .takes_value(true)
+                .default_value("20")
+                .help("how many times to fetch both states before giving up on catching the nodes at the same last id"),
+        )
+        .get_matches();
+
+    let left_addr = matches.value_of("left").unwrap();
+    let right_addr = matches.value_of("right").unwrap();
+    let tries: usize = value_t_or_exit!(matches, "tries", usize);
+
+    let mut attempt = 0;
+    let (left, right) = loop {
+        let left = accounts_hashes(left_addr);
+        let right = accounts_hashes(right_addr);
+        if left.last_id == right.last_id {
+            break (left, right);
+        }
+        attempt += 1;
+        if attempt >= tries {
+            eprintln!(
+                "{} is at {} and {} at {}, never caught them at the same last id",
+                left_addr, left.last_id, right_addr, right.last_id
+            );
+            exit(1);
+        }
+        sleep(Duration::from_millis(50));
+    };
+
+    if left.hash == right.hash {
+        println!(
+            "{} accounts match as of last id {}",
+            left.accounts.len(),
+            left.last_id
+        );
+        return;
+    }
+
+    let left_accounts = by_address(&left);
+    let right_accounts = by_address(&right);
+    let mut addresses: Vec<_> = left_accounts.keys().chain(right_accounts.keys()).collect();
+    addresses.sort();
+    addresses.dedup();
+    for address in addresses {
+        let left_account = left_accounts.get(address);
+        let right_account = right_accounts.get(address);
+        if left_account.map(|a| &a.hash) != right_account.map(|a| &a.hash) {
+            println!(
+                "state diverges as of last id {} at account {}",
+                left.last_id,
+                bs58::encode(address).into_string()
+            );
+            println!("  {}: {}", left_addr, describe(left_account));
+            println!("  {}: {}", right_addr, describe(right_account));
+            exit(1);
+        }
+    }
+    // the per-account hashes match but not the overall one
+    println!(
+        "state diverges as of last id {}: {} {} vs {} {}",
+        left.last_id, left_addr, left.hash, right_addr, right.hash
+    );
+    exit(1);
+}
@@ -1,21 +1,27 @@
 extern crate atty;
 extern crate bincode;
-extern crate bs58;
 #[macro_use]
 extern crate clap;
 extern crate dirs;
+extern crate reqwest;
+#[macro_use]
 extern crate serde_json;
 extern crate solana;
 
 use clap::{App, Arg, SubCommand};
+use serde_json::Value;
 use solana::client::mk_client;
 use solana::crdt::NodeInfo;
-use solana::drone::DRONE_PORT;
+use solana::drone::{AirdropCoupon, DRONE_PORT};
 use solana::fullnode::Config;
+use solana::hash::Hash;
 use solana::logger;
+use solana::network_preset::network_preset;
+use solana::rpc::{RpcConfirmedTransaction, RpcSignatureForAddress, RPC_PORT};
 use solana::signature::{read_keypair, Keypair, KeypairUtil, Pubkey, Signature};
-use solana::thin_client::ThinClient;
-use solana::wallet::request_airdrop;
+use solana::thin_client::{check_genesis_hash, ThinClient};
+use solana::transaction::{Transaction, MAX_MEMO_LEN};
+use solana::wallet::{redeem_coupon, request_airdrop};
 use std::error;
 use std::fmt;
 use std::fs::File;
@@ -27,8 +33,12 @@ enum WalletCommand {
     Address,
     Balance,
     AirDrop(i64),
-    Pay(i64, Pubkey),
+    Pay(i64, Pubkey, Option<String>, Option<(Keypair, i64)>),
     Confirm(Signature),
+    Cancel(Signature),
+    TransactionHistory(Pubkey, usize, Option<String>),
+    AuthorizeVoter(Pubkey),
+    RedeemCoupon(AirdropCoupon),
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +68,8 @@ struct WalletConfig {
     leader: NodeInfo,
     id: Keypair,
     drone_addr: SocketAddr,
+    rpc_addr: SocketAddr,
+    genesis_hash: Option<Hash>,
     command: WalletCommand,
 }
 
@@ -68,6 +80,8 @@ impl Default for WalletConfig {
             leader: NodeInfo::new_leader(&default_addr),
             id: Keypair::new(),
             drone_addr: default_addr,
+            rpc_addr: default_addr,
+            genesis_hash: None,
             command: WalletCommand::Balance,
         }
     }
@@ -84,6 +98,23 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
                 .takes_value(true)
                 .help("/path/to/leader.json"),
         )
+        .arg(
+            Arg::with_name("url")
+                .short("u")
+                .long("url")
+                .value_name("NETWORK")
+                .takes_value(true)
+                .possible_values(&["testnet", "devnet", "localhost", "localnet"])
+                .conflicts_with("leader")
+                .help("The cluster to use in place of a leader.json"),
+        )
+        .arg(
+            Arg::with_name("genesis-hash")
+                .long("genesis-hash")
+                .value_name("HASH")
+                .takes_value(true)
+                .help("Refuse to use a cluster with any other genesis hash"),
+        )
         .arg(
             Arg::with_name("keypair")
                 .short("k")
@@ -124,6 +155,29 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
                         .value_name("PUBKEY")
                         .takes_value(true)
                         .help("The pubkey of recipient"),
+                )
+                .arg(
+                    Arg::with_name("memo")
+                        .long("memo")
+                        .value_name("TEXT")
+                        .takes_value(true)
+                        .help("A short note recorded with the payment, e.g. a deposit reference"),
+                )
+                .arg(
+                    Arg::with_name("fee-payer")
+                        .long("fee-payer")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .requires("fee")
+                        .help("/path/to/keypair.json of the account that pays the fee"),
+                )
+                .arg(
+                    Arg::with_name("fee")
+                        .long("fee")
+                        .value_name("NUMBER")
+                        .takes_value(true)
+                        .requires("fee-payer")
+                        .help("The fee the fee payer pays"),
                 ),
         )
         .subcommand(
@@ -137,13 +191,76 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
                         .help("The transaction signature to confirm"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("cancel")
+                .about("Cancel an escrowed payment and take its tokens back")
+                .arg(
+                    Arg::with_name("signature")
+                        .index(1)
+                        .value_name("SIGNATURE")
+                        .required(true)
+                        .help("The signature of the payment to cancel"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("transaction-history")
+                .about("List the recorded transactions of an address, newest first")
+                .arg(
+                    Arg::with_name("pubkey")
+                        .index(1)
+                        .value_name("PUBKEY")
+                        .help("The address to list, your own if not given"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .value_name("NUMBER")
+                        .takes_value(true)
+                        .default_value("20")
+                        .help("List at most NUMBER transactions"),
+                )
+                .arg(
+                    Arg::with_name("before")
+                        .long("before")
+                        .value_name("SIGNATURE")
+                        .takes_value(true)
+                        .help("Start after the transaction with SIGNATURE, to page back"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("authorize-voter")
+                .about("Let a node vote for your key, in place of any node authorized before")
+                .arg(
+                    Arg::with_name("voter")
+                        .index(1)
+                        .value_name("PUBKEY")
+                        .required(true)
+                        .help("The pubkey the node gossips and votes with"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("redeem-coupon")
+                .about("Redeem an airdrop coupon issued by the drone")
+                .arg(
+                    Arg::with_name("coupon")
+                        .index(1)
+                        .value_name("PATH")
+                        .required(true)
+                        .help("/path/to/coupon.json"),
+                ),
+        )
         .subcommand(SubCommand::with_name("balance").about("Get your balance"))
         .subcommand(SubCommand::with_name("address").about("Get your public key"))
         .get_matches();
 
+    let preset = matches
+        .value_of("url")
+        .map(|url| network_preset(url).unwrap());
     let leader: NodeInfo;
     if let Some(l) = matches.value_of("leader") {
         leader = read_leader(l)?.node_info;
+    } else if let Some(preset) = preset {
+        leader = NodeInfo::new_leader(&preset.tpu_addr()?);
     } else {
         let server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8000);
         leader = NodeInfo::new_leader(&server_addr);
@@ -165,6 +282,20 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
 
     let mut drone_addr = leader.contact_info.tpu;
     drone_addr.set_port(DRONE_PORT);
+    let mut rpc_addr = leader.contact_info.rpu;
+    rpc_addr.set_port(RPC_PORT);
+    let mut genesis_hash = None;
+    if let Some(preset) = preset {
+        drone_addr = preset.drone_addr()?;
+        rpc_addr = preset.rpc_addr()?;
+        genesis_hash = preset.genesis_hash();
+    }
+    if let Some(hash) = matches.value_of("genesis-hash") {
+        let hash: Hash = hash
+            .parse()
+            .map_err(|err| WalletError::BadParameter(format!("Invalid genesis hash: {}", err)))?;
+        genesis_hash = Some(hash);
+    }
 
     let command = match matches.subcommand() {
         ("airdrop", Some(airdrop_matches)) => {
@@ -173,36 +304,110 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
         }
         ("pay", Some(pay_matches)) => {
             let to = if pay_matches.is_present("to") {
-                let pubkey_vec = bs58::decode(pay_matches.value_of("to").unwrap())
-                    .into_vec()
-                    .expect("base58-encoded public key");
-
-                if pubkey_vec.len() != std::mem::size_of::<Pubkey>() {
+                pay_matches.value_of("to").unwrap().parse().map_err(|err| {
                     eprintln!("{}", pay_matches.usage());
-                    Err(WalletError::BadParameter("Invalid public key".to_string()))?;
-                }
-                Pubkey::new(&pubkey_vec)
+                    WalletError::BadParameter(format!("Invalid public key: {}", err))
+                })?
             } else {
                 id.pubkey()
             };
 
             let tokens = pay_matches.value_of("tokens").unwrap().parse()?;
 
-            Ok(WalletCommand::Pay(tokens, to))
+            let memo = pay_matches.value_of("memo").map(|memo| memo.to_string());
+            if memo.as_ref().map(|memo| memo.len()).unwrap_or(0) > MAX_MEMO_LEN {
+                eprintln!("{}", pay_matches.usage());
+                Err(WalletError::BadParameter(format!(
+                    "Memo is longer than {} bytes",
+                    MAX_MEMO_LEN
+                )))?;
+            }
+
+            let fee_payer = match pay_matches.value_of("fee-payer") {
+                Some(fee_payer_path) => {
+                    let fee_payer = read_keypair(fee_payer_path).or_else(|err| {
+                        Err(WalletError::BadParameter(format!(
+                            "{}: Unable to open keypair file: {}",
+                            err, fee_payer_path
+                        )))
+                    })?;
+                    let fee = pay_matches.value_of("fee").unwrap().parse()?;
+                    Some((fee_payer, fee))
+                }
+                None => None,
+            };
+
+            Ok(WalletCommand::Pay(tokens, to, memo, fee_payer))
         }
         ("confirm", Some(confirm_matches)) => {
-            let signatures = bs58::decode(confirm_matches.value_of("signature").unwrap())
-                .into_vec()
-                .expect("base58-encoded signature");
-
-            if signatures.len() == std::mem::size_of::<Signature>() {
-                let signature = Signature::new(&signatures);
-                Ok(WalletCommand::Confirm(signature))
-            } else {
-                eprintln!("{}", confirm_matches.usage());
-                Err(WalletError::BadParameter("Invalid signature".to_string()))
+            let signature: Result<Signature, _> =
+                confirm_matches.value_of("signature").unwrap().parse();
+            match signature {
+                Ok(signature) => Ok(WalletCommand::Confirm(signature)),
+                Err(err) => {
+                    eprintln!("{}", confirm_matches.usage());
+                    Err(WalletError::BadParameter(format!(
+                        "Invalid signature: {}",
+                        err
+                    )))
+                }
+            }
+        }
+        ("cancel", Some(cancel_matches)) => {
+            let signature: Result<Signature, _> =
+                cancel_matches.value_of("signature").unwrap().parse();
+            match signature {
+                Ok(signature) => Ok(WalletCommand::Cancel(signature)),
+                Err(err) => {
+                    eprintln!("{}", cancel_matches.usage());
+                    Err(WalletError::BadParameter(format!(
+                        "Invalid signature: {}",
+                        err
+                    )))
+                }
             }
         }
+        ("transaction-history", Some(history_matches)) => {
+            let pubkey = match history_matches.value_of("pubkey") {
+                Some(pubkey) => pubkey.parse().map_err(|err| {
+                    eprintln!("{}", history_matches.usage());
+                    WalletError::BadParameter(format!("Invalid public key: {}", err))
+                })?,
+                None => id.pubkey(),
+            };
+            let limit = history_matches.value_of("limit").unwrap().parse()?;
+            let before = history_matches
+                .value_of("before")
+                .map(|before| before.to_string());
+            Ok(WalletCommand::TransactionHistory(pubkey, limit, before))
+        }
+        ("authorize-voter", Some(authorize_matches)) => {
+            let voter = authorize_matches
+                .value_of("voter")
+                .unwrap()
+                .parse()
+                .map_err(|err| {
+                    eprintln!("{}", authorize_matches.usage());
+                    WalletError::BadParameter(format!("Invalid public key: {}", err))
+                })?;
+            Ok(WalletCommand::AuthorizeVoter(voter))
+        }
+        ("redeem-coupon", Some(redeem_matches)) => {
+            let path = redeem_matches.value_of("coupon").unwrap();
+            let file = File::open(path).or_else(|err| {
+                Err(WalletError::BadParameter(format!(
+                    "{}: Unable to open coupon file: {}",
+                    err, path
+                )))
+            })?;
+            let coupon = serde_json::from_reader(file).or_else(|err| {
+                Err(WalletError::BadParameter(format!(
+                    "{}: Failed to parse coupon: {}",
+                    err, path
+                )))
+            })?;
+            Ok(WalletCommand::RedeemCoupon(coupon))
+        }
         ("balance", Some(_balance_matches)) => Ok(WalletCommand::Balance),
         ("address", Some(_address_matches)) => Ok(WalletCommand::Address),
         ("", None) => {
@@ -218,10 +423,27 @@ fn parse_args() -> Result<WalletConfig, Box<error::Error>> {
         leader,
         id,
         drone_addr, // TODO: Add an option for this.
+        rpc_addr,
+        genesis_hash,
         command,
     })
 }
 
+// TODO: return airdrop Result from Drone instead of polling the network
+fn wait_for_balance_change(client: &mut ThinClient, pubkey: &Pubkey, previous_balance: i64) -> i64 {
+    let mut current_balance = previous_balance;
+    for _ in 0..20 {
+        sleep(Duration::from_millis(500));
+        current_balance = client.poll_get_balance(pubkey).unwrap_or(previous_balance);
+
+        if previous_balance != current_balance {
+            break;
+        }
+        println!(".");
+    }
+    current_balance
+}
+
 fn process_command(
     config: &WalletConfig,
     client: &mut ThinClient,
@@ -257,31 +479,55 @@ fn process_command(
             let previous_balance = client.poll_get_balance(&config.id.pubkey()).unwrap_or(0);
             request_airdrop(&config.drone_addr, &config.id.pubkey(), tokens as u64)?;
 
-            // TODO: return airdrop Result from Drone instead of polling the
-            //       network
-            let mut current_balance = previous_balance;
-            for _ in 0..20 {
-                sleep(Duration::from_millis(500));
-                current_balance = client
-                    .poll_get_balance(&config.id.pubkey())
-                    .unwrap_or(previous_balance);
-
-                if previous_balance != current_balance {
-                    break;
-                }
-                println!(".");
-            }
+            let current_balance =
+                wait_for_balance_change(client, &config.id.pubkey(), previous_balance);
             println!("Your balance is: {:?}", current_balance);
             if current_balance - previous_balance != tokens {
                 Err("Airdrop failed!")?;
             }
         }
+        WalletCommand::RedeemCoupon(coupon) => {
+            println!(
+                "Redeeming coupon for {} tokens to {} at {}",
+                coupon.tokens, coupon.pubkey, config.drone_addr
+            );
+            let previous_balance = client.poll_get_balance(&coupon.pubkey).unwrap_or(0);
+            redeem_coupon(&config.drone_addr, &coupon)?;
+
+            let current_balance = wait_for_balance_change(client, &coupon.pubkey, previous_balance);
+            println!("Balance of {} is: {:?}", coupon.pubkey, current_balance);
+            if current_balance - previous_balance != coupon.tokens as i64 {
+                Err("Coupon redemption failed!")?;
+            }
+        }
         // If client has positive balance, spend tokens in {balance} number of transactions
-        WalletCommand::Pay(tokens, to) => {
+        WalletCommand::Pay(tokens, to, ref memo, ref fee_payer) => {
             let last_id = client.get_last_id();
-            let signature = client.transfer(tokens, &config.id, to, &last_id)?;
+            let signature = match (memo, fee_payer) {
+                (_, Some((fee_payer, fee))) => {
+                    let mut tx = Transaction::new_with_fee_payer(
+                        &config.id, fee_payer, to, tokens, *fee, last_id,
+                    );
+                    if let Some(memo) = memo {
+                        tx.memo = Some(memo.to_string());
+                        tx.sign(&config.id);
+                        tx.sign_fee_payer(fee_payer);
+                    }
+                    client.transfer_signed(&tx)?
+                }
+                (Some(memo), None) => {
+                    let tx = Transaction::new_with_memo(&config.id, to, tokens, last_id, memo);
+                    client.transfer_signed(&tx)?
+                }
+                (None, None) => client.transfer(tokens, &config.id, to, &last_id)?,
+            };
             println!("{}", signature);
         }
+        WalletCommand::AuthorizeVoter(voter) => {
+            let last_id = client.get_last_id();
+            let tx = Transaction::new_authorize_voter(&config.id, voter, last_id, 0);
+            println!("{}", client.transfer_signed(&tx)?);
+        }
         // Confirm the last client transaction by signature
         WalletCommand::Confirm(signature) => {
             if client.check_signature(&signature) {
@@ -290,10 +536,91 @@ fn process_command(
                 println!("Not found");
             }
         }
+        // Cancel an escrowed payment this wallet may cancel
+        WalletCommand::Cancel(signature) => {
+            let last_id = client.get_last_id();
+            let tx = Transaction::new_cancel(&config.id, signature, last_id);
+            println!("{}", client.transfer_signed(&tx)?);
+        }
+        WalletCommand::TransactionHistory(pubkey, limit, ref before) => {
+            let mut options = json!({ "limit": limit });
+            if let Some(before) = before {
+                options["before"] = json!(before);
+            }
+            let params = json!([pubkey.to_string(), options]);
+            let result = rpc_request(&config.rpc_addr, "getSignaturesForAddress", params)?;
+            let signatures: Vec<RpcSignatureForAddress> = serde_json::from_value(result)?;
+            if signatures.is_empty() {
+                println!("No transactions found");
+            }
+            for info in &signatures {
+                let params = json!([info.signature]);
+                let result = rpc_request(&config.rpc_addr, "getConfirmedTransaction", params)?;
+                let confirmed: Option<RpcConfirmedTransaction> = serde_json::from_value(result)?;
+                // anything in the ledger was processed, and the ledger only
+                // records transactions that succeeded
+                let (counterparty, amount, status) = match confirmed {
+                    Some(confirmed) => {
+                        let tx = confirmed.transaction;
+                        // a transaction may pay several accounts at once
+                        let payments = tx.final_payments();
+                        let tokens: i64 = payments
+                            .iter()
+                            .filter(|payment| tx.from == pubkey || payment.to == pubkey)
+                            .map(|payment| payment.tokens)
+                            .sum();
+                        match payments.first() {
+                            Some(payment) if tx.from == pubkey => {
+                                (payment.to.to_string(), -tokens, "confirmed")
+                            }
+                            _ => (tx.from.to_string(), tokens, "confirmed"),
+                        }
+                    }
+                    None => ("-".to_string(), 0, "not found"),
+                };
+                println!(
+                    "slot {:>8}  {:>44}  {:>+12}  {:<10} {} {}",
+                    info.slot,
+                    counterparty,
+                    amount,
+                    status,
+                    info.signature,
+                    info.memo.as_ref().map(|memo| memo.as_str()).unwrap_or("")
+                );
+            }
+            if limit > 0 && signatures.len() == limit {
+                println!(
+                    "Pass --before {} for older transactions",
+                    signatures[limit - 1].signature
+                );
+            }
+        }
     }
     Ok(())
 }
 
+fn rpc_request(
+    rpc_addr: &SocketAddr,
+    method: &str,
+    params: Value,
+) -> Result<Value, Box<error::Error>> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+    let mut response = reqwest::Client::new()
+        .post(&format!("http://{}", rpc_addr))
+        .json(&request)
+        .send()?;
+    let response: Value = response.json()?;
+    if let Some(error) = response.get("error") {
+        Err(format!("{} failed: {}", method, error["message"]))?;
+    }
+    Ok(response["result"].clone())
+}
+
 fn read_leader(path: &str) -> Result<Config, WalletError> {
     let file = File::open(path.to_string()).or_else(|err| {
         Err(WalletError::BadParameter(format!(
@@ -313,6 +640,9 @@ fn read_leader(path: &str) -> Result<Config, WalletError> {
 fn main() -> Result<(), Box<error::Error>> {
     logger::setup();
     let config = parse_args()?;
+    if let Some(ref genesis_hash) = config.genesis_hash {
+        check_genesis_hash(&config.rpc_addr, genesis_hash)?;
+    }
     let mut client = mk_client(&config.leader);
     process_command(&config, &mut client)
 }
@@ -1,17 +1,65 @@
 //! The `blob_fetch_stage` pulls blobs from UDP sockets and sends it to a channel.
+//! Each socket is read by a thread of its own, which counts what it receives
+//! and watches for packets the kernel dropped because the socket's receive
+//! queue was full. Sockets can be added and removed while the stage runs, such
+//! as when a node's role changes and it moves its repair or retransmit ports.
 
+use counter::Counter;
+use influx_db_client as influxdb;
+use log::Level;
+use metrics;
 use packet::BlobRecycler;
+use result::{Error, Result};
 use service::Service;
-use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, Ordering};
+use socket_stats::udp_socket_stats;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
-use std::sync::Arc;
-use std::thread::{self, JoinHandle};
-use streamer::{self, BlobReceiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Builder, JoinHandle};
+use std::time::{Duration, Instant};
+use streamer::{self, BlobReceiver, BlobSender};
+
+/// How often each socket checks for drops and submits its counts to metrics.
+const CHECK_INTERVAL_MS: u64 = 10_000;
+
+/// What a socket has received since it was added to the stage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FetchSocketStats {
+    pub addr: SocketAddr,
+    /// Blobs received and passed on.
+    pub blobs: usize,
+    /// Reads that failed other than by timing out.
+    pub errors: usize,
+    /// Packets the kernel dropped because the receive queue was full, as of
+    /// the last check.
+    pub drops: usize,
+}
+
+#[derive(Default)]
+struct SocketCounters {
+    blobs: AtomicUsize,
+    errors: AtomicUsize,
+    drops: AtomicUsize,
+}
+
+/// A socket the stage reads from, and how to stop reading it.
+struct FetchSocket {
+    addr: SocketAddr,
+    exit: Arc<AtomicBool>,
+    counters: Arc<SocketCounters>,
+    thread_hdl: JoinHandle<()>,
+}
 
 pub struct BlobFetchStage {
     exit: Arc<AtomicBool>,
-    thread_hdls: Vec<JoinHandle<()>>,
+    blob_recycler: BlobRecycler,
+    /// Where the sockets' blobs go, until the stage is closed.
+    blob_sender: Mutex<Option<BlobSender>>,
+    sockets: Mutex<Vec<FetchSocket>>,
+    /// Threads of removed sockets, which may still be finishing a read.
+    removed_thread_hdls: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl BlobFetchStage {
@@ -28,29 +76,179 @@ impl BlobFetchStage {
         blob_recycler: &BlobRecycler,
     ) -> (Self, BlobReceiver) {
         let (blob_sender, blob_receiver) = channel();
-        let thread_hdls: Vec<_> = sockets
-            .into_iter()
-            .map(|socket| {
-                streamer::blob_receiver(
-                    exit.clone(),
-                    blob_recycler.clone(),
-                    socket,
-                    blob_sender.clone(),
-                ).expect("blob receiver init")
-            })
-            .collect();
+        let stage = BlobFetchStage {
+            exit,
+            blob_recycler: blob_recycler.clone(),
+            blob_sender: Mutex::new(Some(blob_sender)),
+            sockets: Mutex::new(vec![]),
+            removed_thread_hdls: Mutex::new(vec![]),
+        };
+        for socket in sockets {
+            stage.add_socket(socket).expect("blob receiver init");
+        }
+        (stage, blob_receiver)
+    }
+
+    /// Start reading blobs from `socket` too. Returns its address, which
+    /// `remove_socket` takes, or `Error::SendError` if the stage was closed.
+    pub fn add_socket(&self, socket: UdpSocket) -> Result<SocketAddr> {
+        let addr = socket.local_addr()?;
+        //DOCUMENTED SIDE-EFFECT
+        //1 second timeout on socket read
+        socket.set_read_timeout(Some(Duration::new(1, 0)))?;
+        let blob_sender = self
+            .blob_sender
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(Error::SendError)?;
+        let socket_exit = Arc::new(AtomicBool::new(false));
+        let counters = Arc::new(SocketCounters::default());
+        let thread_hdl = {
+            let exit = self.exit.clone();
+            let socket_exit = socket_exit.clone();
+            let counters = counters.clone();
+            let recycler = self.blob_recycler.clone();
+            Builder::new()
+                .name("solana-blob_fetch".to_string())
+                .spawn(move || {
+                    Self::receive(
+                        &exit,
+                        &socket_exit,
+                        &recycler,
+                        &socket,
+                        &blob_sender,
+                        &counters,
+                    )
+                })
+                .unwrap()
+        };
+        self.sockets.lock().unwrap().push(FetchSocket {
+            addr,
+            exit: socket_exit,
+            counters,
+            thread_hdl,
+        });
+        Ok(addr)
+    }
 
-        (BlobFetchStage { exit, thread_hdls }, blob_receiver)
+    /// Stop reading blobs from the socket bound to `addr`. Returns whether the
+    /// stage was reading from it.
+    pub fn remove_socket(&self, addr: &SocketAddr) -> bool {
+        let mut sockets = self.sockets.lock().unwrap();
+        match sockets.iter().position(|socket| socket.addr == *addr) {
+            Some(i) => {
+                let socket = sockets.remove(i);
+                socket.exit.store(true, Ordering::Relaxed);
+                self.removed_thread_hdls
+                    .lock()
+                    .unwrap()
+                    .push(socket.thread_hdl);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// What each of the stage's sockets has received, in the order they were added.
+    pub fn socket_stats(&self) -> Vec<FetchSocketStats> {
+        self.sockets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|socket| FetchSocketStats {
+                addr: socket.addr,
+                blobs: socket.counters.blobs.load(Ordering::Relaxed),
+                errors: socket.counters.errors.load(Ordering::Relaxed),
+                drops: socket.counters.drops.load(Ordering::Relaxed),
+            })
+            .collect()
     }
 
     pub fn close(&self) {
         self.exit.store(true, Ordering::Relaxed);
+        // the blobs' receiver sees the channel close once the threads exit
+        self.blob_sender.lock().unwrap().take();
+    }
+
+    fn receive(
+        exit: &AtomicBool,
+        socket_exit: &AtomicBool,
+        recycler: &BlobRecycler,
+        socket: &UdpSocket,
+        blob_sender: &BlobSender,
+        counters: &SocketCounters,
+    ) {
+        let addr = socket.local_addr().unwrap();
+        // the kernel counts the drops since the socket was bound
+        let initial_drops = udp_socket_stats(addr.port()).map_or(0, |stats| stats.drops);
+        let mut last_check = Instant::now();
+        while !exit.load(Ordering::Relaxed) && !socket_exit.load(Ordering::Relaxed) {
+            match streamer::recv_blobs(recycler, socket, blob_sender) {
+                Ok(count) => {
+                    counters.blobs.fetch_add(count, Ordering::Relaxed);
+                }
+                Err(Error::IO(ref e))
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(Error::SendError) => break,
+                Err(e) => {
+                    inc_new_counter_info!("blob_fetch_stage-recv_error", 1);
+                    debug!("{} recv error: {:?}", addr, e);
+                    counters.errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            if last_check.elapsed() >= Duration::from_millis(CHECK_INTERVAL_MS) {
+                last_check = Instant::now();
+                Self::check_drops(&addr, initial_drops, counters);
+            }
+        }
+    }
+
+    /// Warn about packets dropped on the socket at `addr` since the last
+    /// check, and submit its counts to metrics.
+    fn check_drops(addr: &SocketAddr, initial_drops: u64, counters: &SocketCounters) {
+        if let Some(stats) = udp_socket_stats(addr.port()) {
+            let drops = stats.drops.saturating_sub(initial_drops) as usize;
+            let new_drops = drops.saturating_sub(counters.drops.swap(drops, Ordering::Relaxed));
+            if new_drops > 0 {
+                warn!(
+                    "{} dropped {} packets, its receive queue is full",
+                    addr, new_drops
+                );
+            }
+        }
+        metrics::submit(
+            influxdb::Point::new("blob_fetch_stage")
+                .add_tag("socket", influxdb::Value::String(addr.to_string()))
+                .add_field(
+                    "blobs",
+                    influxdb::Value::Integer(counters.blobs.load(Ordering::Relaxed) as i64),
+                )
+                .add_field(
+                    "errors",
+                    influxdb::Value::Integer(counters.errors.load(Ordering::Relaxed) as i64),
+                )
+                .add_field(
+                    "drops",
+                    influxdb::Value::Integer(counters.drops.load(Ordering::Relaxed) as i64),
+                )
+                .to_owned(),
+        );
     }
 }
 
 impl Service for BlobFetchStage {
     fn thread_hdls(self) -> Vec<JoinHandle<()>> {
-        self.thread_hdls
+        let mut thread_hdls = self.removed_thread_hdls.into_inner().unwrap();
+        thread_hdls.extend(
+            self.sockets
+                .into_inner()
+                .unwrap()
+                .into_iter()
+                .map(|socket| socket.thread_hdl),
+        );
+        thread_hdls
     }
 
     fn join(self) -> thread::Result<()> {
@@ -60,3 +258,59 @@ impl Service for BlobFetchStage {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use blob_fetch_stage::BlobFetchStage;
+    use packet::{Blob, BlobRecycler};
+    use service::Service;
+    use std::net::UdpSocket;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn send_blob(to: &UdpSocket) {
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut blob = Blob::default();
+        blob.meta.size = 64;
+        sender
+            .send_to(&blob.data[..64], to.local_addr().unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_add_remove_socket() {
+        let exit = Arc::new(AtomicBool::new(false));
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let second_addr = second.local_addr().unwrap();
+        let first_sender = first.try_clone().unwrap();
+        let second_sender = second.try_clone().unwrap();
+        let (stage, receiver) = BlobFetchStage::new(first, exit.clone(), &BlobRecycler::default());
+        assert_eq!(stage.add_socket(second).unwrap(), second_addr);
+
+        send_blob(&first_sender);
+        send_blob(&second_sender);
+        let timeout = Duration::from_secs(5);
+        for _ in 0..2 {
+            assert_eq!(receiver.recv_timeout(timeout).unwrap().len(), 1);
+        }
+        let stats = stage.socket_stats();
+        assert_eq!(stats.len(), 2);
+        assert!(stats
+            .iter()
+            .all(|stats| stats.blobs == 1 && stats.errors == 0));
+
+        assert!(stage.remove_socket(&second_addr));
+        assert!(!stage.remove_socket(&second_addr));
+        assert_eq!(stage.socket_stats().len(), 1);
+
+        stage.close();
+        assert!(stage
+            .add_socket(UdpSocket::bind("127.0.0.1:0").unwrap())
+            .is_err());
+        stage.join().unwrap();
+        // with every thread gone, so is the channel
+        assert!(receiver.recv_timeout(timeout).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! The `block_cost` module tallies what each slot a leader writes costs to
+//! process: its transactions, signatures, estimated compute and bytes. The write
+//! stage submits the totals as metrics and hands them to the bank, which serves
+//! them through `getBlockProduction`, for capacity planning and to spot leaders
+//! producing pathological blocks.
+
+use bincode::serialized_size;
+use entry::Entry;
+use influx_db_client as influxdb;
+use ledger::TICKS_PER_SLOT;
+use metrics;
+use transaction::Transaction;
+
+/// Estimated compute units to verify a transaction's signature.
+pub const SIGNATURE_COST: u64 = 100;
+
+/// Estimated compute units to execute one instruction.
+pub const INSTRUCTION_COST: u64 = 10;
+
+/// Estimated compute units per byte of userdata a transaction saves.
+pub const DATA_BYTE_COST: u64 = 1;
+
+/// The estimated compute units it takes the bank to process `tx`.
+pub fn transaction_cost(tx: &Transaction) -> u64 {
+    SIGNATURE_COST
+        + INSTRUCTION_COST * tx.instructions.len() as u64
+        + DATA_BYTE_COST * tx.userdata.len() as u64
+}
+
+/// The totals of one slot's entries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SlotCost {
+    pub slot: u64,
+    pub entries: u64,
+    pub transactions: u64,
+    pub signatures: u64,
+    /// The sum of `transaction_cost` over the slot's transactions
+    pub compute_units: u64,
+    /// The serialized size of the slot's entries, as written to the ledger
+    pub bytes: u64,
+}
+
+impl SlotCost {
+    pub fn new(slot: u64) -> Self {
+        SlotCost {
+            slot,
+            ..SlotCost::default()
+        }
+    }
+
+    /// Add `entry` to the slot's totals.
+    pub fn add_entry(&mut self, entry: &Entry) {
+        self.entries += 1;
+        // every transaction carries exactly one signature
+        self.transactions += entry.transactions.len() as u64;
+        self.signatures += entry.transactions.len() as u64;
+        self.compute_units += entry.transactions.iter().map(transaction_cost).sum::<u64>();
+        self.bytes += serialized_size(entry).unwrap_or(0);
+    }
+
+    /// Submit the slot's totals to metrics.
+    pub fn submit(&self) {
+        metrics::submit(
+            influxdb::Point::new("write_stage-slot_cost")
+                .add_field("slot", influxdb::Value::Integer(self.slot as i64))
+                .add_field("entries", influxdb::Value::Integer(self.entries as i64))
+                .add_field(
+                    "transactions",
+                    influxdb::Value::Integer(self.transactions as i64),
+                )
+                .add_field(
+                    "signatures",
+                    influxdb::Value::Integer(self.signatures as i64),
+                )
+                .add_field(
+                    "compute_units",
+                    influxdb::Value::Integer(self.compute_units as i64),
+                )
+                .add_field("bytes", influxdb::Value::Integer(self.bytes as i64))
+                .to_owned(),
+        );
+    }
+}
+
+/// Tallies the entries a leader writes, in ledger order, into the slots they
+/// make up. A slot's entries run up to and including its last tick.
+pub struct BlockCostTracker {
+    tick_height: u64,
+    current: SlotCost,
+}
+
+impl BlockCostTracker {
+    /// Start with the entry after the first `tick_height` ticks of the ledger.
+    pub fn new(tick_height: u64) -> Self {
+        BlockCostTracker {
+            tick_height,
+            current: SlotCost::new(tick_height / TICKS_PER_SLOT),
+        }
+    }
+
+    /// The totals of the slot still being written.
+    pub fn current(&self) -> SlotCost {
+        self.current
+    }
+
+    /// Add `entries` to the tally, returning the totals of the slots they end.
+    pub fn record(&mut self, entries: &[Entry]) -> Vec<SlotCost> {
+        let mut completed = vec![];
+        for entry in entries {
+            self.current.add_entry(entry);
+            if entry.transactions.is_empty() {
+                self.tick_height += 1;
+                if self.tick_height % TICKS_PER_SLOT == 0 {
+                    completed.push(self.current);
+                    self.current = SlotCost::new(self.tick_height / TICKS_PER_SLOT);
+                }
+            }
+        }
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hash::Hash;
+    use ledger::next_entries;
+    use signature::{Keypair, KeypairUtil};
+
+    #[test]
+    fn test_transaction_cost() {
+        let keypair = Keypair::new();
+        let mut tx = Transaction::new(&keypair, keypair.pubkey(), 1, Hash::default());
+        assert_eq!(transaction_cost(&tx), SIGNATURE_COST + INSTRUCTION_COST);
+        tx.userdata = vec![0; 3];
+        assert_eq!(
+            transaction_cost(&tx),
+            SIGNATURE_COST + INSTRUCTION_COST + 3 * DATA_BYTE_COST
+        );
+    }
+
+    #[test]
+    fn test_block_cost_tracker() {
+        let keypair = Keypair::new();
+        let id = Hash::default();
+        let tx = Transaction::new(&keypair, keypair.pubkey(), 1, id);
+        let ticks = next_entries(&id, 1, vec![]);
+        let txs = next_entries(&id, 1, vec![tx.clone(), tx.clone()]);
+
+        // start one tick short of the end of slot 1
+        let mut tracker = BlockCostTracker::new(2 * TICKS_PER_SLOT - 1);
+        assert_eq!(tracker.record(&txs), vec![]);
+        let completed = tracker.record(&ticks);
+        assert_eq!(completed.len(), 1);
+        let cost = completed[0];
+        assert_eq!(cost.slot, 1);
+        assert_eq!(cost.entries, txs.len() as u64 + 1);
+        assert_eq!(cost.transactions, 2);
+        assert_eq!(cost.signatures, 2);
+        assert_eq!(cost.compute_units, 2 * transaction_cost(&tx));
+        let bytes: u64 = txs
+            .iter()
+            .chain(&ticks)
+            .map(|entry| serialized_size(entry).unwrap())
+            .sum();
+        assert_eq!(cost.bytes, bytes);
+        assert_eq!(tracker.current(), SlotCost::new(2));
+    }
+}
@@ -1,6 +1,7 @@
 //! The `broadcast_stage` broadcasts data from a leader node to validators
 //!
 use counter::Counter;
+use cpu_time;
 use crdt::{Crdt, CrdtError, NodeInfo};
 #[cfg(feature = "erasure")]
 use erasure;
@@ -8,25 +9,90 @@ use log::Level;
 use packet::BlobRecycler;
 use result::{Error, Result};
 use service::Service;
+use sharded_sender::ShardedSender;
 use std::mem;
 use std::net::UdpSocket;
 use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, RwLock};
-use std::thread::{self, Builder, JoinHandle};
-use std::time::Duration;
+use std::thread::{self, sleep, Builder, JoinHandle};
+use std::time::{Duration, Instant};
 use streamer::BlobReceiver;
+use timing::duration_as_us;
 use window::{self, SharedWindow, WindowIndex, WINDOW_SIZE};
 
+/// Limits how fast a leader broadcasts blobs, so that validators' windows
+/// aren't overrun and the blobs they drop don't have to be repaired.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BroadcastPacing {
+    /// The sustained rate, in blobs per tick.
+    pub blobs_per_tick: u64,
+    /// The number of blobs that can be sent at once after a quiet period.
+    pub burst: u64,
+    /// How long a tick lasts, in milliseconds.
+    pub tick_ms: u64,
+}
+
+/// A token bucket that holds up to `burst` blobs, and refills at the pacing's rate.
+struct Pacer {
+    pacing: BroadcastPacing,
+    allowance: f64,
+    last_refill: Instant,
+}
+
+impl Pacer {
+    fn new(pacing: BroadcastPacing) -> Self {
+        assert!(pacing.blobs_per_tick > 0 && pacing.tick_ms > 0);
+        Pacer {
+            pacing,
+            allowance: pacing.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn blobs_per_ms(&self) -> f64 {
+        self.pacing.blobs_per_tick as f64 / self.pacing.tick_ms as f64
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed_ms = duration_as_us(&now.duration_since(self.last_refill)) as f64 / 1000.0;
+        if elapsed_ms > 0.0 {
+            let allowance = self.allowance + elapsed_ms * self.blobs_per_ms();
+            self.allowance = allowance.min(self.pacing.burst as f64);
+            self.last_refill = now;
+        }
+    }
+
+    /// Wait until `blobs` may be sent. More blobs than the burst go out once the
+    /// bucket is full, and are paid back before anything else is sent.
+    fn wait(&mut self, blobs: u64) {
+        let needed = blobs.min(self.pacing.burst) as f64;
+        loop {
+            self.refill();
+            if self.allowance >= needed {
+                break;
+            }
+            let wait_ms = ((needed - self.allowance) / self.blobs_per_ms()).ceil() as u64;
+            inc_new_counter_info!("broadcast_stage-pacing_wait_ms", wait_ms as usize);
+            sleep(Duration::from_millis(wait_ms));
+        }
+        self.allowance -= blobs as f64;
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
 fn broadcast(
     node_info: &NodeInfo,
     broadcast_table: &[NodeInfo],
     window: &SharedWindow,
     recycler: &BlobRecycler,
     receiver: &BlobReceiver,
-    sock: &UdpSocket,
+    sender: &ShardedSender,
     transmit_index: &mut WindowIndex,
     receive_index: &mut u64,
+    tick_height: &mut u64,
+    pacer: &mut Option<Pacer>,
 ) -> Result<()> {
     let debug_id = node_info.debug_id();
     let timer = Duration::new(1, 0);
@@ -51,7 +117,7 @@ fn broadcast(
         trace!("{:x}: broadcast blobs.len: {}", debug_id, blobs_len);
 
         // Index the blobs
-        window::index_blobs(node_info, &blobs, receive_index)
+        window::index_blobs(node_info, &blobs, receive_index, tick_height)
             .expect("index blobs for initial window");
 
         // keep the cache of blobs that are broadcast
@@ -107,12 +173,16 @@ fn broadcast(
 
         *receive_index += blobs_len as u64;
 
+        if let Some(pacer) = pacer {
+            pacer.wait(blobs_len as u64);
+        }
+
         // Send blobs out from the window
         Crdt::broadcast(
             &node_info,
             &broadcast_table,
             &window,
-            &sock,
+            sender,
             transmit_index,
             *receive_index,
         )?;
@@ -121,15 +191,17 @@ fn broadcast(
 }
 
 pub struct BroadcastStage {
-    thread_hdl: JoinHandle<()>,
+    thread_hdls: Vec<JoinHandle<()>>,
 }
 
 impl BroadcastStage {
     fn run(
-        sock: &UdpSocket,
+        sender: &ShardedSender,
         crdt: &Arc<RwLock<Crdt>>,
         window: &SharedWindow,
         entry_height: u64,
+        mut tick_height: u64,
+        pacing: Option<BroadcastPacing>,
         recycler: &BlobRecycler,
         receiver: &BlobReceiver,
     ) {
@@ -138,6 +210,9 @@ impl BroadcastStage {
             coding: entry_height,
         };
         let mut receive_index = entry_height;
+        let mut reported_height = receive_index;
+        crdt.write().unwrap().set_entry_height(reported_height);
+        let mut pacer = pacing.map(Pacer::new);
         let me = crdt.read().unwrap().my_data().clone();
         loop {
             let broadcast_table = crdt.read().unwrap().compute_broadcast_table();
@@ -147,9 +222,11 @@ impl BroadcastStage {
                 &window,
                 &recycler,
                 &receiver,
-                &sock,
+                sender,
                 &mut transmit_index,
                 &mut receive_index,
+                &mut tick_height,
+                &mut pacer,
             ) {
                 match e {
                     Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
@@ -161,6 +238,10 @@ impl BroadcastStage {
                     }
                 }
             }
+            if reported_height != receive_index {
+                reported_height = receive_index;
+                crdt.write().unwrap().set_entry_height(reported_height);
+            }
         }
     }
 
@@ -171,33 +252,86 @@ impl BroadcastStage {
     /// * `exit` - Boolean to signal system exit.
     /// * `crdt` - CRDT structure
     /// * `window` - Cache of blobs that we have broadcast
+    /// * `entry_height` - The number of entries before the first one to broadcast.
+    /// * `tick_height` - The number of ticks before the first entry to broadcast.
+    /// * `pacing` - How fast to broadcast, if not as fast as blobs arrive.
+    /// * `send_threads` - How many sockets and threads to spread sending over.
     /// * `recycler` - Blob recycler.
     /// * `receiver` - Receive channel for blobs to be retransmitted to all the layer 1 nodes.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn new(
         sock: UdpSocket,
         crdt: Arc<RwLock<Crdt>>,
         window: SharedWindow,
         entry_height: u64,
+        tick_height: u64,
+        pacing: Option<BroadcastPacing>,
+        send_threads: usize,
         recycler: BlobRecycler,
         receiver: BlobReceiver,
     ) -> Self {
+        let (sender, mut thread_hdls) =
+            ShardedSender::new(sock, send_threads).expect("binding broadcast sockets");
         let thread_hdl = Builder::new()
             .name("solana-broadcaster".to_string())
             .spawn(move || {
-                Self::run(&sock, &crdt, &window, entry_height, &recycler, &receiver);
+                cpu_time::register_thread("broadcast_stage");
+                Self::run(
+                    &sender,
+                    &crdt,
+                    &window,
+                    entry_height,
+                    tick_height,
+                    pacing,
+                    &recycler,
+                    &receiver,
+                );
             })
             .unwrap();
+        thread_hdls.push(thread_hdl);
 
-        BroadcastStage { thread_hdl }
+        BroadcastStage { thread_hdls }
     }
 }
 
 impl Service for BroadcastStage {
     fn thread_hdls(self) -> Vec<JoinHandle<()>> {
-        vec![self.thread_hdl]
+        self.thread_hdls
     }
 
     fn join(self) -> thread::Result<()> {
-        self.thread_hdl.join()
+        for thread_hdl in self.thread_hdls() {
+            thread_hdl.join()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use broadcast_stage::{BroadcastPacing, Pacer};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_pacer() {
+        // one blob per millisecond, with a burst of 20
+        let mut pacer = Pacer::new(BroadcastPacing {
+            blobs_per_tick: 10,
+            burst: 20,
+            tick_ms: 10,
+        });
+        let start = Instant::now();
+        pacer.wait(20);
+        assert!(start.elapsed() < Duration::from_millis(10));
+
+        let start = Instant::now();
+        pacer.wait(10);
+        assert!(start.elapsed() >= Duration::from_millis(9));
+
+        // a batch bigger than the burst waits for a full bucket, then goes into debt
+        let start = Instant::now();
+        pacer.wait(30);
+        pacer.wait(1);
+        assert!(start.elapsed() >= Duration::from_millis(25));
     }
 }
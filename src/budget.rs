@@ -62,6 +62,15 @@ impl Budget {
         Budget::After(Condition::Timestamp(dt, from), Payment { tokens, to })
     }
 
+    /// The tokens the budget pays out, whichever condition is satisfied.
+    pub fn tokens(&self) -> i64 {
+        match self {
+            Budget::Pay(payment) | Budget::After(_, payment) | Budget::Or((_, payment), _) => {
+                payment.tokens
+            }
+        }
+    }
+
     /// Create a budget that pays `tokens` to `to` after the given DateTime
     /// unless cancelled by `from`.
     pub fn new_cancelable_future_payment(
@@ -0,0 +1,172 @@
+//! The `cpu_time` module accounts the CPU time used by each pipeline stage, so
+//! that a busy node can tell whether it's signature verification, banking or
+//! retransmit keeping it busy. Stage threads register themselves on start, and
+//! their user and system time is read from `/proc`, so on other platforms no
+//! stage reports any time.
+
+use influx_db_client as influxdb;
+use metrics;
+use service::Service;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+use std::thread::{self, sleep, Builder, JoinHandle};
+use std::time::Duration;
+use timing::duration_as_ms;
+
+/// The unit of the times in `/proc/<pid>/task/<tid>/stat`, which is 100Hz on
+/// all the architectures Linux supports.
+const CLOCK_TICKS_PER_SEC: u64 = 100;
+
+/// How often the stages' CPU time is submitted to metrics.
+const SUBMIT_INTERVAL_MS: u64 = 10_000;
+
+#[derive(Default)]
+struct StageThreads {
+    /// The CPU time of each live thread, as of the last sample.
+    threads: HashMap<u64, Duration>,
+    /// The CPU time of threads that have exited.
+    exited: Duration,
+}
+
+fn get_singleton_registry() -> Arc<Mutex<HashMap<&'static str, StageThreads>>> {
+    static INIT: Once = ONCE_INIT;
+    static mut REGISTRY: Option<Arc<Mutex<HashMap<&'static str, StageThreads>>>> = None;
+    unsafe {
+        INIT.call_once(|| REGISTRY = Some(Arc::new(Mutex::new(HashMap::new()))));
+        match REGISTRY {
+            Some(ref registry) => registry.clone(),
+            None => panic!("Failed to initialize cpu time registry"),
+        }
+    }
+}
+
+/// The id of the calling thread, as the kernel knows it.
+fn current_thread_id() -> Option<u64> {
+    let link = fs::read_link("/proc/thread-self").ok()?;
+    link.file_name()?.to_str()?.parse().ok()
+}
+
+/// The CPU time used so far by thread `tid` of this process.
+fn thread_cpu_time(tid: u64) -> Option<Duration> {
+    let stat = fs::read_to_string(format!("/proc/self/task/{}/stat", tid)).ok()?;
+    // the thread name can contain spaces, the fields after it can't
+    let fields: Vec<_> = stat.rsplit(") ").next()?.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(Duration::from_millis(
+        (utime + stime) * 1000 / CLOCK_TICKS_PER_SEC,
+    ))
+}
+
+/// Account the calling thread's CPU time to `stage`.
+pub fn register_thread(stage: &'static str) {
+    if let Some(tid) = current_thread_id() {
+        let registry = get_singleton_registry();
+        let mut registry = registry.lock().unwrap();
+        let stage = registry.entry(stage).or_insert_with(StageThreads::default);
+        stage.threads.insert(tid, Duration::default());
+    }
+}
+
+/// The cumulative CPU time of each stage's threads, including exited ones.
+pub fn stage_cpu_times() -> BTreeMap<&'static str, Duration> {
+    let registry = get_singleton_registry();
+    let mut registry = registry.lock().unwrap();
+    registry
+        .iter_mut()
+        .map(|(name, stage)| {
+            let mut exited = Duration::default();
+            stage
+                .threads
+                .retain(|tid, cpu_time| match thread_cpu_time(*tid) {
+                    Some(current) => {
+                        *cpu_time = current;
+                        true
+                    }
+                    None => {
+                        exited += *cpu_time;
+                        false
+                    }
+                });
+            stage.exited += exited;
+            let live: Duration = stage
+                .threads
+                .values()
+                .fold(Duration::default(), |a, b| a + *b);
+            (*name, stage.exited + live)
+        })
+        .collect()
+}
+
+/// Periodically submits the stages' CPU time to metrics.
+pub struct CpuTimeService {
+    thread_hdl: JoinHandle<()>,
+}
+
+impl CpuTimeService {
+    pub fn new(exit: Arc<AtomicBool>) -> Self {
+        let thread_hdl = Builder::new()
+            .name("solana-cpu-time".to_string())
+            .spawn(move || {
+                let poll = Duration::from_millis(100);
+                let mut elapsed = Duration::default();
+                while !exit.load(Ordering::Relaxed) {
+                    sleep(poll);
+                    elapsed += poll;
+                    if elapsed >= Duration::from_millis(SUBMIT_INTERVAL_MS) {
+                        elapsed = Duration::default();
+                        Self::submit();
+                    }
+                }
+            })
+            .unwrap();
+        CpuTimeService { thread_hdl }
+    }
+
+    fn submit() {
+        let mut point = influxdb::Point::new("stage_cpu_time_ms");
+        for (stage, cpu_time) in stage_cpu_times() {
+            let ms = duration_as_ms(&cpu_time) as i64;
+            point.add_field(stage, influxdb::Value::Integer(ms));
+        }
+        metrics::submit(point);
+    }
+}
+
+impl Service for CpuTimeService {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        vec![self.thread_hdl]
+    }
+
+    fn join(self) -> thread::Result<()> {
+        self.thread_hdl.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu_time::{register_thread, stage_cpu_times};
+    use std::thread::spawn;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_stage_cpu_times() {
+        let busy = Duration::from_millis(30);
+        spawn(move || {
+            register_thread("cpu_time-test");
+            // the thread's time is sampled while it's alive...
+            let start = Instant::now();
+            while stage_cpu_times()["cpu_time-test"] < busy {
+                assert!(start.elapsed() < Duration::from_secs(10));
+            }
+        })
+        .join()
+        .unwrap();
+
+        // ...and kept after it exits
+        assert!(stage_cpu_times()["cpu_time-test"] >= busy);
+    }
+}
@@ -17,14 +17,15 @@ use bincode::{deserialize, serialize};
 use byteorder::{LittleEndian, ReadBytesExt};
 use choose_gossip_peer_strategy::{ChooseGossipPeerStrategy, ChooseWeightedPeerStrategy};
 use counter::Counter;
+use handshake::Capabilities;
 use hash::Hash;
 use ledger::LedgerWindow;
 use log::Level;
 use packet::{to_blob, Blob, BlobRecycler, SharedBlob, BLOB_SIZE};
 use pnet_datalink as datalink;
-use rand::{thread_rng, RngCore};
 use rayon::prelude::*;
 use result::{Error, Result};
+use sharded_sender::ShardedSender;
 use signature::{Keypair, KeypairUtil, Pubkey};
 use std;
 use std::collections::HashMap;
@@ -47,6 +48,9 @@ const GOSSIP_PURGE_MILLIS: u64 = 15000;
 /// minimum membership table size before we start purging dead nodes
 const MIN_TABLE_SIZE: usize = 2;
 
+/// most addresses probed for a single port check request
+const MAX_PORT_CHECK_ADDRS: usize = 8;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum CrdtError {
     NoPeers,
@@ -54,6 +58,7 @@ pub enum CrdtError {
     BadContactInfo,
     BadNodeInfo,
     BadGossipAddress,
+    IncompatibleVersion,
 }
 
 pub fn parse_port_or_addr(optstr: Option<String>) -> SocketAddr {
@@ -111,6 +116,10 @@ pub struct ContactInfo {
     /// repair address, we use this to jump ahead of the packets
     /// destined to the replciate_addr
     pub tvu_window: SocketAddr,
+    /// address to send votes to when this node is leader, so they don't
+    /// compete with other transactions; `tpu` takes them if there's none
+    #[serde(default)]
+    pub tpu_vote: Option<SocketAddr>,
     /// if this struture changes update this value as well
     /// Always update `NodeInfo` version too
     /// This separate version for addresses allows us to use the `Vote`
@@ -119,10 +128,19 @@ pub struct ContactInfo {
     pub version: u64,
 }
 
+impl ContactInfo {
+    /// Where to send votes for this node to record as leader.
+    pub fn vote_addr(&self) -> SocketAddr {
+        self.tpu_vote.unwrap_or(self.tpu)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct LedgerState {
     /// last verified hash that was submitted to the leader
     pub last_id: Hash,
+    /// number of entries this node had processed as of its last vote
+    pub entry_height: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -163,11 +181,13 @@ impl NodeInfo {
                 rpu,
                 tpu,
                 tvu_window,
+                tpu_vote: None,
                 version: 0,
             },
             leader_id: Pubkey::default(),
             ledger_state: LedgerState {
                 last_id: Hash::default(),
+                entry_height: 0,
             },
         }
     }
@@ -214,14 +234,16 @@ impl NodeInfo {
         let replicate_addr = Self::next_port(&bind_addr, 2);
         let requests_addr = Self::next_port(&bind_addr, 3);
         let repair_addr = Self::next_port(&bind_addr, 4);
-        NodeInfo::new(
+        let mut node_info = NodeInfo::new(
             pubkey,
             gossip_addr,
             replicate_addr,
             requests_addr,
             transactions_addr,
             repair_addr,
-        )
+        );
+        node_info.contact_info.tpu_vote = Some(Self::next_port(&bind_addr, 5));
+        node_info
     }
     pub fn new_leader(bind_addr: &SocketAddr) -> Self {
         let keypair = Keypair::new();
@@ -259,6 +281,9 @@ pub struct Crdt {
     pub alive: HashMap<Pubkey, u64>,
     pub update_index: u64,
     pub me: Pubkey,
+    /// The identity this node votes for, when it isn't `me`. The identity must
+    /// authorize `me` in the bank for the votes to count.
+    pub vote_identity: Option<Pubkey>,
     /// last time we heard from anyone getting a message fro this public key
     /// these are rumers and shouldn't be trusted directly
     external_liveness: HashMap<Pubkey, HashMap<Pubkey, u64>>,
@@ -274,9 +299,24 @@ enum Protocol {
     //TODO might need a since?
     /// from id, form's last update index, NodeInfo
     ReceiveUpdates(Pubkey, u64, Vec<NodeInfo>, Vec<(Pubkey, u64)>),
-    /// ask for a missing index
-    /// (my replicated data to keep alive, missing window index)
-    RequestWindowIndex(NodeInfo, u64),
+    /// ask for a missing blob, which was recorded in the given slot or a later one
+    /// (my replicated data to keep alive, slot, missing window index)
+    RequestWindowIndex(NodeInfo, u64, u64),
+    /// ask for the highest blob of a slot, to learn how far the slot goes
+    /// (my replicated data to keep alive, slot, lowest window index wanted)
+    RequestHighestWindowIndex(NodeInfo, u64, u64),
+    /// ask for the last blob of the slot before one whose parent is missing
+    /// (my replicated data to keep alive, orphan slot)
+    RequestOrphan(NodeInfo, u64),
+    /// ask for a probe to each of the requester's addresses on the IP the request came from
+    /// (requester's replicated data, addresses to probe, nonce to put in the probes)
+    RequestPortCheck(NodeInfo, Vec<SocketAddr>, u64),
+    /// trade capabilities with a node before it joins, the answer goes to its gossip address
+    /// (requester's replicated data, requester's capabilities)
+    RequestHandshake(NodeInfo, Capabilities),
+    /// answer to a handshake request
+    /// (answering node's id, its capabilities)
+    Handshake(Pubkey, Capabilities),
 }
 
 impl Crdt {
@@ -312,6 +352,7 @@ impl Crdt {
             alive: HashMap::new(),
             external_liveness: HashMap::new(),
             me: me.id,
+            vote_identity: None,
             update_index: 1,
         };
         g.local.insert(me.id, g.update_index);
@@ -445,6 +486,14 @@ impl Crdt {
         }
     }
 
+    /// Insert every node in `infos` under one lock, returning how many were new
+    /// or newer than what the table had
+    pub fn insert_batch(&mut self, infos: &[NodeInfo]) -> usize {
+        let inserted: usize = infos.iter().map(|v| self.insert(v)).sum();
+        inc_new_counter_info!("crdt-insert_batch-inserted", inserted);
+        inserted
+    }
+
     fn update_liveness(&mut self, id: Pubkey) {
         //update the liveness table
         let now = timestamp();
@@ -556,7 +605,7 @@ impl Crdt {
         me: &NodeInfo,
         broadcast_table: &[NodeInfo],
         window: &SharedWindow,
-        s: &UdpSocket,
+        sender: &ShardedSender,
         transmit_index: &mut WindowIndex,
         received_index: u64,
     ) -> Result<()> {
@@ -618,35 +667,23 @@ impl Crdt {
         }
 
         trace!("broadcast orders table {}", orders.len());
-        let errs: Vec<_> = orders
+        let orders: Vec<_> = orders
             .into_iter()
             .map(|(b, v)| {
                 // only leader should be broadcasting
                 assert!(me.leader_id != v.id);
                 let bl = b.unwrap();
-                let blob = bl.read().expect("blob read lock in streamer::broadcast");
-                //TODO profile this, may need multiple sockets for par_iter
                 trace!(
-                    "{:x}: BROADCAST idx: {} sz: {} to {:x},{} coding: {}",
+                    "{:x}: BROADCAST idx: {} to {:x},{}",
                     me.debug_id(),
-                    blob.get_index().unwrap(),
-                    blob.meta.size,
+                    bl.read().unwrap().get_index().unwrap(),
                     v.debug_id(),
                     v.contact_info.tvu,
-                    blob.is_coding()
-                );
-                assert!(blob.meta.size <= BLOB_SIZE);
-                let e = s.send_to(&blob.data[..blob.meta.size], &v.contact_info.tvu);
-                trace!(
-                    "{:x}: done broadcast {} to {:x} {}",
-                    me.debug_id(),
-                    blob.meta.size,
-                    v.debug_id(),
-                    v.contact_info.tvu
                 );
-                e
+                (bl, v.contact_info.tvu, v.id)
             })
             .collect();
+        let errs = sender.send(orders);
 
         trace!("broadcast results {}", errs.len());
         for e in errs {
@@ -671,11 +708,26 @@ impl Crdt {
     /// # Remarks
     /// We need to avoid having obj locked while doing any io, such as the `send_to`
     pub fn retransmit(obj: &Arc<RwLock<Self>>, blob: &SharedBlob, s: &UdpSocket) -> Result<()> {
-        let (me, table): (NodeInfo, Vec<NodeInfo>) = {
-            // copy to avoid locking during IO
-            let s = obj.read().expect("'obj' read lock in pub fn retransmit");
-            (s.table[&s.me].clone(), s.table.values().cloned().collect())
-        };
+        let (me, table) = Self::retransmit_peers(obj);
+        Self::retransmit_to(&me, &table, blob, s)
+    }
+
+    /// Snapshot of this node and the table for retransmitting a batch of blobs.
+    /// `me.leader_id` is read at the time of the call, so callers should take a
+    /// fresh snapshot for every batch to follow leader changes.
+    pub fn retransmit_peers(obj: &Arc<RwLock<Self>>) -> (NodeInfo, Vec<NodeInfo>) {
+        // copy to avoid locking during IO
+        let s = obj.read().expect("'obj' read lock in pub fn retransmit_peers");
+        (s.table[&s.me].clone(), s.table.values().cloned().collect())
+    }
+
+    /// retransmit a blob to every node in `table` except `me` and its leader
+    pub fn retransmit_to(
+        me: &NodeInfo,
+        table: &[NodeInfo],
+        blob: &SharedBlob,
+        s: &UdpSocket,
+    ) -> Result<()> {
         blob.write()
             .unwrap()
             .set_id(me.id)
@@ -728,10 +780,6 @@ impl Crdt {
         self.remote.values().fold(max, |a, b| std::cmp::min(a, *b))
     }
 
-    fn random() -> u64 {
-        thread_rng().next_u64()
-    }
-
     // TODO: fill in with real implmentation once staking is implemented
     fn get_stake(_id: Pubkey) -> f64 {
         1.0
@@ -750,20 +798,102 @@ impl Crdt {
         (id, ups, data)
     }
 
-    pub fn window_index_request(&self, ix: u64) -> Result<(SocketAddr, Vec<u8>)> {
+    /// Create a repair request for the blob at `ix`, which the requester expects in
+    /// `slot` or a later one, addressed to a peer `strategy` chooses
+    pub fn window_index_request(
+        &self,
+        strategy: &ChooseGossipPeerStrategy,
+        slot: u64,
+        ix: u64,
+    ) -> Result<(SocketAddr, Vec<u8>)> {
+        let addr = self.repair_peer(strategy)?;
+        let req = Protocol::RequestWindowIndex(self.table[&self.me].clone(), slot, ix);
+        let out = serialize(&req)?;
+        Ok((addr, out))
+    }
+
+    /// Create a request for the highest blob of `slot` at or past `ix`, addressed
+    /// to a peer `strategy` chooses
+    pub fn window_highest_index_request(
+        &self,
+        strategy: &ChooseGossipPeerStrategy,
+        slot: u64,
+        ix: u64,
+    ) -> Result<(SocketAddr, Vec<u8>)> {
+        let addr = self.repair_peer(strategy)?;
+        let req = Protocol::RequestHighestWindowIndex(self.table[&self.me].clone(), slot, ix);
+        let out = serialize(&req)?;
+        Ok((addr, out))
+    }
+
+    /// Create a request for the last blob before `slot`, addressed to a peer
+    /// `strategy` chooses
+    pub fn window_orphan_request(
+        &self,
+        strategy: &ChooseGossipPeerStrategy,
+        slot: u64,
+    ) -> Result<(SocketAddr, Vec<u8>)> {
+        let addr = self.repair_peer(strategy)?;
+        let req = Protocol::RequestOrphan(self.table[&self.me].clone(), slot);
+        let out = serialize(&req)?;
+        Ok((addr, out))
+    }
+
+    /// The gossip address of the peer `strategy` chooses to send a repair
+    /// request to
+    fn repair_peer(&self, strategy: &ChooseGossipPeerStrategy) -> Result<SocketAddr> {
         let valid: Vec<_> = self
             .table
             .values()
             .filter(|r| r.id != self.me && Self::is_valid_address(r.contact_info.tvu_window))
             .collect();
-        if valid.is_empty() {
-            Err(CrdtError::NoPeers)?;
-        }
-        let n = (Self::random() as usize) % valid.len();
-        let addr = valid[n].contact_info.ncp;
-        let req = Protocol::RequestWindowIndex(self.table[&self.me].clone(), ix);
+        let peer = strategy.choose_peer(valid)?;
+        Ok(peer.contact_info.ncp)
+    }
+
+    /// Create a request asking a peer to probe each of `addrs` with `nonce`
+    pub fn port_check_request(me: &NodeInfo, addrs: &[SocketAddr], nonce: u64) -> Result<Vec<u8>> {
+        let req = Protocol::RequestPortCheck(me.clone(), addrs.to_vec(), nonce);
         let out = serialize(&req)?;
-        Ok((addr, out))
+        Ok(out)
+    }
+
+    /// Create a request to trade capabilities with a peer before joining
+    pub fn handshake_request(me: &NodeInfo, capabilities: &Capabilities) -> Result<Vec<u8>> {
+        let req = Protocol::RequestHandshake(me.clone(), *capabilities);
+        let out = serialize(&req)?;
+        Ok(out)
+    }
+
+    /// Return the sender and capabilities of a handshake answer in `packet`
+    pub fn parse_handshake(packet: &[u8]) -> Option<(Pubkey, Capabilities)> {
+        match deserialize(packet) {
+            Ok(Protocol::Handshake(id, capabilities)) => Some((id, capabilities)),
+            _ => None,
+        }
+    }
+
+    /// Send a probe carrying `nonce` to each address of `from` that was asked for.
+    /// Only addresses on the IP the request came from are probed, and only a handful
+    /// of them, so the request can't be used to aim traffic at someone else. The
+    /// addresses in `from` are whatever the requester claims, so they aren't trusted.
+    fn run_port_check(
+        from: &NodeInfo,
+        from_addr: &SocketAddr,
+        addrs: &[SocketAddr],
+        nonce: u64,
+    ) -> Result<()> {
+        let probe = serialize(&nonce)?;
+        let sock = UdpSocket::bind("0.0.0.0:0")?;
+        for addr in addrs
+            .iter()
+            .filter(|addr| addr.ip() == from_addr.ip())
+            .take(MAX_PORT_CHECK_ADDRS)
+        {
+            trace!("port check probe {:x} {}", from.debug_id(), addr);
+            sock.send_to(&probe, addr)?;
+        }
+        Ok(())
     }
 
     /// Create a random gossip request
@@ -811,7 +941,11 @@ impl Crdt {
         Ok((v.contact_info.ncp, req))
     }
 
-    pub fn new_vote(&mut self, last_id: Hash) -> Result<(Vote, SocketAddr)> {
+    pub fn new_vote(
+        &mut self,
+        last_id: Hash,
+        accounts_hash: Option<Hash>,
+    ) -> Result<(Vote, SocketAddr)> {
         let mut me = self.my_data().clone();
         let leader = self.leader_data().ok_or(CrdtError::NoLeader)?.clone();
         me.version += 1;
@@ -819,9 +953,21 @@ impl Crdt {
         let vote = Vote {
             version: me.version,
             contact_info_version: me.contact_info.version,
+            identity: self.vote_identity,
+            accounts_hash,
         };
         self.insert(&me);
-        Ok((vote, leader.contact_info.tpu))
+        Ok((vote, leader.contact_info.vote_addr()))
+    }
+
+    /// Record how many entries this node has processed. The height isn't
+    /// gossiped by itself; it rides along with the next `new_vote`, which
+    /// bumps the version of this node's `NodeInfo`.
+    pub fn set_entry_height(&mut self, entry_height: u64) {
+        let me = self.me;
+        if let Some(node) = self.table.get_mut(&me) {
+            node.ledger_state.entry_height = entry_height;
+        }
     }
 
     /// At random pick a node and try to get updated changes from them
@@ -897,10 +1043,7 @@ impl Crdt {
         trace!("got updates {}", data.len());
         // TODO we need to punish/spam resist here
         // sigverify the whole update and slash anyone who sends a bad update
-        let mut insert_total = 0;
-        for v in data {
-            insert_total += self.insert(&v);
-        }
+        let insert_total = self.insert_batch(data);
         inc_new_counter_info!("crdt-update-count", insert_total);
 
         for (pubkey, external_remote_index) in external_liveness {
@@ -958,11 +1101,122 @@ impl Crdt {
             })
             .unwrap()
     }
+    /// Copy `wblob` into a response to `from`'s repair request
+    fn repair_response(
+        me: &NodeInfo,
+        from: &NodeInfo,
+        wblob: &mut Blob,
+        blob_recycler: &BlobRecycler,
+    ) -> SharedBlob {
+        let num_retransmits = wblob.meta.num_retransmits;
+        wblob.meta.num_retransmits += 1;
+        // Setting the sender id to the requester id
+        // prevents the requester from retransmitting this response
+        // to other peers
+        let mut sender_id = from.id;
+
+        // Allow retransmission of this response if the node
+        // is the leader and the number of repair requests equals
+        // a power of two
+        if me.leader_id == me.id && (num_retransmits == 0 || num_retransmits.is_power_of_two()) {
+            sender_id = me.id
+        }
+
+        let out = blob_recycler.allocate();
+
+        // copy to avoid doing IO inside the lock
+        {
+            let mut outblob = out.write().unwrap();
+            let sz = wblob.meta.size;
+            outblob.meta.size = sz;
+            outblob.data[..sz].copy_from_slice(&wblob.data[..sz]);
+            outblob.meta.set_addr(&from.contact_info.tvu_window);
+            outblob.set_id(sender_id).expect("blob set_id");
+        }
+        out
+    }
+
+    /// Find the data blob in the window with the highest index of those recorded
+    /// in `slot` at or past `ix`
+    fn run_highest_window_request(
+        window: &SharedWindow,
+        me: &NodeInfo,
+        from: &NodeInfo,
+        slot: u64,
+        ix: u64,
+        blob_recycler: &BlobRecycler,
+    ) -> Option<SharedBlob> {
+        let window = window.read().unwrap();
+        let highest = window
+            .iter()
+            .filter_map(|w| w.data.as_ref())
+            .filter_map(|blob| {
+                let r_blob = blob.read().unwrap();
+                let blob_ix = r_blob.get_index().ok()?;
+                if r_blob.get_slot().ok()? == slot && blob_ix >= ix {
+                    Some((blob_ix, blob))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|(blob_ix, _)| *blob_ix);
+        if let Some((_, blob)) = highest {
+            inc_new_counter_info!("crdt-window-request-highest-pass", 1);
+            let mut wblob = blob.write().unwrap();
+            return Some(Self::repair_response(me, from, &mut wblob, blob_recycler));
+        }
+        inc_new_counter_info!("crdt-window-request-highest-fail", 1);
+        None
+    }
+
+    /// Find the last data blob of the slot before `slot`, the parent a requester
+    /// missing everything between its ledger and `slot` needs to walk back
+    fn run_orphan_request(
+        window: &SharedWindow,
+        me: &NodeInfo,
+        from: &NodeInfo,
+        slot: u64,
+        blob_recycler: &BlobRecycler,
+    ) -> Option<SharedBlob> {
+        let window = window.read().unwrap();
+        let index_in_window = |ix: u64| {
+            let blob = window[(ix as usize) % window.len()].data.as_ref()?;
+            if blob.read().unwrap().get_index().ok()? == ix {
+                Some(blob)
+            } else {
+                None
+            }
+        };
+        let first_ix = window
+            .iter()
+            .filter_map(|w| w.data.as_ref())
+            .filter_map(|blob| {
+                let r_blob = blob.read().unwrap();
+                if r_blob.get_slot().ok()? == slot {
+                    r_blob.get_index().ok()
+                } else {
+                    None
+                }
+            })
+            .min();
+        let parent = first_ix
+            .and_then(|ix| ix.checked_sub(1))
+            .and_then(index_in_window);
+        if let Some(blob) = parent {
+            inc_new_counter_info!("crdt-window-request-orphan-pass", 1);
+            let mut wblob = blob.write().unwrap();
+            return Some(Self::repair_response(me, from, &mut wblob, blob_recycler));
+        }
+        inc_new_counter_info!("crdt-window-request-orphan-fail", 1);
+        None
+    }
+
     fn run_window_request(
         window: &SharedWindow,
         ledger_window: &mut Option<&mut LedgerWindow>,
         me: &NodeInfo,
         from: &NodeInfo,
+        slot: u64,
         ix: u64,
         blob_recycler: &BlobRecycler,
     ) -> Option<SharedBlob> {
@@ -970,42 +1224,19 @@ impl Crdt {
         if let Some(blob) = &window.read().unwrap()[pos].data {
             let mut wblob = blob.write().unwrap();
             let blob_ix = wblob.get_index().expect("run_window_request get_index");
-            if blob_ix == ix {
-                let num_retransmits = wblob.meta.num_retransmits;
-                wblob.meta.num_retransmits += 1;
-                // Setting the sender id to the requester id
-                // prevents the requester from retransmitting this response
-                // to other peers
-                let mut sender_id = from.id;
-
-                // Allow retransmission of this response if the node
-                // is the leader and the number of repair requests equals
-                // a power of two
-                if me.leader_id == me.id
-                    && (num_retransmits == 0 || num_retransmits.is_power_of_two())
-                {
-                    sender_id = me.id
-                }
-
-                let out = blob_recycler.allocate();
-
-                // copy to avoid doing IO inside the lock
-                {
-                    let mut outblob = out.write().unwrap();
-                    let sz = wblob.meta.size;
-                    outblob.meta.size = sz;
-                    outblob.data[..sz].copy_from_slice(&wblob.data[..sz]);
-                    outblob.meta.set_addr(&from.contact_info.tvu_window);
-                    outblob.set_id(sender_id).expect("blob set_id");
-                }
+            let blob_slot = wblob.get_slot().expect("run_window_request get_slot");
+            // a blob from an earlier slot than the requester has already seen was
+            // recorded by a leader the requester has moved on from
+            if blob_ix == ix && blob_slot >= slot {
                 inc_new_counter_info!("crdt-window-request-pass", 1);
-
-                return Some(out);
+                return Some(Self::repair_response(me, from, &mut wblob, blob_recycler));
             } else {
                 inc_new_counter_info!("crdt-window-request-outside", 1);
                 trace!(
-                    "requested ix {} != blob_ix {}, outside window!",
+                    "requested slot {} ix {} != blob_slot {} blob_ix {}, outside window!",
+                    slot,
                     ix,
+                    blob_slot,
                     blob_ix
                 );
                 // falls through to checking window_ledger
@@ -1029,9 +1260,10 @@ impl Crdt {
 
         inc_new_counter_info!("crdt-window-request-fail", 1);
         trace!(
-            "{:x}: failed RequestWindowIndex {:x} {} {}",
+            "{:x}: failed RequestWindowIndex {:x} {} {} {}",
             me.debug_id(),
             from.debug_id(),
+            slot,
             ix,
             pos,
         );
@@ -1039,6 +1271,18 @@ impl Crdt {
         None
     }
 
+    /// Keep the requester of a repair alive and return my data, unless the
+    /// request came from me
+    fn accept_repair_request(obj: &Arc<RwLock<Self>>, from: &NodeInfo) -> Option<NodeInfo> {
+        obj.write().unwrap().insert(from);
+        let me = obj.read().unwrap().my_data().clone();
+        if from.contact_info.tvu_window == me.contact_info.tvu_window {
+            inc_new_counter_info!("crdt-window-request-address-eq", 1);
+            return None;
+        }
+        Some(me)
+    }
+
     //TODO we should first coalesce all the requests
     fn handle_blob(
         obj: &Arc<RwLock<Self>>,
@@ -1048,9 +1292,14 @@ impl Crdt {
         blob: &Blob,
     ) -> Option<SharedBlob> {
         match deserialize(&blob.data[..blob.meta.size]) {
-            Ok(request) => {
-                Crdt::handle_protocol(request, obj, window, ledger_window, blob_recycler)
-            }
+            Ok(request) => Crdt::handle_protocol(
+                request,
+                &blob.meta.addr(),
+                obj,
+                window,
+                ledger_window,
+                blob_recycler,
+            ),
             Err(_) => {
                 warn!("deserialize crdt packet failed");
                 None
@@ -1060,6 +1309,7 @@ impl Crdt {
 
     fn handle_protocol(
         request: Protocol,
+        from_addr: &SocketAddr,
         obj: &Arc<RwLock<Self>>,
         window: &SharedWindow,
         ledger_window: &mut Option<&mut LedgerWindow>,
@@ -1134,7 +1384,7 @@ impl Crdt {
                 );
                 None
             }
-            Protocol::RequestWindowIndex(from, ix) => {
+            Protocol::RequestWindowIndex(from, slot, ix) => {
                 let now = Instant::now();
                 //TODO this doesn't depend on CRDT module, can be moved
                 //but we are using the listen thread to service these request
@@ -1143,9 +1393,10 @@ impl Crdt {
                 let me = obj.read().unwrap().my_data().clone();
                 inc_new_counter_info!("crdt-window-request-recv", 1);
                 trace!(
-                    "{:x}:received RequestWindowIndex {:x} {} ",
+                    "{:x}:received RequestWindowIndex {:x} {} {} ",
                     me.debug_id(),
                     from.debug_id(),
+                    slot,
                     ix,
                 );
                 if from.contact_info.tvu_window == me.contact_info.tvu_window {
@@ -1158,15 +1409,65 @@ impl Crdt {
                     inc_new_counter_info!("crdt-window-request-address-eq", 1);
                     return None;
                 }
-                let res =
-                    Self::run_window_request(&window, ledger_window, &me, &from, ix, blob_recycler);
+                let res = Self::run_window_request(
+                    &window,
+                    ledger_window,
+                    &me,
+                    &from,
+                    slot,
+                    ix,
+                    blob_recycler,
+                );
                 report_time_spent(
                     "RequestWindowIndex",
                     &now.elapsed(),
-                    &format!(" ix: {}", ix),
+                    &format!(" slot: {} ix: {}", slot, ix),
                 );
                 res
             }
+            Protocol::RequestHighestWindowIndex(from, slot, ix) => {
+                let me = Self::accept_repair_request(obj, &from)?;
+                inc_new_counter_info!("crdt-window-request-highest-recv", 1);
+                Self::run_highest_window_request(&window, &me, &from, slot, ix, blob_recycler)
+            }
+            Protocol::RequestOrphan(from, slot) => {
+                let me = Self::accept_repair_request(obj, &from)?;
+                inc_new_counter_info!("crdt-window-request-orphan-recv", 1);
+                Self::run_orphan_request(&window, &me, &from, slot, blob_recycler)
+            }
+            Protocol::RequestPortCheck(from, addrs, nonce) => {
+                inc_new_counter_info!("crdt-port_check-recv", 1);
+                if let Err(e) = Self::run_port_check(&from, from_addr, &addrs, nonce) {
+                    warn!("port check for {:x} failed: {:?}", from.debug_id(), e);
+                }
+                None
+            }
+            Protocol::RequestHandshake(from, capabilities) => {
+                inc_new_counter_info!("crdt-handshake-recv", 1);
+                let me = obj.read().unwrap().my_data().clone();
+                let local = Capabilities::local();
+                // answer anyway, so the requester can report why it can't join
+                if !local.is_compatible(&capabilities) {
+                    inc_new_counter_info!("crdt-handshake-incompatible", 1);
+                    warn!(
+                        "{:x}: incompatible node {:x} ours {:?} theirs {:?}",
+                        me.debug_id(),
+                        from.debug_id(),
+                        local,
+                        capabilities
+                    );
+                }
+                let rsp = Protocol::Handshake(me.id, local);
+                match to_blob(rsp, from.contact_info.ncp, &blob_recycler) {
+                    Ok(r) => Some(r),
+                    Err(e) => {
+                        warn!("handshake to_blob failed: {:?}", e);
+                        None
+                    }
+                }
+            }
+            // only expected by a node that hasn't started gossip yet
+            Protocol::Handshake(_, _) => None,
         }
     }
 
@@ -1244,6 +1545,42 @@ impl Crdt {
     }
 }
 
+/// A copy of this node and the table to retransmit to, kept across batches so
+/// the table is only cloned when it has changed. Every insert, including a
+/// leader change, bumps `update_index` and every purge shrinks the table, so
+/// checking the two is enough to know the copy is stale.
+pub struct RetransmitPeers {
+    pub me: NodeInfo,
+    pub table: Vec<NodeInfo>,
+    update_index: u64,
+}
+
+impl RetransmitPeers {
+    pub fn new(obj: &Arc<RwLock<Crdt>>) -> Self {
+        let s = obj.read().expect("'obj' read lock in RetransmitPeers::new");
+        RetransmitPeers {
+            me: s.my_data().clone(),
+            table: s.table.values().cloned().collect(),
+            update_index: s.update_index,
+        }
+    }
+
+    /// Re-copy the table if it changed since the last copy. When it hasn't,
+    /// the read lock is only held long enough to compare two numbers.
+    /// Returns whether the copy was refreshed.
+    pub fn refresh(&mut self, obj: &Arc<RwLock<Crdt>>) -> bool {
+        {
+            let s = obj.read().unwrap();
+            if s.update_index == self.update_index && s.table.len() == self.table.len() {
+                return false;
+            }
+        }
+        inc_new_counter_info!("crdt-retransmit_peers-refresh", 1);
+        *self = Self::new(obj);
+        true
+    }
+}
+
 pub struct Sockets {
     pub gossip: UdpSocket,
     pub gossip_send: UdpSocket,
@@ -1254,6 +1591,8 @@ pub struct Sockets {
     pub broadcast: UdpSocket,
     pub repair: UdpSocket,
     pub retransmit: UdpSocket,
+    /// Where the node takes votes as leader, if it advertises a port for them.
+    pub vote: Option<UdpSocket>,
 }
 
 pub struct TestNode {
@@ -1272,12 +1611,13 @@ impl TestNode {
         let replicate = UdpSocket::bind("127.0.0.1:0").unwrap();
         let requests = UdpSocket::bind("127.0.0.1:0").unwrap();
         let repair = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let vote = UdpSocket::bind("127.0.0.1:0").unwrap();
 
         let gossip_send = UdpSocket::bind("0.0.0.0:0").unwrap();
         let respond = UdpSocket::bind("0.0.0.0:0").unwrap();
         let broadcast = UdpSocket::bind("0.0.0.0:0").unwrap();
         let retransmit = UdpSocket::bind("0.0.0.0:0").unwrap();
-        let data = NodeInfo::new(
+        let mut data = NodeInfo::new(
             pubkey,
             gossip.local_addr().unwrap(),
             replicate.local_addr().unwrap(),
@@ -1285,6 +1625,7 @@ impl TestNode {
             transaction.local_addr().unwrap(),
             repair.local_addr().unwrap(),
         );
+        data.contact_info.tpu_vote = Some(vote.local_addr().unwrap());
         TestNode {
             data,
             sockets: Sockets {
@@ -1297,6 +1638,7 @@ impl TestNode {
                 broadcast,
                 repair,
                 retransmit,
+                vote: Some(vote),
             },
         }
     }
@@ -1330,6 +1672,11 @@ impl TestNode {
         let replicate = bind(local_replicate_addr);
         let repair = bind(local_repair_addr);
         let requests = bind(local_requests_addr);
+        let vote = data.contact_info.tpu_vote.map(|addr| {
+            let mut local_vote_addr = bind_addr;
+            local_vote_addr.set_port(addr.port());
+            bind(local_vote_addr)
+        });
 
         // Responses are sent from the same Udp port as requests are received
         // from, in hopes that a NAT sitting in the middle will route the
@@ -1351,6 +1698,7 @@ impl TestNode {
                 broadcast,
                 repair,
                 retransmit,
+                vote,
             },
         }
     }
@@ -1365,18 +1713,23 @@ fn report_time_spent(label: &str, time: &Duration, extra: &str) {
 
 #[cfg(test)]
 mod tests {
+    use bincode::deserialize;
+    use choose_gossip_peer_strategy::ChooseRandomPeerStrategy;
     use crdt::{
-        parse_port_or_addr, Crdt, CrdtError, NodeInfo, Protocol, GOSSIP_PURGE_MILLIS,
-        GOSSIP_SLEEP_MILLIS, MIN_TABLE_SIZE,
+        parse_port_or_addr, Crdt, CrdtError, NodeInfo, Protocol, RetransmitPeers,
+        GOSSIP_PURGE_MILLIS, GOSSIP_SLEEP_MILLIS, MIN_TABLE_SIZE,
     };
     use entry::Entry;
+    use handshake::Capabilities;
     use hash::{hash, Hash};
-    use ledger::{LedgerWindow, LedgerWriter};
+    use ledger::{FileLedgerWriter, LedgerWindow};
     use logger;
-    use packet::BlobRecycler;
+    use packet::{BlobRecycler, SharedBlob};
+    use rand::{thread_rng, RngCore};
     use result::Error;
     use signature::{Keypair, KeypairUtil, Pubkey};
     use std::fs::remove_dir_all;
+    use std::net::{SocketAddr, UdpSocket};
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::mpsc::channel;
     use std::sync::{Arc, RwLock};
@@ -1528,6 +1881,40 @@ mod tests {
         assert_eq!(crdt.table[&d.id].version, 3);
         assert!(liveness < crdt.alive[&d.id]);
     }
+    #[test]
+    fn test_insert_batch() {
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let mut crdt = Crdt::new(me).unwrap();
+        let mut a = NodeInfo::new_leader(&"127.0.0.2:1234".parse().unwrap());
+        let b = NodeInfo::new_leader(&"127.0.0.3:1234".parse().unwrap());
+        assert_eq!(crdt.insert_batch(&[a.clone(), b.clone()]), 2);
+        assert_eq!(crdt.table.len(), 3);
+
+        // only the newer version of `a` counts
+        a.version = 1;
+        assert_eq!(crdt.insert_batch(&[a.clone(), b.clone()]), 1);
+        assert_eq!(crdt.table[&a.id].version, 1);
+    }
+
+    #[test]
+    fn test_retransmit_peers_refresh() {
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let crdt = Arc::new(RwLock::new(Crdt::new(me).unwrap()));
+        let mut peers = RetransmitPeers::new(&crdt);
+        assert_eq!(peers.table.len(), 1);
+        assert!(!peers.refresh(&crdt));
+
+        let leader = NodeInfo::new_leader(&"127.0.0.2:1234".parse().unwrap());
+        crdt.write().unwrap().insert(&leader);
+        assert!(peers.refresh(&crdt));
+        assert_eq!(peers.table.len(), 2);
+        assert!(!peers.refresh(&crdt));
+
+        crdt.write().unwrap().set_leader(leader.id);
+        assert!(peers.refresh(&crdt));
+        assert_eq!(peers.me.leader_id, leader.id);
+    }
+
     #[test]
     fn test_new_vote() {
         let d = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
@@ -1537,12 +1924,12 @@ mod tests {
         let leader = NodeInfo::new_leader(&"127.0.0.2:1235".parse().unwrap());
         assert_ne!(d.id, leader.id);
         assert_matches!(
-            crdt.new_vote(Hash::default()).err(),
+            crdt.new_vote(Hash::default(), None).err(),
             Some(Error::CrdtError(CrdtError::NoLeader))
         );
         crdt.insert(&leader);
         assert_matches!(
-            crdt.new_vote(Hash::default()).err(),
+            crdt.new_vote(Hash::default(), None).err(),
             Some(Error::CrdtError(CrdtError::NoLeader))
         );
         crdt.set_leader(leader.id);
@@ -1550,9 +1937,34 @@ mod tests {
         let v = Vote {
             version: 2, //version should increase when we vote
             contact_info_version: 0,
+            identity: None,
+            accounts_hash: Some(Hash::default()),
         };
-        let expected = (v, crdt.table[&leader.id].contact_info.tpu);
-        assert_eq!(crdt.new_vote(Hash::default()).unwrap(), expected);
+        let expected = (v, crdt.table[&leader.id].contact_info.vote_addr());
+        assert_eq!(
+            crdt.new_vote(Hash::default(), Some(Hash::default()))
+                .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_set_entry_height() {
+        let d = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let mut crdt = Crdt::new(d.clone()).unwrap();
+        let leader = NodeInfo::new_leader(&"127.0.0.2:1235".parse().unwrap());
+        crdt.insert(&leader);
+        crdt.set_leader(leader.id);
+        let version = crdt.table[&d.id].version;
+
+        crdt.set_entry_height(42);
+        assert_eq!(crdt.my_data().ledger_state.entry_height, 42);
+        assert_eq!(crdt.table[&d.id].version, version);
+
+        // the height goes out with the next vote
+        crdt.new_vote(Hash::default(), None).unwrap();
+        assert_eq!(crdt.table[&d.id].version, version + 1);
+        assert_eq!(crdt.my_data().ledger_state.entry_height, 42);
     }
 
     #[test]
@@ -1564,6 +1976,8 @@ mod tests {
         let vote_same_version = Vote {
             version: d.version,
             contact_info_version: 0,
+            identity: None,
+            accounts_hash: None,
         };
         crdt.insert_vote(&d.id, &vote_same_version, Hash::default());
         assert_eq!(crdt.table[&d.id].version, 0);
@@ -1571,6 +1985,8 @@ mod tests {
         let vote_new_version_new_addrs = Vote {
             version: d.version + 1,
             contact_info_version: 1,
+            identity: None,
+            accounts_hash: None,
         };
         crdt.insert_vote(&d.id, &vote_new_version_new_addrs, Hash::default());
         //should be dropped since the address is newer then we know
@@ -1579,6 +1995,8 @@ mod tests {
         let vote_new_version_old_addrs = Vote {
             version: d.version + 1,
             contact_info_version: 0,
+            identity: None,
+            accounts_hash: None,
         };
         crdt.insert_vote(&d.id, &vote_new_version_old_addrs, Hash::default());
         //should be accepted, since the update is for the same address field as the one we know
@@ -1676,7 +2094,9 @@ mod tests {
             "127.0.0.1:1238".parse().unwrap(),
         );
         let mut crdt = Crdt::new(me.clone()).expect("Crdt::new");
-        let rv = crdt.window_index_request(0);
+        let next_u64 = || thread_rng().next_u64();
+        let random = ChooseRandomPeerStrategy::new(&next_u64);
+        let rv = crdt.window_index_request(&random, 0, 0);
         assert_matches!(rv, Err(Error::CrdtError(CrdtError::NoPeers)));
         let nxt = NodeInfo::new(
             Keypair::new().pubkey(),
@@ -1687,7 +2107,7 @@ mod tests {
             "0.0.0.0:0".parse().unwrap(),
         );
         crdt.insert(&nxt);
-        let rv = crdt.window_index_request(0);
+        let rv = crdt.window_index_request(&random, 0, 0);
         assert_matches!(rv, Err(Error::CrdtError(CrdtError::NoPeers)));
         let nxt = NodeInfo::new(
             Keypair::new().pubkey(),
@@ -1698,7 +2118,7 @@ mod tests {
             "127.0.0.1:1238".parse().unwrap(),
         );
         crdt.insert(&nxt);
-        let rv = crdt.window_index_request(0).unwrap();
+        let rv = crdt.window_index_request(&random, 0, 0).unwrap();
         assert_eq!(nxt.contact_info.ncp, "127.0.0.2:1234".parse().unwrap());
         assert_eq!(rv.0, "127.0.0.2:1234".parse().unwrap());
 
@@ -1715,7 +2135,7 @@ mod tests {
         let mut two = false;
         while !one || !two {
             //this randomly picks an option, so eventually it should pick both
-            let rv = crdt.window_index_request(0).unwrap();
+            let rv = crdt.window_index_request(&random, 0, 0).unwrap();
             if rv.0 == "127.0.0.2:1234".parse().unwrap() {
                 one = true;
             }
@@ -1896,18 +2316,23 @@ mod tests {
             "127.0.0.1:1238".parse().unwrap(),
         );
         let recycler = BlobRecycler::default();
-        let rv = Crdt::run_window_request(&window, &mut None, &me, &me, 0, &recycler);
+        let rv = Crdt::run_window_request(&window, &mut None, &me, &me, 0, 0, &recycler);
         assert!(rv.is_none());
         let out = recycler.allocate();
         out.write().unwrap().meta.size = 200;
+        out.write().unwrap().set_slot(1).unwrap();
         window.write().unwrap()[0].data = Some(out);
-        let rv = Crdt::run_window_request(&window, &mut None, &me, &me, 0, &recycler);
+        let rv = Crdt::run_window_request(&window, &mut None, &me, &me, 1, 0, &recycler);
         assert!(rv.is_some());
         let v = rv.unwrap();
         //test we copied the blob
         assert_eq!(v.read().unwrap().meta.size, 200);
+        assert_eq!(v.read().unwrap().get_slot().unwrap(), 1);
         let len = window.read().unwrap().len() as u64;
-        let rv = Crdt::run_window_request(&window, &mut None, &me, &me, len, &recycler);
+        let rv = Crdt::run_window_request(&window, &mut None, &me, &me, 1, len, &recycler);
+        assert!(rv.is_none());
+        // the requester has already seen a later slot than the blob's
+        let rv = Crdt::run_window_request(&window, &mut None, &me, &me, 2, 0, &recycler);
         assert!(rv.is_none());
 
         fn tmp_ledger(name: &str) -> String {
@@ -1917,7 +2342,7 @@ mod tests {
 
             let path = format!("{}/tmp-ledger-{}-{}", out_dir, name, keypair.pubkey());
 
-            let mut writer = LedgerWriter::open(&path, true).unwrap();
+            let mut writer = FileLedgerWriter::open(&path, true).unwrap();
             let zero = Hash::default();
             let one = hash(&zero.as_ref());
             writer
@@ -1934,6 +2359,7 @@ mod tests {
             &mut Some(&mut ledger_window),
             &me,
             &me,
+            0,
             1,
             &recycler,
         );
@@ -1942,6 +2368,39 @@ mod tests {
         remove_dir_all(ledger_path).unwrap();
     }
 
+    /// test highest and orphan requests find the right blob by slot
+    #[test]
+    fn run_highest_and_orphan_request() {
+        let window = default_window();
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let recycler = BlobRecycler::default();
+        for (ix, slot) in [1, 2, 2, 2].iter().enumerate() {
+            let blob = recycler.allocate();
+            {
+                let mut w = blob.write().unwrap();
+                w.meta.size = 200;
+                w.set_index(ix as u64).unwrap();
+                w.set_slot(*slot).unwrap();
+            }
+            window.write().unwrap()[ix].data = Some(blob);
+        }
+        let index_of =
+            |blob: Option<SharedBlob>| blob.map(|b| b.read().unwrap().get_index().unwrap());
+
+        let rv = Crdt::run_highest_window_request(&window, &me, &me, 2, 0, &recycler);
+        assert_eq!(index_of(rv), Some(3));
+        let rv = Crdt::run_highest_window_request(&window, &me, &me, 2, 4, &recycler);
+        assert_eq!(index_of(rv), None);
+        let rv = Crdt::run_highest_window_request(&window, &me, &me, 3, 0, &recycler);
+        assert_eq!(index_of(rv), None);
+
+        // the parent of slot 2 is the last blob of slot 1
+        let rv = Crdt::run_orphan_request(&window, &me, &me, 2, &recycler);
+        assert_eq!(index_of(rv), Some(0));
+        let rv = Crdt::run_orphan_request(&window, &me, &me, 1, &recycler);
+        assert_eq!(index_of(rv), None);
+    }
+
     /// test window requests respond with the right blob, and do not overrun
     #[test]
     fn run_window_request_with_backoff() {
@@ -1955,7 +2414,7 @@ mod tests {
         let recycler = BlobRecycler::default();
 
         // Simulate handling a repair request from mock_peer
-        let rv = Crdt::run_window_request(&window, &mut None, &me, &mock_peer, 0, &recycler);
+        let rv = Crdt::run_window_request(&window, &mut None, &me, &mock_peer, 0, 0, &recycler);
         assert!(rv.is_none());
         let blob = recycler.allocate();
         let blob_size = 200;
@@ -1964,9 +2423,9 @@ mod tests {
 
         let num_requests: u32 = 64;
         for i in 0..num_requests {
-            let shared_blob = Crdt::run_window_request(
-                &window, &mut None, &me, &mock_peer, 0, &recycler,
-            ).unwrap();
+            let shared_blob =
+                Crdt::run_window_request(&window, &mut None, &me, &mock_peer, 0, 0, &recycler)
+                    .unwrap();
             let blob = shared_blob.read().unwrap();
             // Test we copied the blob
             assert_eq!(blob.meta.size, blob_size);
@@ -2025,14 +2484,20 @@ mod tests {
 
         let obj = Arc::new(RwLock::new(crdt));
 
+        let addr = node.contact_info.ncp;
         let request = Protocol::RequestUpdates(1, node.clone());
-        assert!(Crdt::handle_protocol(request, &obj, &window, &mut None, &recycler).is_none());
+        assert!(
+            Crdt::handle_protocol(request, &addr, &obj, &window, &mut None, &recycler).is_none()
+        );
 
         let request = Protocol::RequestUpdates(1, node_with_same_addr.clone());
-        assert!(Crdt::handle_protocol(request, &obj, &window, &mut None, &recycler).is_none());
+        assert!(
+            Crdt::handle_protocol(request, &addr, &obj, &window, &mut None, &recycler).is_none()
+        );
 
+        let addr = node_with_diff_addr.contact_info.ncp;
         let request = Protocol::RequestUpdates(1, node_with_diff_addr.clone());
-        Crdt::handle_protocol(request, &obj, &window, &mut None, &recycler);
+        Crdt::handle_protocol(request, &addr, &obj, &window, &mut None, &recycler);
 
         let me = obj.write().unwrap();
 
@@ -2069,4 +2534,59 @@ mod tests {
         crdt.insert(&network_entry_point);
         assert!(crdt.leader_data().is_none());
     }
+
+    #[test]
+    fn protocol_port_check() {
+        logger::setup();
+        let window = default_window();
+        let recycler = BlobRecycler::default();
+        let obj = Arc::new(RwLock::new(
+            Crdt::new(NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap())).unwrap(),
+        ));
+
+        let probed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        probed
+            .set_read_timeout(Some(Duration::from_millis(1000)))
+            .unwrap();
+        // the requester claims to be elsewhere, but the probes follow the request's source
+        let from = NodeInfo::new_leader(&"127.0.0.2:4321".parse().unwrap());
+        let from_addr: SocketAddr = "127.0.0.1:4321".parse().unwrap();
+        let elsewhere: SocketAddr = "127.0.0.2:4321".parse().unwrap();
+        let addrs = vec![elsewhere, probed.local_addr().unwrap()];
+        let request = Protocol::RequestPortCheck(from, addrs, 42);
+        assert!(
+            Crdt::handle_protocol(request, &from_addr, &obj, &window, &mut None, &recycler)
+                .is_none()
+        );
+
+        let mut buf = [0u8; 64];
+        let (size, _) = probed.recv_from(&mut buf).unwrap();
+        let nonce: u64 = deserialize(&buf[..size]).unwrap();
+        assert_eq!(nonce, 42);
+    }
+
+    #[test]
+    fn protocol_handshake() {
+        logger::setup();
+        let window = default_window();
+        let recycler = BlobRecycler::default();
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let me_id = me.id;
+        let obj = Arc::new(RwLock::new(Crdt::new(me).unwrap()));
+
+        let from = NodeInfo::new_leader(&"127.0.0.1:4321".parse().unwrap());
+        let mut capabilities = Capabilities::local();
+        capabilities.repair_version += 1;
+        let request = Protocol::RequestHandshake(from.clone(), capabilities);
+        let from_addr = from.contact_info.ncp;
+        // incompatible nodes are answered too
+        let rsp = Crdt::handle_protocol(request, &from_addr, &obj, &window, &mut None, &recycler)
+            .unwrap();
+        let rsp = rsp.read().unwrap();
+        assert_eq!(rsp.meta.addr(), from.contact_info.ncp);
+        assert_eq!(
+            Crdt::parse_handshake(&rsp.data[..rsp.meta.size]),
+            Some((me_id, Capabilities::local()))
+        );
+    }
 }
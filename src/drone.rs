@@ -2,17 +2,25 @@
 //! which is the custodian of any remaining tokens in a mint.
 //! The Solana Drone builds and send airdrop transactions,
 //! checking requests against a request cap for a given time time_slice
-//! and (to come) an IP rate limit.
+//! and (to come) an IP rate limit. It also redeems coupons that the drone
+//! operator signed ahead of time, so tokens can be handed out offline. The
+//! redeemed coupons can be kept in a file until they expire, so that a
+//! restarted drone doesn't pay one out again.
 
+use bincode::serialize;
 use influx_db_client as influxdb;
 use metrics;
+use serde_json;
 use signature::Signature;
-use signature::{Keypair, Pubkey};
+use signature::{Keypair, KeypairUtil, Pubkey};
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::io;
 use std::io::{Error, ErrorKind};
 use std::net::{IpAddr, SocketAddr, UdpSocket};
 use std::time::Duration;
 use thin_client::ThinClient;
+use timing::timestamp;
 use transaction::Transaction;
 
 pub const TIME_SLICE: u64 = 60;
@@ -25,6 +33,41 @@ pub enum DroneRequest {
         airdrop_request_amount: u64,
         client_pubkey: Pubkey,
     },
+    RedeemCoupon(AirdropCoupon),
+}
+
+/// A pre-issued airdrop of `tokens` to `pubkey`, signed by the drone's mint
+/// keypair. The coupon can be redeemed once, before `expiry` (ms since the
+/// epoch).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct AirdropCoupon {
+    pub pubkey: Pubkey,
+    pub tokens: u64,
+    pub expiry: u64,
+    pub signature: Signature,
+}
+
+impl AirdropCoupon {
+    pub fn new(issuer: &Keypair, pubkey: Pubkey, tokens: u64, expiry: u64) -> Self {
+        let mut coupon = AirdropCoupon {
+            pubkey,
+            tokens,
+            expiry,
+            signature: Signature::default(),
+        };
+        coupon.signature = issuer.sign_message(&coupon.get_sign_data());
+        coupon
+    }
+
+    fn get_sign_data(&self) -> Vec<u8> {
+        serialize(&(&self.pubkey, self.tokens, self.expiry)).expect("serialize coupon")
+    }
+
+    /// Verify that `issuer` signed this coupon.
+    pub fn verify(&self, issuer: &Pubkey) -> bool {
+        self.signature
+            .verify(issuer.as_ref(), &self.get_sign_data())
+    }
 }
 
 pub struct Drone {
@@ -36,6 +79,10 @@ pub struct Drone {
     pub time_slice: Duration,
     request_cap: u64,
     pub request_current: u64,
+    /// The expiry of each redeemed coupon, by signature, until it passes
+    redeemed_coupons: HashMap<Signature, u64>,
+    /// Where `redeemed_coupons` is saved, if anywhere
+    redeemed_coupons_path: Option<String>,
 }
 
 impl Drone {
@@ -64,7 +111,53 @@ impl Drone {
             time_slice,
             request_cap,
             request_current: 0,
+            redeemed_coupons: HashMap::new(),
+            redeemed_coupons_path: None,
+        }
+    }
+
+    /// Save redeemed coupons to `path` from now on, starting from those saved
+    /// there before, if any. Coupons that have expired since are forgotten.
+    pub fn load_redeemed_coupons(&mut self, path: &str) -> io::Result<()> {
+        match File::open(path) {
+            Ok(file) => {
+                let redeemed: Vec<(Signature, u64)> = serde_json::from_reader(file)?;
+                self.redeemed_coupons = redeemed.into_iter().collect();
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
         }
+        self.redeemed_coupons_path = Some(path.to_string());
+        self.prune_redeemed_coupons(timestamp());
+        info!(
+            "loaded {} redeemed coupons from {}",
+            self.redeemed_coupons.len(),
+            path
+        );
+        Ok(())
+    }
+
+    /// Forget the coupons that expired by `now`, they can't be redeemed anyway.
+    fn prune_redeemed_coupons(&mut self, now: u64) {
+        self.redeemed_coupons.retain(|_, expiry| *expiry > now);
+    }
+
+    /// Write the redeemed coupons to their file, replacing the previous one in
+    /// one step so a crash can't leave it half written.
+    fn save_redeemed_coupons(&self) -> io::Result<()> {
+        if let Some(ref path) = self.redeemed_coupons_path {
+            let redeemed: Vec<(Signature, u64)> = self
+                .redeemed_coupons
+                .iter()
+                .map(|(signature, expiry)| (*signature, *expiry))
+                .collect();
+            let tmp_path = format!("{}.tmp", path);
+            let file = File::create(&tmp_path)?;
+            serde_json::to_writer(&file, &redeemed)?;
+            file.sync_all()?;
+            fs::rename(tmp_path, path)?;
+        }
+        Ok(())
     }
 
     pub fn check_request_limit(&mut self, request_amount: u64) -> bool {
@@ -95,7 +188,25 @@ impl Drone {
         }
     }
 
+    /// Check that `coupon` was issued by this drone, hasn't expired and
+    /// hasn't been redeemed yet.
+    pub fn check_coupon(&self, coupon: &AirdropCoupon) -> Result<(), io::Error> {
+        if !coupon.verify(&self.mint_keypair.pubkey()) {
+            return Err(Error::new(ErrorKind::Other, "invalid coupon signature"));
+        }
+        if coupon.expiry <= timestamp() {
+            return Err(Error::new(ErrorKind::Other, "coupon expired"));
+        }
+        if self.redeemed_coupons.contains_key(&coupon.signature) {
+            return Err(Error::new(ErrorKind::Other, "coupon already redeemed"));
+        }
+        Ok(())
+    }
+
     pub fn send_airdrop(&mut self, req: DroneRequest) -> Result<Signature, io::Error> {
+        if let DroneRequest::RedeemCoupon(coupon) = req {
+            return self.redeem_coupon(&coupon);
+        }
         let request_amount: u64;
         let requests_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
         let transactions_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
@@ -125,6 +236,7 @@ impl Drone {
                     last_id,
                 )
             }
+            DroneRequest::RedeemCoupon(_) => unreachable!(),
         };
         if self.check_request_limit(request_amount) {
             self.request_current += request_amount;
@@ -146,6 +258,58 @@ impl Drone {
             Err(Error::new(ErrorKind::Other, "token limit reached"))
         }
     }
+
+    /// Coupons were authorized by the operator when they were issued, so
+    /// they don't count against the request cap. A coupon is saved as redeemed
+    /// before its airdrop is sent, so a crash can't let it be paid twice.
+    fn redeem_coupon(&mut self, coupon: &AirdropCoupon) -> Result<Signature, io::Error> {
+        self.check_coupon(coupon)?;
+        self.prune_redeemed_coupons(timestamp());
+        self.redeemed_coupons
+            .insert(coupon.signature, coupon.expiry);
+        if let Err(e) = self.save_redeemed_coupons() {
+            self.redeemed_coupons.remove(&coupon.signature);
+            return Err(e);
+        }
+        info!(
+            "Redeeming coupon for {} tokens to {:?}",
+            coupon.tokens, coupon.pubkey
+        );
+        let requests_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let transactions_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let mut client = ThinClient::new(
+            self.requests_addr,
+            requests_socket,
+            self.transactions_addr,
+            transactions_socket,
+        );
+        let last_id = client.get_last_id();
+        let tx = Transaction::new(
+            &self.mint_keypair,
+            coupon.pubkey,
+            coupon.tokens as i64,
+            last_id,
+        );
+        let signature = match client.transfer_signed(&tx) {
+            Ok(signature) => signature,
+            Err(e) => {
+                // nothing was paid, so the coupon can be tried again
+                self.redeemed_coupons.remove(&coupon.signature);
+                self.save_redeemed_coupons()?;
+                return Err(e);
+            }
+        };
+        metrics::submit(
+            influxdb::Point::new("drone")
+                .add_tag("op", influxdb::Value::String("coupon".to_string()))
+                .add_field(
+                    "request_amount",
+                    influxdb::Value::Integer(coupon.tokens as i64),
+                )
+                .to_owned(),
+        );
+        Ok(signature)
+    }
 }
 
 impl Drop for Drone {
@@ -158,19 +322,21 @@ impl Drop for Drone {
 mod tests {
     use bank::Bank;
     use crdt::{get_ip_addr, TestNode};
-    use drone::{Drone, DroneRequest, REQUEST_CAP, TIME_SLICE};
+    use drone::{AirdropCoupon, Drone, DroneRequest, REQUEST_CAP, TIME_SLICE};
     use fullnode::Fullnode;
+    use identity_lease::IdentityLease;
+    use ledger::StoragePaths;
     use logger;
     use mint::Mint;
-    use service::Service;
     use signature::{Keypair, KeypairUtil};
-    use std::fs::remove_dir_all;
+    use std::fs::{remove_dir_all, remove_file};
     use std::net::{SocketAddr, UdpSocket};
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::atomic::AtomicBool;
     use std::sync::Arc;
     use std::thread::sleep;
     use std::time::Duration;
     use thin_client::ThinClient;
+    use timing::timestamp;
 
     #[test]
     fn test_check_request_limit() {
@@ -259,6 +425,94 @@ mod tests {
         assert_eq!(drone.request_cap, REQUEST_CAP);
     }
 
+    #[test]
+    fn test_check_coupon() {
+        let mint = Mint::new(1);
+        let issuer = mint.keypair();
+        let addr = "0.0.0.0:9900".parse().unwrap();
+        let transactions_addr = "0.0.0.0:0".parse().unwrap();
+        let requests_addr = "0.0.0.0:0".parse().unwrap();
+        let mut drone = Drone::new(
+            mint.keypair(),
+            addr,
+            transactions_addr,
+            requests_addr,
+            None,
+            None,
+        );
+        let pubkey = Keypair::new().pubkey();
+
+        let coupon = AirdropCoupon::new(&issuer, pubkey, 50, timestamp() + 60_000);
+        assert!(coupon.verify(&issuer.pubkey()));
+        assert!(drone.check_coupon(&coupon).is_ok());
+
+        let mut forged = coupon;
+        forged.tokens = 5_000;
+        assert!(!forged.verify(&issuer.pubkey()));
+        assert!(drone.check_coupon(&forged).is_err());
+
+        let stranger = AirdropCoupon::new(&Keypair::new(), pubkey, 50, timestamp() + 60_000);
+        assert!(drone.check_coupon(&stranger).is_err());
+
+        let expired = AirdropCoupon::new(&issuer, pubkey, 50, timestamp() - 1);
+        assert!(expired.verify(&issuer.pubkey()));
+        assert!(drone.check_coupon(&expired).is_err());
+
+        drone
+            .redeemed_coupons
+            .insert(coupon.signature, coupon.expiry);
+        assert!(drone.check_coupon(&coupon).is_err());
+    }
+
+    #[test]
+    fn test_redeemed_coupons_survive_restart() {
+        let mint = Mint::new(1);
+        let issuer = mint.keypair();
+        let addr = "0.0.0.0:9900".parse().unwrap();
+        let transactions_addr = "0.0.0.0:0".parse().unwrap();
+        let requests_addr = "0.0.0.0:0".parse().unwrap();
+        let path = tmp_ledger_path("redeemed_coupons");
+        let pubkey = Keypair::new().pubkey();
+        let now = timestamp();
+        let coupon = AirdropCoupon::new(&issuer, pubkey, 50, now + 60_000);
+        let expiring = AirdropCoupon::new(&issuer, pubkey, 50, now + 1_000);
+
+        let mut drone = Drone::new(
+            mint.keypair(),
+            addr,
+            transactions_addr,
+            requests_addr,
+            None,
+            None,
+        );
+        drone.load_redeemed_coupons(&path).unwrap();
+        assert!(drone.redeemed_coupons.is_empty());
+        drone
+            .redeemed_coupons
+            .insert(coupon.signature, coupon.expiry);
+        drone
+            .redeemed_coupons
+            .insert(expiring.signature, expiring.expiry);
+        drone.save_redeemed_coupons().unwrap();
+
+        let mut restarted = Drone::new(
+            mint.keypair(),
+            addr,
+            transactions_addr,
+            requests_addr,
+            None,
+            None,
+        );
+        restarted.load_redeemed_coupons(&path).unwrap();
+        assert!(restarted.check_coupon(&coupon).is_err());
+        assert!(restarted.redeemed_coupons.contains_key(&expiring.signature));
+
+        restarted.prune_redeemed_coupons(now + 1_000);
+        assert!(restarted.redeemed_coupons.contains_key(&coupon.signature));
+        assert!(!restarted.redeemed_coupons.contains_key(&expiring.signature));
+        remove_file(&path).unwrap();
+    }
+
     fn tmp_ledger_path(name: &str) -> String {
         use std::env;
         let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
@@ -287,14 +541,23 @@ mod tests {
 
         let server = Fullnode::new_leader(
             leader_keypair,
+            Arc::new(IdentityLease::default()),
             bank,
             0,
             &[],
             leader,
-            exit.clone(),
-            &ledger_path,
+            exit,
+            &StoragePaths::new(&ledger_path),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
             false,
-        );
+        )
+        .unwrap();
         //TODO: this seems unstable
         sleep(Duration::from_millis(900));
 
@@ -342,8 +605,7 @@ mod tests {
         info!("TPS request balance: {:?}", carlos_balance);
         assert_eq!(carlos_balance.unwrap(), TPS_BATCH);
 
-        exit.store(true, Ordering::Relaxed);
-        server.join().unwrap();
+        server.close().unwrap();
         remove_dir_all(ledger_path).unwrap();
     }
 }
@@ -2,12 +2,18 @@
 //! unique ID that is the hash of the Entry before it, plus the hash of the
 //! transactions within it. Entries cannot be reordered, and its field `num_hashes`
 //! represents an approximate amount of time since the last Entry was created.
-use bincode::{serialize_into, serialized_size};
-use hash::{extend_and_hash, hash, Hash};
+//!
+//! `Entry::new_batch`, `Entry::verify_chain` and the `to_bytes`/`from_bytes`
+//! helpers are the stable interface for tools outside the node, such as
+//! explorers and other clients, that produce or check ledger data.
+use bincode::{deserialize, deserialize_from, serialize, serialize_into, serialized_size};
+use hash::{Hash, PohHash, PohHasher};
+use ledger::{next_entries, Block};
 use packet::{BlobRecycler, SharedBlob, BLOB_DATA_SIZE};
 use rayon::prelude::*;
+use result::Result;
 use signature::Pubkey;
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 use std::net::SocketAddr;
 use transaction::Transaction;
 
@@ -56,7 +62,7 @@ impl Entry {
         has_more: bool,
     ) -> Self {
         let num_hashes = num_hashes + if transactions.is_empty() { 0 } else { 1 };
-        let id = next_hash(start_hash, 0, &transactions);
+        let id = next_hash(PohHash::default().hasher(), start_hash, 0, &transactions);
         let entry = Entry {
             num_hashes,
             id,
@@ -143,6 +149,10 @@ impl Entry {
     /// Verifies self.id is the result of hashing a `start_hash` `self.num_hashes` times.
     /// If the transaction is not a Tick, then hash that as well.
     pub fn verify(&self, start_hash: &Hash) -> bool {
+        self.verify_with(PohHash::default().hasher(), start_hash)
+    }
+
+    fn verify_with(&self, hasher: &PohHasher, start_hash: &Hash) -> bool {
         let tx_plans_verified = self.transactions.par_iter().all(|tx| {
             let r = tx.verify_plan();
             if !r {
@@ -153,7 +163,7 @@ impl Entry {
         if !tx_plans_verified {
             return false;
         }
-        let ref_hash = next_hash(start_hash, self.num_hashes, &self.transactions);
+        let ref_hash = next_hash(hasher, start_hash, self.num_hashes, &self.transactions);
         if self.id != ref_hash {
             warn!(
                 "next_hash is invalid expected: {:?} actual: {:?}",
@@ -163,6 +173,91 @@ impl Entry {
         }
         true
     }
+
+    /// Verifies self is the Entry following `last_id` in the PoH `domain`.
+    pub fn verify_in_domain(&self, last_id: &Hash, domain: &PohDomain) -> bool {
+        self.verify_with(domain.hash.hasher(), &domain.seed(last_id))
+    }
+
+    /// Creates the Entries holding `transactions`, `num_hashes` after
+    /// `start_hash`, with as many transactions in each as fit in a blob.
+    pub fn new_batch(
+        start_hash: &Hash,
+        num_hashes: u64,
+        transactions: Vec<Transaction>,
+    ) -> Vec<Self> {
+        next_entries(start_hash, num_hashes, transactions)
+    }
+
+    /// Verifies each of `entries` follows the one before it, and the first
+    /// follows `start_hash`.
+    pub fn verify_chain(entries: &[Entry], start_hash: &Hash) -> bool {
+        entries.verify(start_hash)
+    }
+
+    /// The Entry as it's carried in a blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serialize(self).unwrap()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        Ok(deserialize(data)?)
+    }
+
+    /// The Entries as they're stored in a ledger's data file, each preceded by
+    /// its length as a u64.
+    pub fn batch_to_bytes(entries: &[Entry]) -> Vec<u8> {
+        let mut data = vec![];
+        for entry in entries {
+            serialize_into(&mut data, &serialized_size(entry).unwrap()).unwrap();
+            serialize_into(&mut data, entry).unwrap();
+        }
+        data
+    }
+
+    pub fn batch_from_bytes(data: &[u8]) -> Result<Vec<Self>> {
+        let mut cursor = Cursor::new(data);
+        let mut entries = vec![];
+        while cursor.position() < data.len() as u64 {
+            let len: u64 = deserialize_from((&mut cursor).take(8))?;
+            entries.push(deserialize_from((&mut cursor).take(len))?);
+        }
+        Ok(entries)
+    }
+}
+
+/// The PoH domain names the chain a recorder is extending. Every Entry after the
+/// genesis entries is hashed from its predecessor's `id` mixed with the domain,
+/// so entries recorded on another network, or on this one before a hard fork
+/// moved it to a new epoch, never verify here even where entry heights coincide.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct PohDomain {
+    /// The `id` of the ledger's first entry.
+    pub genesis_id: Hash,
+
+    /// Bumped by each hard fork of the chain.
+    pub epoch: u64,
+
+    /// The hash function the chain is computed with.
+    #[serde(default)]
+    pub hash: PohHash,
+}
+
+impl PohDomain {
+    pub fn new(genesis_id: Hash, epoch: u64) -> Self {
+        PohDomain {
+            genesis_id,
+            epoch,
+            hash: PohHash::default(),
+        }
+    }
+
+    /// The hash the PoH of the Entry following `last_id` starts from.
+    pub fn seed(&self, last_id: &Hash) -> Hash {
+        let mut data = self.genesis_id.as_ref().to_vec();
+        data.extend_from_slice(&serialize(&self.epoch).unwrap());
+        self.hash.hasher().extend_and_hash(last_id, &data)
+    }
 }
 
 fn add_transaction_data(hash_data: &mut Vec<u8>, tx: &Transaction) {
@@ -174,10 +269,15 @@ fn add_transaction_data(hash_data: &mut Vec<u8>, tx: &Transaction) {
 /// a signature, the final hash will be a hash of both the previous ID and
 /// the signature.  If num_hashes is zero and there's no transaction data,
 ///  start_hash is returned.
-fn next_hash(start_hash: &Hash, num_hashes: u64, transactions: &[Transaction]) -> Hash {
+fn next_hash(
+    hasher: &PohHasher,
+    start_hash: &Hash,
+    num_hashes: u64,
+    transactions: &[Transaction],
+) -> Hash {
     let mut id = *start_hash;
     for _ in 1..num_hashes {
-        id = hash(&id.as_ref());
+        id = hasher.hash(&id.as_ref());
     }
 
     // Hash all the transaction data
@@ -187,9 +287,9 @@ fn next_hash(start_hash: &Hash, num_hashes: u64, transactions: &[Transaction]) -
     }
 
     if !hash_data.is_empty() {
-        extend_and_hash(&id, &hash_data)
+        hasher.extend_and_hash(&id, &hash_data)
     } else if num_hashes != 0 {
-        hash(&id.as_ref())
+        hasher.hash(&id.as_ref())
     } else {
         id
     }
@@ -200,7 +300,12 @@ pub fn next_entry(start_hash: &Hash, num_hashes: u64, transactions: Vec<Transact
     assert!(num_hashes > 0 || transactions.is_empty());
     Entry {
         num_hashes,
-        id: next_hash(start_hash, num_hashes, &transactions),
+        id: next_hash(
+            PohHash::default().hasher(),
+            start_hash,
+            num_hashes,
+            &transactions,
+        ),
         transactions,
         has_more: false,
     }
@@ -225,6 +330,33 @@ mod tests {
         assert!(!next_entry(&zero, 1, vec![]).verify(&one)); // inductive step, bad
     }
 
+    #[test]
+    fn test_entry_verify_in_domain() {
+        let zero = Hash::default();
+        let one = hash(&zero.as_ref());
+        let domain = PohDomain::new(one, 0);
+        let tick = next_entry(&domain.seed(&zero), 1, vec![]);
+        assert!(tick.verify_in_domain(&zero, &domain));
+
+        // the same heights on another network, or before a fork, don't verify
+        assert!(!tick.verify_in_domain(&zero, &PohDomain::new(zero, 0)));
+        assert!(!tick.verify_in_domain(&zero, &PohDomain::new(one, 1)));
+        assert!(!next_entry(&zero, 1, vec![]).verify_in_domain(&zero, &domain));
+    }
+
+    #[test]
+    fn test_entry_verify_with_simd_hasher() {
+        let zero = Hash::default();
+        let one = hash(&zero.as_ref());
+        let domain = PohDomain::new(one, 0);
+        let simd_domain = PohDomain {
+            hash: PohHash::Sha256Simd,
+            ..domain
+        };
+        let tick = next_entry(&domain.seed(&zero), 3, vec![]);
+        assert!(tick.verify_in_domain(&zero, &simd_domain));
+    }
+
     #[test]
     fn test_transaction_reorder_attack() {
         let zero = Hash::default();
@@ -274,7 +406,49 @@ mod tests {
         let tx0 = Transaction::new_timestamp(&keypair, Utc::now(), zero);
         let entry0 = next_entry(&zero, 1, vec![tx0.clone()]);
         assert_eq!(entry0.num_hashes, 1);
-        assert_eq!(entry0.id, next_hash(&zero, 1, &vec![tx0]));
+        assert_eq!(
+            entry0.id,
+            next_hash(PohHash::default().hasher(), &zero, 1, &vec![tx0])
+        );
+    }
+
+    #[test]
+    fn test_new_batch_verify_chain() {
+        let zero = Hash::default();
+        let keypair = Keypair::new();
+        let tx = Transaction::new(&keypair, keypair.pubkey(), 1, zero);
+        let transactions = vec![tx; 1000];
+
+        let entries = Entry::new_batch(&zero, 1, transactions.clone());
+        assert!(entries.len() > 1);
+        let batched: Vec<_> = entries
+            .iter()
+            .flat_map(|entry| entry.transactions.clone())
+            .collect();
+        assert_eq!(batched, transactions);
+        assert!(Entry::verify_chain(&entries, &zero));
+        assert!(!Entry::verify_chain(&entries, &hash(&zero.as_ref())));
+
+        let mut reordered = entries.clone();
+        reordered.swap(0, 1);
+        assert!(!Entry::verify_chain(&reordered, &zero));
+    }
+
+    #[test]
+    fn test_entry_bytes() {
+        let zero = Hash::default();
+        let keypair = Keypair::new();
+        let tx = Transaction::new(&keypair, keypair.pubkey(), 1, zero);
+        let entries = vec![next_entry(&zero, 1, vec![tx]), next_entry(&zero, 1, vec![])];
+
+        let entry = &entries[0];
+        assert_eq!(Entry::from_bytes(&entry.to_bytes()).unwrap(), *entry);
+        assert!(Entry::from_bytes(&entry.to_bytes()[..8]).is_err());
+
+        let data = Entry::batch_to_bytes(&entries);
+        assert_eq!(Entry::batch_from_bytes(&data).unwrap(), entries);
+        assert_eq!(Entry::batch_from_bytes(&[]).unwrap(), vec![]);
+        assert!(Entry::batch_from_bytes(&data[..data.len() - 1]).is_err());
     }
 
     #[test]
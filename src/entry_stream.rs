@@ -0,0 +1,348 @@
+//! The `entry_stream` module publishes the entries a validator replicates over
+//! TCP, so that read replicas can follow the ledger without joining the network.
+//! A subscriber first sends the entry height it has already processed. It is then
+//! sent the rest of the ledger, followed by every batch of entries the validator
+//! replicates. Each message on the stream is a bincode-serialized `Vec<Entry>`.
+//!
+//! Each new subscriber is caught up from the ledger on a thread of its own, and
+//! only then handed to the thread publishing the batches, so a slow one can't
+//! hold up the others. At most `MAX_SUBSCRIBERS` are served at once.
+
+use bincode::{deserialize_from, serialize};
+use counter::Counter;
+use entry::Entry;
+use ledger::read_ledger;
+use log::Level;
+use result::Result;
+use service::Service;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, Builder, JoinHandle};
+use std::time::Duration;
+
+pub const ENTRY_STREAM_PORT: u16 = 8901;
+
+/// Entries to publish, along with the entry height of the first one.
+pub type EntryStreamSender = Sender<(u64, Vec<Entry>)>;
+pub type EntryStreamReceiver = Receiver<(u64, Vec<Entry>)>;
+
+/// Number of ledger entries sent per message while a subscriber catches up.
+const BACKLOG_CHUNK: usize = 256;
+
+/// A subscriber that doesn't keep up within this timeout is dropped.
+const SUBSCRIBER_TIMEOUT_MS: u64 = 1000;
+
+/// The most subscribers served at once, counting those still catching up.
+pub const MAX_SUBSCRIBERS: usize = 16;
+
+/// Send `entries` as one message on the stream.
+pub fn write_entries<W: Write>(writer: &mut W, entries: &[Entry]) -> Result<()> {
+    writer.write_all(&serialize(&entries)?)?;
+    Ok(())
+}
+
+/// Receive one message from the stream.
+pub fn read_entries<R: Read>(reader: &mut R) -> Result<Vec<Entry>> {
+    Ok(deserialize_from(reader)?)
+}
+
+struct Subscriber {
+    stream: TcpStream,
+    /// Height of the next entry the subscriber needs.
+    entry_height: u64,
+}
+
+impl Subscriber {
+    /// Send the entries of a batch starting at `start` that the subscriber
+    /// hasn't seen yet. Batches can overlap the ledger backlog it was sent.
+    fn send(&mut self, start: u64, entries: &[Entry]) -> Result<()> {
+        let end = start + entries.len() as u64;
+        if self.entry_height < start {
+            return Err(io::Error::new(ErrorKind::Other, "gap in the entry stream").into());
+        }
+        if self.entry_height >= end {
+            return Ok(());
+        }
+        let seen = (self.entry_height - start) as usize;
+        write_entries(&mut self.stream, &entries[seen..])?;
+        self.entry_height = end;
+        Ok(())
+    }
+}
+
+/// Where subscribers that have caught up go, and how many are connected.
+#[derive(Clone)]
+struct Handoff {
+    caught_up_sender: Sender<Subscriber>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl Handoff {
+    /// Run `catch_up` on a thread of its own and pass the subscriber it returns
+    /// to the publisher, or drop the connection if it fails.
+    fn spawn<F>(&self, catch_up: F)
+    where
+        F: FnOnce() -> Result<Subscriber> + Send + 'static,
+    {
+        let handoff = self.clone();
+        let spawned = Builder::new()
+            .name("solana-entry-stream-catch-up".to_string())
+            .spawn(move || match catch_up() {
+                Ok(subscriber) => {
+                    // fails only once the service has exited
+                    let _ = handoff.caught_up_sender.send(subscriber);
+                }
+                Err(e) => {
+                    warn!("entry stream subscriber failed: {:?}", e);
+                    handoff.drop_connection();
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("no thread to catch up an entry stream subscriber: {:?}", e);
+            self.drop_connection();
+        }
+    }
+
+    fn drop_connection(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct EntryStreamService {
+    thread_hdl: JoinHandle<()>,
+}
+
+impl EntryStreamService {
+    /// Accept subscribers on `listener` and publish the entries from
+    /// `entry_receiver` to them, catching new subscribers up from the ledger
+    /// at `ledger_path`.
+    pub fn new(
+        listener: TcpListener,
+        ledger_path: &str,
+        entry_receiver: EntryStreamReceiver,
+        exit: Arc<AtomicBool>,
+    ) -> Self {
+        listener
+            .set_nonblocking(true)
+            .expect("set_nonblocking on the entry stream listener");
+        let ledger_path = ledger_path.to_string();
+        let thread_hdl = Builder::new()
+            .name("solana-entry-stream".to_string())
+            .spawn(move || {
+                let (caught_up_sender, caught_up_receiver) = channel();
+                let handoff = Handoff {
+                    caught_up_sender,
+                    connections: Arc::new(AtomicUsize::new(0)),
+                };
+                let mut subscribers = Vec::new();
+                // the entry height the published batches have reached
+                let mut published = 0;
+                loop {
+                    if exit.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    Self::accept(&listener, &ledger_path, &handoff);
+                    match entry_receiver.recv_timeout(Duration::from_millis(100)) {
+                        Ok((start, entries)) => {
+                            published = start + entries.len() as u64;
+                            Self::publish(&mut subscribers, start, &entries, &handoff);
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                        Err(RecvTimeoutError::Timeout) => (),
+                    }
+                    for subscriber in caught_up_receiver.try_iter() {
+                        Self::join_publisher(
+                            subscriber,
+                            published,
+                            &ledger_path,
+                            &mut subscribers,
+                            &handoff,
+                        );
+                    }
+                }
+            })
+            .unwrap();
+        EntryStreamService { thread_hdl }
+    }
+
+    /// Read the subscriber's entry height and catch it up from there.
+    fn subscribe(mut stream: TcpStream, ledger_path: &str) -> Result<Subscriber> {
+        let timeout = Some(Duration::from_millis(SUBSCRIBER_TIMEOUT_MS));
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        let entry_height = deserialize_from(&mut stream)?;
+        let mut subscriber = Subscriber {
+            stream,
+            entry_height,
+        };
+        Self::catch_up(&mut subscriber, ledger_path)?;
+        Ok(subscriber)
+    }
+
+    /// Send `subscriber` the ledger from its entry height on.
+    fn catch_up(subscriber: &mut Subscriber, ledger_path: &str) -> Result<()> {
+        let mut backlog = Vec::with_capacity(BACKLOG_CHUNK);
+        let skip = subscriber.entry_height as usize;
+        for entry in read_ledger(ledger_path, false)?.skip(skip) {
+            backlog.push(entry?);
+            if backlog.len() == BACKLOG_CHUNK {
+                let start = subscriber.entry_height;
+                subscriber.send(start, &backlog)?;
+                backlog.clear();
+            }
+        }
+        let start = subscriber.entry_height;
+        subscriber.send(start, &backlog)?;
+        info!(
+            "entry stream subscriber caught up to entry height {}",
+            subscriber.entry_height
+        );
+        Ok(())
+    }
+
+    fn accept(listener: &TcpListener, ledger_path: &str, handoff: &Handoff) {
+        while let Ok((stream, addr)) = listener.accept() {
+            if handoff.connections.load(Ordering::Relaxed) >= MAX_SUBSCRIBERS {
+                inc_new_counter_info!("entry_stream-refuse", 1);
+                warn!("refusing entry stream subscriber {}, too many", addr);
+                continue;
+            }
+            handoff.connections.fetch_add(1, Ordering::Relaxed);
+            inc_new_counter_info!("entry_stream-subscribe", 1);
+            let ledger_path = ledger_path.to_string();
+            handoff.spawn(move || Self::subscribe(stream, &ledger_path));
+        }
+    }
+
+    /// Start publishing to a `subscriber` that has caught up, unless batches
+    /// up to `published` went out while it did. Those are in the ledger by
+    /// now, so it catches up again.
+    fn join_publisher(
+        mut subscriber: Subscriber,
+        published: u64,
+        ledger_path: &str,
+        subscribers: &mut Vec<Subscriber>,
+        handoff: &Handoff,
+    ) {
+        if subscriber.entry_height >= published {
+            subscribers.push(subscriber);
+            return;
+        }
+        let ledger_path = ledger_path.to_string();
+        handoff.spawn(move || {
+            Self::catch_up(&mut subscriber, &ledger_path)?;
+            Ok(subscriber)
+        });
+    }
+
+    fn publish(
+        subscribers: &mut Vec<Subscriber>,
+        start: u64,
+        entries: &[Entry],
+        handoff: &Handoff,
+    ) {
+        let mut live = Vec::with_capacity(subscribers.len());
+        for mut subscriber in subscribers.drain(..) {
+            match subscriber.send(start, entries) {
+                Ok(()) => live.push(subscriber),
+                Err(e) => {
+                    inc_new_counter_info!("entry_stream-drop", 1);
+                    warn!("dropping entry stream subscriber: {:?}", e);
+                    handoff.drop_connection();
+                }
+            }
+        }
+        *subscribers = live;
+        inc_new_counter_info!("entry_stream-entries", entries.len());
+    }
+}
+
+impl Service for EntryStreamService {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        vec![self.thread_hdl]
+    }
+
+    fn join(self) -> thread::Result<()> {
+        self.thread_hdl.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::serialize;
+    use entry::{next_entry, Entry};
+    use entry_stream::{read_entries, EntryStreamService};
+    use hash::Hash;
+    use ledger::FileLedgerWriter;
+    use service::Service;
+    use signature::{Keypair, KeypairUtil};
+    use std::env;
+    use std::fs::remove_dir_all;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Read messages from `stream` until `count` entries have arrived, however
+    /// they are split up.
+    fn read_count(stream: &mut TcpStream, count: usize) -> Vec<Entry> {
+        let mut entries = vec![];
+        while entries.len() < count {
+            entries.extend(read_entries(stream).unwrap());
+        }
+        entries
+    }
+
+    #[test]
+    fn test_entry_stream_backlog_and_live() {
+        let ledger_path = format!(
+            "{}/tmp-ledger-entry_stream-{}",
+            env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string()),
+            Keypair::new().pubkey()
+        );
+        let mut entries: Vec<Entry> = vec![];
+        let mut last_id = Hash::default();
+        for i in 0..6 {
+            entries.push(next_entry(&last_id, i + 1, vec![]));
+            last_id = entries[entries.len() - 1].id;
+        }
+        let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
+        writer.write_entries(entries[..4].to_vec()).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let exit = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = channel();
+        let service = EntryStreamService::new(listener, &ledger_path, receiver, exit.clone());
+
+        // a subscriber that never sends its entry height doesn't hold up others
+        let _silent = TcpStream::connect(addr).unwrap();
+
+        // the subscriber already has the first entry, and gets the rest of the ledger
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        stream.write_all(&serialize(&1u64).unwrap()).unwrap();
+        assert_eq!(read_count(&mut stream, 3), entries[1..4].to_vec());
+
+        // batches are written to the ledger before they are published, and
+        // whether the subscriber gets them from one or the other, it gets each
+        // entry once
+        writer.write_entries(entries[4..5].to_vec()).unwrap();
+        sender.send((4, entries[4..5].to_vec())).unwrap();
+        writer.write_entries(entries[5..].to_vec()).unwrap();
+        sender.send((5, entries[5..].to_vec())).unwrap();
+        assert_eq!(read_count(&mut stream, 2), entries[4..].to_vec());
+
+        exit.store(true, Ordering::Relaxed);
+        service.join().unwrap();
+        remove_dir_all(ledger_path).unwrap();
+    }
+}
@@ -291,20 +291,23 @@ pub fn generate_coding(
             for i in 0..max_data_size {
                 coding_wl.data[i] = 0;
             }
-            // copy index and id from the data blob
+            // copy index, slot and id from the data blob
             if let Some(data) = &window[n].data {
                 let data_rl = data.read().unwrap();
 
                 let index = data_rl.get_index().unwrap();
+                let slot = data_rl.get_slot().unwrap();
                 let id = data_rl.get_id().unwrap();
 
                 trace!(
-                    "{:x} copying index {} id {:?} from data to coding",
+                    "{:x} copying index {} slot {} id {:?} from data to coding",
                     debug_id,
                     index,
+                    slot,
                     id
                 );
                 coding_wl.set_index(index).unwrap();
+                coding_wl.set_slot(slot).unwrap();
                 coding_wl.set_id(id).unwrap();
             }
             coding_wl.set_size(max_data_size);
@@ -772,7 +775,7 @@ mod test {
             "127.0.0.1:1237".parse().unwrap(),
             "127.0.0.1:1238".parse().unwrap(),
         );
-        assert!(index_blobs(&d, &blobs, &mut (offset as u64)).is_ok());
+        assert!(index_blobs(&d, &blobs, &mut (offset as u64), &mut 0).is_ok());
         for b in blobs {
             let idx = b.read().unwrap().get_index().unwrap() as usize % WINDOW_SIZE;
 
@@ -0,0 +1,97 @@
+//! The `feature` module names the consensus-affecting changes that stay off
+//! until the cluster activates them, so a new rule can ship in a release
+//! before every node has upgraded. A feature the genesis block activates is
+//! active from the start. Later, the mint's key activates one with an
+//! `ActivateFeature` instruction, and it takes effect at the next rent epoch
+//! boundary, so that every node switches over after the same entry.
+
+use signature::Pubkey;
+use std::collections::HashMap;
+
+/// A change to how the bank processes transactions.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Transactions whose fee is paid by a key other than the signer's.
+    FeePayer,
+}
+
+/// Every feature, in the order they were added.
+pub const FEATURES: &[Feature] = &[Feature::FeePayer];
+
+impl Feature {
+    /// The name the feature goes by on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::FeePayer => "fee-payer",
+        }
+    }
+
+    /// The feature called `name`, if there is one.
+    pub fn from_name(name: &str) -> Option<Feature> {
+        FEATURES
+            .iter()
+            .cloned()
+            .find(|feature| feature.name() == name)
+    }
+}
+
+/// How far a feature's activation has got.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureStatus {
+    /// Activated during the current epoch, and active from the next.
+    Pending,
+    Active,
+}
+
+/// The features activated so far, and the key allowed to activate more.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Features {
+    /// The mint's key, once the bank has applied the genesis block.
+    pub authority: Option<Pubkey>,
+    /// The status of each feature that was activated. Those missing are off.
+    pub statuses: HashMap<Feature, FeatureStatus>,
+}
+
+impl Features {
+    pub fn is_active(&self, feature: Feature) -> bool {
+        self.statuses.get(&feature) == Some(&FeatureStatus::Active)
+    }
+
+    /// The features waiting for the current epoch to end.
+    pub fn pending(&self) -> Vec<Feature> {
+        self.statuses
+            .iter()
+            .filter(|(_, status)| **status == FeatureStatus::Pending)
+            .map(|(feature, _)| *feature)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_names() {
+        for feature in FEATURES {
+            assert_eq!(Feature::from_name(feature.name()), Some(*feature));
+        }
+        assert_eq!(Feature::from_name("no-such-feature"), None);
+    }
+
+    #[test]
+    fn test_pending_features() {
+        let mut features = Features::default();
+        assert!(!features.is_active(Feature::FeePayer));
+        features
+            .statuses
+            .insert(Feature::FeePayer, FeatureStatus::Pending);
+        assert!(!features.is_active(Feature::FeePayer));
+        assert_eq!(features.pending(), vec![Feature::FeePayer]);
+        features
+            .statuses
+            .insert(Feature::FeePayer, FeatureStatus::Active);
+        assert!(features.is_active(Feature::FeePayer));
+        assert!(features.pending().is_empty());
+    }
+}
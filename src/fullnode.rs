@@ -1,18 +1,32 @@
 //! The `fullnode` module hosts all the fullnode microservices.
 
+use accounts_db::AccountsDb;
 use bank::Bank;
-use broadcast_stage::BroadcastStage;
+use broadcast_stage::{BroadcastPacing, BroadcastStage};
+use cpu_time::CpuTimeService;
 use crdt::{Crdt, NodeInfo, TestNode};
 use entry::Entry;
-use ledger::read_ledger;
+use entry_stream::{EntryStreamService, ENTRY_STREAM_PORT};
+use hash::PohHash;
+use identity_lease::IdentityLease;
+use ipc_plugin::IpcPluginService;
+use ledger::{read_ledger, StoragePaths};
 use ncp::Ncp;
 use packet::BlobRecycler;
-use rpc::{JsonRpcService, RPC_PORT};
+use peer_cache::{insert_cached_peers, peer_cache_path, PeerCacheService};
+use result::{self, StartupError};
+use rpc::{AdminRpcService, JsonRpcConfig, JsonRpcService, RPC_PORT};
+use rpc_pubsub::PubSubService;
 use rpu::Rpu;
 use service::Service;
-use signature::{Keypair, KeypairUtil};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use signature::{Keypair, KeypairUtil, Pubkey};
+use sigverify_stage::SigVerifyConfig;
+use socket_stats::SocketStatsService;
+use std::cell::Cell;
+use std::fs::{read_dir, remove_file};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
 use std::sync::{Arc, RwLock};
 use std::thread::{JoinHandle, Result};
 use tpu::Tpu;
@@ -22,6 +36,9 @@ use window;
 
 pub struct Fullnode {
     exit: Arc<AtomicBool>,
+    rpc_service: JsonRpcService,
+    admin_rpc_service: AdminRpcService,
+    pubsub_service: PubSubService,
     thread_hdls: Vec<JoinHandle<()>>,
 }
 
@@ -30,6 +47,51 @@ pub struct Fullnode {
 pub struct Config {
     pub node_info: NodeInfo,
     pkcs8: Vec<u8>,
+    /// Overrides the default storage layout under the ledger directory
+    #[serde(default)]
+    pub storage: Option<StoragePaths>,
+    /// Limits how fast the node broadcasts while it's the leader
+    #[serde(default)]
+    pub broadcast_pacing: Option<BroadcastPacing>,
+    /// Spreads broadcasting over this many sockets, each sent from on its own thread
+    #[serde(default)]
+    pub broadcast_send_threads: Option<usize>,
+    /// Overrides the signature verification threads sized by the core count
+    #[serde(default)]
+    pub sigverify: Option<SigVerifyConfig>,
+    /// Overrides the RPC server's threads and batch limit
+    #[serde(default)]
+    pub rpc: Option<JsonRpcConfig>,
+    /// The identity the node votes for, if not its own key. Only its pubkey is
+    /// kept here, the identity's keypair signs the authorization elsewhere.
+    #[serde(default)]
+    pub vote_identity: Option<Pubkey>,
+    /// Overrides the hash function implementation PoH is computed with
+    #[serde(default)]
+    pub poh_hash: Option<PohHash>,
+    /// Streams committed accounts and entries to plugins that connect to a
+    /// unix socket at this path
+    #[serde(default)]
+    pub plugin_socket: Option<String>,
+    /// Where a validator keeping a ledger publishes it to read replicas, if not
+    /// `ENTRY_STREAM_PORT` on every interface
+    #[serde(default)]
+    pub entry_stream_addr: Option<SocketAddr>,
+    /// Where the admin RPC that hands the identity to a standby listens, if
+    /// not `ADMIN_RPC_PORT` on localhost
+    #[serde(default)]
+    pub admin_rpc_addr: Option<SocketAddr>,
+    /// Where clients subscribe to accounts and signatures, if not
+    /// `PUBSUB_PORT` on every interface
+    #[serde(default)]
+    pub pubsub_addr: Option<SocketAddr>,
+}
+
+/// Any free port on the loopback interface, where a node serves what it wasn't
+/// given an address for, so that nodes sharing a host, as in tests, don't
+/// collide.
+fn any_local_addr() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0)
 }
 
 /// Structure to be replicated by the network
@@ -39,85 +101,202 @@ impl Config {
             Keypair::from_pkcs8(Input::from(&pkcs8)).expect("from_pkcs8 in fullnode::Config new");
         let pubkey = keypair.pubkey();
         let node_info = NodeInfo::new_leader_with_pubkey(pubkey, bind_addr);
-        Config { node_info, pkcs8 }
+        Config {
+            node_info,
+            pkcs8,
+            storage: None,
+            broadcast_pacing: None,
+            broadcast_send_threads: None,
+            sigverify: None,
+            rpc: None,
+            vote_identity: None,
+            poh_hash: None,
+            plugin_socket: None,
+            entry_stream_addr: None,
+            admin_rpc_addr: None,
+            pubsub_addr: None,
+        }
     }
-    pub fn keypair(&self) -> Keypair {
-        Keypair::from_pkcs8(Input::from(&self.pkcs8))
-            .expect("from_pkcs8 in fullnode::Config keypair")
+    pub fn keypair(&self) -> result::Result<Keypair> {
+        let keypair =
+            Keypair::from_pkcs8(Input::from(&self.pkcs8)).map_err(|_| StartupError::BadKeypair)?;
+        Ok(keypair)
     }
 }
 
 impl Fullnode {
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     fn new_internal(
         mut node: TestNode,
         leader: bool,
-        ledger_path: &str,
+        storage: &StoragePaths,
         keypair: Keypair,
+        lease: Arc<IdentityLease>,
         network_entry_for_validator: Option<SocketAddr>,
+        broadcast_pacing: Option<BroadcastPacing>,
+        broadcast_send_threads: Option<usize>,
+        sigverify_config: Option<SigVerifyConfig>,
+        rpc_config: Option<JsonRpcConfig>,
+        vote_identity: Option<Pubkey>,
+        poh_hash: Option<PohHash>,
+        plugin_socket: Option<String>,
+        entry_stream_addr: Option<SocketAddr>,
+        admin_rpc_addr: Option<SocketAddr>,
+        pubsub_addr: Option<SocketAddr>,
+        snapshot: Option<String>,
         sigverify_disabled: bool,
-    ) -> Self {
+    ) -> result::Result<Self> {
+        storage.create_dirs()?;
+
         info!("creating bank...");
-        let bank = Bank::new_default(leader);
+        let mut snapshot_at = None;
+        let mut bank = match snapshot {
+            Some(path) => {
+                info!("loading bank snapshot from {}...", path);
+                let (bank, entry_height, last_entry_id) = Bank::from_snapshot(&path)
+                    .map_err(|err| StartupError::BadLedger(path.clone(), err.to_string()))?;
+                // the snapshot replaces whatever accounts were saved before
+                for file in read_dir(&storage.accounts)? {
+                    remove_file(file?.path())?;
+                }
+                snapshot_at = Some((entry_height, last_entry_id));
+                bank
+            }
+            None => Bank::default(),
+        };
+        bank.is_leader = leader;
+        if let Some(poh_hash) = poh_hash {
+            bank.set_poh_hash(poh_hash);
+        }
 
-        let entries = read_ledger(ledger_path, true).expect("opening ledger");
+        let bad_ledger = |reason: String| StartupError::BadLedger(storage.ledger.clone(), reason);
+        let entries =
+            read_ledger(&storage.ledger, true).map_err(|err| bad_ledger(err.to_string()))?;
+
+        // the ledger ends at the first entry that can't be parsed, which fails
+        // the start once the entries before it are processed
+        let bad_entry = Cell::new(None);
+        let entries = entries
+            .map(|entry| {
+                if let Err(ref err) = entry {
+                    bad_entry.set(Some(err.to_string()));
+                }
+                entry
+            })
+            .take_while(|entry| entry.is_ok())
+            .filter_map(|entry| entry.ok());
 
-        let entries = entries.map(|e| e.expect("failed to parse entry"));
+        let accounts_db = AccountsDb::open(&storage.accounts)?;
+        let saved = bank.use_accounts_db(accounts_db)?;
 
         info!("processing ledger...");
-        let (entry_height, ledger_tail) = bank.process_ledger(entries).expect("process_ledger");
+        let processed = match snapshot_at.or(saved) {
+            Some((saved_height, last_entry_id)) => {
+                info!("resuming from entry {}...", saved_height);
+                bank.process_ledger_from(entries, saved_height, &last_entry_id)
+            }
+            None => bank.process_ledger(entries),
+        };
+        // a ledger that doesn't extend the one the bank was saved from fails
+        // here too
+        let (entry_height, ledger_tail) =
+            processed.map_err(|err| bad_ledger(format!("{:?}", err)))?;
+        if let Some(reason) = bad_entry.take() {
+            return Err(bad_ledger(reason).into());
+        }
         // entry_height is the network-wide agreed height of the ledger.
         //  initialize it from the input ledger
         info!("processed {} ledger...", entry_height);
 
+        // nothing can be rolled back yet, so the next start can resume from here
+        let last_entry_id = ledger_tail.last().expect("ledger tail").id;
+        bank.save_accounts(entry_height, &last_entry_id)?;
+
         info!("creating networking stack...");
 
-        let local_gossip_addr = node.sockets.gossip.local_addr().unwrap();
-        let local_requests_addr = node.sockets.requests.local_addr().unwrap();
+        let local_gossip_addr = node.sockets.gossip.local_addr()?;
+        let local_requests_addr = node.sockets.requests.local_addr()?;
         info!(
             "starting... local gossip address: {} (advertising {})",
             local_gossip_addr, node.data.contact_info.ncp
         );
         let requests_addr = node.data.contact_info.rpu;
         let exit = Arc::new(AtomicBool::new(false));
-        if !leader {
+        let plugin_service = match plugin_socket {
+            Some(socket_path) => {
+                let (service, observer) = IpcPluginService::new(&socket_path, exit.clone())?;
+                bank.add_observer(observer);
+                info!("plugins connect to {}", socket_path);
+                Some(service)
+            }
+            None => None,
+        };
+        let server = if !leader {
             let testnet_addr = network_entry_for_validator.expect("validator requires entry");
 
             let network_entry_point = NodeInfo::new_entry_point(testnet_addr);
             let server = Self::new_validator(
                 keypair,
+                lease,
                 bank,
                 entry_height,
                 &ledger_tail,
                 node,
                 &network_entry_point,
                 exit.clone(),
-                Some(ledger_path),
+                Some(storage),
+                entry_stream_addr,
+                rpc_config,
+                vote_identity,
+                admin_rpc_addr,
+                pubsub_addr,
                 sigverify_disabled,
             );
-            info!(
-                "validator ready... local request address: {} (advertising {}) connected to: {}",
-                local_requests_addr, requests_addr, testnet_addr
-            );
+            if server.is_ok() {
+                info!(
+                    "validator ready... local request address: {} (advertising {}) connected to: {}",
+                    local_requests_addr, requests_addr, testnet_addr
+                );
+            }
             server
         } else {
             node.data.leader_id = node.data.id;
 
             let server = Self::new_leader(
                 keypair,
+                lease,
                 bank,
                 entry_height,
                 &ledger_tail,
                 node,
                 exit.clone(),
-                ledger_path,
+                storage,
+                broadcast_pacing,
+                broadcast_send_threads,
+                sigverify_config,
+                rpc_config,
+                vote_identity,
+                admin_rpc_addr,
+                pubsub_addr,
                 sigverify_disabled,
             );
-            info!(
-                "leader ready... local request address: {} (advertising {})",
-                local_requests_addr, requests_addr
-            );
+            if server.is_ok() {
+                info!(
+                    "leader ready... local request address: {} (advertising {})",
+                    local_requests_addr, requests_addr
+                );
+            }
             server
+        };
+        if server.is_err() {
+            // stop the services that started before one failed to
+            exit.store(true, Ordering::Relaxed);
         }
+        let mut server = server?;
+        if let Some(plugin_service) = plugin_service {
+            server.thread_hdls.extend(plugin_service.thread_hdls());
+        }
+        Ok(server)
     }
 
     pub fn new(
@@ -126,13 +305,80 @@ impl Fullnode {
         ledger: &str,
         keypair: Keypair,
         network_entry_for_validator: Option<SocketAddr>,
-    ) -> Self {
+    ) -> result::Result<Self> {
+        Self::new_with_storage(
+            node,
+            leader,
+            &StoragePaths::new(ledger),
+            keypair,
+            Arc::new(IdentityLease::default()),
+            network_entry_for_validator,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    /// Like `new`, with the ledger, snapshots and accounts kept at separate paths,
+    /// `lease` deciding whether this node signs with `keypair` or stands by,
+    /// `broadcast_pacing` limiting how fast it broadcasts as the leader,
+    /// `broadcast_send_threads` spreading those broadcasts over sockets,
+    /// `sigverify_config` laying out its signature verification threads,
+    /// `rpc_config` sizing its RPC server, `vote_identity` naming the identity
+    /// it votes for, if not `keypair`'s, `poh_hash` picking how it hashes PoH,
+    /// `plugin_socket` where plugins connect to follow its commits,
+    /// `entry_stream_addr` where it publishes its ledger to read replicas,
+    /// `admin_rpc_addr` where it serves failover requests and `pubsub_addr`
+    /// where it serves subscriptions, each on any free loopback port if `None`,
+    /// and `snapshot` a bank snapshot file to start from instead of replaying
+    /// the ledger up to it. Fails with a `StartupError` if a port is taken or the
+    /// ledger or snapshot is bad.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    pub fn new_with_storage(
+        node: TestNode,
+        leader: bool,
+        storage: &StoragePaths,
+        keypair: Keypair,
+        lease: Arc<IdentityLease>,
+        network_entry_for_validator: Option<SocketAddr>,
+        broadcast_pacing: Option<BroadcastPacing>,
+        broadcast_send_threads: Option<usize>,
+        sigverify_config: Option<SigVerifyConfig>,
+        rpc_config: Option<JsonRpcConfig>,
+        vote_identity: Option<Pubkey>,
+        poh_hash: Option<PohHash>,
+        plugin_socket: Option<String>,
+        entry_stream_addr: Option<SocketAddr>,
+        admin_rpc_addr: Option<SocketAddr>,
+        pubsub_addr: Option<SocketAddr>,
+        snapshot: Option<String>,
+    ) -> result::Result<Self> {
         Self::new_internal(
             node,
             leader,
-            ledger,
+            storage,
             keypair,
+            lease,
             network_entry_for_validator,
+            broadcast_pacing,
+            broadcast_send_threads,
+            sigverify_config,
+            rpc_config,
+            vote_identity,
+            poh_hash,
+            plugin_socket,
+            entry_stream_addr,
+            admin_rpc_addr,
+            pubsub_addr,
+            snapshot,
             false,
         )
     }
@@ -143,17 +389,40 @@ impl Fullnode {
         ledger_path: &str,
         keypair: Keypair,
         network_entry_for_validator: Option<SocketAddr>,
-    ) -> Self {
+    ) -> result::Result<Self> {
         Self::new_internal(
             node,
             leader,
-            ledger_path,
+            &StoragePaths::new(ledger_path),
             keypair,
+            Arc::new(IdentityLease::default()),
             network_entry_for_validator,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
             true,
         )
     }
 
+    /// Serve admin RPC for `lease` at `addr`, or on any free loopback port.
+    fn new_admin_rpc(
+        lease: &Arc<IdentityLease>,
+        addr: Option<SocketAddr>,
+    ) -> result::Result<AdminRpcService> {
+        let addr = addr.unwrap_or_else(any_local_addr);
+        let admin_rpc_service = AdminRpcService::new(lease.clone(), addr)?;
+        info!("admin RPC listening on {}", admin_rpc_service.local_addr());
+        Ok(admin_rpc_service)
+    }
+
     /// Create a server instance acting as a leader.
     ///
     /// ```text
@@ -178,22 +447,33 @@ impl Fullnode {
     ///              |                     |    `------------`
     ///              `---------------------`
     /// ```
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn new_leader(
         keypair: Keypair,
+        lease: Arc<IdentityLease>,
         bank: Bank,
         entry_height: u64,
         ledger_tail: &[Entry],
         node: TestNode,
         exit: Arc<AtomicBool>,
-        ledger_path: &str,
+        storage: &StoragePaths,
+        broadcast_pacing: Option<BroadcastPacing>,
+        broadcast_send_threads: Option<usize>,
+        sigverify_config: Option<SigVerifyConfig>,
+        rpc_config: Option<JsonRpcConfig>,
+        vote_identity: Option<Pubkey>,
+        admin_rpc_addr: Option<SocketAddr>,
+        pubsub_addr: Option<SocketAddr>,
         sigverify_disabled: bool,
-    ) -> Self {
+    ) -> result::Result<Self> {
         let tick_duration = None;
         // TODO: To light up PoH, uncomment the following line:
         //let tick_duration = Some(Duration::from_millis(1000));
 
         let bank = Arc::new(bank);
         let mut thread_hdls = vec![];
+        let socket_stats_service = SocketStatsService::new(&node.sockets, exit.clone());
+        thread_hdls.extend(socket_stats_service.thread_hdls());
         let rpu = Rpu::new(
             &bank,
             node.sockets.requests,
@@ -202,36 +482,69 @@ impl Fullnode {
         );
         thread_hdls.extend(rpu.thread_hdls());
 
-        let rpc_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), RPC_PORT);
-        let rpc_service = JsonRpcService::new(bank.clone(), rpc_addr, exit.clone());
-        thread_hdls.extend(rpc_service.thread_hdls());
+        // sent transactions go straight into this node's own TPU
+        let transactions_addr = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            node.data.contact_info.tpu.port(),
+        );
+
+        let pubsub_service = PubSubService::new(&bank, pubsub_addr.unwrap_or_else(any_local_addr))?;
+
+        let admin_rpc_service = Self::new_admin_rpc(&lease, admin_rpc_addr)?;
+        let cpu_time_service = CpuTimeService::new(exit.clone());
+        thread_hdls.extend(cpu_time_service.thread_hdls());
 
         let blob_recycler = BlobRecycler::default();
-        let window =
-            window::new_window_from_entries(ledger_tail, entry_height, &node.data, &blob_recycler);
+        let window = window::new_window_from_entries(
+            ledger_tail,
+            entry_height,
+            bank.tick_height(),
+            &node.data,
+            &blob_recycler,
+        );
 
-        let crdt = Arc::new(RwLock::new(Crdt::new(node.data).expect("Crdt::new")));
+        let mut crdt = Crdt::new(node.data)?;
+        crdt.vote_identity = vote_identity;
+        let crdt = Arc::new(RwLock::new(crdt));
+        let peer_cache = peer_cache_path(&storage.ledger);
+        insert_cached_peers(&crdt, &peer_cache);
+        let peer_cache_service = PeerCacheService::new(crdt.clone(), peer_cache, exit.clone());
+        thread_hdls.extend(peer_cache_service.thread_hdls());
+
+        let rpc_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), RPC_PORT);
+        let rpc_service = JsonRpcService::new(
+            bank.clone(),
+            Some(&storage.ledger),
+            Some(crdt.clone()),
+            Some(transactions_addr),
+            rpc_config.unwrap_or_default(),
+            rpc_addr,
+        )?;
 
         let (tpu, blob_receiver) = Tpu::new(
             keypair,
+            &lease,
             &bank,
             &crdt,
             tick_duration,
             node.sockets.transaction,
+            node.sockets.vote,
             &blob_recycler,
             exit.clone(),
-            ledger_path,
+            storage,
+            entry_height,
+            sigverify_config,
             sigverify_disabled,
-        );
+        )?;
         thread_hdls.extend(tpu.thread_hdls());
         let ncp = Ncp::new(
             &crdt,
             window.clone(),
-            Some(ledger_path),
+            Some(&storage.ledger),
             node.sockets.gossip,
             node.sockets.gossip_send,
             exit.clone(),
-        ).expect("Ncp::new");
+        )?;
         thread_hdls.extend(ncp.thread_hdls());
 
         let broadcast_stage = BroadcastStage::new(
@@ -239,12 +552,21 @@ impl Fullnode {
             crdt,
             window,
             entry_height,
+            bank.tick_height(),
+            broadcast_pacing,
+            broadcast_send_threads.unwrap_or(1),
             blob_recycler.clone(),
             blob_receiver,
         );
         thread_hdls.extend(broadcast_stage.thread_hdls());
 
-        Fullnode { exit, thread_hdls }
+        Ok(Fullnode {
+            exit,
+            rpc_service,
+            admin_rpc_service,
+            pubsub_service,
+            thread_hdls,
+        })
     }
 
     /// Create a server instance acting as a validator.
@@ -276,19 +598,28 @@ impl Fullnode {
     ///   `--------`  |                               |    `------------`
     ///               `-------------------------------`
     /// ```
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn new_validator(
         keypair: Keypair,
+        lease: Arc<IdentityLease>,
         bank: Bank,
         entry_height: u64,
         ledger_tail: &[Entry],
         node: TestNode,
         entry_point: &NodeInfo,
         exit: Arc<AtomicBool>,
-        ledger_path: Option<&str>,
+        storage: Option<&StoragePaths>,
+        entry_stream_addr: Option<SocketAddr>,
+        rpc_config: Option<JsonRpcConfig>,
+        vote_identity: Option<Pubkey>,
+        admin_rpc_addr: Option<SocketAddr>,
+        pubsub_addr: Option<SocketAddr>,
         _sigverify_disabled: bool,
-    ) -> Self {
+    ) -> result::Result<Self> {
         let bank = Arc::new(bank);
         let mut thread_hdls = vec![];
+        let socket_stats_service = SocketStatsService::new(&node.sockets, exit.clone());
+        thread_hdls.extend(socket_stats_service.thread_hdls());
         let rpu = Rpu::new(
             &bank,
             node.sockets.requests,
@@ -297,30 +628,86 @@ impl Fullnode {
         );
         thread_hdls.extend(rpu.thread_hdls());
 
-        let rpc_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), RPC_PORT);
-        let rpc_service = JsonRpcService::new(bank.clone(), rpc_addr, exit.clone());
-        thread_hdls.extend(rpc_service.thread_hdls());
+        let pubsub_service = PubSubService::new(&bank, pubsub_addr.unwrap_or_else(any_local_addr))?;
+
+        let admin_rpc_service = Self::new_admin_rpc(&lease, admin_rpc_addr)?;
+        let cpu_time_service = CpuTimeService::new(exit.clone());
+        thread_hdls.extend(cpu_time_service.thread_hdls());
 
         let blob_recycler = BlobRecycler::default();
-        let window =
-            window::new_window_from_entries(ledger_tail, entry_height, &node.data, &blob_recycler);
+        let window = window::new_window_from_entries(
+            ledger_tail,
+            entry_height,
+            bank.tick_height(),
+            &node.data,
+            &blob_recycler,
+        );
 
-        let crdt = Arc::new(RwLock::new(Crdt::new(node.data).expect("Crdt::new")));
+        let mut crdt = Crdt::new(node.data)?;
+        crdt.vote_identity = vote_identity;
+        let crdt = Arc::new(RwLock::new(crdt));
         crdt.write()
             .expect("'crdt' write lock before insert() in pub fn replicate")
             .insert(&entry_point);
+        // rejoin through the peers from the last run too, in case the entry point is down
+        if let Some(storage) = storage {
+            let peer_cache = peer_cache_path(&storage.ledger);
+            insert_cached_peers(&crdt, &peer_cache);
+            let peer_cache_service = PeerCacheService::new(crdt.clone(), peer_cache, exit.clone());
+            thread_hdls.extend(peer_cache_service.thread_hdls());
+        }
+
+        let rpc_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), RPC_PORT);
+        // a validator doesn't bank transactions, so sent ones go to the leader
+        // it joined through
+        let rpc_service = JsonRpcService::new(
+            bank.clone(),
+            storage.map(|s| s.ledger.as_str()),
+            Some(crdt.clone()),
+            Some(entry_point.contact_info.tpu),
+            rpc_config.unwrap_or_default(),
+            rpc_addr,
+        )?;
 
         let ncp = Ncp::new(
             &crdt,
             window.clone(),
-            ledger_path,
+            storage.map(|s| s.ledger.as_str()),
             node.sockets.gossip,
             node.sockets.gossip_send,
             exit.clone(),
-        ).expect("Ncp::new");
+        )?;
+
+        // publish the replicated ledger to read replicas, if this node keeps one
+        let entry_stream = match storage {
+            Some(storage) => {
+                let entry_stream_addr = entry_stream_addr.unwrap_or_else(|| {
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), ENTRY_STREAM_PORT)
+                });
+                match TcpListener::bind(entry_stream_addr) {
+                    Ok(listener) => {
+                        let (entry_sender, entry_receiver) = channel();
+                        let entry_stream_service = EntryStreamService::new(
+                            listener,
+                            &storage.ledger,
+                            entry_receiver,
+                            exit.clone(),
+                        );
+                        thread_hdls.extend(entry_stream_service.thread_hdls());
+                        Some(entry_sender)
+                    }
+                    Err(e) => {
+                        warn!("no entry stream on {}: {:?}", entry_stream_addr, e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
 
         let tvu = Tvu::new(
             keypair,
+            &lease,
             &bank,
             entry_height,
             crdt.clone(),
@@ -328,17 +715,27 @@ impl Fullnode {
             node.sockets.replicate,
             node.sockets.repair,
             node.sockets.retransmit,
-            ledger_path,
+            storage,
+            entry_stream,
             exit.clone(),
-        );
+        )?;
         thread_hdls.extend(tvu.thread_hdls());
         thread_hdls.extend(ncp.thread_hdls());
-        Fullnode { exit, thread_hdls }
+        Ok(Fullnode {
+            exit,
+            rpc_service,
+            admin_rpc_service,
+            pubsub_service,
+            thread_hdls,
+        })
     }
 
     //used for notifying many nodes in parallel to exit
     pub fn exit(&self) {
         self.exit.store(true, Ordering::Relaxed);
+        self.rpc_service.close();
+        self.admin_rpc_service.close();
+        self.pubsub_service.close();
     }
     pub fn close(self) -> Result<()> {
         self.exit();
@@ -348,7 +745,11 @@ impl Fullnode {
 
 impl Service for Fullnode {
     fn thread_hdls(self) -> Vec<JoinHandle<()>> {
-        self.thread_hdls
+        let mut thread_hdls = self.thread_hdls;
+        thread_hdls.extend(self.rpc_service.thread_hdls());
+        thread_hdls.extend(self.admin_rpc_service.thread_hdls());
+        thread_hdls.extend(self.pubsub_service.thread_hdls());
+        thread_hdls
     }
 
     fn join(self) -> Result<()> {
@@ -364,12 +765,35 @@ mod tests {
     use bank::Bank;
     use crdt::TestNode;
     use fullnode::Fullnode;
+    use identity_lease::IdentityLease;
     use mint::Mint;
+    use result::{Error, StartupError};
     use service::Service;
     use signature::{Keypair, KeypairUtil};
+    use std::env;
+    use std::fs::remove_dir_all;
     use std::sync::atomic::AtomicBool;
     use std::sync::Arc;
 
+    fn tmp_ledger_path(name: &str) -> String {
+        let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
+        let keypair = Keypair::new();
+        format!("{}/tmp-ledger-{}-{}", out_dir, name, keypair.pubkey())
+    }
+
+    #[test]
+    fn fullnode_missing_ledger() {
+        let keypair = Keypair::new();
+        let tn = TestNode::new_localhost_with_pubkey(keypair.pubkey());
+        let ledger_path = tmp_ledger_path("fullnode_missing_ledger");
+        let result = Fullnode::new(tn, true, &ledger_path, keypair, None);
+        assert_matches!(
+            result.err(),
+            Some(Error::StartupError(StartupError::BadLedger(ref path, _))) if *path == ledger_path
+        );
+        remove_dir_all(ledger_path).unwrap();
+    }
+
     #[test]
     fn validator_exit() {
         let keypair = Keypair::new();
@@ -378,7 +802,24 @@ mod tests {
         let bank = Bank::new(&alice);
         let exit = Arc::new(AtomicBool::new(false));
         let entry = tn.data.clone();
-        let v = Fullnode::new_validator(keypair, bank, 0, &[], tn, &entry, exit, None, false);
+        let v = Fullnode::new_validator(
+            keypair,
+            Arc::new(IdentityLease::default()),
+            bank,
+            0,
+            &[],
+            tn,
+            &entry,
+            exit,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+        )
+        .unwrap();
         v.exit();
         v.join().unwrap();
     }
@@ -392,7 +833,24 @@ mod tests {
                 let bank = Bank::new(&alice);
                 let exit = Arc::new(AtomicBool::new(false));
                 let entry = tn.data.clone();
-                Fullnode::new_validator(keypair, bank, 0, &[], tn, &entry, exit, None, false)
+                Fullnode::new_validator(
+                    keypair,
+                    Arc::new(IdentityLease::default()),
+                    bank,
+                    0,
+                    &[],
+                    tn,
+                    &entry,
+                    exit,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    false,
+                )
+                .unwrap()
             })
             .collect();
         //each validator can exit in parallel to speed many sequential calls to `join`
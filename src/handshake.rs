@@ -0,0 +1,186 @@
+//! The `handshake` module lets a joining node and its gossip entry point trade
+//! capabilities before the node starts gossiping, so that nodes of different
+//! versions can share a cluster during a rolling upgrade, and a node that can't
+//! talk to the cluster at all fails with a clear error instead of going quiet.
+
+use crdt::{Crdt, CrdtError, TestNode};
+use packet::BLOB_SIZE;
+use result::{Error, Result};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// The gossip wire format, bumped on changes older nodes can't parse.
+pub const WIRE_VERSION: u16 = 1;
+/// The oldest gossip wire format this node still understands.
+pub const MIN_WIRE_VERSION: u16 = 1;
+/// The window repair protocol, bumped on changes to the repair requests.
+pub const REPAIR_VERSION: u16 = 1;
+
+/// The node can decompress zstd-compressed data.
+pub const FEATURE_COMPRESSION: u32 = 1;
+
+/// How long to wait for the entry point's half of the handshake
+pub const HANDSHAKE_TIMEOUT_MS: u64 = 2_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Capabilities {
+    pub wire_version: u16,
+    pub min_wire_version: u16,
+    pub repair_version: u16,
+    /// A bitmap of the `FEATURE_*` flags
+    pub features: u32,
+}
+
+impl Capabilities {
+    /// The capabilities of this build.
+    pub fn local() -> Self {
+        let mut features = 0;
+        if cfg!(feature = "zstd") {
+            features |= FEATURE_COMPRESSION;
+        }
+        Capabilities {
+            wire_version: WIRE_VERSION,
+            min_wire_version: MIN_WIRE_VERSION,
+            repair_version: REPAIR_VERSION,
+            features,
+        }
+    }
+
+    /// Whether each side can parse the other's gossip and serve its repairs.
+    pub fn is_compatible(&self, other: &Capabilities) -> bool {
+        self.wire_version >= other.min_wire_version
+            && other.wire_version >= self.min_wire_version
+            && self.repair_version == other.repair_version
+    }
+
+    /// The capabilities both sides can use.
+    pub fn negotiate(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            wire_version: self.wire_version.min(other.wire_version),
+            min_wire_version: self.min_wire_version.max(other.min_wire_version),
+            repair_version: self.repair_version,
+            features: self.features & other.features,
+        }
+    }
+}
+
+/// Trade capabilities with `entry_point`, and return the ones both sides can
+/// use. The answer arrives on the node's gossip socket, so this has to run
+/// before the socket is handed to gossip. An entry point that doesn't answer
+/// is assumed to predate the handshake, and `None` is returned.
+pub fn handshake(node: &TestNode, entry_point: &SocketAddr) -> Result<Option<Capabilities>> {
+    let local = Capabilities::local();
+    let req = Crdt::handshake_request(&node.data, &local)?;
+    UdpSocket::bind("0.0.0.0:0")?.send_to(&req, entry_point)?;
+
+    let sock = &node.sockets.gossip;
+    let old_timeout = sock.read_timeout()?;
+    let deadline = Instant::now() + Duration::from_millis(HANDSHAKE_TIMEOUT_MS);
+    let mut buf = vec![0u8; BLOB_SIZE];
+    let mut remote = None;
+    while remote.is_none() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        sock.set_read_timeout(Some(deadline - now))?;
+        match sock.recv_from(&mut buf) {
+            Ok((size, _)) => remote = Crdt::parse_handshake(&buf[..size]),
+            Err(_) => break,
+        }
+    }
+    sock.set_read_timeout(old_timeout)?;
+
+    match remote {
+        Some((id, remote)) => {
+            if !local.is_compatible(&remote) {
+                error!(
+                    "entry point {} ({}) is incompatible: ours {:?} theirs {:?}",
+                    entry_point, id, local, remote
+                );
+                return Err(Error::CrdtError(CrdtError::IncompatibleVersion));
+            }
+            Ok(Some(local.negotiate(&remote)))
+        }
+        None => {
+            warn!("entry point {} didn't answer the handshake", entry_point);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crdt::{Crdt, TestNode};
+    use handshake::{handshake, Capabilities, FEATURE_COMPRESSION};
+    use logger;
+    use ncp::Ncp;
+    use service::Service;
+    use std::net::UdpSocket;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, RwLock};
+    use window::default_window;
+
+    #[test]
+    fn test_capabilities() {
+        let old = Capabilities {
+            wire_version: 1,
+            min_wire_version: 1,
+            repair_version: 1,
+            features: 0,
+        };
+        let new = Capabilities {
+            wire_version: 2,
+            min_wire_version: 1,
+            repair_version: 1,
+            features: FEATURE_COMPRESSION,
+        };
+        assert!(old.is_compatible(&new) && new.is_compatible(&old));
+        assert_eq!(new.negotiate(&old), old);
+
+        let newer = Capabilities {
+            wire_version: 3,
+            min_wire_version: 2,
+            ..new
+        };
+        assert!(!old.is_compatible(&newer) && !newer.is_compatible(&old));
+        assert!(new.is_compatible(&newer));
+
+        let other_repair = Capabilities {
+            repair_version: 2,
+            ..old
+        };
+        assert!(!old.is_compatible(&other_repair));
+    }
+
+    #[test]
+    fn test_handshake() {
+        logger::setup();
+        let entry = TestNode::new_localhost();
+        let entry_point = entry.data.contact_info.ncp;
+        let crdt = Arc::new(RwLock::new(Crdt::new(entry.data).expect("Crdt::new")));
+        let exit = Arc::new(AtomicBool::new(false));
+        let ncp = Ncp::new(
+            &crdt,
+            default_window(),
+            None,
+            entry.sockets.gossip,
+            entry.sockets.gossip_send,
+            exit.clone(),
+        ).expect("Ncp::new");
+
+        let node = TestNode::new_localhost();
+        assert_eq!(
+            handshake(&node, &entry_point).unwrap(),
+            Some(Capabilities::local())
+        );
+
+        // nothing answers on a bare socket
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+        assert_eq!(handshake(&node, &silent_addr).unwrap(), None);
+
+        exit.store(true, Ordering::Relaxed);
+        ncp.join().unwrap();
+    }
+}
@@ -3,8 +3,13 @@
 use bs58;
 use generic_array::typenum::U32;
 use generic_array::GenericArray;
+#[cfg(not(target_arch = "wasm32"))]
+use ring::digest;
 use sha2::{Digest, Sha256};
+use signature::{decode_base58, ParsePubkeyError};
 use std::fmt;
+use std::mem;
+use std::str::FromStr;
 
 #[derive(Serialize, Deserialize, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Hash(GenericArray<u8, U32>);
@@ -26,6 +31,16 @@ impl fmt::Display for Hash {
         write!(f, "{}", bs58::encode(self.0).into_string())
     }
 }
+
+impl FromStr for Hash {
+    type Err = ParsePubkeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = decode_base58(s, mem::size_of::<Hash>())?;
+        Ok(Hash(GenericArray::clone_from_slice(&bytes)))
+    }
+}
+
 /// Return a Sha256 hash for the given data.
 pub fn hash(val: &[u8]) -> Hash {
     let mut hasher = Sha256::default();
@@ -42,3 +57,88 @@ pub fn extend_and_hash(id: &Hash, val: &[u8]) -> Hash {
     hash_data.extend_from_slice(val);
     hash(&hash_data)
 }
+
+/// A hash function the Proof of History chain can be computed with.
+pub trait PohHasher: Send + Sync {
+    fn hash(&self, val: &[u8]) -> Hash;
+
+    /// Return the hash of the given hash extended with the given value.
+    fn extend_and_hash(&self, id: &Hash, val: &[u8]) -> Hash {
+        let mut hash_data = id.as_ref().to_vec();
+        hash_data.extend_from_slice(val);
+        self.hash(&hash_data)
+    }
+}
+
+/// SHA-256 from the portable `sha2` crate, the same as `hash`.
+pub struct Sha256Hasher;
+
+impl PohHasher for Sha256Hasher {
+    fn hash(&self, val: &[u8]) -> Hash {
+        hash(val)
+    }
+}
+
+/// SHA-256 from `ring`, whose assembly uses the CPU's SIMD and SHA extensions
+/// where it has them. There's no `ring` on wasm32.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct SimdSha256Hasher;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PohHasher for SimdSha256Hasher {
+    fn hash(&self, val: &[u8]) -> Hash {
+        let digest = digest::digest(&digest::SHA256, val);
+        Hash(GenericArray::clone_from_slice(digest.as_ref()))
+    }
+}
+
+/// Names the `PohHasher` a PoH chain is computed with. Both compute SHA-256 and
+/// agree on every hash, so a chain verifies with either and the choice only
+/// sets how fast a node hashes. A different function would need a new chain.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PohHash {
+    Sha256,
+    #[cfg(not(target_arch = "wasm32"))]
+    Sha256Simd,
+}
+
+impl Default for PohHash {
+    fn default() -> Self {
+        PohHash::Sha256
+    }
+}
+
+impl PohHash {
+    pub fn hasher(self) -> &'static PohHasher {
+        match self {
+            PohHash::Sha256 => &Sha256Hasher,
+            #[cfg(not(target_arch = "wasm32"))]
+            PohHash::Sha256Simd => &SimdSha256Hasher,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hash() {
+        let id = hash(b"hello");
+        assert_eq!(id.to_string().parse(), Ok(id));
+        assert!("hello".parse::<Hash>().is_err());
+    }
+
+    #[test]
+    fn test_poh_hashers_agree() {
+        let id = hash(b"hello");
+        for poh_hash in &[PohHash::Sha256, PohHash::Sha256Simd] {
+            let hasher = poh_hash.hasher();
+            assert_eq!(hasher.hash(b"hello"), id);
+            assert_eq!(
+                hasher.extend_and_hash(&id, b"world"),
+                extend_and_hash(&id, b"world")
+            );
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! The `identity_lease` module lets two fullnodes share one identity as a warm
+//! standby pair. Both nodes follow the network, but only the one holding the
+//! lease signs votes and broadcasts entries, so the pair never signs twice.
+//!
+//! Failover hands the lease over in two steps: the active node releases it,
+//! which moves the lease to a new term, and the standby acquires it by
+//! presenting that term. A term that isn't newer than one the standby has
+//! already seen is refused, so a replayed handoff can't reactivate a node.
+//!
+//! Each node serves the handoff over admin RPC, on localhost by default. To
+//! keep the standby on another host, give both nodes an `admin_rpc_addr` on a
+//! private interface the other host can reach, with `solana-fullnode-config
+//! --admin-rpc`, and run `solana-fullnode-failover --identity <active config>
+//! --standby <standby admin address>`. Anyone who reaches the admin RPC can
+//! move the identity, so never bind it to a public interface.
+
+use std::sync::RwLock;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LeaseError {
+    /// The lease can only be released by the node holding it.
+    NotHeld,
+    /// The presented term is not newer than the latest known term.
+    StaleTerm(u64),
+}
+
+#[derive(Debug)]
+struct LeaseState {
+    active: bool,
+    term: u64,
+}
+
+#[derive(Debug)]
+pub struct IdentityLease {
+    state: RwLock<LeaseState>,
+}
+
+impl IdentityLease {
+    /// Create a lease for a node starting as the `active` or standby half of a pair.
+    pub fn new(active: bool) -> Self {
+        IdentityLease {
+            state: RwLock::new(LeaseState { active, term: 0 }),
+        }
+    }
+
+    /// Whether this node may sign with the shared identity.
+    pub fn is_active(&self) -> bool {
+        self.state.read().unwrap().active
+    }
+
+    pub fn term(&self) -> u64 {
+        self.state.read().unwrap().term
+    }
+
+    /// Stop signing and return the new term to hand to the standby.
+    pub fn release(&self) -> Result<u64, LeaseError> {
+        let mut state = self.state.write().unwrap();
+        if !state.active {
+            return Err(LeaseError::NotHeld);
+        }
+        state.active = false;
+        state.term += 1;
+        info!("released identity lease, term {}", state.term);
+        Ok(state.term)
+    }
+
+    /// Start signing with the `term` returned by the active node's `release`.
+    pub fn acquire(&self, term: u64) -> Result<u64, LeaseError> {
+        let mut state = self.state.write().unwrap();
+        if term <= state.term {
+            return Err(LeaseError::StaleTerm(state.term));
+        }
+        state.active = true;
+        state.term = term;
+        info!("acquired identity lease, term {}", term);
+        Ok(term)
+    }
+}
+
+impl Default for IdentityLease {
+    fn default() -> Self {
+        IdentityLease::new(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use identity_lease::{IdentityLease, LeaseError};
+
+    #[test]
+    fn test_failover_and_back() {
+        let primary = IdentityLease::default();
+        let standby = IdentityLease::new(false);
+        assert!(primary.is_active());
+        assert!(!standby.is_active());
+        assert_eq!(standby.release(), Err(LeaseError::NotHeld));
+
+        let term = primary.release().unwrap();
+        assert!(!primary.is_active());
+        assert_eq!(standby.acquire(term), Ok(term));
+        assert!(standby.is_active());
+
+        let term = standby.release().unwrap();
+        assert_eq!(primary.acquire(term), Ok(term));
+        assert!(primary.is_active());
+        assert!(!standby.is_active());
+    }
+
+    #[test]
+    fn test_stale_term_is_refused() {
+        let primary = IdentityLease::default();
+        let standby = IdentityLease::new(false);
+        let term = primary.release().unwrap();
+        standby.acquire(term).unwrap();
+        standby.release().unwrap();
+
+        // replaying the first handoff doesn't reactivate the standby
+        assert_eq!(standby.acquire(term), Err(LeaseError::StaleTerm(term + 1)));
+        assert!(!standby.is_active());
+    }
+}
@@ -0,0 +1,338 @@
+//! The `ipc_plugin` module streams what the bank commits to plugins, external
+//! processes that connect to a unix socket, so they can keep their own indexes
+//! of the accounts without being linked into the node. A plugin is sent every
+//! account a committed batch of transactions wrote and the id of every entry the
+//! bank registers, from the time it connects. Each message is a little-endian
+//! `u32` length followed by that many bytes of a bincode-serialized
+//! `PluginMessage`.
+//!
+//! The bank only ever queues messages, so a slow plugin can't stall it. When a
+//! plugin falls so far behind that the queue fills up, every plugin is
+//! disconnected, since each has then missed messages, and has to reconnect.
+
+use account::Account;
+use bank::{BalanceDelta, BankObserver, Result as BankResult};
+use bincode::{deserialize, serialize};
+use byteorder::{ByteOrder, LittleEndian};
+use counter::Counter;
+use hash::Hash;
+use log::Level;
+use result::Result;
+use service::Service;
+use signature::Pubkey;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Builder, JoinHandle};
+use std::time::Duration;
+use transaction::{Instruction, Transaction};
+
+/// Most messages queued for plugins before they're disconnected.
+const PLUGIN_QUEUE_LEN: usize = 100_000;
+
+/// A plugin that doesn't take a message within this timeout is dropped.
+const PLUGIN_TIMEOUT_MS: u64 = 1000;
+
+/// Largest message a plugin will be sent.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PluginMessage {
+    /// An account a committed batch of transactions wrote, as the batch left
+    /// it. An account the batch emptied is sent with no tokens and no data.
+    Account { pubkey: Pubkey, account: Account },
+    /// The bank registered the id of an entry, at this tick height.
+    Entry { id: Hash, tick_height: u64 },
+}
+
+/// Send `message` to a plugin.
+pub fn write_message<W: Write>(writer: &mut W, message: &PluginMessage) -> Result<()> {
+    let data = serialize(message)?;
+    let mut len = [0u8; 4];
+    LittleEndian::write_u32(&mut len, data.len() as u32);
+    writer.write_all(&len)?;
+    writer.write_all(&data)?;
+    Ok(())
+}
+
+/// Receive one message, as a plugin.
+pub fn read_message<R: Read>(reader: &mut R) -> Result<PluginMessage> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = LittleEndian::read_u32(&len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(ErrorKind::InvalidData, "plugin message too long").into());
+    }
+    let mut data = vec![0u8; len];
+    reader.read_exact(&mut data)?;
+    Ok(deserialize(&data)?)
+}
+
+/// Queues the bank's commits for the plugins, without ever blocking the bank.
+struct PluginObserver {
+    sender: SyncSender<PluginMessage>,
+    /// Number of connected plugins. Nothing is queued while there are none.
+    plugins: Arc<AtomicUsize>,
+    /// Set when a message didn't fit in the queue.
+    overflowed: Arc<AtomicBool>,
+    /// Accounts the transactions of the current batch wrote.
+    written: Mutex<Vec<Pubkey>>,
+}
+
+impl PluginObserver {
+    fn send(&self, message: PluginMessage) {
+        match self.sender.try_send(message) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                if !self.overflowed.swap(true, Ordering::Relaxed) {
+                    inc_new_counter_info!("ipc_plugin-overflow", 1);
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+
+    fn has_plugins(&self) -> bool {
+        self.plugins.load(Ordering::Relaxed) > 0
+    }
+}
+
+impl BankObserver for PluginObserver {
+    fn transaction_committed(&self, tx: &Transaction, deltas: &[BalanceDelta]) {
+        if !self.has_plugins() {
+            return;
+        }
+        let mut written = self.written.lock().unwrap();
+        written.extend(deltas.iter().map(|delta| delta.pubkey));
+        // the signer's userdata, an account it assigned or loaded, or a nonce
+        // it advanced may have changed without any tokens moving
+        written.push(tx.from);
+        for instruction in &tx.instructions {
+            match instruction {
+                Instruction::AdvanceNonce(pubkey) => written.push(*pubkey),
+                _ => written.extend(instruction.data_pubkey()),
+            }
+        }
+    }
+
+    fn transactions_processed(
+        &self,
+        _txs: &[Transaction],
+        _results: &[BankResult<()>],
+        accounts: &Fn(&Pubkey) -> Option<Account>,
+    ) {
+        let mut written = self.written.lock().unwrap();
+        if !self.has_plugins() {
+            written.clear();
+            return;
+        }
+        written.sort();
+        written.dedup();
+        for pubkey in written.drain(..) {
+            let account = accounts(&pubkey).unwrap_or_default();
+            self.send(PluginMessage::Account { pubkey, account });
+        }
+    }
+
+    fn entries_registered(&self, ids: &[Hash], tick_height: u64) {
+        if !self.has_plugins() {
+            return;
+        }
+        for id in ids {
+            self.send(PluginMessage::Entry {
+                id: *id,
+                tick_height,
+            });
+        }
+    }
+}
+
+pub struct IpcPluginService {
+    thread_hdl: JoinHandle<()>,
+}
+
+impl IpcPluginService {
+    /// Accept plugins on a unix socket at `socket_path`, replacing a stale
+    /// socket left there, and return the service along with the observer to
+    /// add to the bank whose commits they're sent.
+    pub fn new(socket_path: &str, exit: Arc<AtomicBool>) -> Result<(Self, Arc<BankObserver>)> {
+        if let Ok(metadata) = fs::symlink_metadata(socket_path) {
+            if !metadata.file_type().is_socket() {
+                let message = format!("{} exists and isn't a socket", socket_path);
+                return Err(io::Error::new(ErrorKind::AlreadyExists, message).into());
+            }
+            fs::remove_file(socket_path)?;
+        }
+        let listener = UnixListener::bind(socket_path)?;
+        listener.set_nonblocking(true)?;
+
+        let (sender, receiver) = sync_channel(PLUGIN_QUEUE_LEN);
+        let plugins = Arc::new(AtomicUsize::new(0));
+        let overflowed = Arc::new(AtomicBool::new(false));
+        let observer = PluginObserver {
+            sender,
+            plugins: plugins.clone(),
+            overflowed: overflowed.clone(),
+            written: Mutex::new(vec![]),
+        };
+        let socket_path = socket_path.to_string();
+        let thread_hdl = Builder::new()
+            .name("solana-ipc-plugin".to_string())
+            .spawn(move || {
+                Self::run(&listener, &receiver, &plugins, &overflowed, &exit);
+                let _ = fs::remove_file(&socket_path);
+            })
+            .unwrap();
+        Ok((IpcPluginService { thread_hdl }, Arc::new(observer)))
+    }
+
+    fn run(
+        listener: &UnixListener,
+        receiver: &Receiver<PluginMessage>,
+        plugins: &AtomicUsize,
+        overflowed: &AtomicBool,
+        exit: &AtomicBool,
+    ) {
+        let mut streams = Vec::new();
+        loop {
+            if exit.load(Ordering::Relaxed) {
+                break;
+            }
+            Self::accept(listener, &mut streams);
+            if overflowed.swap(false, Ordering::Relaxed) {
+                warn!("plugins fell behind, disconnecting {}", streams.len());
+                inc_new_counter_info!("ipc_plugin-drop", streams.len());
+                streams.clear();
+                while receiver.try_recv().is_ok() {}
+            }
+            plugins.store(streams.len(), Ordering::Relaxed);
+            match receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(message) => {
+                    Self::publish(&mut streams, &message);
+                    while let Ok(message) = receiver.try_recv() {
+                        Self::publish(&mut streams, &message);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+        }
+    }
+
+    fn accept(listener: &UnixListener, streams: &mut Vec<UnixStream>) {
+        while let Ok((stream, _)) = listener.accept() {
+            let timeout = Some(Duration::from_millis(PLUGIN_TIMEOUT_MS));
+            match stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_write_timeout(timeout))
+            {
+                Ok(()) => {
+                    inc_new_counter_info!("ipc_plugin-connect", 1);
+                    streams.push(stream);
+                }
+                Err(e) => warn!("plugin failed to connect: {:?}", e),
+            }
+        }
+    }
+
+    fn publish(streams: &mut Vec<UnixStream>, message: &PluginMessage) {
+        let mut live = Vec::with_capacity(streams.len());
+        for mut stream in streams.drain(..) {
+            match write_message(&mut stream, message) {
+                Ok(()) => live.push(stream),
+                Err(e) => {
+                    inc_new_counter_info!("ipc_plugin-drop", 1);
+                    warn!("dropping plugin: {:?}", e);
+                }
+            }
+        }
+        *streams = live;
+    }
+}
+
+impl Service for IpcPluginService {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        vec![self.thread_hdl]
+    }
+
+    fn join(self) -> thread::Result<()> {
+        self.thread_hdl.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use account::Account;
+    use bank::Bank;
+    use ipc_plugin::{read_message, IpcPluginService, PluginMessage};
+    use mint::Mint;
+    use service::Service;
+    use signature::{Keypair, KeypairUtil};
+    use std::env;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_ipc_plugin_stream() {
+        // unix socket paths are short, too short for OUT_DIR and a whole pubkey
+        let name = format!(
+            "ipc_plugin-{}.sock",
+            &Keypair::new().pubkey().to_string()[..8]
+        );
+        let socket_path = env::temp_dir().join(name).to_str().unwrap().to_string();
+        let mint = Mint::new(2);
+        let bank = Bank::new(&mint);
+        let exit = Arc::new(AtomicBool::new(false));
+        let (service, observer) = IpcPluginService::new(&socket_path, exit.clone()).unwrap();
+        bank.add_observer(observer);
+
+        let mut stream = UnixStream::connect(&socket_path).unwrap();
+        // wait for the service to count the plugin
+        sleep(Duration::from_millis(300));
+
+        let pubkey = Keypair::new().pubkey();
+        bank.transfer(2, &mint.keypair(), pubkey, mint.last_id())
+            .unwrap();
+        let mut messages = vec![read_message(&mut stream).unwrap()];
+        messages.push(read_message(&mut stream).unwrap());
+        messages.sort_by_key(|message| match message {
+            PluginMessage::Account { pubkey, .. } => *pubkey,
+            PluginMessage::Entry { .. } => panic!("no entry was registered"),
+        });
+        let mut expected = vec![
+            PluginMessage::Account {
+                pubkey,
+                account: Account {
+                    tokens: 2,
+                    ..Account::default()
+                },
+            },
+            PluginMessage::Account {
+                pubkey: mint.pubkey(),
+                account: Account::default(),
+            },
+        ];
+        expected.sort_by_key(|message| match message {
+            PluginMessage::Account { pubkey, .. } => *pubkey,
+            PluginMessage::Entry { .. } => unreachable!(),
+        });
+        assert_eq!(messages, expected);
+
+        let id = mint.last_id();
+        bank.register_entry_id(&id);
+        assert_eq!(
+            read_message(&mut stream).unwrap(),
+            PluginMessage::Entry { id, tick_height: 1 }
+        );
+
+        exit.store(true, Ordering::Relaxed);
+        service.join().unwrap();
+    }
+}
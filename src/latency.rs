@@ -0,0 +1,126 @@
+//! The `latency` module keeps latency histograms for the TPU's stages. Packets
+//! are stamped with the time they were read off the socket, and each stage
+//! records how old its packets are when it picks them up, and how long it takes
+//! to process them. A regression in confirmation latency then shows up first in
+//! the stage that caused it.
+
+use influx_db_client as influxdb;
+use metrics;
+use std::time::{Duration, Instant};
+use timing::duration_as_us;
+
+/// Upper bounds, in microseconds, of the latency buckets. Anything slower than
+/// the last bound is counted in an extra overflow bucket.
+const LATENCY_BUCKETS_US: [u64; 9] = [
+    100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000,
+];
+
+/// How often a histogram is submitted to metrics.
+const SUBMIT_INTERVAL_MS: u64 = 10_000;
+
+pub struct LatencyHistogram {
+    name: &'static str,
+    kind: &'static str,
+    count: usize,
+    total_us: u64,
+    max_us: u64,
+    /// One count per bound in `LATENCY_BUCKETS_US`, plus the overflow bucket.
+    buckets: Vec<usize>,
+    last_submit: Instant,
+}
+
+impl LatencyHistogram {
+    pub fn new(name: &'static str, kind: &'static str) -> Self {
+        LatencyHistogram {
+            name,
+            kind,
+            count: 0,
+            total_us: 0,
+            max_us: 0,
+            buckets: vec![0; LATENCY_BUCKETS_US.len() + 1],
+            last_submit: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, elapsed: &Duration) {
+        let elapsed_us = duration_as_us(elapsed);
+        self.count += 1;
+        self.total_us += elapsed_us;
+        self.max_us = self.max_us.max(elapsed_us);
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|bound| elapsed_us <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.buckets[bucket] += 1;
+        if self.last_submit.elapsed() >= Duration::from_millis(SUBMIT_INTERVAL_MS) {
+            self.submit();
+        }
+    }
+
+    fn submit(&mut self) {
+        let mut point = influxdb::Point::new(self.name);
+        point
+            .add_tag("kind", influxdb::Value::String(self.kind.to_string()))
+            .add_field("count", influxdb::Value::Integer(self.count as i64))
+            .add_field(
+                "mean_us",
+                influxdb::Value::Integer((self.total_us / self.count.max(1) as u64) as i64),
+            )
+            .add_field("max_us", influxdb::Value::Integer(self.max_us as i64));
+        for (i, count) in self.buckets.iter().enumerate() {
+            let field = match LATENCY_BUCKETS_US.get(i) {
+                Some(bound) => format!("le_{}us", bound),
+                None => "overflow".to_string(),
+            };
+            point.add_field(&field, influxdb::Value::Integer(*count as i64));
+        }
+        metrics::submit(point);
+
+        *self = Self::new(self.name, self.kind);
+    }
+}
+
+/// The latency histograms of one stage.
+pub struct StageLatency {
+    /// How long after they were received the stage picks packets up.
+    pub since_recv: LatencyHistogram,
+    /// How long the stage takes to process a batch.
+    pub process: LatencyHistogram,
+}
+
+impl StageLatency {
+    /// Histograms submitted to metrics as `name`.
+    pub fn new(name: &'static str) -> Self {
+        StageLatency {
+            since_recv: LatencyHistogram::new(name, "since_recv"),
+            process: LatencyHistogram::new(name, "process"),
+        }
+    }
+
+    /// Record the age of a batch received at `recv_time`, if it's known.
+    pub fn record_since_recv(&mut self, recv_time: Option<Instant>) {
+        if let Some(recv_time) = recv_time {
+            self.since_recv.record(&recv_time.elapsed());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use latency::{LatencyHistogram, LATENCY_BUCKETS_US};
+    use std::time::Duration;
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut histogram = LatencyHistogram::new("latency-test", "process");
+        histogram.record(&Duration::from_millis(0));
+        histogram.record(&Duration::from_millis(3));
+        histogram.record(&Duration::from_millis(3));
+        histogram.record(&Duration::from_secs(2));
+        assert_eq!(histogram.count, 4);
+        assert_eq!(histogram.max_us, 2_000_000);
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[3], 2);
+        assert_eq!(histogram.buckets[LATENCY_BUCKETS_US.len()], 1);
+    }
+}
@@ -1,20 +1,24 @@
 //! The `ledger` module provides functions for parallel verification of the
 //! Proof of History ledger as well as iterative read, append write, and random
-//! access read to a persistent file-based ledger.
+//! access read to a persistent file-based ledger. The stages that record
+//! entries write them through the `LedgerWriter` trait, so that tests can hand
+//! them an in-memory `MemLedger` instead.
 
 use bincode::{self, deserialize, deserialize_from, serialize_into, serialized_size};
-use entry::Entry;
+use entry::{Entry, PohDomain};
 use hash::Hash;
 use log::Level::Trace;
 use packet::{self, SharedBlob, BLOB_DATA_SIZE};
 use rayon::prelude::*;
 use result::{Error, Result};
+use signature::{Pubkey, Signature};
 use std::collections::VecDeque;
 use std::fs::{create_dir_all, remove_dir_all, File, OpenOptions};
 use std::io::prelude::*;
 use std::io::{self, BufReader, BufWriter, Seek, SeekFrom};
 use std::mem::size_of;
 use std::path::Path;
+use std::sync::{Arc, RwLock};
 use transaction::Transaction;
 use window::WINDOW_SIZE;
 
@@ -57,6 +61,35 @@ use window::WINDOW_SIZE;
 //   both files to this syncrhonization point.
 //
 
+/// Locations of a fullnode's persistent state. Ledger segments are only ever
+/// appended to and can sit on a large, slow disk, while snapshots and the
+/// accounts database are rewritten often and belong on faster storage.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StoragePaths {
+    pub ledger: String,
+    pub snapshots: String,
+    /// where the accounts database is kept
+    pub accounts: String,
+}
+
+impl StoragePaths {
+    /// Keep snapshots and accounts in directories under `ledger_path`
+    pub fn new(ledger_path: &str) -> Self {
+        StoragePaths {
+            ledger: ledger_path.to_string(),
+            snapshots: format!("{}/snapshots", ledger_path),
+            accounts: format!("{}/accounts", ledger_path),
+        }
+    }
+
+    /// Create the snapshot and accounts directories, so a bad path is reported
+    /// at startup. The ledger itself is created by `genesis`.
+    pub fn create_dirs(&self) -> io::Result<()> {
+        create_dir_all(&self.snapshots)?;
+        create_dir_all(&self.accounts)
+    }
+}
+
 // ledger window
 #[derive(Debug)]
 pub struct LedgerWindow {
@@ -267,19 +300,19 @@ fn recover_ledger(ledger_path: &str) -> io::Result<()> {
 //}
 
 #[derive(Debug)]
-pub struct LedgerWriter {
+pub struct FileLedgerWriter {
     index: BufWriter<File>,
     data: BufWriter<File>,
 }
 
-impl LedgerWriter {
+impl FileLedgerWriter {
     // recover and open the ledger for writing
     pub fn recover(ledger_path: &str) -> io::Result<Self> {
         recover_ledger(ledger_path)?;
-        LedgerWriter::open(ledger_path, false)
+        FileLedgerWriter::open(ledger_path, false)
     }
 
-    // opens or creates a LedgerWriter in ledger_path directory
+    // opens or creates a FileLedgerWriter in ledger_path directory
     pub fn open(ledger_path: &str, create: bool) -> io::Result<Self> {
         let ledger_path = Path::new(&ledger_path);
 
@@ -295,7 +328,7 @@ impl LedgerWriter {
 
         if log_enabled!(Trace) {
             let len = index.metadata()?.len();
-            trace!("LedgerWriter::new: index fp:{}", len);
+            trace!("FileLedgerWriter::new: index fp:{}", len);
         }
         let index = BufWriter::new(index);
 
@@ -306,11 +339,11 @@ impl LedgerWriter {
 
         if log_enabled!(Trace) {
             let len = data.metadata()?.len();
-            trace!("LedgerWriter::new: data fp:{}", len);
+            trace!("FileLedgerWriter::new: data fp:{}", len);
         }
         let data = BufWriter::new(data);
 
-        Ok(LedgerWriter { index, data })
+        Ok(FileLedgerWriter { index, data })
     }
 
     fn write_entry_noflush(&mut self, entry: &Entry) -> io::Result<()> {
@@ -361,11 +394,11 @@ impl LedgerWriter {
 }
 
 #[derive(Debug)]
-pub struct LedgerReader {
+pub struct FileLedgerEntries {
     data: BufReader<File>,
 }
 
-impl Iterator for LedgerReader {
+impl Iterator for FileLedgerEntries {
     type Item = io::Result<Entry>;
 
     fn next(&mut self) -> Option<io::Result<Entry>> {
@@ -389,46 +422,136 @@ pub fn read_ledger(
     let data = File::open(ledger_path.join("data"))?;
     let data = BufReader::new(data);
 
-    Ok(LedgerReader { data })
+    Ok(FileLedgerEntries { data })
+}
+
+/// Appends entries to a ledger, such as the file-based one a node keeps, or a
+/// `MemLedger` in tests.
+pub trait LedgerWriter: Send {
+    /// Append `entries`, in order, after those already written.
+    fn append_entries(&mut self, entries: &[Entry]) -> io::Result<()>;
+}
+
+/// Reads back a ledger's entries in the order they were written.
+pub trait LedgerReader {
+    fn read_entries(&self) -> io::Result<Box<Iterator<Item = io::Result<Entry>>>>;
+}
+
+impl LedgerWriter for FileLedgerWriter {
+    fn append_entries(&mut self, entries: &[Entry]) -> io::Result<()> {
+        self.write_entries(entries.iter().cloned())
+    }
+}
+
+/// Reads the file-based ledger at a path.
+#[derive(Debug)]
+pub struct FileLedgerReader {
+    ledger_path: String,
+}
+
+impl FileLedgerReader {
+    pub fn new(ledger_path: &str) -> Self {
+        FileLedgerReader {
+            ledger_path: ledger_path.to_string(),
+        }
+    }
+}
+
+impl LedgerReader for FileLedgerReader {
+    fn read_entries(&self) -> io::Result<Box<Iterator<Item = io::Result<Entry>>>> {
+        let entries = read_ledger(&self.ledger_path, false)?;
+        Ok(Box::new(entries))
+    }
+}
+
+/// A ledger kept in memory, so that tests of the stages that write one need no
+/// files. Its clones share its entries, so a test can read what a stage wrote
+/// to the clone it was given.
+#[derive(Clone, Debug, Default)]
+pub struct MemLedger {
+    entries: Arc<RwLock<Vec<Entry>>>,
+}
+
+impl MemLedger {
+    /// A ledger holding `entries`, such as the genesis entries.
+    pub fn new(entries: Vec<Entry>) -> Self {
+        MemLedger {
+            entries: Arc::new(RwLock::new(entries)),
+        }
+    }
+
+    /// The entries written so far.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries.read().unwrap().clone()
+    }
+}
+
+impl LedgerWriter for MemLedger {
+    fn append_entries(&mut self, entries: &[Entry]) -> io::Result<()> {
+        self.entries.write().unwrap().extend_from_slice(entries);
+        Ok(())
+    }
+}
+
+impl LedgerReader for MemLedger {
+    fn read_entries(&self) -> io::Result<Box<Iterator<Item = io::Result<Entry>>>> {
+        Ok(Box::new(self.entries().into_iter().map(Ok)))
+    }
 }
 
 ///// copy ledger is doesn't fix up the "from" ledger
 //pub fn copy_ledger(from: &str, to: &str) -> io::Result<()> {
-//    let mut to = LedgerWriter::new(to, true)?;
+//    let mut to = FileLedgerWriter::new(to, true)?;
 //
 //    let from = Path::new(&from);
 //
 //    // for a copy, we read "readonly" from data
 //    let data = File::open(from.join("data"))?;
 //
-//    for entry in (LedgerReader { data }) {
+//    for entry in (FileLedgerEntries { data }) {
 //        let entry = entry?;
 //        to.write_entry(&entry)?;
 //    }
 //    Ok(())
 //}
 
+fn verify_chain<F>(entries: &[Entry], start_hash: &Hash, verify: F) -> bool
+where
+    F: Fn(&Entry, &Entry) -> bool + Sync,
+{
+    let genesis = [Entry::new_tick(0, start_hash)];
+    let entry_pairs = genesis.par_iter().chain(entries).zip(entries);
+    entry_pairs.all(|(x0, x1)| {
+        let r = verify(x0, x1);
+        if !r {
+            warn!(
+                "entry invalid!: {:?} num txs: {}",
+                x1.id,
+                x1.transactions.len()
+            );
+        }
+        r
+    })
+}
+
 // a Block is a slice of Entries
 pub trait Block {
     /// Verifies the hashes and counts of a slice of transactions are all consistent.
     fn verify(&self, start_hash: &Hash) -> bool;
+    /// Same as `verify`, but each entry's PoH must start from its predecessor's id
+    /// seeded with `domain`.
+    fn verify_in_domain(&self, start_hash: &Hash, domain: &PohDomain) -> bool;
     fn to_blobs(&self, blob_recycler: &packet::BlobRecycler, q: &mut VecDeque<SharedBlob>);
 }
 
 impl Block for [Entry] {
     fn verify(&self, start_hash: &Hash) -> bool {
-        let genesis = [Entry::new_tick(0, start_hash)];
-        let entry_pairs = genesis.par_iter().chain(self).zip(self);
-        entry_pairs.all(|(x0, x1)| {
-            let r = x1.verify(&x0.id);
-            if !r {
-                warn!(
-                    "entry invalid!: {:?} num txs: {}",
-                    x1.id,
-                    x1.transactions.len()
-                );
-            }
-            r
+        verify_chain(self, start_hash, |x0, x1| x1.verify(&x0.id))
+    }
+
+    fn verify_in_domain(&self, start_hash: &Hash, domain: &PohDomain) -> bool {
+        verify_chain(self, start_hash, |x0, x1| {
+            x1.verify_in_domain(&x0.id, domain)
         })
     }
 
@@ -461,17 +584,213 @@ pub fn reconstruct_entries_from_blobs(blobs: VecDeque<SharedBlob>) -> Result<Vec
     Ok(entries)
 }
 
+/// Creates the next Entry, then moves `start_hash` on to the start of the
+/// following Entry's PoH
+fn next_entry_mut(
+    start_hash: &mut Hash,
+    num_hashes: &mut u64,
+    transactions: Vec<Transaction>,
+    has_more: bool,
+    domain: Option<&PohDomain>,
+) -> Entry {
+    let entry = Entry::new_mut(start_hash, num_hashes, transactions, has_more);
+    if let Some(domain) = domain {
+        *start_hash = domain.seed(start_hash);
+    }
+    entry
+}
+
+/// The number of ticks in a leader slot. A slot's entries run up to and
+/// including its last tick.
+pub const TICKS_PER_SLOT: u64 = 8;
+
+/// The entries of one complete leader slot.
+#[derive(Debug, PartialEq)]
+pub struct SlotEntries {
+    pub slot: u64,
+    pub parent_slot: u64,
+    /// The id of the last entry before the slot.
+    pub previous_blockhash: Hash,
+    pub entries: Vec<Entry>,
+}
+
+impl SlotEntries {
+    /// The id of the slot's last entry.
+    pub fn blockhash(&self) -> Hash {
+        self.entries[self.entries.len() - 1].id
+    }
+}
+
+/// The index of the first entry of each leader slot in the ledger at a path,
+/// so a slot's entries can be read without reading the ones before. Each
+/// update only reads the entries written since the last one.
+#[derive(Debug)]
+pub struct SlotIndex {
+    ledger_path: String,
+    /// Where each slot seen to start starts, the first always at 0.
+    starts: Vec<u64>,
+    /// The number of entries indexed so far.
+    num_entries: u64,
+    /// The number of ticks among them.
+    num_ticks: u64,
+}
+
+impl SlotIndex {
+    pub fn new(ledger_path: &str) -> Self {
+        SlotIndex {
+            ledger_path: ledger_path.to_string(),
+            starts: vec![0],
+            num_entries: 0,
+            num_ticks: 0,
+        }
+    }
+
+    /// Index the entries written since the last update, while `has_time`
+    /// says so. The rest are left for the next update.
+    pub fn update<F: Fn() -> bool>(&mut self, has_time: F) -> io::Result<()> {
+        let mut window = LedgerWindow::open(&self.ledger_path)?;
+        let len = window.index.get_ref().metadata()?.len() / SIZEOF_U64;
+        if self.num_entries >= len {
+            return Ok(());
+        }
+        let offset = u64_at(&mut window.index, self.num_entries * SIZEOF_U64)?;
+        window.data.seek(SeekFrom::Start(offset))?;
+        while self.num_entries < len && has_time() {
+            // the writer may be partway through the last entry
+            let entry = match next_entry(&mut window.data) {
+                Ok(entry) => entry,
+                Err(_) => break,
+            };
+            self.num_entries += 1;
+            if entry.transactions.is_empty() {
+                self.num_ticks += 1;
+                if self.num_ticks % TICKS_PER_SLOT == 0 {
+                    self.starts.push(self.num_entries);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The entries of `slot`, if the entries indexed so far hold all of them.
+    pub fn slot_entries(&self, slot: u64) -> io::Result<Option<SlotEntries>> {
+        if slot + 1 >= self.starts.len() as u64 {
+            return Ok(None);
+        }
+        let start = self.starts[slot as usize];
+        let end = self.starts[slot as usize + 1];
+        let mut window = LedgerWindow::open(&self.ledger_path)?;
+        let previous_blockhash = if start == 0 {
+            Hash::default()
+        } else {
+            window.get_entry(start - 1)?.id
+        };
+        let entries = (start..end)
+            .map(|index| window.get_entry(index))
+            .collect::<io::Result<_>>()?;
+        Ok(Some(SlotEntries {
+            slot,
+            parent_slot: slot.saturating_sub(1),
+            previous_blockhash,
+            entries,
+        }))
+    }
+}
+
+/// Find the transaction with `signature` in `entries`, a ledger from its first
+/// entry on, and return it along with the leader slot it was recorded in.
+pub fn find_transaction<I>(entries: I, signature: &Signature) -> Option<(u64, Transaction)>
+where
+    I: IntoIterator<Item = Entry>,
+{
+    let mut num_ticks = 0;
+    for entry in entries {
+        if entry.transactions.is_empty() {
+            num_ticks += 1;
+            continue;
+        }
+        if let Some(tx) = entry
+            .transactions
+            .into_iter()
+            .find(|tx| tx.signature == *signature)
+        {
+            return Some((num_ticks / TICKS_PER_SLOT, tx));
+        }
+    }
+    None
+}
+
+/// Find every transaction in `entries`, a ledger from its first entry on, that
+/// was signed by, drew tokens from, or paid `pubkey`, and return them oldest
+/// first along with the leader slot each was recorded in. The ledger isn't
+/// indexed by address, so every entry is read.
+pub fn find_transactions_for_address<I>(entries: I, pubkey: &Pubkey) -> Vec<(u64, Transaction)>
+where
+    I: IntoIterator<Item = Entry>,
+{
+    let mut num_ticks = 0;
+    let mut found = vec![];
+    for entry in entries {
+        if entry.transactions.is_empty() {
+            num_ticks += 1;
+            continue;
+        }
+        let slot = num_ticks / TICKS_PER_SLOT;
+        found.extend(
+            entry
+                .transactions
+                .into_iter()
+                .filter(|tx| {
+                    tx.from == *pubkey
+                        || tx.funding_pubkeys().contains(pubkey)
+                        || tx
+                            .final_payments()
+                            .iter()
+                            .any(|payment| payment.to == *pubkey)
+                })
+                .map(|tx| (slot, tx)),
+        );
+    }
+    found
+}
+
 /// Creates the next entries for given transactions, outputs
 /// updates start_hash to id of last Entry, sets num_hashes to 0
 pub fn next_entries_mut(
     start_hash: &mut Hash,
     num_hashes: &mut u64,
     transactions: Vec<Transaction>,
+) -> Vec<Entry> {
+    chunk_entries(start_hash, num_hashes, transactions, None)
+}
+
+/// Same as `next_entries_mut`, but `start_hash` is the PoH hash in `domain`, and
+/// is left at the seed of the Entry following the last one
+pub fn next_entries_mut_in_domain(
+    start_hash: &mut Hash,
+    num_hashes: &mut u64,
+    transactions: Vec<Transaction>,
+    domain: &PohDomain,
+) -> Vec<Entry> {
+    chunk_entries(start_hash, num_hashes, transactions, Some(domain))
+}
+
+fn chunk_entries(
+    start_hash: &mut Hash,
+    num_hashes: &mut u64,
+    transactions: Vec<Transaction>,
+    domain: Option<&PohDomain>,
 ) -> Vec<Entry> {
     // TODO: find a magic number that works better than |  ?
     //                                                  V
     if transactions.is_empty() || transactions.len() == 1 {
-        vec![Entry::new_mut(start_hash, num_hashes, transactions, false)]
+        vec![next_entry_mut(
+            start_hash,
+            num_hashes,
+            transactions,
+            false,
+            domain,
+        )]
     } else {
         let mut chunk_start = 0;
         let mut entries = Vec::new();
@@ -511,11 +830,12 @@ pub fn next_entries_mut(
                 }
                 chunk_end = next;
             }
-            entries.push(Entry::new_mut(
+            entries.push(next_entry_mut(
                 start_hash,
                 num_hashes,
                 transactions[chunk_start..chunk_end].to_vec(),
                 transactions.len() - chunk_end > 0,
+                domain,
             ));
             chunk_start = chunk_end;
         }
@@ -535,6 +855,18 @@ pub fn next_entries(
     next_entries_mut(&mut id, &mut num_hashes, transactions)
 }
 
+/// Creates the next Entries for given transactions in the PoH `domain`
+pub fn next_entries_in_domain(
+    last_id: &Hash,
+    num_hashes: u64,
+    transactions: Vec<Transaction>,
+    domain: &PohDomain,
+) -> Vec<Entry> {
+    let mut id = domain.seed(last_id);
+    let mut num_hashes = num_hashes;
+    next_entries_mut_in_domain(&mut id, &mut num_hashes, transactions, domain)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -600,6 +932,8 @@ mod tests {
             Vote {
                 version: 0,
                 contact_info_version: 1,
+                identity: None,
+                accounts_hash: None,
             },
             one,
             1,
@@ -655,6 +989,8 @@ mod tests {
             Vote {
                 version: 0,
                 contact_info_version: 2,
+                identity: None,
+                accounts_hash: None,
             },
             next_id,
             2,
@@ -667,7 +1003,7 @@ mod tests {
         assert!(tx_large_size < PACKET_DATA_SIZE as u64);
 
         // NOTE: if Entry grows to larger than a transaction, the code below falls over
-        let threshold = (BLOB_DATA_SIZE / PACKET_DATA_SIZE) - 1;
+        let threshold = BLOB_DATA_SIZE / tx_small_size as usize - 1;
 
         // verify no split
         let transactions = vec![tx_small.clone(); threshold];
@@ -695,6 +1031,72 @@ mod tests {
         assert!(entries0[0].has_more);
         assert!(!entries0[entries0.len() - 1].has_more);
         assert!(entries0.verify(&id));
+
+        // verify the split in a PoH domain
+        let domain = PohDomain::new(next_id, 0);
+        let entries0 = next_entries_in_domain(&id, 0, transactions.clone(), &domain);
+        assert!(entries0.len() >= 2);
+        assert!(entries0.verify_in_domain(&id, &domain));
+        assert!(!entries0.verify(&id));
+        assert!(!entries0.verify_in_domain(&id, &PohDomain::new(next_id, 1)));
+    }
+
+    #[test]
+    fn test_slot_entries() {
+        let zero = Hash::default();
+        let keypair = Keypair::new();
+        let tx = Transaction::new(&keypair, keypair.pubkey(), 1, zero);
+        let mut id = zero;
+        let mut entries = vec![];
+        for i in 0..TICKS_PER_SLOT * 2 + 1 {
+            if i == TICKS_PER_SLOT {
+                entries.push(next_entry(&id, 1, vec![tx.clone()]));
+                id = entries[entries.len() - 1].id;
+            }
+            entries.push(next_entry(&id, 1, vec![]));
+            id = entries[entries.len() - 1].id;
+        }
+
+        let ledger_path = tmp_ledger_path("test_slot_entries");
+        let (first, rest) = entries.split_at(TICKS_PER_SLOT as usize);
+        let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
+        writer.write_entries(first.to_vec()).unwrap();
+        let mut slots = SlotIndex::new(&ledger_path);
+        slots.update(|| true).unwrap();
+        assert_eq!(slots.slot_entries(1).unwrap(), None);
+
+        // an update only reads the entries written since the last one
+        writer.write_entries(rest.to_vec()).unwrap();
+        slots.update(|| true).unwrap();
+        let slot0 = slots.slot_entries(0).unwrap().unwrap();
+        assert_eq!(slot0.parent_slot, 0);
+        assert_eq!(slot0.previous_blockhash, zero);
+        assert_eq!(slot0.entries[..], entries[..TICKS_PER_SLOT as usize]);
+        assert_eq!(slot0.blockhash(), entries[TICKS_PER_SLOT as usize - 1].id);
+
+        // the transaction entry belongs to the slot of the tick after it
+        let slot1 = slots.slot_entries(1).unwrap().unwrap();
+        assert_eq!(slot1.parent_slot, 0);
+        assert_eq!(slot1.previous_blockhash, slot0.blockhash());
+        assert_eq!(slot1.entries.len(), TICKS_PER_SLOT as usize + 1);
+        assert_eq!(slot1.entries[0].transactions, vec![tx.clone()]);
+        assert_eq!(
+            find_transaction(entries.clone(), &tx.signature),
+            Some((1, tx.clone()))
+        );
+        assert_eq!(
+            find_transaction(entries.clone(), &Signature::default()),
+            None
+        );
+        assert_eq!(
+            find_transactions_for_address(entries.clone(), &keypair.pubkey()),
+            vec![(1, tx.clone())]
+        );
+        assert!(find_transactions_for_address(entries.clone(), &Pubkey::default()).is_empty());
+
+        // slot 2 isn't complete yet
+        assert_eq!(slots.slot_entries(2).unwrap(), None);
+        remove_dir_all(ledger_path).unwrap();
     }
 
     #[test]
@@ -705,7 +1107,7 @@ mod tests {
         let entries = make_tiny_test_entries(10);
 
         {
-            let mut writer = LedgerWriter::open(&ledger_path, true).unwrap();
+            let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
             writer.write_entries(entries.clone()).unwrap();
             // drops writer, flushes buffers
         }
@@ -727,6 +1129,10 @@ mod tests {
         }
         assert!(window.get_entry(100).is_err());
 
+        // the data file holds the entries as `Entry::batch_to_bytes` frames them
+        let data = std::fs::read(Path::new(&ledger_path).join("data")).unwrap();
+        assert_eq!(data, Entry::batch_to_bytes(&entries));
+
         std::fs::remove_file(Path::new(&ledger_path).join("data")).unwrap();
         // empty data file should fall over
         assert!(LedgerWindow::open(&ledger_path).is_err());
@@ -735,9 +1141,41 @@ mod tests {
         std::fs::remove_dir_all(ledger_path).unwrap();
     }
 
+    /// Append `entries` to `writer` in two batches, and read them back.
+    fn write_then_read<W>(mut writer: W, entries: &[Entry], reader: &LedgerReader) -> Vec<Entry>
+    where
+        W: LedgerWriter,
+    {
+        let (first, second) = entries.split_at(entries.len() / 2);
+        writer.append_entries(first).unwrap();
+        writer.append_entries(second).unwrap();
+        drop(writer);
+        reader
+            .read_entries()
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_ledger_traits() {
+        let entries = make_tiny_test_entries(10);
+
+        let ledger = MemLedger::default();
+        let read_entries = write_then_read(ledger.clone(), &entries, &ledger);
+        assert_eq!(read_entries, entries);
+        assert_eq!(ledger.entries(), entries);
+
+        let ledger_path = tmp_ledger_path("test_ledger_traits");
+        let writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
+        let reader = FileLedgerReader::new(&ledger_path);
+        assert_eq!(write_then_read(writer, &entries, &reader), entries);
+        std::fs::remove_dir_all(ledger_path).unwrap();
+    }
+
     fn truncated_last_entry(ledger_path: &str, entries: Vec<Entry>) {
         let len = {
-            let mut writer = LedgerWriter::open(&ledger_path, true).unwrap();
+            let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
             writer.write_entries(entries).unwrap();
             writer.data.seek(SeekFrom::Current(0)).unwrap()
         };
@@ -751,7 +1189,7 @@ mod tests {
     }
 
     fn garbage_on_data(ledger_path: &str, entries: Vec<Entry>) {
-        let mut writer = LedgerWriter::open(&ledger_path, true).unwrap();
+        let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
         writer.write_entries(entries).unwrap();
         writer.data.write_all(b"hi there!").unwrap();
     }
@@ -791,12 +1229,12 @@ mod tests {
         truncated_last_entry(&ledger_path, entries.clone());
         ledger_window_check(&ledger_path, entries.clone(), entries.len() - 1);
 
-        // restore last entry, tests recover_ledger() inside LedgerWriter::new()
+        // restore last entry, tests recover_ledger() inside FileLedgerWriter::new()
         truncated_last_entry(&ledger_path, entries.clone());
         // verify should fail at first
         assert!(verify_ledger(&ledger_path).is_err());
         {
-            let mut writer = LedgerWriter::recover(&ledger_path).unwrap();
+            let mut writer = FileLedgerWriter::recover(&ledger_path).unwrap();
             writer.write_entry(&entries[entries.len() - 1]).unwrap();
         }
         // and be fine after recover()
@@ -817,7 +1255,7 @@ mod tests {
         garbage_on_data(&ledger_path, entries[..entries.len() - 1].to_vec());
         assert!(verify_ledger(&ledger_path).is_err());
         {
-            let mut writer = LedgerWriter::recover(&ledger_path).unwrap();
+            let mut writer = FileLedgerWriter::recover(&ledger_path).unwrap();
             writer.write_entry(&entries[entries.len() - 1]).unwrap();
         }
         verify_ledger(&ledger_path).unwrap();
@@ -834,7 +1272,7 @@ mod tests {
         let entries = make_tiny_test_entries(10);
         let ledger_path = tmp_ledger_path("test_verify_ledger");
         {
-            let mut writer = LedgerWriter::open(&ledger_path, true).unwrap();
+            let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
             writer.write_entries(entries.clone()).unwrap();
         }
         // TODO more cases that make ledger_verify() fail
@@ -844,6 +1282,30 @@ mod tests {
         let _ignored = remove_dir_all(&ledger_path);
     }
 
+    #[test]
+    fn test_storage_paths() {
+        let ledger_path = tmp_ledger_path("test_storage_paths");
+        let snapshots_path = tmp_ledger_path("test_storage_paths_snapshots");
+        {
+            let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
+            writer.write_entries(make_tiny_test_entries(1)).unwrap();
+        }
+
+        let mut storage = StoragePaths::new(&ledger_path);
+        assert_eq!(storage.ledger, ledger_path);
+        storage.snapshots = snapshots_path.clone();
+        storage.create_dirs().unwrap();
+        assert!(Path::new(&storage.snapshots).is_dir());
+        assert!(Path::new(&storage.accounts).starts_with(&ledger_path));
+        assert!(Path::new(&storage.accounts).is_dir());
+
+        // the extra directories don't get in the way of reading the ledger
+        assert_eq!(read_ledger(&ledger_path, true).unwrap().count(), 1);
+
+        let _ignored = remove_dir_all(&ledger_path);
+        let _ignored = remove_dir_all(&snapshots_path);
+    }
+
     //    #[test]
     //    fn test_copy_ledger() {
     //        use logger;
@@ -852,7 +1314,7 @@ mod tests {
     //        let from = tmp_ledger_path("test_ledger_copy_from");
     //        let entries = make_tiny_test_entries(10);
     //
-    //        let mut writer = LedgerWriter::new(&from, true).unwrap();
+    //        let mut writer = FileLedgerWriter::new(&from, true).unwrap();
     //        writer.write_entries(entries.clone()).unwrap();
     //
     //        let to = tmp_ledger_path("test_ledger_copy_to");
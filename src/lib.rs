@@ -5,85 +5,204 @@
 //! command-line tools to spin up fullnodes and a Rust library
 //! (see [ThinClient](thin_client/struct.ThinClient.html)) to interact with them.
 //!
+//! The fullnode stages, along with the streamer, GPU and JSON RPC server, are built
+//! by the default `fullnode` feature. Wallets and exchanges that only need to build
+//! transactions and talk to a fullnode can build with `default-features = false`.
+//! Browser wallets can build and sign transactions for `wasm32-unknown-unknown` with
+//! `default-features = false`, which there also leaves out the ThinClient. The `wasm`
+//! feature takes the entropy for `KeypairUtil::new` from the browser; without it,
+//! keypairs have to come from `signature::keypair_from_seed` with a caller's seed.
+//!
 
 #![cfg_attr(feature = "unstable", feature(test))]
+#[cfg(not(target_arch = "wasm32"))]
 #[macro_use]
 pub mod counter;
+pub mod account;
+#[cfg(feature = "fullnode")]
+pub mod accounts_db;
+#[cfg(feature = "fullnode")]
+pub mod accounts_hash;
+#[cfg(feature = "fullnode")]
 pub mod bank;
+#[cfg(feature = "fullnode")]
+pub mod bank_forks;
+#[cfg(feature = "fullnode")]
 pub mod banking_stage;
+#[cfg(feature = "fullnode")]
 pub mod blob_fetch_stage;
+#[cfg(feature = "fullnode")]
+pub mod block_cost;
+#[cfg(feature = "fullnode")]
 pub mod broadcast_stage;
 pub mod budget;
+#[cfg(feature = "fullnode")]
 pub mod choose_gossip_peer_strategy;
+#[cfg(feature = "fullnode")]
 pub mod client;
+#[cfg(feature = "fullnode")]
+pub mod cpu_time;
+#[cfg(feature = "fullnode")]
 pub mod crdt;
+#[cfg(feature = "fullnode")]
 pub mod drone;
+#[cfg(feature = "fullnode")]
 pub mod entry;
+#[cfg(feature = "fullnode")]
+pub mod entry_stream;
+#[cfg(feature = "fullnode")]
 pub mod entry_writer;
-#[cfg(feature = "erasure")]
+#[cfg(all(feature = "erasure", feature = "fullnode"))]
 pub mod erasure;
+pub mod feature;
+#[cfg(feature = "fullnode")]
 pub mod fetch_stage;
+#[cfg(feature = "fullnode")]
 pub mod fullnode;
+#[cfg(feature = "fullnode")]
+pub mod handshake;
 pub mod hash;
+#[cfg(feature = "fullnode")]
+pub mod identity_lease;
+#[cfg(feature = "fullnode")]
+pub mod ipc_plugin;
+#[cfg(feature = "fullnode")]
+pub mod latency;
+#[cfg(feature = "fullnode")]
 pub mod ledger;
 pub mod logger;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod metrics;
+#[cfg(feature = "fullnode")]
 pub mod mint;
+#[cfg(feature = "fullnode")]
 pub mod nat;
+pub mod native_program;
+#[cfg(feature = "fullnode")]
 pub mod ncp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod network_preset;
+#[cfg(feature = "fullnode")]
 pub mod packet;
+#[cfg(feature = "fullnode")]
+pub mod packet_capture;
 pub mod payment_plan;
+#[cfg(feature = "fullnode")]
+pub mod peer_cache;
+#[cfg(feature = "fullnode")]
+pub mod port_check;
+#[cfg(feature = "fullnode")]
 pub mod record_stage;
+#[cfg(feature = "fullnode")]
 pub mod recorder;
+#[cfg(feature = "fullnode")]
+pub mod rent;
+#[cfg(feature = "fullnode")]
+pub mod replica;
+#[cfg(feature = "fullnode")]
+pub mod replay_stats;
+#[cfg(feature = "fullnode")]
 pub mod replicate_stage;
 pub mod request;
+#[cfg(feature = "fullnode")]
 pub mod request_processor;
+#[cfg(feature = "fullnode")]
 pub mod request_stage;
+#[cfg(feature = "fullnode")]
 pub mod result;
+#[cfg(feature = "fullnode")]
 pub mod retransmit_stage;
+#[cfg(feature = "fullnode")]
 pub mod rpc;
+#[cfg(feature = "fullnode")]
+pub mod rpc_pubsub;
+#[cfg(feature = "fullnode")]
+pub mod rpc_tls;
+#[cfg(feature = "fullnode")]
 pub mod rpu;
+#[cfg(feature = "fullnode")]
 pub mod service;
+#[cfg(feature = "fullnode")]
+pub mod sharded_sender;
 pub mod signature;
+#[cfg(feature = "fullnode")]
 pub mod sigverify;
+#[cfg(feature = "fullnode")]
 pub mod sigverify_stage;
+#[cfg(feature = "fullnode")]
+pub mod socket_stats;
+#[cfg(feature = "fullnode")]
 pub mod streamer;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod thin_client;
 pub mod timing;
+#[cfg(feature = "fullnode")]
 pub mod tpu;
 pub mod transaction;
+#[cfg(feature = "fullnode")]
 pub mod tvu;
+#[cfg(feature = "fullnode")]
+pub mod vote_ingest_stage;
+#[cfg(feature = "fullnode")]
 pub mod vote_stage;
+#[cfg(feature = "fullnode")]
 pub mod voting;
+#[cfg(feature = "fullnode")]
 pub mod wallet;
+#[cfg(feature = "fullnode")]
 pub mod window;
+#[cfg(feature = "fullnode")]
 pub mod write_stage;
+#[cfg(feature = "fullnode")]
+extern crate base64;
 extern crate bincode;
 extern crate bs58;
 extern crate byteorder;
 extern crate chrono;
+#[cfg(target_arch = "wasm32")]
+extern crate ed25519_dalek;
 extern crate generic_array;
 extern crate itertools;
+#[cfg(feature = "fullnode")]
 extern crate jsonrpc_core;
+#[cfg(feature = "fullnode")]
 #[macro_use]
 extern crate jsonrpc_macros;
+#[cfg(feature = "fullnode")]
 extern crate jsonrpc_http_server;
+#[cfg(feature = "fullnode")]
+extern crate jsonrpc_pubsub;
+#[cfg(feature = "fullnode")]
+extern crate jsonrpc_ws_server;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "fullnode")]
+extern crate memmap;
 extern crate rayon;
+#[cfg(not(target_arch = "wasm32"))]
+extern crate reqwest;
+#[cfg(not(target_arch = "wasm32"))]
 extern crate ring;
+#[cfg(feature = "fullnode")]
+extern crate rustls;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "fullnode")]
 extern crate pnet_datalink;
 extern crate serde_json;
 extern crate sha2;
+#[cfg(not(target_arch = "wasm32"))]
 extern crate sys_info;
+#[cfg(not(target_arch = "wasm32"))]
 extern crate untrusted;
+#[cfg(feature = "fullnode")]
+extern crate zstd;
 
 #[cfg(test)]
 #[macro_use]
 extern crate matches;
 
+#[cfg(not(target_arch = "wasm32"))]
 extern crate influx_db_client;
 extern crate rand;
@@ -1,17 +1,32 @@
 //! The `mint` module is a library for generating the chain's genesis block.
 
-use entry::Entry;
+use entry::{Entry, PohDomain};
+use feature::Feature;
 use hash::{hash, Hash};
 use ring::rand::SystemRandom;
 use signature::{Keypair, KeypairUtil, Pubkey};
 use transaction::Transaction;
 use untrusted::Input;
 
+/// Tokens the genesis block grants to an account other than the mint's.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Grant {
+    pub pubkey: Pubkey,
+    pub tokens: i64,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Mint {
     pub pkcs8: Vec<u8>,
     pubkey: Pubkey,
+    /// All the tokens in the genesis block, including the grants.
     pub tokens: i64,
+    /// Accounts funded from the mint's tokens by the genesis block.
+    #[serde(default)]
+    pub grants: Vec<Grant>,
+    /// Features active from the genesis block on.
+    #[serde(default)]
+    pub features: Vec<Feature>,
 }
 
 impl Mint {
@@ -23,6 +38,8 @@ impl Mint {
             pkcs8,
             pubkey,
             tokens,
+            grants: vec![],
+            features: vec![],
         }
     }
 
@@ -34,6 +51,20 @@ impl Mint {
         Self::new_with_pkcs8(tokens, pkcs8)
     }
 
+    /// A new mint of `tokens`, some of which the genesis block grants to other accounts.
+    pub fn new_with_grants(tokens: i64, grants: Vec<Grant>) -> Self {
+        let mut mint = Self::new(tokens);
+        mint.grants = grants;
+        mint
+    }
+
+    /// A new mint of `tokens` whose genesis block activates `features`.
+    pub fn new_with_features(tokens: i64, features: Vec<Feature>) -> Self {
+        let mut mint = Self::new(tokens);
+        mint.features = features;
+        mint
+    }
+
     pub fn seed(&self) -> Hash {
         hash(&self.pkcs8)
     }
@@ -50,10 +81,31 @@ impl Mint {
         self.pubkey
     }
 
+    /// The tokens left to the mint after the grants.
+    pub fn remaining_tokens(&self) -> i64 {
+        self.tokens - self.grants.iter().map(|grant| grant.tokens).sum::<i64>()
+    }
+
+    /// The genesis transactions: a deposit of all the tokens to the mint,
+    /// followed by a transfer from the mint for each grant and an activation
+    /// of each feature.
     pub fn create_transactions(&self) -> Vec<Transaction> {
         let keypair = self.keypair();
         let tx = Transaction::new(&keypair, self.pubkey(), self.tokens, self.seed());
-        vec![tx]
+        let grants = self
+            .grants
+            .iter()
+            .map(|grant| Transaction::new(&keypair, grant.pubkey, grant.tokens, self.seed()));
+        let features = self
+            .features
+            .iter()
+            .map(|feature| Transaction::new_activate_feature(&keypair, *feature, self.seed(), 0));
+        Some(tx).into_iter().chain(grants).chain(features).collect()
+    }
+
+    /// The PoH domain of a ledger that starts with this mint's entries.
+    pub fn poh_domain(&self) -> PohDomain {
+        PohDomain::new(self.create_entries()[0].id, 0)
     }
 
     pub fn create_entries(&self) -> Vec<Entry> {
@@ -74,7 +126,7 @@ mod tests {
     fn test_create_transactions() {
         let mut transactions = Mint::new(100).create_transactions().into_iter();
         let tx = transactions.next().unwrap();
-        if let Instruction::NewContract(contract) = tx.instruction {
+        if let Instruction::NewContract(contract) = &tx.instructions[0] {
             if let Plan::Budget(Budget::Pay(payment)) = contract.plan {
                 assert_eq!(tx.from, payment.to);
             }
@@ -82,6 +134,46 @@ mod tests {
         assert_eq!(transactions.next(), None);
     }
 
+    #[test]
+    fn test_create_transactions_with_grants() {
+        let grants = vec![
+            Grant {
+                pubkey: Keypair::new().pubkey(),
+                tokens: 10,
+            },
+            Grant {
+                pubkey: Keypair::new().pubkey(),
+                tokens: 20,
+            },
+        ];
+        let mint = Mint::new_with_grants(100, grants.clone());
+        assert_eq!(mint.remaining_tokens(), 70);
+
+        let transactions = mint.create_transactions();
+        assert_eq!(transactions.len(), 3);
+        for (tx, grant) in transactions[1..].iter().zip(&grants) {
+            assert_eq!(tx.from, mint.pubkey());
+            if let Instruction::NewContract(contract) = &tx.instructions[0] {
+                assert_eq!(contract.tokens, grant.tokens);
+                if let Plan::Budget(Budget::Pay(payment)) = &contract.plan {
+                    assert_eq!(payment.to, grant.pubkey);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_create_transactions_with_features() {
+        let mint = Mint::new_with_features(100, vec![Feature::FeePayer]);
+        let transactions = mint.create_transactions();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[1].from, mint.pubkey());
+        assert_eq!(
+            transactions[1].instructions,
+            vec![Instruction::ActivateFeature(Feature::FeePayer)]
+        );
+    }
+
     #[test]
     fn test_verify_entries() {
         let entries = Mint::new(100).create_entries();
@@ -0,0 +1,69 @@
+//! The `native_program` module names the programs built into the bank. Every
+//! instruction belongs to one of them, and the bank runs it with the handler
+//! it registered under that program's ID.
+
+use signature::Pubkey;
+
+/// A program whose instructions the bank runs with native Rust code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NativeProgram {
+    /// Manages accounts' owners and userdata, durable nonces, and the
+    /// cluster's features.
+    System,
+    /// Moves tokens by budget payment plans, from plain transfers to contracts
+    /// waiting on a witness.
+    Budget,
+    /// Records votes, and the nodes authorized to vote for identities.
+    Vote,
+}
+
+/// Every native program, in the order they were added.
+pub const NATIVE_PROGRAMS: &[NativeProgram] = &[
+    NativeProgram::System,
+    NativeProgram::Budget,
+    NativeProgram::Vote,
+];
+
+impl NativeProgram {
+    /// The program's ID. Native programs are numbered from one, leaving out
+    /// `Pubkey::default()`, which an account's owner is until it's assigned.
+    pub fn id(self) -> Pubkey {
+        let mut id = [0; 32];
+        id[0] = match self {
+            NativeProgram::System => 1,
+            NativeProgram::Budget => 2,
+            NativeProgram::Vote => 3,
+        };
+        Pubkey::new(&id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NativeProgram::System => "system",
+            NativeProgram::Budget => "budget",
+            NativeProgram::Vote => "vote",
+        }
+    }
+
+    /// The native program with ID `id`, if there is one.
+    pub fn from_id(id: &Pubkey) -> Option<NativeProgram> {
+        NATIVE_PROGRAMS
+            .iter()
+            .cloned()
+            .find(|program| program.id() == *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_native_program_ids() {
+        for program in NATIVE_PROGRAMS {
+            assert_ne!(program.id(), Pubkey::default());
+            assert_eq!(NativeProgram::from_id(&program.id()), Some(*program));
+        }
+        assert_eq!(NativeProgram::from_id(&Pubkey::default()), None);
+    }
+}
@@ -0,0 +1,117 @@
+//! The `network_preset` module names the clusters a wallet or ThinClient can be
+//! pointed at with `--url testnet|devnet|localhost`, in place of a leader.json.
+
+use hash::Hash;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+/// The addresses of a cluster's entrypoint leader, with the ports a leader
+/// binds by default, and the hash of the cluster's genesis entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkPreset {
+    pub name: &'static str,
+    tpu: &'static str,
+    rpu: &'static str,
+    rpc: &'static str,
+    drone: &'static str,
+    /// A client refuses a cluster with any other genesis hash. Left out until
+    /// the cluster is launched with a genesis it keeps across restarts.
+    genesis_hash: Option<&'static str>,
+}
+
+pub const TESTNET: NetworkPreset = NetworkPreset {
+    name: "testnet",
+    tpu: "testnet.solana.com:8000",
+    rpu: "testnet.solana.com:8003",
+    rpc: "testnet.solana.com:8899",
+    drone: "testnet.solana.com:9900",
+    genesis_hash: None,
+};
+
+pub const DEVNET: NetworkPreset = NetworkPreset {
+    name: "devnet",
+    tpu: "devnet.solana.com:8000",
+    rpu: "devnet.solana.com:8003",
+    rpc: "devnet.solana.com:8899",
+    drone: "devnet.solana.com:9900",
+    genesis_hash: None,
+};
+
+/// A leader and drone started on this machine, as `multinode-demo` does.
+pub const LOCALHOST: NetworkPreset = NetworkPreset {
+    name: "localhost",
+    tpu: "127.0.0.1:8000",
+    rpu: "127.0.0.1:8003",
+    rpc: "127.0.0.1:8899",
+    drone: "127.0.0.1:9900",
+    genesis_hash: None,
+};
+
+pub const NETWORK_PRESETS: [NetworkPreset; 3] = [TESTNET, DEVNET, LOCALHOST];
+
+/// The preset called `name`. `localnet` is another name for `localhost`.
+pub fn network_preset(name: &str) -> Option<NetworkPreset> {
+    let name = match name {
+        "localnet" => "localhost",
+        name => name,
+    };
+    NETWORK_PRESETS
+        .iter()
+        .find(|preset| preset.name == name)
+        .cloned()
+}
+
+fn resolve(addr: &str) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?.next().ok_or_else(|| {
+        let msg = format!("{} doesn't resolve to any address", addr);
+        io::Error::new(io::ErrorKind::NotFound, msg)
+    })
+}
+
+impl NetworkPreset {
+    /// Where the entrypoint leader takes transactions.
+    pub fn tpu_addr(&self) -> io::Result<SocketAddr> {
+        resolve(self.tpu)
+    }
+
+    /// Where the entrypoint leader answers ThinClient requests.
+    pub fn rpu_addr(&self) -> io::Result<SocketAddr> {
+        resolve(self.rpu)
+    }
+
+    /// The entrypoint leader's JSON RPC service.
+    pub fn rpc_addr(&self) -> io::Result<SocketAddr> {
+        resolve(self.rpc)
+    }
+
+    /// The cluster's drone, which airdrops tokens.
+    pub fn drone_addr(&self) -> io::Result<SocketAddr> {
+        resolve(self.drone)
+    }
+
+    /// The hash of the cluster's genesis entry, if the preset pins one.
+    pub fn genesis_hash(&self) -> Option<Hash> {
+        self.genesis_hash
+            .map(|hash| hash.parse().expect("preset genesis hash"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_preset() {
+        assert_eq!(network_preset("testnet"), Some(TESTNET));
+        assert_eq!(network_preset("devnet"), Some(DEVNET));
+        assert_eq!(network_preset("localnet"), Some(LOCALHOST));
+        assert_eq!(network_preset("mainnet"), None);
+
+        let rpc_addr: SocketAddr = "127.0.0.1:8899".parse().unwrap();
+        assert_eq!(LOCALHOST.rpc_addr().unwrap(), rpc_addr);
+        for preset in &NETWORK_PRESETS {
+            // pinned hashes must parse
+            preset.genesis_hash();
+        }
+    }
+}
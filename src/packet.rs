@@ -13,6 +13,7 @@ use std::mem::size_of;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
 pub type SharedPackets = Arc<RwLock<Packets>>;
 pub type SharedBlob = Arc<RwLock<Blob>>;
@@ -23,7 +24,7 @@ pub type BlobRecycler = Recycler<Blob>;
 pub const NUM_PACKETS: usize = 1024 * 8;
 pub const BLOB_SIZE: usize = (64 * 1024 - 128); // wikipedia says there should be 20b for ipv4 headers
 pub const BLOB_DATA_SIZE: usize = BLOB_SIZE - (BLOB_HEADER_SIZE * 2);
-pub const PACKET_DATA_SIZE: usize = 256;
+pub const PACKET_DATA_SIZE: usize = 512;
 pub const NUM_BLOBS: usize = (NUM_PACKETS * PACKET_DATA_SIZE) / BLOB_SIZE;
 
 #[derive(Clone, Default, Debug, PartialEq)]
@@ -115,6 +116,8 @@ impl Meta {
 #[derive(Debug)]
 pub struct Packets {
     pub packets: Vec<Packet>,
+    /// When the first packet was read off the socket, if they came from one
+    pub recv_time: Option<Instant>,
 }
 
 //auto derive doesn't support large arrays
@@ -122,6 +125,7 @@ impl Default for Packets {
     fn default() -> Packets {
         Packets {
             packets: vec![Packet::default(); NUM_PACKETS],
+            recv_time: None,
         }
     }
 }
@@ -131,6 +135,7 @@ impl Reset for Packets {
         for i in 0..self.packets.len() {
             self.packets[i].reset();
         }
+        self.recv_time = None;
     }
 }
 
@@ -260,6 +265,7 @@ impl Packets {
                     p.meta.set_addr(&from);
                     trace!("got {} bytes from {}", nrecv, from);
                     if i == 0 {
+                        self.recv_time = Some(Instant::now());
                         socket.set_nonblocking(true)?;
                     }
                 }
@@ -340,7 +346,8 @@ pub fn to_blobs<T: Serialize>(
 }
 
 const BLOB_INDEX_END: usize = size_of::<u64>();
-const BLOB_ID_END: usize = BLOB_INDEX_END + size_of::<usize>() + size_of::<Pubkey>();
+const BLOB_SLOT_END: usize = BLOB_INDEX_END + size_of::<u64>();
+const BLOB_ID_END: usize = BLOB_SLOT_END + size_of::<usize>() + size_of::<Pubkey>();
 const BLOB_FLAGS_END: usize = BLOB_ID_END + size_of::<u32>();
 const BLOB_SIZE_END: usize = BLOB_FLAGS_END + size_of::<u64>();
 
@@ -365,16 +372,28 @@ impl Blob {
         self.data[..BLOB_INDEX_END].clone_from_slice(&wtr);
         Ok(())
     }
+    /// the leader slot the blob's entry was recorded in
+    pub fn get_slot(&self) -> Result<u64> {
+        let mut rdr = io::Cursor::new(&self.data[BLOB_INDEX_END..BLOB_SLOT_END]);
+        let r = rdr.read_u64::<LittleEndian>()?;
+        Ok(r)
+    }
+    pub fn set_slot(&mut self, slot: u64) -> Result<()> {
+        let mut wtr = vec![];
+        wtr.write_u64::<LittleEndian>(slot)?;
+        self.data[BLOB_INDEX_END..BLOB_SLOT_END].clone_from_slice(&wtr);
+        Ok(())
+    }
     /// sender id, we use this for identifying if its a blob from the leader that we should
     /// retransmit.  eventually blobs should have a signature that we can use ffor spam filtering
     pub fn get_id(&self) -> Result<Pubkey> {
-        let e = deserialize(&self.data[BLOB_INDEX_END..BLOB_ID_END])?;
+        let e = deserialize(&self.data[BLOB_SLOT_END..BLOB_ID_END])?;
         Ok(e)
     }
 
     pub fn set_id(&mut self, id: Pubkey) -> Result<()> {
         let wtr = serialize(&id)?;
-        self.data[BLOB_INDEX_END..BLOB_ID_END].clone_from_slice(&wtr);
+        self.data[BLOB_SLOT_END..BLOB_ID_END].clone_from_slice(&wtr);
         Ok(())
     }
 
@@ -653,9 +672,12 @@ mod tests {
         let mut b = Blob::default();
         b.set_index(<u64>::max_value()).unwrap();
         assert_eq!(b.get_index().unwrap(), <u64>::max_value());
+        b.set_slot(7).unwrap();
+        assert_eq!(b.get_slot().unwrap(), 7);
         b.data_mut()[0] = 1;
         assert_eq!(b.data()[0], 1);
         assert_eq!(b.get_index().unwrap(), <u64>::max_value());
+        assert_eq!(b.get_slot().unwrap(), 7);
         b.reset();
         assert!(b.data[..BLOB_HEADER_SIZE].starts_with(&[0u8; BLOB_HEADER_SIZE]));
         assert_eq!(b.meta, Meta::default());
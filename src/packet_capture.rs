@@ -0,0 +1,337 @@
+//! The `packet_capture` module mirrors the raw packets and blobs a node
+//! receives, before any stage looks at them, to a pcap file or to another UDP
+//! address. It's for debugging the protocol between specific peers without
+//! needing capture tools on the host. Capture is off until started through the
+//! admin RPC, and until then costs each receive one atomic load.
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use packet::{Packets, SharedBlobs};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The pcap link type of packets that start with their IPv4 or IPv6 header.
+const LINKTYPE_RAW: u32 = 101;
+
+/// The most bytes of one packet a pcap file keeps.
+const SNAPLEN: u32 = 65_535;
+
+const IPPROTO_UDP: u8 = 17;
+
+/// Where captured packets go.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureTarget {
+    /// Written to a new pcap file at this path, behind IP and UDP headers
+    /// rebuilt from the addresses they were sent from and to.
+    Pcap(String),
+    /// Sent on as they are, payload only, to this address.
+    Udp(SocketAddr),
+}
+
+impl CaptureTarget {
+    /// A target that parses as a socket address is mirrored to over UDP, and
+    /// anything else is taken as the path of a pcap file.
+    pub fn parse(target: &str) -> Self {
+        match target.parse() {
+            Ok(addr) => CaptureTarget::Udp(addr),
+            Err(_) => CaptureTarget::Pcap(target.to_string()),
+        }
+    }
+}
+
+enum Sink {
+    Pcap(BufWriter<File>),
+    Udp(UdpSocket, SocketAddr),
+}
+
+struct Capture {
+    sink: Sink,
+    /// Only packets from these addresses are captured, or from any if empty.
+    peers: Vec<IpAddr>,
+    count: u64,
+}
+
+impl Capture {
+    fn record(&mut self, from: SocketAddr, to: SocketAddr, data: &[u8]) {
+        if !self.peers.is_empty() && !self.peers.contains(&from.ip()) {
+            return;
+        }
+        let result = match &mut self.sink {
+            Sink::Pcap(file) => write_pcap_record(file, from, to, data),
+            Sink::Udp(sock, addr) => sock.send_to(data, *addr).map(|_| ()),
+        };
+        match result {
+            Ok(()) => self.count += 1,
+            Err(e) => warn!("packet capture error: {:?}", e),
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Sink::Pcap(file) = &mut self.sink {
+            if let Err(e) = file.flush() {
+                warn!("packet capture flush error: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Whether a capture is running, checked before taking the capture's lock.
+static CAPTURING: AtomicBool = ATOMIC_BOOL_INIT;
+
+fn get_singleton_capture() -> Arc<Mutex<Option<Capture>>> {
+    static INIT: Once = ONCE_INIT;
+    static mut CAPTURE: Option<Arc<Mutex<Option<Capture>>>> = None;
+    unsafe {
+        INIT.call_once(|| CAPTURE = Some(Arc::new(Mutex::new(None))));
+        match CAPTURE {
+            Some(ref capture) => capture.clone(),
+            None => panic!("Failed to initialize packet capture"),
+        }
+    }
+}
+
+/// Capture the packets received from `peers`, or from anyone if it's empty, to
+/// `target`, stopping any capture already running.
+pub fn start(target: &CaptureTarget, peers: Vec<IpAddr>) -> io::Result<()> {
+    let sink = match target {
+        CaptureTarget::Pcap(path) => {
+            let mut file = BufWriter::new(File::create(path)?);
+            write_pcap_header(&mut file)?;
+            Sink::Pcap(file)
+        }
+        CaptureTarget::Udp(addr) => {
+            let bind_addr = if addr.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            Sink::Udp(UdpSocket::bind(bind_addr)?, *addr)
+        }
+    };
+    let capture = get_singleton_capture();
+    let mut capture = capture.lock().unwrap();
+    if let Some(mut old) = capture.take() {
+        old.flush();
+    }
+    *capture = Some(Capture {
+        sink,
+        peers,
+        count: 0,
+    });
+    CAPTURING.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Stop capturing, returning how many packets were captured, or `None` if no
+/// capture was running.
+pub fn stop() -> Option<u64> {
+    let capture = get_singleton_capture();
+    let mut capture = capture.lock().unwrap();
+    CAPTURING.store(false, Ordering::Relaxed);
+    capture.take().map(|mut capture| {
+        capture.flush();
+        capture.count
+    })
+}
+
+/// Run `f` on the running capture, if any, with the address `sock` is bound to.
+fn with_capture<F>(sock: &UdpSocket, f: F)
+where
+    F: FnOnce(&mut Capture, SocketAddr),
+{
+    if !CAPTURING.load(Ordering::Relaxed) {
+        return;
+    }
+    let local = match sock.local_addr() {
+        Ok(local) => local,
+        Err(_) => return,
+    };
+    let capture = get_singleton_capture();
+    let mut capture = capture.lock().unwrap();
+    if let Some(capture) = capture.as_mut() {
+        f(capture, local);
+        capture.flush();
+    }
+}
+
+/// Capture `packets`, which `sock` just received.
+pub fn capture_packets(sock: &UdpSocket, packets: &Packets) {
+    with_capture(sock, |capture, local| {
+        for packet in &packets.packets {
+            capture.record(packet.meta.addr(), local, &packet.data[..packet.meta.size]);
+        }
+    });
+}
+
+/// Capture `blobs`, which `sock` just received.
+pub fn capture_blobs(sock: &UdpSocket, blobs: &SharedBlobs) {
+    with_capture(sock, |capture, local| {
+        for blob in blobs {
+            let blob = blob.read().unwrap();
+            capture.record(blob.meta.addr(), local, &blob.data[..blob.meta.size]);
+        }
+    });
+}
+
+fn write_pcap_header<W: Write>(w: &mut W) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(0xa1b2_c3d4)?;
+    w.write_u16::<LittleEndian>(2)?;
+    w.write_u16::<LittleEndian>(4)?;
+    // the timestamps are UTC, to microseconds
+    w.write_i32::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(0)?;
+    w.write_u32::<LittleEndian>(SNAPLEN)?;
+    w.write_u32::<LittleEndian>(LINKTYPE_RAW)
+}
+
+fn write_pcap_record<W: Write>(
+    w: &mut W,
+    from: SocketAddr,
+    to: SocketAddr,
+    data: &[u8],
+) -> io::Result<()> {
+    let mut packet = ip_udp_headers(from, to, data.len());
+    packet.extend_from_slice(data);
+    let orig_len = packet.len() as u32;
+    packet.truncate(SNAPLEN as usize);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    w.write_u32::<LittleEndian>(now.as_secs() as u32)?;
+    w.write_u32::<LittleEndian>(now.subsec_micros())?;
+    w.write_u32::<LittleEndian>(packet.len() as u32)?;
+    w.write_u32::<LittleEndian>(orig_len)?;
+    w.write_all(&packet)
+}
+
+/// The IP and UDP headers of a datagram of `len` bytes sent from `from` to
+/// `to`. IPv4 addresses are mapped to IPv6 ones if the other address is IPv6.
+fn ip_udp_headers(from: SocketAddr, to: SocketAddr, len: usize) -> Vec<u8> {
+    let udp_len = (8 + len).min(0xffff);
+    let mut headers = vec![];
+    match (from.ip(), to.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut ip = vec![0x45, 0];
+            ip.write_u16::<BigEndian>((20 + udp_len).min(0xffff) as u16)
+                .unwrap();
+            // no id, don't fragment, a ttl of 64
+            ip.extend_from_slice(&[0, 0, 0x40, 0, 64, IPPROTO_UDP, 0, 0]);
+            ip.extend_from_slice(&src.octets());
+            ip.extend_from_slice(&dst.octets());
+            let checksum = ipv4_checksum(&ip);
+            ip[10] = (checksum >> 8) as u8;
+            ip[11] = checksum as u8;
+            headers.extend(ip);
+        }
+        (src, dst) => {
+            let ipv6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            headers.write_u32::<BigEndian>(0x6000_0000).unwrap();
+            headers.write_u16::<BigEndian>(udp_len as u16).unwrap();
+            headers.extend_from_slice(&[IPPROTO_UDP, 64]);
+            headers.extend_from_slice(&ipv6(src).octets());
+            headers.extend_from_slice(&ipv6(dst).octets());
+        }
+    }
+    headers.write_u16::<BigEndian>(from.port()).unwrap();
+    headers.write_u16::<BigEndian>(to.port()).unwrap();
+    headers.write_u16::<BigEndian>(udp_len as u16).unwrap();
+    // a zero checksum tells readers it wasn't computed
+    headers.write_u16::<BigEndian>(0).unwrap();
+    headers
+}
+
+/// The checksum of an IPv4 header whose own checksum field is zero.
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | u32::from(word[1]))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet::Packet;
+    use signature::{Keypair, KeypairUtil};
+    use std::env;
+    use std::fs;
+
+    fn tmp_capture_path(name: &str) -> String {
+        let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
+        let keypair = Keypair::new();
+        format!("{}/tmp-capture-{}-{}.pcap", out_dir, name, keypair.pubkey())
+    }
+
+    #[test]
+    fn test_capture_target_parse() {
+        let addr: SocketAddr = "127.0.0.1:8000".parse().unwrap();
+        assert_eq!(
+            CaptureTarget::parse("127.0.0.1:8000"),
+            CaptureTarget::Udp(addr)
+        );
+        assert_eq!(
+            CaptureTarget::parse("/tmp/node.pcap"),
+            CaptureTarget::Pcap("/tmp/node.pcap".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ip_udp_headers() {
+        let from: SocketAddr = "10.0.0.1:8001".parse().unwrap();
+        let to: SocketAddr = "10.0.0.2:8002".parse().unwrap();
+        let headers = ip_udp_headers(from, to, 100);
+        assert_eq!(headers.len(), 28);
+        assert_eq!(&headers[2..4], &[0, 128]);
+        // a header with its checksum in place sums to all ones
+        assert_eq!(ipv4_checksum(&headers[..20]), 0);
+        assert_eq!(&headers[20..26], &[0x1f, 0x41, 0x1f, 0x42, 0, 108]);
+
+        let to: SocketAddr = "[::1]:8002".parse().unwrap();
+        let headers = ip_udp_headers(from, to, 100);
+        assert_eq!(headers.len(), 48);
+        assert_eq!(headers[0] >> 4, 6);
+        assert_eq!(&headers[4..6], &[0, 108]);
+    }
+
+    #[test]
+    fn test_capture_to_pcap() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let peer: SocketAddr = "10.0.0.1:8001".parse().unwrap();
+        let mut packets = Packets::default();
+        let mut packet = Packet::default();
+        packet.meta.size = 10;
+        packet.meta.set_addr(&peer);
+        packets.packets = vec![packet.clone(), packet];
+
+        // nothing is captured until started
+        capture_packets(&sock, &packets);
+        assert_eq!(stop(), None);
+
+        let path = tmp_capture_path("test_capture_to_pcap");
+        start(&CaptureTarget::Pcap(path.clone()), vec![]).unwrap();
+        capture_packets(&sock, &packets);
+        assert_eq!(stop(), Some(2));
+        let len = fs::metadata(&path).unwrap().len();
+        assert_eq!(len, 24 + 2 * (16 + 28 + 10));
+
+        // packets from other peers are left out
+        start(
+            &CaptureTarget::Pcap(path.clone()),
+            vec!["10.0.0.2".parse().unwrap()],
+        )
+        .unwrap();
+        capture_packets(&sock, &packets);
+        assert_eq!(stop(), Some(0));
+        fs::remove_file(path).unwrap();
+    }
+}
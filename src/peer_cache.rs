@@ -0,0 +1,166 @@
+//! The `peer_cache` module keeps the contact info of the peers a node has
+//! recently heard from on disk, so that a restarting node can rejoin through
+//! them even while its configured entry point is down.
+
+use crdt::{Crdt, NodeInfo};
+use serde_json;
+use service::Service;
+use signature::Pubkey;
+use std::fs::{self, File};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, sleep, Builder, JoinHandle};
+use std::time::Duration;
+use timing::timestamp;
+
+/// How often the peers are saved.
+const SAVE_INTERVAL_MS: u64 = 60_000;
+
+/// Peers not heard from for this long aren't saved.
+const MAX_PEER_AGE_MS: u64 = 15_000;
+
+/// The peer cache of the node whose ledger is at `ledger_path`.
+pub fn peer_cache_path(ledger_path: &str) -> String {
+    format!("{}/peers.json", ledger_path)
+}
+
+/// The peers in `crdt` that have been heard from recently, other than this node
+/// and entry points whose identity isn't known yet.
+pub fn live_peers(crdt: &Crdt) -> Vec<NodeInfo> {
+    let now = timestamp();
+    crdt.table
+        .values()
+        .filter(|v| v.id != crdt.me && v.id != Pubkey::default())
+        .filter(|v| Crdt::is_valid_address(v.contact_info.ncp))
+        .filter(|v| match crdt.alive.get(&v.id) {
+            Some(last) => now.saturating_sub(*last) <= MAX_PEER_AGE_MS,
+            None => false,
+        })
+        .cloned()
+        .collect()
+}
+
+/// Write `peers` to `path`, replacing the previous cache in one step so a
+/// crash can't leave it half written.
+pub fn save_peers(path: &str, peers: &[NodeInfo]) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let file = File::create(&tmp_path)?;
+    serde_json::to_writer(file, peers)?;
+    fs::rename(tmp_path, path)
+}
+
+/// Read the peers saved at `path`.
+pub fn load_peers(path: &str) -> io::Result<Vec<NodeInfo>> {
+    let file = File::open(path)?;
+    let peers = serde_json::from_reader(file)?;
+    Ok(peers)
+}
+
+/// Insert the peers cached at `path` into `crdt`, returning how many there were.
+/// A missing or unreadable cache only means there's nothing to add.
+pub fn insert_cached_peers(crdt: &RwLock<Crdt>, path: &str) -> usize {
+    match load_peers(path) {
+        Ok(peers) => {
+            let mut crdt = crdt.write().unwrap();
+            for peer in &peers {
+                crdt.insert(peer);
+            }
+            info!("loaded {} peers from {}", peers.len(), path);
+            peers.len()
+        }
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("failed to load peer cache {}: {:?}", path, e);
+            }
+            0
+        }
+    }
+}
+
+/// Periodically saves the live peers in `crdt`, and once more on exit.
+pub struct PeerCacheService {
+    thread_hdl: JoinHandle<()>,
+}
+
+impl PeerCacheService {
+    pub fn new(crdt: Arc<RwLock<Crdt>>, path: String, exit: Arc<AtomicBool>) -> Self {
+        let thread_hdl = Builder::new()
+            .name("solana-peer-cache".to_string())
+            .spawn(move || {
+                let poll = Duration::from_millis(100);
+                let mut elapsed = Duration::default();
+                while !exit.load(Ordering::Relaxed) {
+                    sleep(poll);
+                    elapsed += poll;
+                    if elapsed >= Duration::from_millis(SAVE_INTERVAL_MS) {
+                        elapsed = Duration::default();
+                        Self::save(&crdt, &path);
+                    }
+                }
+                Self::save(&crdt, &path);
+            })
+            .unwrap();
+        PeerCacheService { thread_hdl }
+    }
+
+    fn save(crdt: &RwLock<Crdt>, path: &str) {
+        let peers = live_peers(&crdt.read().unwrap());
+        // keep the previous cache rather than forget every peer while isolated
+        if peers.is_empty() {
+            return;
+        }
+        if let Err(e) = save_peers(path, &peers) {
+            warn!("failed to save peer cache {}: {:?}", path, e);
+        }
+    }
+}
+
+impl Service for PeerCacheService {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        vec![self.thread_hdl]
+    }
+
+    fn join(self) -> thread::Result<()> {
+        self.thread_hdl.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crdt::{Crdt, NodeInfo};
+    use peer_cache::{insert_cached_peers, live_peers, load_peers, save_peers};
+    use signature::{Keypair, KeypairUtil};
+    use std::env;
+    use std::fs::remove_file;
+    use std::sync::RwLock;
+
+    #[test]
+    fn test_peer_cache() {
+        let path = format!(
+            "{}/tmp-peers-{}.json",
+            env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string()),
+            Keypair::new().pubkey()
+        );
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let peer = NodeInfo::new_leader(&"127.0.0.1:2345".parse().unwrap());
+        let mut crdt = Crdt::new(me.clone()).unwrap();
+        crdt.insert(&NodeInfo::new_entry_point(
+            "127.0.0.1:3456".parse().unwrap(),
+        ));
+        crdt.insert(&peer);
+
+        // neither this node nor the anonymous entry point are cached
+        let peers = live_peers(&crdt);
+        assert_eq!(peers, vec![peer.clone()]);
+        save_peers(&path, &peers).unwrap();
+        assert_eq!(load_peers(&path).unwrap(), peers);
+
+        let restarted = RwLock::new(Crdt::new(me).unwrap());
+        assert_eq!(insert_cached_peers(&restarted, &path), 1);
+        assert_eq!(restarted.read().unwrap().table[&peer.id], peer);
+
+        remove_file(&path).unwrap();
+        assert_eq!(insert_cached_peers(&restarted, &path), 0);
+    }
+}
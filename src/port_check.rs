@@ -0,0 +1,136 @@
+//! The `port_check` module is a startup self-test of a node's advertised ports.
+//! The node asks a gossip entry point to send a probe to each of them and
+//! reports the ones the probe never reaches, which is nearly always a firewall
+//! or NAT problem rather than anything wrong with the node itself.
+
+use bincode::deserialize;
+use crdt::{Crdt, TestNode};
+use rand::{thread_rng, Rng};
+use result::Result;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How long to wait for the entry point's probes
+pub const PORT_CHECK_TIMEOUT_MS: u64 = 2_000;
+
+/// Wait on `sock` until a probe carrying `nonce` arrives or `deadline` passes
+fn wait_for_probe(sock: &UdpSocket, nonce: u64, deadline: Instant) -> Result<bool> {
+    let old_timeout = sock.read_timeout()?;
+    let mut buf = [0u8; 64];
+    let mut found = false;
+    loop {
+        let now = Instant::now();
+        // ports checked after the deadline still get to read what has already arrived
+        let wait = if deadline > now {
+            deadline - now
+        } else {
+            Duration::from_millis(10)
+        };
+        sock.set_read_timeout(Some(wait))?;
+        match sock.recv_from(&mut buf) {
+            Ok((size, _)) => {
+                if deserialize::<u64>(&buf[..size]).ok() == Some(nonce) {
+                    found = true;
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    sock.set_read_timeout(old_timeout)?;
+    Ok(found)
+}
+
+/// Throw away anything still queued on `sock`, so no probe reaches the stages
+/// the socket is handed to
+fn drain(sock: &UdpSocket) -> Result<()> {
+    let mut buf = [0u8; 64];
+    sock.set_nonblocking(true)?;
+    while sock.recv_from(&mut buf).is_ok() {}
+    sock.set_nonblocking(false)?;
+    Ok(())
+}
+
+/// Ask `entry_point` to probe the node's advertised gossip, replicate, repair,
+/// transaction, request and vote ports and return the name and address of each
+/// port that wasn't reached. The entry point only probes addresses on the IP the
+/// request comes from, so ports advertised on any other IP are reported
+/// unreachable. The retransmit socket only sends, so it isn't checked.
+pub fn check_ports(
+    node: &TestNode,
+    entry_point: &SocketAddr,
+) -> Result<Vec<(&'static str, SocketAddr)>> {
+    let contact_info = &node.data.contact_info;
+    let mut ports = vec![
+        ("gossip", &node.sockets.gossip, contact_info.ncp),
+        ("replicate", &node.sockets.replicate, contact_info.tvu),
+        ("repair", &node.sockets.repair, contact_info.tvu_window),
+        ("transaction", &node.sockets.transaction, contact_info.tpu),
+        ("requests", &node.sockets.requests, contact_info.rpu),
+    ];
+    if let (Some(sock), Some(addr)) = (&node.sockets.vote, contact_info.tpu_vote) {
+        ports.push(("vote", sock, addr));
+    }
+    let addrs: Vec<_> = ports.iter().map(|(_, _, addr)| *addr).collect();
+
+    let nonce = thread_rng().gen();
+    let req = Crdt::port_check_request(&node.data, &addrs, nonce)?;
+    UdpSocket::bind("0.0.0.0:0")?.send_to(&req, entry_point)?;
+
+    let deadline = Instant::now() + Duration::from_millis(PORT_CHECK_TIMEOUT_MS);
+    let mut unreachable = vec![];
+    for (name, sock, addr) in &ports {
+        if !wait_for_probe(sock, nonce, deadline)? {
+            warn!("{} port {} is unreachable from {}", name, addr, entry_point);
+            unreachable.push((*name, *addr));
+        }
+    }
+    for (_, sock, _) in &ports {
+        drain(sock)?;
+    }
+    Ok(unreachable)
+}
+
+#[cfg(test)]
+mod tests {
+    use crdt::{Crdt, TestNode};
+    use logger;
+    use ncp::Ncp;
+    use port_check::check_ports;
+    use service::Service;
+    use std::net::UdpSocket;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, RwLock};
+    use window::default_window;
+
+    #[test]
+    fn test_check_ports() {
+        logger::setup();
+        let entry = TestNode::new_localhost();
+        let entry_point = entry.data.contact_info.ncp;
+        let crdt = Arc::new(RwLock::new(Crdt::new(entry.data).expect("Crdt::new")));
+        let exit = Arc::new(AtomicBool::new(false));
+        let ncp = Ncp::new(
+            &crdt,
+            default_window(),
+            None,
+            entry.sockets.gossip,
+            entry.sockets.gossip_send,
+            exit.clone(),
+        ).expect("Ncp::new");
+
+        let node = TestNode::new_localhost();
+        assert_eq!(check_ports(&node, &entry_point).unwrap(), vec![]);
+
+        // advertise a repair port that isn't the node's repair socket
+        let mut node = TestNode::new_localhost();
+        let other = UdpSocket::bind("127.0.0.1:0").unwrap();
+        node.data.contact_info.tvu_window = other.local_addr().unwrap();
+        let unreachable = check_ports(&node, &entry_point).unwrap();
+        assert_eq!(unreachable.len(), 1);
+        assert_eq!(unreachable[0].0, "repair");
+
+        exit.store(true, Ordering::Relaxed);
+        ncp.join().unwrap();
+    }
+}
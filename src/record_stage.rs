@@ -4,12 +4,22 @@
 //! tags each Transaction with an Entry, and sends it back. The Entry includes the
 //! Transaction, the latest hash, and the number of hashes since the last transaction.
 //! The resulting stream of entries represents ordered transactions in time.
+//!
+//! A leader's record stage also registers each entry's ID with the bank as it
+//! records the entry. The stages applying transactions to the bank take turns
+//! with it, so the bank holds exactly the transactions recorded so far, and
+//! it ends a rent epoch at the same entry every node replaying the ledger does.
 
-use entry::Entry;
+use bank::Bank;
+use cpu_time;
+use entry::{Entry, PohDomain};
 use hash::Hash;
 use recorder::Recorder;
 use service::Service;
-use std::sync::mpsc::{channel, Receiver, RecvError, Sender, TryRecvError};
+use std::sync::mpsc::{
+    channel, Receiver, RecvError, RecvTimeoutError, SendError, Sender, TryRecvError,
+};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::thread::{self, Builder, JoinHandle};
 use std::time::{Duration, Instant};
 use transaction::Transaction;
@@ -17,7 +27,152 @@ use transaction::Transaction;
 #[cfg_attr(feature = "cargo-clippy", allow(large_enum_variant))]
 pub enum Signal {
     Tick,
-    Transactions(Vec<Transaction>),
+    /// Transactions to record, and when their packets were received
+    Transactions(Vec<Transaction>, Option<Instant>),
+}
+
+/// Recorded entries, and when the packets of their transactions were received
+pub type EntryBatch = (Vec<Entry>, Option<Instant>);
+
+/// How many batches of transactions a record stage has recorded, and whether
+/// it has stopped.
+#[derive(Default)]
+struct Recorded {
+    batches: u64,
+    closed: bool,
+}
+
+/// The turn the stages applying transactions to a leader's bank take with its
+/// record stage. A stage holds it from applying a batch until the batch is
+/// recorded, and the record stage holds it to record a tick, so no entry is
+/// recorded while the bank holds transactions that aren't. Clones share the
+/// same turn. Every batch of transactions sent to the record stage has to go
+/// through it.
+#[derive(Clone)]
+pub struct CommitLock {
+    /// The number of batches sent to be recorded.
+    turn: Arc<Mutex<u64>>,
+    /// `None` if nothing waits for the batches to be recorded.
+    recorded: Option<Arc<(Mutex<Recorded>, Condvar)>>,
+}
+
+impl CommitLock {
+    /// A lock for a record stage created with `RecordStage::new_with_bank` to
+    /// release the batches it records.
+    pub fn new() -> Self {
+        CommitLock {
+            turn: Arc::new(Mutex::new(0)),
+            recorded: Some(Arc::new((Mutex::new(Recorded::default()), Condvar::new()))),
+        }
+    }
+
+    /// A lock that doesn't wait for batches to be recorded, for signals read
+    /// by something other than a leader's record stage.
+    pub fn unrecorded() -> Self {
+        CommitLock {
+            turn: Arc::new(Mutex::new(0)),
+            recorded: None,
+        }
+    }
+
+    /// Take the turn, apply a batch to the bank with `apply`, and send the
+    /// transactions it returns to be recorded, if there are any, holding the
+    /// turn until they are.
+    pub fn commit<F>(
+        &self,
+        signal_sender: &Sender<Signal>,
+        recv_time: Option<Instant>,
+        apply: F,
+    ) -> Result<(), SendError<Signal>>
+    where
+        F: FnOnce() -> Vec<Transaction>,
+    {
+        let mut sent = self.turn.lock().unwrap();
+        let txs = apply();
+        if txs.is_empty() {
+            return Ok(());
+        }
+        signal_sender.send(Signal::Transactions(txs, recv_time))?;
+        *sent += 1;
+        if let Some(recorded) = &self.recorded {
+            let (recorded, cvar) = &**recorded;
+            let mut recorded = recorded.lock().unwrap();
+            while recorded.batches < *sent && !recorded.closed {
+                recorded = cvar.wait(recorded).unwrap();
+            }
+        }
+        Ok(())
+    }
+
+    /// The turn, unless a stage is applying a batch or waiting for one. No
+    /// entry is recorded while it's held.
+    pub fn try_turn(&self) -> Option<MutexGuard<u64>> {
+        self.turn.try_lock().ok()
+    }
+
+    /// Let the stage waiting for the batch just recorded go on.
+    fn batch_recorded(&self) {
+        self.update_recorded(|recorded| recorded.batches += 1);
+    }
+
+    /// Stop waiting for batches to be recorded.
+    fn close(&self) {
+        self.update_recorded(|recorded| recorded.closed = true);
+    }
+
+    fn update_recorded<F: FnOnce(&mut Recorded)>(&self, update: F) {
+        if let Some(recorded) = &self.recorded {
+            let (recorded, cvar) = &**recorded;
+            update(&mut recorded.lock().unwrap());
+            cvar.notify_all();
+        }
+    }
+}
+
+/// The bank a leader's record stage registers its entries' IDs with, and the
+/// lock it takes turns on with the stages applying transactions to the bank.
+struct Leader {
+    bank: Arc<Bank>,
+    commit_lock: CommitLock,
+}
+
+/// The time a clocked `RecordStage` ticks by.
+pub trait Clock: Send {
+    /// The time since the stage started.
+    fn elapsed(&self) -> Duration;
+}
+
+/// Wall clock time since the clock was created.
+struct SystemClock {
+    start_time: Instant,
+}
+
+impl Clock for SystemClock {
+    fn elapsed(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+}
+
+/// A clock that only moves when it's advanced, so tests can step through
+/// ticks without sleeping. Clones share the same time.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockClock {
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
 }
 
 pub struct RecordStage {
@@ -27,50 +182,125 @@ pub struct RecordStage {
 impl RecordStage {
     /// A background thread that will continue tagging received Transaction messages and
     /// sending back Entry messages until either the receiver or sender channel is closed.
+    /// The entries extend `start_hash` in the PoH `domain`.
     pub fn new(
         signal_receiver: Receiver<Signal>,
         start_hash: &Hash,
-    ) -> (Self, Receiver<Vec<Entry>>) {
-        let (entry_sender, entry_receiver) = channel();
-        let start_hash = *start_hash;
-
-        let thread_hdl = Builder::new()
-            .name("solana-record-stage".to_string())
-            .spawn(move || {
-                let mut recorder = Recorder::new(start_hash);
-                let _ = Self::process_signals(&mut recorder, &signal_receiver, &entry_sender);
-            })
-            .unwrap();
+        domain: &PohDomain,
+    ) -> (Self, Receiver<EntryBatch>) {
+        Self::spawn::<SystemClock>(signal_receiver, start_hash, domain, None, None)
+    }
 
-        (RecordStage { thread_hdl }, entry_receiver)
+    /// A leader's record stage, extending `bank`'s last ID and ticking every
+    /// `tick_duration`, if given. It registers each entry's ID with `bank` as
+    /// it records the entry, taking turns on `commit_lock` with the stages
+    /// applying transactions to `bank`.
+    pub fn new_with_bank(
+        signal_receiver: Receiver<Signal>,
+        bank: Arc<Bank>,
+        commit_lock: CommitLock,
+        tick_duration: Option<Duration>,
+    ) -> (Self, Receiver<EntryBatch>) {
+        let clock = tick_duration.map(|tick_duration| {
+            let clock = SystemClock {
+                start_time: Instant::now(),
+            };
+            (tick_duration, clock)
+        });
+        let leader = Leader {
+            bank: bank.clone(),
+            commit_lock,
+        };
+        Self::spawn(
+            signal_receiver,
+            &bank.last_id(),
+            &bank.poh_domain(),
+            clock,
+            Some(leader),
+        )
     }
 
     /// Same as `RecordStage::new`, but will automatically produce entries every `tick_duration`.
     pub fn new_with_clock(
         signal_receiver: Receiver<Signal>,
         start_hash: &Hash,
+        domain: &PohDomain,
+        tick_duration: Duration,
+    ) -> (Self, Receiver<EntryBatch>) {
+        Self::new_with_clock_source(
+            signal_receiver,
+            start_hash,
+            domain,
+            tick_duration,
+            SystemClock {
+                start_time: Instant::now(),
+            },
+        )
+    }
+
+    /// Same as `RecordStage::new_with_clock`, but ticks by the time `clock` tells.
+    pub fn new_with_clock_source<C: Clock + 'static>(
+        signal_receiver: Receiver<Signal>,
+        start_hash: &Hash,
+        domain: &PohDomain,
         tick_duration: Duration,
-    ) -> (Self, Receiver<Vec<Entry>>) {
+        clock: C,
+    ) -> (Self, Receiver<EntryBatch>) {
+        Self::spawn(
+            signal_receiver,
+            start_hash,
+            domain,
+            Some((tick_duration, clock)),
+            None,
+        )
+    }
+
+    /// Record the signals from `signal_receiver`, ticking by `clock` if given,
+    /// for `leader`, if it's a leader's stage.
+    fn spawn<C: Clock + 'static>(
+        signal_receiver: Receiver<Signal>,
+        start_hash: &Hash,
+        domain: &PohDomain,
+        clock: Option<(Duration, C)>,
+        leader: Option<Leader>,
+    ) -> (Self, Receiver<EntryBatch>) {
         let (entry_sender, entry_receiver) = channel();
         let start_hash = *start_hash;
+        let domain = *domain;
 
         let thread_hdl = Builder::new()
             .name("solana-record-stage".to_string())
             .spawn(move || {
-                let mut recorder = Recorder::new(start_hash);
-                let start_time = Instant::now();
-                loop {
-                    if Self::try_process_signals(
-                        &mut recorder,
-                        start_time,
-                        tick_duration,
-                        &signal_receiver,
-                        &entry_sender,
-                    ).is_err()
-                    {
-                        return;
+                cpu_time::register_thread("record_stage");
+                let mut recorder = Recorder::new(start_hash, domain);
+                let leader = leader.as_ref();
+                match clock {
+                    Some((tick_duration, clock)) => loop {
+                        if Self::try_process_signals(
+                            &mut recorder,
+                            &clock,
+                            tick_duration,
+                            &signal_receiver,
+                            &entry_sender,
+                            leader,
+                        )
+                        .is_err()
+                        {
+                            break;
+                        }
+                        recorder.hash();
+                    },
+                    None => {
+                        let _ = Self::process_signals(
+                            &mut recorder,
+                            &signal_receiver,
+                            &entry_sender,
+                            leader,
+                        );
                     }
-                    recorder.hash();
+                }
+                if let Some(leader) = leader {
+                    leader.commit_lock.close();
                 }
             })
             .unwrap();
@@ -81,44 +311,120 @@ impl RecordStage {
     fn process_signal(
         signal: Signal,
         recorder: &mut Recorder,
-        sender: &Sender<Vec<Entry>>,
+        receiver: &Receiver<Signal>,
+        sender: &Sender<EntryBatch>,
+        leader: Option<&Leader>,
     ) -> Result<(), ()> {
-        let txs = if let Signal::Transactions(txs) = signal {
-            txs
-        } else {
-            vec![]
-        };
-        let entries = recorder.record(txs);
-        sender.send(entries).or(Err(()))?;
-        Ok(())
+        match signal {
+            Signal::Transactions(txs, recv_time) => {
+                let entries = recorder.record(txs);
+                Self::send_entries(entries, recv_time, sender, leader)?;
+                if let Some(leader) = leader {
+                    leader.commit_lock.batch_recorded();
+                }
+                Ok(())
+            }
+            Signal::Tick => {
+                let _turn = match leader {
+                    Some(leader) => Some(Self::wait_for_turn(recorder, receiver, sender, leader)?),
+                    None => None,
+                };
+                let entries = recorder.record(vec![]);
+                Self::send_entries(entries, None, sender, leader)
+            }
+        }
+    }
+
+    /// Take the turn from the stages applying transactions to the leader's
+    /// bank, recording the batches they send meanwhile, which they hold the
+    /// turn until they're recorded.
+    fn wait_for_turn<'a>(
+        recorder: &mut Recorder,
+        receiver: &Receiver<Signal>,
+        sender: &Sender<EntryBatch>,
+        leader: &'a Leader,
+    ) -> Result<MutexGuard<'a, u64>, ()> {
+        loop {
+            if let Some(turn) = leader.commit_lock.try_turn() {
+                return Ok(turn);
+            }
+            match receiver.recv_timeout(Duration::from_millis(1)) {
+                Ok(signal) => {
+                    Self::process_signal(signal, recorder, receiver, sender, Some(leader))?
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return Err(()),
+            }
+        }
+    }
+
+    /// Register the IDs of `entries` with the leader's bank, if this is a
+    /// leader's stage, and send them on.
+    fn send_entries(
+        entries: Vec<Entry>,
+        recv_time: Option<Instant>,
+        sender: &Sender<EntryBatch>,
+        leader: Option<&Leader>,
+    ) -> Result<(), ()> {
+        if let Some(leader) = leader {
+            for entry in &entries {
+                if !entry.has_more {
+                    leader.bank.register_entry_id(&entry.id);
+                }
+            }
+        }
+        sender.send((entries, recv_time)).or(Err(()))
     }
 
     fn process_signals(
         recorder: &mut Recorder,
         receiver: &Receiver<Signal>,
-        sender: &Sender<Vec<Entry>>,
+        sender: &Sender<EntryBatch>,
+        leader: Option<&Leader>,
     ) -> Result<(), ()> {
         loop {
             match receiver.recv() {
-                Ok(signal) => Self::process_signal(signal, recorder, sender)?,
+                Ok(signal) => Self::process_signal(signal, recorder, receiver, sender, leader)?,
                 Err(RecvError) => return Err(()),
             }
         }
     }
 
-    fn try_process_signals(
+    /// Record a tick if one is due. A leader's stage leaves it for later while
+    /// a batch is being applied to the bank, as the batch would be missing
+    /// from the entries before it.
+    fn try_tick<C: Clock>(
         recorder: &mut Recorder,
-        start_time: Instant,
+        clock: &C,
+        tick_duration: Duration,
+        sender: &Sender<EntryBatch>,
+        leader: Option<&Leader>,
+    ) -> Result<(), ()> {
+        let _turn = match leader {
+            Some(leader) => match leader.commit_lock.try_turn() {
+                Some(turn) => Some(turn),
+                None => return Ok(()),
+            },
+            None => None,
+        };
+        match recorder.tick(clock.elapsed(), tick_duration) {
+            Some(entry) => Self::send_entries(vec![entry], None, sender, leader),
+            None => Ok(()),
+        }
+    }
+
+    fn try_process_signals<C: Clock>(
+        recorder: &mut Recorder,
+        clock: &C,
         tick_duration: Duration,
         receiver: &Receiver<Signal>,
-        sender: &Sender<Vec<Entry>>,
+        sender: &Sender<EntryBatch>,
+        leader: Option<&Leader>,
     ) -> Result<(), ()> {
         loop {
-            if let Some(entry) = recorder.tick(start_time, tick_duration) {
-                sender.send(vec![entry]).or(Err(()))?;
-            }
+            Self::try_tick(recorder, clock, tick_duration, sender, leader)?;
             match receiver.try_recv() {
-                Ok(signal) => Self::process_signal(signal, recorder, sender)?,
+                Ok(signal) => Self::process_signal(signal, recorder, receiver, sender, leader)?,
                 Err(TryRecvError::Empty) => return Ok(()),
                 Err(TryRecvError::Disconnected) => return Err(()),
             };
@@ -139,16 +445,23 @@ impl Service for RecordStage {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bank::BankError;
+    use bincode::serialize;
+    use feature::Feature;
+    use hash::hash;
     use ledger::Block;
+    use mint::Mint;
+    use rent;
     use signature::{Keypair, KeypairUtil};
     use std::sync::mpsc::channel;
-    use std::thread::sleep;
+    use std::thread::{sleep, spawn};
 
     #[test]
     fn test_historian() {
         let (tx_sender, tx_receiver) = channel();
         let zero = Hash::default();
-        let (record_stage, entry_receiver) = RecordStage::new(tx_receiver, &zero);
+        let domain = PohDomain::new(hash(&zero.as_ref()), 0);
+        let (record_stage, entry_receiver) = RecordStage::new(tx_receiver, &zero, &domain);
 
         tx_sender.send(Signal::Tick).unwrap();
         sleep(Duration::new(0, 1_000_000));
@@ -156,9 +469,9 @@ mod tests {
         sleep(Duration::new(0, 1_000_000));
         tx_sender.send(Signal::Tick).unwrap();
 
-        let entry0 = entry_receiver.recv().unwrap()[0].clone();
-        let entry1 = entry_receiver.recv().unwrap()[0].clone();
-        let entry2 = entry_receiver.recv().unwrap()[0].clone();
+        let entry0 = entry_receiver.recv().unwrap().0[0].clone();
+        let entry1 = entry_receiver.recv().unwrap().0[0].clone();
+        let entry2 = entry_receiver.recv().unwrap().0[0].clone();
 
         assert_eq!(entry0.num_hashes, 0);
         assert_eq!(entry1.num_hashes, 0);
@@ -167,14 +480,15 @@ mod tests {
         drop(tx_sender);
         assert_eq!(record_stage.thread_hdl.join().unwrap(), ());
 
-        assert!([entry0, entry1, entry2].verify(&zero));
+        assert!([entry0, entry1, entry2].verify_in_domain(&zero, &domain));
     }
 
     #[test]
     fn test_historian_closed_sender() {
         let (tx_sender, tx_receiver) = channel();
         let zero = Hash::default();
-        let (record_stage, entry_receiver) = RecordStage::new(tx_receiver, &zero);
+        let (record_stage, entry_receiver) =
+            RecordStage::new(tx_receiver, &zero, &PohDomain::default());
         drop(entry_receiver);
         tx_sender.send(Signal::Tick).unwrap();
         assert_eq!(record_stage.thread_hdl.join().unwrap(), ());
@@ -184,32 +498,201 @@ mod tests {
     fn test_transactions() {
         let (tx_sender, signal_receiver) = channel();
         let zero = Hash::default();
-        let (_record_stage, entry_receiver) = RecordStage::new(signal_receiver, &zero);
+        let (_record_stage, entry_receiver) =
+            RecordStage::new(signal_receiver, &zero, &PohDomain::default());
         let alice_keypair = Keypair::new();
         let bob_pubkey = Keypair::new().pubkey();
         let tx0 = Transaction::new(&alice_keypair, bob_pubkey, 1, zero);
         let tx1 = Transaction::new(&alice_keypair, bob_pubkey, 2, zero);
+        let recv_time = Instant::now();
         tx_sender
-            .send(Signal::Transactions(vec![tx0, tx1]))
+            .send(Signal::Transactions(vec![tx0, tx1], Some(recv_time)))
             .unwrap();
         drop(tx_sender);
-        let entries: Vec<_> = entry_receiver.iter().collect();
-        assert_eq!(entries.len(), 1);
+        let batches: Vec<_> = entry_receiver.iter().collect();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].1, Some(recv_time));
     }
 
     #[test]
     fn test_clock() {
         let (tx_sender, tx_receiver) = channel();
         let zero = Hash::default();
+        let domain = PohDomain::new(hash(&zero.as_ref()), 0);
         let (_record_stage, entry_receiver) =
-            RecordStage::new_with_clock(tx_receiver, &zero, Duration::from_millis(20));
+            RecordStage::new_with_clock(tx_receiver, &zero, &domain, Duration::from_millis(20));
         sleep(Duration::from_millis(900));
         tx_sender.send(Signal::Tick).unwrap();
         drop(tx_sender);
-        let entries: Vec<_> = entry_receiver.iter().flat_map(|x| x).collect();
+        let entries: Vec<_> = entry_receiver.iter().flat_map(|(x, _)| x).collect();
         assert!(entries.len() > 1);
 
         // Ensure the ID is not the seed.
         assert_ne!(entries[0].id, zero);
+        assert!(entries.verify_in_domain(&zero, &domain));
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        let (tx_sender, tx_receiver) = channel();
+        let zero = Hash::default();
+        let domain = PohDomain::new(hash(&zero.as_ref()), 0);
+        let clock = MockClock::default();
+        let tick_duration = Duration::from_millis(20);
+        let (record_stage, entry_receiver) = RecordStage::new_with_clock_source(
+            tx_receiver,
+            &zero,
+            &domain,
+            tick_duration,
+            clock.clone(),
+        );
+
+        // the stage ticks once for each tick_duration the clock moves past, no
+        // matter how much real time goes by
+        clock.advance(tick_duration * 3 + Duration::from_millis(1));
+        let mut entries: Vec<_> = (0..3)
+            .map(|_| entry_receiver.recv().unwrap().0[0].clone())
+            .collect();
+        assert!(entries.iter().all(|entry| entry.transactions.is_empty()));
+
+        let keypair = Keypair::new();
+        let tx = Transaction::new(&keypair, keypair.pubkey(), 1, zero);
+        tx_sender
+            .send(Signal::Transactions(vec![tx.clone()], None))
+            .unwrap();
+        let batch = entry_receiver.recv().unwrap().0;
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].transactions, vec![tx]);
+        entries.extend(batch);
+
+        clock.advance(tick_duration);
+        entries.extend(entry_receiver.recv().unwrap().0);
+        assert_eq!(entries.len(), 5);
+        assert!(entries[4].transactions.is_empty());
+
+        drop(tx_sender);
+        record_stage.join().unwrap();
+        assert!(entry_receiver.try_recv().is_err());
+        assert!(entries.verify_in_domain(&zero, &domain));
+    }
+
+    /// A leader's bank and a validator's, both a few entries short of the end
+    /// of a rent epoch.
+    fn banks_near_epoch_end(mint: &Mint) -> (Arc<Bank>, Bank) {
+        let leader = Arc::new(Bank::new(mint));
+        let mut validator = Bank::new(mint);
+        validator.is_leader = false;
+        while leader.entry_id_count() + 20 < rent::RENT_EPOCH_ENTRY_IDS {
+            let id = hash(&serialize(&leader.entry_id_count()).unwrap());
+            leader.register_entry_id(&id);
+            validator.register_entry_id(&id);
+        }
+        (leader, validator)
+    }
+
+    /// Send `count` ticks to be recorded from a thread of their own.
+    fn spawn_ticker(signal_sender: &Sender<Signal>, count: usize) -> JoinHandle<()> {
+        let signal_sender = signal_sender.clone();
+        spawn(move || {
+            for _ in 0..count {
+                signal_sender.send(Signal::Tick).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn test_leader_ends_rent_epoch_where_validators_do() {
+        let mint = Mint::new(100);
+        let (leader, validator) = banks_near_epoch_end(&mint);
+        let commit_lock = CommitLock::new();
+        let (signal_sender, signal_receiver) = channel();
+        let (record_stage, entry_receiver) =
+            RecordStage::new_with_bank(signal_receiver, leader.clone(), commit_lock.clone(), None);
+
+        // Ticks end the epoch while dust accounts are being created, each of
+        // which the rent collection reaps if it comes after the account.
+        let ticker = spawn_ticker(&signal_sender, 40);
+        for _ in 0..40 {
+            let dust = Keypair::new().pubkey();
+            let tx = Transaction::new(&mint.keypair(), dust, 1, leader.last_id());
+            commit_lock
+                .commit(&signal_sender, None, || {
+                    assert_eq!(leader.process_transaction(&tx), Ok(()));
+                    vec![tx]
+                })
+                .unwrap();
+        }
+        ticker.join().unwrap();
+        drop(signal_sender);
+        record_stage.join().unwrap();
+
+        let entries: Vec<_> = entry_receiver.iter().flat_map(|batch| batch.0).collect();
+        assert_eq!(entries.len(), 80);
+        validator.process_entries(entries).unwrap();
+        assert!(leader.entry_id_count() > rent::RENT_EPOCH_ENTRY_IDS);
+        assert_eq!(validator.entry_id_count(), leader.entry_id_count());
+        assert_eq!(validator.accounts_hash(), leader.accounts_hash());
+    }
+
+    #[test]
+    fn test_leader_activates_features_where_validators_do() {
+        let mint = Mint::new(100);
+        let (leader, validator) = banks_near_epoch_end(&mint);
+        let commit_lock = CommitLock::new();
+        let (signal_sender, signal_receiver) = channel();
+        let (record_stage, entry_receiver) =
+            RecordStage::new_with_bank(signal_receiver, leader.clone(), commit_lock.clone(), None);
+        let commit = |tx: Transaction| {
+            commit_lock
+                .commit(&signal_sender, None, || {
+                    match leader.process_transaction(&tx) {
+                        Ok(()) => vec![tx],
+                        Err(BankError::FeatureNotActive(_)) => vec![],
+                        Err(err) => panic!("{:?}", err),
+                    }
+                })
+                .unwrap()
+        };
+
+        let fee_payer = Keypair::new();
+        let last_id = leader.last_id();
+        commit(Transaction::new(
+            &mint.keypair(),
+            fee_payer.pubkey(),
+            50,
+            last_id,
+        ));
+        commit(Transaction::new_activate_feature(
+            &mint.keypair(),
+            Feature::FeePayer,
+            last_id,
+            0,
+        ));
+
+        // The leader takes a transaction with a fee payer only once validators
+        // replaying the ledger would, however the ticks fall.
+        let ticker = spawn_ticker(&signal_sender, 40);
+        for _ in 0..40 {
+            let to = Keypair::new().pubkey();
+            let last_id = leader.last_id();
+            commit(Transaction::new_with_fee_payer(
+                &mint.keypair(),
+                &fee_payer,
+                to,
+                1,
+                1,
+                last_id,
+            ));
+        }
+        ticker.join().unwrap();
+        drop(commit);
+        drop(signal_sender);
+        record_stage.join().unwrap();
+
+        let entries: Vec<_> = entry_receiver.iter().flat_map(|batch| batch.0).collect();
+        validator.process_entries(entries).unwrap();
+        assert!(leader.is_feature_active(Feature::FeePayer));
+        assert!(validator.is_feature_active(Feature::FeePayer));
+        assert_eq!(validator.accounts_hash(), leader.accounts_hash());
     }
 }
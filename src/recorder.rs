@@ -1,46 +1,53 @@
 //! The `recorder` module provides an object for generating a Proof of History.
 //! It records Transaction items on behalf of its users.
 
-use entry::Entry;
-use hash::{hash, Hash};
+use entry::{Entry, PohDomain};
+use hash::Hash;
 use ledger;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use transaction::Transaction;
 
 pub struct Recorder {
+    domain: PohDomain,
     last_hash: Hash,
     num_hashes: u64,
     num_ticks: u32,
 }
 
 impl Recorder {
-    pub fn new(last_hash: Hash) -> Self {
+    /// Create a Recorder extending the entry `last_id` in the PoH `domain`.
+    pub fn new(last_id: Hash, domain: PohDomain) -> Self {
         Recorder {
-            last_hash,
+            domain,
+            last_hash: domain.seed(&last_id),
             num_hashes: 0,
             num_ticks: 0,
         }
     }
 
     pub fn hash(&mut self) {
-        self.last_hash = hash(&self.last_hash.as_ref());
+        self.last_hash = self.domain.hash.hasher().hash(&self.last_hash.as_ref());
         self.num_hashes += 1;
     }
 
     pub fn record(&mut self, transactions: Vec<Transaction>) -> Vec<Entry> {
-        ledger::next_entries_mut(&mut self.last_hash, &mut self.num_hashes, transactions)
+        ledger::next_entries_mut_in_domain(
+            &mut self.last_hash,
+            &mut self.num_hashes,
+            transactions,
+            &self.domain,
+        )
     }
 
-    pub fn tick(&mut self, start_time: Instant, tick_duration: Duration) -> Option<Entry> {
-        if start_time.elapsed() > tick_duration * (self.num_ticks + 1) {
+    /// Produce the next tick entry if `elapsed`, the time since the first
+    /// tick was due to start, has passed its deadline.
+    pub fn tick(&mut self, elapsed: Duration, tick_duration: Duration) -> Option<Entry> {
+        if elapsed > tick_duration * (self.num_ticks + 1) {
             // TODO: don't let this overflow u32
             self.num_ticks += 1;
-            Some(Entry::new_mut(
-                &mut self.last_hash,
-                &mut self.num_hashes,
-                vec![],
-                false,
-            ))
+            let entry = Entry::new_mut(&mut self.last_hash, &mut self.num_hashes, vec![], false);
+            self.last_hash = self.domain.seed(&entry.id);
+            Some(entry)
         } else {
             None
         }
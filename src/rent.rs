@@ -0,0 +1,79 @@
+//! The `rent` module prices keeping an account in the bank. Every
+//! `RENT_EPOCH_ENTRY_IDS` entry ids the bank registers, each account holding
+//! less than its rent-exempt balance is charged a rent for the bytes it takes
+//! up, and dropped once that empties it. Otherwise accounts nobody wants any
+//! more, such as the dust spammers leave behind, would be kept forever.
+
+use account::Account;
+use bank::MAX_ENTRY_IDS;
+
+/// How many entry ids the bank registers between rent collections, the span a
+/// `last_id` stays valid for.
+pub const RENT_EPOCH_ENTRY_IDS: usize = MAX_ENTRY_IDS;
+
+/// Bytes the bank keeps for every account on top of its userdata.
+pub const ACCOUNT_STORAGE_OVERHEAD: u64 = 128;
+
+/// Bytes of storage one token pays for per epoch.
+pub const RENT_BYTES_PER_TOKEN: u64 = 128;
+
+/// An account holding enough tokens to pay rent for this many epochs pays
+/// none at all.
+pub const RENT_EXEMPT_EPOCHS: i64 = 2;
+
+/// The bytes the bank keeps for `account`.
+pub fn storage_size(account: &Account) -> u64 {
+    ACCOUNT_STORAGE_OVERHEAD + account.userdata.len() as u64
+}
+
+/// The rent `account` owes each epoch unless it's exempt.
+pub fn rent_per_epoch(account: &Account) -> i64 {
+    ((storage_size(account) + RENT_BYTES_PER_TOKEN - 1) / RENT_BYTES_PER_TOKEN) as i64
+}
+
+/// The balance from which `account` pays no rent.
+pub fn exempt_balance(account: &Account) -> i64 {
+    rent_per_epoch(account) * RENT_EXEMPT_EPOCHS
+}
+
+/// The tokens to take from `account` when rent is collected, at most all it holds.
+pub fn rent_due(account: &Account) -> i64 {
+    if account.tokens >= exempt_balance(account) {
+        0
+    } else {
+        rent_per_epoch(account).min(account.tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rent_per_epoch() {
+        let mut account = Account::default();
+        assert_eq!(storage_size(&account), ACCOUNT_STORAGE_OVERHEAD);
+        assert_eq!(rent_per_epoch(&account), 1);
+        account.userdata = vec![0; RENT_BYTES_PER_TOKEN as usize];
+        assert_eq!(rent_per_epoch(&account), 2);
+        account.userdata.push(0);
+        assert_eq!(rent_per_epoch(&account), 3);
+        assert_eq!(exempt_balance(&account), 3 * RENT_EXEMPT_EPOCHS);
+    }
+
+    #[test]
+    fn test_rent_due() {
+        let mut account = Account::default();
+        assert_eq!(rent_due(&account), 0);
+        account.tokens = 1;
+        assert_eq!(rent_due(&account), 1);
+        account.tokens = exempt_balance(&account);
+        assert_eq!(rent_due(&account), 0);
+
+        account.userdata = vec![0; 4 * RENT_BYTES_PER_TOKEN as usize];
+        account.tokens = 3;
+        assert_eq!(rent_due(&account), 3);
+        account.tokens = 6;
+        assert_eq!(rent_due(&account), rent_per_epoch(&account));
+    }
+}
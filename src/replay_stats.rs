@@ -0,0 +1,151 @@
+//! The `replay_stats` module counts, per source address, the transactions a
+//! leader rejects because it has seen their signature before or their last id
+//! has expired. A spike from one address points at a replay attack, or at a
+//! client that resends transactions without refreshing its last id.
+
+use bank::BankError;
+use counter::Counter;
+use influx_db_client as influxdb;
+use log::Level;
+use metrics;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::AtomicUsize;
+use std::time::{Duration, Instant};
+
+/// How often the counts are submitted to metrics.
+const SUBMIT_INTERVAL_MS: u64 = 10_000;
+
+/// Only the sources with the most rejections are submitted, to bound the number
+/// of series in metrics.
+const MAX_REPORTED_SOURCES: usize = 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReplayCounts {
+    pub duplicate_signature: usize,
+    pub last_id_not_found: usize,
+}
+
+impl ReplayCounts {
+    pub fn total(&self) -> usize {
+        self.duplicate_signature + self.last_id_not_found
+    }
+}
+
+pub struct ReplayStats {
+    sources: HashMap<IpAddr, ReplayCounts>,
+    last_submit: Instant,
+}
+
+impl Default for ReplayStats {
+    fn default() -> Self {
+        ReplayStats {
+            sources: HashMap::new(),
+            last_submit: Instant::now(),
+        }
+    }
+}
+
+impl ReplayStats {
+    /// Count a transaction from `source` the bank rejected with `err`, if it was
+    /// rejected as a replay.
+    pub fn record(&mut self, source: IpAddr, err: &BankError) {
+        match err {
+            BankError::DuplicateSignature(_) => {
+                inc_new_counter_info!("banking_stage-duplicate_signature", 1);
+                self.sources
+                    .entry(source)
+                    .or_insert_with(ReplayCounts::default)
+                    .duplicate_signature += 1;
+            }
+            BankError::LastIdNotFound(_) => {
+                inc_new_counter_info!("banking_stage-last_id_not_found", 1);
+                self.sources
+                    .entry(source)
+                    .or_insert_with(ReplayCounts::default)
+                    .last_id_not_found += 1;
+            }
+            _ => return,
+        }
+        if self.last_submit.elapsed() >= Duration::from_millis(SUBMIT_INTERVAL_MS) {
+            self.submit();
+        }
+    }
+
+    /// The `n` sources with the most rejections since the last submission, most first.
+    pub fn top_sources(&self, n: usize) -> Vec<(IpAddr, ReplayCounts)> {
+        let mut sources: Vec<_> = self
+            .sources
+            .iter()
+            .map(|(source, counts)| (*source, *counts))
+            .collect();
+        sources.sort_by(|a, b| b.1.total().cmp(&a.1.total()));
+        sources.truncate(n);
+        sources
+    }
+
+    fn submit(&mut self) {
+        for (source, counts) in self.top_sources(MAX_REPORTED_SOURCES) {
+            metrics::submit(
+                influxdb::Point::new("banking_stage-replay_rejections")
+                    .add_tag("source", influxdb::Value::String(source.to_string()))
+                    .add_field(
+                        "duplicate_signature",
+                        influxdb::Value::Integer(counts.duplicate_signature as i64),
+                    )
+                    .add_field(
+                        "last_id_not_found",
+                        influxdb::Value::Integer(counts.last_id_not_found as i64),
+                    )
+                    .to_owned(),
+            );
+        }
+        self.sources.clear();
+        self.last_submit = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bank::BankError;
+    use hash::Hash;
+    use replay_stats::{ReplayCounts, ReplayStats};
+    use signature::Signature;
+
+    #[test]
+    fn test_replay_stats() {
+        let mut stats = ReplayStats::default();
+        let replayer = "10.0.0.1".parse().unwrap();
+        let stale = "10.0.0.2".parse().unwrap();
+        for _ in 0..3 {
+            stats.record(
+                replayer,
+                &BankError::DuplicateSignature(Signature::default()),
+            );
+        }
+        stats.record(stale, &BankError::LastIdNotFound(Hash::default()));
+        // other rejections aren't replays
+        stats.record(stale, &BankError::NegativeTokens);
+
+        assert_eq!(
+            stats.top_sources(2),
+            vec![
+                (
+                    replayer,
+                    ReplayCounts {
+                        duplicate_signature: 3,
+                        last_id_not_found: 0,
+                    }
+                ),
+                (
+                    stale,
+                    ReplayCounts {
+                        duplicate_signature: 0,
+                        last_id_not_found: 1,
+                    }
+                ),
+            ]
+        );
+        assert_eq!(stats.top_sources(1).len(), 1);
+    }
+}
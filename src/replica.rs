@@ -0,0 +1,228 @@
+//! The `replica` module implements a read replica, a node that serves JSON RPC
+//! requests to take that load off the consensus nodes. It runs no gossip, votes
+//! or TPU. Instead it follows the entry stream of a trusted validator, applying
+//! each entry to its bank and appending it to its own ledger.
+
+use bank::Bank;
+use bincode::serialize;
+use counter::Counter;
+use entry_stream::read_entries;
+use ledger::{read_ledger, FileLedgerWriter};
+use log::Level;
+use result::Result;
+use rpc::{JsonRpcConfig, JsonRpcService};
+use service::Service;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep, Builder, JoinHandle};
+use std::time::Duration;
+
+/// How long to wait on the stream before checking for exit.
+const STREAM_TIMEOUT_MS: u64 = 1000;
+
+pub struct Replica {
+    exit: Arc<AtomicBool>,
+    rpc_service: JsonRpcService,
+    thread_hdls: Vec<JoinHandle<()>>,
+}
+
+impl Replica {
+    /// Process the ledger at `ledger_path`, which needs at least the genesis
+    /// entries, then follow the entry stream at `stream_addr` and serve RPC
+    /// requests on `rpc_addr`.
+    pub fn new(ledger_path: &str, stream_addr: SocketAddr, rpc_addr: SocketAddr) -> Self {
+        info!("creating bank...");
+        let bank = Bank::new_default(false);
+        let entries = read_ledger(ledger_path, true)
+            .expect("opening ledger")
+            .map(|e| e.expect("failed to parse entry"));
+        info!("processing ledger...");
+        let (mut entry_height, _) = bank.process_ledger(entries).expect("process_ledger");
+        let bank = Arc::new(bank);
+        let mut ledger_writer = FileLedgerWriter::open(ledger_path, false).expect("opening ledger");
+
+        let exit = Arc::new(AtomicBool::new(false));
+        // a replica only follows the ledger, so it doesn't take transactions
+        let rpc_service = JsonRpcService::new(
+            bank.clone(),
+            Some(ledger_path),
+            None,
+            None,
+            JsonRpcConfig::default(),
+            rpc_addr,
+        )
+        .expect("starting RPC service");
+
+        let stream_exit = exit.clone();
+        let t_stream = Builder::new()
+            .name("solana-replica-stream".to_string())
+            .spawn(move || {
+                while !stream_exit.load(Ordering::Relaxed) {
+                    if let Err(e) = Self::follow(
+                        &bank,
+                        &mut ledger_writer,
+                        &mut entry_height,
+                        stream_addr,
+                        &stream_exit,
+                    ) {
+                        warn!("entry stream {}: {:?}, reconnecting", stream_addr, e);
+                        sleep(Duration::from_millis(STREAM_TIMEOUT_MS));
+                    }
+                }
+            })
+            .unwrap();
+
+        Replica {
+            exit,
+            rpc_service,
+            thread_hdls: vec![t_stream],
+        }
+    }
+
+    /// Subscribe at `entry_height` and process entries until the stream fails
+    /// or `exit` is set.
+    fn follow(
+        bank: &Bank,
+        ledger_writer: &mut FileLedgerWriter,
+        entry_height: &mut u64,
+        stream_addr: SocketAddr,
+        exit: &AtomicBool,
+    ) -> Result<()> {
+        let timeout = Duration::from_millis(STREAM_TIMEOUT_MS);
+        let mut stream = TcpStream::connect_timeout(&stream_addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.write_all(&serialize(entry_height)?)?;
+        info!(
+            "following {} from entry height {}",
+            stream_addr, entry_height
+        );
+
+        let mut stream = BufReader::new(stream);
+        while !exit.load(Ordering::Relaxed) {
+            // wait for the next message without consuming any of it
+            match stream.fill_buf() {
+                Ok(buf) if buf.is_empty() => {
+                    return Err(io::Error::new(ErrorKind::UnexpectedEof, "stream closed").into())
+                }
+                Ok(_) => (),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e.into()),
+            }
+            let entries = read_entries(&mut stream)?;
+            let num_entries = entries.len();
+            bank.process_entries(entries.clone())?;
+            ledger_writer.write_entries(entries)?;
+            *entry_height += num_entries as u64;
+            inc_new_counter_info!("replica-entries", num_entries);
+        }
+        Ok(())
+    }
+
+    pub fn exit(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+        self.rpc_service.close();
+    }
+
+    pub fn close(self) -> thread::Result<()> {
+        self.exit();
+        self.join()
+    }
+}
+
+impl Service for Replica {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        let mut thread_hdls = self.thread_hdls;
+        thread_hdls.extend(self.rpc_service.thread_hdls());
+        thread_hdls
+    }
+
+    fn join(self) -> thread::Result<()> {
+        for thread_hdl in self.thread_hdls() {
+            thread_hdl.join()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use entry::next_entry;
+    use entry_stream::EntryStreamService;
+    use ledger::{read_ledger, FileLedgerWriter};
+    use mint::Mint;
+    use replica::Replica;
+    use service::Service;
+    use signature::{Keypair, KeypairUtil};
+    use std::env;
+    use std::fs::remove_dir_all;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+    use transaction::Transaction;
+
+    fn tmp_ledger_path(name: &str) -> String {
+        let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
+        format!(
+            "{}/tmp-ledger-{}-{}",
+            out_dir,
+            name,
+            Keypair::new().pubkey()
+        )
+    }
+
+    #[test]
+    fn test_replica_follows_entry_stream() {
+        let alice = Mint::new(10_000);
+        let bob_pubkey = Keypair::new().pubkey();
+        let validator_ledger = tmp_ledger_path("replica-validator");
+        let replica_ledger = tmp_ledger_path("replica");
+        let genesis = alice.create_entries();
+        for path in &[&validator_ledger, &replica_ledger] {
+            let mut writer = FileLedgerWriter::open(path, true).unwrap();
+            writer.write_entries(genesis.clone()).unwrap();
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream_addr = listener.local_addr().unwrap();
+        let exit = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = channel();
+        let service = EntryStreamService::new(listener, &validator_ledger, receiver, exit.clone());
+
+        let rpc_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let rpc_addr: SocketAddr = format!("127.0.0.1:{}", rpc_port).parse().unwrap();
+        let replica = Replica::new(&replica_ledger, stream_addr, rpc_addr);
+
+        let tx = Transaction::new(&alice.keypair(), bob_pubkey, 20, alice.last_id());
+        let entry = next_entry(&alice.last_id(), 1, vec![tx]);
+        // like the replicate stage, write the ledger before publishing, so the
+        // replica gets the entry whether or not it has subscribed yet
+        FileLedgerWriter::open(&validator_ledger, false)
+            .unwrap()
+            .write_entry(&entry)
+            .unwrap();
+        sender.send((genesis.len() as u64, vec![entry])).unwrap();
+
+        // the replica only writes an entry to its ledger once the bank has processed it
+        let caught_up = (0..50).any(|_| {
+            sleep(Duration::from_millis(100));
+            read_ledger(&replica_ledger, false).unwrap().count() == genesis.len() + 1
+        });
+        assert!(caught_up);
+
+        replica.close().unwrap();
+        exit.store(true, Ordering::Relaxed);
+        service.join().unwrap();
+        remove_dir_all(validator_ledger).unwrap();
+        remove_dir_all(replica_ledger).unwrap();
+    }
+}
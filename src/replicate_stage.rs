@@ -1,25 +1,36 @@
 //! The `replicate_stage` replicates transactions broadcast by the leader.
 
-use bank::Bank;
+use bank::{Bank, ACCOUNTS_SAVE_INTERVAL};
+use bank_forks::BankForks;
 use counter::Counter;
+use cpu_time;
 use crdt::Crdt;
-use ledger::{reconstruct_entries_from_blobs, LedgerWriter};
+use entry::Entry;
+use entry_stream::EntryStreamSender;
+use hash::Hash;
+use ledger::{reconstruct_entries_from_blobs, FileLedgerWriter, LedgerWriter, StoragePaths};
 use log::Level;
 use packet::BlobRecycler;
 use result::{Error, Result};
 use service::Service;
-use signature::Keypair;
-use std::net::UdpSocket;
-use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicUsize;
-use std::sync::mpsc::channel;
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, RwLock};
-use std::thread::{self, Builder, JoinHandle};
-use std::time::Duration;
-use streamer::{responder, BlobReceiver};
-use vote_stage::VoteStage;
-use voting::entries_to_votes;
+use std::thread::{self, sleep, Builder, JoinHandle};
+use std::time::{Duration, Instant};
+use streamer::BlobReceiver;
+use timing::duration_as_ms;
+use vote_stage::VOTE_TIMEOUT_MS;
+use voting::{check_accounts_hashes, entries_to_votes};
+
+/// Most entries applied to the bank in one batch. The bank is locked for the
+/// whole of a batch, and snapshotted after it, so a backlog is split into
+/// batches rather than locking out the vote stage until it's all applied.
+pub const MAX_REPLICATE_BATCH_ENTRIES: usize = 1024;
+
+/// How long a backlog is applied before the stage pauses between batches, to
+/// let the vote stage take the bank's locks and vote on the progress so far.
+pub const REPLICATE_BUDGET_MS: u64 = VOTE_TIMEOUT_MS / 2;
 
 pub struct ReplicateStage {
     thread_hdls: Vec<JoinHandle<()>>,
@@ -27,32 +38,77 @@ pub struct ReplicateStage {
 
 impl ReplicateStage {
     /// Process entry blobs, already in order
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     fn replicate_requests(
-        bank: &Arc<Bank>,
+        forks: &mut BankForks,
         crdt: &Arc<RwLock<Crdt>>,
         blob_recycler: &BlobRecycler,
         window_receiver: &BlobReceiver,
-        ledger_writer: Option<&mut LedgerWriter>,
+        ledger_writer: &mut Option<Box<LedgerWriter>>,
+        entry_height: &mut u64,
+        next_save: &mut u64,
+        entry_stream: Option<&EntryStreamSender>,
     ) -> Result<()> {
         let timer = Duration::new(1, 0);
-        //coalesce all the available blobs into a single vote
+        //coalesce all the available blobs, then apply them in batches
         let mut blobs = window_receiver.recv_timeout(timer)?;
         while let Ok(mut more) = window_receiver.try_recv() {
             blobs.append(&mut more);
         }
         let entries = reconstruct_entries_from_blobs(blobs.clone())?;
 
-        let res = bank.process_entries(entries.clone());
-
         while let Some(blob) = blobs.pop_front() {
             blob_recycler.recycle(blob);
         }
 
-        {
+        let last_votes: Vec<_> = {
             let votes = entries_to_votes(&entries);
+            check_accounts_hashes(forks.bank(), &votes);
             let mut wcrdt = crdt.write().unwrap();
             wcrdt.insert_votes(&votes);
+            wcrdt
+                .table
+                .values()
+                .map(|x| x.ledger_state.last_id)
+                .collect()
+        };
+
+        let mut budget_start = Instant::now();
+        for batch in entries.chunks(MAX_REPLICATE_BATCH_ENTRIES) {
+            if duration_as_ms(&budget_start.elapsed()) >= REPLICATE_BUDGET_MS {
+                inc_new_counter_info!("replicate-budget_exhausted", 1);
+                sleep(Duration::from_millis(1));
+                budget_start = Instant::now();
+            }
+            Self::replicate_batch(
+                forks,
+                crdt,
+                batch.to_vec(),
+                &last_votes,
+                ledger_writer,
+                entry_height,
+                next_save,
+                entry_stream,
+            )?;
         }
+        Ok(())
+    }
+
+    /// Apply one batch of entries and record it, if it extends the fork the
+    /// bank follows. Once the ledger reaches `next_save`, the accounts are
+    /// saved as soon as the forks allow.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    fn replicate_batch(
+        forks: &mut BankForks,
+        crdt: &Arc<RwLock<Crdt>>,
+        entries: Vec<Entry>,
+        last_votes: &[Hash],
+        ledger_writer: &mut Option<Box<LedgerWriter>>,
+        entry_height: &mut u64,
+        next_save: &mut u64,
+        entry_stream: Option<&EntryStreamSender>,
+    ) -> Result<()> {
+        let res = forks.process_entries(entries.clone(), last_votes);
 
         inc_new_counter_info!(
             "replicate-transactions",
@@ -60,8 +116,24 @@ impl ReplicateStage {
         );
 
         // TODO: move this to another stage?
-        if let Some(ledger_writer) = ledger_writer {
-            ledger_writer.write_entries(entries)?;
+        // only entries that extend the followed fork keep the ledger a single chain
+        if let (Some(ledger_writer), Ok(true)) = (ledger_writer.as_mut(), &res) {
+            ledger_writer.append_entries(&entries)?;
+        }
+
+        // published after the ledger write, so that a subscriber catching up
+        // from the ledger can't miss a batch
+        if let Ok(true) = res {
+            let start = *entry_height;
+            let last_entry_id = entries[entries.len() - 1].id;
+            *entry_height += entries.len() as u64;
+            crdt.write().unwrap().set_entry_height(*entry_height);
+            if let Some(entry_stream) = entry_stream {
+                entry_stream.send((start, entries))?;
+            }
+            if *entry_height >= *next_save && forks.save_accounts(*entry_height, &last_entry_id)? {
+                *next_save = *entry_height + ACCOUNTS_SAVE_INTERVAL;
+            }
         }
 
         if res.is_err() {
@@ -72,57 +144,71 @@ impl ReplicateStage {
         Ok(())
     }
     pub fn new(
-        keypair: Keypair,
         bank: Arc<Bank>,
         crdt: Arc<RwLock<Crdt>>,
         blob_recycler: BlobRecycler,
         window_receiver: BlobReceiver,
-        ledger_path: Option<&str>,
-        exit: Arc<AtomicBool>,
+        entry_height: u64,
+        storage: Option<&StoragePaths>,
+        entry_stream: Option<EntryStreamSender>,
     ) -> Self {
-        let (vote_blob_sender, vote_blob_receiver) = channel();
-        let send = UdpSocket::bind("0.0.0.0:0").expect("bind");
-        let t_responder = responder(
-            "replicate_stage",
-            send,
-            blob_recycler.clone(),
-            vote_blob_receiver,
-        );
-
-        let vote_stage = VoteStage::new(
-            Arc::new(keypair),
-            bank.clone(),
-            crdt.clone(),
-            blob_recycler.clone(),
-            vote_blob_sender,
-            exit,
-        );
+        let ledger_writer = storage.map(|s| {
+            let writer = FileLedgerWriter::open(&s.ledger, false).unwrap();
+            Box::new(writer) as Box<LedgerWriter>
+        });
+        Self::new_with_ledger_writer(
+            bank,
+            crdt,
+            blob_recycler,
+            window_receiver,
+            entry_height,
+            ledger_writer,
+            entry_stream,
+        )
+    }
 
-        let mut ledger_writer = ledger_path.map(|p| LedgerWriter::open(p, false).unwrap());
+    /// Same as `new`, but records the entries to `ledger_writer`, if any.
+    pub fn new_with_ledger_writer(
+        bank: Arc<Bank>,
+        crdt: Arc<RwLock<Crdt>>,
+        blob_recycler: BlobRecycler,
+        window_receiver: BlobReceiver,
+        mut entry_height: u64,
+        mut ledger_writer: Option<Box<LedgerWriter>>,
+        entry_stream: Option<EntryStreamSender>,
+    ) -> Self {
+        let mut forks = BankForks::new(bank);
+        crdt.write().unwrap().set_entry_height(entry_height);
+        let mut next_save = entry_height + ACCOUNTS_SAVE_INTERVAL;
 
         let t_replicate = Builder::new()
             .name("solana-replicate-stage".to_string())
-            .spawn(move || loop {
-                if let Err(e) = Self::replicate_requests(
-                    &bank,
-                    &crdt,
-                    &blob_recycler,
-                    &window_receiver,
-                    ledger_writer.as_mut(),
-                ) {
-                    match e {
-                        Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
-                        Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
-                        _ => error!("{:?}", e),
+            .spawn(move || {
+                cpu_time::register_thread("replicate_stage");
+                loop {
+                    if let Err(e) = Self::replicate_requests(
+                        &mut forks,
+                        &crdt,
+                        &blob_recycler,
+                        &window_receiver,
+                        &mut ledger_writer,
+                        &mut entry_height,
+                        &mut next_save,
+                        entry_stream.as_ref(),
+                    ) {
+                        match e {
+                            Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
+                            Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
+                            _ => error!("{:?}", e),
+                        }
                     }
                 }
             })
             .unwrap();
 
-        let mut thread_hdls = vec![t_responder, t_replicate];
-        thread_hdls.extend(vote_stage.thread_hdls());
-
-        ReplicateStage { thread_hdls }
+        ReplicateStage {
+            thread_hdls: vec![t_replicate],
+        }
     }
 }
 
@@ -137,3 +223,66 @@ impl Service for ReplicateStage {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crdt::TestNode;
+    use entry::next_entry;
+    use ledger::{Block, MemLedger};
+    use mint::Mint;
+    use std::collections::VecDeque;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_replicate_backlog_in_batches() {
+        let mint = Mint::new(10);
+        let bank = Arc::new(Bank::new(&mint));
+        let domain = bank.poh_domain();
+        let mut forks = BankForks::new(bank.clone());
+        let crdt = Arc::new(RwLock::new(
+            Crdt::new(TestNode::new_localhost().data).unwrap(),
+        ));
+        let recycler = BlobRecycler::default();
+
+        let mut last_id = mint.last_id();
+        let entries: Vec<_> = (0..2 * MAX_REPLICATE_BATCH_ENTRIES + 1)
+            .map(|_| {
+                let tick = next_entry(&domain.seed(&last_id), 1, vec![]);
+                last_id = tick.id;
+                tick
+            })
+            .collect();
+        let mut blobs = VecDeque::new();
+        entries.to_blobs(&recycler, &mut blobs);
+        let (blob_sender, blob_receiver) = channel();
+        blob_sender.send(blobs).unwrap();
+
+        let (entry_stream_sender, entry_stream_receiver) = channel();
+        let ledger = MemLedger::default();
+        let mut ledger_writer: Option<Box<LedgerWriter>> = Some(Box::new(ledger.clone()));
+        let mut entry_height = 0;
+        let mut next_save = ACCOUNTS_SAVE_INTERVAL;
+        ReplicateStage::replicate_requests(
+            &mut forks,
+            &crdt,
+            &recycler,
+            &blob_receiver,
+            &mut ledger_writer,
+            &mut entry_height,
+            &mut next_save,
+            Some(&entry_stream_sender),
+        )
+        .unwrap();
+
+        assert_eq!(entry_height, entries.len() as u64);
+        assert_eq!(bank.last_id(), last_id);
+        assert_eq!(forks.head(), last_id);
+        let batches: Vec<_> = entry_stream_receiver.try_iter().collect();
+        let starts: Vec<_> = batches.iter().map(|batch| batch.0).collect();
+        let max = MAX_REPLICATE_BATCH_ENTRIES as u64;
+        assert_eq!(starts, vec![0, max, 2 * max]);
+        assert_eq!(batches[2].1.len(), 1);
+        assert_eq!(ledger.entries(), entries);
+    }
+}
@@ -12,7 +12,7 @@ use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
 use std::sync::Arc;
 use std::thread::{self, Builder, JoinHandle};
 use std::time::Instant;
-use streamer::{self, BlobReceiver, BlobSender};
+use streamer::{self, BlobReceiver, BlobSender, MAX_BATCH_PACKETS};
 use timing;
 
 pub struct RequestStage {
@@ -39,7 +39,7 @@ impl RequestStage {
         packet_recycler: &PacketRecycler,
         blob_recycler: &BlobRecycler,
     ) -> Result<()> {
-        let (batch, batch_len) = streamer::recv_batch(packet_receiver)?;
+        let (batch, batch_len) = streamer::recv_batch(packet_receiver, MAX_BATCH_PACKETS)?;
 
         debug!(
             "@{:?} request_stage: processing: {}",
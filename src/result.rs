@@ -1,6 +1,7 @@
 //! The `result` module exposes a Result type that propagates one of many different Error types.
 
 use bank;
+use bank_forks;
 use bincode;
 use crdt;
 #[cfg(feature = "erasure")]
@@ -9,8 +10,56 @@ use packet;
 use serde_json;
 use std;
 use std::any::Any;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
 use window;
 
+/// Why a node couldn't start its services.
+#[derive(Debug)]
+pub enum StartupError {
+    /// Something else is bound to the address already.
+    PortInUse(SocketAddr),
+    /// The ledger or snapshot at the path can't be read or doesn't verify.
+    BadLedger(String, String),
+    /// The node's keypair can't be decoded.
+    BadKeypair,
+    /// The RPC API keys file at the path can't be read or parsed.
+    BadApiKeys(String, String),
+    /// The RPC server's limits don't make sense together.
+    BadRpcConfig(String),
+}
+
+impl fmt::Display for StartupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartupError::PortInUse(addr) => write!(
+                f,
+                "{} is already in use, is another node running on this machine?",
+                addr
+            ),
+            StartupError::BadLedger(path, reason) => {
+                write!(f, "bad ledger at {}: {}", path, reason)
+            }
+            StartupError::BadKeypair => write!(f, "the keypair isn't a valid pkcs8 document"),
+            StartupError::BadApiKeys(path, reason) => {
+                write!(f, "bad RPC API keys file at {}: {}", path, reason)
+            }
+            StartupError::BadRpcConfig(reason) => write!(f, "bad RPC config: {}", reason),
+        }
+    }
+}
+
+/// The error binding `addr` failed with, as a `StartupError::PortInUse` if
+/// the address was taken.
+pub fn bind_error(addr: SocketAddr, err: io::Error) -> Error {
+    if err.kind() == io::ErrorKind::AddrInUse {
+        Error::StartupError(StartupError::PortInUse(addr))
+    } else {
+        Error::IO(err)
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     IO(std::io::Error),
@@ -21,11 +70,13 @@ pub enum Error {
     RecvTimeoutError(std::sync::mpsc::RecvTimeoutError),
     Serialize(std::boxed::Box<bincode::ErrorKind>),
     BankError(bank::BankError),
+    ForkError(bank_forks::ForkError),
     CrdtError(crdt::CrdtError),
     WindowError(window::WindowError),
     BlobError(packet::BlobError),
     #[cfg(feature = "erasure")]
     ErasureError(erasure::ErasureError),
+    StartupError(StartupError),
     SendError,
 }
 
@@ -46,6 +97,11 @@ impl std::convert::From<bank::BankError> for Error {
         Error::BankError(e)
     }
 }
+impl std::convert::From<bank_forks::ForkError> for Error {
+    fn from(e: bank_forks::ForkError) -> Error {
+        Error::ForkError(e)
+    }
+}
 impl std::convert::From<crdt::CrdtError> for Error {
     fn from(e: crdt::CrdtError) -> Error {
         Error::CrdtError(e)
@@ -62,6 +118,11 @@ impl std::convert::From<erasure::ErasureError> for Error {
         Error::ErasureError(e)
     }
 }
+impl std::convert::From<StartupError> for Error {
+    fn from(e: StartupError) -> Error {
+        Error::StartupError(e)
+    }
+}
 impl<T> std::convert::From<std::sync::mpsc::SendError<T>> for Error {
     fn from(_e: std::sync::mpsc::SendError<T>) -> Error {
         Error::SendError
@@ -98,10 +159,11 @@ impl std::convert::From<std::boxed::Box<bincode::ErrorKind>> for Error {
 mod tests {
     use result::Error;
     use result::Result;
+    use result::{bind_error, StartupError};
     use serde_json;
     use std::io;
     use std::io::Write;
-    use std::net::SocketAddr;
+    use std::net::{SocketAddr, UdpSocket};
     use std::panic;
     use std::sync::mpsc::channel;
     use std::sync::mpsc::RecvError;
@@ -143,6 +205,18 @@ mod tests {
         assert_matches!(Error::from(ioe), Error::IO(_));
     }
     #[test]
+    fn bind_error_test() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let err = UdpSocket::bind(addr).unwrap_err();
+        assert_matches!(
+            bind_error(addr, err),
+            Error::StartupError(StartupError::PortInUse(a)) if a == addr
+        );
+        let ioe = io::Error::new(io::ErrorKind::NotFound, "hi");
+        assert_matches!(bind_error(addr, ioe), Error::IO(_));
+    }
+    #[test]
     fn fmt_test() {
         write!(io::sink(), "{:?}", addr_parse_error()).unwrap();
         write!(io::sink(), "{:?}", Error::from(RecvError {})).unwrap();
@@ -1,7 +1,8 @@
 //! The `retransmit_stage` retransmits blobs between validators
 
 use counter::Counter;
-use crdt::Crdt;
+use cpu_time;
+use crdt::{Crdt, RetransmitPeers};
 use log::Level;
 use packet::BlobRecycler;
 use result::{Error, Result};
@@ -18,6 +19,7 @@ use window::{self, SharedWindow};
 
 fn retransmit(
     crdt: &Arc<RwLock<Crdt>>,
+    peers: &mut RetransmitPeers,
     recycler: &BlobRecycler,
     r: &BlobReceiver,
     sock: &UdpSocket,
@@ -27,10 +29,11 @@ fn retransmit(
     while let Ok(mut nq) = r.try_recv() {
         dq.append(&mut nq);
     }
-    {
-        for b in &dq {
-            Crdt::retransmit(&crdt, b, sock)?;
-        }
+    // check for changes to crdt, such as a new leader, before every batch, but
+    // only copy the table when there are some
+    peers.refresh(crdt);
+    for b in &dq {
+        Crdt::retransmit_to(&peers.me, &peers.table, b, sock)?;
     }
     while let Some(b) = dq.pop_front() {
         recycler.recycle(b);
@@ -55,9 +58,11 @@ fn retransmitter(
     Builder::new()
         .name("solana-retransmitter".to_string())
         .spawn(move || {
+            cpu_time::register_thread("retransmit_stage");
             trace!("retransmitter started");
+            let mut peers = RetransmitPeers::new(&crdt);
             loop {
-                if let Err(e) = retransmit(&crdt, &recycler, &r, &sock) {
+                if let Err(e) = retransmit(&crdt, &mut peers, &recycler, &r, &sock) {
                     match e {
                         Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
                         Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
@@ -1,271 +1,3889 @@
 //! The `rpc` module implements the Solana RPC interface.
 
-use bank::Bank;
+use self::error::RpcCustomError;
+use account::Account;
+use bank::{self, Bank, RecentFees, FEE_BURN_PERCENT, INFLATION_PERCENT, MAX_ENTRY_IDS};
+use base64;
+use bincode::{deserialize, serialize};
+use block_cost::SlotCost;
 use bs58;
+use counter::Counter;
+use cpu_time::stage_cpu_times;
+use crdt::Crdt;
+use entry::Entry;
+use hash::{extend_and_hash, hash, Hash};
+use identity_lease::{IdentityLease, LeaseError};
+use influx_db_client as influxdb;
+use jsonrpc_core::futures::{future, Future};
 use jsonrpc_core::*;
 use jsonrpc_http_server::*;
+use jsonrpc_macros::Trailing;
+use ledger::{
+    find_transaction, find_transactions_for_address, read_ledger, LedgerWindow, SlotIndex,
+    TICKS_PER_SLOT,
+};
+use log::Level;
+use metrics;
+use packet::PACKET_DATA_SIZE;
+use packet_capture::{self, CaptureTarget};
+use rent;
+use result::{bind_error, Result as ServiceResult, StartupError};
+use rpc_tls::{ClientAddrs, RpcTlsIdentity, TlsProxy};
 use service::Service;
-use signature::{Pubkey, Signature};
+use signature::{ParsePubkeyError, Pubkey, Signature};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io;
 use std::mem;
-use std::net::SocketAddr;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::result;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{self, Builder, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use timing::{duration_as_ms, duration_as_us, timestamp};
+use transaction::Transaction;
+use zstd;
 
 pub const RPC_PORT: u16 = 8899;
+pub const ADMIN_RPC_PORT: u16 = 8900;
+
+/// Where a fullnode serves admin RPC unless its config says otherwise:
+/// `ADMIN_RPC_PORT` on the loopback interface.
+pub fn default_admin_rpc_addr() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), ADMIN_RPC_PORT)
+}
+
+/// Upper bounds, in microseconds, of the latency buckets kept for each RPC method.
+/// Calls slower than the last bound are counted in an extra overflow bucket.
+const RPC_LATENCY_BUCKETS_US: [u64; 7] = [100, 500, 1_000, 5_000, 10_000, 50_000, 100_000];
+
+/// Number of calls to a method between submissions of its histogram to metrics.
+const RPC_STATS_SUBMIT_RATE: usize = 100;
+
+/// How long a request that reads the ledger or every account may run before
+/// it gives up with a timeout error, rather than tie up a server thread.
+pub const RPC_REQUEST_TIMEOUT_MS: u64 = 10_000;
+
+/// Most signatures `getSignaturesForAddress` returns at once.
+pub const MAX_SIGNATURES_FOR_ADDRESS: usize = 1000;
+
+/// Most signatures `getSignatureStatuses` looks up at once.
+pub const MAX_SIGNATURE_STATUSES: usize = 256;
+
+/// Most entries `getEntries` returns, and how many it returns by default.
+pub const MAX_ENTRIES_PER_REQUEST: usize = 1000;
+
+/// Most bytes of encoded entries `getEntries` returns. At least one entry is
+/// always returned, as long as there is one.
+pub const MAX_ENTRIES_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Most slots `getSlotLeaders` returns the leaders of.
+pub const MAX_SLOT_LEADERS: u64 = 5000;
+
+/// Most ids `getRecentLastIds` returns, and how many it returns by default.
+pub const MAX_RECENT_LAST_IDS: usize = 150;
+
+/// Largest account data returned base58 encoded, which gets slow to encode and
+/// decode well before data gets big.
+pub const MAX_BASE58_BYTES: usize = 128;
+
+/// Most client IPs the rate limiter keeps a bucket for. Clients that have
+/// been quiet long enough to fill their bucket are forgotten, and past this
+/// many the ones quiet the longest are dropped to make room for new ones.
+const MAX_RATE_LIMITED_IPS: usize = 100_000;
+
+/// How many buckets the rate limiter drops at once when it's full, so that
+/// finding them is paid for by many new clients.
+const RATE_LIMITED_IPS_EVICTED: usize = MAX_RATE_LIMITED_IPS / 16;
+
+/// How often the API keys file is checked for changes.
+const API_KEYS_CHECK_MS: u64 = 1000;
+
+/// The errors RPC requests fail with beyond JSON-RPC's own. Each has a
+/// Solana-specific code in the server error range, or `InvalidParams` for a
+/// malformed parameter, and a `data` object whose `kind` says which error it
+/// is, so clients needn't match on messages.
+pub mod error {
+    use jsonrpc_core::{Error, ErrorCode};
+    use serde_json;
+
+    /// The identity lease isn't held, or is already at a later term.
+    pub const IDENTITY_LEASE: i64 = -32001;
+    /// The method reads a ledger this node doesn't keep.
+    pub const NO_LEDGER: i64 = -32002;
+    /// This node doesn't take transactions.
+    pub const TRANSACTIONS_NOT_ACCEPTED: i64 = -32003;
+    /// The request ran out of time.
+    pub const REQUEST_TIMEOUT: i64 = -32004;
+    /// The client, or all clients together, went over the rate limit.
+    pub const RATE_LIMITED: i64 = -32005;
+    /// The method reads a gossip table this node doesn't keep.
+    pub const NO_GOSSIP: i64 = -32006;
+    /// The request's API key isn't known, or it has none and needs one.
+    pub const INVALID_API_KEY: i64 = -32007;
+    /// The request's API key may not call the method.
+    pub const METHOD_NOT_ALLOWED: i64 = -32008;
+    /// The node is too far behind the leader to be served from.
+    pub const NODE_UNHEALTHY: i64 = -32009;
+    /// The packet capture couldn't be started.
+    pub const PACKET_CAPTURE: i64 = -32010;
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    #[serde(tag = "kind", rename_all = "camelCase")]
+    pub enum RpcCustomError {
+        InvalidPubkey {
+            reason: String,
+        },
+        InvalidSignature {
+            reason: String,
+        },
+        InvalidAddress {
+            reason: String,
+        },
+        IdentityLeaseNotHeld,
+        StaleIdentityLeaseTerm {
+            term: u64,
+        },
+        NoLedger,
+        TransactionsNotAccepted,
+        RequestTimeout {
+            method: String,
+            #[serde(rename = "timeoutMs")]
+            timeout_ms: u64,
+        },
+        RateLimited {
+            reason: String,
+        },
+        NoGossip,
+        MissingApiKey,
+        InvalidApiKey,
+        MethodNotAllowed {
+            method: String,
+        },
+        /// `entries_behind` is `None` when the leader's height isn't known.
+        NodeUnhealthy {
+            #[serde(rename = "entriesBehind")]
+            entries_behind: Option<u64>,
+        },
+        PacketCaptureFailed {
+            reason: String,
+        },
+    }
+
+    impl RpcCustomError {
+        pub fn code(&self) -> ErrorCode {
+            match self {
+                RpcCustomError::InvalidPubkey { .. }
+                | RpcCustomError::InvalidSignature { .. }
+                | RpcCustomError::InvalidAddress { .. } => ErrorCode::InvalidParams,
+                RpcCustomError::IdentityLeaseNotHeld
+                | RpcCustomError::StaleIdentityLeaseTerm { .. } => {
+                    ErrorCode::ServerError(IDENTITY_LEASE)
+                }
+                RpcCustomError::NoLedger => ErrorCode::ServerError(NO_LEDGER),
+                RpcCustomError::TransactionsNotAccepted => {
+                    ErrorCode::ServerError(TRANSACTIONS_NOT_ACCEPTED)
+                }
+                RpcCustomError::RequestTimeout { .. } => ErrorCode::ServerError(REQUEST_TIMEOUT),
+                RpcCustomError::RateLimited { .. } => ErrorCode::ServerError(RATE_LIMITED),
+                RpcCustomError::NoGossip => ErrorCode::ServerError(NO_GOSSIP),
+                RpcCustomError::MissingApiKey | RpcCustomError::InvalidApiKey => {
+                    ErrorCode::ServerError(INVALID_API_KEY)
+                }
+                RpcCustomError::MethodNotAllowed { .. } => {
+                    ErrorCode::ServerError(METHOD_NOT_ALLOWED)
+                }
+                RpcCustomError::NodeUnhealthy { .. } => ErrorCode::ServerError(NODE_UNHEALTHY),
+                RpcCustomError::PacketCaptureFailed { .. } => {
+                    ErrorCode::ServerError(PACKET_CAPTURE)
+                }
+            }
+        }
+
+        fn message(&self) -> String {
+            match self {
+                RpcCustomError::InvalidPubkey { reason } => format!("invalid pubkey: {}", reason),
+                RpcCustomError::InvalidSignature { reason } => {
+                    format!("invalid signature: {}", reason)
+                }
+                RpcCustomError::InvalidAddress { reason } => format!("invalid address: {}", reason),
+                RpcCustomError::IdentityLeaseNotHeld => {
+                    "identity lease is not held by this node".to_string()
+                }
+                RpcCustomError::StaleIdentityLeaseTerm { term } => {
+                    format!("identity lease term is already {}", term)
+                }
+                RpcCustomError::NoLedger => "this node doesn't keep a ledger".to_string(),
+                RpcCustomError::TransactionsNotAccepted => {
+                    "this node doesn't accept transactions".to_string()
+                }
+                RpcCustomError::RequestTimeout { method, timeout_ms } => {
+                    format!("{} took longer than {}ms", method, timeout_ms)
+                }
+                RpcCustomError::RateLimited { reason } => format!("Rate limited: {}", reason),
+                RpcCustomError::NoGossip => "this node doesn't gossip".to_string(),
+                RpcCustomError::MissingApiKey => "this node requires an API key".to_string(),
+                RpcCustomError::InvalidApiKey => "unknown API key".to_string(),
+                RpcCustomError::MethodNotAllowed { method } => {
+                    format!("{} isn't allowed for this API key", method)
+                }
+                RpcCustomError::NodeUnhealthy {
+                    entries_behind: Some(entries_behind),
+                } => format!("node is behind by {} entries", entries_behind),
+                RpcCustomError::NodeUnhealthy {
+                    entries_behind: None,
+                } => "node doesn't know the leader's entry height".to_string(),
+                RpcCustomError::PacketCaptureFailed { reason } => {
+                    format!("packet capture failed: {}", reason)
+                }
+            }
+        }
+    }
+
+    impl From<RpcCustomError> for Error {
+        fn from(err: RpcCustomError) -> Self {
+            Error {
+                code: err.code(),
+                message: err.message(),
+                data: serde_json::to_value(&err).ok(),
+            }
+        }
+    }
+}
+
+/// The thread layout and request limits of the RPC server.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct JsonRpcConfig {
+    /// The number of threads serving requests. Requests of a batch are served
+    /// one after the other on a single thread.
+    pub threads: usize,
+    /// The most requests one batch may hold. Larger batches are refused whole.
+    pub max_batch_size: usize,
+    /// The requests per second each client IP may sustain, or 0 for no limit.
+    /// Each request of a batch counts.
+    pub requests_per_second_per_ip: u64,
+    /// The requests a client IP may make at once after a quiet period. A batch
+    /// larger than this is always refused while the per-IP limit is on.
+    pub burst_per_ip: u64,
+    /// The requests per second the server serves for all clients together, or
+    /// 0 for no limit. Up to a second's worth may come at once.
+    pub requests_per_second: u64,
+    /// A JSON file of `RpcApiKeys`, giving the clients that send one of its
+    /// keys their own allowed methods and request rate. It's read again
+    /// whenever it changes.
+    pub api_keys: Option<String>,
+    /// How many entries the node may be behind the leader's gossiped height
+    /// before `getHealth` reports it unhealthy.
+    pub health_max_entries_behind: u64,
+    /// Serve HTTPS with this certificate and key rather than plain HTTP.
+    pub tls: Option<RpcTlsIdentity>,
+}
+
+impl Default for JsonRpcConfig {
+    fn default() -> Self {
+        JsonRpcConfig {
+            threads: 4,
+            max_batch_size: 100,
+            requests_per_second_per_ip: 0,
+            burst_per_ip: 100,
+            requests_per_second: 0,
+            api_keys: None,
+            health_max_entries_behind: 1000,
+            tls: None,
+        }
+    }
+}
+
+/// A token bucket of requests, refilled at some rate up to some burst.
+#[derive(Clone, Copy, Debug)]
+struct TokenBucket {
+    allowance: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: u64, now: Instant) -> Self {
+        TokenBucket {
+            allowance: burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, per_second: u64, burst: u64, now: Instant) {
+        if now > self.last_refill {
+            let elapsed_us = duration_as_us(&now.duration_since(self.last_refill)) as f64;
+            let allowance = self.allowance + elapsed_us * per_second as f64 / 1_000_000.0;
+            self.allowance = allowance.min(burst as f64);
+            self.last_refill = now;
+        }
+    }
+
+    /// Take `requests` from the bucket if they're all there.
+    fn take(&mut self, requests: u64, per_second: u64, burst: u64, now: Instant) -> bool {
+        self.refill(per_second, burst, now);
+        if self.allowance < requests as f64 {
+            return false;
+        }
+        self.allowance -= requests as f64;
+        true
+    }
+}
+
+/// The address a client's requests are counted under. An IPv6 client can
+/// usually send from any address in its /64, so those count as one.
+fn rate_limited_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => {
+            let s = ip.segments();
+            if s[..5] == [0; 5] && s[5] == 0xffff {
+                let o = ip.octets();
+                return IpAddr::V4(Ipv4Addr::new(o[12], o[13], o[14], o[15]));
+            }
+            IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0))
+        }
+    }
+}
+
+/// Limits the requests each client IP makes, and the requests of all clients
+/// together, to the rates in a `JsonRpcConfig`.
+struct RateLimiter {
+    config: JsonRpcConfig,
+    /// The buckets of the clients seen since `generation_start`.
+    per_ip: HashMap<IpAddr, TokenBucket>,
+    /// The buckets of the clients seen in the generation before and not since.
+    /// A generation lasts as long as an empty bucket takes to fill, so these
+    /// are dropped all at once at the end of the next one.
+    previous: HashMap<IpAddr, TokenBucket>,
+    generation_start: Instant,
+    /// How long a generation lasts
+    generation_us: u64,
+    global: TokenBucket,
+}
+
+impl RateLimiter {
+    /// Fails if the per-IP limit is on without room for a single request.
+    fn new(config: &JsonRpcConfig) -> result::Result<Self, String> {
+        let generation_us = match config.requests_per_second_per_ip {
+            0 => 0,
+            rate if config.burst_per_ip == 0 => {
+                return Err(format!(
+                    "a limit of {} requests per second per IP needs a burst of at least 1",
+                    rate
+                ));
+            }
+            rate => match config.burst_per_ip.checked_mul(1_000_000) {
+                Some(burst_us) => burst_us / rate,
+                None => return Err(format!("burst of {} is too large", config.burst_per_ip)),
+            },
+        };
+        let now = Instant::now();
+        Ok(RateLimiter {
+            config: config.clone(),
+            per_ip: HashMap::new(),
+            previous: HashMap::new(),
+            generation_start: now,
+            generation_us,
+            global: TokenBucket::new(config.requests_per_second, now),
+        })
+    }
+
+    /// Start a new generation of buckets if the current one is over.
+    fn age(&mut self, now: Instant) {
+        if now > self.generation_start
+            && duration_as_us(&now.duration_since(self.generation_start)) >= self.generation_us
+        {
+            self.previous = mem::replace(&mut self.per_ip, HashMap::new());
+            self.generation_start = now;
+        }
+    }
+
+    /// Make room for new clients by dropping buckets of the previous
+    /// generation, whose clients have been quiet the longest. If every bucket
+    /// is from this generation, it's ended early first, so that the clients
+    /// that aren't dropped keep their buckets when they come back.
+    fn evict(&mut self, now: Instant) {
+        if self.previous.is_empty() {
+            self.previous = mem::replace(&mut self.per_ip, HashMap::new());
+            self.generation_start = now;
+        }
+        let mut evicted = 0;
+        self.previous.retain(|_, _| {
+            if evicted == RATE_LIMITED_IPS_EVICTED {
+                return true;
+            }
+            evicted += 1;
+            false
+        });
+        inc_new_counter_info!("rpc-rate_limit_evicted", evicted);
+    }
+
+    /// Take `requests` from the buckets of `client_ip` and of the server, or
+    /// say why they can't be served. A client is charged for its requests even
+    /// if the server then refuses them, so it can't drain the server's bucket.
+    fn check(
+        &mut self,
+        client_ip: Option<IpAddr>,
+        requests: u64,
+        now: Instant,
+    ) -> result::Result<(), &'static str> {
+        if let (Some(ip), true) = (client_ip, self.config.requests_per_second_per_ip > 0) {
+            self.age(now);
+            let ip = rate_limited_ip(ip);
+            if !self.per_ip.contains_key(&ip) {
+                let bucket = match self.previous.remove(&ip) {
+                    Some(bucket) => bucket,
+                    None => {
+                        if self.per_ip.len() + self.previous.len() >= MAX_RATE_LIMITED_IPS {
+                            self.evict(now);
+                        }
+                        TokenBucket::new(self.config.burst_per_ip, now)
+                    }
+                };
+                self.per_ip.insert(ip, bucket);
+            }
+            let config = &self.config;
+            let bucket = self.per_ip.get_mut(&ip).unwrap();
+            if !bucket.take(
+                requests,
+                config.requests_per_second_per_ip,
+                config.burst_per_ip,
+                now,
+            ) {
+                return Err("too many requests from this address");
+            }
+        }
+        let rate = self.config.requests_per_second;
+        if rate > 0 && !self.global.take(requests, rate, rate, now) {
+            return Err("too many requests");
+        }
+        Ok(())
+    }
+}
+
+/// What the clients sending one API key may do.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct RpcApiKey {
+    /// The methods the key may call, or None for all of them
+    pub methods: Option<HashSet<String>>,
+    /// The requests per second the key may sustain, or 0 for no limit. A
+    /// request with a key isn't held to the per-IP limit as well.
+    pub requests_per_second: u64,
+    /// The requests the key may make at once after a quiet period
+    pub burst: u64,
+}
+
+impl Default for RpcApiKey {
+    fn default() -> Self {
+        RpcApiKey {
+            methods: None,
+            requests_per_second: 0,
+            burst: 100,
+        }
+    }
+}
+
+/// The API keys file, which clients pick their key from with the `X-Api-Key`
+/// header of their requests.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct RpcApiKeys {
+    /// Refuse requests without a key, rather than serve them under the per-IP
+    /// limit
+    pub require_key: bool,
+    pub keys: HashMap<String, RpcApiKey>,
+}
+
+impl RpcApiKeys {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+}
+
+/// Holds requests with an API key to what the key allows, picking up changes
+/// to the keys file as it goes.
+struct ApiKeyLimiter {
+    path: String,
+    keys: RpcApiKeys,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+    buckets: HashMap<String, TokenBucket>,
+}
+
+impl ApiKeyLimiter {
+    fn new(path: &str) -> io::Result<Self> {
+        Ok(ApiKeyLimiter {
+            path: path.to_string(),
+            keys: RpcApiKeys::load(path)?,
+            modified: Self::modified(path),
+            last_check: Instant::now(),
+            buckets: HashMap::new(),
+        })
+    }
+
+    fn modified(path: &str) -> Option<SystemTime> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Read the keys again if the file changed since they were last read. A
+    /// file that doesn't load leaves the keys as they were.
+    fn reload_if_changed(&mut self, now: Instant) {
+        if now < self.last_check + Duration::from_millis(API_KEYS_CHECK_MS) {
+            return;
+        }
+        self.last_check = now;
+        let modified = Self::modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match RpcApiKeys::load(&self.path) {
+            Ok(keys) => {
+                info!("reloaded RPC API keys from {}", self.path);
+                self.buckets.retain(|key, _| keys.keys.contains_key(key));
+                self.keys = keys;
+            }
+            Err(err) => warn!("keeping the RPC API keys, {}: {:?}", self.path, err),
+        }
+    }
+
+    /// Check the `methods` of a request of `requests` calls against what
+    /// `api_key` allows, and take the calls from the key's quota. Returns
+    /// whether the request was made with a key, and so skips the per-IP limit.
+    fn check(
+        &mut self,
+        api_key: Option<&str>,
+        methods: &[&str],
+        requests: u64,
+        now: Instant,
+    ) -> result::Result<bool, RpcCustomError> {
+        self.reload_if_changed(now);
+        let api_key = match api_key {
+            Some(api_key) => api_key,
+            None if self.keys.require_key => return Err(RpcCustomError::MissingApiKey),
+            None => return Ok(false),
+        };
+        let limits = self
+            .keys
+            .keys
+            .get(api_key)
+            .ok_or(RpcCustomError::InvalidApiKey)?;
+        if let Some(ref allowed) = limits.methods {
+            if let Some(method) = methods.iter().find(|method| !allowed.contains(**method)) {
+                return Err(RpcCustomError::MethodNotAllowed {
+                    method: method.to_string(),
+                });
+            }
+        }
+        if limits.requests_per_second > 0 {
+            let bucket = self
+                .buckets
+                .entry(api_key.to_string())
+                .or_insert_with(|| TokenBucket::new(limits.burst, now));
+            if !bucket.take(requests, limits.requests_per_second, limits.burst, now) {
+                return Err(RpcCustomError::RateLimited {
+                    reason: "over the quota of this API key".to_string(),
+                });
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Refuses batches of more than `max_batch_size` requests, requests an API key
+/// doesn't allow, and requests over the rate limits, before any of them runs.
+#[derive(Clone)]
+struct RequestLimits {
+    max_batch_size: usize,
+    api_keys: Option<Arc<Mutex<ApiKeyLimiter>>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+}
+
+impl RequestLimits {
+    /// Fails if the API keys file can't be loaded.
+    fn new(config: &JsonRpcConfig) -> ServiceResult<Self> {
+        let api_keys = match config.api_keys {
+            Some(ref path) => {
+                let api_keys = ApiKeyLimiter::new(path)
+                    .map_err(|err| StartupError::BadApiKeys(path.clone(), err.to_string()))?;
+                Some(Arc::new(Mutex::new(api_keys)))
+            }
+            None => None,
+        };
+        let rate_limiter = RateLimiter::new(config).map_err(StartupError::BadRpcConfig)?;
+        Ok(RequestLimits {
+            max_batch_size: config.max_batch_size,
+            api_keys,
+            rate_limiter: Arc::new(Mutex::new(rate_limiter)),
+        })
+    }
+
+    /// Check `request` against the API key it was made with, if any, and the
+    /// rate limits.
+    fn check(&self, request: &Request, meta: &Meta) -> result::Result<(), Error> {
+        let calls: Vec<&Call> = match *request {
+            Request::Batch(ref calls) => calls.iter().collect(),
+            Request::Single(ref call) => vec![call],
+        };
+        let requests = calls.len();
+        if requests > self.max_batch_size {
+            inc_new_counter_info!("rpc-batch_too_large", 1);
+            return Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: format!(
+                    "Batch of {} requests exceeds the limit of {}",
+                    requests, self.max_batch_size
+                ),
+                data: None,
+            });
+        }
+        let now = Instant::now();
+        let keyed = match self.api_keys {
+            Some(ref api_keys) => {
+                let methods: Vec<&str> = calls
+                    .iter()
+                    .filter_map(|call| match **call {
+                        Call::MethodCall(ref call) => Some(call.method.as_str()),
+                        Call::Notification(ref call) => Some(call.method.as_str()),
+                        Call::Invalid(_) => None,
+                    })
+                    .collect();
+                let api_key = meta.api_key.as_ref().map(|api_key| api_key.as_str());
+                api_keys
+                    .lock()
+                    .unwrap()
+                    .check(api_key, &methods, requests as u64, now)
+                    .map_err(|err| {
+                        inc_new_counter_info!("rpc-api_key_refused", requests);
+                        err
+                    })?
+            }
+            None => false,
+        };
+        let client_ip = if keyed { None } else { meta.client_ip };
+        let checked = self
+            .rate_limiter
+            .lock()
+            .unwrap()
+            .check(client_ip, requests as u64, now);
+        if let Err(reason) = checked {
+            inc_new_counter_info!("rpc-rate_limited", requests);
+            return Err(RpcCustomError::RateLimited {
+                reason: reason.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn refuse(error: Error) -> FutureResponse {
+        Box::new(future::ok(Some(Response::Single(Output::Failure(
+            Failure {
+                jsonrpc: Some(Version::V2),
+                error,
+                id: Id::Null,
+            },
+        )))))
+    }
+}
+
+impl Middleware<Meta> for RequestLimits {
+    type Future = FutureResponse;
+
+    fn on_request<F, X>(&self, request: Request, meta: Meta, next: F) -> Self::Future
+    where
+        F: FnOnce(Request, Meta) -> X + Send,
+        X: Future<Item = Option<Response>, Error = ()> + Send + 'static,
+    {
+        if let Err(error) = self.check(&request, &meta) {
+            return Self::refuse(error);
+        }
+        Box::new(next(request, meta))
+    }
+}
 
 pub struct JsonRpcService {
     thread_hdl: JoinHandle<()>,
+    close_handle: CloseHandle,
+    tls_proxy: Option<TlsProxy>,
+}
+
+impl JsonRpcService {
+    /// Serve RPC requests on `backend`, block requests from the ledger at
+    /// `ledger_path`, if the node keeps one, and cluster requests from `crdt`,
+    /// if the node gossips, and forward sent transactions to
+    /// `transactions_addr`, if the node accepts them. `config` sizes the server,
+    /// and with a TLS identity the server listens on a loopback port behind a
+    /// `TlsProxy` on `rpc_addr`. It serves until `close` is called.
+    pub fn new(
+        backend: Arc<RpcBackend>,
+        ledger_path: Option<&str>,
+        crdt: Option<Arc<RwLock<Crdt>>>,
+        transactions_addr: Option<SocketAddr>,
+        config: JsonRpcConfig,
+        rpc_addr: SocketAddr,
+    ) -> ServiceResult<Self> {
+        let mut request_processor = match ledger_path {
+            Some(ledger_path) => JsonRpcRequestProcessor::new_with_ledger(backend, ledger_path),
+            None => JsonRpcRequestProcessor::new(backend),
+        };
+        request_processor.crdt = crdt;
+        request_processor.transactions_addr = transactions_addr;
+        request_processor.health_max_entries_behind = config.health_max_entries_behind;
+        let mut io = MetaIoHandler::with_middleware(RequestLimits::new(&config)?);
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+
+        let tls_config = match config.tls {
+            Some(ref identity) => Some(identity.load()?),
+            None => None,
+        };
+        let http_addr = match tls_config {
+            Some(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0),
+            None => rpc_addr,
+        };
+        let clients = ClientAddrs::default();
+        let server_clients = clients.clone();
+        let server = ServerBuilder::with_meta_extractor(io, move |req: &hyper::Request| Meta {
+            request_processor: request_processor.clone(),
+            client_ip: req
+                .remote_addr()
+                .map(|addr| server_clients.resolve(addr).ip()),
+            api_key: req
+                .headers()
+                .get_raw("X-Api-Key")
+                .and_then(|value| value.one())
+                .and_then(|value| str::from_utf8(value).ok())
+                .map(|value| value.to_string()),
+        })
+        .threads(config.threads)
+        .cors(DomainsValidation::AllowOnly(vec![
+            AccessControlAllowOrigin::Any,
+        ]))
+        .start_http(&http_addr)
+        .map_err(|err| bind_error(http_addr, err))?;
+        let tls_proxy = match tls_config {
+            Some(tls_config) => Some(
+                TlsProxy::new(tls_config, rpc_addr, *server.address(), clients)
+                    .map_err(|err| bind_error(rpc_addr, err))?,
+            ),
+            None => None,
+        };
+        let close_handle = server.close_handle();
+        let thread_hdl = Builder::new()
+            .name("solana-jsonrpc".to_string())
+            .spawn(move || server.wait())?;
+        Ok(JsonRpcService {
+            thread_hdl,
+            close_handle,
+            tls_proxy,
+        })
+    }
+
+    /// Stop serving requests. The service's thread finishes once the server
+    /// has shut down, without the rest of the node having to exit.
+    pub fn close(&self) {
+        if let Some(ref tls_proxy) = self.tls_proxy {
+            tls_proxy.close();
+        }
+        self.close_handle.clone().close();
+    }
+}
+
+impl Service for JsonRpcService {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        let mut thread_hdls = vec![self.thread_hdl];
+        if let Some(tls_proxy) = self.tls_proxy {
+            thread_hdls.extend(tls_proxy.thread_hdls());
+        }
+        thread_hdls
+    }
+
+    fn join(self) -> thread::Result<()> {
+        for thread_hdl in self.thread_hdls() {
+            thread_hdl.join()?;
+        }
+        Ok(())
+    }
+}
+
+/// Operator-only RPC for handing the identity lease between a warm standby pair.
+/// It should only be bound to a local or otherwise trusted address.
+pub struct AdminRpcService {
+    thread_hdl: JoinHandle<()>,
+    close_handle: CloseHandle,
+    local_addr: SocketAddr,
+}
+
+impl AdminRpcService {
+    /// Serve admin requests for `lease` on `rpc_addr` until `close` is called.
+    pub fn new(lease: Arc<IdentityLease>, rpc_addr: SocketAddr) -> ServiceResult<Self> {
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcAdminImpl;
+        io.extend_with(rpc.to_delegate());
+
+        let server =
+            ServerBuilder::with_meta_extractor(io, move |_req: &hyper::Request| AdminMeta {
+                lease: lease.clone(),
+            })
+            // more than one thread shares the port with SO_REUSEPORT, which would
+            // let a second node on this host take half of the failover requests
+            .threads(1)
+            .start_http(&rpc_addr)
+            .map_err(|err| bind_error(rpc_addr, err))?;
+        let close_handle = server.close_handle();
+        let local_addr = *server.address();
+        let thread_hdl = Builder::new()
+            .name("solana-admin-jsonrpc".to_string())
+            .spawn(move || server.wait())?;
+        Ok(AdminRpcService {
+            thread_hdl,
+            close_handle,
+            local_addr,
+        })
+    }
+
+    /// The address the server listens on, with the port it was given if
+    /// `rpc_addr` asked for any free port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop serving requests. The service's thread finishes once the server
+    /// has shut down.
+    pub fn close(&self) {
+        self.close_handle.clone().close();
+    }
+}
+
+impl Service for AdminRpcService {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        vec![self.thread_hdl]
+    }
+
+    fn join(self) -> thread::Result<()> {
+        self.thread_hdl.join()
+    }
+}
+
+/// A leader slot's transactions, as returned by `getConfirmedBlock`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcConfirmedBlock {
+    pub slot: u64,
+    pub parent_slot: u64,
+    /// The id of the slot's last entry
+    pub blockhash: String,
+    /// The id of the last entry of the parent slot
+    pub previous_blockhash: String,
+    pub transactions: Vec<Transaction>,
+}
+
+/// How `getEntries` encodes entries
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RpcEntryEncoding {
+    Json,
+    /// The bincode encoding of `Entry::to_bytes`, base64 encoded
+    Base64,
+}
+
+impl Default for RpcEntryEncoding {
+    fn default() -> Self {
+        RpcEntryEncoding::Json
+    }
+}
+
+/// Paging options of `getEntries`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcEntriesConfig {
+    /// Return at most this many, up to `MAX_ENTRIES_PER_REQUEST`
+    pub max_count: Option<usize>,
+    pub encoding: Option<RpcEntryEncoding>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum RpcEntry {
+    Json(Entry),
+    Base64(String),
+}
+
+/// A page of the ledger, as returned by `getEntries`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcEntries {
+    /// The height of the first entry
+    pub start_height: u64,
+    /// The height to ask for the next page from
+    pub next_height: u64,
+    pub entries: Vec<RpcEntry>,
+}
+
+/// The hash of every account, as returned by `getAccountsHashes`, for comparing
+/// the state of two nodes.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcAccountsHashes {
+    /// The last id the accounts are the state as of
+    pub last_id: String,
+    /// The hash of all the accounts, in order
+    pub hash: String,
+    /// The accounts, sorted by address
+    pub accounts: Vec<RpcAccountHash>,
+}
+
+/// The build of a node, as returned by `getVersion`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcVersionInfo {
+    /// The crate version
+    pub version: String,
+    /// The optional features compiled in, such as `cuda` and `erasure`
+    pub features: Vec<String>,
+}
+
+/// A node of the cluster, as returned by `getClusterNodes`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcContactInfo {
+    pub pubkey: String,
+    pub gossip: String,
+    pub tpu: String,
+    pub tvu: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcAccountHash {
+    pub pubkey: String,
+    pub tokens: i64,
+    pub hash: String,
+}
+
+/// A recorded transaction, as returned by `getConfirmedTransaction`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcConfirmedTransaction {
+    /// The leader slot the transaction was recorded in
+    pub slot: u64,
+    pub transaction: Transaction,
+    pub memo: Option<String>,
+}
+
+/// How a transaction fared, as returned by `getSignatureStatuses`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum RpcTransactionStatus {
+    Confirmed,
+    /// The node never saw the transaction, or its last id expired
+    NotFound,
+    /// The transaction failed with this error
+    Failed(String),
+}
+
+/// A transaction that involves an address, as returned by `getSignaturesForAddress`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSignatureForAddress {
+    pub signature: String,
+    /// The leader slot the transaction was recorded in
+    pub slot: u64,
+    pub memo: Option<String>,
+}
+
+/// Paging options of `getSignaturesForAddress`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSignaturesForAddressConfig {
+    /// Return at most this many, up to `MAX_SIGNATURES_FOR_ADDRESS`
+    pub limit: Option<usize>,
+    /// Only return transactions older than the one with this signature
+    pub before: Option<String>,
+}
+
+/// The network's economics, as returned by `getEconomicParameters`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcEconomicParameters {
+    /// The yearly growth of the token supply, in percent
+    pub inflation_percent: u64,
+    /// The share of each transaction fee that's burned, in percent
+    pub fee_burn_percent: u64,
+    /// The entry ids registered between rent collections
+    pub rent_epoch_entry_ids: usize,
+    /// The bytes of account storage a token of rent pays for per epoch
+    pub rent_bytes_per_token: u64,
+    /// The epochs of rent an account has to hold to be exempt from it
+    pub rent_exempt_epochs: i64,
+}
+
+/// Recent fee levels, as returned by `getFeeEstimate`. Only a leader banks
+/// transactions, so other nodes report no samples.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcFeeEstimate {
+    /// The number of recently banked transactions the fees are taken from
+    pub samples: usize,
+    pub min_fee: i64,
+    pub median_fee: i64,
+    /// The fee that 90% of recent transactions paid at most
+    pub high_fee: i64,
+    pub max_fee: i64,
+    /// The number of verified transactions in the last batch the leader banked
+    pub queue_depth: usize,
+    /// A fee likely to land within the next slot
+    pub recommended_fee: i64,
+}
+
+/// The totals of the slots a leader recently wrote, as returned by
+/// `getBlockProduction`. Only a node that wrote slots as leader reports any.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBlockProduction {
+    /// The slots, oldest first
+    pub slots: Vec<RpcSlotCost>,
+    /// The most compute units any one of the slots took
+    pub max_compute_units: u64,
+}
+
+/// One slot's totals in `RpcBlockProduction`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSlotCost {
+    pub slot: u64,
+    pub entries: u64,
+    pub transactions: u64,
+    pub signatures: u64,
+    /// Estimated compute units to process the slot's transactions
+    pub compute_units: u64,
+    /// The serialized size of the slot's entries
+    pub bytes: u64,
+}
+
+/// An entry id transactions can be made against, as returned by
+/// `getRecentLastIds`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcRecentLastId {
+    pub last_id: String,
+    /// How long ago the node registered the id
+    pub age_ms: u64,
+    /// The number of ids the node can register before this one expires
+    pub remaining_ids: usize,
+}
+
+/// The ids returned by `getRecentLastIds`, newest first, with the fee
+/// `getFeeEstimate` recommends, for signing a transaction to send later.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcRecentLastIds {
+    pub last_ids: Vec<RpcRecentLastId>,
+    pub recommended_fee: i64,
+}
+
+/// A whole account record, as returned by `getAccountInfo`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcAccountInfo {
+    pub tokens: i64,
+    /// The program that owns the account, base58 encoded
+    pub owner: String,
+    pub userdata: String,
+    /// How `userdata` is encoded
+    pub encoding: RpcAccountEncoding,
+}
+
+/// How account data is encoded in a response
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RpcAccountEncoding {
+    /// Only for data up to `MAX_BASE58_BYTES`
+    Base58,
+    Base64,
+    /// Compressed with zstd, then base64 encoded
+    #[serde(rename = "base64+zstd")]
+    Base64Zstd,
+}
+
+impl Default for RpcAccountEncoding {
+    fn default() -> Self {
+        RpcAccountEncoding::Base58
+    }
+}
+
+/// Options of `sendTransaction`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSendTransactionConfig {
+    /// How the transaction is encoded
+    pub encoding: Option<RpcAccountEncoding>,
+    /// Run the transaction against the bank without committing or sending it
+    pub simulate: Option<bool>,
+}
+
+/// The last parameter of `sendTransaction`, which may be just the encoding.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum RpcSendTransactionOptions {
+    Encoding(RpcAccountEncoding),
+    Config(RpcSendTransactionConfig),
+}
+
+impl Default for RpcSendTransactionOptions {
+    fn default() -> Self {
+        RpcSendTransactionOptions::Config(RpcSendTransactionConfig::default())
+    }
+}
+
+impl RpcSendTransactionOptions {
+    fn into_config(self) -> RpcSendTransactionConfig {
+        match self {
+            RpcSendTransactionOptions::Encoding(encoding) => RpcSendTransactionConfig {
+                encoding: Some(encoding),
+                simulate: None,
+            },
+            RpcSendTransactionOptions::Config(config) => config,
+        }
+    }
+}
+
+/// How a transaction would fare, as returned by `sendTransaction` when it
+/// simulates.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSimulatedTransaction {
+    pub signature: String,
+    /// The error the transaction would fail with, if any
+    pub err: Option<String>,
+    /// Each account the transaction touches and its balance afterwards
+    pub balances: Vec<(String, i64)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum RpcSendTransactionResult {
+    Signature(String),
+    Simulated(RpcSimulatedTransaction),
+}
+
+/// A fee likely to land within the next slot. Transactions are banked highest
+/// fee first, so while the leader keeps up with its queue any fee lands,
+/// otherwise outbid most recent ones.
+fn recommended_fee(recent_fees: &RecentFees) -> i64 {
+    if recent_fees.queue_depth == 0 {
+        0
+    } else {
+        recent_fees.percentile(75).unwrap_or(0)
+    }
+}
+
+fn encode_account_data(data: &[u8], encoding: RpcAccountEncoding) -> Result<String> {
+    match encoding {
+        RpcAccountEncoding::Base58 => {
+            if data.len() > MAX_BASE58_BYTES {
+                return Err(Error {
+                    code: ErrorCode::InvalidParams,
+                    message: format!(
+                        "base58 encoding is limited to {} bytes of account data, use base64",
+                        MAX_BASE58_BYTES
+                    ),
+                    data: None,
+                });
+            }
+            Ok(bs58::encode(data).into_string())
+        }
+        RpcAccountEncoding::Base64 => Ok(base64::encode(data)),
+        RpcAccountEncoding::Base64Zstd => {
+            let compressed = zstd::stream::encode_all(data, 0).map_err(|err| {
+                warn!("compressing account data: {:?}", err);
+                Error::internal_error()
+            })?;
+            Ok(base64::encode(&compressed))
+        }
+    }
+}
+
+fn decode_data(data: &str, encoding: RpcAccountEncoding) -> Result<Vec<u8>> {
+    let invalid = |what: &str| Error {
+        code: ErrorCode::InvalidParams,
+        message: format!("data isn't valid {}", what),
+        data: None,
+    };
+    match encoding {
+        RpcAccountEncoding::Base58 => bs58::decode(data).into_vec().map_err(|_| invalid("base58")),
+        RpcAccountEncoding::Base64 => base64::decode(data).map_err(|_| invalid("base64")),
+        RpcAccountEncoding::Base64Zstd => {
+            let compressed = base64::decode(data).map_err(|_| invalid("base64"))?;
+            zstd::stream::decode_all(&compressed[..]).map_err(|_| invalid("zstd"))
+        }
+    }
+}
+
+fn parse_pubkey(id: String) -> Result<Pubkey> {
+    let pubkey = id.parse().map_err(|err: ParsePubkeyError| {
+        let reason = err.to_string();
+        RpcCustomError::InvalidPubkey { reason }
+    })?;
+    Ok(pubkey)
+}
+
+fn parse_signature(id: String) -> Result<Signature> {
+    let signature = id.parse().map_err(|err: ParsePubkeyError| {
+        let reason = err.to_string();
+        RpcCustomError::InvalidSignature { reason }
+    })?;
+    Ok(signature)
 }
 
-impl JsonRpcService {
-    pub fn new(bank: Arc<Bank>, rpc_addr: SocketAddr, exit: Arc<AtomicBool>) -> Self {
-        let request_processor = JsonRpcRequestProcessor::new(bank);
-        let thread_hdl = Builder::new()
-            .name("solana-jsonrpc".to_string())
-            .spawn(move || {
-                let mut io = MetaIoHandler::default();
-                let rpc = RpcSolImpl;
-                io.extend_with(rpc.to_delegate());
-
-                let server =
-                    ServerBuilder::with_meta_extractor(io, move |_req: &hyper::Request| Meta {
-                        request_processor: request_processor.clone(),
-                    }).threads(4)
-                        .cors(DomainsValidation::AllowOnly(vec![
-                            AccessControlAllowOrigin::Any,
-                        ]))
-                        .start_http(&rpc_addr)
-                        .unwrap();
-                loop {
-                    if exit.load(Ordering::Relaxed) {
-                        server.close();
-                        break;
-                    }
-                }
-                ()
+#[derive(Clone)]
+pub struct Meta {
+    pub request_processor: JsonRpcRequestProcessor,
+    /// The address the request came from, if known
+    pub client_ip: Option<IpAddr>,
+    /// The API key the request was made with, if any
+    pub api_key: Option<String>,
+}
+impl Metadata for Meta {}
+
+build_rpc_trait! {
+    pub trait RpcSol {
+        type Metadata;
+
+        #[rpc(meta, name = "confirmTransaction")]
+        fn confirm_transaction(&self, Self::Metadata, String) -> Result<bool>;
+
+        #[rpc(meta, name = "getAccountData")]
+        fn get_account_data(&self, Self::Metadata, String, Trailing<RpcAccountEncoding>)
+            -> Result<Option<(String, RpcAccountEncoding)>>;
+
+        #[rpc(meta, name = "getAccountInfo")]
+        fn get_account_info(&self, Self::Metadata, String, Trailing<RpcAccountEncoding>)
+            -> Result<Option<RpcAccountInfo>>;
+
+        #[rpc(meta, name = "getAccountsHashes")]
+        fn get_accounts_hashes(&self, Self::Metadata) -> Result<RpcAccountsHashes>;
+
+        #[rpc(meta, name = "getBalance")]
+        fn get_balance(&self, Self::Metadata, String) -> Result<i64>;
+
+        #[rpc(meta, name = "getBlockProduction")]
+        fn get_block_production(&self, Self::Metadata) -> Result<RpcBlockProduction>;
+
+        #[rpc(meta, name = "getClusterNodes")]
+        fn get_cluster_nodes(&self, Self::Metadata) -> Result<Vec<RpcContactInfo>>;
+
+        #[rpc(meta, name = "getConfirmedBlock")]
+        fn get_confirmed_block(&self, Self::Metadata, u64) -> Result<Option<RpcConfirmedBlock>>;
+
+        #[rpc(meta, name = "getConfirmedTransaction")]
+        fn get_confirmed_transaction(&self, Self::Metadata, String)
+            -> Result<Option<RpcConfirmedTransaction>>;
+
+        #[rpc(meta, name = "getEconomicParameters")]
+        fn get_economic_parameters(&self, Self::Metadata) -> Result<RpcEconomicParameters>;
+
+        #[rpc(meta, name = "getEntries")]
+        fn get_entries(&self, Self::Metadata, u64, Trailing<RpcEntriesConfig>)
+            -> Result<RpcEntries>;
+
+        #[rpc(meta, name = "getFeeEstimate")]
+        fn get_fee_estimate(&self, Self::Metadata) -> Result<RpcFeeEstimate>;
+
+        #[rpc(meta, name = "getFinality")]
+        fn get_finality(&self, Self::Metadata) -> Result<usize>;
+
+        #[rpc(meta, name = "getGenesisHash")]
+        fn get_genesis_hash(&self, Self::Metadata) -> Result<String>;
+
+        #[rpc(meta, name = "getHealth")]
+        fn get_health(&self, Self::Metadata) -> Result<String>;
+
+        #[rpc(meta, name = "getLastId")]
+        fn get_last_id(&self, Self::Metadata) -> Result<String>;
+
+        #[rpc(meta, name = "getRecentLastIds")]
+        fn get_recent_last_ids(&self, Self::Metadata, Trailing<usize>) -> Result<RpcRecentLastIds>;
+
+        #[rpc(meta, name = "getSignatureStatuses")]
+        fn get_signature_statuses(&self, Self::Metadata, Vec<String>)
+            -> Result<Vec<RpcTransactionStatus>>;
+
+        #[rpc(meta, name = "getSignaturesForAddress")]
+        fn get_signatures_for_address(&self, Self::Metadata, String,
+            Trailing<RpcSignaturesForAddressConfig>) -> Result<Vec<RpcSignatureForAddress>>;
+
+        #[rpc(meta, name = "getSlot")]
+        fn get_slot(&self, Self::Metadata) -> Result<u64>;
+
+        #[rpc(meta, name = "getSlotLeader")]
+        fn get_slot_leader(&self, Self::Metadata) -> Result<Option<String>>;
+
+        #[rpc(meta, name = "getSlotLeaders")]
+        fn get_slot_leaders(&self, Self::Metadata, u64, u64) -> Result<Option<Vec<String>>>;
+
+        #[rpc(meta, name = "getTransactionCount")]
+        fn get_transaction_count(&self, Self::Metadata) -> Result<u64>;
+
+        #[rpc(meta, name = "getVersion")]
+        fn get_version(&self, Self::Metadata) -> Result<RpcVersionInfo>;
+
+        #[rpc(meta, name = "sendTransaction")]
+        fn send_transaction(&self, Self::Metadata, String, Trailing<RpcSendTransactionOptions>)
+            -> Result<RpcSendTransactionResult>;
+    }
+}
+
+#[derive(Clone)]
+pub struct AdminMeta {
+    pub lease: Arc<IdentityLease>,
+}
+impl Metadata for AdminMeta {}
+
+build_rpc_trait! {
+    pub trait RpcAdmin {
+        type Metadata;
+
+        #[rpc(meta, name = "getIdentityLease")]
+        fn get_identity_lease(&self, Self::Metadata) -> Result<(bool, u64)>;
+
+        #[rpc(meta, name = "releaseIdentity")]
+        fn release_identity(&self, Self::Metadata) -> Result<u64>;
+
+        #[rpc(meta, name = "acquireIdentity")]
+        fn acquire_identity(&self, Self::Metadata, u64) -> Result<u64>;
+
+        #[rpc(meta, name = "getStageCpuTime")]
+        fn get_stage_cpu_time(&self, Self::Metadata) -> Result<BTreeMap<String, u64>>;
+
+        #[rpc(meta, name = "startPacketCapture")]
+        fn start_packet_capture(&self, Self::Metadata, String, Trailing<Vec<String>>)
+            -> Result<bool>;
+
+        #[rpc(meta, name = "stopPacketCapture")]
+        fn stop_packet_capture(&self, Self::Metadata) -> Result<Option<u64>>;
+    }
+}
+
+fn lease_error(err: LeaseError) -> Error {
+    match err {
+        LeaseError::NotHeld => RpcCustomError::IdentityLeaseNotHeld,
+        LeaseError::StaleTerm(term) => RpcCustomError::StaleIdentityLeaseTerm { term },
+    }
+    .into()
+}
+
+pub struct RpcAdminImpl;
+impl RpcAdmin for RpcAdminImpl {
+    type Metadata = AdminMeta;
+
+    fn get_identity_lease(&self, meta: Self::Metadata) -> Result<(bool, u64)> {
+        Ok((meta.lease.is_active(), meta.lease.term()))
+    }
+    fn release_identity(&self, meta: Self::Metadata) -> Result<u64> {
+        meta.lease.release().map_err(lease_error)
+    }
+    fn acquire_identity(&self, meta: Self::Metadata, term: u64) -> Result<u64> {
+        meta.lease.acquire(term).map_err(lease_error)
+    }
+    fn get_stage_cpu_time(&self, _meta: Self::Metadata) -> Result<BTreeMap<String, u64>> {
+        Ok(stage_cpu_times()
+            .into_iter()
+            .map(|(stage, cpu_time)| (stage.to_string(), duration_as_ms(&cpu_time)))
+            .collect())
+    }
+    fn start_packet_capture(
+        &self,
+        _meta: Self::Metadata,
+        target: String,
+        peers: Trailing<Vec<String>>,
+    ) -> Result<bool> {
+        let peers = peers
+            .unwrap_or_default()
+            .into_iter()
+            .map(parse_peer_ip)
+            .collect::<Result<_>>()?;
+        let target = CaptureTarget::parse(&target);
+        info!("starting packet capture to {:?}", target);
+        packet_capture::start(&target, peers).map_err(|err| {
+            let reason = err.to_string();
+            RpcCustomError::PacketCaptureFailed { reason }
+        })?;
+        Ok(true)
+    }
+    fn stop_packet_capture(&self, _meta: Self::Metadata) -> Result<Option<u64>> {
+        Ok(packet_capture::stop())
+    }
+}
+
+/// The IP address of a peer to capture packets from, given with or without
+/// its port.
+fn parse_peer_ip(peer: String) -> Result<IpAddr> {
+    if let Ok(addr) = peer.parse::<SocketAddr>() {
+        return Ok(addr.ip());
+    }
+    let ip = peer.parse().map_err(|err: AddrParseError| {
+        let reason = err.to_string();
+        RpcCustomError::InvalidAddress { reason }
+    })?;
+    Ok(ip)
+}
+
+pub struct RpcSolImpl;
+impl RpcSol for RpcSolImpl {
+    type Metadata = Meta;
+
+    fn confirm_transaction(&self, meta: Self::Metadata, id: String) -> Result<bool> {
+        let processor = &meta.request_processor;
+        processor.trace("confirmTransaction", || {
+            let signature = parse_signature(id)?;
+            processor.get_signature_status(signature)
+        })
+    }
+    fn get_account_data(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+        encoding: Trailing<RpcAccountEncoding>,
+    ) -> Result<Option<(String, RpcAccountEncoding)>> {
+        let processor = &meta.request_processor;
+        processor.trace("getAccountData", || {
+            let pubkey = parse_pubkey(id)?;
+            processor.get_account_data(pubkey, encoding.unwrap_or_default())
+        })
+    }
+    fn get_account_info(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+        encoding: Trailing<RpcAccountEncoding>,
+    ) -> Result<Option<RpcAccountInfo>> {
+        let processor = &meta.request_processor;
+        processor.trace("getAccountInfo", || {
+            let pubkey = parse_pubkey(id)?;
+            processor.get_account_info(pubkey, encoding.unwrap_or_default())
+        })
+    }
+    fn get_accounts_hashes(&self, meta: Self::Metadata) -> Result<RpcAccountsHashes> {
+        let processor = &meta.request_processor;
+        processor.trace("getAccountsHashes", || processor.get_accounts_hashes())
+    }
+    fn get_balance(&self, meta: Self::Metadata, id: String) -> Result<i64> {
+        let processor = &meta.request_processor;
+        processor.trace("getBalance", || {
+            let pubkey = parse_pubkey(id)?;
+            processor.get_balance(pubkey)
+        })
+    }
+    fn get_block_production(&self, meta: Self::Metadata) -> Result<RpcBlockProduction> {
+        let processor = &meta.request_processor;
+        processor.trace("getBlockProduction", || processor.get_block_production())
+    }
+    fn get_cluster_nodes(&self, meta: Self::Metadata) -> Result<Vec<RpcContactInfo>> {
+        let processor = &meta.request_processor;
+        processor.trace("getClusterNodes", || processor.get_cluster_nodes())
+    }
+    fn get_confirmed_block(
+        &self,
+        meta: Self::Metadata,
+        slot: u64,
+    ) -> Result<Option<RpcConfirmedBlock>> {
+        let processor = &meta.request_processor;
+        processor.trace("getConfirmedBlock", || processor.get_confirmed_block(slot))
+    }
+    fn get_confirmed_transaction(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+    ) -> Result<Option<RpcConfirmedTransaction>> {
+        let processor = &meta.request_processor;
+        processor.trace("getConfirmedTransaction", || {
+            let signature = parse_signature(id)?;
+            processor.get_confirmed_transaction(signature)
+        })
+    }
+    fn get_economic_parameters(&self, meta: Self::Metadata) -> Result<RpcEconomicParameters> {
+        let processor = &meta.request_processor;
+        processor.trace("getEconomicParameters", || {
+            processor.get_economic_parameters()
+        })
+    }
+    fn get_entries(
+        &self,
+        meta: Self::Metadata,
+        start_height: u64,
+        config: Trailing<RpcEntriesConfig>,
+    ) -> Result<RpcEntries> {
+        let processor = &meta.request_processor;
+        processor.trace("getEntries", || {
+            let config = config.unwrap_or_default();
+            let max_count = config
+                .max_count
+                .unwrap_or(MAX_ENTRIES_PER_REQUEST)
+                .min(MAX_ENTRIES_PER_REQUEST);
+            processor.get_entries(start_height, max_count, config.encoding.unwrap_or_default())
+        })
+    }
+    fn get_fee_estimate(&self, meta: Self::Metadata) -> Result<RpcFeeEstimate> {
+        let processor = &meta.request_processor;
+        processor.trace("getFeeEstimate", || processor.get_fee_estimate())
+    }
+    fn get_finality(&self, meta: Self::Metadata) -> Result<usize> {
+        let processor = &meta.request_processor;
+        processor.trace("getFinality", || processor.get_finality())
+    }
+    fn get_genesis_hash(&self, meta: Self::Metadata) -> Result<String> {
+        let processor = &meta.request_processor;
+        processor.trace("getGenesisHash", || processor.get_genesis_hash())
+    }
+    fn get_health(&self, meta: Self::Metadata) -> Result<String> {
+        let processor = &meta.request_processor;
+        processor.trace("getHealth", || processor.get_health())
+    }
+    fn get_last_id(&self, meta: Self::Metadata) -> Result<String> {
+        let processor = &meta.request_processor;
+        processor.trace("getLastId", || processor.get_last_id())
+    }
+    fn get_recent_last_ids(
+        &self,
+        meta: Self::Metadata,
+        count: Trailing<usize>,
+    ) -> Result<RpcRecentLastIds> {
+        let processor = &meta.request_processor;
+        processor.trace("getRecentLastIds", || {
+            let count = count
+                .unwrap_or(MAX_RECENT_LAST_IDS)
+                .min(MAX_RECENT_LAST_IDS);
+            processor.get_recent_last_ids(count)
+        })
+    }
+    fn get_signature_statuses(
+        &self,
+        meta: Self::Metadata,
+        ids: Vec<String>,
+    ) -> Result<Vec<RpcTransactionStatus>> {
+        let processor = &meta.request_processor;
+        processor.trace("getSignatureStatuses", || {
+            if ids.len() > MAX_SIGNATURE_STATUSES {
+                return Err(Error {
+                    code: ErrorCode::InvalidParams,
+                    message: format!(
+                        "{} signatures, more than the {} allowed",
+                        ids.len(),
+                        MAX_SIGNATURE_STATUSES
+                    ),
+                    data: None,
+                });
+            }
+            let signatures = ids
+                .into_iter()
+                .map(parse_signature)
+                .collect::<Result<Vec<_>>>()?;
+            processor.get_signature_statuses(&signatures)
+        })
+    }
+    fn get_signatures_for_address(
+        &self,
+        meta: Self::Metadata,
+        id: String,
+        config: Trailing<RpcSignaturesForAddressConfig>,
+    ) -> Result<Vec<RpcSignatureForAddress>> {
+        let processor = &meta.request_processor;
+        processor.trace("getSignaturesForAddress", || {
+            let pubkey = parse_pubkey(id)?;
+            let config = config.unwrap_or_default();
+            let before = match config.before {
+                Some(before) => Some(parse_signature(before)?),
+                None => None,
+            };
+            let limit = config
+                .limit
+                .unwrap_or(MAX_SIGNATURES_FOR_ADDRESS)
+                .min(MAX_SIGNATURES_FOR_ADDRESS);
+            processor.get_signatures_for_address(pubkey, before, limit)
+        })
+    }
+    fn get_slot(&self, meta: Self::Metadata) -> Result<u64> {
+        let processor = &meta.request_processor;
+        processor.trace("getSlot", || processor.get_slot())
+    }
+    fn get_slot_leader(&self, meta: Self::Metadata) -> Result<Option<String>> {
+        let processor = &meta.request_processor;
+        processor.trace("getSlotLeader", || processor.get_slot_leader())
+    }
+    fn get_slot_leaders(
+        &self,
+        meta: Self::Metadata,
+        start_slot: u64,
+        limit: u64,
+    ) -> Result<Option<Vec<String>>> {
+        let processor = &meta.request_processor;
+        processor.trace("getSlotLeaders", || {
+            processor.get_slot_leaders(start_slot, limit.min(MAX_SLOT_LEADERS))
+        })
+    }
+    fn get_transaction_count(&self, meta: Self::Metadata) -> Result<u64> {
+        let processor = &meta.request_processor;
+        processor.trace("getTransactionCount", || processor.get_transaction_count())
+    }
+    fn get_version(&self, meta: Self::Metadata) -> Result<RpcVersionInfo> {
+        let processor = &meta.request_processor;
+        processor.trace("getVersion", || processor.get_version())
+    }
+    fn send_transaction(
+        &self,
+        meta: Self::Metadata,
+        data: String,
+        options: Trailing<RpcSendTransactionOptions>,
+    ) -> Result<RpcSendTransactionResult> {
+        let processor = &meta.request_processor;
+        processor.trace("sendTransaction", || {
+            let config = options.unwrap_or_default().into_config();
+            let data = decode_data(&data, config.encoding.unwrap_or_default())?;
+            if config.simulate.unwrap_or(false) {
+                processor
+                    .simulate_transaction(&data)
+                    .map(RpcSendTransactionResult::Simulated)
+            } else {
+                processor
+                    .send_transaction(&data)
+                    .map(RpcSendTransactionResult::Signature)
+            }
+        })
+    }
+}
+
+/// The time an expensive request has left. Long loops check it as they go and
+/// stop once it's up, so the request fails with a timeout error instead of
+/// running on.
+struct Deadline {
+    method: &'static str,
+    timeout: Duration,
+    expires: Instant,
+    expired: Cell<bool>,
+}
+
+impl Deadline {
+    fn new(method: &'static str, timeout: Duration) -> Self {
+        Deadline {
+            method,
+            timeout,
+            expires: Instant::now() + timeout,
+            expired: Cell::new(false),
+        }
+    }
+
+    /// Whether there's time left. Once there isn't, this stays false.
+    fn has_time(&self) -> bool {
+        if !self.expired.get() && Instant::now() >= self.expires {
+            self.expired.set(true);
+        }
+        !self.expired.get()
+    }
+
+    /// Fail with a timeout error if the time ran out, including at an earlier
+    /// check whose loop stopped early.
+    fn check(&self) -> Result<()> {
+        if self.has_time() {
+            return Ok(());
+        }
+        inc_new_counter_info!("rpc-request_timeout", 1);
+        warn!(
+            "{}: gave up after {}ms",
+            self.method,
+            duration_as_ms(&self.timeout)
+        );
+        Err(RpcCustomError::RequestTimeout {
+            method: self.method.to_string(),
+            timeout_ms: duration_as_ms(&self.timeout),
+        }
+        .into())
+    }
+}
+
+/// Call count and latency histogram of a single RPC method.
+struct RpcMethodStats {
+    calls: usize,
+    errors: usize,
+    total_us: u64,
+    /// One count per bound in `RPC_LATENCY_BUCKETS_US`, plus the overflow bucket.
+    buckets: Vec<usize>,
+}
+
+impl Default for RpcMethodStats {
+    fn default() -> Self {
+        RpcMethodStats {
+            calls: 0,
+            errors: 0,
+            total_us: 0,
+            buckets: vec![0; RPC_LATENCY_BUCKETS_US.len() + 1],
+        }
+    }
+}
+
+impl RpcMethodStats {
+    fn record(&mut self, elapsed_us: u64, is_err: bool) {
+        self.calls += 1;
+        if is_err {
+            self.errors += 1;
+        }
+        self.total_us += elapsed_us;
+        let bucket = RPC_LATENCY_BUCKETS_US
+            .iter()
+            .position(|bound| elapsed_us <= *bound)
+            .unwrap_or(RPC_LATENCY_BUCKETS_US.len());
+        self.buckets[bucket] += 1;
+    }
+
+    fn to_point(&self, method: &str) -> influxdb::Point {
+        let mut point = influxdb::Point::new("rpc-method_latency");
+        point
+            .add_tag("method", influxdb::Value::String(method.to_string()))
+            .add_field("calls", influxdb::Value::Integer(self.calls as i64))
+            .add_field("errors", influxdb::Value::Integer(self.errors as i64))
+            .add_field(
+                "mean_us",
+                influxdb::Value::Integer((self.total_us / self.calls.max(1) as u64) as i64),
+            );
+        for (i, count) in self.buckets.iter().enumerate() {
+            let field = match RPC_LATENCY_BUCKETS_US.get(i) {
+                Some(bound) => format!("le_{}us", bound),
+                None => "overflow".to_string(),
+            };
+            point.add_field(&field, influxdb::Value::Integer(*count as i64));
+        }
+        point
+    }
+}
+
+/// The state RPC requests are served from. A live node serves them from its
+/// bank, but anything that can answer these can stand in for it.
+pub trait RpcBackend: Send + Sync {
+    fn get_account(&self, pubkey: &Pubkey) -> Option<Account>;
+    fn get_balance(&self, pubkey: &Pubkey) -> i64;
+    fn finality(&self) -> usize;
+    /// The id of the first entry of the ledger, which names the cluster.
+    fn genesis_hash(&self) -> Hash;
+    fn last_id(&self) -> Hash;
+    fn recent_last_ids(&self, count: usize) -> Vec<(Hash, u64)>;
+    fn get_signature_status(&self, signature: &Signature) -> Option<bank::Result<()>>;
+    fn get_signature_statuses(&self, signatures: &[Signature]) -> Vec<Option<bank::Result<()>>>;
+    fn transaction_count(&self) -> usize;
+    fn tick_height(&self) -> u64;
+    fn recent_fees(&self) -> RecentFees;
+    /// The totals of the slots this node recently wrote as leader, oldest first.
+    fn recent_slot_costs(&self) -> Vec<SlotCost>;
+    fn accounts_snapshot(&self) -> (Hash, Vec<(Pubkey, Account)>);
+    fn simulate_transaction(&self, tx: &Transaction) -> (bank::Result<()>, Vec<(Pubkey, i64)>);
+}
+
+impl RpcBackend for Bank {
+    fn get_account(&self, pubkey: &Pubkey) -> Option<Account> {
+        Bank::get_account(self, pubkey)
+    }
+    fn get_balance(&self, pubkey: &Pubkey) -> i64 {
+        Bank::get_balance(self, pubkey)
+    }
+    fn finality(&self) -> usize {
+        Bank::finality(self)
+    }
+    fn genesis_hash(&self) -> Hash {
+        self.poh_domain().genesis_id
+    }
+    fn last_id(&self) -> Hash {
+        Bank::last_id(self)
+    }
+    fn recent_last_ids(&self, count: usize) -> Vec<(Hash, u64)> {
+        Bank::recent_last_ids(self, count)
+    }
+    fn get_signature_status(&self, signature: &Signature) -> Option<bank::Result<()>> {
+        Bank::get_signature_status(self, signature)
+    }
+    fn get_signature_statuses(&self, signatures: &[Signature]) -> Vec<Option<bank::Result<()>>> {
+        Bank::get_signature_statuses(self, signatures)
+    }
+    fn transaction_count(&self) -> usize {
+        Bank::transaction_count(self)
+    }
+    fn tick_height(&self) -> u64 {
+        Bank::tick_height(self)
+    }
+    fn recent_fees(&self) -> RecentFees {
+        Bank::recent_fees(self)
+    }
+    fn recent_slot_costs(&self) -> Vec<SlotCost> {
+        Bank::recent_slot_costs(self)
+    }
+    fn accounts_snapshot(&self) -> (Hash, Vec<(Pubkey, Account)>) {
+        Bank::accounts_snapshot(self)
+    }
+    fn simulate_transaction(&self, tx: &Transaction) -> (bank::Result<()>, Vec<(Pubkey, i64)>) {
+        Bank::simulate_transaction(self, tx)
+    }
+}
+
+#[derive(Clone)]
+pub struct JsonRpcRequestProcessor {
+    backend: Arc<RpcBackend>,
+    ledger_path: Option<String>,
+    /// Where the ledger's slots start, shared by the requests
+    slot_index: Option<Arc<Mutex<SlotIndex>>>,
+    /// The gossip table, if this node gossips
+    crdt: Option<Arc<RwLock<Crdt>>>,
+    /// Where sent transactions go, if this node accepts them
+    transactions_addr: Option<SocketAddr>,
+    /// How long expensive requests may run
+    request_timeout: Duration,
+    /// How far behind the leader `getHealth` tolerates
+    health_max_entries_behind: u64,
+    request_count: Arc<AtomicUsize>,
+    method_stats: Arc<Mutex<HashMap<&'static str, RpcMethodStats>>>,
+}
+impl JsonRpcRequestProcessor {
+    /// Create a new request processor that wraps the given backend.
+    pub fn new(backend: Arc<RpcBackend>) -> Self {
+        JsonRpcRequestProcessor {
+            backend,
+            ledger_path: None,
+            slot_index: None,
+            crdt: None,
+            transactions_addr: None,
+            request_timeout: Duration::from_millis(RPC_REQUEST_TIMEOUT_MS),
+            health_max_entries_behind: JsonRpcConfig::default().health_max_entries_behind,
+            request_count: Arc::new(AtomicUsize::new(0)),
+            method_stats: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Same as `new`, but also serves blocks from the ledger at `ledger_path`.
+    pub fn new_with_ledger(backend: Arc<RpcBackend>, ledger_path: &str) -> Self {
+        JsonRpcRequestProcessor {
+            ledger_path: Some(ledger_path.to_string()),
+            slot_index: Some(Arc::new(Mutex::new(SlotIndex::new(ledger_path)))),
+            ..Self::new(backend)
+        }
+    }
+
+    /// Run the handler of an RPC `method`, recording its latency in the method's
+    /// histogram. Each request is assigned a sequential id that is included in
+    /// the debug log, so slow or failing calls can be followed through the logs.
+    fn trace<T, F>(&self, method: &'static str, handler: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let request_id = self.request_count.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let result = handler();
+        let elapsed_us = duration_as_us(&now.elapsed());
+        debug!(
+            "rpc request #{} {}: {} us, ok: {}",
+            request_id,
+            method,
+            elapsed_us,
+            result.is_ok()
+        );
+
+        let mut method_stats = self.method_stats.lock().unwrap();
+        let stats = method_stats
+            .entry(method)
+            .or_insert_with(RpcMethodStats::default);
+        stats.record(elapsed_us, result.is_err());
+        if stats.calls % RPC_STATS_SUBMIT_RATE == 0 {
+            metrics::submit(stats.to_point(method));
+        }
+        result
+    }
+
+    /// Process JSON-RPC request items sent via JSON-RPC.
+    fn get_account(&self, pubkey: Pubkey) -> Option<Account> {
+        self.backend.get_account(&pubkey)
+    }
+    fn get_account_data(
+        &self,
+        pubkey: Pubkey,
+        encoding: RpcAccountEncoding,
+    ) -> Result<Option<(String, RpcAccountEncoding)>> {
+        match self.get_account(pubkey) {
+            Some(account) => {
+                let data = encode_account_data(&account.userdata, encoding)?;
+                Ok(Some((data, encoding)))
+            }
+            None => Ok(None),
+        }
+    }
+    fn get_account_info(
+        &self,
+        pubkey: Pubkey,
+        encoding: RpcAccountEncoding,
+    ) -> Result<Option<RpcAccountInfo>> {
+        match self.get_account(pubkey) {
+            Some(account) => Ok(Some(RpcAccountInfo {
+                tokens: account.tokens,
+                owner: account.owner.to_string(),
+                userdata: encode_account_data(&account.userdata, encoding)?,
+                encoding,
+            })),
+            None => Ok(None),
+        }
+    }
+    fn deadline(&self, method: &'static str) -> Deadline {
+        Deadline::new(method, self.request_timeout)
+    }
+    fn get_accounts_hashes(&self) -> Result<RpcAccountsHashes> {
+        let deadline = self.deadline("getAccountsHashes");
+        let (last_id, accounts) = self.backend.accounts_snapshot();
+        let mut all = Hash::default();
+        let accounts = accounts
+            .into_iter()
+            .take_while(|_| deadline.has_time())
+            .map(|(pubkey, account)| {
+                let account_hash = hash(&serialize(&account).unwrap());
+                all = extend_and_hash(&all, &[pubkey.as_ref(), account_hash.as_ref()].concat());
+                RpcAccountHash {
+                    pubkey: pubkey.to_string(),
+                    tokens: account.tokens,
+                    hash: account_hash.to_string(),
+                }
+            })
+            .collect();
+        deadline.check()?;
+        Ok(RpcAccountsHashes {
+            last_id: last_id.to_string(),
+            hash: all.to_string(),
+            accounts,
+        })
+    }
+    fn get_balance(&self, pubkey: Pubkey) -> Result<i64> {
+        let val = self.backend.get_balance(&pubkey);
+        Ok(val)
+    }
+    /// The entries of this node's ledger, up to the last one fully written, or
+    /// up to where `deadline` ran out.
+    fn ledger_entries<'a>(
+        &self,
+        deadline: &'a Deadline,
+    ) -> Result<impl Iterator<Item = Entry> + 'a> {
+        let ledger_path = self.ledger_path.as_ref().ok_or(RpcCustomError::NoLedger)?;
+        let entries = read_ledger(ledger_path, false).map_err(|err| {
+            warn!("{}: reading {}: {:?}", deadline.method, ledger_path, err);
+            Error::internal_error()
+        })?;
+        // the writer may be partway through the last entry
+        Ok(entries
+            .take_while(move |e| e.is_ok() && deadline.has_time())
+            .map(|e| e.unwrap()))
+    }
+    /// The nodes in this node's gossip table, itself included, sorted by pubkey.
+    /// Entry points that haven't been heard from yet have no pubkey to list.
+    fn get_cluster_nodes(&self) -> Result<Vec<RpcContactInfo>> {
+        let crdt = self.crdt.as_ref().ok_or(RpcCustomError::NoGossip)?;
+        let crdt = crdt.read().unwrap();
+        let mut nodes: Vec<_> = crdt
+            .table
+            .values()
+            .filter(|node| node.id != Pubkey::default())
+            .collect();
+        nodes.sort_by_key(|node| node.id);
+        Ok(nodes
+            .into_iter()
+            .map(|node| RpcContactInfo {
+                pubkey: node.id.to_string(),
+                gossip: node.contact_info.ncp.to_string(),
+                tpu: node.contact_info.tpu.to_string(),
+                tvu: node.contact_info.tvu.to_string(),
+            })
+            .collect())
+    }
+    fn get_confirmed_block(&self, slot: u64) -> Result<Option<RpcConfirmedBlock>> {
+        let deadline = self.deadline("getConfirmedBlock");
+        let slot_index = self.slot_index.as_ref().ok_or(RpcCustomError::NoLedger)?;
+        let mut slot_index = slot_index.lock().unwrap();
+        let block = slot_index
+            .update(|| deadline.has_time())
+            .and_then(|_| slot_index.slot_entries(slot))
+            .map_err(|err| {
+                warn!("getConfirmedBlock: reading slot {}: {:?}", slot, err);
+                Error::internal_error()
+            })?;
+        if block.is_none() {
+            // it may be further along than the index got
+            deadline.check()?;
+        }
+        Ok(block.map(|block| RpcConfirmedBlock {
+            slot: block.slot,
+            parent_slot: block.parent_slot,
+            blockhash: bs58::encode(block.blockhash()).into_string(),
+            previous_blockhash: bs58::encode(block.previous_blockhash).into_string(),
+            transactions: block
+                .entries
+                .into_iter()
+                .flat_map(|entry| entry.transactions)
+                .collect(),
+        }))
+    }
+    fn get_confirmed_transaction(
+        &self,
+        signature: Signature,
+    ) -> Result<Option<RpcConfirmedTransaction>> {
+        let deadline = self.deadline("getConfirmedTransaction");
+        let found = find_transaction(self.ledger_entries(&deadline)?, &signature);
+        if found.is_none() {
+            // it may be further along than the scan got
+            deadline.check()?;
+        }
+        Ok(found.map(|(slot, transaction)| RpcConfirmedTransaction {
+            slot,
+            memo: transaction.memo.clone(),
+            transaction,
+        }))
+    }
+    fn get_economic_parameters(&self) -> Result<RpcEconomicParameters> {
+        Ok(RpcEconomicParameters {
+            inflation_percent: INFLATION_PERCENT,
+            fee_burn_percent: FEE_BURN_PERCENT,
+            rent_epoch_entry_ids: rent::RENT_EPOCH_ENTRY_IDS,
+            rent_bytes_per_token: rent::RENT_BYTES_PER_TOKEN,
+            rent_exempt_epochs: rent::RENT_EXEMPT_EPOCHS,
+        })
+    }
+    /// Up to `max_count` entries of this node's ledger from `start_height`, and
+    /// no more than `MAX_ENTRIES_RESPONSE_BYTES` of them. A page stops short at
+    /// the end of the ledger, or when the deadline runs out.
+    fn get_entries(
+        &self,
+        start_height: u64,
+        max_count: usize,
+        encoding: RpcEntryEncoding,
+    ) -> Result<RpcEntries> {
+        let deadline = self.deadline("getEntries");
+        let ledger_path = self.ledger_path.as_ref().ok_or(RpcCustomError::NoLedger)?;
+        let mut window = LedgerWindow::open(ledger_path).map_err(|err| {
+            warn!("getEntries: opening {}: {:?}", ledger_path, err);
+            Error::internal_error()
+        })?;
+
+        let mut entries = vec![];
+        let mut bytes = 0;
+        let mut height = start_height;
+        while entries.len() < max_count && deadline.has_time() {
+            // the writer may be partway through the last entry
+            let entry = match window.get_entry(height) {
+                Ok(entry) => entry,
+                Err(_) => break,
+            };
+            let (entry, len) = match encoding {
+                RpcEntryEncoding::Json => {
+                    let len = serde_json::to_vec(&entry).unwrap().len();
+                    (RpcEntry::Json(entry), len)
+                }
+                RpcEntryEncoding::Base64 => {
+                    let data = base64::encode(&entry.to_bytes());
+                    let len = data.len();
+                    (RpcEntry::Base64(data), len)
+                }
+            };
+            if !entries.is_empty() && bytes + len > MAX_ENTRIES_RESPONSE_BYTES {
+                break;
+            }
+            bytes += len;
+            entries.push(entry);
+            height += 1;
+        }
+        Ok(RpcEntries {
+            start_height,
+            next_height: height,
+            entries,
+        })
+    }
+    fn get_block_production(&self) -> Result<RpcBlockProduction> {
+        let slots: Vec<_> = self
+            .backend
+            .recent_slot_costs()
+            .into_iter()
+            .map(|cost| RpcSlotCost {
+                slot: cost.slot,
+                entries: cost.entries,
+                transactions: cost.transactions,
+                signatures: cost.signatures,
+                compute_units: cost.compute_units,
+                bytes: cost.bytes,
+            })
+            .collect();
+        let max_compute_units = slots
+            .iter()
+            .map(|slot| slot.compute_units)
+            .max()
+            .unwrap_or(0);
+        Ok(RpcBlockProduction {
+            slots,
+            max_compute_units,
+        })
+    }
+    fn get_fee_estimate(&self) -> Result<RpcFeeEstimate> {
+        let recent_fees = self.backend.recent_fees();
+        let percentile = |percentile| recent_fees.percentile(percentile).unwrap_or(0);
+        Ok(RpcFeeEstimate {
+            samples: recent_fees.fees.len(),
+            min_fee: percentile(0),
+            median_fee: percentile(50),
+            high_fee: percentile(90),
+            max_fee: percentile(100),
+            queue_depth: recent_fees.queue_depth,
+            recommended_fee: recommended_fee(&recent_fees),
+        })
+    }
+    fn get_finality(&self) -> Result<usize> {
+        Ok(self.backend.finality())
+    }
+    fn get_genesis_hash(&self) -> Result<String> {
+        Ok(self.backend.genesis_hash().to_string())
+    }
+    /// "ok" when this node is the leader or within `health_max_entries_behind`
+    /// of the entry height the leader last gossiped.
+    fn get_health(&self) -> Result<String> {
+        let crdt = self.crdt.as_ref().ok_or(RpcCustomError::NoGossip)?;
+        let crdt = crdt.read().unwrap();
+        let me = crdt.my_data();
+        if me.leader_id == me.id {
+            return Ok("ok".to_string());
+        }
+        let leader_height = crdt
+            .leader_data()
+            .map(|leader| leader.ledger_state.entry_height)
+            .ok_or(RpcCustomError::NodeUnhealthy {
+                entries_behind: None,
+            })?;
+        let entries_behind = leader_height.saturating_sub(me.ledger_state.entry_height);
+        if entries_behind > self.health_max_entries_behind {
+            inc_new_counter_info!("rpc-node_unhealthy", 1);
+            return Err(RpcCustomError::NodeUnhealthy {
+                entries_behind: Some(entries_behind),
+            }
+            .into());
+        }
+        Ok("ok".to_string())
+    }
+    fn get_last_id(&self) -> Result<String> {
+        let id = self.backend.last_id();
+        Ok(bs58::encode(id).into_string())
+    }
+    fn get_recent_last_ids(&self, count: usize) -> Result<RpcRecentLastIds> {
+        let now = timestamp();
+        let last_ids = self
+            .backend
+            .recent_last_ids(count)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (id, registered))| RpcRecentLastId {
+                last_id: bs58::encode(id).into_string(),
+                age_ms: now.saturating_sub(registered),
+                remaining_ids: MAX_ENTRY_IDS - 1 - i,
+            })
+            .collect();
+        Ok(RpcRecentLastIds {
+            last_ids,
+            recommended_fee: recommended_fee(&self.backend.recent_fees()),
+        })
+    }
+    fn get_signature_status(&self, signature: Signature) -> Result<bool> {
+        Ok(self.backend.get_signature_status(&signature) == Some(Ok(())))
+    }
+    fn get_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> Result<Vec<RpcTransactionStatus>> {
+        Ok(self
+            .backend
+            .get_signature_statuses(signatures)
+            .into_iter()
+            .map(|status| match status {
+                Some(Ok(())) => RpcTransactionStatus::Confirmed,
+                Some(Err(err)) => RpcTransactionStatus::Failed(format!("{:?}", err)),
+                None => RpcTransactionStatus::NotFound,
+            })
+            .collect())
+    }
+    /// The transactions involving `pubkey`, newest first, starting after the
+    /// one with signature `before`, if given.
+    fn get_signatures_for_address(
+        &self,
+        pubkey: Pubkey,
+        before: Option<Signature>,
+        limit: usize,
+    ) -> Result<Vec<RpcSignatureForAddress>> {
+        let deadline = self.deadline("getSignaturesForAddress");
+        let mut found = find_transactions_for_address(self.ledger_entries(&deadline)?, &pubkey);
+        deadline.check()?;
+        found.reverse();
+        let skip = match before {
+            Some(before) => found
+                .iter()
+                .position(|(_, tx)| tx.signature == before)
+                .map(|pos| pos + 1)
+                .unwrap_or(found.len()),
+            None => 0,
+        };
+        Ok(found
+            .into_iter()
+            .skip(skip)
+            .take(limit)
+            .map(|(slot, tx)| RpcSignatureForAddress {
+                signature: bs58::encode(tx.signature).into_string(),
+                slot,
+                memo: tx.memo,
+            })
+            .collect())
+    }
+    /// The slot the bank's latest tick is in.
+    fn get_slot(&self) -> Result<u64> {
+        Ok(self.backend.tick_height() / TICKS_PER_SLOT)
+    }
+    /// The leader of the current slot, or `None` if gossip hasn't named one.
+    fn get_slot_leader(&self) -> Result<Option<String>> {
+        let crdt = self.crdt.as_ref().ok_or(RpcCustomError::NoGossip)?;
+        let crdt = crdt.read().unwrap();
+        Ok(crdt.leader_data().map(|leader| leader.id.to_string()))
+    }
+    /// The leaders of `limit` slots from `start_slot`. Leaders don't rotate:
+    /// one keeps every slot until another takes over from it, so the coming
+    /// slots all go to the current leader. Nothing records who led past slots.
+    fn get_slot_leaders(&self, start_slot: u64, limit: u64) -> Result<Option<Vec<String>>> {
+        let slot = self.get_slot()?;
+        if start_slot < slot {
+            return Err(Error {
+                code: ErrorCode::InvalidParams,
+                message: format!(
+                    "slot {} has passed, and only the leaders of slot {} on are known",
+                    start_slot, slot
+                ),
+                data: None,
+            });
+        }
+        let leader = self.get_slot_leader()?;
+        Ok(leader.map(|leader| vec![leader; limit as usize]))
+    }
+    fn get_transaction_count(&self) -> Result<u64> {
+        Ok(self.backend.transaction_count() as u64)
+    }
+    fn get_version(&self) -> Result<RpcVersionInfo> {
+        let features = [
+            ("cuda", cfg!(feature = "cuda")),
+            ("erasure", cfg!(feature = "erasure")),
+            ("ipv6", cfg!(feature = "ipv6")),
+            ("unstable", cfg!(feature = "unstable")),
+        ];
+        Ok(RpcVersionInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            features: features
+                .iter()
+                .filter(|&&(_, enabled)| enabled)
+                .map(|&(name, _)| name.to_string())
+                .collect(),
+        })
+    }
+    /// Check that `data` is a signed transaction and hand it to the TPU,
+    /// returning its signature. Whether it lands is up to the leader, so
+    /// clients should follow up with `confirmTransaction`.
+    fn send_transaction(&self, data: &[u8]) -> Result<String> {
+        let transactions_addr = self
+            .transactions_addr
+            .ok_or(RpcCustomError::TransactionsNotAccepted)?;
+        let tx = parse_transaction(data)?;
+
+        let sock = UdpSocket::bind("0.0.0.0:0").map_err(|err| {
+            warn!("sendTransaction: binding a socket: {:?}", err);
+            Error::internal_error()
+        })?;
+        sock.send_to(data, transactions_addr).map_err(|err| {
+            warn!(
+                "sendTransaction: sending to {}: {:?}",
+                transactions_addr, err
+            );
+            Error::internal_error()
+        })?;
+        Ok(bs58::encode(tx.signature).into_string())
+    }
+    /// Run the signed transaction in `data` against the bank without committing
+    /// or sending it, so clients can catch a failure before paying for it.
+    fn simulate_transaction(&self, data: &[u8]) -> Result<RpcSimulatedTransaction> {
+        let tx = parse_transaction(data)?;
+        let (result, balances) = self.backend.simulate_transaction(&tx);
+        Ok(RpcSimulatedTransaction {
+            signature: bs58::encode(tx.signature).into_string(),
+            err: result.err().map(|err| format!("{:?}", err)),
+            balances: balances
+                .into_iter()
+                .map(|(pubkey, tokens)| (pubkey.to_string(), tokens))
+                .collect(),
+        })
+    }
+}
+
+/// Check that `data` is a signed transaction that fits in a packet.
+fn parse_transaction(data: &[u8]) -> Result<Transaction> {
+    let invalid = |message: String| Error {
+        code: ErrorCode::InvalidParams,
+        message,
+        data: None,
+    };
+    if data.len() > PACKET_DATA_SIZE {
+        return Err(invalid(format!(
+            "transaction is {} bytes, more than the {} that fit in a packet",
+            data.len(),
+            PACKET_DATA_SIZE
+        )));
+    }
+    let tx: Transaction =
+        deserialize(data).map_err(|_| invalid("data isn't a transaction".to_string()))?;
+    if !tx.verify_signature() {
+        return Err(invalid("transaction signature is invalid".to_string()));
+    }
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bank::{Bank, BankError};
+    use cpu_time;
+    use crdt::NodeInfo;
+    use entry::next_entry;
+    use jsonrpc_core::Response;
+    use ledger::FileLedgerWriter;
+    use mint::Mint;
+    use result::Error as ServiceError;
+    use signature::{Keypair, KeypairUtil};
+    use std::env;
+    use std::fs::remove_dir_all;
+    use std::net::{IpAddr, Ipv4Addr, TcpListener};
+    use std::sync::Arc;
+    use transaction::Transaction;
+
+    #[test]
+    fn test_rpc_request() {
+        let alice = Mint::new(10_000);
+        let bob_pubkey = Keypair::new().pubkey();
+        let bank = Bank::new(&alice);
+
+        let last_id = bank.last_id();
+        let tx = Transaction::new(&alice.keypair(), bob_pubkey, 20, last_id);
+        bank.process_transaction(&tx).expect("process transaction");
+
+        let request_processor = JsonRpcRequestProcessor::new(Arc::new(bank));
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let meta = Meta {
+            request_processor,
+            client_ip: None,
+            api_key: None,
+        };
+
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"getBalance","params":["{}"]}}"#,
+            bob_pubkey
+        );
+        let res = io.handle_request_sync(&req, meta.clone());
+        let expected = format!(r#"{{"jsonrpc":"2.0","result":20,"id":1}}"#);
+        let expected: Response =
+            serde_json::from_str(&expected).expect("expected response deserialization");
+
+        let result: Response = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(expected, result);
+
+        let req = format!(r#"{{"jsonrpc":"2.0","id":1,"method":"getTransactionCount"}}"#);
+        let res = io.handle_request_sync(&req, meta.clone());
+        let expected = format!(r#"{{"jsonrpc":"2.0","result":1,"id":1}}"#);
+        let expected: Response =
+            serde_json::from_str(&expected).expect("expected response deserialization");
+
+        let result: Response = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(expected, result);
+    }
+    #[test]
+    fn test_rpc_get_account_data() {
+        let alice = Mint::new(10_000);
+        let bank = Bank::new(&alice);
+        let keypair = Keypair::new();
+        let mut tx = Transaction::new(&alice.keypair(), keypair.pubkey(), 1, bank.last_id());
+        bank.process_transaction(&tx).unwrap();
+        tx = Transaction::new(&keypair, keypair.pubkey(), 0, bank.last_id());
+        tx.userdata = vec![7; MAX_BASE58_BYTES + 1];
+        bank.process_transaction(&tx).unwrap();
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new(Arc::new(bank)),
+            client_ip: None,
+            api_key: None,
+        };
+        let get_account_data = |params: String| {
+            let req = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"getAccountData","params":[{}]}}"#,
+                params
+            );
+            let res = io.handle_request_sync(&req, meta.clone()).unwrap();
+            serde_json::from_str::<serde_json::Value>(&res).unwrap()
+        };
+
+        let res = get_account_data(format!(r#""{}","base64""#, keypair.pubkey()));
+        assert_eq!(res["result"][1], "base64");
+        let data = base64::decode(res["result"][0].as_str().unwrap()).unwrap();
+        assert_eq!(data, vec![7; MAX_BASE58_BYTES + 1]);
+
+        let res = get_account_data(format!(r#""{}","base64+zstd""#, keypair.pubkey()));
+        assert_eq!(res["result"][1], "base64+zstd");
+        let data = base64::decode(res["result"][0].as_str().unwrap()).unwrap();
+        let data = zstd::stream::decode_all(&data[..]).unwrap();
+        assert_eq!(data, vec![7; MAX_BASE58_BYTES + 1]);
+
+        // base58 is the default, and too slow for this much data
+        let res = get_account_data(format!(r#""{}""#, keypair.pubkey()));
+        assert_eq!(res["error"]["code"], -32602);
+
+        let res = get_account_data(format!(r#""{}""#, alice.pubkey()));
+        assert_eq!(res["result"][0], "");
+        assert_eq!(res["result"][1], "base58");
+
+        let res = get_account_data(format!(r#""{}""#, Keypair::new().pubkey()));
+        assert!(res["result"].is_null());
+    }
+    #[test]
+    fn test_rpc_get_cluster_nodes() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let peer = NodeInfo::new_leader(&"127.0.0.2:1234".parse().unwrap());
+        // an entry point isn't listed until it's heard from
+        let entry_point = NodeInfo::new_entry_point("127.0.0.3:1234".parse().unwrap());
+        let mut crdt = Crdt::new(me.clone()).unwrap();
+        crdt.insert(&peer);
+        crdt.insert(&entry_point);
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let mut request_processor = JsonRpcRequestProcessor::new(bank);
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"getClusterNodes"}"#;
+        let get_cluster_nodes = |request_processor: &JsonRpcRequestProcessor| {
+            let meta = Meta {
+                request_processor: request_processor.clone(),
+                client_ip: None,
+                api_key: None,
+            };
+            let res = io.handle_request_sync(req, meta).unwrap();
+            serde_json::from_str::<serde_json::Value>(&res).unwrap()
+        };
+
+        let res = get_cluster_nodes(&request_processor);
+        assert_eq!(res["error"]["code"], -32006);
+
+        request_processor.crdt = Some(Arc::new(RwLock::new(crdt)));
+        let res = get_cluster_nodes(&request_processor);
+        let nodes: Vec<RpcContactInfo> = serde_json::from_value(res["result"].clone()).unwrap();
+        let expected: Vec<_> = [me, peer]
+            .iter()
+            .map(|node| RpcContactInfo {
+                pubkey: node.id.to_string(),
+                gossip: node.contact_info.ncp.to_string(),
+                tpu: node.contact_info.tpu.to_string(),
+                tvu: node.contact_info.tvu.to_string(),
+            })
+            .collect();
+        assert_eq!(nodes.len(), 2);
+        for node in &expected {
+            assert!(nodes.contains(node));
+        }
+    }
+
+    #[test]
+    fn test_rpc_get_health() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let mut leader = NodeInfo::new_leader(&"127.0.0.2:1234".parse().unwrap());
+        leader.ledger_state.entry_height = 2000;
+        let crdt = Arc::new(RwLock::new(Crdt::new(me.clone()).unwrap()));
+        crdt.write().unwrap().insert(&leader);
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let mut request_processor = JsonRpcRequestProcessor::new(bank);
+        request_processor.crdt = Some(crdt.clone());
+        let meta = Meta {
+            request_processor,
+            client_ip: None,
+            api_key: None,
+        };
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"getHealth"}"#;
+        let get_health = || {
+            let res = io.handle_request_sync(req, meta.clone()).unwrap();
+            serde_json::from_str::<serde_json::Value>(&res).unwrap()
+        };
+
+        // without a leader there's no height to compare to
+        let res = get_health();
+        assert_eq!(res["error"]["code"], -32009);
+        assert_eq!(
+            res["error"]["data"]["entriesBehind"],
+            serde_json::Value::Null
+        );
+
+        crdt.write().unwrap().set_leader(leader.id);
+        let res = get_health();
+        assert_eq!(res["error"]["code"], -32009);
+        assert_eq!(res["error"]["data"]["entriesBehind"], 2000);
+
+        crdt.write().unwrap().set_entry_height(1000);
+        let res = get_health();
+        assert_eq!(res["result"], "ok");
+
+        // the leader is always healthy
+        crdt.write().unwrap().set_leader(me.id);
+        crdt.write().unwrap().set_entry_height(0);
+        let res = get_health();
+        assert_eq!(res["result"], "ok");
+    }
+
+    #[test]
+    fn test_rpc_get_slot_leaders() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let me = NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap());
+        let leader = NodeInfo::new_leader(&"127.0.0.2:1234".parse().unwrap());
+        let crdt = Arc::new(RwLock::new(Crdt::new(me).unwrap()));
+        crdt.write().unwrap().insert(&leader);
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let mut request_processor = JsonRpcRequestProcessor::new(bank.clone());
+        request_processor.crdt = Some(crdt.clone());
+        let meta = Meta {
+            request_processor,
+            client_ip: None,
+            api_key: None,
+        };
+        let request = |method: &str, params: &str| {
+            let req = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":[{}]}}"#,
+                method, params
+            );
+            let res = io.handle_request_sync(&req, meta.clone()).unwrap();
+            serde_json::from_str::<serde_json::Value>(&res).unwrap()
+        };
+
+        // gossip hasn't named a leader yet
+        assert_eq!(request("getSlotLeader", "")["result"], json!(null));
+        assert_eq!(request("getSlotLeaders", "0, 2")["result"], json!(null));
+
+        crdt.write().unwrap().set_leader(leader.id);
+        let id = leader.id.to_string();
+        assert_eq!(request("getSlotLeader", "")["result"], json!(id));
+        assert_eq!(request("getSlotLeaders", "0, 2")["result"], json!([id, id]));
+        let res = request("getSlotLeaders", &format!("0, {}", MAX_SLOT_LEADERS + 1));
+        assert_eq!(
+            res["result"].as_array().unwrap().len(),
+            MAX_SLOT_LEADERS as usize
+        );
+
+        // past slots' leaders aren't known
+        for _ in 0..TICKS_PER_SLOT {
+            bank.process_entries(vec![next_entry(&bank.last_id(), 1, vec![])])
+                .unwrap();
+        }
+        assert_eq!(request("getSlot", "")["result"], 1);
+        assert_eq!(request("getSlotLeaders", "0, 2")["error"]["code"], -32602);
+        assert_eq!(request("getSlotLeaders", "1, 1")["result"], json!([id]));
+
+        // nor is any leader to a node that doesn't gossip
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new(bank),
+            client_ip: None,
+            api_key: None,
+        };
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"getSlotLeader"}"#;
+        let res = io.handle_request_sync(req, meta).unwrap();
+        let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+        assert_eq!(res["error"]["code"], -32006);
+    }
+
+    #[test]
+    fn test_rpc_get_version() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new(bank),
+            client_ip: None,
+            api_key: None,
+        };
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"getVersion"}"#;
+        let res = io.handle_request_sync(req, meta).unwrap();
+        let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+        let version: RpcVersionInfo = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            version.features.contains(&"erasure".to_string()),
+            cfg!(feature = "erasure")
+        );
+    }
+    #[test]
+    fn test_rpc_request_tracing() {
+        let alice = Mint::new(10_000);
+        let bank = Bank::new(&alice);
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let request_processor = JsonRpcRequestProcessor::new(Arc::new(bank));
+        let meta = Meta {
+            request_processor: request_processor.clone(),
+            client_ip: None,
+            api_key: None,
+        };
+
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"getTransactionCount"}"#;
+        io.handle_request_sync(req, meta.clone());
+        io.handle_request_sync(req, meta.clone());
+        let req =
+            r#"{"jsonrpc":"2.0","id":1,"method":"confirmTransaction","params":["a1b2c3d4e5"]}"#;
+        io.handle_request_sync(req, meta);
+
+        assert_eq!(request_processor.request_count.load(Ordering::Relaxed), 3);
+        let method_stats = request_processor.method_stats.lock().unwrap();
+        let stats = &method_stats["getTransactionCount"];
+        assert_eq!(stats.calls, 2);
+        assert_eq!(stats.errors, 0);
+        assert_eq!(stats.buckets.iter().sum::<usize>(), 2);
+        let stats = &method_stats["confirmTransaction"];
+        assert_eq!(stats.calls, 1);
+        assert_eq!(stats.errors, 1);
+    }
+    #[test]
+    fn test_rpc_request_bad_parameter_type() {
+        let alice = Mint::new(10_000);
+        let bank = Bank::new(&alice);
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"confirmTransaction","params":[1234567890]}"#;
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new(Arc::new(bank)),
+            client_ip: None,
+            api_key: None,
+        };
+
+        let res = io.handle_request_sync(req, meta);
+        let expected = r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"Invalid params: invalid type: integer `1234567890`, expected a string."},"id":1}"#;
+        let expected: Response =
+            serde_json::from_str(expected).expect("expected response deserialization");
+
+        let result: Response = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(expected, result);
+    }
+    #[test]
+    fn test_rpc_request_bad_signature() {
+        let alice = Mint::new(10_000);
+        let bank = Bank::new(&alice);
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let req =
+            r#"{"jsonrpc":"2.0","id":1,"method":"confirmTransaction","params":["a1b2c3d4e5"]}"#;
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new(Arc::new(bank)),
+            client_ip: None,
+            api_key: None,
+        };
+
+        let res = io.handle_request_sync(req, meta.clone());
+        let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(res["error"]["code"], -32602);
+        assert_eq!(res["error"]["data"]["kind"], "invalidSignature");
+
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"confirmTransaction","params":["0OIl"]}"#;
+        let res = io.handle_request_sync(req, meta);
+        let expected = r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"invalid signature: '0' at position 0 isn't base58; did you mean 'o'?","data":{"kind":"invalidSignature","reason":"'0' at position 0 isn't base58; did you mean 'o'?"}},"id":1}"#;
+        let expected: Response =
+            serde_json::from_str(expected).expect("expected response deserialization");
+
+        let result: Response = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(expected, result);
+    }
+    #[test]
+    fn test_rpc_custom_error() {
+        let errors = vec![
+            (
+                RpcCustomError::InvalidPubkey {
+                    reason: "is 3 bytes, not 32".to_string(),
+                },
+                -32602,
+                r#"{"kind":"invalidPubkey","reason":"is 3 bytes, not 32"}"#,
+            ),
+            (
+                RpcCustomError::StaleIdentityLeaseTerm { term: 7 },
+                error::IDENTITY_LEASE,
+                r#"{"kind":"staleIdentityLeaseTerm","term":7}"#,
+            ),
+            (
+                RpcCustomError::RequestTimeout {
+                    method: "getConfirmedBlock".to_string(),
+                    timeout_ms: 10,
+                },
+                error::REQUEST_TIMEOUT,
+                r#"{"kind":"requestTimeout","method":"getConfirmedBlock","timeoutMs":10}"#,
+            ),
+            (
+                RpcCustomError::NoLedger,
+                error::NO_LEDGER,
+                r#"{"kind":"noLedger"}"#,
+            ),
+        ];
+        for (err, code, data) in errors {
+            let error: Error = err.clone().into();
+            assert_eq!(error.code.code(), code);
+            let data: serde_json::Value = serde_json::from_str(data).unwrap();
+            assert_eq!(error.data, Some(data.clone()));
+            assert_eq!(serde_json::from_value::<RpcCustomError>(data).unwrap(), err);
+        }
+    }
+
+    /// A backend with one account and one signature
+    struct MockBackend {
+        pubkey: Pubkey,
+        signature: Signature,
+    }
+
+    impl RpcBackend for MockBackend {
+        fn get_account(&self, pubkey: &Pubkey) -> Option<Account> {
+            if *pubkey == self.pubkey {
+                Some(Account {
+                    tokens: 42,
+                    userdata: vec![1, 2, 3],
+                    owner: Pubkey::default(),
+                })
+            } else {
+                None
+            }
+        }
+        fn get_balance(&self, pubkey: &Pubkey) -> i64 {
+            self.get_account(pubkey).map_or(0, |account| account.tokens)
+        }
+        fn finality(&self) -> usize {
+            7
+        }
+        fn genesis_hash(&self) -> Hash {
+            hash(b"genesis")
+        }
+        fn last_id(&self) -> Hash {
+            Hash::default()
+        }
+        fn recent_last_ids(&self, count: usize) -> Vec<(Hash, u64)> {
+            vec![(Hash::default(), timestamp()); count.min(2)]
+        }
+        fn get_signature_status(&self, signature: &Signature) -> Option<bank::Result<()>> {
+            if *signature == self.signature {
+                Some(Ok(()))
+            } else if *signature == Signature::new(&[8; 64]) {
+                Some(Err(BankError::InsufficientFunds(self.pubkey)))
+            } else {
+                None
+            }
+        }
+        fn get_signature_statuses(
+            &self,
+            signatures: &[Signature],
+        ) -> Vec<Option<bank::Result<()>>> {
+            signatures
+                .iter()
+                .map(|signature| self.get_signature_status(signature))
+                .collect()
+        }
+        fn transaction_count(&self) -> usize {
+            3
+        }
+        fn tick_height(&self) -> u64 {
+            2 * TICKS_PER_SLOT + 1
+        }
+        fn recent_fees(&self) -> RecentFees {
+            RecentFees {
+                fees: (1..101).collect(),
+                queue_depth: 10,
+            }
+        }
+        fn recent_slot_costs(&self) -> Vec<SlotCost> {
+            let mut cost = SlotCost::new(1);
+            cost.transactions = 2;
+            cost.compute_units = 220;
+            vec![SlotCost::new(0), cost]
+        }
+        fn accounts_snapshot(&self) -> (Hash, Vec<(Pubkey, Account)>) {
+            (
+                Hash::default(),
+                vec![(self.pubkey, self.get_account(&self.pubkey).unwrap())],
+            )
+        }
+        fn simulate_transaction(&self, tx: &Transaction) -> (bank::Result<()>, Vec<(Pubkey, i64)>) {
+            (Err(BankError::AccountNotFound(tx.from)), vec![])
+        }
+    }
+
+    #[test]
+    fn test_rpc_mock_backend() {
+        let pubkey = Keypair::new().pubkey();
+        let signature = Signature::new(&[9; 64]);
+        let backend = MockBackend { pubkey, signature };
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new(Arc::new(backend)),
+            client_ip: None,
+            api_key: None,
+        };
+        let request = |method: &str, params: String| {
+            let req = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":[{}]}}"#,
+                method, params
+            );
+            let res = io.handle_request_sync(&req, meta.clone()).unwrap();
+            serde_json::from_str::<serde_json::Value>(&res).unwrap()
+        };
+
+        let res = request("getBalance", format!(r#""{}""#, pubkey));
+        assert_eq!(res["result"], 42);
+        let other = Keypair::new().pubkey();
+        let res = request("getBalance", format!(r#""{}""#, other));
+        assert_eq!(res["result"], 0);
+        let res = request("getBalance", r#""not a pubkey""#.to_string());
+        assert_eq!(res["error"]["code"], -32602);
+        assert_eq!(res["error"]["data"]["kind"], "invalidPubkey");
+
+        let res = request("getAccountData", format!(r#""{}""#, pubkey));
+        assert_eq!(res["result"][0], bs58::encode(&[1u8, 2, 3]).into_string());
+        let res = request("getAccountData", format!(r#""{}""#, other));
+        assert!(res["result"].is_null());
+
+        let res = request("getAccountInfo", format!(r#""{}""#, pubkey));
+        assert_eq!(res["result"]["tokens"], 42);
+        assert_eq!(res["result"]["owner"], Pubkey::default().to_string());
+        assert_eq!(
+            res["result"]["userdata"],
+            bs58::encode(&[1u8, 2, 3]).into_string()
+        );
+        assert_eq!(res["result"]["encoding"], "base58");
+        let res = request("getAccountInfo", format!(r#""{}", "base64""#, pubkey));
+        assert_eq!(res["result"]["userdata"], base64::encode(&[1u8, 2, 3]));
+        let res = request("getAccountInfo", format!(r#""{}""#, other));
+        assert!(res["result"].is_null());
+
+        let res = request(
+            "confirmTransaction",
+            format!(r#""{}""#, bs58::encode(signature)),
+        );
+        assert_eq!(res["result"], true);
+        let res = request(
+            "confirmTransaction",
+            format!(r#""{}""#, bs58::encode(Signature::default())),
+        );
+        assert_eq!(res["result"], false);
+
+        let failed = Signature::new(&[8; 64]);
+        let res = request(
+            "getSignatureStatuses",
+            format!(
+                r#"["{}", "{}", "{}"]"#,
+                bs58::encode(signature),
+                bs58::encode(failed),
+                bs58::encode(Signature::default())
+            ),
+        );
+        let statuses: Vec<RpcTransactionStatus> =
+            serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(
+            statuses,
+            vec![
+                RpcTransactionStatus::Confirmed,
+                RpcTransactionStatus::Failed(format!("InsufficientFunds({:?})", pubkey)),
+                RpcTransactionStatus::NotFound,
+            ]
+        );
+        let too_many =
+            vec![format!(r#""{}""#, bs58::encode(signature)); MAX_SIGNATURE_STATUSES + 1];
+        let res = request("getSignatureStatuses", format!("[{}]", too_many.join(",")));
+        assert_eq!(res["error"]["code"], -32602);
+
+        let res = request("getFeeEstimate", String::new());
+        let estimate: RpcFeeEstimate = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(
+            estimate,
+            RpcFeeEstimate {
+                samples: 100,
+                min_fee: 1,
+                median_fee: 50,
+                high_fee: 90,
+                max_fee: 100,
+                queue_depth: 10,
+                recommended_fee: 75,
+            }
+        );
+        let res = request("getBlockProduction", String::new());
+        let production: RpcBlockProduction = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(production.slots.len(), 2);
+        assert_eq!(production.slots[1].slot, 1);
+        assert_eq!(production.slots[1].transactions, 2);
+        assert_eq!(production.max_compute_units, 220);
+        let res = request("getEconomicParameters", String::new());
+        let parameters: RpcEconomicParameters =
+            serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(parameters.inflation_percent, 0);
+        assert_eq!(parameters.fee_burn_percent, 100);
+        assert_eq!(parameters.rent_epoch_entry_ids, MAX_ENTRY_IDS);
+        assert_eq!(parameters.rent_exempt_epochs, 2);
+        let res = request("getFinality", String::new());
+        assert_eq!(res["result"], 7);
+        let res = request("getGenesisHash", String::new());
+        assert_eq!(res["result"], hash(b"genesis").to_string());
+        let res = request("getTransactionCount", String::new());
+        assert_eq!(res["result"], 3);
+        let res = request("getSlot", String::new());
+        assert_eq!(res["result"], 2);
+        let res = request("getLastId", String::new());
+        assert_eq!(res["result"], bs58::encode(Hash::default()).into_string());
+        let res = request("getRecentLastIds", "1".to_string());
+        let recent: RpcRecentLastIds = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(recent.last_ids.len(), 1);
+        assert_eq!(recent.last_ids[0].last_id, Hash::default().to_string());
+        assert!(recent.last_ids[0].age_ms < 10_000);
+        assert_eq!(recent.last_ids[0].remaining_ids, MAX_ENTRY_IDS - 1);
+        assert_eq!(recent.recommended_fee, 75);
+        let res = request("getRecentLastIds", String::new());
+        let recent: RpcRecentLastIds = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(recent.last_ids[1].remaining_ids, MAX_ENTRY_IDS - 2);
+
+        let res = request("getAccountsHashes", String::new());
+        let hashes: RpcAccountsHashes = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(hashes.last_id, Hash::default().to_string());
+        assert_eq!(hashes.accounts.len(), 1);
+        assert_eq!(hashes.accounts[0].pubkey, pubkey.to_string());
+        assert_eq!(hashes.accounts[0].tokens, 42);
+        assert_ne!(hashes.hash, Hash::default().to_string());
+    }
+
+    #[test]
+    fn test_rpc_get_confirmed_block() {
+        let alice = Mint::new(10_000);
+        let bob_pubkey = Keypair::new().pubkey();
+        let bank = Arc::new(Bank::new(&alice));
+        let ledger_path = format!(
+            "{}/tmp-ledger-rpc-get_confirmed_block-{}",
+            env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string()),
+            bob_pubkey
+        );
+
+        // genesis is two entries, one of them a tick, then a transfer in slot 1
+        let mut entries = alice.create_entries();
+        let tx = Transaction::new(&alice.keypair(), bob_pubkey, 20, alice.last_id());
+        let mut last_id = alice.last_id();
+        for i in 1..TICKS_PER_SLOT * 2 {
+            if i == TICKS_PER_SLOT {
+                entries.push(next_entry(&last_id, 1, vec![tx.clone()]));
+                last_id = entries[entries.len() - 1].id;
+            }
+            entries.push(next_entry(&last_id, 1, vec![]));
+            last_id = entries[entries.len() - 1].id;
+        }
+        let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
+        writer.write_entries(entries.clone()).unwrap();
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let request_processor = JsonRpcRequestProcessor::new_with_ledger(bank, &ledger_path);
+        let meta = Meta {
+            request_processor,
+            client_ip: None,
+            api_key: None,
+        };
+
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"getConfirmedBlock","params":[1]}"#;
+        let res = io.handle_request_sync(req, meta.clone());
+        let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        let block: RpcConfirmedBlock = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(block.slot, 1);
+        assert_eq!(block.parent_slot, 0);
+        assert_eq!(block.transactions, vec![tx]);
+        assert_eq!(
+            block.blockhash,
+            bs58::encode(entries[entries.len() - 1].id).into_string()
+        );
+        assert_eq!(
+            block.previous_blockhash,
+            bs58::encode(entries[TICKS_PER_SLOT as usize].id).into_string()
+        );
+
+        // slot 2 hasn't been recorded
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"getConfirmedBlock","params":[2]}"#;
+        let res = io.handle_request_sync(req, meta.clone());
+        let expected: Response =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","result":null,"id":1}"#).unwrap();
+        let result: Response = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(expected, result);
+
+        // until it's written
+        let slot2: Vec<_> = (0..TICKS_PER_SLOT)
+            .map(|_| {
+                let tick = next_entry(&last_id, 1, vec![]);
+                last_id = tick.id;
+                tick
+            })
+            .collect();
+        writer.write_entries(slot2).unwrap();
+        let res = io.handle_request_sync(req, meta);
+        let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        let block: RpcConfirmedBlock = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(block.slot, 2);
+        assert!(block.transactions.is_empty());
+        assert_eq!(block.blockhash, bs58::encode(last_id).into_string());
+
+        remove_dir_all(ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_rpc_get_entries() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let ledger_path = format!(
+            "{}/tmp-ledger-rpc-get_entries-{}",
+            env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string()),
+            Keypair::new().pubkey()
+        );
+        let entries = alice.create_entries();
+        let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
+        writer.write_entries(entries.clone()).unwrap();
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new_with_ledger(bank.clone(), &ledger_path),
+            client_ip: None,
+            api_key: None,
+        };
+        let get_entries = |meta: &Meta, params: &str| {
+            let req = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"getEntries","params":{}}}"#,
+                params
+            );
+            let res = io.handle_request_sync(&req, meta.clone()).unwrap();
+            serde_json::from_str::<serde_json::Value>(&res).unwrap()
+        };
+
+        let res = get_entries(&meta, "[0]");
+        let page: RpcEntries = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(page.start_height, 0);
+        assert_eq!(page.next_height, entries.len() as u64);
+        let expected: Vec<_> = entries.iter().cloned().map(RpcEntry::Json).collect();
+        assert_eq!(page.entries, expected);
+
+        let res = get_entries(&meta, r#"[1, {"maxCount": 1, "encoding": "base64"}]"#);
+        let page: RpcEntries = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(page.next_height, 2);
+        assert_eq!(
+            page.entries,
+            vec![RpcEntry::Base64(base64::encode(&entries[1].to_bytes()))]
+        );
+
+        // past the end of the ledger
+        let res = get_entries(&meta, "[100]");
+        let page: RpcEntries = serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(page.next_height, 100);
+        assert!(page.entries.is_empty());
+
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new(bank),
+            client_ip: None,
+            api_key: None,
+        };
+        let res = get_entries(&meta, "[0]");
+        assert_eq!(res["error"]["code"], -32002);
+
+        remove_dir_all(ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_rpc_get_confirmed_transaction() {
+        let alice = Mint::new(10_000);
+        let bob_pubkey = Keypair::new().pubkey();
+        let bank = Arc::new(Bank::new(&alice));
+        let ledger_path = format!(
+            "{}/tmp-ledger-rpc-get_confirmed_transaction-{}",
+            env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string()),
+            bob_pubkey
+        );
+
+        let mut entries = alice.create_entries();
+        let tx = Transaction::new_with_memo(
+            &alice.keypair(),
+            bob_pubkey,
+            20,
+            alice.last_id(),
+            "deposit 1234",
+        );
+        let mut last_id = alice.last_id();
+        for i in 1..TICKS_PER_SLOT * 2 {
+            if i == TICKS_PER_SLOT {
+                entries.push(next_entry(&last_id, 1, vec![tx.clone()]));
+                last_id = entries[entries.len() - 1].id;
+            }
+            entries.push(next_entry(&last_id, 1, vec![]));
+            last_id = entries[entries.len() - 1].id;
+        }
+        let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
+        writer.write_entries(entries).unwrap();
+
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let request_processor = JsonRpcRequestProcessor::new_with_ledger(bank, &ledger_path);
+        let meta = Meta {
+            request_processor,
+            client_ip: None,
+            api_key: None,
+        };
+
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"getConfirmedTransaction","params":["{}"]}}"#,
+            bs58::encode(tx.signature).into_string()
+        );
+        let res = io.handle_request_sync(&req, meta.clone());
+        let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        let confirmed: RpcConfirmedTransaction =
+            serde_json::from_value(res["result"].clone()).unwrap();
+        assert_eq!(confirmed.slot, 1);
+        assert_eq!(confirmed.memo, Some("deposit 1234".to_string()));
+        assert_eq!(confirmed.transaction, tx);
+
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"getConfirmedTransaction","params":["{}"]}}"#,
+            bs58::encode(Signature::default()).into_string()
+        );
+        let res = io.handle_request_sync(&req, meta);
+        let expected: Response =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","result":null,"id":1}"#).unwrap();
+        let result: Response = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(expected, result);
+
+        remove_dir_all(ledger_path).unwrap();
+    }
+
+    #[test]
+    fn test_rpc_get_signatures_for_address() {
+        let alice = Mint::new(10_000);
+        let bob_pubkey = Keypair::new().pubkey();
+        let bank = Arc::new(Bank::new(&alice));
+        let ledger_path = format!(
+            "{}/tmp-ledger-rpc-get_signatures_for_address-{}",
+            env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string()),
+            bob_pubkey
+        );
+
+        let mut entries = alice.create_entries();
+        let mut last_id = alice.last_id();
+        let txs: Vec<_> = (0..3)
+            .map(|i| {
+                Transaction::new_with_memo(
+                    &alice.keypair(),
+                    bob_pubkey,
+                    i + 1,
+                    alice.last_id(),
+                    &format!("payment {}", i),
+                )
             })
-            .unwrap();
-        JsonRpcService { thread_hdl }
-    }
-}
+            .collect();
+        for tx in &txs {
+            entries.push(next_entry(&last_id, 1, vec![tx.clone()]));
+            last_id = entries[entries.len() - 1].id;
+            for _ in 0..TICKS_PER_SLOT {
+                entries.push(next_entry(&last_id, 1, vec![]));
+                last_id = entries[entries.len() - 1].id;
+            }
+        }
+        let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
+        writer.write_entries(entries).unwrap();
 
-impl Service for JsonRpcService {
-    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
-        vec![self.thread_hdl]
-    }
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let request_processor = JsonRpcRequestProcessor::new_with_ledger(bank, &ledger_path);
+        let meta = Meta {
+            request_processor,
+            client_ip: None,
+            api_key: None,
+        };
+        let get_signatures = |params: String| -> Vec<RpcSignatureForAddress> {
+            let req = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"getSignaturesForAddress","params":{}}}"#,
+                params
+            );
+            let res = io.handle_request_sync(&req, meta.clone());
+            let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
+                .expect("actual response deserialization");
+            serde_json::from_value(res["result"].clone()).unwrap()
+        };
 
-    fn join(self) -> thread::Result<()> {
-        self.thread_hdl.join()
-    }
-}
+        // newest first
+        let mut all = get_signatures(format!(r#"["{}"]"#, bob_pubkey));
+        assert_eq!(all.len(), 3);
+        assert_eq!(
+            all[0].signature,
+            bs58::encode(txs[2].signature).into_string()
+        );
+        assert_eq!(all[0].memo, Some("payment 2".to_string()));
+        assert!(all[0].slot > all[1].slot && all[1].slot > all[2].slot);
 
-#[derive(Clone)]
-pub struct Meta {
-    pub request_processor: JsonRpcRequestProcessor,
-}
-impl Metadata for Meta {}
+        let page = get_signatures(format!(
+            r#"["{}",{{"limit":1,"before":"{}"}}]"#,
+            bob_pubkey, all[0].signature
+        ));
+        assert_eq!(page, vec![all.remove(1)]);
 
-build_rpc_trait! {
-    pub trait RpcSol {
-        type Metadata;
+        assert!(get_signatures(format!(r#"["{}"]"#, Keypair::new().pubkey())).is_empty());
 
-        #[rpc(meta, name = "confirmTransaction")]
-        fn confirm_transaction(&self, Self::Metadata, String) -> Result<bool>;
+        remove_dir_all(ledger_path).unwrap();
+    }
 
-        #[rpc(meta, name = "getBalance")]
-        fn get_balance(&self, Self::Metadata, String) -> Result<i64>;
+    #[test]
+    fn test_rpc_request_timeout() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let ledger_path = format!(
+            "{}/tmp-ledger-rpc-request_timeout-{}",
+            env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string()),
+            Keypair::new().pubkey()
+        );
+        let mut writer = FileLedgerWriter::open(&ledger_path, true).unwrap();
+        writer.write_entries(alice.create_entries()).unwrap();
 
-        #[rpc(meta, name = "getFinality")]
-        fn get_finality(&self, Self::Metadata) -> Result<usize>;
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let mut request_processor = JsonRpcRequestProcessor::new_with_ledger(bank, &ledger_path);
+        request_processor.request_timeout = Duration::from_millis(0);
+        let meta = Meta {
+            request_processor,
+            client_ip: None,
+            api_key: None,
+        };
 
-        #[rpc(meta, name = "getLastId")]
-        fn get_last_id(&self, Self::Metadata) -> Result<String>;
+        for req in &[
+            r#"{"jsonrpc":"2.0","id":1,"method":"getConfirmedBlock","params":[0]}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":"getAccountsHashes","params":[]}"#,
+        ] {
+            let res = io.handle_request_sync(req, meta.clone());
+            let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
+                .expect("actual response deserialization");
+            assert_eq!(res["error"]["code"], -32004);
+        }
 
-        #[rpc(meta, name = "getTransactionCount")]
-        fn get_transaction_count(&self, Self::Metadata) -> Result<u64>;
+        remove_dir_all(ledger_path).unwrap();
+    }
 
-        // #[rpc(meta, name = "sendTransaction")]
-        // fn send_transaction(&self, Self::Metadata, String, i64) -> Result<String>;
+    #[test]
+    fn test_rpc_service_close() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let rpc_addr = "127.0.0.1:0".parse().unwrap();
+        let rpc_service =
+            JsonRpcService::new(bank, None, None, None, JsonRpcConfig::default(), rpc_addr)
+                .unwrap();
+        rpc_service.close();
+        rpc_service.join().unwrap();
     }
-}
 
-pub struct RpcSolImpl;
-impl RpcSol for RpcSolImpl {
-    type Metadata = Meta;
+    #[test]
+    fn test_admin_rpc_service_close() {
+        let lease = Arc::new(IdentityLease::new(false));
+        let rpc_addr = "127.0.0.1:0".parse().unwrap();
+        let admin_rpc_service = AdminRpcService::new(lease, rpc_addr).unwrap();
+        admin_rpc_service.close();
+        admin_rpc_service.join().unwrap();
+    }
 
-    fn confirm_transaction(&self, meta: Self::Metadata, id: String) -> Result<bool> {
-        let signature_vec = bs58::decode(id)
-            .into_vec()
-            .map_err(|_| Error::invalid_request())?;
-        if signature_vec.len() != mem::size_of::<Signature>() {
-            return Err(Error::invalid_request());
-        }
-        let signature = Signature::new(&signature_vec);
-        meta.request_processor.get_signature_status(signature)
+    #[test]
+    fn test_admin_rpc_service_port_in_use() {
+        let rpc_addr = "127.0.0.1:0".parse().unwrap();
+        let admin_rpc_service =
+            AdminRpcService::new(Arc::new(IdentityLease::new(true)), rpc_addr).unwrap();
+        let rpc_addr = admin_rpc_service.local_addr();
+        let result = AdminRpcService::new(Arc::new(IdentityLease::new(false)), rpc_addr);
+        assert_matches!(
+            result.err(),
+            Some(ServiceError::StartupError(StartupError::PortInUse(addr))) if addr == rpc_addr
+        );
+        admin_rpc_service.close();
+        admin_rpc_service.join().unwrap();
     }
-    fn get_balance(&self, meta: Self::Metadata, id: String) -> Result<i64> {
-        let pubkey_vec = bs58::decode(id)
-            .into_vec()
-            .map_err(|_| Error::invalid_request())?;
-        if pubkey_vec.len() != mem::size_of::<Pubkey>() {
-            return Err(Error::invalid_request());
-        }
-        let pubkey = Pubkey::new(&pubkey_vec);
-        meta.request_processor.get_balance(pubkey)
+
+    #[test]
+    fn test_rpc_service_port_in_use() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let rpc_addr = listener.local_addr().unwrap();
+        let result =
+            JsonRpcService::new(bank, None, None, None, JsonRpcConfig::default(), rpc_addr);
+        assert_matches!(
+            result.err(),
+            Some(ServiceError::StartupError(StartupError::PortInUse(addr))) if addr == rpc_addr
+        );
     }
-    fn get_finality(&self, meta: Self::Metadata) -> Result<usize> {
-        meta.request_processor.get_finality()
+
+    #[test]
+    fn test_rpc_service_bad_api_keys() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let path = tmp_api_keys_path("missing");
+        let config = JsonRpcConfig {
+            api_keys: Some(path.clone()),
+            ..JsonRpcConfig::default()
+        };
+        let rpc_addr = "127.0.0.1:0".parse().unwrap();
+        let result = JsonRpcService::new(bank, None, None, None, config, rpc_addr);
+        assert_matches!(
+            result.err(),
+            Some(ServiceError::StartupError(StartupError::BadApiKeys(p, _))) if p == path
+        );
     }
-    fn get_last_id(&self, meta: Self::Metadata) -> Result<String> {
-        meta.request_processor.get_last_id()
+
+    #[test]
+    fn test_rpc_batch_limit() {
+        let pubkey = Keypair::new().pubkey();
+        let backend = MockBackend {
+            pubkey,
+            signature: Signature::default(),
+        };
+        let limits = RequestLimits::new(&JsonRpcConfig {
+            max_batch_size: 3,
+            ..JsonRpcConfig::default()
+        })
+        .unwrap();
+        let mut io = MetaIoHandler::with_middleware(limits);
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new(Arc::new(backend)),
+            client_ip: None,
+            api_key: None,
+        };
+        let batch = |len: usize| {
+            let calls: Vec<_> = (0..len)
+                .map(|id| {
+                    format!(
+                        r#"{{"jsonrpc":"2.0","id":{},"method":"getBalance","params":["{}"]}}"#,
+                        id, pubkey
+                    )
+                })
+                .collect();
+            let req = format!("[{}]", calls.join(","));
+            let res = io.handle_request_sync(&req, meta.clone()).unwrap();
+            serde_json::from_str::<serde_json::Value>(&res).unwrap()
+        };
+
+        let res = batch(3);
+        assert_eq!(res.as_array().unwrap().len(), 3);
+        for (id, res) in res.as_array().unwrap().iter().enumerate() {
+            assert_eq!(res["id"], id);
+            assert_eq!(res["result"], 42);
+        }
+
+        let res = batch(4);
+        assert_eq!(res["error"]["code"], -32600);
+        assert!(res["id"].is_null());
     }
-    fn get_transaction_count(&self, meta: Self::Metadata) -> Result<u64> {
-        meta.request_processor.get_transaction_count()
-    }
-    // fn send_transaction(&self, meta: Self::Metadata, to: String, tokens: i64) -> Result<String> {
-    //     let client_keypair = read_keypair(&meta.keypair_location.unwrap()).unwrap();
-    //     let mut client = mk_client(&meta.leader.unwrap());
-    //     let last_id = client.get_last_id();
-    //     let to_pubkey_vec = bs58::decode(to)
-    //         .into_vec()
-    //         .expect("base58-encoded public key");
-    //
-    //     if to_pubkey_vec.len() != mem::size_of::<Pubkey>() {
-    //         Err(Error::invalid_request())
-    //     } else {
-    //         let to_pubkey = Pubkey::new(&to_pubkey_vec);
-    //         let signature = client
-    //             .transfer(tokens, &client_keypair, to_pubkey, &last_id)
-    //             .unwrap();
-    //         Ok(bs58::encode(signature).into_string())
-    //     }
-    // }
-}
-#[derive(Clone)]
-pub struct JsonRpcRequestProcessor {
-    bank: Arc<Bank>,
-}
-impl JsonRpcRequestProcessor {
-    /// Create a new request processor that wraps the given Bank.
-    pub fn new(bank: Arc<Bank>) -> Self {
-        JsonRpcRequestProcessor { bank }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(&JsonRpcConfig {
+            requests_per_second_per_ip: 10,
+            burst_per_ip: 5,
+            requests_per_second: 100,
+            ..JsonRpcConfig::default()
+        })
+        .unwrap();
+        let alice = Some("10.0.0.1".parse().unwrap());
+        let bob = Some("10.0.0.2".parse().unwrap());
+        let now = Instant::now();
+        assert!(limiter.check(alice, 5, now).is_ok());
+        assert!(limiter.check(alice, 1, now).is_err());
+        assert!(limiter.check(bob, 5, now).is_ok());
+
+        // a tenth of a second buys alice one more request
+        let later = now + Duration::from_millis(100);
+        assert!(limiter.check(alice, 1, later).is_ok());
+        assert!(limiter.check(alice, 1, later).is_err());
+
+        // requests from an unknown address only count against the server
+        assert!(limiter.check(None, 89, later).is_ok());
+        assert!(limiter.check(None, 11, later).is_err());
     }
 
-    /// Process JSON-RPC request items sent via JSON-RPC.
-    fn get_balance(&self, pubkey: Pubkey) -> Result<i64> {
-        let val = self.bank.get_balance(&pubkey);
-        Ok(val)
+    #[test]
+    fn test_rate_limiter_ipv6_prefix() {
+        let mut limiter = RateLimiter::new(&JsonRpcConfig {
+            requests_per_second_per_ip: 10,
+            burst_per_ip: 5,
+            ..JsonRpcConfig::default()
+        })
+        .unwrap();
+        let now = Instant::now();
+        let first = Some("2001:db8::1".parse().unwrap());
+        assert!(limiter.check(first, 5, now).is_ok());
+        // another address in the same /64 shares the bucket
+        let same = Some("2001:db8::ffff:2".parse().unwrap());
+        assert!(limiter.check(same, 1, now).is_err());
+        let other = Some("2001:db8:0:1::1".parse().unwrap());
+        assert!(limiter.check(other, 5, now).is_ok());
+
+        // a mapped IPv4 address counts as the IPv4 address
+        let ipv4 = Some("10.0.0.1".parse().unwrap());
+        assert!(limiter.check(ipv4, 5, now).is_ok());
+        let mapped = Some("::ffff:10.0.0.1".parse().unwrap());
+        assert!(limiter.check(mapped, 1, now).is_err());
     }
-    fn get_finality(&self) -> Result<usize> {
-        Ok(self.bank.finality())
+
+    #[test]
+    fn test_rate_limiter_forgets_quiet_clients() {
+        // an empty bucket fills in half a second
+        let mut limiter = RateLimiter::new(&JsonRpcConfig {
+            requests_per_second_per_ip: 10,
+            burst_per_ip: 5,
+            ..JsonRpcConfig::default()
+        })
+        .unwrap();
+        let alice = Some("10.0.0.1".parse().unwrap());
+        let bob = Some("10.0.0.2".parse().unwrap());
+        let now = Instant::now();
+        assert!(limiter.check(alice, 5, now).is_ok());
+        assert!(limiter.check(bob, 5, now).is_ok());
+
+        // a client seen in the last generation keeps its bucket
+        let later = now + Duration::from_millis(600);
+        assert!(limiter.check(alice, 5, later).is_ok());
+        assert!(limiter.check(alice, 1, later).is_err());
+        assert_eq!(limiter.per_ip.len() + limiter.previous.len(), 2);
+
+        // one quiet for a whole generation is forgotten
+        let later = later + Duration::from_millis(600);
+        assert!(limiter.check(alice, 1, later).is_ok());
+        assert_eq!(limiter.per_ip.len() + limiter.previous.len(), 1);
     }
-    fn get_last_id(&self) -> Result<String> {
-        let id = self.bank.last_id();
-        Ok(bs58::encode(id).into_string())
+
+    #[test]
+    fn test_rate_limiter_evicts_when_full() {
+        let mut limiter = RateLimiter::new(&JsonRpcConfig {
+            requests_per_second_per_ip: 10,
+            burst_per_ip: 5,
+            ..JsonRpcConfig::default()
+        })
+        .unwrap();
+        let now = Instant::now();
+        let client = |i: usize| Some(IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + i as u32)));
+        for i in 0..MAX_RATE_LIMITED_IPS {
+            assert!(limiter.check(client(i), 1, now).is_ok());
+        }
+        assert_eq!(limiter.per_ip.len(), MAX_RATE_LIMITED_IPS);
+
+        // a new client still gets in, at the cost of some of the others
+        let newest = client(MAX_RATE_LIMITED_IPS);
+        assert!(limiter.check(newest, 1, now).is_ok());
+        assert!(limiter.per_ip.contains_key(&newest.unwrap()));
+        assert_eq!(
+            limiter.per_ip.len() + limiter.previous.len(),
+            MAX_RATE_LIMITED_IPS + 1 - RATE_LIMITED_IPS_EVICTED
+        );
     }
-    fn get_signature_status(&self, signature: Signature) -> Result<bool> {
-        Ok(self.bank.has_signature(&signature))
+
+    #[test]
+    fn test_rate_limiter_bad_config() {
+        let config = JsonRpcConfig {
+            requests_per_second_per_ip: 10,
+            burst_per_ip: 0,
+            ..JsonRpcConfig::default()
+        };
+        assert!(RateLimiter::new(&config).is_err());
+        let config = JsonRpcConfig {
+            burst_per_ip: u64::max_value(),
+            ..config
+        };
+        assert!(RateLimiter::new(&config).is_err());
+
+        // without a per-IP limit the burst isn't used
+        let config = JsonRpcConfig {
+            requests_per_second_per_ip: 0,
+            ..config
+        };
+        assert!(RateLimiter::new(&config).is_ok());
+        let config = JsonRpcConfig {
+            requests_per_second_per_ip: 10,
+            burst_per_ip: 0,
+            ..JsonRpcConfig::default()
+        };
+        assert_matches!(
+            RequestLimits::new(&config).err(),
+            Some(ServiceError::StartupError(StartupError::BadRpcConfig(_)))
+        );
     }
-    fn get_transaction_count(&self) -> Result<u64> {
-        Ok(self.bank.transaction_count() as u64)
+
+    #[test]
+    fn test_rpc_rate_limit() {
+        let pubkey = Keypair::new().pubkey();
+        let backend = MockBackend {
+            pubkey,
+            signature: Signature::default(),
+        };
+        let limits = RequestLimits::new(&JsonRpcConfig {
+            requests_per_second_per_ip: 1,
+            burst_per_ip: 2,
+            ..JsonRpcConfig::default()
+        })
+        .unwrap();
+        let mut io = MetaIoHandler::with_middleware(limits);
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let request_processor = JsonRpcRequestProcessor::new(Arc::new(backend));
+        let get_balance = |client_ip: &str| {
+            let meta = Meta {
+                request_processor: request_processor.clone(),
+                client_ip: Some(client_ip.parse().unwrap()),
+                api_key: None,
+            };
+            let req = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"getBalance","params":["{}"]}}"#,
+                pubkey
+            );
+            let res = io.handle_request_sync(&req, meta).unwrap();
+            serde_json::from_str::<serde_json::Value>(&res).unwrap()
+        };
+
+        assert_eq!(get_balance("10.0.0.1")["result"], 42);
+        assert_eq!(get_balance("10.0.0.1")["result"], 42);
+        assert_eq!(get_balance("10.0.0.1")["error"]["code"], -32005);
+        assert_eq!(get_balance("10.0.0.2")["result"], 42);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use bank::Bank;
-    use jsonrpc_core::Response;
-    use mint::Mint;
-    use signature::{Keypair, KeypairUtil};
-    use std::sync::Arc;
-    use transaction::Transaction;
+    fn tmp_api_keys_path(name: &str) -> String {
+        format!(
+            "{}/tmp-api-keys-{}-{}.json",
+            env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string()),
+            name,
+            Keypair::new().pubkey()
+        )
+    }
 
     #[test]
-    fn test_rpc_request() {
-        let alice = Mint::new(10_000);
-        let bob_pubkey = Keypair::new().pubkey();
-        let bank = Bank::new(&alice);
+    fn test_api_key_limiter() {
+        let path = tmp_api_keys_path("limiter");
+        fs::write(
+            &path,
+            r#"{"keys":{
+                "free":{"methods":["getBalance"],"requests_per_second":1,"burst":2},
+                "paid":{}
+            }}"#,
+        )
+        .unwrap();
+        let mut limiter = ApiKeyLimiter::new(&path).unwrap();
+        let now = Instant::now();
 
-        let last_id = bank.last_id();
-        let tx = Transaction::new(&alice.keypair(), bob_pubkey, 20, last_id);
-        bank.process_transaction(&tx).expect("process transaction");
+        // requests without a key are left to the per-IP limit
+        assert_eq!(limiter.check(None, &["getAccountInfo"], 1, now), Ok(false));
+        assert_eq!(
+            limiter.check(Some("nope"), &["getBalance"], 1, now),
+            Err(RpcCustomError::InvalidApiKey)
+        );
+        assert_eq!(
+            limiter.check(Some("free"), &["getBalance", "getAccountInfo"], 2, now),
+            Err(RpcCustomError::MethodNotAllowed {
+                method: "getAccountInfo".to_string()
+            })
+        );
+        assert_eq!(
+            limiter.check(Some("free"), &["getBalance"], 2, now),
+            Ok(true)
+        );
+        assert!(limiter
+            .check(Some("free"), &["getBalance"], 1, now)
+            .is_err());
+        assert_eq!(
+            limiter.check(Some("paid"), &["getAccountInfo"], 50, now),
+            Ok(true)
+        );
 
-        let request_processor = JsonRpcRequestProcessor::new(Arc::new(bank));
+        fs::write(&path, r#"{"require_key":true,"keys":{"paid":{}}}"#).unwrap();
+        // the file's modified time may not have moved on a coarse filesystem
+        limiter.modified = None;
+        let later = now + Duration::from_millis(API_KEYS_CHECK_MS);
+        assert_eq!(
+            limiter.check(None, &["getBalance"], 1, later),
+            Err(RpcCustomError::MissingApiKey)
+        );
+        assert_eq!(
+            limiter.check(Some("free"), &["getBalance"], 1, later),
+            Err(RpcCustomError::InvalidApiKey)
+        );
+        assert!(limiter.buckets.is_empty());
+
+        // a file that doesn't load keeps the keys
+        fs::write(&path, "{").unwrap();
+        limiter.modified = None;
+        let later = later + Duration::from_millis(API_KEYS_CHECK_MS);
+        assert_eq!(
+            limiter.check(Some("paid"), &["getBalance"], 1, later),
+            Ok(true)
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rpc_api_keys() {
+        let pubkey = Keypair::new().pubkey();
+        let backend = MockBackend {
+            pubkey,
+            signature: Signature::default(),
+        };
+        let path = tmp_api_keys_path("rpc");
+        fs::write(&path, r#"{"keys":{"key":{"methods":["getBalance"]}}}"#).unwrap();
+        let limits = RequestLimits::new(&JsonRpcConfig {
+            requests_per_second_per_ip: 1,
+            burst_per_ip: 1,
+            api_keys: Some(path.clone()),
+            ..JsonRpcConfig::default()
+        })
+        .unwrap();
+        let mut io = MetaIoHandler::with_middleware(limits);
+        let rpc = RpcSolImpl;
+        io.extend_with(rpc.to_delegate());
+        let request_processor = JsonRpcRequestProcessor::new(Arc::new(backend));
+        let request = |method: &str, api_key: Option<&str>| {
+            let meta = Meta {
+                request_processor: request_processor.clone(),
+                client_ip: Some("10.0.0.1".parse().unwrap()),
+                api_key: api_key.map(|api_key| api_key.to_string()),
+            };
+            let req = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":["{}"]}}"#,
+                method, pubkey
+            );
+            let res = io.handle_request_sync(&req, meta).unwrap();
+            serde_json::from_str::<serde_json::Value>(&res).unwrap()
+        };
+
+        // with a key, the client isn't held to its IP's limit
+        for _ in 0..3 {
+            assert_eq!(request("getBalance", Some("key"))["result"], 42);
+        }
+        let res = request("getAccountInfo", Some("key"));
+        assert_eq!(res["error"]["code"], error::METHOD_NOT_ALLOWED);
+        assert_eq!(res["error"]["data"]["kind"], "methodNotAllowed");
+        let res = request("getBalance", Some("other"));
+        assert_eq!(res["error"]["code"], error::INVALID_API_KEY);
+
+        assert!(request("getAccountInfo", None)["result"].is_object());
+        assert_eq!(
+            request("getAccountInfo", None)["error"]["code"],
+            error::RATE_LIMITED
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rpc_send_transaction() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let tpu = UdpSocket::bind("127.0.0.1:0").unwrap();
 
         let mut io = MetaIoHandler::default();
         let rpc = RpcSolImpl;
         io.extend_with(rpc.to_delegate());
-        let meta = Meta { request_processor };
+        let mut request_processor = JsonRpcRequestProcessor::new(bank.clone());
+        request_processor.transactions_addr = Some(tpu.local_addr().unwrap());
+        let meta = Meta {
+            request_processor,
+            client_ip: None,
+            api_key: None,
+        };
 
+        let to = Keypair::new().pubkey();
+        let tx = Transaction::new(&alice.keypair(), to, 20, alice.last_id());
+        let data = serialize(&tx).unwrap();
         let req = format!(
-            r#"{{"jsonrpc":"2.0","id":1,"method":"getBalance","params":["{}"]}}"#,
-            bob_pubkey
+            r#"{{"jsonrpc":"2.0","id":1,"method":"sendTransaction","params":["{}","base64"]}}"#,
+            base64::encode(&data)
         );
         let res = io.handle_request_sync(&req, meta.clone());
-        let expected = format!(r#"{{"jsonrpc":"2.0","result":20,"id":1}}"#);
-        let expected: Response =
-            serde_json::from_str(&expected).expect("expected response deserialization");
-
-        let result: Response = serde_json::from_str(&res.expect("actual response"))
+        let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
             .expect("actual response deserialization");
-        assert_eq!(expected, result);
+        assert_eq!(res["result"], bs58::encode(tx.signature).into_string());
+        let mut buf = [0u8; PACKET_DATA_SIZE];
+        let (size, _) = tpu.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..size], &data[..]);
 
-        let req = format!(r#"{{"jsonrpc":"2.0","id":1,"method":"getTransactionCount"}}"#);
+        // a simulation reports how the transaction would fare and sends nothing
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"sendTransaction","params":["{}",{{"encoding":"base64","simulate":true}}]}}"#,
+            base64::encode(&data)
+        );
         let res = io.handle_request_sync(&req, meta.clone());
-        let expected = format!(r#"{{"jsonrpc":"2.0","result":1,"id":1}}"#);
-        let expected: Response =
-            serde_json::from_str(&expected).expect("expected response deserialization");
+        let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        let expected = json!({
+            "signature": bs58::encode(tx.signature).into_string(),
+            "err": null,
+            "balances": [
+                [alice.pubkey().to_string(), 9_980],
+                [to.to_string(), 20],
+            ],
+        });
+        assert_eq!(res["result"], expected);
+        let broke_tx = Transaction::new(&alice.keypair(), to, 20_000, alice.last_id());
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"sendTransaction","params":["{}",{{"simulate":true}}]}}"#,
+            bs58::encode(serialize(&broke_tx).unwrap()).into_string()
+        );
+        let res = io.handle_request_sync(&req, meta.clone());
+        let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(
+            res["result"]["err"],
+            format!("{:?}", BankError::InsufficientFunds(alice.pubkey()))
+        );
+        assert_eq!(res["result"]["balances"], json!([]));
+        tpu.set_nonblocking(true).unwrap();
+        assert!(tpu.recv_from(&mut buf).is_err());
 
-        let result: Response = serde_json::from_str(&res.expect("actual response"))
+        // a tampered transaction is turned away
+        let mut bad_tx = tx.clone();
+        bad_tx.fee += 1;
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"sendTransaction","params":["{}"]}}"#,
+            bs58::encode(serialize(&bad_tx).unwrap()).into_string()
+        );
+        let res = io.handle_request_sync(&req, meta);
+        let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
             .expect("actual response deserialization");
-        assert_eq!(expected, result);
+        assert_eq!(res["error"]["code"], -32602);
+
+        // as is every transaction when the node doesn't take any
+        let meta = Meta {
+            request_processor: JsonRpcRequestProcessor::new(bank),
+            client_ip: None,
+            api_key: None,
+        };
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"sendTransaction","params":["{}"]}}"#,
+            bs58::encode(&data).into_string()
+        );
+        let res = io.handle_request_sync(&req, meta);
+        let res: serde_json::Value = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(res["error"]["code"], -32003);
     }
-    #[test]
-    fn test_rpc_request_bad_parameter_type() {
-        let alice = Mint::new(10_000);
-        let bank = Bank::new(&alice);
 
+    #[test]
+    fn test_rpc_admin_identity_failover() {
         let mut io = MetaIoHandler::default();
-        let rpc = RpcSolImpl;
+        let rpc = RpcAdminImpl;
         io.extend_with(rpc.to_delegate());
-        let req = r#"{"jsonrpc":"2.0","id":1,"method":"confirmTransaction","params":[1234567890]}"#;
-        let meta = Meta {
-            request_processor: JsonRpcRequestProcessor::new(Arc::new(bank)),
+        let active = AdminMeta {
+            lease: Arc::new(IdentityLease::default()),
+        };
+        let standby = AdminMeta {
+            lease: Arc::new(IdentityLease::new(false)),
         };
 
-        let res = io.handle_request_sync(req, meta);
-        let expected = r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"Invalid params: invalid type: integer `1234567890`, expected a string."},"id":1}"#;
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"releaseIdentity"}"#;
+        let res = io.handle_request_sync(req, active.clone());
         let expected: Response =
-            serde_json::from_str(expected).expect("expected response deserialization");
+            serde_json::from_str(r#"{"jsonrpc":"2.0","result":1,"id":1}"#).unwrap();
+        let result: Response = serde_json::from_str(&res.expect("actual response"))
+            .expect("actual response deserialization");
+        assert_eq!(expected, result);
+        assert!(!active.lease.is_active());
+
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"acquireIdentity","params":[1]}"#;
+        io.handle_request_sync(req, standby.clone());
+        assert!(standby.lease.is_active());
 
+        // the old term can't be used twice
+        let res = io.handle_request_sync(req, active.clone());
+        let expected = r#"{"jsonrpc":"2.0","error":{"code":-32001,"message":"identity lease term is already 1"},"id":1}"#;
+        let expected: Response =
+            serde_json::from_str(expected).expect("expected response deserialization");
         let result: Response = serde_json::from_str(&res.expect("actual response"))
             .expect("actual response deserialization");
         assert_eq!(expected, result);
+        assert!(!active.lease.is_active());
     }
-    #[test]
-    fn test_rpc_request_bad_signature() {
-        let alice = Mint::new(10_000);
-        let bank = Bank::new(&alice);
 
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_rpc_admin_stage_cpu_time() {
         let mut io = MetaIoHandler::default();
-        let rpc = RpcSolImpl;
+        let rpc = RpcAdminImpl;
         io.extend_with(rpc.to_delegate());
-        let req =
-            r#"{"jsonrpc":"2.0","id":1,"method":"confirmTransaction","params":["a1b2c3d4e5"]}"#;
-        let meta = Meta {
-            request_processor: JsonRpcRequestProcessor::new(Arc::new(bank)),
+        let meta = AdminMeta {
+            lease: Arc::new(IdentityLease::default()),
         };
+        cpu_time::register_thread("rpc-test");
 
-        let res = io.handle_request_sync(req, meta);
-        let expected =
-            r#"{"jsonrpc":"2.0","error":{"code":-32600,"message":"Invalid request"},"id":1}"#;
-        let expected: Response =
-            serde_json::from_str(expected).expect("expected response deserialization");
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"getStageCpuTime"}"#;
+        let res = io.handle_request_sync(req, meta).unwrap();
+        let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+        assert!(res["result"]["rpc-test"].is_u64());
+    }
 
-        let result: Response = serde_json::from_str(&res.expect("actual response"))
-            .expect("actual response deserialization");
-        assert_eq!(expected, result);
+    #[test]
+    fn test_rpc_admin_packet_capture_invalid_peer() {
+        let mut io = MetaIoHandler::default();
+        let rpc = RpcAdminImpl;
+        io.extend_with(rpc.to_delegate());
+        let meta = AdminMeta {
+            lease: Arc::new(IdentityLease::default()),
+        };
+
+        let req = r#"{"jsonrpc":"2.0","id":1,"method":"startPacketCapture","params":["127.0.0.1:9000",["10.0.0.1:8001","not-a-peer"]]}"#;
+        let res = io.handle_request_sync(req, meta).unwrap();
+        let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+        assert_eq!(res["error"]["code"], -32602);
+        assert_eq!(res["error"]["data"]["kind"], "invalidAddress");
     }
 }
@@ -0,0 +1,624 @@
+//! The `rpc_pubsub` module implements a WebSocket endpoint where clients
+//! subscribe to an account or a signature and get a notification when the
+//! account changes or the signature lands, instead of polling `getBalance` or
+//! `confirmTransaction`.
+
+use account::Account;
+use bank::{Bank, BankError, BankObserver, Result as BankResult};
+use jsonrpc_core::futures::Future;
+use jsonrpc_core::*;
+use jsonrpc_macros::pubsub;
+use jsonrpc_pubsub::{PubSubHandler, PubSubMetadata, Session, SubscriptionId};
+use jsonrpc_ws_server::{
+    CloseHandle, Error as WsError, ErrorKind as WsErrorKind, RequestContext, ServerBuilder,
+};
+use result::{bind_error, Error as ServiceError, Result as ServiceResult};
+use service::Service;
+use signature::{Pubkey, Signature};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, Builder, JoinHandle};
+use std::time::Duration;
+use transaction::Transaction;
+
+pub const PUBSUB_PORT: u16 = 8902;
+
+/// What became of a transaction a client subscribed to by signature.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum RpcSignatureStatus {
+    Confirmed,
+    Failed,
+}
+
+/// A notification for one subscriber, queued by the bank's observer so it's
+/// sent without holding the bank's locks.
+enum Notification {
+    Account(pubsub::Sink<Account>, Account),
+    Signature(pubsub::Sink<RpcSignatureStatus>, RpcSignatureStatus),
+}
+
+impl Notification {
+    fn send(self) {
+        // the subscriber may have hung up, which the session cleans up after
+        let _ = match self {
+            Notification::Account(sink, account) => sink.notify(Ok(account)).wait(),
+            Notification::Signature(sink, status) => sink.notify(Ok(status)).wait(),
+        };
+    }
+}
+
+type SignatureSubscribers = HashMap<SubscriptionId, pubsub::Sink<RpcSignatureStatus>>;
+
+/// The subscribers to an account, and the account as they were last told about
+/// it.
+struct AccountSubscribers {
+    last: Option<Account>,
+    sinks: HashMap<SubscriptionId, pubsub::Sink<Account>>,
+}
+
+/// Every open subscription. Registered with the bank, it checks each batch of
+/// transactions against the subscriptions and queues the notifications due.
+pub struct Subscriptions {
+    accounts: RwLock<HashMap<Pubkey, AccountSubscribers>>,
+    signatures: RwLock<HashMap<Signature, SignatureSubscribers>>,
+    notification_sender: Mutex<Sender<Notification>>,
+}
+
+impl Subscriptions {
+    fn new(notification_sender: Sender<Notification>) -> Self {
+        Subscriptions {
+            accounts: RwLock::new(HashMap::new()),
+            signatures: RwLock::new(HashMap::new()),
+            notification_sender: Mutex::new(notification_sender),
+        }
+    }
+
+    fn add_account(
+        &self,
+        pubkey: Pubkey,
+        account: Option<Account>,
+        id: SubscriptionId,
+        sink: pubsub::Sink<Account>,
+    ) {
+        let mut accounts = self.accounts.write().unwrap();
+        accounts
+            .entry(pubkey)
+            .or_insert_with(|| AccountSubscribers {
+                last: account,
+                sinks: HashMap::new(),
+            })
+            .sinks
+            .insert(id, sink);
+    }
+
+    fn remove_account(&self, id: &SubscriptionId) -> bool {
+        let mut accounts = self.accounts.write().unwrap();
+        let pubkey = accounts
+            .iter()
+            .find(|(_, subscribers)| subscribers.sinks.contains_key(id))
+            .map(|(pubkey, _)| *pubkey);
+        match pubkey {
+            Some(pubkey) => {
+                let no_subscribers_left = {
+                    let subscribers = accounts.get_mut(&pubkey).unwrap();
+                    subscribers.sinks.remove(id);
+                    subscribers.sinks.is_empty()
+                };
+                if no_subscribers_left {
+                    accounts.remove(&pubkey);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn add_signature(
+        &self,
+        signature: Signature,
+        id: SubscriptionId,
+        sink: pubsub::Sink<RpcSignatureStatus>,
+    ) {
+        let mut signatures = self.signatures.write().unwrap();
+        signatures
+            .entry(signature)
+            .or_insert_with(HashMap::new)
+            .insert(id, sink);
+    }
+
+    fn remove_signature(&self, id: &SubscriptionId) -> bool {
+        let mut signatures = self.signatures.write().unwrap();
+        let signature = signatures
+            .iter()
+            .find(|(_, sinks)| sinks.contains_key(id))
+            .map(|(signature, _)| *signature);
+        match signature {
+            Some(signature) => {
+                let no_subscribers_left = {
+                    let sinks = signatures.get_mut(&signature).unwrap();
+                    sinks.remove(id);
+                    sinks.is_empty()
+                };
+                if no_subscribers_left {
+                    signatures.remove(&signature);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Queue a notification of `status` for every subscriber to `signature`,
+    /// and drop their subscriptions, as a signature only lands once.
+    fn signature_landed(&self, signature: &Signature, status: RpcSignatureStatus) {
+        let sinks = self.signatures.write().unwrap().remove(signature);
+        if let Some(sinks) = sinks {
+            let notification_sender = self.notification_sender.lock().unwrap();
+            for sink in sinks.into_iter().map(|(_, sink)| sink) {
+                let _ = notification_sender.send(Notification::Signature(sink, status));
+            }
+        }
+    }
+}
+
+impl BankObserver for Subscriptions {
+    fn transactions_processed(
+        &self,
+        txs: &[Transaction],
+        results: &[BankResult<()>],
+        accounts: &Fn(&Pubkey) -> Option<Account>,
+    ) {
+        for (tx, result) in txs.iter().zip(results) {
+            let status = match result {
+                Ok(_) => RpcSignatureStatus::Confirmed,
+                // the signature already landed, or was never going to
+                Err(BankError::DuplicateSignature(_)) => continue,
+                Err(_) => RpcSignatureStatus::Failed,
+            };
+            if self.signatures.read().unwrap().contains_key(&tx.signature) {
+                self.signature_landed(&tx.signature, status);
+            }
+        }
+
+        // a transaction can change accounts it doesn't name, such as by
+        // completing a pending payment, so compare every subscribed account
+        let mut subscribed = self.accounts.write().unwrap();
+        let notification_sender = self.notification_sender.lock().unwrap();
+        for (pubkey, subscribers) in subscribed.iter_mut() {
+            let account = accounts(pubkey);
+            if account == subscribers.last {
+                continue;
+            }
+            subscribers.last = account.clone();
+            let account = account.unwrap_or_default();
+            for sink in subscribers.sinks.values() {
+                let notification = Notification::Account(sink.clone(), account.clone());
+                let _ = notification_sender.send(notification);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Meta {
+    pub session: Option<Arc<Session>>,
+}
+impl Metadata for Meta {}
+impl PubSubMetadata for Meta {
+    fn session(&self) -> Option<Arc<Session>> {
+        self.session.clone()
+    }
+}
+
+build_rpc_trait! {
+    pub trait RpcSolPubSub {
+        type Metadata;
+
+        #[pubsub(name = "accountNotification")] {
+            #[rpc(name = "accountSubscribe")]
+            fn account_subscribe(&self, Self::Metadata, pubsub::Subscriber<Account>, String);
+
+            #[rpc(name = "accountUnsubscribe")]
+            fn account_unsubscribe(&self, SubscriptionId) -> Result<bool>;
+        }
+
+        #[pubsub(name = "signatureNotification")] {
+            #[rpc(name = "signatureSubscribe")]
+            fn signature_subscribe(&self, Self::Metadata, pubsub::Subscriber<RpcSignatureStatus>, String);
+
+            #[rpc(name = "signatureUnsubscribe")]
+            fn signature_unsubscribe(&self, SubscriptionId) -> Result<bool>;
+        }
+    }
+}
+
+pub struct RpcSolPubSubImpl {
+    bank: Arc<Bank>,
+    subscriptions: Arc<Subscriptions>,
+    next_id: AtomicUsize,
+}
+
+impl RpcSolPubSubImpl {
+    fn new(bank: Arc<Bank>, subscriptions: Arc<Subscriptions>) -> Self {
+        RpcSolPubSubImpl {
+            bank,
+            subscriptions,
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    fn next_id(&self) -> SubscriptionId {
+        SubscriptionId::Number(self.next_id.fetch_add(1, Ordering::Relaxed) as u64)
+    }
+}
+
+impl RpcSolPubSub for RpcSolPubSubImpl {
+    type Metadata = Meta;
+
+    fn account_subscribe(
+        &self,
+        _meta: Self::Metadata,
+        subscriber: pubsub::Subscriber<Account>,
+        id: String,
+    ) {
+        let pubkey: Pubkey = match id.parse() {
+            Ok(pubkey) => pubkey,
+            Err(err) => {
+                let message = format!("invalid public key: {}", err);
+                let _ = subscriber.reject(Error::invalid_params(message));
+                return;
+            }
+        };
+        let sub_id = self.next_id();
+        if let Ok(sink) = subscriber.assign_id(sub_id.clone()) {
+            // read the account before taking the subscriptions lock, which the
+            // bank's observer takes while the bank holds its accounts lock
+            let account = self.bank.get_account(&pubkey);
+            self.subscriptions
+                .add_account(pubkey, account, sub_id, sink);
+        }
+    }
+
+    fn account_unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        if self.subscriptions.remove_account(&id) {
+            Ok(true)
+        } else {
+            Err(Error::invalid_params("no such subscription"))
+        }
+    }
+
+    fn signature_subscribe(
+        &self,
+        _meta: Self::Metadata,
+        subscriber: pubsub::Subscriber<RpcSignatureStatus>,
+        id: String,
+    ) {
+        let signature: Signature = match id.parse() {
+            Ok(signature) => signature,
+            Err(err) => {
+                let message = format!("invalid signature: {}", err);
+                let _ = subscriber.reject(Error::invalid_params(message));
+                return;
+            }
+        };
+        let sub_id = self.next_id();
+        if let Ok(sink) = subscriber.assign_id(sub_id.clone()) {
+            self.subscriptions.add_signature(signature, sub_id, sink);
+            // it may have landed, or failed, before the subscription was added
+            let status = match self.bank.get_signature_status(&signature) {
+                Some(Ok(())) => RpcSignatureStatus::Confirmed,
+                Some(Err(_)) => RpcSignatureStatus::Failed,
+                None => return,
+            };
+            self.subscriptions.signature_landed(&signature, status);
+        }
+    }
+
+    fn signature_unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        if self.subscriptions.remove_signature(&id) {
+            Ok(true)
+        } else {
+            Err(Error::invalid_params("no such subscription"))
+        }
+    }
+}
+
+/// Send queued notifications until `exit` is set.
+fn send_notifications(receiver: &Receiver<Notification>, exit: &AtomicBool) {
+    while !exit.load(Ordering::Relaxed) {
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(notification) => notification.send(),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+/// The error starting the WebSocket server on `addr` failed with, as a
+/// `StartupError::PortInUse` if the address was taken.
+fn start_error(addr: SocketAddr, err: WsError) -> ServiceError {
+    match err.into_kind() {
+        WsErrorKind::Io(err) => bind_error(addr, err),
+        kind => ServiceError::IO(io::Error::new(io::ErrorKind::Other, kind.to_string())),
+    }
+}
+
+pub struct PubSubService {
+    thread_hdls: Vec<JoinHandle<()>>,
+    close_handle: CloseHandle,
+    exit: Arc<AtomicBool>,
+}
+
+impl PubSubService {
+    /// Serve subscriptions to the accounts and transactions of `bank` over
+    /// WebSocket on `pubsub_addr` until `close` is called.
+    pub fn new(bank: &Arc<Bank>, pubsub_addr: SocketAddr) -> ServiceResult<Self> {
+        let (notification_sender, notification_receiver) = channel();
+        let subscriptions = Arc::new(Subscriptions::new(notification_sender));
+
+        let mut io = PubSubHandler::default();
+        io.extend_with(RpcSolPubSubImpl::new(bank.clone(), subscriptions.clone()).to_delegate());
+        let server = ServerBuilder::with_meta_extractor(io, |context: &RequestContext| {
+            info!("new pubsub connection");
+            let session = Arc::new(Session::new(context.sender().clone()));
+            session.on_drop(Box::new(|| info!("pubsub connection dropped")));
+            Meta {
+                session: Some(session),
+            }
+        })
+        .start(&pubsub_addr)
+        .map_err(|err| start_error(pubsub_addr, err))?;
+        let close_handle = server.close_handle();
+        bank.add_observer(subscriptions);
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let notify_exit = exit.clone();
+        let t_notify = Builder::new()
+            .name("solana-pubsub-notify".to_string())
+            .spawn(move || send_notifications(&notification_receiver, &notify_exit))?;
+        let t_server = Builder::new()
+            .name("solana-pubsub".to_string())
+            .spawn(move || {
+                let _ = server.wait();
+            })?;
+        Ok(PubSubService {
+            thread_hdls: vec![t_notify, t_server],
+            close_handle,
+            exit,
+        })
+    }
+
+    /// Stop serving subscriptions. The service's threads finish once the
+    /// server has shut down and the queued notifications are dropped.
+    pub fn close(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+        self.close_handle.clone().close();
+    }
+}
+
+impl Service for PubSubService {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        self.thread_hdls
+    }
+
+    fn join(self) -> thread::Result<()> {
+        for thread_hdl in self.thread_hdls() {
+            thread_hdl.join()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use accounts_db::AccountsDb;
+    use jsonrpc_core::futures::sync::mpsc;
+    use jsonrpc_core::futures::Stream;
+    use mint::Mint;
+    use result::StartupError;
+    use serde_json;
+    use signature::{Keypair, KeypairUtil};
+    use std::env;
+    use std::fs;
+    use std::net::TcpListener;
+
+    /// A handler for `bank`, a session to call it with, the session's
+    /// outgoing messages, and where notifications are queued.
+    fn setup(
+        bank: &Arc<Bank>,
+    ) -> (
+        PubSubHandler<Meta>,
+        Meta,
+        mpsc::Receiver<String>,
+        Receiver<Notification>,
+    ) {
+        let (notification_sender, notification_receiver) = channel();
+        let subscriptions = Arc::new(Subscriptions::new(notification_sender));
+        bank.add_observer(subscriptions.clone());
+        let mut io = PubSubHandler::default();
+        io.extend_with(RpcSolPubSubImpl::new(bank.clone(), subscriptions).to_delegate());
+        let (sender, receiver) = mpsc::channel(8);
+        let meta = Meta {
+            session: Some(Arc::new(Session::new(sender))),
+        };
+        (io, meta, receiver, notification_receiver)
+    }
+
+    fn subscribe(io: &PubSubHandler<Meta>, meta: &Meta, method: &str, param: String) -> u64 {
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":["{}"]}}"#,
+            method, param
+        );
+        let res = io.handle_request_sync(&req, meta.clone()).unwrap();
+        let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+        res["result"].as_u64().expect("subscription id")
+    }
+
+    /// Send the queued notifications, and return the next message the session
+    /// got.
+    fn next_notification(
+        notifications: &Receiver<Notification>,
+        messages: mpsc::Receiver<String>,
+    ) -> (serde_json::Value, mpsc::Receiver<String>) {
+        for notification in notifications.try_iter() {
+            notification.send();
+        }
+        let (message, messages) = messages.into_future().wait().ok().unwrap();
+        (serde_json::from_str(&message.unwrap()).unwrap(), messages)
+    }
+
+    #[test]
+    fn test_signature_subscribe() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let (io, meta, messages, notifications) = setup(&bank);
+        let tx = Transaction::new(
+            &alice.keypair(),
+            Keypair::new().pubkey(),
+            20,
+            alice.last_id(),
+        );
+        let sub_id = subscribe(
+            &io,
+            &meta,
+            "signatureSubscribe",
+            bs58::encode(tx.signature).into_string(),
+        );
+
+        bank.process_transaction(&tx).unwrap();
+        let (notification, _) = next_notification(&notifications, messages);
+        assert_eq!(notification["method"], "signatureNotification");
+        assert_eq!(notification["params"]["subscription"], sub_id);
+        assert_eq!(notification["params"]["result"], "Confirmed");
+
+        // the subscription is gone once the signature lands
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"signatureUnsubscribe","params":[{}]}}"#,
+            sub_id
+        );
+        let res = io.handle_request_sync(&req, meta).unwrap();
+        let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+        assert!(res["error"].is_object());
+    }
+
+    #[test]
+    fn test_account_subscribe() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let (io, meta, messages, notifications) = setup(&bank);
+        let bob_pubkey = Keypair::new().pubkey();
+        let sub_id = subscribe(&io, &meta, "accountSubscribe", bob_pubkey.to_string());
+
+        // a transaction that doesn't touch the account isn't reported
+        let tx = Transaction::new(
+            &alice.keypair(),
+            Keypair::new().pubkey(),
+            10,
+            alice.last_id(),
+        );
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(notifications.try_iter().count(), 0);
+
+        bank.transfer(20, &alice.keypair(), bob_pubkey, alice.last_id())
+            .unwrap();
+        let (notification, _) = next_notification(&notifications, messages);
+        assert_eq!(notification["method"], "accountNotification");
+        assert_eq!(notification["params"]["subscription"], sub_id);
+        assert_eq!(notification["params"]["result"]["tokens"], 20);
+
+        let req = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"accountUnsubscribe","params":[{}]}}"#,
+            sub_id
+        );
+        let res = io.handle_request_sync(&req, meta).unwrap();
+        let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+        assert_eq!(res["result"], true);
+    }
+
+    #[test]
+    fn test_account_subscribe_across_save() {
+        let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
+        let path = format!("{}/tmp-pubsub-{}", out_dir, Keypair::new().pubkey());
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        bank.use_accounts_db(AccountsDb::open(&path).unwrap())
+            .unwrap();
+        let (io, meta, messages, notifications) = setup(&bank);
+        let bob_pubkey = Keypair::new().pubkey();
+        subscribe(&io, &meta, "accountSubscribe", bob_pubkey.to_string());
+
+        bank.transfer(20, &alice.keypair(), bob_pubkey, alice.last_id())
+            .unwrap();
+        let (notification, messages) = next_notification(&notifications, messages);
+        assert_eq!(notification["params"]["result"]["tokens"], 20);
+
+        // saving moves the account out of memory, which isn't a change
+        bank.save_accounts(1, &alice.last_id()).unwrap();
+        let tx = Transaction::new(
+            &alice.keypair(),
+            Keypair::new().pubkey(),
+            10,
+            alice.last_id(),
+        );
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(notifications.try_iter().count(), 0);
+
+        bank.transfer(5, &alice.keypair(), bob_pubkey, alice.last_id())
+            .unwrap();
+        let (notification, _) = next_notification(&notifications, messages);
+        assert_eq!(notification["params"]["result"]["tokens"], 25);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_signature_subscribe_after_failure() {
+        let alice = Mint::new(10);
+        let bank = Arc::new(Bank::new(&alice));
+        let (io, meta, messages, notifications) = setup(&bank);
+        let tx = Transaction::new(
+            &alice.keypair(),
+            Keypair::new().pubkey(),
+            20,
+            alice.last_id(),
+        );
+        assert!(bank.process_transaction(&tx).is_err());
+
+        let sub_id = subscribe(
+            &io,
+            &meta,
+            "signatureSubscribe",
+            bs58::encode(tx.signature).into_string(),
+        );
+        let (notification, _) = next_notification(&notifications, messages);
+        assert_eq!(notification["params"]["subscription"], sub_id);
+        assert_eq!(notification["params"]["result"], "Failed");
+    }
+
+    #[test]
+    fn test_pubsub_service_close() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let pubsub_addr = "127.0.0.1:0".parse().unwrap();
+        let pubsub_service = PubSubService::new(&bank, pubsub_addr).unwrap();
+        pubsub_service.close();
+        pubsub_service.join().unwrap();
+    }
+
+    #[test]
+    fn test_pubsub_service_port_in_use() {
+        let alice = Mint::new(10_000);
+        let bank = Arc::new(Bank::new(&alice));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pubsub_addr = listener.local_addr().unwrap();
+        let result = PubSubService::new(&bank, pubsub_addr);
+        assert_matches!(
+            result.err(),
+            Some(ServiceError::StartupError(StartupError::PortInUse(addr))) if addr == pubsub_addr
+        );
+    }
+}
@@ -0,0 +1,392 @@
+//! The `rpc_tls` module terminates TLS for the RPC server. The HTTP server
+//! can't listen for TLS itself, so `TlsProxy` takes connections on the RPC
+//! port, decrypts them, and forwards them to the HTTP server on a loopback
+//! port. `ClientAddrs` maps each forwarded connection back to the client it
+//! came from, so that limits per client IP still apply. Each connection gets a
+//! thread, so the proxy caps how many are open and drops those that stall.
+
+use counter::Counter;
+use log::Level;
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{NoClientAuth, ServerConfig, ServerSession, Session};
+use service::Service;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, sleep, Builder, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long a connection waits on one side before checking the other.
+const POLL_MS: u64 = 10;
+
+/// The most connections the proxy keeps open, new ones are refused past it.
+pub const MAX_TLS_CONNECTIONS: usize = 256;
+
+/// How long a client has to finish the TLS handshake.
+const HANDSHAKE_TIMEOUT_MS: u64 = 10_000;
+
+/// How long a connection may go without traffic either way before it's closed.
+const IDLE_TIMEOUT_MS: u64 = 60_000;
+
+/// The certificate chain and private key, both PEM encoded, that the RPC
+/// server proves its identity with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RpcTlsIdentity {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+impl RpcTlsIdentity {
+    /// Read the certificate chain and key into a TLS server configuration.
+    /// The key may be PKCS#8 or PKCS#1 RSA.
+    pub fn load(&self) -> io::Result<Arc<ServerConfig>> {
+        let invalid = |what: &str, path: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no {} in {}", what, path),
+            )
+        };
+        let mut reader = BufReader::new(File::open(&self.cert_path)?);
+        let cert_chain =
+            certs(&mut reader).map_err(|_| invalid("certificates", &self.cert_path))?;
+        if cert_chain.is_empty() {
+            return Err(invalid("certificates", &self.cert_path));
+        }
+
+        let mut reader = BufReader::new(File::open(&self.key_path)?);
+        let mut keys =
+            pkcs8_private_keys(&mut reader).map_err(|_| invalid("private key", &self.key_path))?;
+        if keys.is_empty() {
+            let mut reader = BufReader::new(File::open(&self.key_path)?);
+            keys = rsa_private_keys(&mut reader)
+                .map_err(|_| invalid("private key", &self.key_path))?;
+        }
+        if keys.is_empty() {
+            return Err(invalid("private key", &self.key_path));
+        }
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(cert_chain, keys.remove(0))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+        Ok(Arc::new(config))
+    }
+}
+
+/// The clients behind the proxy's connections to the HTTP server, by the
+/// local address of each connection.
+#[derive(Clone, Default)]
+pub struct ClientAddrs(Arc<RwLock<HashMap<SocketAddr, SocketAddr>>>);
+
+impl ClientAddrs {
+    /// The client that `addr` is forwarding for, or `addr` itself if it isn't
+    /// one of the proxy's connections.
+    pub fn resolve(&self, addr: SocketAddr) -> SocketAddr {
+        self.0.read().unwrap().get(&addr).cloned().unwrap_or(addr)
+    }
+
+    fn insert(&self, addr: SocketAddr, client: SocketAddr) {
+        self.0.write().unwrap().insert(addr, client);
+    }
+
+    fn remove(&self, addr: &SocketAddr) {
+        self.0.write().unwrap().remove(addr);
+    }
+}
+
+/// Counts the open connections against a maximum.
+#[derive(Clone)]
+struct ConnectionSlots {
+    open: Arc<AtomicUsize>,
+    max: usize,
+}
+
+/// One open connection, counted until it's dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlots {
+    fn new(max: usize) -> Self {
+        ConnectionSlots {
+            open: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// Count a new connection, or `None` if `max` are already open.
+    fn acquire(&self) -> Option<ConnectionSlot> {
+        if self.open.fetch_add(1, Ordering::Relaxed) >= self.max {
+            self.open.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(ConnectionSlot(self.open.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct TlsProxy {
+    exit: Arc<AtomicBool>,
+    thread_hdl: JoinHandle<()>,
+}
+
+impl TlsProxy {
+    /// Take TLS connections on `addr` and forward them to `http_addr`,
+    /// recording each connection's client in `clients`. At most
+    /// `MAX_TLS_CONNECTIONS` are served at once.
+    pub fn new(
+        config: Arc<ServerConfig>,
+        addr: SocketAddr,
+        http_addr: SocketAddr,
+        clients: ClientAddrs,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let exit = Arc::new(AtomicBool::new(false));
+        let thread_exit = exit.clone();
+        let slots = ConnectionSlots::new(MAX_TLS_CONNECTIONS);
+        let thread_hdl = Builder::new()
+            .name("solana-rpc-tls".to_string())
+            .spawn(move || loop {
+                if thread_exit.load(Ordering::Relaxed) {
+                    break;
+                }
+                match listener.accept() {
+                    Ok((stream, client)) => {
+                        let slot = match slots.acquire() {
+                            Some(slot) => slot,
+                            None => {
+                                inc_new_counter_info!("rpc_tls-connection_refused", 1);
+                                debug!("rpc tls refused {}, too many connections", client);
+                                continue;
+                            }
+                        };
+                        let config = config.clone();
+                        let clients = clients.clone();
+                        let exit = thread_exit.clone();
+                        let _ = Builder::new()
+                            .name("solana-rpc-tls-connection".to_string())
+                            .spawn(move || {
+                                let _slot = slot;
+                                if let Err(e) =
+                                    serve(&config, stream, client, http_addr, &clients, &exit)
+                                {
+                                    inc_new_counter_info!("rpc_tls-connection_error", 1);
+                                    debug!("rpc tls connection from {}: {:?}", client, e);
+                                }
+                            });
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        sleep(Duration::from_millis(100));
+                    }
+                    Err(e) => warn!("rpc tls accept: {:?}", e),
+                }
+            })?;
+        Ok(TlsProxy { exit, thread_hdl })
+    }
+
+    /// Stop taking connections and drop the open ones.
+    pub fn close(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Service for TlsProxy {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        vec![self.thread_hdl]
+    }
+
+    fn join(self) -> thread::Result<()> {
+        self.thread_hdl.join()
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+/// Forward one client connection to the HTTP server until either side closes.
+fn serve(
+    config: &Arc<ServerConfig>,
+    mut stream: TcpStream,
+    client: SocketAddr,
+    http_addr: SocketAddr,
+    clients: &ClientAddrs,
+    exit: &Arc<AtomicBool>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut http = TcpStream::connect(http_addr)?;
+    let local_addr = http.local_addr()?;
+    clients.insert(local_addr, client);
+    let result = forward(
+        &mut ServerSession::new(config),
+        &mut stream,
+        &mut http,
+        exit,
+        Duration::from_millis(HANDSHAKE_TIMEOUT_MS),
+        Duration::from_millis(IDLE_TIMEOUT_MS),
+    );
+    clients.remove(&local_addr);
+    result
+}
+
+/// Pass traffic between the client and the HTTP server until either closes,
+/// the handshake takes longer than `handshake_timeout`, or neither side sends
+/// anything for `idle_timeout`.
+fn forward(
+    session: &mut ServerSession,
+    stream: &mut TcpStream,
+    http: &mut TcpStream,
+    exit: &Arc<AtomicBool>,
+    handshake_timeout: Duration,
+    idle_timeout: Duration,
+) -> io::Result<()> {
+    let timeout = Some(Duration::from_millis(POLL_MS));
+    stream.set_read_timeout(timeout)?;
+    http.set_read_timeout(timeout)?;
+    let mut buf = [0u8; 16 * 1024];
+    let start = Instant::now();
+    let mut last_traffic = start;
+    while !exit.load(Ordering::Relaxed) {
+        if session.is_handshaking() && start.elapsed() > handshake_timeout {
+            inc_new_counter_info!("rpc_tls-handshake_timeout", 1);
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "tls handshake timed out",
+            ));
+        }
+        if last_traffic.elapsed() > idle_timeout {
+            inc_new_counter_info!("rpc_tls-idle_timeout", 1);
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "connection idle"));
+        }
+
+        match session.read_tls(stream) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {
+                last_traffic = Instant::now();
+                session
+                    .process_new_packets()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+                loop {
+                    let len = session.read(&mut buf)?;
+                    if len == 0 {
+                        break;
+                    }
+                    http.write_all(&buf[..len])?;
+                }
+            }
+            Err(ref e) if is_timeout(e) => (),
+            Err(e) => return Err(e),
+        }
+
+        if !session.is_handshaking() {
+            match http.read(&mut buf) {
+                Ok(0) => {
+                    session.send_close_notify();
+                    while session.wants_write() {
+                        session.write_tls(stream)?;
+                    }
+                    return Ok(());
+                }
+                Ok(len) => {
+                    last_traffic = Instant::now();
+                    session.write_all(&buf[..len])?;
+                }
+                Err(ref e) if is_timeout(e) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        while session.wants_write() {
+            session.write_tls(stream)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_client_addrs() {
+        let clients = ClientAddrs::default();
+        let local: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let client: SocketAddr = "10.0.0.1:6000".parse().unwrap();
+        assert_eq!(clients.resolve(local), local);
+
+        clients.insert(local, client);
+        assert_eq!(clients.resolve(local), client);
+        assert_eq!(clients.clone().resolve(local), client);
+
+        clients.remove(&local);
+        assert_eq!(clients.resolve(local), local);
+    }
+
+    #[test]
+    fn test_connection_slots() {
+        let slots = ConnectionSlots::new(2);
+        let first = slots.acquire().unwrap();
+        let second = slots.clone().acquire().unwrap();
+        assert!(slots.acquire().is_none());
+
+        drop(first);
+        let third = slots.acquire().unwrap();
+        assert!(slots.acquire().is_none());
+        drop(second);
+        drop(third);
+        assert_eq!(slots.open.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_forward_handshake_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let http_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut http = TcpStream::connect(http_listener.local_addr().unwrap()).unwrap();
+
+        // the client connects but never says hello
+        let config = Arc::new(ServerConfig::new(NoClientAuth::new()));
+        let exit = Arc::new(AtomicBool::new(false));
+        let err = forward(
+            &mut ServerSession::new(&config),
+            &mut stream,
+            &mut http,
+            &exit,
+            Duration::from_millis(50),
+            Duration::from_secs(60),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_load_invalid_identity() {
+        let identity = RpcTlsIdentity {
+            cert_path: "/nonexistent/rpc-tls.pem".to_string(),
+            key_path: "/nonexistent/rpc-tls.key".to_string(),
+        };
+        assert!(identity.load().is_err());
+
+        let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
+        let path = format!("{}/tmp-rpc-tls-not-pem", out_dir);
+        fs::create_dir_all(&out_dir).unwrap();
+        fs::write(&path, "not a certificate").unwrap();
+        let identity = RpcTlsIdentity {
+            cert_path: path.clone(),
+            key_path: path.clone(),
+        };
+        let err = identity.load().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
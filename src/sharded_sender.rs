@@ -0,0 +1,164 @@
+//! The `sharded_sender` module spreads sending blobs over several sockets, each
+//! driven by its own thread, so that a leader's egress isn't capped by how fast
+//! a single thread can push packets through a single socket.
+
+use packet::{SharedBlob, BLOB_SIZE};
+use signature::Pubkey;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{Builder, JoinHandle};
+
+/// A blob, the address to send it to, and the id of the peer at that address.
+pub type SendOrder = (SharedBlob, SocketAddr, Pubkey);
+
+/// The orders for one shard, each with its position in the batch, and where to
+/// report how each send went.
+type ShardBatch = (
+    Vec<(usize, SharedBlob, SocketAddr)>,
+    Sender<Vec<(usize, io::Result<usize>)>>,
+);
+
+pub struct ShardedSender {
+    shards: Vec<Sender<ShardBatch>>,
+}
+
+impl ShardedSender {
+    /// Send from `sock` and `num_shards - 1` more sockets bound to its address.
+    /// Returns the sender and the shards' threads, which exit once the sender
+    /// is dropped.
+    pub fn new(sock: UdpSocket, num_shards: usize) -> io::Result<(Self, Vec<JoinHandle<()>>)> {
+        let mut addr = sock.local_addr()?;
+        addr.set_port(0);
+        let mut sockets = vec![sock];
+        for _ in 1..num_shards {
+            sockets.push(UdpSocket::bind(addr)?);
+        }
+
+        let mut shards = vec![];
+        let mut thread_hdls = vec![];
+        for (i, sock) in sockets.into_iter().enumerate() {
+            let (sender, receiver) = channel::<ShardBatch>();
+            let thread_hdl = Builder::new()
+                .name(format!("solana-send-shard-{}", i))
+                .spawn(move || {
+                    for (orders, reply) in receiver.iter() {
+                        let results = orders
+                            .into_iter()
+                            .map(|(pos, blob, addr)| {
+                                let blob = blob.read().unwrap();
+                                assert!(blob.meta.size <= BLOB_SIZE);
+                                (pos, sock.send_to(&blob.data[..blob.meta.size], &addr))
+                            })
+                            .collect();
+                        let _ = reply.send(results);
+                    }
+                })
+                .unwrap();
+            shards.push(sender);
+            thread_hdls.push(thread_hdl);
+        }
+        Ok((ShardedSender { shards }, thread_hdls))
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The shard that sends to the peer with `id`. A peer always gets the same
+    /// shard, so the blobs it's sent leave in the order they were handed over.
+    pub fn shard_of(&self, id: &Pubkey) -> usize {
+        let key = id.as_ref()[..8]
+            .iter()
+            .fold(0u64, |key, byte| key << 8 | u64::from(*byte));
+        (key % self.shards.len() as u64) as usize
+    }
+
+    /// Send every blob in `orders` from the shard of the peer it's for, and
+    /// return the result of each send, in the order of `orders`.
+    pub fn send(&self, orders: Vec<SendOrder>) -> Vec<io::Result<usize>> {
+        let num_orders = orders.len();
+        let mut batches: Vec<Vec<_>> = (0..self.shards.len()).map(|_| vec![]).collect();
+        for (pos, (blob, addr, id)) in orders.into_iter().enumerate() {
+            batches[self.shard_of(&id)].push((pos, blob, addr));
+        }
+
+        let (reply_sender, reply_receiver) = channel();
+        for (shard, batch) in self.shards.iter().zip(batches) {
+            if !batch.is_empty() {
+                let _ = shard.send((batch, reply_sender.clone()));
+            }
+        }
+        drop(reply_sender);
+
+        let mut results: Vec<Option<io::Result<usize>>> = (0..num_orders).map(|_| None).collect();
+        for replies in reply_receiver.iter() {
+            for (pos, result) in replies {
+                results[pos] = Some(result);
+            }
+        }
+        results
+            .into_iter()
+            .map(|result| {
+                result.unwrap_or_else(|| {
+                    Err(io::Error::new(io::ErrorKind::Other, "send shard exited"))
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use packet::{BlobRecycler, BLOB_SIZE};
+    use sharded_sender::ShardedSender;
+    use signature::{Keypair, KeypairUtil};
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_sharded_sender() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (sender, thread_hdls) = ShardedSender::new(sock, 4).unwrap();
+        assert_eq!(sender.num_shards(), 4);
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+        let recycler = BlobRecycler::default();
+        let ids: Vec<_> = (0..8).map(|_| Keypair::new().pubkey()).collect();
+        for id in &ids {
+            assert_eq!(sender.shard_of(id), sender.shard_of(id));
+        }
+        let orders: Vec<_> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let blob = recycler.allocate();
+                {
+                    let mut b = blob.write().unwrap();
+                    b.data[0] = i as u8;
+                    b.meta.size = 1;
+                }
+                (blob, addr, *id)
+            })
+            .collect();
+
+        let results = sender.send(orders);
+        assert_eq!(results.len(), ids.len());
+        assert!(results.iter().all(|r| *r.as_ref().unwrap() == 1));
+
+        let mut received = vec![];
+        let mut buf = [0u8; BLOB_SIZE];
+        for _ in 0..ids.len() {
+            let (size, _) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(size, 1);
+            received.push(buf[0]);
+        }
+        received.sort();
+        assert_eq!(received, (0..ids.len() as u8).collect::<Vec<_>>());
+
+        drop(sender);
+        for thread_hdl in thread_hdls {
+            thread_hdl.join().unwrap();
+        }
+    }
+}
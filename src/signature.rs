@@ -1,19 +1,30 @@
 //! The `signature` module provides functionality for public, and private keys.
 
 use bs58;
+#[cfg(target_arch = "wasm32")]
+use ed25519_dalek;
 use generic_array::typenum::{U32, U64};
 use generic_array::GenericArray;
+use hash::hash;
 use rand::{ChaChaRng, Rng, SeedableRng};
 use rayon::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
 use ring::signature::Ed25519KeyPair;
+#[cfg(not(target_arch = "wasm32"))]
 use ring::{rand, signature};
 use serde_json;
 use std::error;
 use std::fmt;
 use std::fs::File;
+use std::mem;
+use std::str::FromStr;
+#[cfg(not(target_arch = "wasm32"))]
 use untrusted::Input;
 
+#[cfg(not(target_arch = "wasm32"))]
 pub type Keypair = Ed25519KeyPair;
+#[cfg(target_arch = "wasm32")]
+pub type Keypair = ed25519_dalek::Keypair;
 #[derive(Serialize, Deserialize, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Pubkey(GenericArray<u8, U32>);
 
@@ -21,6 +32,16 @@ impl Pubkey {
     pub fn new(pubkey_vec: &[u8]) -> Self {
         Pubkey(GenericArray::clone_from_slice(&pubkey_vec))
     }
+
+    /// Derive an address from `base` that only the holder of `base`'s key can
+    /// spend from. Different seeds give an unbounded number of addresses for one
+    /// key, and `owner` keeps the addresses of different services apart.
+    pub fn create_with_seed(base: &Pubkey, seed: &str, owner: &Pubkey) -> Self {
+        let mut data = base.as_ref().to_vec();
+        data.extend_from_slice(seed.as_bytes());
+        data.extend_from_slice(owner.as_ref());
+        Pubkey::new(hash(&data).as_ref())
+    }
 }
 
 impl AsRef<[u8]> for Pubkey {
@@ -48,12 +69,27 @@ impl Signature {
     pub fn new(signature_slice: &[u8]) -> Self {
         Signature(GenericArray::clone_from_slice(&signature_slice))
     }
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn verify(&self, pubkey_bytes: &[u8], message_bytes: &[u8]) -> bool {
         let pubkey = Input::from(pubkey_bytes);
         let message = Input::from(message_bytes);
         let signature = Input::from(self.0.as_slice());
         signature::verify(&signature::ED25519, pubkey, message, signature).is_ok()
     }
+    #[cfg(target_arch = "wasm32")]
+    pub fn verify(&self, pubkey_bytes: &[u8], message_bytes: &[u8]) -> bool {
+        use ed25519_dalek::Verifier;
+        use std::convert::TryFrom;
+        let pubkey = match ed25519_dalek::PublicKey::from_bytes(pubkey_bytes) {
+            Ok(pubkey) => pubkey,
+            Err(_) => return false,
+        };
+        let signature = match ed25519_dalek::Signature::try_from(self.0.as_slice()) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        pubkey.verify(message_bytes, &signature).is_ok()
+    }
 }
 
 impl AsRef<[u8]> for Signature {
@@ -74,11 +110,123 @@ impl fmt::Display for Signature {
     }
 }
 
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Why a base58 string isn't a `Pubkey`, or a `Signature` or `Hash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParsePubkeyError {
+    Empty,
+    /// `ch` at character `index` isn't base58. `suggestion` is the character it
+    /// was most likely mistyped for.
+    InvalidChar {
+        ch: char,
+        index: usize,
+        suggestion: Option<char>,
+    },
+    /// The string decodes to `len` bytes rather than `expected`.
+    WrongSize {
+        len: usize,
+        expected: usize,
+    },
+}
+
+impl fmt::Display for ParsePubkeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParsePubkeyError::Empty => write!(f, "is empty"),
+            ParsePubkeyError::InvalidChar {
+                ch,
+                index,
+                suggestion: Some(suggestion),
+            } => write!(
+                f,
+                "'{}' at position {} isn't base58; did you mean '{}'?",
+                ch, index, suggestion
+            ),
+            ParsePubkeyError::InvalidChar { ch, index, .. } if ch.is_whitespace() => {
+                write!(f, "has whitespace at position {}; remove it", index)
+            }
+            ParsePubkeyError::InvalidChar { ch, index, .. } => {
+                write!(f, "'{}' at position {} isn't base58", ch, index)
+            }
+            ParsePubkeyError::WrongSize { len, expected } if len < expected => write!(
+                f,
+                "is {} bytes, not {}; check it wasn't cut short when copied",
+                len, expected
+            ),
+            ParsePubkeyError::WrongSize { len, expected } => write!(
+                f,
+                "is {} bytes, not {}; check it has no extra characters",
+                len, expected
+            ),
+        }
+    }
+}
+
+impl error::Error for ParsePubkeyError {
+    fn description(&self) -> &str {
+        "invalid base58 key"
+    }
+}
+
+/// The character a user most likely meant by `ch`, which base58 leaves out
+/// because it's easily confused with another.
+fn base58_suggestion(ch: char) -> Option<char> {
+    match ch {
+        '0' | 'O' => Some('o'),
+        'I' | 'l' => Some('1'),
+        _ => None,
+    }
+}
+
+/// Decode base58 `s` into exactly `expected` bytes.
+pub fn decode_base58(s: &str, expected: usize) -> Result<Vec<u8>, ParsePubkeyError> {
+    if s.is_empty() {
+        return Err(ParsePubkeyError::Empty);
+    }
+    if let Some((index, ch)) = s
+        .chars()
+        .enumerate()
+        .find(|&(_, ch)| !BASE58_ALPHABET.contains(ch))
+    {
+        let suggestion = base58_suggestion(ch);
+        return Err(ParsePubkeyError::InvalidChar {
+            ch,
+            index,
+            suggestion,
+        });
+    }
+    let bytes = bs58::decode(s).into_vec().unwrap_or_default();
+    if bytes.len() != expected {
+        let len = bytes.len();
+        return Err(ParsePubkeyError::WrongSize { len, expected });
+    }
+    Ok(bytes)
+}
+
+impl FromStr for Pubkey {
+    type Err = ParsePubkeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        decode_base58(s, mem::size_of::<Pubkey>()).map(|bytes| Pubkey::new(&bytes))
+    }
+}
+
+impl FromStr for Signature {
+    type Err = ParsePubkeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        decode_base58(s, mem::size_of::<Signature>()).map(|bytes| Signature::new(&bytes))
+    }
+}
+
 pub trait KeypairUtil {
     fn new() -> Self;
     fn pubkey(&self) -> Pubkey;
+    fn sign_message(&self, message: &[u8]) -> Signature;
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl KeypairUtil for Ed25519KeyPair {
     /// Return a new ED25519 keypair
     fn new() -> Self {
@@ -91,6 +239,49 @@ impl KeypairUtil for Ed25519KeyPair {
     fn pubkey(&self) -> Pubkey {
         Pubkey(GenericArray::clone_from_slice(self.public_key_bytes()))
     }
+
+    /// Return the signature of `message` by this keypair
+    fn sign_message(&self, message: &[u8]) -> Signature {
+        Signature::new(self.sign(message).as_ref())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl KeypairUtil for ed25519_dalek::Keypair {
+    /// Return a new ED25519 keypair. The entropy comes from the browser with the
+    /// `wasm` feature, and this panics without it, use `keypair_from_seed` then.
+    fn new() -> Self {
+        let mut seed = [0u8; 32];
+        ::rand::rngs::OsRng::new()
+            .expect("OsRng::new")
+            .fill(&mut seed);
+        keypair_from_seed(&seed)
+    }
+
+    /// Return the public key for the given keypair
+    fn pubkey(&self) -> Pubkey {
+        Pubkey(GenericArray::clone_from_slice(self.public.as_bytes()))
+    }
+
+    /// Return the signature of `message` by this keypair
+    fn sign_message(&self, message: &[u8]) -> Signature {
+        use ed25519_dalek::Signer;
+        Signature::new(&self.sign(message).to_bytes())
+    }
+}
+
+/// Return the keypair derived from the 32 byte `seed`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn keypair_from_seed(seed: &[u8; 32]) -> Keypair {
+    Keypair::from_seed_unchecked(Input::from(seed)).expect("from_seed_unchecked")
+}
+
+/// Return the keypair derived from the 32 byte `seed`.
+#[cfg(target_arch = "wasm32")]
+pub fn keypair_from_seed(seed: &[u8; 32]) -> Keypair {
+    let secret = ed25519_dalek::SecretKey::from_bytes(seed).expect("SecretKey::from_bytes");
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    Keypair { secret, public }
 }
 
 pub struct GenKeys {
@@ -116,7 +307,7 @@ impl GenKeys {
     pub fn gen_n_keypairs(&mut self, n: i64) -> Vec<Keypair> {
         self.gen_n_seeds(n)
             .into_par_iter()
-            .map(|seed| Keypair::from_seed_unchecked(Input::from(&seed)).unwrap())
+            .map(|seed| keypair_from_seed(&seed))
             .collect()
     }
 }
@@ -127,16 +318,53 @@ pub fn read_pkcs8(path: &str) -> Result<Vec<u8>, Box<error::Error>> {
     Ok(pkcs8)
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub fn read_keypair(path: &str) -> Result<Keypair, Box<error::Error>> {
     let pkcs8 = read_pkcs8(path)?;
     let keypair = Ed25519KeyPair::from_pkcs8(Input::from(&pkcs8))?;
     Ok(keypair)
 }
 
+/// Return the public key of the keypair stored at `path`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_pubkey(path: &str) -> Result<Pubkey, Box<error::Error>> {
+    Ok(read_keypair(path)?.pubkey())
+}
+
+/// Return whether the keypair stored at `path` signs for `pubkey`. The check
+/// signs a random message and verifies it against `pubkey`, rather than only
+/// comparing public keys.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn keypair_signs_for(path: &str, pubkey: &Pubkey) -> Result<bool, Box<error::Error>> {
+    let keypair = read_keypair(path)?;
+    let message: [u8; 32] = ::rand::random();
+    let signature = keypair.sign_message(&message);
+    Ok(signature.verify(pubkey.as_ref(), &message))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::collections::HashSet;
+    use std::env;
+    use std::fs::remove_file;
+    use std::io::Write;
+
+    #[test]
+    fn test_create_with_seed() {
+        let base = Keypair::new().pubkey();
+        let owner = Keypair::new().pubkey();
+        let address = Pubkey::create_with_seed(&base, "deposit", &owner);
+        assert_eq!(address, Pubkey::create_with_seed(&base, "deposit", &owner));
+        assert_ne!(address, base);
+
+        let other_seed = Pubkey::create_with_seed(&base, "other", &owner);
+        let other_base = Pubkey::create_with_seed(&owner, "deposit", &owner);
+        let other_owner = Pubkey::create_with_seed(&base, "deposit", &base);
+        for other in &[other_seed, other_base, other_owner] {
+            assert_ne!(address, *other);
+        }
+    }
 
     #[test]
     fn test_new_key_is_deterministic() {
@@ -162,4 +390,84 @@ mod tests {
         let seed = [0u8; 32];
         assert_eq!(gen_n_pubkeys(seed, 50), gen_n_pubkeys(seed, 50));
     }
+
+    #[test]
+    fn test_sign_message() {
+        let keypair = Keypair::new();
+        let signature = keypair.sign_message(b"hello");
+        assert!(signature.verify(keypair.pubkey().as_ref(), b"hello"));
+        assert!(!signature.verify(keypair.pubkey().as_ref(), b"world"));
+        assert!(!signature.verify(Keypair::new().pubkey().as_ref(), b"hello"));
+    }
+
+    #[test]
+    fn test_read_pubkey_and_keypair_signs_for() {
+        let path = format!(
+            "{}/tmp-keypair-{}.json",
+            env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string()),
+            Keypair::new().pubkey()
+        );
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rand::SystemRandom::new()).unwrap();
+        File::create(&path)
+            .unwrap()
+            .write_all(&serde_json::to_vec(&pkcs8.to_vec()).unwrap())
+            .unwrap();
+
+        let pubkey = read_keypair(&path).unwrap().pubkey();
+        assert_eq!(read_pubkey(&path).unwrap(), pubkey);
+        assert!(keypair_signs_for(&path, &pubkey).unwrap());
+        assert!(!keypair_signs_for(&path, &Keypair::new().pubkey()).unwrap());
+        assert!(read_pubkey("no-such-keypair.json").is_err());
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_keypair_from_seed() {
+        let seed = [1u8; 32];
+        assert_eq!(
+            keypair_from_seed(&seed).pubkey(),
+            keypair_from_seed(&seed).pubkey()
+        );
+    }
+
+    #[test]
+    fn test_parse_pubkey() {
+        let pubkey = Keypair::new().pubkey();
+        assert_eq!(pubkey.to_string().parse(), Ok(pubkey));
+        let signature = Keypair::new().sign_message(b"hello");
+        assert_eq!(signature.to_string().parse(), Ok(signature));
+
+        assert_eq!("".parse::<Pubkey>(), Err(ParsePubkeyError::Empty));
+        let typo = format!("{}0", &pubkey.to_string()[1..]);
+        let err = typo.parse::<Pubkey>().unwrap_err();
+        assert_eq!(
+            err,
+            ParsePubkeyError::InvalidChar {
+                ch: '0',
+                index: typo.len() - 1,
+                suggestion: Some('o'),
+            }
+        );
+        assert!(err.to_string().contains("did you mean 'o'?"));
+        let spaced = format!("{} ", pubkey);
+        let err = spaced.parse::<Pubkey>().unwrap_err();
+        assert!(err.to_string().contains("whitespace"));
+
+        let err = "abc".parse::<Pubkey>().unwrap_err();
+        assert_eq!(
+            err,
+            ParsePubkeyError::WrongSize {
+                len: 3,
+                expected: 32
+            }
+        );
+        let err = signature.to_string().parse::<Pubkey>().unwrap_err();
+        assert_eq!(
+            err,
+            ParsePubkeyError::WrongSize {
+                len: 64,
+                expected: 32
+            }
+        );
+    }
 }
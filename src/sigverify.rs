@@ -3,13 +3,20 @@
 //! cores.  When `--features=cuda` is enabled, signature verification is
 //! offloaded to the GPU.
 //!
+//! A transaction whose fee is paid by a key other than its signer's carries a
+//! second signature, which is always checked on the CPU since few do.
+//!
 
 use counter::Counter;
 use log::Level;
-use packet::{Packet, SharedPackets};
+use packet::{Packet, Packets, SharedPackets, PACKET_DATA_SIZE};
+use signature::{Pubkey, Signature};
 use std::mem::size_of;
 use std::sync::atomic::AtomicUsize;
-use transaction::{PUB_KEY_OFFSET, SIGNED_DATA_OFFSET, SIG_OFFSET};
+use std::sync::{Arc, RwLock};
+use transaction::{
+    FEE_PAYER_PUB_KEY_OFFSET, FEE_PAYER_SIG_OFFSET, PUB_KEY_OFFSET, SIGNED_DATA_OFFSET, SIG_OFFSET,
+};
 
 pub const TX_OFFSET: usize = 0;
 
@@ -42,15 +49,16 @@ pub fn init() {
     // stub
 }
 
-fn verify_packet(packet: &Packet) -> u8 {
+/// Verify the signature at `sig_offset` in `packet` with the key at
+/// `pubkey_offset`.
+fn verify_signature_at(packet: &Packet, sig_offset: usize, pubkey_offset: usize) -> u8 {
     use ring::signature;
-    use signature::{Pubkey, Signature};
     use untrusted;
 
     let msg_start = TX_OFFSET + SIGNED_DATA_OFFSET;
-    let sig_start = TX_OFFSET + SIG_OFFSET;
+    let sig_start = TX_OFFSET + sig_offset;
     let sig_end = sig_start + size_of::<Signature>();
-    let pubkey_start = TX_OFFSET + PUB_KEY_OFFSET;
+    let pubkey_start = TX_OFFSET + pubkey_offset;
     let pubkey_end = pubkey_start + size_of::<Pubkey>();
 
     if packet.meta.size <= msg_start {
@@ -66,6 +74,34 @@ fn verify_packet(packet: &Packet) -> u8 {
     ).is_ok() as u8
 }
 
+fn verify_signer(packet: &Packet) -> u8 {
+    verify_signature_at(packet, SIG_OFFSET, PUB_KEY_OFFSET)
+}
+
+/// Verify the fee payer's signature, unless the signer pays the fee.
+fn verify_fee_payer(packet: &Packet) -> u8 {
+    let pubkey_start = TX_OFFSET + PUB_KEY_OFFSET;
+    let pubkey_end = pubkey_start + size_of::<Pubkey>();
+    let fee_payer_start = TX_OFFSET + FEE_PAYER_PUB_KEY_OFFSET;
+    let fee_payer_end = fee_payer_start + size_of::<Pubkey>();
+
+    if packet.meta.size < fee_payer_end {
+        return 0;
+    }
+    if packet.data[fee_payer_start..fee_payer_end] == packet.data[pubkey_start..pubkey_end] {
+        return 1;
+    }
+    inc_new_counter_info!("sigverify-fee_payer", 1);
+    verify_signature_at(packet, FEE_PAYER_SIG_OFFSET, FEE_PAYER_PUB_KEY_OFFSET)
+}
+
+fn verify_packet(packet: &Packet) -> u8 {
+    if verify_signer(packet) == 0 {
+        return 0;
+    }
+    verify_fee_payer(packet)
+}
+
 fn verify_packet_disabled(_packet: &Packet) -> u8 {
     warn!("signature verification is disabled");
     1
@@ -83,7 +119,14 @@ pub fn ed25519_verify(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
     ed25519_verify_cpu(batches)
 }
 
-pub fn ed25519_verify_cpu(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
+/// Verify only the signers' signatures, not the fee payers'.
+#[cfg(not(feature = "cuda"))]
+fn ed25519_verify_signers(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
+    verify_batches_cpu(batches, verify_signer)
+}
+
+/// Apply `verify` to every packet of `batches` in parallel.
+fn verify_batches_cpu(batches: &[SharedPackets], verify: fn(&Packet) -> u8) -> Vec<Vec<u8>> {
     use rayon::prelude::*;
     let count = batch_size(batches);
     info!("CPU ECDSA for {}", batch_size(batches));
@@ -94,7 +137,7 @@ pub fn ed25519_verify_cpu(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
                 .expect("'p' read lock in ed25519_verify")
                 .packets
                 .par_iter()
-                .map(verify_packet)
+                .map(verify)
                 .collect()
         })
         .collect();
@@ -102,22 +145,65 @@ pub fn ed25519_verify_cpu(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
     rv
 }
 
+pub fn ed25519_verify_cpu(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
+    verify_batches_cpu(batches, verify_packet)
+}
+
 pub fn ed25519_verify_disabled(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
-    use rayon::prelude::*;
-    let count = batch_size(batches);
-    info!("CPU ECDSA for {}", batch_size(batches));
-    let rv = batches
-        .into_par_iter()
-        .map(|p| {
-            p.read()
-                .expect("'p' read lock in ed25519_verify")
-                .packets
-                .par_iter()
-                .map(verify_packet_disabled)
-                .collect()
-        })
-        .collect();
-    inc_new_counter_info!("ed25519_verify", count);
+    verify_batches_cpu(batches, verify_packet_disabled)
+}
+
+/// Pack `msg`, `signature` and `pubkey` into a packet using the transaction layout
+/// expected by `verify_packet` and the GPU verifier. Returns None if `msg` is too
+/// large to fit in a packet.
+fn packet_from_parts(msg: &[u8], signature: &Signature, pubkey: &Pubkey) -> Option<Packet> {
+    let msg_start = TX_OFFSET + SIGNED_DATA_OFFSET;
+    if msg_start + msg.len() > PACKET_DATA_SIZE {
+        return None;
+    }
+    let sig_start = TX_OFFSET + SIG_OFFSET;
+    let pubkey_start = TX_OFFSET + PUB_KEY_OFFSET;
+
+    let sig_end = sig_start + size_of::<Signature>();
+    let pubkey_end = pubkey_start + size_of::<Pubkey>();
+
+    let mut packet = Packet::default();
+    packet.data[sig_start..sig_end].copy_from_slice(signature.as_ref());
+    packet.data[pubkey_start..pubkey_end].copy_from_slice(pubkey.as_ref());
+    packet.data[msg_start..msg_start + msg.len()].copy_from_slice(msg);
+    packet.meta.size = msg_start + msg.len();
+    Some(packet)
+}
+
+/// Verify a batch of `(message, signature, pubkey)` triples and return whether each
+/// signature is valid. The batch takes the same CPU or GPU path as `ed25519_verify`,
+/// so callers outside of the TPU get the same throughput as the `SigVerifyStage`.
+/// Messages too large to fit in a packet are verified individually on the CPU.
+pub fn verify_batch(items: &[(&[u8], &Signature, &Pubkey)]) -> Vec<bool> {
+    let mut rv = vec![false; items.len()];
+    let mut packed = Vec::with_capacity(items.len());
+    let mut packets = Packets {
+        packets: Vec::with_capacity(items.len()),
+        recv_time: None,
+    };
+    for (i, &(msg, signature, pubkey)) in items.iter().enumerate() {
+        match packet_from_parts(msg, signature, pubkey) {
+            Some(packet) => {
+                packed.push(i);
+                packets.packets.push(packet);
+            }
+            None => rv[i] = signature.verify(pubkey.as_ref(), msg),
+        }
+    }
+
+    if !packed.is_empty() {
+        // the packets hold bare messages, with no fee payer to check
+        let batches = vec![Arc::new(RwLock::new(packets))];
+        let verified = ed25519_verify_signers(&batches);
+        for (i, v) in packed.into_iter().zip(&verified[0]) {
+            rv[i] = *v != 0;
+        }
+    }
     rv
 }
 
@@ -134,6 +220,23 @@ pub fn init() {
 
 #[cfg(feature = "cuda")]
 pub fn ed25519_verify(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
+    let mut rvs = ed25519_verify_signers(batches);
+    for (packets, rv) in batches.iter().zip(rvs.iter_mut()) {
+        let packets = packets
+            .read()
+            .expect("'packets' read lock in pub fn ed25519_verify");
+        for (packet, v) in packets.packets.iter().zip(rv.iter_mut()) {
+            if *v != 0 {
+                *v = verify_fee_payer(packet);
+            }
+        }
+    }
+    rvs
+}
+
+/// Verify only the signers' signatures, not the fee payers'.
+#[cfg(feature = "cuda")]
+fn ed25519_verify_signers(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
     use packet::PACKET_DATA_SIZE;
     let count = batch_size(batches);
 
@@ -143,7 +246,7 @@ pub fn ed25519_verify(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
     // may be busy doing other things while being a real fullnode
     // TODO: dynamically adjust this crossover
     if count < 64 {
-        return ed25519_verify_cpu(batches);
+        return verify_batches_cpu(batches, verify_signer);
     }
 
     info!("CUDA ECDSA for {}", batch_size(batches));
@@ -211,7 +314,9 @@ pub fn ed25519_verify(batches: &[SharedPackets]) -> Vec<Vec<u8>> {
 #[cfg(test)]
 mod tests {
     use bincode::serialize;
-    use packet::{Packet, Packets, SharedPackets};
+    use hash::Hash;
+    use packet::{Packet, Packets, SharedPackets, PACKET_DATA_SIZE};
+    use signature::{Keypair, KeypairUtil};
     use sigverify;
     use std::sync::RwLock;
     use transaction::Transaction;
@@ -279,4 +384,53 @@ mod tests {
     fn test_verify_fail() {
         test_verify_n(5, true);
     }
+
+    #[test]
+    fn test_verify_fee_payer() {
+        let keypair = Keypair::new();
+        let fee_payer = Keypair::new();
+        let to = Keypair::new().pubkey();
+        let tx = Transaction::new_with_fee_payer(&keypair, &fee_payer, to, 42, 1, Hash::default());
+        let mut unsigned_tx = tx.clone();
+        unsigned_tx.fee_payer_signature = tx.signature;
+
+        let mut packets = Packets::default();
+        packets.packets = vec![
+            make_packet_from_transaction(tx),
+            make_packet_from_transaction(unsigned_tx),
+        ];
+        let batches = vec![SharedPackets::new(RwLock::new(packets))];
+        assert_eq!(sigverify::ed25519_verify(&batches), vec![vec![1, 0]]);
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let keypair = Keypair::new();
+        let pubkey = keypair.pubkey();
+        let short_msg = vec![1u8; 32];
+        let long_msg = vec![2u8; PACKET_DATA_SIZE];
+        let short_sig = keypair.sign_message(&short_msg);
+        let long_sig = keypair.sign_message(&long_msg);
+        let bad_msg = vec![3u8; 32];
+
+        let items = vec![
+            (&short_msg[..], &short_sig, &pubkey),
+            (&long_msg[..], &long_sig, &pubkey),
+            (&bad_msg[..], &short_sig, &pubkey),
+            (&long_msg[..], &short_sig, &pubkey),
+        ];
+        assert_eq!(
+            sigverify::verify_batch(&items),
+            vec![true, true, false, false]
+        );
+        assert!(sigverify::verify_batch(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_verify_batch_transaction() {
+        let tx = test_tx();
+        let sign_data = tx.get_sign_data();
+        let items = vec![(&sign_data[..], &tx.signature, &tx.from); 71];
+        assert_eq!(sigverify::verify_batch(&items), vec![true; 71]);
+    }
 }
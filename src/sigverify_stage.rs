@@ -5,22 +5,51 @@
 //! transaction. All processing is done on the CPU by default and on a GPU
 //! if the `cuda` feature is enabled with `--features=cuda`.
 
+use cpu_time;
 use influx_db_client as influxdb;
+use latency::StageLatency;
 use metrics;
 use packet::SharedPackets;
 use rand::{thread_rng, Rng};
 use result::{Error, Result};
 use service::Service;
 use sigverify;
-use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::cmp;
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, spawn, JoinHandle};
 use std::time::Instant;
-use streamer::{self, PacketReceiver};
+use streamer::{self, PacketReceiver, MAX_BATCH_PACKETS};
+use sys_info;
 use timing;
 
 pub type VerifiedPackets = Vec<(SharedPackets, Vec<u8>)>;
 
+/// The thread layout of the stage.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SigVerifyConfig {
+    /// The number of threads verifying batches.
+    pub workers: usize,
+    /// The most packets a worker verifies in one batch.
+    pub batch_size: usize,
+    /// The most verified batches waiting for the banking stage. Workers wait
+    /// once it's reached, instead of verifying packets the bank can't keep up with.
+    pub max_in_flight: usize,
+}
+
+impl Default for SigVerifyConfig {
+    /// Half the cores verify, leaving the rest to the other stages.
+    fn default() -> Self {
+        let cores = sys_info::cpu_num().unwrap_or(8) as usize;
+        let workers = cmp::max(cores / 2, 1);
+        SigVerifyConfig {
+            workers,
+            batch_size: MAX_BATCH_PACKETS,
+            max_in_flight: workers * 2,
+        }
+    }
+}
+
 pub struct SigVerifyStage {
     thread_hdls: Vec<JoinHandle<()>>,
 }
@@ -28,12 +57,15 @@ pub struct SigVerifyStage {
 impl SigVerifyStage {
     pub fn new(
         packet_receiver: Receiver<SharedPackets>,
+        config: Option<SigVerifyConfig>,
         sigverify_disabled: bool,
     ) -> (Self, Receiver<VerifiedPackets>) {
         sigverify::init();
-        let (verified_sender, verified_receiver) = channel();
+        let config = config.unwrap_or_default();
+        info!("sigverify stage: {:?}", config);
+        let (verified_sender, verified_receiver) = sync_channel(config.max_in_flight);
         let thread_hdls =
-            Self::verifier_services(packet_receiver, verified_sender, sigverify_disabled);
+            Self::verifier_services(packet_receiver, verified_sender, config, sigverify_disabled);
         (SigVerifyStage { thread_hdls }, verified_receiver)
     }
 
@@ -48,12 +80,19 @@ impl SigVerifyStage {
 
     fn verifier(
         recvr: &Arc<Mutex<PacketReceiver>>,
-        sendr: &Arc<Mutex<Sender<VerifiedPackets>>>,
+        sendr: &Arc<Mutex<SyncSender<VerifiedPackets>>>,
+        batch_size: usize,
         sigverify_disabled: bool,
+        latency: &mut StageLatency,
     ) -> Result<()> {
-        let (batch, len) =
-            streamer::recv_batch(&recvr.lock().expect("'recvr' lock in fn verifier"))?;
+        let (batch, len) = streamer::recv_batch(
+            &recvr.lock().expect("'recvr' lock in fn verifier"),
+            batch_size,
+        )?;
 
+        for packets in &batch {
+            latency.record_since_recv(packets.read().unwrap().recv_time);
+        }
         let now = Instant::now();
         let batch_len = batch.len();
         let rand_id = thread_rng().gen_range(0, 100);
@@ -65,10 +104,15 @@ impl SigVerifyStage {
         );
 
         let verified_batch = Self::verify_batch(batch, sigverify_disabled);
+        latency.process.record(&now.elapsed());
+        let verify_time_us = timing::duration_as_us(&now.elapsed());
+        // blocks while `max_in_flight` batches are waiting for the banking stage
+        let send_start = Instant::now();
         sendr
             .lock()
             .expect("lock in fn verify_batch in tpu")
             .send(verified_batch)?;
+        let send_wait_us = timing::duration_as_us(&send_start.elapsed());
 
         let total_time_ms = timing::duration_as_ms(&now.elapsed());
         let total_time_s = timing::duration_as_s(&now.elapsed());
@@ -90,6 +134,14 @@ impl SigVerifyStage {
                     "total_time_ms",
                     influxdb::Value::Integer(total_time_ms as i64),
                 )
+                .add_field(
+                    "verify_time_us",
+                    influxdb::Value::Integer(verify_time_us as i64),
+                )
+                .add_field(
+                    "send_wait_us",
+                    influxdb::Value::Integer(send_wait_us as i64),
+                )
                 .to_owned(),
         );
 
@@ -98,15 +150,26 @@ impl SigVerifyStage {
 
     fn verifier_service(
         packet_receiver: Arc<Mutex<PacketReceiver>>,
-        verified_sender: Arc<Mutex<Sender<VerifiedPackets>>>,
+        verified_sender: Arc<Mutex<SyncSender<VerifiedPackets>>>,
+        batch_size: usize,
         sigverify_disabled: bool,
     ) -> JoinHandle<()> {
-        spawn(move || loop {
-            if let Err(e) = Self::verifier(&packet_receiver, &verified_sender, sigverify_disabled) {
-                match e {
-                    Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
-                    Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
-                    _ => error!("{:?}", e),
+        spawn(move || {
+            cpu_time::register_thread("sigverify_stage");
+            let mut latency = StageLatency::new("sigverify_stage-latency");
+            loop {
+                if let Err(e) = Self::verifier(
+                    &packet_receiver,
+                    &verified_sender,
+                    batch_size,
+                    sigverify_disabled,
+                    &mut latency,
+                ) {
+                    match e {
+                        Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
+                        Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
+                        _ => error!("{:?}", e),
+                    }
                 }
             }
         })
@@ -114,13 +177,21 @@ impl SigVerifyStage {
 
     fn verifier_services(
         packet_receiver: PacketReceiver,
-        verified_sender: Sender<VerifiedPackets>,
+        verified_sender: SyncSender<VerifiedPackets>,
+        config: SigVerifyConfig,
         sigverify_disabled: bool,
     ) -> Vec<JoinHandle<()>> {
         let sender = Arc::new(Mutex::new(verified_sender));
         let receiver = Arc::new(Mutex::new(packet_receiver));
-        (0..4)
-            .map(|_| Self::verifier_service(receiver.clone(), sender.clone(), sigverify_disabled))
+        (0..config.workers)
+            .map(|_| {
+                Self::verifier_service(
+                    receiver.clone(),
+                    sender.clone(),
+                    config.batch_size,
+                    sigverify_disabled,
+                )
+            })
             .collect()
     }
 }
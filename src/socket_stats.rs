@@ -0,0 +1,227 @@
+//! The `socket_stats` module reports the kernel's statistics for the node's UDP
+//! sockets, so that packets dropped because a socket's receive queue overflowed
+//! can be told apart from packets lost on the network. The statistics are read
+//! from `/proc/net`, so on other platforms nothing is reported.
+
+use crdt::Sockets;
+use influx_db_client as influxdb;
+use metrics;
+use service::Service;
+use std::fs;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep, Builder, JoinHandle};
+use std::time::Duration;
+
+/// How often the socket statistics are submitted to metrics.
+const SUBMIT_INTERVAL_MS: u64 = 10_000;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct UdpSocketStats {
+    /// Bytes waiting in the receive queue.
+    pub rx_queue: u64,
+    /// Bytes waiting in the send queue.
+    pub tx_queue: u64,
+    /// Packets dropped since the socket was created, mostly because the
+    /// receive queue was full.
+    pub drops: u64,
+}
+
+/// Host-wide UDP counters from `/proc/net/snmp`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct UdpHostStats {
+    /// Packets that couldn't be delivered, including `rcvbuf_errors`.
+    pub in_errors: u64,
+    /// Packets dropped because a receive queue was full.
+    pub rcvbuf_errors: u64,
+    /// Packets dropped because a send queue was full.
+    pub sndbuf_errors: u64,
+}
+
+/// Parse one socket line of `/proc/net/udp` or `/proc/net/udp6`, returning the
+/// local port and the socket's statistics.
+fn parse_udp_line(line: &str) -> Option<(u16, UdpSocketStats)> {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    let port = fields.get(1)?.rsplit(':').next()?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut queues = fields.get(4)?.split(':');
+    let tx_queue = u64::from_str_radix(queues.next()?, 16).ok()?;
+    let rx_queue = u64::from_str_radix(queues.next()?, 16).ok()?;
+    let drops = fields.get(12)?.parse().ok()?;
+    Some((
+        port,
+        UdpSocketStats {
+            rx_queue,
+            tx_queue,
+            drops,
+        },
+    ))
+}
+
+/// The statistics of the UDP sockets bound to `port`, summed over IPv4 and
+/// IPv6, or `None` if there are none.
+pub fn udp_socket_stats(port: u16) -> Option<UdpSocketStats> {
+    let mut found = None;
+    for path in &["/proc/net/udp", "/proc/net/udp6"] {
+        let table = match fs::read_to_string(path) {
+            Ok(table) => table,
+            Err(_) => continue,
+        };
+        for (_, stats) in table
+            .lines()
+            .skip(1)
+            .filter_map(parse_udp_line)
+            .filter(|(p, _)| *p == port)
+        {
+            let total = found.get_or_insert_with(UdpSocketStats::default);
+            total.rx_queue += stats.rx_queue;
+            total.tx_queue += stats.tx_queue;
+            total.drops += stats.drops;
+        }
+    }
+    found
+}
+
+/// The host's UDP error counters.
+pub fn udp_host_stats() -> Option<UdpHostStats> {
+    let snmp = fs::read_to_string("/proc/net/snmp").ok()?;
+    // the counters follow a header line naming them
+    let mut lines = snmp.lines().filter(|line| line.starts_with("Udp: "));
+    let names: Vec<_> = lines.next()?.split_whitespace().collect();
+    let values: Vec<_> = lines.next()?.split_whitespace().collect();
+    let counter = |name| -> Option<u64> {
+        let i = names.iter().position(|n| *n == name)?;
+        values.get(i)?.parse().ok()
+    };
+    Some(UdpHostStats {
+        in_errors: counter("InErrors")?,
+        rcvbuf_errors: counter("RcvbufErrors")?,
+        sndbuf_errors: counter("SndbufErrors")?,
+    })
+}
+
+/// Periodically submits the statistics of the node's sockets to metrics.
+pub struct SocketStatsService {
+    thread_hdl: JoinHandle<()>,
+}
+
+impl SocketStatsService {
+    pub fn new(sockets: &Sockets, exit: Arc<AtomicBool>) -> Self {
+        let mut named: Vec<(&'static str, &UdpSocket)> = vec![
+            ("gossip", &sockets.gossip),
+            ("gossip_send", &sockets.gossip_send),
+            ("requests", &sockets.requests),
+            ("replicate", &sockets.replicate),
+            ("transaction", &sockets.transaction),
+            ("respond", &sockets.respond),
+            ("broadcast", &sockets.broadcast),
+            ("repair", &sockets.repair),
+            ("retransmit", &sockets.retransmit),
+        ];
+        if let Some(vote) = &sockets.vote {
+            named.push(("vote", vote));
+        }
+        let ports: Vec<_> = named
+            .iter()
+            .filter_map(|(name, socket)| Some((*name, socket.local_addr().ok()?.port())))
+            .collect();
+
+        let thread_hdl = Builder::new()
+            .name("solana-socket-stats".to_string())
+            .spawn(move || {
+                let poll = Duration::from_millis(100);
+                let mut elapsed = Duration::default();
+                while !exit.load(Ordering::Relaxed) {
+                    sleep(poll);
+                    elapsed += poll;
+                    if elapsed >= Duration::from_millis(SUBMIT_INTERVAL_MS) {
+                        elapsed = Duration::default();
+                        Self::submit(&ports);
+                    }
+                }
+            })
+            .unwrap();
+        SocketStatsService { thread_hdl }
+    }
+
+    fn submit(ports: &[(&'static str, u16)]) {
+        let mut point = influxdb::Point::new("udp_socket_stats");
+        for (name, port) in ports {
+            if let Some(stats) = udp_socket_stats(*port) {
+                let field = |stat| format!("{}-{}", name, stat);
+                point.add_field(
+                    &field("drops"),
+                    influxdb::Value::Integer(stats.drops as i64),
+                );
+                point.add_field(
+                    &field("rx_queue"),
+                    influxdb::Value::Integer(stats.rx_queue as i64),
+                );
+            }
+        }
+        if let Some(stats) = udp_host_stats() {
+            point.add_field(
+                "host-in_errors",
+                influxdb::Value::Integer(stats.in_errors as i64),
+            );
+            point.add_field(
+                "host-rcvbuf_errors",
+                influxdb::Value::Integer(stats.rcvbuf_errors as i64),
+            );
+            point.add_field(
+                "host-sndbuf_errors",
+                influxdb::Value::Integer(stats.sndbuf_errors as i64),
+            );
+        }
+        metrics::submit(point);
+    }
+}
+
+impl Service for SocketStatsService {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        vec![self.thread_hdl]
+    }
+
+    fn join(self) -> thread::Result<()> {
+        self.thread_hdl.join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use socket_stats::{parse_udp_line, udp_host_stats, udp_socket_stats, UdpSocketStats};
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_parse_udp_line() {
+        let line = "  42: 0100007F:1F90 00000000:0000 07 00000010:00000200 00:00000000 \
+                    00000000  1000        0 12345 2 0000000000000000 7";
+        assert_eq!(
+            parse_udp_line(line),
+            Some((
+                8080,
+                UdpSocketStats {
+                    rx_queue: 0x200,
+                    tx_queue: 0x10,
+                    drops: 7,
+                }
+            ))
+        );
+        assert_eq!(parse_udp_line("garbage"), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_udp_socket_stats() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&[0u8; 64], addr).unwrap();
+
+        // the packet sits in the receive queue until it's read
+        let stats = udp_socket_stats(addr.port()).unwrap();
+        assert!(stats.rx_queue > 0);
+        assert!(udp_host_stats().is_some());
+    }
+}
@@ -1,6 +1,7 @@
 //! The `streamer` module defines a set of services for efficiently pulling data from UDP sockets.
 //!
 use packet::{Blob, BlobRecycler, PacketRecycler, SharedBlobs, SharedPackets};
+use packet_capture;
 use result::{Error, Result};
 use std::net::UdpSocket;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -29,6 +30,7 @@ fn recv_loop(
                 .recv_from(sock);
             match result {
                 Ok(()) => {
+                    packet_capture::capture_packets(sock, &msgs.read().unwrap());
                     channel.send(msgs)?;
                     break;
                 }
@@ -69,7 +71,15 @@ fn recv_send(sock: &UdpSocket, recycler: &BlobRecycler, r: &BlobReceiver) -> Res
     Ok(())
 }
 
-pub fn recv_batch(recvr: &PacketReceiver) -> Result<(Vec<SharedPackets>, usize)> {
+/// The default for the most packets `recv_batch` gathers into one batch.
+pub const MAX_BATCH_PACKETS: usize = 100_000;
+
+/// Wait for packets, then take whatever else is queued, stopping once the
+/// batch holds more than `max_packets`.
+pub fn recv_batch(
+    recvr: &PacketReceiver,
+    max_packets: usize,
+) -> Result<(Vec<SharedPackets>, usize)> {
     let timer = Duration::new(1, 0);
     let msgs = recvr.recv_timeout(timer)?;
     trace!("got msgs");
@@ -80,7 +90,7 @@ pub fn recv_batch(recvr: &PacketReceiver) -> Result<(Vec<SharedPackets>, usize)>
         len += more.read().unwrap().packets.len();
         batch.push(more);
 
-        if len > 100_000 {
+        if len > max_packets {
             break;
         }
     }
@@ -110,13 +120,16 @@ pub fn responder(
 
 //TODO, we would need to stick block authentication before we create the
 //window.
-fn recv_blobs(recycler: &BlobRecycler, sock: &UdpSocket, s: &BlobSender) -> Result<()> {
+/// Receive the blobs waiting on `sock` and send them on, returning how many.
+pub fn recv_blobs(recycler: &BlobRecycler, sock: &UdpSocket, s: &BlobSender) -> Result<usize> {
     trace!("recv_blobs: receiving on {}", sock.local_addr().unwrap());
     let dq = Blob::recv_from(recycler, sock)?;
-    if !dq.is_empty() {
+    packet_capture::capture_blobs(sock, &dq);
+    let count = dq.len();
+    if count > 0 {
         s.send(dq)?;
     }
-    Ok(())
+    Ok(count)
 }
 
 pub fn blob_receiver(
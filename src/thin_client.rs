@@ -3,14 +3,20 @@
 //! messages to the network directly. The binary encoding of its messages are
 //! unstable and may change in future releases.
 
-use bank::Account;
+use account::Account;
 use bincode::{deserialize, serialize};
 use hash::Hash;
+use network_preset::NetworkPreset;
 use request::{Request, Response};
+use reqwest;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{self, Value};
 use signature::{Keypair, Pubkey, Signature};
 use std::collections::HashMap;
 use std::io;
 use std::net::{SocketAddr, UdpSocket};
+use std::sync::Mutex;
 use std::thread::sleep;
 use std::time::Duration;
 use std::time::Instant;
@@ -20,6 +26,127 @@ use transaction::Transaction;
 use influx_db_client as influxdb;
 use metrics;
 
+/// The number of slots, starting with the current one, whose leaders a client
+/// sending to the leaders' TPUs sends each transaction to.
+pub const TPU_FANOUT_SLOTS: u64 = 3;
+
+/// How long a client sends to the same leaders' TPUs before asking the cluster
+/// for the upcoming leaders again.
+pub const LEADER_TPUS_REFRESH_MS: u64 = 400;
+
+/// The TPUs of the upcoming leaders, as learned from a node's JSON RPC service.
+struct LeaderTpus {
+    rpc_addr: SocketAddr,
+    tpus: Vec<SocketAddr>,
+    refreshed: Option<Instant>,
+}
+
+/// The part of each `getClusterNodes` result the client needs.
+#[derive(Deserialize)]
+struct ClusterNode {
+    pubkey: String,
+    tpu: String,
+}
+
+#[derive(Serialize)]
+struct RpcRequest<'a> {
+    jsonrpc: &'a str,
+    id: u64,
+    method: &'a str,
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    #[serde(default)]
+    result: Value,
+    error: Option<Value>,
+}
+
+fn err_to_io<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+
+/// Call `method` on the JSON RPC service at `rpc_addr`.
+fn rpc_request<T>(rpc_addr: &SocketAddr, method: &str, params: Value) -> io::Result<T>
+where
+    T: DeserializeOwned,
+{
+    let request = RpcRequest {
+        jsonrpc: "2.0",
+        id: 1,
+        method,
+        params,
+    };
+    let response: RpcResponse = reqwest::Client::new()
+        .post(&format!("http://{}", rpc_addr))
+        .json(&request)
+        .send()
+        .and_then(|mut response| response.json())
+        .map_err(err_to_io)?;
+    if let Some(error) = response.error {
+        return Err(err_to_io(format!("{} failed: {}", method, error)));
+    }
+    serde_json::from_value(response.result).map_err(err_to_io)
+}
+
+/// The TPU of each of `leaders` that `nodes` lists, in order and without repeats.
+fn leader_tpus(leaders: &[String], nodes: &[ClusterNode]) -> Vec<SocketAddr> {
+    let mut tpus = vec![];
+    for leader in leaders {
+        let tpu = nodes
+            .iter()
+            .find(|node| node.pubkey == *leader)
+            .and_then(|node| node.tpu.parse().ok());
+        if let Some(tpu) = tpu {
+            if !tpus.contains(&tpu) {
+                tpus.push(tpu);
+            }
+        }
+    }
+    tpus
+}
+
+/// Ask the node at `rpc_addr` for the TPUs of the leaders of the current slot
+/// and the `TPU_FANOUT_SLOTS - 1` after it.
+fn get_leader_tpus(rpc_addr: &SocketAddr) -> io::Result<Vec<SocketAddr>> {
+    let slot: u64 = rpc_request(rpc_addr, "getSlot", json_params(()))?;
+    let leaders: Option<Vec<String>> = rpc_request(
+        rpc_addr,
+        "getSlotLeaders",
+        json_params((slot, TPU_FANOUT_SLOTS)),
+    )?;
+    let nodes: Vec<ClusterNode> = rpc_request(rpc_addr, "getClusterNodes", json_params(()))?;
+    Ok(leader_tpus(&leaders.unwrap_or_default(), &nodes))
+}
+
+/// Ask the node at `rpc_addr` for the hash of its cluster's genesis entry.
+pub fn get_genesis_hash(rpc_addr: &SocketAddr) -> io::Result<Hash> {
+    let genesis_hash: String = rpc_request(rpc_addr, "getGenesisHash", json_params(()))?;
+    genesis_hash.parse().map_err(err_to_io)
+}
+
+/// Fail unless the node at `rpc_addr` is in the cluster whose genesis hash is
+/// `expected`, so that tokens aren't sent on the wrong cluster.
+pub fn check_genesis_hash(rpc_addr: &SocketAddr, expected: &Hash) -> io::Result<()> {
+    let genesis_hash = get_genesis_hash(rpc_addr)?;
+    if genesis_hash != *expected {
+        return Err(err_to_io(format!(
+            "the node at {} is in the cluster with genesis hash {}, not {}; check --url",
+            rpc_addr, genesis_hash, expected
+        )));
+    }
+    Ok(())
+}
+
+/// JSON RPC params, which `()` makes none of.
+fn json_params<T: Serialize>(params: T) -> Value {
+    match serde_json::to_value(params).expect("serialize JSON RPC params") {
+        Value::Null => Value::Array(vec![]),
+        params => params,
+    }
+}
+
 /// An object for querying and sending transactions to the network.
 pub struct ThinClient {
     requests_addr: SocketAddr,
@@ -31,6 +158,7 @@ pub struct ThinClient {
     balances: HashMap<Pubkey, Account>,
     signature_status: bool,
     finality: Option<usize>,
+    leader_tpus: Option<Mutex<LeaderTpus>>,
 }
 
 impl ThinClient {
@@ -53,7 +181,63 @@ impl ThinClient {
             balances: HashMap::new(),
             signature_status: false,
             finality: None,
+            leader_tpus: None,
+        }
+    }
+
+    /// Create a ThinClient for the entrypoint leader of the cluster `preset`
+    /// names, after checking the cluster has the genesis hash the preset pins,
+    /// if it pins one.
+    pub fn from_preset(preset: &NetworkPreset) -> io::Result<Self> {
+        if let Some(genesis_hash) = preset.genesis_hash() {
+            check_genesis_hash(&preset.rpc_addr()?, &genesis_hash)?;
         }
+        let requests_socket = UdpSocket::bind("0.0.0.0:0")?;
+        requests_socket.set_read_timeout(Some(Duration::new(1, 0)))?;
+        let transactions_socket = UdpSocket::bind("0.0.0.0:0")?;
+        Ok(ThinClient::new(
+            preset.rpu_addr()?,
+            requests_socket,
+            preset.tpu_addr()?,
+            transactions_socket,
+        ))
+    }
+
+    /// Send transactions straight to the TPUs of the leaders of the current
+    /// slot and the `TPU_FANOUT_SLOTS - 1` after it, rather than only to
+    /// `transactions_addr`. The leaders and their TPUs are looked up with the
+    /// `getSlotLeaders` and `getClusterNodes` methods of the JSON RPC service at
+    /// `rpc_addr`. A transaction sent near the end of a slot then still reaches
+    /// the leader that records it.
+    pub fn use_leader_tpus(&mut self, rpc_addr: SocketAddr) {
+        self.leader_tpus = Some(Mutex::new(LeaderTpus {
+            rpc_addr,
+            tpus: vec![],
+            refreshed: None,
+        }));
+    }
+
+    /// Where to send transactions: the upcoming leaders' TPUs if they're used
+    /// and known, or else `transactions_addr`.
+    fn tpus(&self) -> Vec<SocketAddr> {
+        let mut leader_tpus = match self.leader_tpus {
+            Some(ref leader_tpus) => leader_tpus.lock().unwrap(),
+            None => return vec![self.transactions_addr],
+        };
+        let stale = leader_tpus.refreshed.map_or(true, |refreshed| {
+            timing::duration_as_ms(&refreshed.elapsed()) >= LEADER_TPUS_REFRESH_MS
+        });
+        if stale {
+            match get_leader_tpus(&leader_tpus.rpc_addr) {
+                Ok(tpus) => leader_tpus.tpus = tpus,
+                Err(err) => warn!("looking up the leaders' TPUs failed: {}", err),
+            }
+            leader_tpus.refreshed = Some(Instant::now());
+        }
+        if leader_tpus.tpus.is_empty() {
+            return vec![self.transactions_addr];
+        }
+        leader_tpus.tpus.clone()
     }
 
     pub fn recv_response(&self) -> io::Result<Response> {
@@ -99,13 +283,23 @@ impl ThinClient {
         }
     }
 
-    /// Send a signed Transaction to the server for processing. This method
-    /// does not wait for a response.
+    /// Send a signed Transaction to the server, or to the upcoming leaders, for
+    /// processing. This method does not wait for a response. It fails only if
+    /// the transaction couldn't be sent anywhere.
     pub fn transfer_signed(&self, tx: &Transaction) -> io::Result<Signature> {
         let data = serialize(&tx).expect("serialize Transaction in pub fn transfer_signed");
-        self.transactions_socket
-            .send_to(&data, &self.transactions_addr)?;
-        Ok(tx.signature)
+        let mut sent = false;
+        let mut result = Ok(tx.signature);
+        for tpu in self.tpus() {
+            match self.transactions_socket.send_to(&data, &tpu) {
+                Ok(_) => sent = true,
+                Err(err) => result = Err(err),
+            }
+        }
+        if sent {
+            return Ok(tx.signature);
+        }
+        result
     }
 
     /// Creates, signs, and processes a Transaction. Useful for writing unit-tests.
@@ -327,13 +521,13 @@ mod tests {
     use budget::Budget;
     use crdt::TestNode;
     use fullnode::Fullnode;
-    use ledger::LedgerWriter;
+    use identity_lease::IdentityLease;
+    use ledger::{FileLedgerWriter, StoragePaths};
     use logger;
     use mint::Mint;
-    use service::Service;
     use signature::{Keypair, KeypairUtil};
     use std::fs::remove_dir_all;
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::atomic::AtomicBool;
     use std::sync::Arc;
     use transaction::{Instruction, Plan};
 
@@ -344,7 +538,7 @@ mod tests {
 
         let path = format!("{}/tmp-ledger-{}-{}", out_dir, name, keypair.pubkey());
 
-        let mut writer = LedgerWriter::open(&path, true).unwrap();
+        let mut writer = FileLedgerWriter::open(&path, true).unwrap();
         writer.write_entries(mint.create_entries()).unwrap();
 
         path
@@ -365,14 +559,23 @@ mod tests {
 
         let server = Fullnode::new_leader(
             leader_keypair,
+            Arc::new(IdentityLease::default()),
             bank,
             0,
             &[],
             leader,
-            exit.clone(),
-            &ledger_path,
+            exit,
+            &StoragePaths::new(&ledger_path),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
             false,
-        );
+        )
+        .unwrap();
         sleep(Duration::from_millis(900));
 
         let requests_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
@@ -391,8 +594,7 @@ mod tests {
         client.poll_for_signature(&signature).unwrap();
         let balance = client.get_balance(&bob_pubkey);
         assert_eq!(balance.unwrap(), 500);
-        exit.store(true, Ordering::Relaxed);
-        server.join().unwrap();
+        server.close().unwrap();
         remove_dir_all(ledger_path).unwrap();
     }
 
@@ -412,14 +614,23 @@ mod tests {
 
         let server = Fullnode::new_leader(
             leader_keypair,
+            Arc::new(IdentityLease::default()),
             bank,
             0,
             &[],
             leader,
-            exit.clone(),
-            &ledger_path,
+            exit,
+            &StoragePaths::new(&ledger_path),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
             false,
-        );
+        )
+        .unwrap();
         //TODO: remove this sleep, or add a retry so CI is stable
         sleep(Duration::from_millis(300));
 
@@ -443,7 +654,7 @@ mod tests {
         let last_id = client.get_last_id();
 
         let mut tr2 = Transaction::new(&alice.keypair(), bob_pubkey, 501, last_id);
-        if let Instruction::NewContract(contract) = &mut tr2.instruction {
+        if let Instruction::NewContract(contract) = &mut tr2.instructions[0] {
             contract.tokens = 502;
             contract.plan = Plan::Budget(Budget::new_payment(502, bob_pubkey));
         }
@@ -452,11 +663,81 @@ mod tests {
 
         let balance = client.get_balance(&bob_pubkey);
         assert_eq!(balance.unwrap(), 500);
-        exit.store(true, Ordering::Relaxed);
-        server.join().unwrap();
+        server.close().unwrap();
         remove_dir_all(ledger_path).unwrap();
     }
 
+    #[test]
+    fn test_leader_tpus() {
+        let node = |pubkey: &str, tpu: &str| ClusterNode {
+            pubkey: pubkey.to_string(),
+            tpu: tpu.to_string(),
+        };
+        let nodes = vec![
+            node("alice", "127.0.0.1:8001"),
+            node("bob", "127.0.0.1:8002"),
+            node("carol", "not an address"),
+        ];
+        let leaders: Vec<_> = ["bob", "bob", "carol", "dave", "alice"]
+            .iter()
+            .map(|leader| leader.to_string())
+            .collect();
+        let tpus: Vec<SocketAddr> = vec![
+            "127.0.0.1:8002".parse().unwrap(),
+            "127.0.0.1:8001".parse().unwrap(),
+        ];
+        assert_eq!(leader_tpus(&leaders, &nodes), tpus);
+        assert_eq!(json_params(()), Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_transfer_signed_to_leader_tpus() {
+        let recv = |socket: &UdpSocket| -> io::Result<Transaction> {
+            let mut buf = [0u8; 1024];
+            let (len, _) = socket.recv_from(&mut buf)?;
+            Ok(deserialize::<Transaction>(&buf[..len]).unwrap())
+        };
+        let fallback = UdpSocket::bind("127.0.0.1:0").unwrap();
+        fallback
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let leaders: Vec<_> = (0..2)
+            .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let mut client = ThinClient::new(
+            fallback.local_addr().unwrap(),
+            UdpSocket::bind("0.0.0.0:0").unwrap(),
+            fallback.local_addr().unwrap(),
+            UdpSocket::bind("0.0.0.0:0").unwrap(),
+        );
+        let set_tpus = |client: &ThinClient, tpus: Vec<SocketAddr>| {
+            let mut leader_tpus = client.leader_tpus.as_ref().unwrap().lock().unwrap();
+            leader_tpus.tpus = tpus;
+            leader_tpus.refreshed = Some(Instant::now());
+        };
+        client.use_leader_tpus("127.0.0.1:0".parse().unwrap());
+        set_tpus(
+            &client,
+            leaders
+                .iter()
+                .map(|leader| leader.local_addr().unwrap())
+                .collect(),
+        );
+
+        let tx = Transaction::new(&Keypair::new(), Keypair::new().pubkey(), 1, Hash::default());
+        assert_eq!(client.transfer_signed(&tx).unwrap(), tx.signature);
+        for leader in &leaders {
+            leader.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+            assert_eq!(recv(leader).unwrap(), tx);
+        }
+        assert!(recv(&fallback).is_err());
+
+        // with no leaders known, transactions go to the transactions address
+        set_tpus(&client, vec![]);
+        client.transfer_signed(&tx).unwrap();
+        assert_eq!(recv(&fallback).unwrap(), tx);
+    }
+
     #[test]
     fn test_client_check_signature() {
         logger::setup();
@@ -471,14 +752,23 @@ mod tests {
 
         let server = Fullnode::new_leader(
             leader_keypair,
+            Arc::new(IdentityLease::default()),
             bank,
             0,
             &[],
             leader,
-            exit.clone(),
-            &ledger_path,
+            exit,
+            &StoragePaths::new(&ledger_path),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
             false,
-        );
+        )
+        .unwrap();
         sleep(Duration::from_millis(300));
 
         let requests_socket = UdpSocket::bind("0.0.0.0:0").unwrap();
@@ -500,8 +790,7 @@ mod tests {
 
         assert!(client.check_signature(&signature));
 
-        exit.store(true, Ordering::Relaxed);
-        server.join().unwrap();
+        server.close().unwrap();
         remove_dir_all(ledger_path).unwrap();
     }
 }
@@ -2,6 +2,10 @@
 use std::time::Duration;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub fn duration_as_ns(d: &Duration) -> u64 {
+    (d.as_secs() * 1000 * 1000 * 1000) + u64::from(d.subsec_nanos())
+}
+
 pub fn duration_as_us(d: &Duration) -> u64 {
     (d.as_secs() * 1000 * 1000) + (u64::from(d.subsec_nanos()) / 1_000)
 }
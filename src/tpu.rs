@@ -24,83 +24,124 @@
 //!                                            | Bank |               | Ledger |
 //!                                            `------`               `--------`
 //! ```
+//!
+//! Votes may also come in on a port of their own, where the vote ingest stage
+//! verifies and applies them, and hands them to the record stage alongside the
+//! banking stage's transactions.
 
 use bank::Bank;
 use banking_stage::BankingStage;
 use crdt::Crdt;
 use fetch_stage::FetchStage;
+use identity_lease::IdentityLease;
+use ledger::StoragePaths;
 use packet::{BlobRecycler, PacketRecycler};
-use record_stage::RecordStage;
+use record_stage::{CommitLock, RecordStage};
+use result::Result;
 use service::Service;
 use signature::Keypair;
-use sigverify_stage::SigVerifyStage;
+use sigverify_stage::{SigVerifyConfig, SigVerifyStage};
 use std::net::UdpSocket;
 use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::channel;
 use std::sync::{Arc, RwLock};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 use streamer::BlobReceiver;
+use vote_ingest_stage::VoteIngestStage;
 use write_stage::WriteStage;
 
 pub struct Tpu {
     fetch_stage: FetchStage,
     sigverify_stage: SigVerifyStage,
     banking_stage: BankingStage,
+    /// Takes votes apart from other transactions, if the node has a port for them.
+    vote_ingest_stage: Option<VoteIngestStage>,
     record_stage: RecordStage,
     write_stage: WriteStage,
 }
 
 impl Tpu {
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn new(
         keypair: Keypair,
+        lease: &Arc<IdentityLease>,
         bank: &Arc<Bank>,
         crdt: &Arc<RwLock<Crdt>>,
         tick_duration: Option<Duration>,
         transactions_socket: UdpSocket,
+        vote_socket: Option<UdpSocket>,
         blob_recycler: &BlobRecycler,
         exit: Arc<AtomicBool>,
-        ledger_path: &str,
+        storage: &StoragePaths,
+        entry_height: u64,
+        sigverify_config: Option<SigVerifyConfig>,
         sigverify_disabled: bool,
-    ) -> (Self, BlobReceiver) {
+    ) -> Result<(Self, BlobReceiver)> {
         let packet_recycler = PacketRecycler::default();
 
         let (fetch_stage, packet_receiver) =
-            FetchStage::new(transactions_socket, exit, &packet_recycler);
+            FetchStage::new(transactions_socket, exit.clone(), &packet_recycler);
 
         let (sigverify_stage, verified_receiver) =
-            SigVerifyStage::new(packet_receiver, sigverify_disabled);
+            SigVerifyStage::new(packet_receiver, sigverify_config, sigverify_disabled);
 
-        let (banking_stage, signal_receiver) =
-            BankingStage::new(bank.clone(), verified_receiver, packet_recycler.clone());
+        let (signal_sender, signal_receiver) = channel();
+        let commit_lock = CommitLock::new();
+        let vote_ingest_stage = vote_socket.map(|socket| {
+            VoteIngestStage::new(
+                bank.clone(),
+                crdt.clone(),
+                socket,
+                exit,
+                &packet_recycler,
+                signal_sender.clone(),
+                commit_lock.clone(),
+            )
+        });
+        let banking_stage = BankingStage::new_with_signal_sender(
+            bank.clone(),
+            verified_receiver,
+            packet_recycler.clone(),
+            signal_sender,
+            commit_lock.clone(),
+        );
 
-        let (record_stage, entry_receiver) = match tick_duration {
-            Some(tick_duration) => {
-                RecordStage::new_with_clock(signal_receiver, &bank.last_id(), tick_duration)
-            }
-            None => RecordStage::new(signal_receiver, &bank.last_id()),
-        };
+        let (record_stage, entry_receiver) = RecordStage::new_with_bank(
+            signal_receiver,
+            bank.clone(),
+            commit_lock.clone(),
+            tick_duration,
+        );
 
         let (write_stage, blob_receiver) = WriteStage::new(
             keypair,
+            lease.clone(),
             bank.clone(),
             crdt.clone(),
             blob_recycler.clone(),
-            ledger_path,
+            storage,
             entry_receiver,
-        );
+            commit_lock,
+            entry_height,
+        )?;
 
         let tpu = Tpu {
             fetch_stage,
             sigverify_stage,
             banking_stage,
+            vote_ingest_stage,
             record_stage,
             write_stage,
         };
-        (tpu, blob_receiver)
+        Ok((tpu, blob_receiver))
     }
 
     pub fn close(self) -> thread::Result<()> {
         self.fetch_stage.close();
+        if let Some(vote_ingest_stage) = &self.vote_ingest_stage {
+            vote_ingest_stage.close();
+        }
         self.join()
     }
 }
@@ -111,6 +152,9 @@ impl Service for Tpu {
         thread_hdls.extend(self.fetch_stage.thread_hdls().into_iter());
         thread_hdls.extend(self.sigverify_stage.thread_hdls().into_iter());
         thread_hdls.extend(self.banking_stage.thread_hdls().into_iter());
+        if let Some(vote_ingest_stage) = self.vote_ingest_stage {
+            thread_hdls.extend(vote_ingest_stage.thread_hdls().into_iter());
+        }
         thread_hdls.extend(self.record_stage.thread_hdls().into_iter());
         thread_hdls.extend(self.write_stage.thread_hdls().into_iter());
         thread_hdls
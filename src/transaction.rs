@@ -3,13 +3,25 @@
 use bincode::serialize;
 use budget::{Budget, Condition};
 use chrono::prelude::*;
+use feature::Feature;
 use hash::Hash;
+use native_program::NativeProgram;
 use payment_plan::{Payment, PaymentPlan, Witness};
 use signature::{Keypair, KeypairUtil, Pubkey, Signature};
 
-pub const SIGNED_DATA_OFFSET: usize = 112;
+pub const SIGNED_DATA_OFFSET: usize = 184;
 pub const SIG_OFFSET: usize = 8;
-pub const PUB_KEY_OFFSET: usize = 80;
+pub const FEE_PAYER_SIG_OFFSET: usize = 80;
+pub const PUB_KEY_OFFSET: usize = 152;
+/// The fee payer's key starts the signed data, so both signatures commit to it.
+pub const FEE_PAYER_PUB_KEY_OFFSET: usize = 192;
+
+/// The longest memo, in bytes, a transaction may carry. A transfer with a memo
+/// has to fit in one packet.
+pub const MAX_MEMO_LEN: usize = 32;
+
+/// The most bytes `Transaction::new_load` writes in one transaction.
+pub const MAX_WRITE_LEN: usize = 96;
 
 /// The type of payment plan. Each item must implement the PaymentPlan trait.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -18,6 +30,15 @@ pub enum Plan {
     Budget(Budget),
 }
 
+impl Plan {
+    /// The tokens the plan pays out, whichever way it's reduced.
+    pub fn tokens(&self) -> i64 {
+        match self {
+            Plan::Budget(budget) => budget.tokens(),
+        }
+    }
+}
+
 // A proxy for the underlying DSL.
 impl PaymentPlan for Plan {
     fn final_payment(&self) -> Option<Payment> {
@@ -45,6 +66,10 @@ pub struct Contract {
     /// The number of tokens allocated to the `Plan` and any transaction fees.
     pub tokens: i64,
     pub plan: Plan,
+    /// The key that may cancel the plan while it waits on a witness, which
+    /// returns its tokens to the account that funded it. `None` if the plan
+    /// can't be canceled.
+    pub cancel_authority: Option<Pubkey>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -55,7 +80,14 @@ pub struct Vote {
     pub version: u64,
     /// The version of the CRDT struct that has the same network configuration as this one
     pub contact_info_version: u64,
-    // TODO: add signature of the state here as well
+    /// The identity the vote is cast for, if not the signing node's own key. It
+    /// must have authorized the node with `Instruction::AuthorizeVoter`.
+    pub identity: Option<Pubkey>,
+    /// The voter's `Bank::accounts_hash` as of the `last_id` voted on, so
+    /// validators whose account state diverged find out even where their
+    /// entry IDs agree. Leaders, whose banks run ahead of the entries they
+    /// register, leave it out.
+    pub accounts_hash: Option<Hash>,
 }
 
 /// An instruction to progress the smart contract.
@@ -73,19 +105,116 @@ pub enum Instruction {
 
     /// Vote for a PoH that is equal to the lastid of this transaction
     NewVote(Vote),
+
+    /// Declare and instantiate `Contract`, funded by the address derived from
+    /// the signer's key with the seed and owner by `Pubkey::create_with_seed`.
+    NewContractFromSeed(String, Pubkey, Contract),
+
+    /// Make the second `Pubkey` the owner of the account at the first. Only the
+    /// account's current owner may sign it.
+    Assign(Pubkey, Pubkey),
+
+    /// Let the node with `Pubkey` vote for the signing identity, in place of any
+    /// node authorized before. The identity's key can then be kept offline while
+    /// the node gossips and votes with a key of its own.
+    AuthorizeVoter(Pubkey),
+
+    /// Replace the durable nonce stored in the userdata of the account at
+    /// `Pubkey`, whose owner must sign, with one derived from this transaction's
+    /// signature. A transaction that starts with it may use the stored nonce as
+    /// its `last_id` in place of a recent entry ID, so it can be signed offline.
+    AdvanceNonce(Pubkey),
+
+    /// Cancel the plan still waiting on a witness that the `NewContract` with
+    /// `Signature` declared, returning its tokens to the account that funded
+    /// it. Only the plan's cancel authority may sign it.
+    Cancel(Signature),
+
+    /// Turn `Feature` on at the end of the current rent epoch. Only the mint's
+    /// key may sign it, and in the genesis block it takes effect at once.
+    ActivateFeature(Feature),
+
+    /// Resize the userdata of the account at `Pubkey` to `u64` bytes, zeroing
+    /// any it gains. Only the account's owner may sign it.
+    Allocate(Pubkey, u64),
+
+    /// Copy the bytes into the userdata of the account at `Pubkey`, starting at
+    /// offset `u64`. They must fit within what's allocated by then, and only
+    /// the account's owner may sign it.
+    Write(Pubkey, u64, Vec<u8>),
+}
+
+impl Instruction {
+    /// The contract the instruction declares, if any.
+    pub fn contract(&self) -> Option<&Contract> {
+        match self {
+            Instruction::NewContract(contract) => Some(contract),
+            Instruction::NewContractFromSeed(_, _, contract) => Some(contract),
+            _ => None,
+        }
+    }
+
+    /// The account the instruction draws tokens from when signed by `from`,
+    /// `from` itself unless the contract is funded by an address derived from it.
+    pub fn funding_pubkey(&self, from: &Pubkey) -> Pubkey {
+        match self {
+            Instruction::NewContractFromSeed(seed, owner, _) => {
+                Pubkey::create_with_seed(from, seed, owner)
+            }
+            _ => *from,
+        }
+    }
+
+    /// The ID of the program the bank runs the instruction with.
+    pub fn program_id(&self) -> Pubkey {
+        let program = match self {
+            Instruction::NewContract(_)
+            | Instruction::NewContractFromSeed(_, _, _)
+            | Instruction::ApplyTimestamp(_)
+            | Instruction::ApplySignature(_)
+            | Instruction::Cancel(_) => NativeProgram::Budget,
+            Instruction::NewVote(_) | Instruction::AuthorizeVoter(_) => NativeProgram::Vote,
+            Instruction::Assign(_, _)
+            | Instruction::Allocate(_, _)
+            | Instruction::Write(_, _, _)
+            | Instruction::AdvanceNonce(_)
+            | Instruction::ActivateFeature(_) => NativeProgram::System,
+        };
+        program.id()
+    }
+
+    /// The account whose owner or userdata the instruction sets, if any. An
+    /// advanced nonce is left out, as it changes along with the debits.
+    pub fn data_pubkey(&self) -> Option<Pubkey> {
+        match self {
+            Instruction::Assign(pubkey, _)
+            | Instruction::Allocate(pubkey, _)
+            | Instruction::Write(pubkey, _, _) => Some(*pubkey),
+            _ => None,
+        }
+    }
 }
 
 /// An instruction signed by a client with `Pubkey`.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Transaction {
-    /// A digital signature of `instruction`, `last_id` and `fee`, signed by `Pubkey`.
+    /// A digital signature of `fee_payer`, `instructions`, `last_id` and `fee`, signed by `Pubkey`.
     pub signature: Signature,
 
+    /// The same data signed by `fee_payer`. Equal to `signature` unless the fee
+    /// payer is someone other than the signer.
+    pub fee_payer_signature: Signature,
+
     /// The `Pubkey` of the entity that signed the transaction data.
     pub from: Pubkey,
 
-    /// The action the server should take.
-    pub instruction: Instruction,
+    /// The account `fee` is drawn from. It's `from` unless another key pays
+    /// the fee, in which case the fee isn't taken out of the first contract.
+    pub fee_payer: Pubkey,
+
+    /// The actions the server should take, in order. The bank applies either
+    /// all of them or, if any of them fails, none.
+    pub instructions: Vec<Instruction>,
 
     /// The ID of a recent ledger entry.
     pub last_id: Hash,
@@ -94,15 +223,24 @@ pub struct Transaction {
     pub fee: i64,
     /// Optional user data to be stored in the account
     /// TODO: This will be a required field for all contract operations including a simple spend.
-    /// `instruction` will be serialized into `userdata` once Budget is its own generic contract.
+    /// `instructions` will be serialized into `userdata` once Budget is its own generic contract.
     pub userdata: Vec<u8>,
+
+    /// An optional short UTF-8 note recorded in the ledger with the transaction,
+    /// such as a deposit reference. It doesn't affect how the transaction is processed.
+    pub memo: Option<String>,
+
+    /// Accounts the transaction only reads. Any number of transactions in a
+    /// batch may read one at once, and the bank rejects the transaction if it
+    /// would change one.
+    pub read_only: Vec<Pubkey>,
 }
 
 impl Transaction {
-    /// Create a signed transaction with userdata and an instruction
-    fn new_with_userdata_and_instruction(
+    /// Create a signed transaction with userdata and instructions
+    fn new_with_userdata_and_instructions(
         from_keypair: &Keypair,
-        instruction: Instruction,
+        instructions: Vec<Instruction>,
         last_id: Hash,
         fee: i64,
         userdata: Vec<u8>,
@@ -110,11 +248,15 @@ impl Transaction {
         let from = from_keypair.pubkey();
         let mut tx = Transaction {
             signature: Signature::default(),
-            instruction,
+            fee_payer_signature: Signature::default(),
+            instructions,
             last_id,
             from,
+            fee_payer: from,
             fee,
             userdata,
+            memo: None,
+            read_only: vec![],
         };
         tx.sign(from_keypair);
         tx
@@ -126,7 +268,34 @@ impl Transaction {
         last_id: Hash,
         fee: i64,
     ) -> Self {
-        Self::new_with_userdata_and_instruction(from_keypair, instruction, last_id, fee, vec![])
+        Self::new_with_instructions(from_keypair, vec![instruction], last_id, fee)
+    }
+
+    /// Create a signed transaction applying `instructions` in order, all of
+    /// them or none.
+    pub fn new_with_instructions(
+        from_keypair: &Keypair,
+        instructions: Vec<Instruction>,
+        last_id: Hash,
+        fee: i64,
+    ) -> Self {
+        Self::new_with_userdata_and_instructions(from_keypair, instructions, last_id, fee, vec![])
+    }
+
+    /// Create a signed transaction applying `instructions` that declares the
+    /// accounts at `read_only` as only read, so that other transactions reading
+    /// them can be processed alongside it.
+    pub fn new_with_read_only(
+        from_keypair: &Keypair,
+        instructions: Vec<Instruction>,
+        read_only: &[Pubkey],
+        last_id: Hash,
+        fee: i64,
+    ) -> Self {
+        let mut tx = Self::new_with_instructions(from_keypair, instructions, last_id, fee);
+        tx.read_only = read_only.to_vec();
+        tx.sign(from_keypair);
+        tx
     }
 
     /// Create and sign a new Transaction. Used for unit-testing.
@@ -143,7 +312,11 @@ impl Transaction {
         };
         let budget = Budget::Pay(payment);
         let plan = Plan::Budget(budget);
-        let instruction = Instruction::NewContract(Contract { plan, tokens });
+        let instruction = Instruction::NewContract(Contract {
+            plan,
+            tokens,
+            cancel_authority: None,
+        });
         Self::new_from_instruction(from_keypair, instruction, last_id, fee)
     }
 
@@ -152,6 +325,57 @@ impl Transaction {
         Self::new_taxed(from_keypair, to, tokens, 0, last_id)
     }
 
+    /// Create and sign one transaction making each of `payments`, all of them
+    /// or none.
+    pub fn new_payments(from_keypair: &Keypair, payments: &[Payment], last_id: Hash) -> Self {
+        let instructions = payments
+            .iter()
+            .map(|payment| {
+                let plan = Plan::Budget(Budget::Pay(payment.clone()));
+                let tokens = payment.tokens;
+                Instruction::NewContract(Contract {
+                    plan,
+                    tokens,
+                    cancel_authority: None,
+                })
+            })
+            .collect();
+        Self::new_with_instructions(from_keypair, instructions, last_id, 0)
+    }
+
+    /// Create and sign a payment of `tokens` to `to` whose `fee` is paid by
+    /// `fee_payer_keypair` on top of the payment, rather than out of it. Both
+    /// keys sign it.
+    pub fn new_with_fee_payer(
+        from_keypair: &Keypair,
+        fee_payer_keypair: &Keypair,
+        to: Pubkey,
+        tokens: i64,
+        fee: i64,
+        last_id: Hash,
+    ) -> Self {
+        let mut tx = Self::new(from_keypair, to, tokens, last_id);
+        tx.fee = fee;
+        tx.fee_payer = fee_payer_keypair.pubkey();
+        tx.sign(from_keypair);
+        tx.sign_fee_payer(fee_payer_keypair);
+        tx
+    }
+
+    /// Create and sign a payment of `tokens` to `to` carrying `memo`.
+    pub fn new_with_memo(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        tokens: i64,
+        last_id: Hash,
+        memo: &str,
+    ) -> Self {
+        let mut tx = Self::new(from_keypair, to, tokens, last_id);
+        tx.memo = Some(memo.to_string());
+        tx.sign(from_keypair);
+        tx
+    }
+
     /// Create and sign a new Witness Timestamp. Used for unit-testing.
     pub fn new_timestamp(from_keypair: &Keypair, dt: DateTime<Utc>, last_id: Hash) -> Self {
         let instruction = Instruction::ApplyTimestamp(dt);
@@ -164,6 +388,125 @@ impl Transaction {
         Self::new_from_instruction(from_keypair, instruction, last_id, 0)
     }
 
+    /// Create and sign a payment of `tokens` to `to` from the address derived from
+    /// `base_keypair`'s key with `seed` and `owner`.
+    pub fn new_from_seed(
+        base_keypair: &Keypair,
+        seed: &str,
+        owner: Pubkey,
+        to: Pubkey,
+        tokens: i64,
+        last_id: Hash,
+    ) -> Self {
+        let budget = Budget::Pay(Payment { tokens, to });
+        let plan = Plan::Budget(budget);
+        let contract = Contract {
+            plan,
+            tokens,
+            cancel_authority: None,
+        };
+        let instruction = Instruction::NewContractFromSeed(seed.to_string(), owner, contract);
+        Self::new_from_instruction(base_keypair, instruction, last_id, 0)
+    }
+
+    /// Create and sign a transaction assigning the account at `pubkey` to `owner`.
+    pub fn new_assign(
+        from_keypair: &Keypair,
+        pubkey: Pubkey,
+        owner: Pubkey,
+        last_id: Hash,
+    ) -> Self {
+        let instruction = Instruction::Assign(pubkey, owner);
+        Self::new_from_instruction(from_keypair, instruction, last_id, 0)
+    }
+
+    /// Create and sign a transaction resizing the userdata of the account at
+    /// `pubkey` to `len` bytes.
+    pub fn new_allocate(from_keypair: &Keypair, pubkey: Pubkey, len: u64, last_id: Hash) -> Self {
+        let instruction = Instruction::Allocate(pubkey, len);
+        Self::new_from_instruction(from_keypair, instruction, last_id, 0)
+    }
+
+    /// Create and sign a transaction writing `bytes` into the userdata of the
+    /// account at `pubkey`, starting at `offset`.
+    pub fn new_write(
+        from_keypair: &Keypair,
+        pubkey: Pubkey,
+        offset: u64,
+        bytes: Vec<u8>,
+        last_id: Hash,
+    ) -> Self {
+        let instruction = Instruction::Write(pubkey, offset, bytes);
+        Self::new_from_instruction(from_keypair, instruction, last_id, 0)
+    }
+
+    /// Create and sign the transactions loading `data` into the userdata of
+    /// the empty account at `pubkey`. Each allocates room for the next
+    /// `MAX_WRITE_LEN` bytes at most and writes them, so that it fits in a
+    /// packet and grows the account little enough for the bank to accept.
+    pub fn new_load(
+        from_keypair: &Keypair,
+        pubkey: Pubkey,
+        data: &[u8],
+        last_id: Hash,
+    ) -> Vec<Self> {
+        data.chunks(MAX_WRITE_LEN)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let offset = (i * MAX_WRITE_LEN) as u64;
+                let instructions = vec![
+                    Instruction::Allocate(pubkey, offset + chunk.len() as u64),
+                    Instruction::Write(pubkey, offset, chunk.to_vec()),
+                ];
+                Self::new_with_instructions(from_keypair, instructions, last_id, 0)
+            })
+            .collect()
+    }
+
+    /// Create and sign a transaction storing a fresh durable nonce in the
+    /// account at `nonce_pubkey`, which `from_keypair` must own.
+    pub fn new_advance_nonce(from_keypair: &Keypair, nonce_pubkey: Pubkey, last_id: Hash) -> Self {
+        let instruction = Instruction::AdvanceNonce(nonce_pubkey);
+        Self::new_from_instruction(from_keypair, instruction, last_id, 0)
+    }
+
+    /// Create and sign a transaction of `instructions` that uses `nonce`, the
+    /// nonce stored in the account at `nonce_pubkey`, as its `last_id`. It
+    /// stays valid until that nonce is advanced, however old it gets.
+    pub fn new_with_nonce(
+        from_keypair: &Keypair,
+        nonce_pubkey: Pubkey,
+        nonce: Hash,
+        mut instructions: Vec<Instruction>,
+        fee: i64,
+    ) -> Self {
+        instructions.insert(0, Instruction::AdvanceNonce(nonce_pubkey));
+        Self::new_with_instructions(from_keypair, instructions, nonce, fee)
+    }
+
+    /// Create and sign a transaction letting the node with `voter` vote for
+    /// `identity_keypair`'s identity.
+    pub fn new_authorize_voter(
+        identity_keypair: &Keypair,
+        voter: Pubkey,
+        last_id: Hash,
+        fee: i64,
+    ) -> Self {
+        let instruction = Instruction::AuthorizeVoter(voter);
+        Self::new_from_instruction(identity_keypair, instruction, last_id, fee)
+    }
+
+    /// Create and sign a transaction activating `feature`.
+    pub fn new_activate_feature(
+        mint_keypair: &Keypair,
+        feature: Feature,
+        last_id: Hash,
+        fee: i64,
+    ) -> Self {
+        let instruction = Instruction::ActivateFeature(feature);
+        Self::new_from_instruction(mint_keypair, instruction, last_id, fee)
+    }
+
     pub fn new_vote(from_keypair: &Keypair, vote: Vote, last_id: Hash, fee: i64) -> Self {
         Transaction::new_from_instruction(&from_keypair, Instruction::NewVote(vote), last_id, fee)
     }
@@ -182,13 +525,46 @@ impl Transaction {
             (Condition::Signature(from), Payment { tokens, to: from }),
         );
         let plan = Plan::Budget(budget);
-        let instruction = Instruction::NewContract(Contract { plan, tokens });
+        let instruction = Instruction::NewContract(Contract {
+            plan,
+            tokens,
+            cancel_authority: None,
+        });
         Self::new_from_instruction(from_keypair, instruction, last_id, 0)
     }
 
+    /// Create and sign a payment of `tokens` to `to` held in escrow until
+    /// `from_keypair` witnesses a timestamp at or after `dt`. Until then,
+    /// `from_keypair` may cancel it and take its tokens back.
+    pub fn new_escrow(
+        from_keypair: &Keypair,
+        to: Pubkey,
+        dt: DateTime<Utc>,
+        tokens: i64,
+        last_id: Hash,
+    ) -> Self {
+        let from = from_keypair.pubkey();
+        let budget = Budget::new_future_payment(dt, from, tokens, to);
+        let plan = Plan::Budget(budget);
+        let instruction = Instruction::NewContract(Contract {
+            plan,
+            tokens,
+            cancel_authority: Some(from),
+        });
+        Self::new_from_instruction(from_keypair, instruction, last_id, 0)
+    }
+
+    /// Create and sign a transaction canceling the escrow `signature` created.
+    pub fn new_cancel(from_keypair: &Keypair, signature: Signature, last_id: Hash) -> Self {
+        Self::new_from_instruction(from_keypair, Instruction::Cancel(signature), last_id, 0)
+    }
+
     /// Get the transaction data to sign.
-    fn get_sign_data(&self) -> Vec<u8> {
-        let mut data = serialize(&(&self.instruction)).expect("serialize Contract");
+    pub fn get_sign_data(&self) -> Vec<u8> {
+        let mut data = serialize(&(&self.fee_payer)).expect("serialize fee_payer");
+        let instructions = serialize(&(&self.instructions)).expect("serialize instructions");
+        data.extend_from_slice(&instructions);
+
         let last_id_data = serialize(&(&self.last_id)).expect("serialize last_id");
         data.extend_from_slice(&last_id_data);
 
@@ -198,31 +574,141 @@ impl Transaction {
         let userdata = serialize(&(&self.userdata)).expect("serialize userdata");
         data.extend_from_slice(&userdata);
 
+        let memo = serialize(&(&self.memo)).expect("serialize memo");
+        data.extend_from_slice(&memo);
+
+        let read_only = serialize(&(&self.read_only)).expect("serialize read_only");
+        data.extend_from_slice(&read_only);
+
         data
     }
 
     /// Sign this transaction.
     pub fn sign(&mut self, keypair: &Keypair) {
         let sign_data = self.get_sign_data();
-        self.signature = Signature::new(keypair.sign(&sign_data).as_ref());
+        self.signature = keypair.sign_message(&sign_data);
+        if !self.has_fee_payer() {
+            self.fee_payer_signature = self.signature;
+        }
+    }
+
+    /// Sign this transaction as its fee payer, once `sign` has been called.
+    pub fn sign_fee_payer(&mut self, keypair: &Keypair) {
+        let sign_data = self.get_sign_data();
+        self.fee_payer_signature = keypair.sign_message(&sign_data);
     }
 
-    /// Verify only the transaction signature.
+    /// Whether the fee is paid by a key other than the signer's.
+    pub fn has_fee_payer(&self) -> bool {
+        self.fee_payer != self.from
+    }
+
+    /// Verify only the transaction signatures.
     pub fn verify_signature(&self) -> bool {
         warn!("transaction signature verification called");
-        self.signature
-            .verify(&self.from.as_ref(), &self.get_sign_data())
+        let sign_data = self.get_sign_data();
+        self.signature.verify(&self.from.as_ref(), &sign_data)
+            && (!self.has_fee_payer()
+                || self
+                    .fee_payer_signature
+                    .verify(&self.fee_payer.as_ref(), &sign_data))
+    }
+
+    /// The contracts the transaction declares, each with the account it's
+    /// funded from.
+    pub fn contracts(&self) -> Vec<(Pubkey, &Contract)> {
+        self.instructions
+            .iter()
+            .filter_map(|instruction| {
+                let contract = instruction.contract()?;
+                Some((instruction.funding_pubkey(&self.from), contract))
+            })
+            .collect()
+    }
+
+    /// The tokens the transaction draws from each account: each contract's
+    /// from the account funding it, and the fee from the fee payer if it's
+    /// someone other than the signer.
+    pub fn debits(&self) -> Vec<(Pubkey, i64)> {
+        let mut debits: Vec<_> = self
+            .contracts()
+            .into_iter()
+            .map(|(pubkey, contract)| (pubkey, contract.tokens))
+            .collect();
+        if self.has_fee_payer() {
+            debits.push((self.fee_payer, self.fee));
+        }
+        debits
     }
 
-    /// Verify only the payment plan.
+    /// The payments the transaction makes as soon as it's processed.
+    pub fn final_payments(&self) -> Vec<Payment> {
+        self.contracts()
+            .into_iter()
+            .filter_map(|(_, contract)| contract.plan.final_payment())
+            .collect()
+    }
+
+    /// The accounts the transaction's instructions draw from, without repeats,
+    /// followed by the fee payer if it's someone other than the signer. Each
+    /// of the rest is the signer's own unless a contract is funded by an
+    /// address derived from it.
+    pub fn funding_pubkeys(&self) -> Vec<Pubkey> {
+        let mut pubkeys = vec![];
+        for instruction in &self.instructions {
+            let pubkey = instruction.funding_pubkey(&self.from);
+            if !pubkeys.contains(&pubkey) {
+                pubkeys.push(pubkey);
+            }
+        }
+        if self.has_fee_payer() && !pubkeys.contains(&self.fee_payer) {
+            pubkeys.push(self.fee_payer);
+        }
+        pubkeys
+    }
+
+    /// Whether the memo, if any, is short enough to be recorded.
+    pub fn verify_memo(&self) -> bool {
+        self.memo
+            .as_ref()
+            .map_or(true, |memo| memo.len() <= MAX_MEMO_LEN)
+    }
+
+    /// The account whose durable nonce the transaction may use as its
+    /// `last_id`, if its first instruction advances one.
+    pub fn nonce_pubkey(&self) -> Option<Pubkey> {
+        match self.instructions.first() {
+            Some(Instruction::AdvanceNonce(pubkey)) => Some(*pubkey),
+            _ => None,
+        }
+    }
+
+    /// Verify only the payment plans. The fee is paid out of the first
+    /// contract, and at most one contract may wait on a witness, since a
+    /// witness names the contract by the transaction's signature.
     pub fn verify_plan(&self) -> bool {
-        if let Instruction::NewContract(contract) = &self.instruction {
-            self.fee >= 0
-                && self.fee <= contract.tokens
-                && contract.plan.verify(contract.tokens - self.fee)
-        } else {
-            true
+        if self.instructions.is_empty() {
+            return false;
         }
+        // a fee payer pays the fee on top of the contracts
+        let mut fee = self.fee;
+        if self.has_fee_payer() {
+            if fee < 0 {
+                return false;
+            }
+            fee = 0;
+        }
+        let mut pending = 0;
+        for (_, contract) in self.contracts() {
+            if fee < 0 || fee > contract.tokens || !contract.plan.verify(contract.tokens - fee) {
+                return false;
+            }
+            fee = 0;
+            if contract.plan.final_payment().is_none() {
+                pending += 1;
+            }
+        }
+        pending <= 1
     }
 }
 
@@ -249,6 +735,7 @@ pub fn memfind<A: Eq>(a: &[A], b: &[A]) -> Option<usize> {
 mod tests {
     use super::*;
     use bincode::{deserialize, serialize};
+    use packet::PACKET_DATA_SIZE;
 
     #[test]
     fn test_claim() {
@@ -285,14 +772,22 @@ mod tests {
             to: Default::default(),
         });
         let plan = Plan::Budget(budget);
-        let instruction = Instruction::NewContract(Contract { plan, tokens: 0 });
+        let instruction = Instruction::NewContract(Contract {
+            plan,
+            tokens: 0,
+            cancel_authority: None,
+        });
         let claim0 = Transaction {
-            instruction,
+            instructions: vec![instruction],
             from: Default::default(),
+            fee_payer: Default::default(),
             last_id: Default::default(),
             signature: Default::default(),
+            fee_payer_signature: Default::default(),
             fee: 0,
             userdata: vec![],
+            memo: None,
+            read_only: vec![],
         };
         let buf = serialize(&claim0).unwrap();
         let claim1: Transaction = deserialize(&buf).unwrap();
@@ -305,7 +800,7 @@ mod tests {
         let keypair = Keypair::new();
         let pubkey = keypair.pubkey();
         let mut tx = Transaction::new(&keypair, pubkey, 42, zero);
-        if let Instruction::NewContract(contract) = &mut tx.instruction {
+        if let Instruction::NewContract(contract) = &mut tx.instructions[0] {
             contract.tokens = 1_000_000; // <-- attack, part 1!
             if let Plan::Budget(Budget::Pay(ref mut payment)) = contract.plan {
                 payment.tokens = contract.tokens; // <-- attack, part 2!
@@ -323,7 +818,7 @@ mod tests {
         let pubkey1 = keypair1.pubkey();
         let zero = Hash::default();
         let mut tx = Transaction::new(&keypair0, pubkey1, 42, zero);
-        if let Instruction::NewContract(contract) = &mut tx.instruction {
+        if let Instruction::NewContract(contract) = &mut tx.instructions[0] {
             if let Plan::Budget(Budget::Pay(ref mut payment)) = contract.plan {
                 payment.to = thief_keypair.pubkey(); // <-- attack!
             }
@@ -340,13 +835,77 @@ mod tests {
         assert_matches!(memfind(&tx_bytes, &tx.signature.as_ref()), Some(SIG_OFFSET));
         assert_matches!(memfind(&tx_bytes, &tx.from.as_ref()), Some(PUB_KEY_OFFSET));
     }
+    #[test]
+    fn test_fee_payer_layout() {
+        let keypair = Keypair::new();
+        let fee_payer = Keypair::new();
+        let to = Keypair::new().pubkey();
+        let tx = Transaction::new_with_fee_payer(&keypair, &fee_payer, to, 42, 1, Hash::default());
+        let tx_bytes = serialize(&tx).unwrap();
+        assert_eq!(
+            memfind(&tx_bytes, &tx.get_sign_data()),
+            Some(SIGNED_DATA_OFFSET)
+        );
+        assert_eq!(memfind(&tx_bytes, &tx.signature.as_ref()), Some(SIG_OFFSET));
+        assert_eq!(
+            memfind(&tx_bytes, &tx.fee_payer_signature.as_ref()),
+            Some(FEE_PAYER_SIG_OFFSET)
+        );
+        assert_eq!(memfind(&tx_bytes, &tx.from.as_ref()), Some(PUB_KEY_OFFSET));
+        assert_eq!(
+            memfind(&tx_bytes, &fee_payer.pubkey().as_ref()),
+            Some(FEE_PAYER_PUB_KEY_OFFSET)
+        );
+    }
+
+    #[test]
+    fn test_fee_payer() {
+        let keypair = Keypair::new();
+        let fee_payer = Keypair::new();
+        let to = Keypair::new().pubkey();
+        let zero = Hash::default();
+        let tx = Transaction::new_with_fee_payer(&keypair, &fee_payer, to, 42, 2, zero);
+        assert!(tx.has_fee_payer());
+        assert!(tx.verify_plan());
+        assert!(tx.verify_signature());
+        assert_eq!(tx.final_payments(), vec![Payment { tokens: 42, to }]);
+        assert_eq!(
+            tx.debits(),
+            vec![(keypair.pubkey(), 42), (fee_payer.pubkey(), 2)]
+        );
+        assert_eq!(
+            tx.funding_pubkeys(),
+            vec![keypair.pubkey(), fee_payer.pubkey()]
+        );
+
+        // the fee payer has to sign
+        let mut unsigned = tx.clone();
+        unsigned.fee_payer_signature = tx.signature;
+        assert!(!unsigned.verify_signature());
+
+        // and the signer agreed to who pays, so the fee can't be moved onto it
+        let mut stripped = tx.clone();
+        stripped.fee_payer = tx.from;
+        stripped.fee_payer_signature = tx.signature;
+        assert!(!stripped.verify_signature());
+
+        let negative = Transaction::new_with_fee_payer(&keypair, &fee_payer, to, 42, -1, zero);
+        assert!(!negative.verify_plan());
+
+        // without a fee payer the signer pays
+        let tx = Transaction::new_taxed(&keypair, to, 42, 2, zero);
+        assert!(!tx.has_fee_payer());
+        assert_eq!(tx.fee_payer_signature, tx.signature);
+        assert_eq!(tx.debits(), vec![(keypair.pubkey(), 42)]);
+    }
+
     #[test]
     fn test_userdata_layout() {
         let mut tx0 = test_tx();
         tx0.userdata = vec![1, 2, 3];
         let sign_data0a = tx0.get_sign_data();
         let tx_bytes = serialize(&tx0).unwrap();
-        assert!(tx_bytes.len() < 256);
+        assert!(tx_bytes.len() < 384);
         assert_eq!(memfind(&tx_bytes, &sign_data0a), Some(SIGNED_DATA_OFFSET));
         assert_eq!(
             memfind(&tx_bytes, &tx0.signature.as_ref()),
@@ -362,13 +921,65 @@ mod tests {
         assert_ne!(sign_data0a, sign_data0b);
     }
 
+    #[test]
+    fn test_memo() {
+        let keypair = Keypair::new();
+        let to = Keypair::new().pubkey();
+        let zero = Hash::default();
+        let memo = "x".repeat(MAX_MEMO_LEN);
+        let tx = Transaction::new_with_memo(&keypair, to, 42, zero, &memo);
+        assert!(tx.verify_memo());
+        assert!(tx.verify_signature());
+        let tx_bytes = serialize(&tx).unwrap();
+        assert!(tx_bytes.len() <= PACKET_DATA_SIZE);
+        assert_eq!(
+            memfind(&tx_bytes, &tx.get_sign_data()),
+            Some(SIGNED_DATA_OFFSET)
+        );
+        let tx1: Transaction = deserialize(&tx_bytes).unwrap();
+        assert_eq!(tx1.memo, Some(memo.clone()));
+
+        // the memo is signed
+        let mut tx2 = tx.clone();
+        tx2.memo = Some("y".to_string());
+        assert!(!tx2.verify_signature());
+
+        let long = Transaction::new_with_memo(&keypair, to, 42, zero, &format!("{}x", memo));
+        assert!(!long.verify_memo());
+    }
+
+    #[test]
+    fn test_read_only() {
+        let keypair = Keypair::new();
+        let oracle = Keypair::new().pubkey();
+        let instructions = vec![Instruction::NewVote(Vote {
+            version: 1,
+            contact_info_version: 0,
+            identity: None,
+            accounts_hash: None,
+        })];
+        let tx =
+            Transaction::new_with_read_only(&keypair, instructions, &[oracle], Hash::default(), 0);
+        assert!(tx.verify_signature());
+        let tx_bytes = serialize(&tx).unwrap();
+        assert_eq!(
+            memfind(&tx_bytes, &tx.get_sign_data()),
+            Some(SIGNED_DATA_OFFSET)
+        );
+
+        // the declaration is signed
+        let mut tx1 = tx.clone();
+        tx1.read_only.clear();
+        assert!(!tx1.verify_signature());
+    }
+
     #[test]
     fn test_overspend_attack() {
         let keypair0 = Keypair::new();
         let keypair1 = Keypair::new();
         let zero = Hash::default();
         let mut tx = Transaction::new(&keypair0, keypair1.pubkey(), 1, zero);
-        if let Instruction::NewContract(contract) = &mut tx.instruction {
+        if let Instruction::NewContract(contract) = &mut tx.instructions[0] {
             if let Plan::Budget(Budget::Pay(ref mut payment)) = contract.plan {
                 payment.tokens = 2; // <-- attack!
             }
@@ -376,11 +987,90 @@ mod tests {
         assert!(!tx.verify_plan());
 
         // Also, ensure all branchs of the plan spend all tokens
-        if let Instruction::NewContract(contract) = &mut tx.instruction {
+        if let Instruction::NewContract(contract) = &mut tx.instructions[0] {
             if let Plan::Budget(Budget::Pay(ref mut payment)) = contract.plan {
                 payment.tokens = 0; // <-- whoops!
             }
         }
         assert!(!tx.verify_plan());
     }
+
+    #[test]
+    fn test_nonce_pubkey() {
+        let keypair = Keypair::new();
+        let nonce_pubkey = Keypair::new().pubkey();
+        let nonce = Hash::default();
+        let tx = Transaction::new_with_nonce(&keypair, nonce_pubkey, nonce, vec![], 0);
+        assert_eq!(tx.nonce_pubkey(), Some(nonce_pubkey));
+        assert_eq!(tx.last_id, nonce);
+        assert!(tx.verify_plan());
+        assert!(tx.verify_signature());
+        assert_eq!(test_tx().nonce_pubkey(), None);
+    }
+
+    #[test]
+    fn test_load() {
+        let keypair = Keypair::new();
+        let pubkey = Keypair::new().pubkey();
+        let zero = Hash::default();
+        let data: Vec<u8> = (0..MAX_WRITE_LEN * 2 + 1).map(|i| i as u8).collect();
+        let txs = Transaction::new_load(&keypair, pubkey, &data, zero);
+        assert_eq!(txs.len(), 3);
+        for (i, tx) in txs.iter().enumerate() {
+            let offset = (i * MAX_WRITE_LEN) as u64;
+            let end = ((i + 1) * MAX_WRITE_LEN).min(data.len());
+            let bytes = data[offset as usize..end].to_vec();
+            assert_eq!(
+                tx.instructions,
+                vec![
+                    Instruction::Allocate(pubkey, end as u64),
+                    Instruction::Write(pubkey, offset, bytes),
+                ]
+            );
+            assert!(tx.verify_signature());
+            let tx_bytes = serialize(&tx).unwrap();
+            assert!(tx_bytes.len() <= PACKET_DATA_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_payments() {
+        let keypair = Keypair::new();
+        let zero = Hash::default();
+        let payment = |tokens| Payment {
+            tokens,
+            to: Keypair::new().pubkey(),
+        };
+        let payments = vec![payment(1), payment(2)];
+        let tx = Transaction::new_payments(&keypair, &payments, zero);
+        assert_eq!(tx.final_payments(), payments);
+        assert_eq!(tx.funding_pubkeys(), vec![keypair.pubkey()]);
+        assert!(tx.verify_plan());
+        assert!(tx.verify_signature());
+        let tx_bytes = serialize(&tx).unwrap();
+        assert!(tx_bytes.len() <= PACKET_DATA_SIZE);
+        assert_eq!(
+            memfind(&tx_bytes, &tx.get_sign_data()),
+            Some(SIGNED_DATA_OFFSET)
+        );
+
+        // every instruction is signed
+        let mut tx1 = tx.clone();
+        tx1.instructions.pop();
+        assert!(!tx1.verify_signature());
+
+        // a transaction does something
+        let empty = Transaction::new_with_instructions(&keypair, vec![], zero, 0);
+        assert!(!empty.verify_plan());
+
+        // only one contract may wait on a witness
+        let from = keypair.pubkey();
+        let postdated = Transaction::new_on_date(&keypair, from, Utc::now(), 1, zero);
+        let mut instructions = postdated.instructions.clone();
+        let tx = Transaction::new_with_instructions(&keypair, instructions.clone(), zero, 0);
+        assert!(tx.verify_plan());
+        instructions.extend(postdated.instructions);
+        let tx = Transaction::new_with_instructions(&keypair, instructions, zero, 0);
+        assert!(!tx.verify_plan());
+    }
 }
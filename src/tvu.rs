@@ -35,12 +35,18 @@
 //! 3. Replicate Stage
 //! - Transactions in blobs are processed and applied to the bank.
 //! - TODO We need to verify the signatures in the blobs.
+//! 4. Vote Stage
+//! - The bank's last_id is voted on at a regular cadence, within the vote lockout.
 
 use bank::Bank;
 use blob_fetch_stage::BlobFetchStage;
 use crdt::Crdt;
+use entry_stream::EntryStreamSender;
+use identity_lease::IdentityLease;
+use ledger::StoragePaths;
 use packet::BlobRecycler;
 use replicate_stage::ReplicateStage;
+use result::Result;
 use retransmit_stage::RetransmitStage;
 use service::Service;
 use signature::Keypair;
@@ -48,10 +54,12 @@ use std::net::UdpSocket;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, RwLock};
 use std::thread::{self, JoinHandle};
+use vote_stage::VoteStage;
 use window::SharedWindow;
 
 pub struct Tvu {
     replicate_stage: ReplicateStage,
+    vote_stage: VoteStage,
     fetch_stage: BlobFetchStage,
     retransmit_stage: RetransmitStage,
 }
@@ -60,6 +68,8 @@ impl Tvu {
     /// This service receives messages from a leader in the network and processes the transactions
     /// on the bank state.
     /// # Arguments
+    /// * `keypair` - This node's identity, used to sign votes.
+    /// * `lease` - Whether this node currently holds the identity and may vote.
     /// * `bank` - The bank state.
     /// * `entry_height` - Initial ledger height, passed to replicate stage
     /// * `crdt` - The crdt state.
@@ -67,10 +77,13 @@ impl Tvu {
     /// * `replicate_socket` - my replicate socket
     /// * `repair_socket` - my repair socket
     /// * `retransmit_socket` - my retransmit socket
+    /// * `storage` - Where to write the replicated ledger, if anywhere.
+    /// * `entry_stream` - Where to publish replicated entries for read replicas, if anywhere.
     /// * `exit` - The exit signal.
     #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn new(
         keypair: Keypair,
+        lease: &Arc<IdentityLease>,
         bank: &Arc<Bank>,
         entry_height: u64,
         crdt: Arc<RwLock<Crdt>>,
@@ -78,9 +91,10 @@ impl Tvu {
         replicate_socket: UdpSocket,
         repair_socket: UdpSocket,
         retransmit_socket: UdpSocket,
-        ledger_path: Option<&str>,
+        storage: Option<&StoragePaths>,
+        entry_stream: Option<EntryStreamSender>,
         exit: Arc<AtomicBool>,
-    ) -> Self {
+    ) -> Result<Self> {
         let blob_recycler = BlobRecycler::default();
         let (fetch_stage, blob_fetch_receiver) = BlobFetchStage::new_multi_socket(
             vec![replicate_socket, repair_socket],
@@ -100,20 +114,36 @@ impl Tvu {
         );
 
         let replicate_stage = ReplicateStage::new(
-            keypair,
+            bank.clone(),
+            crdt.clone(),
+            blob_recycler.clone(),
+            blob_window_receiver,
+            entry_height,
+            storage,
+            entry_stream,
+        );
+
+        let vote_stage = VoteStage::new_with_responder(
+            Arc::new(keypair),
+            lease.clone(),
             bank.clone(),
             crdt,
             blob_recycler,
-            blob_window_receiver,
-            ledger_path,
             exit,
-        );
+        )?;
 
-        Tvu {
+        Ok(Tvu {
             replicate_stage,
+            vote_stage,
             fetch_stage,
             retransmit_stage,
-        }
+        })
+    }
+
+    /// The stage reading the replicate and repair sockets, which sockets can
+    /// be added to or removed from while the Tvu runs.
+    pub fn fetch_stage(&self) -> &BlobFetchStage {
+        &self.fetch_stage
     }
 
     pub fn close(self) -> thread::Result<()> {
@@ -126,6 +156,7 @@ impl Service for Tvu {
     fn thread_hdls(self) -> Vec<JoinHandle<()>> {
         let mut thread_hdls = vec![];
         thread_hdls.extend(self.replicate_stage.thread_hdls().into_iter());
+        thread_hdls.extend(self.vote_stage.thread_hdls().into_iter());
         thread_hdls.extend(self.fetch_stage.thread_hdls().into_iter());
         thread_hdls.extend(self.retransmit_stage.thread_hdls().into_iter());
         thread_hdls
@@ -144,8 +175,8 @@ pub mod tests {
     use bank::Bank;
     use bincode::serialize;
     use crdt::{Crdt, TestNode};
-    use entry::Entry;
-    use hash::{hash, Hash};
+    use entry::next_entry;
+    use identity_lease::IdentityLease;
     use logger;
     use mint::Mint;
     use ncp::Ncp;
@@ -236,6 +267,7 @@ pub mod tests {
 
         let tvu = Tvu::new(
             target1_keypair,
+            &Arc::new(IdentityLease::default()),
             &bank,
             0,
             cref1,
@@ -244,20 +276,22 @@ pub mod tests {
             target1.sockets.repair,
             target1.sockets.retransmit,
             None,
+            None,
             exit.clone(),
-        );
+        )
+        .unwrap();
 
         let mut alice_ref_balance = starting_balance;
         let mut msgs = VecDeque::new();
-        let mut cur_hash = Hash::default();
+        let domain = bank.poh_domain();
+        let mut cur_hash = bank.last_id();
         let mut blob_id = 0;
         let num_transfers = 10;
         let transfer_amount = 501;
         let bob_keypair = Keypair::new();
         for i in 0..num_transfers {
-            let entry0 = Entry::new(&cur_hash, i, vec![], false);
-            bank.register_entry_id(&cur_hash);
-            cur_hash = hash(&cur_hash.as_ref());
+            let entry0 = next_entry(&domain.seed(&cur_hash), i + 1, vec![]);
+            cur_hash = entry0.id;
 
             let tx0 = Transaction::new(
                 &mint.keypair(),
@@ -265,11 +299,8 @@ pub mod tests {
                 transfer_amount,
                 cur_hash,
             );
-            bank.register_entry_id(&cur_hash);
-            cur_hash = hash(&cur_hash.as_ref());
-            let entry1 = Entry::new(&cur_hash, i + num_transfers, vec![tx0], false);
-            bank.register_entry_id(&cur_hash);
-            cur_hash = hash(&cur_hash.as_ref());
+            let entry1 = next_entry(&domain.seed(&cur_hash), i + num_transfers, vec![tx0]);
+            cur_hash = entry1.id;
 
             alice_ref_balance -= transfer_amount;
 
@@ -0,0 +1,315 @@
+//! The `vote_ingest_stage` takes validators' votes on a port of their own, so
+//! that they reach the bank even while the TPU's transaction port is saturated.
+//! Votes that come this way skip the sigverify and banking stages, where they
+//! would queue behind user transactions and lose every account conflict to one
+//! paying a fee. The stage verifies their signatures itself, on the CPU, and
+//! drops any packet that isn't a transaction of nothing but votes.
+//!
+//! Since these votes pay no fee to get in, the stage only takes votes for nodes
+//! gossip knows of, signed by the node or the voter it authorized, and no more
+//! than `MAX_VOTES_PER_INTERVAL` of them from each signer per vote interval.
+
+use bank::{Bank, BankError};
+use banking_stage::MAX_BATCH_PASSES;
+use bincode::deserialize;
+use counter::Counter;
+use crdt::Crdt;
+use fetch_stage::FetchStage;
+use log::Level;
+use packet::{Packet, PacketRecycler};
+use record_stage::{CommitLock, Signal};
+use result::{Error, Result};
+use service::Service;
+use signature::Pubkey;
+use sigverify;
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, Builder, JoinHandle};
+use std::time::Duration;
+use streamer::PacketReceiver;
+use timing;
+use transaction::{Instruction, Transaction};
+use vote_stage::VOTE_TIMEOUT_MS;
+
+/// The most vote transactions the stage takes from one signer each
+/// `VOTE_TIMEOUT_MS`, a few more than the one an honest node sends.
+pub const MAX_VOTES_PER_INTERVAL: usize = 4;
+
+/// Counts each signer's votes in the current vote interval. The counts are all
+/// dropped when the next interval starts, so there is nothing to sweep.
+#[derive(Default)]
+pub struct VoteLimits {
+    interval: u64,
+    counts: HashMap<Pubkey, usize>,
+}
+
+impl VoteLimits {
+    /// Count a vote from `signer` at `now`, in milliseconds, and return whether
+    /// it is within the signer's limit.
+    pub fn allow(&mut self, signer: &Pubkey, now: u64) -> bool {
+        let interval = now / VOTE_TIMEOUT_MS;
+        if interval != self.interval {
+            self.interval = interval;
+            self.counts.clear();
+        }
+        let count = self.counts.entry(*signer).or_insert(0);
+        *count += 1;
+        *count <= MAX_VOTES_PER_INTERVAL
+    }
+}
+
+pub struct VoteIngestStage {
+    fetch_stage: FetchStage,
+    thread_hdl: JoinHandle<()>,
+}
+
+impl VoteIngestStage {
+    /// Take votes from `socket` until `exit` is set, applying those of the nodes
+    /// in `crdt` to `bank` and sending those it accepts to the record stage with
+    /// `signal_sender`, on its turns of `commit_lock`.
+    pub fn new(
+        bank: Arc<Bank>,
+        crdt: Arc<RwLock<Crdt>>,
+        socket: UdpSocket,
+        exit: Arc<AtomicBool>,
+        packet_recycler: &PacketRecycler,
+        signal_sender: Sender<Signal>,
+        commit_lock: CommitLock,
+    ) -> Self {
+        let (fetch_stage, packet_receiver) = FetchStage::new(socket, exit, packet_recycler);
+        let packet_recycler = packet_recycler.clone();
+        let thread_hdl = Builder::new()
+            .name("solana-vote-ingest".to_string())
+            .spawn(move || {
+                let mut limits = VoteLimits::default();
+                loop {
+                    if let Err(e) = Self::ingest_votes(
+                        &bank,
+                        &crdt,
+                        &packet_receiver,
+                        &signal_sender,
+                        &commit_lock,
+                        &packet_recycler,
+                        &mut limits,
+                    ) {
+                        match e {
+                            Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
+                            Error::RecvTimeoutError(RecvTimeoutError::Timeout) => (),
+                            _ => error!("{:?}", e),
+                        }
+                    }
+                }
+            })
+            .unwrap();
+        VoteIngestStage {
+            fetch_stage,
+            thread_hdl,
+        }
+    }
+
+    /// The transaction in `packet`, if it holds one of nothing but votes.
+    fn deserialize_votes(packet: &Packet) -> Option<Transaction> {
+        let tx: Transaction = deserialize(&packet.data[..packet.meta.size]).ok()?;
+        let only_votes = tx.instructions.iter().all(|instruction| match instruction {
+            Instruction::NewVote(_) => true,
+            _ => false,
+        });
+        if only_votes && !tx.instructions.is_empty() {
+            Some(tx)
+        } else {
+            None
+        }
+    }
+
+    /// Whether every vote in `tx` is for a node in `crdt`, signed by the node
+    /// itself or by the voter it authorized in `bank`.
+    fn is_authorized(crdt: &Crdt, bank: &Bank, tx: &Transaction) -> bool {
+        tx.instructions.iter().all(|instruction| match instruction {
+            Instruction::NewVote(vote) => {
+                let identity = vote.identity.unwrap_or(tx.from);
+                crdt.table.contains_key(&identity)
+                    && (identity == tx.from || bank.authorized_voter(&identity) == Some(tx.from))
+            }
+            _ => false,
+        })
+    }
+
+    /// Verify the votes waiting on `packet_receiver`, apply those `crdt` and
+    /// `limits` allow to `bank`, and send those it accepts to `signal_sender`
+    /// on turns of `commit_lock`.
+    pub fn ingest_votes(
+        bank: &Arc<Bank>,
+        crdt: &Arc<RwLock<Crdt>>,
+        packet_receiver: &PacketReceiver,
+        signal_sender: &Sender<Signal>,
+        commit_lock: &CommitLock,
+        packet_recycler: &PacketRecycler,
+        limits: &mut VoteLimits,
+    ) -> Result<()> {
+        let mut batches = vec![packet_receiver.recv_timeout(Duration::new(1, 0))?];
+        batches.extend(packet_receiver.try_iter());
+        let verified = sigverify::ed25519_verify_cpu(&batches);
+
+        let mut votes = vec![];
+        let mut rejected = 0;
+        let mut recv_time = None;
+        for (msgs, vers) in batches.iter().zip(verified) {
+            let msgs = msgs.read().unwrap();
+            recv_time = recv_time.or(msgs.recv_time);
+            for (packet, ver) in msgs.packets.iter().zip(vers) {
+                match Self::deserialize_votes(packet) {
+                    Some(tx) if ver != 0 => votes.push(tx),
+                    _ => rejected += 1,
+                }
+            }
+        }
+        for msgs in batches {
+            packet_recycler.recycle(msgs);
+        }
+        inc_new_counter_info!("vote_ingest_stage-rejected", rejected);
+
+        let count = votes.len();
+        {
+            let crdt = crdt.read().expect("'crdt' read lock in ingest_votes");
+            votes.retain(|tx| Self::is_authorized(&crdt, bank, tx));
+        }
+        inc_new_counter_info!("vote_ingest_stage-unauthorized", count - votes.len());
+        let count = votes.len();
+        let now = timing::timestamp();
+        votes.retain(|tx| limits.allow(&tx.from, now));
+        inc_new_counter_info!("vote_ingest_stage-rate_limited", count - votes.len());
+        inc_new_counter_info!("vote_ingest_stage-votes", votes.len());
+
+        // a vote deferred over its signer's account, held by an earlier vote of
+        // the same node, is retried in a batch of its own, as in the banking stage
+        for _ in 0..MAX_BATCH_PASSES {
+            if votes.is_empty() {
+                break;
+            }
+            let mut deferred = vec![];
+            commit_lock.commit(signal_sender, recv_time, || {
+                let results = bank.process_transactions(&votes);
+                let mut processed = vec![];
+                for (tx, result) in votes.into_iter().zip(results) {
+                    match result {
+                        Ok(()) => processed.push(tx),
+                        Err(BankError::AccountInUse(_)) => deferred.push(tx),
+                        Err(err) => debug!("vote from {} rejected: {:?}", tx.from, err),
+                    }
+                }
+                processed
+            })?;
+            votes = deferred;
+        }
+        if !votes.is_empty() {
+            inc_new_counter_info!("vote_ingest_stage-account_in_use-dropped", votes.len());
+        }
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.fetch_stage.close();
+    }
+}
+
+impl Service for VoteIngestStage {
+    fn thread_hdls(self) -> Vec<JoinHandle<()>> {
+        let mut thread_hdls = self.fetch_stage.thread_hdls();
+        thread_hdls.push(self.thread_hdl);
+        thread_hdls
+    }
+
+    fn join(self) -> thread::Result<()> {
+        for thread_hdl in self.thread_hdls() {
+            thread_hdl.join()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::serialize;
+    use crdt::NodeInfo;
+    use mint::Mint;
+    use signature::{Keypair, KeypairUtil};
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc::channel;
+    use transaction::Vote;
+
+    #[test]
+    fn test_ingest_votes() {
+        let mint = Mint::new(10);
+        let bank = Arc::new(Bank::new(&mint));
+        let node = Keypair::new();
+        bank.transfer(1, &mint.keypair(), node.pubkey(), mint.last_id())
+            .unwrap();
+
+        let crdt = Crdt::new(NodeInfo::new_leader(&"127.0.0.1:1234".parse().unwrap())).unwrap();
+        let crdt = Arc::new(RwLock::new(crdt));
+        let node_info =
+            NodeInfo::new_leader_with_pubkey(node.pubkey(), &"127.0.0.1:1235".parse().unwrap());
+        crdt.write().unwrap().insert(&node_info);
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let vote_addr = socket.local_addr().unwrap();
+        let (signal_sender, signal_receiver) = channel();
+        let stage = VoteIngestStage::new(
+            bank.clone(),
+            crdt,
+            socket,
+            exit.clone(),
+            &PacketRecycler::default(),
+            signal_sender,
+            CommitLock::unrecorded(),
+        );
+
+        let vote = Vote {
+            version: 1,
+            contact_info_version: 0,
+            identity: None,
+            accounts_hash: None,
+        };
+        let vote_tx = Transaction::new_vote(&node, vote.clone(), bank.last_id(), 0);
+        // anything but a vote is dropped, however valid
+        let transfer = Transaction::new(&mint.keypair(), node.pubkey(), 1, bank.last_id());
+        // and so are votes of nodes gossip doesn't know of
+        let stranger_vote_tx = Transaction::new_vote(&Keypair::new(), vote, bank.last_id(), 0);
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for tx in &[transfer, stranger_vote_tx, vote_tx.clone()] {
+            sender.send_to(&serialize(tx).unwrap(), vote_addr).unwrap();
+        }
+
+        match signal_receiver.recv_timeout(Duration::new(5, 0)).unwrap() {
+            Signal::Transactions(txs, _) => assert_eq!(txs, vec![vote_tx.clone()]),
+            Signal::Tick => panic!("expected the vote"),
+        }
+        assert_eq!(bank.get_signature_status(&vote_tx.signature), Some(Ok(())));
+        assert_eq!(bank.get_balance(&node.pubkey()), 1);
+
+        exit.store(true, Ordering::Relaxed);
+        stage.join().unwrap();
+    }
+
+    #[test]
+    fn test_vote_limits() {
+        let mut limits = VoteLimits::default();
+        let signer = Keypair::new().pubkey();
+        let other = Keypair::new().pubkey();
+        let now = 10 * VOTE_TIMEOUT_MS;
+        for _ in 0..MAX_VOTES_PER_INTERVAL {
+            assert!(limits.allow(&signer, now));
+        }
+        assert!(!limits.allow(&signer, now + VOTE_TIMEOUT_MS - 1));
+        assert!(limits.allow(&other, now));
+
+        // every signer starts afresh in the next interval
+        assert!(limits.allow(&signer, now + VOTE_TIMEOUT_MS));
+        assert_eq!(limits.counts.len(), 1);
+    }
+}
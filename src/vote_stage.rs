@@ -1,10 +1,16 @@
-//! The `vote_stage` votes on the `last_id` of the bank at a regular cadence
+//! The `vote_stage` votes on the `last_id` of the bank at a regular cadence.
+//!
+//! Each vote locks the node out of other forks for a while: until
+//! `VOTE_LOCKOUT_SLOTS` vote intervals have passed, the stage only votes on a
+//! bank whose ledger still contains the last id it voted on.
 
 use bank::Bank;
 use bincode::serialize;
 use counter::Counter;
+use cpu_time;
 use crdt::Crdt;
 use hash::Hash;
+use identity_lease::IdentityLease;
 use influx_db_client as influxdb;
 use log::Level;
 use metrics;
@@ -13,19 +19,57 @@ use result::Result;
 use service::Service;
 use signature::Keypair;
 use std::collections::VecDeque;
+use std::net::UdpSocket;
 use std::result;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
 use std::sync::{Arc, RwLock};
 use std::thread::{self, sleep, spawn, JoinHandle};
 use std::time::Duration;
-use streamer::BlobSender;
+use streamer::{responder, BlobSender};
 use timing;
 use transaction::Transaction;
 
 pub const VOTE_TIMEOUT_MS: u64 = 1000;
 
+/// The number of vote intervals a vote keeps the node off conflicting forks.
+pub const VOTE_LOCKOUT_SLOTS: u64 = 32;
+
 pub struct VoteStage {
-    thread_hdl: JoinHandle<()>,
+    thread_hdls: Vec<JoinHandle<()>>,
+}
+
+/// Tracks the node's last vote to enforce its lockout. A slot here is one vote
+/// interval of `VOTE_TIMEOUT_MS`.
+#[derive(Debug, Default)]
+pub struct Lockout {
+    last_vote: Option<Hash>,
+    slots_since_vote: u64,
+}
+
+impl Lockout {
+    /// Start the next slot.
+    pub fn next_slot(&mut self) {
+        self.slots_since_vote += 1;
+    }
+
+    /// Whether voting on `bank` now would conflict with a recent vote, that
+    /// is, whether the last vote is both within the lockout and missing from
+    /// the bank's ledger.
+    pub fn is_locked_out(&self, bank: &Bank) -> bool {
+        match self.last_vote {
+            Some(last_vote) => {
+                self.slots_since_vote < VOTE_LOCKOUT_SLOTS
+                    && bank.count_valid_ids(&[last_vote]).is_empty()
+            }
+            None => false,
+        }
+    }
+
+    pub fn record_vote(&mut self, last_id: Hash) {
+        self.last_vote = Some(last_id);
+        self.slots_since_vote = 0;
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -35,6 +79,7 @@ enum VoteError {
 
 pub fn create_new_signed_vote_blob(
     last_id: &Hash,
+    accounts_hash: Option<Hash>,
     keypair: &Keypair,
     crdt: &Arc<RwLock<Crdt>>,
     blob_recycler: &BlobRecycler,
@@ -44,7 +89,7 @@ pub fn create_new_signed_vote_blob(
         let mut wcrdt = crdt.write().unwrap();
         //TODO: doesn't seem like there is a synchronous call to get height and id
         debug!("voting on {:?}", &last_id.as_ref()[..8]);
-        wcrdt.new_vote(*last_id)
+        wcrdt.new_vote(*last_id, accounts_hash)
     }?;
     let tx = Transaction::new_vote(&keypair, vote, *last_id, 0);
     {
@@ -139,8 +184,10 @@ pub fn send_leader_vote(
             last_vote,
             last_valid_validator_timestamp,
         ) {
+            // the leader's bank has moved on past `last_id`, so it can't
+            // vouch for the accounts as of it
             if let Ok(shared_blob) =
-                create_new_signed_vote_blob(&last_id, keypair, crdt, blob_recycler)
+                create_new_signed_vote_blob(&last_id, None, keypair, crdt, blob_recycler)
             {
                 vote_blob_sender.send(VecDeque::from(vec![shared_blob]))?;
                 let finality_ms = now - super_majority_timestamp;
@@ -171,19 +218,61 @@ fn send_validator_vote(
     crdt: &Arc<RwLock<Crdt>>,
     blob_recycler: &BlobRecycler,
     vote_blob_sender: &BlobSender,
+    lockout: &mut Lockout,
 ) -> Result<()> {
+    lockout.next_slot();
+    if lockout.is_locked_out(bank) {
+        inc_new_counter_info!("vote_stage-vote_skipped", 1);
+        return Ok(());
+    }
     let last_id = bank.last_id();
-    if let Ok(shared_blob) = create_new_signed_vote_blob(&last_id, keypair, crdt, blob_recycler) {
-        inc_new_counter_info!("replicate-vote_sent", 1);
+    let accounts_hash = bank.accounts_hash_at(&last_id);
+    if let Ok(shared_blob) =
+        create_new_signed_vote_blob(&last_id, accounts_hash, keypair, crdt, blob_recycler)
+    {
+        inc_new_counter_info!("vote_stage-vote_sent", 1);
 
         vote_blob_sender.send(VecDeque::from(vec![shared_blob]))?;
+        lockout.record_vote(last_id);
     }
     Ok(())
 }
 
 impl VoteStage {
+    /// Vote with `keypair` from a socket of the stage's own.
+    pub fn new_with_responder(
+        keypair: Arc<Keypair>,
+        lease: Arc<IdentityLease>,
+        bank: Arc<Bank>,
+        crdt: Arc<RwLock<Crdt>>,
+        blob_recycler: BlobRecycler,
+        exit: Arc<AtomicBool>,
+    ) -> Result<Self> {
+        let (vote_blob_sender, vote_blob_receiver) = channel();
+        let send = UdpSocket::bind("0.0.0.0:0")?;
+        let t_responder = responder(
+            "vote_stage_vote_sender",
+            send,
+            blob_recycler.clone(),
+            vote_blob_receiver,
+        );
+
+        let mut vote_stage = Self::new(
+            keypair,
+            lease,
+            bank,
+            crdt,
+            blob_recycler,
+            vote_blob_sender,
+            exit,
+        );
+        vote_stage.thread_hdls.push(t_responder);
+        Ok(vote_stage)
+    }
+
     pub fn new(
         keypair: Arc<Keypair>,
+        lease: Arc<IdentityLease>,
         bank: Arc<Bank>,
         crdt: Arc<RwLock<Crdt>>,
         blob_recycler: BlobRecycler,
@@ -191,8 +280,10 @@ impl VoteStage {
         exit: Arc<AtomicBool>,
     ) -> Self {
         let thread_hdl = spawn(move || {
+            cpu_time::register_thread("vote_stage");
             Self::run(
                 &keypair,
+                &lease,
                 &bank,
                 &crdt,
                 &blob_recycler,
@@ -200,21 +291,33 @@ impl VoteStage {
                 &exit,
             );
         });
-        VoteStage { thread_hdl }
+        VoteStage {
+            thread_hdls: vec![thread_hdl],
+        }
     }
 
     fn run(
         keypair: &Arc<Keypair>,
+        lease: &Arc<IdentityLease>,
         bank: &Arc<Bank>,
         crdt: &Arc<RwLock<Crdt>>,
         blob_recycler: &BlobRecycler,
         vote_blob_sender: &BlobSender,
         exit: &Arc<AtomicBool>,
     ) {
+        let mut lockout = Lockout::default();
         while !exit.load(Ordering::Relaxed) {
-            if let Err(err) =
-                send_validator_vote(bank, keypair, crdt, blob_recycler, vote_blob_sender)
-            {
+            // a standby shares the identity, it must not vote until it holds the lease
+            if !lease.is_active() {
+                inc_new_counter_info!("vote_stage-standby", 1);
+            } else if let Err(err) = send_validator_vote(
+                bank,
+                keypair,
+                crdt,
+                blob_recycler,
+                vote_blob_sender,
+                &mut lockout,
+            ) {
                 info!("Vote failed: {:?}", err);
             }
             sleep(Duration::from_millis(VOTE_TIMEOUT_MS));
@@ -224,11 +327,13 @@ impl VoteStage {
 
 impl Service for VoteStage {
     fn thread_hdls(self) -> Vec<JoinHandle<()>> {
-        vec![self.thread_hdl]
+        self.thread_hdls
     }
 
     fn join(self) -> thread::Result<()> {
-        self.thread_hdl.join()?;
+        for thread_hdl in self.thread_hdls() {
+            thread_hdl.join()?;
+        }
         Ok(())
     }
 }
@@ -268,6 +373,7 @@ pub mod tests {
 
         let vote_stage = VoteStage::new(
             Arc::new(keypair),
+            Arc::new(IdentityLease::default()),
             bank.clone(),
             Arc::new(RwLock::new(crdt)),
             blob_recycler.clone(),
@@ -285,6 +391,28 @@ pub mod tests {
         vote_stage.join().expect("join");
     }
 
+    #[test]
+    fn test_lockout() {
+        let mint = Mint::new(1234);
+        let bank = Bank::new(&mint);
+        let other_fork = Bank::new(&Mint::new(1234));
+        let mut lockout = Lockout::default();
+        assert!(!lockout.is_locked_out(&bank));
+        assert!(!lockout.is_locked_out(&other_fork));
+
+        lockout.record_vote(bank.last_id());
+        lockout.next_slot();
+        let entry = next_entry(&bank.last_id(), 1, vec![]);
+        bank.register_entry_id(&entry.id);
+        assert!(!lockout.is_locked_out(&bank));
+        assert!(lockout.is_locked_out(&other_fork));
+
+        for _ in 1..VOTE_LOCKOUT_SLOTS {
+            lockout.next_slot();
+        }
+        assert!(!lockout.is_locked_out(&other_fork));
+    }
+
     #[test]
     fn test_send_leader_vote() {
         logger::setup();
@@ -319,6 +447,8 @@ pub mod tests {
             let vote = Vote {
                 version: validator.version + 1,
                 contact_info_version: 1,
+                identity: None,
+                accounts_hash: None,
             };
 
             if i < 7 {
@@ -361,6 +491,8 @@ pub mod tests {
             let vote = Vote {
                 version: validator.version + 1,
                 contact_info_version: 1,
+                identity: None,
+                accounts_hash: None,
             };
 
             validator.ledger_state.last_id = entry.id;
@@ -1,6 +1,10 @@
+use bank::Bank;
+use counter::Counter;
 use entry::Entry;
 use hash::Hash;
+use log::Level;
 use signature::Pubkey;
+use std::sync::atomic::AtomicUsize;
 use transaction::{Instruction, Transaction, Vote};
 
 pub fn entries_to_votes(entries: &[Entry]) -> Vec<(Pubkey, Vote, Hash)> {
@@ -11,8 +15,64 @@ pub fn entries_to_votes(entries: &[Entry]) -> Vec<(Pubkey, Vote, Hash)> {
 }
 
 pub fn transaction_to_vote(tx: &Transaction) -> Option<(Pubkey, Vote, Hash)> {
-    match tx.instruction {
-        Instruction::NewVote(ref vote) => Some((tx.from, vote.clone(), tx.last_id)),
-        _ => None,
+    tx.instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::NewVote(vote) => Some((tx.from, vote.clone(), tx.last_id)),
+            _ => None,
+        })
+        .next()
+}
+
+/// Compare the accounts hash each of `votes` carries with the one `bank`
+/// recorded as of the same entry ID, and return how many differ. Votes that
+/// carry none, or are on IDs the bank didn't replay, can't be checked.
+pub fn check_accounts_hashes(bank: &Bank, votes: &[(Pubkey, Vote, Hash)]) -> usize {
+    let mut mismatches = 0;
+    for (pubkey, vote, last_id) in votes {
+        if let (Some(theirs), Some(ours)) = (vote.accounts_hash, bank.accounts_hash_at(last_id)) {
+            if theirs != ours {
+                warn!(
+                    "accounts diverged from {:?} as of {:?}: theirs {:?}, ours {:?}",
+                    pubkey, last_id, theirs, ours
+                );
+                inc_new_counter_info!("voting-accounts_hash_mismatch", 1);
+                mismatches += 1;
+            }
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entry::next_entry;
+    use mint::Mint;
+    use signature::{Keypair, KeypairUtil};
+
+    #[test]
+    fn test_check_accounts_hashes() {
+        let mint = Mint::new(2);
+        let bank = Bank::new(&mint);
+        let tx = Transaction::new(&mint.keypair(), Keypair::new().pubkey(), 1, mint.last_id());
+        let entry = next_entry(&mint.last_id(), 1, vec![tx]);
+        bank.process_entries(vec![entry.clone()]).unwrap();
+
+        let vote = |accounts_hash| Vote {
+            version: 1,
+            contact_info_version: 0,
+            identity: None,
+            accounts_hash,
+        };
+        let voter = Keypair::new().pubkey();
+        let votes = vec![
+            (voter, vote(Some(bank.accounts_hash())), entry.id),
+            (voter, vote(Some(Hash::default())), entry.id),
+            (voter, vote(None), entry.id),
+            // the bank recorded no accounts hash as of the genesis entry
+            (voter, vote(Some(Hash::default())), mint.last_id()),
+        ];
+        assert_eq!(check_accounts_hashes(&bank, &votes), 1);
     }
 }
@@ -1,5 +1,5 @@
 use bincode::serialize;
-use drone::DroneRequest;
+use drone::{AirdropCoupon, DroneRequest};
 use signature::Pubkey;
 use std::error;
 use std::io::Write;
@@ -20,3 +20,14 @@ pub fn request_airdrop(
     // TODO: add timeout to this function, in case of unresponsive drone
     Ok(())
 }
+
+pub fn redeem_coupon(
+    drone_addr: &SocketAddr,
+    coupon: &AirdropCoupon,
+) -> Result<(), Box<error::Error>> {
+    let mut stream = TcpStream::connect(drone_addr)?;
+    let req = DroneRequest::RedeemCoupon(*coupon);
+    let tx = serialize(&req).expect("serialize drone request");
+    stream.write_all(&tx)?;
+    Ok(())
+}
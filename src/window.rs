@@ -1,17 +1,22 @@
 //! The `window` module defines data structure for storing the tail of the ledger.
 //!
+use bincode::deserialize;
+use choose_gossip_peer_strategy::ChooseGossipPeerStrategy;
 use counter::Counter;
-use crdt::{Crdt, NodeInfo};
+use cpu_time;
+use crdt::{Crdt, CrdtError, NodeInfo};
 use entry::Entry;
 #[cfg(feature = "erasure")]
 use erasure;
-use ledger::Block;
+use hash::Hash;
+use ledger::{Block, TICKS_PER_SLOT};
 use log::Level;
-use packet::{BlobRecycler, SharedBlob, SharedBlobs, BLOB_SIZE};
+use packet::{Blob, BlobRecycler, SharedBlob, SharedBlobs, BLOB_SIZE};
+use rand::{thread_rng, Rng};
 use result::{Error, Result};
 use signature::Pubkey;
 use std::cmp;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::mem;
 use std::net::{SocketAddr, UdpSocket};
 use std::sync::atomic::AtomicUsize;
@@ -24,6 +29,142 @@ use timing::duration_as_ms;
 
 pub const WINDOW_SIZE: u64 = 2 * 1024;
 
+/// How long blobs signed by a replaced leader are still retransmitted after
+/// a leader change is observed through gossip.
+pub const LEADER_TRANSITION_MS: u64 = 2_000;
+
+/// The most missing blobs asked for in one repair interval. The holes nearest
+/// `consumed` go first, since they hold up replication; holes further out wait
+/// for a later interval.
+pub const MAX_REPAIR_REQUESTS: usize = 128;
+
+/// How long a repair request may go unanswered before it counts against the
+/// peer it was sent to.
+pub const REPAIR_TIMEOUT_MS: u64 = 2_000;
+
+/// One in this many repair requests goes to a peer chosen uniformly at random,
+/// so that a peer that was slow once gets the chance to show it has recovered.
+pub const REPAIR_EXPLORE_ONE_IN: u64 = 10;
+
+/// How far each answered or unanswered request moves a peer's averages.
+const REPAIR_STATS_WEIGHT: f64 = 0.2;
+
+/// How a peer has answered the repair requests sent to it, as moving averages
+/// that favor the most recent requests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RepairPeerStats {
+    /// The share of requests answered within `REPAIR_TIMEOUT_MS`
+    pub response_rate: f64,
+    /// How long the answered requests took
+    pub latency_ms: f64,
+}
+
+impl Default for RepairPeerStats {
+    /// A peer not asked yet is taken to answer, so it gets asked.
+    fn default() -> Self {
+        RepairPeerStats {
+            response_rate: 1.0,
+            latency_ms: REPAIR_TIMEOUT_MS as f64 / 2.0,
+        }
+    }
+}
+
+impl RepairPeerStats {
+    fn answered(&mut self, latency: Duration) {
+        let latency_ms = duration_as_ms(&latency) as f64;
+        self.response_rate += REPAIR_STATS_WEIGHT * (1.0 - self.response_rate);
+        self.latency_ms += REPAIR_STATS_WEIGHT * (latency_ms - self.latency_ms);
+    }
+
+    fn timed_out(&mut self) {
+        self.response_rate -= REPAIR_STATS_WEIGHT * self.response_rate;
+    }
+
+    /// How strongly to prefer the peer: the answers it's expected to give per
+    /// thousand seconds, and at least 1.
+    fn weight(&self) -> u64 {
+        let weight = self.response_rate * 1_000_000.0 / self.latency_ms.max(1.0);
+        cmp::max(weight as u64, 1)
+    }
+}
+
+/// Tracks how each peer answers the repair requests sent to it, to send
+/// repairs to the peers that answer them, and quickly. Requests are matched
+/// to answers by blob index, so a blob that comes in through broadcast after
+/// being asked for counts as an answer too.
+#[derive(Debug, Default)]
+pub struct RepairPeers {
+    stats: HashMap<SocketAddr, RepairPeerStats>,
+    /// The peer the blob at each index was last asked of, and when.
+    outstanding: HashMap<u64, (SocketAddr, Instant)>,
+}
+
+impl RepairPeers {
+    /// How the peer at gossip address `addr` has answered repair requests.
+    pub fn stats(&self, addr: &SocketAddr) -> RepairPeerStats {
+        self.stats.get(addr).cloned().unwrap_or_default()
+    }
+
+    /// Note that the blob at `ix` was asked of the peer at `to`.
+    pub fn requested(&mut self, to: SocketAddr, ix: u64, now: Instant) {
+        self.outstanding.insert(ix, (to, now));
+    }
+
+    /// Credit the peers the blobs at `ixs`, which just arrived, were asked of.
+    pub fn received(&mut self, ixs: &[u64], now: Instant) {
+        for ix in ixs {
+            if let Some((addr, sent)) = self.outstanding.remove(ix) {
+                self.stats
+                    .entry(addr)
+                    .or_insert_with(RepairPeerStats::default)
+                    .answered(now.duration_since(sent));
+            }
+        }
+    }
+
+    /// Count the requests unanswered for `REPAIR_TIMEOUT_MS` against their peers.
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = Duration::from_millis(REPAIR_TIMEOUT_MS);
+        let stats = &mut self.stats;
+        self.outstanding.retain(|_, (addr, sent)| {
+            if now.duration_since(*sent) < timeout {
+                return true;
+            }
+            stats
+                .entry(*addr)
+                .or_insert_with(RepairPeerStats::default)
+                .timed_out();
+            false
+        });
+    }
+}
+
+impl ChooseGossipPeerStrategy for RepairPeers {
+    /// Choose a peer with a chance in proportion to its weight, or once in
+    /// `REPAIR_EXPLORE_ONE_IN` times, uniformly at random.
+    fn choose_peer<'a>(&self, options: Vec<&'a NodeInfo>) -> Result<&'a NodeInfo> {
+        if options.is_empty() {
+            Err(CrdtError::NoPeers)?;
+        }
+        let mut rng = thread_rng();
+        if rng.gen_range(0, REPAIR_EXPLORE_ONE_IN) == 0 {
+            return Ok(options[rng.gen_range(0, options.len())]);
+        }
+        let weights: Vec<_> = options
+            .iter()
+            .map(|peer| self.stats(&peer.contact_info.ncp).weight())
+            .collect();
+        let mut pick = rng.gen_range(0, weights.iter().sum::<u64>());
+        for (peer, weight) in options.iter().zip(weights) {
+            if pick < weight {
+                return Ok(*peer);
+            }
+            pick -= weight;
+        }
+        unreachable!("the pick is below the sum of the weights")
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct WindowSlot {
     pub data: Option<SharedBlob>,
@@ -38,23 +179,137 @@ pub enum WindowError {
     GenericError,
 }
 
+/// Tracks the current leader and the one it replaced, so that blobs the prior
+/// leader broadcast just before the handoff are not dropped in flight.
+#[derive(Debug, Default)]
+pub struct LeaderTracker {
+    current: Option<Pubkey>,
+    prior: Option<(Pubkey, Instant)>,
+}
+
+impl LeaderTracker {
+    pub fn current(&self) -> Option<Pubkey> {
+        self.current
+    }
+
+    /// Record the leader as currently seen in `Crdt`. An unknown leader
+    /// leaves the last known one in place.
+    pub fn update(&mut self, leader_id: Option<Pubkey>) {
+        if leader_id.is_none() || leader_id == self.current {
+            return;
+        }
+        if let Some(old) = self.current {
+            info!("leader changed from {:?} to {:?}", old, leader_id);
+            inc_new_counter_info!("streamer-recv_window-leader_change", 1);
+            self.prior = Some((old, Instant::now()));
+        }
+        self.current = leader_id;
+    }
+
+    fn is_prior(&self, id: Pubkey) -> bool {
+        match self.prior {
+            Some((prior_id, since)) => {
+                prior_id == id && duration_as_ms(&since.elapsed()) < LEADER_TRANSITION_MS
+            }
+            None => false,
+        }
+    }
+
+    /// Whether a blob signed by `id` should be retransmitted.
+    pub fn is_leader(&self, id: Pubkey) -> bool {
+        self.current == Some(id) || self.is_prior(id)
+    }
+}
+
+/// Sits between the window and the replicate stage, so that the blobs handed
+/// on are strictly in index order with no duplicates, however repair and
+/// erasure recovery shuffle what the window releases. Blobs ahead of the next
+/// index wait for the gap before them to fill; blobs behind it, or already
+/// waiting, are dropped.
+#[derive(Debug)]
+pub struct BlobSequencer {
+    next: u64,
+    pending: BTreeMap<u64, SharedBlob>,
+}
+
+impl BlobSequencer {
+    pub fn new(next: u64) -> Self {
+        BlobSequencer {
+            next,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// The index of the next blob to hand on.
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    /// The number of blobs waiting for a gap to fill.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Take `blobs` in and return the ones that are now in sequence. Blobs
+    /// `WINDOW_SIZE` or more ahead are dropped, as the window can't hold them
+    /// either.
+    pub fn sequence(&mut self, blobs: SharedBlobs, recycler: &BlobRecycler) -> SharedBlobs {
+        for blob in blobs {
+            let ix = match blob.read().unwrap().get_index() {
+                Ok(ix) => ix,
+                Err(_) => {
+                    inc_new_counter_info!("window-sequencer-bad_index", 1);
+                    recycler.recycle(blob);
+                    continue;
+                }
+            };
+            if ix < self.next || ix >= self.next + WINDOW_SIZE || self.pending.contains_key(&ix) {
+                inc_new_counter_info!("window-sequencer-dropped", 1);
+                trace!("sequencer dropped blob {}, next: {}", ix, self.next);
+                recycler.recycle(blob);
+                continue;
+            }
+            if ix != self.next {
+                inc_new_counter_info!("window-sequencer-out_of_order", 1);
+            }
+            self.pending.insert(ix, blob);
+        }
+
+        let mut sequenced = VecDeque::new();
+        while let Some(blob) = self.pending.remove(&self.next) {
+            sequenced.push_back(blob);
+            self.next += 1;
+        }
+        sequenced
+    }
+}
+
 #[derive(Debug)]
 pub struct WindowIndex {
     pub data: u64,
     pub coding: u64,
 }
 
+/// Ask for up to `max_requests` of the blobs missing between `consumed` and
+/// `received`, nearest first, noting the requests in `repair_peers`
 fn find_next_missing(
     window: &SharedWindow,
     crdt: &Arc<RwLock<Crdt>>,
     recycler: &BlobRecycler,
+    repair_peers: &mut RepairPeers,
     consumed: u64,
     received: u64,
+    max_requests: usize,
 ) -> Result<Vec<(SocketAddr, Vec<u8>)>> {
     if received <= consumed {
         Err(WindowError::GenericError)?;
     }
     let mut window = window.write().unwrap();
+    // a missing blob was recorded in the slot of the blob before it or a later one
+    let mut slot = consumed
+        .checked_sub(1)
+        .and_then(|ix| blob_slot(&window, ix))
+        .unwrap_or(0);
     let reqs: Vec<_> = (consumed..received)
         .filter_map(|pix| {
             let i = (pix % WINDOW_SIZE) as usize;
@@ -67,18 +322,84 @@ fn find_next_missing(
                     recycler.recycle(blob);
                 }
             }
-            if window[i].data.is_none() {
-                let val = crdt.read().unwrap().window_index_request(pix as u64);
-                if let Ok((to, req)) = val {
-                    return Some((to, req));
-                }
+            if let Some(blob) = &window[i].data {
+                slot = blob.read().unwrap().get_slot().unwrap();
+                return None;
+            }
+            let val = crdt
+                .read()
+                .unwrap()
+                .window_index_request(&*repair_peers, slot, pix as u64);
+            if let Ok((to, req)) = val {
+                repair_peers.requested(to, pix, Instant::now());
+                return Some((to, req));
             }
             None
         })
+        .take(max_requests)
         .collect();
     Ok(reqs)
 }
 
+/// Ask for the highest blob of the last consumed blob's slot, for when nothing
+/// past `consumed` has arrived and the slot may have gone on without us
+fn find_highest(
+    window: &SharedWindow,
+    crdt: &Arc<RwLock<Crdt>>,
+    repair_peers: &RepairPeers,
+    consumed: u64,
+) -> Option<(SocketAddr, Vec<u8>)> {
+    let slot = consumed
+        .checked_sub(1)
+        .and_then(|ix| blob_slot(&window.read().unwrap(), ix))?;
+    crdt.read()
+        .unwrap()
+        .window_highest_index_request(repair_peers, slot, consumed)
+        .ok()
+}
+
+/// Ask for the parent of the first blob past `consumed` if whole slots are
+/// missing between it and the last consumed blob, so the window can walk back
+/// to a slot it knows
+fn find_orphan(
+    window: &SharedWindow,
+    crdt: &Arc<RwLock<Crdt>>,
+    repair_peers: &RepairPeers,
+    consumed: u64,
+    received: u64,
+) -> Option<(SocketAddr, Vec<u8>)> {
+    let (parent_slot, slot) = {
+        let window = window.read().unwrap();
+        let parent_slot = consumed
+            .checked_sub(1)
+            .and_then(|ix| blob_slot(&window, ix))?;
+        let slot = (consumed..received)
+            .filter_map(|ix| blob_slot(&window, ix))
+            .next()?;
+        (parent_slot, slot)
+    };
+    if slot <= parent_slot + 1 {
+        return None;
+    }
+    crdt.read()
+        .unwrap()
+        .window_orphan_request(repair_peers, slot)
+        .ok()
+}
+
+/// The slot of the blob at index `ix`, if the window still holds it
+fn blob_slot(window: &[WindowSlot], ix: u64) -> Option<u64> {
+    let i = (ix % WINDOW_SIZE) as usize;
+    window[i].data.as_ref().and_then(|blob| {
+        let blob = blob.read().unwrap();
+        if blob.get_index().ok()? == ix {
+            blob.get_slot().ok()
+        } else {
+            None
+        }
+    })
+}
+
 fn calculate_highest_lost_blob_index(num_peers: u64, consumed: u64, received: u64) -> u64 {
     // Calculate the highest blob index that this node should have already received
     // via avalanche. The avalanche splits data stream into nodes and each node retransmits
@@ -92,11 +413,13 @@ fn calculate_highest_lost_blob_index(num_peers: u64, consumed: u64, received: u6
     cmp::min(consumed + WINDOW_SIZE - 1, highest_lost)
 }
 
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
 fn repair_window(
     debug_id: u64,
     window: &SharedWindow,
     crdt: &Arc<RwLock<Crdt>>,
     recycler: &BlobRecycler,
+    repair_peers: &mut RepairPeers,
     last: &mut u64,
     times: &mut usize,
     consumed: u64,
@@ -113,13 +436,38 @@ fn repair_window(
         trace!("repair_window counter {} {} {}", *times, consumed, received);
         return Ok(());
     }
+    repair_peers.expire(Instant::now());
 
     let highest_lost = calculate_highest_lost_blob_index(
         crdt.read().unwrap().table.len() as u64,
         consumed,
         received,
     );
-    let reqs = find_next_missing(window, crdt, recycler, consumed, highest_lost)?;
+    let reqs = if highest_lost > consumed {
+        let mut reqs = find_next_missing(
+            window,
+            crdt,
+            recycler,
+            repair_peers,
+            consumed,
+            highest_lost,
+            MAX_REPAIR_REQUESTS,
+        )?;
+        if reqs.len() == MAX_REPAIR_REQUESTS {
+            inc_new_counter_info!("streamer-repair_window-budget_reached", 1);
+        }
+        if let Some(req) = find_orphan(window, crdt, repair_peers, consumed, received) {
+            inc_new_counter_info!("streamer-repair_window-orphan", 1);
+            reqs.push(req);
+        }
+        reqs
+    } else {
+        let req = find_highest(window, crdt, repair_peers, consumed);
+        if req.is_some() {
+            inc_new_counter_info!("streamer-repair_window-highest", 1);
+        }
+        req.into_iter().collect()
+    };
     trace!("{:x}: repair_window missing: {}", debug_id, reqs.len());
     if !reqs.is_empty() {
         inc_new_counter_info!("streamer-repair_window-repair", reqs.len());
@@ -147,7 +495,7 @@ fn repair_window(
 
 fn add_block_to_retransmit_queue(
     b: &SharedBlob,
-    leader_id: Pubkey,
+    leaders: &LeaderTracker,
     recycler: &BlobRecycler,
     retransmit_queue: &mut VecDeque<SharedBlob>,
 ) {
@@ -163,11 +511,14 @@ fn add_block_to_retransmit_queue(
         p.get_id()
             .expect("get_id in trace! fn add_block_to_retransmit_queue"),
         p.meta.addr(),
-        leader_id
+        leaders.current()
     );
-    if p.get_id()
-        .expect("get_id in fn add_block_to_retransmit_queue") == leader_id
-    {
+    let id = p.get_id()
+        .expect("get_id in fn add_block_to_retransmit_queue");
+    if leaders.current() != Some(id) && leaders.is_prior(id) {
+        inc_new_counter_info!("streamer-recv_window-prior_leader_blob", 1);
+    }
+    if leaders.is_leader(id) {
         //TODO
         //need to copy the retransmitted blob
         //otherwise we get into races with which thread
@@ -189,7 +540,7 @@ fn add_block_to_retransmit_queue(
 }
 
 fn retransmit_all_leader_blocks(
-    maybe_leader: Option<NodeInfo>,
+    leaders: &LeaderTracker,
     dq: &mut SharedBlobs,
     debug_id: u64,
     recycler: &BlobRecycler,
@@ -200,10 +551,9 @@ fn retransmit_all_leader_blocks(
     pending_retransmits: &mut bool,
 ) -> Result<()> {
     let mut retransmit_queue: VecDeque<SharedBlob> = VecDeque::new();
-    if let Some(leader) = maybe_leader {
-        let leader_id = leader.id;
+    if leaders.current().is_some() {
         for b in dq {
-            add_block_to_retransmit_queue(b, leader_id, recycler, &mut retransmit_queue);
+            add_block_to_retransmit_queue(b, leaders, recycler, &mut retransmit_queue);
         }
 
         if *pending_retransmits {
@@ -217,7 +567,7 @@ fn retransmit_all_leader_blocks(
                     if let Some(b) = w.clone().data {
                         add_block_to_retransmit_queue(
                             &b,
-                            leader_id,
+                            leaders,
                             recycler,
                             &mut retransmit_queue,
                         );
@@ -271,26 +621,30 @@ fn process_blob(
     let mut window = window.write().unwrap();
     let w = (pix % WINDOW_SIZE) as usize;
 
-    let is_coding = {
+    let (is_coding, slot) = {
         let blob_r = blob
             .read()
             .expect("blob read lock for flogs streamer::window");
-        blob_r.is_coding()
+        (blob_r.is_coding(), blob_r.get_slot().unwrap())
     };
 
     // insert a newly received blob into a window slot, clearing out and recycling any previous
-    //  blob unless the incoming blob is a duplicate (based on idx)
+    //  blob unless the incoming blob is a duplicate (based on slot and idx)
     // returns whether the incoming is a duplicate blob
     fn insert_blob_is_dup(
         debug_id: u64,
         blob: SharedBlob,
+        slot: u64,
         pix: u64,
         window_slot: &mut Option<SharedBlob>,
         recycler: &BlobRecycler,
         c_or_d: &str,
     ) -> bool {
         if let Some(old) = mem::replace(window_slot, Some(blob)) {
-            let is_dup = old.read().unwrap().get_index().unwrap() == pix;
+            let is_dup = {
+                let old_r = old.read().unwrap();
+                old_r.get_index().unwrap() == pix && old_r.get_slot().unwrap() == slot
+            };
             recycler.recycle(old);
             trace!(
                 "{:x}: occupied {} window slot {:}, is_dup: {}",
@@ -311,13 +665,22 @@ fn process_blob(
         insert_blob_is_dup(
             debug_id,
             blob,
+            slot,
             pix,
             &mut window[w].coding,
             recycler,
             "coding",
         )
     } else {
-        insert_blob_is_dup(debug_id, blob, pix, &mut window[w].data, recycler, "data")
+        insert_blob_is_dup(
+            debug_id,
+            blob,
+            slot,
+            pix,
+            &mut window[w].data,
+            recycler,
+            "data",
+        )
     };
 
     if is_duplicate {
@@ -405,15 +768,20 @@ fn recv_window(
     s: &BlobSender,
     retransmit: &BlobSender,
     pending_retransmits: &mut bool,
+    leaders: &mut LeaderTracker,
+    sequencer: &mut BlobSequencer,
+    repair_peers: &mut RepairPeers,
 ) -> Result<()> {
     let timer = Duration::from_millis(200);
     let mut dq = r.recv_timeout(timer)?;
-    let maybe_leader: Option<NodeInfo> = crdt
+    // re-read the leader for every batch, it may have changed through gossip
+    let maybe_leader: Option<Pubkey> = crdt
         .read()
         .expect("'crdt' read lock in fn recv_window")
         .leader_data()
-        .cloned();
+        .map(|leader| leader.id);
     let leader_unknown = maybe_leader.is_none();
+    leaders.update(maybe_leader);
     while let Ok(mut nq) = r.try_recv() {
         dq.append(&mut nq)
     }
@@ -428,7 +796,7 @@ fn recv_window(
     );
 
     retransmit_all_leader_blocks(
-        maybe_leader,
+        leaders,
         &mut dq,
         debug_id,
         recycler,
@@ -468,6 +836,7 @@ fn recv_window(
             pending_retransmits,
         );
     }
+    repair_peers.received(&pixs, now);
     if log_enabled!(Level::Trace) {
         trace!("{}", print_window(debug_id, window, *consumed));
     }
@@ -480,6 +849,7 @@ fn recv_window(
         pixs,
         duration_as_ms(&now.elapsed())
     );
+    let consume_queue = sequencer.sequence(consume_queue, recycler);
     if !consume_queue.is_empty() {
         debug!(
             "{:x}: RECV_WINDOW {} {}: forwarding consume_queue {}",
@@ -550,10 +920,22 @@ pub fn default_window() -> SharedWindow {
     ]))
 }
 
+/// Whether `blob` holds a tick, an entry without transactions
+fn is_tick(blob: &Blob) -> bool {
+    // an entry serializes as its num_hashes, its id, then its transaction count
+    let size = blob.get_size().unwrap_or(0);
+    deserialize::<(u64, Hash, u64)>(&blob.data()[..size])
+        .map(|(_, _, num_transactions)| num_transactions == 0)
+        .unwrap_or(false)
+}
+
+/// Address `blobs` by slot and index. `tick_height`, the number of ticks before
+/// the first blob, is moved past the ticks in `blobs`.
 pub fn index_blobs(
     node_info: &NodeInfo,
     blobs: &[SharedBlob],
     receive_index: &mut u64,
+    tick_height: &mut u64,
 ) -> Result<()> {
     // enumerate all the blobs, those are the indices
     trace!("{:x}: INDEX_BLOBS {}", node_info.debug_id(), blobs.len());
@@ -564,7 +946,13 @@ pub fn index_blobs(
             .expect("set_id in pub fn broadcast");
         blob.set_index(*receive_index + i as u64)
             .expect("set_index in pub fn broadcast");
+        // a slot's last tick is still in the slot
+        blob.set_slot(*tick_height / TICKS_PER_SLOT)
+            .expect("set_slot in pub fn broadcast");
         blob.set_flags(0).unwrap();
+        if is_tick(&blob) {
+            *tick_height += 1;
+        }
     }
 
     Ok(())
@@ -574,10 +962,12 @@ pub fn index_blobs(
 /// * `crdt` - gossip instance, used to set blob ids
 /// * `blobs` - up to WINDOW_SIZE most recent blobs
 /// * `entry_height` - current entry height
+/// * `tick_height` - the number of ticks before the first of `blobs`
 pub fn initialized_window(
     node_info: &NodeInfo,
     blobs: Vec<SharedBlob>,
     entry_height: u64,
+    mut tick_height: u64,
 ) -> SharedWindow {
     let window = default_window();
     let debug_id = node_info.debug_id();
@@ -594,7 +984,8 @@ pub fn initialized_window(
 
         // Index the blobs
         let mut received = entry_height - blobs.len() as u64;
-        index_blobs(&node_info, &blobs, &mut received).expect("index blobs for initial window");
+        index_blobs(&node_info, &blobs, &mut received, &mut tick_height)
+            .expect("index blobs for initial window");
 
         // populate the window, offset by implied index
         let diff = cmp::max(blobs.len() as isize - win.len() as isize, 0) as usize;
@@ -610,19 +1001,27 @@ pub fn initialized_window(
     window
 }
 
+/// Initialize a window with `ledger_tail`, the last entries of a ledger of
+/// `entry_height` entries and `tick_height` ticks
 pub fn new_window_from_entries(
     ledger_tail: &[Entry],
     entry_height: u64,
+    tick_height: u64,
     node_info: &NodeInfo,
     blob_recycler: &BlobRecycler,
 ) -> SharedWindow {
+    let tail_ticks = ledger_tail
+        .iter()
+        .filter(|entry| entry.transactions.is_empty())
+        .count() as u64;
+
     // convert to blobs
     let mut blobs = VecDeque::new();
     ledger_tail.to_blobs(&blob_recycler, &mut blobs);
 
     // flatten deque to vec
     let blobs: Vec<_> = blobs.into_iter().collect();
-    initialized_window(&node_info, blobs, entry_height)
+    initialized_window(&node_info, blobs, entry_height, tick_height - tail_ticks)
 }
 
 pub fn window(
@@ -637,12 +1036,16 @@ pub fn window(
     Builder::new()
         .name("solana-window".to_string())
         .spawn(move || {
+            cpu_time::register_thread("window");
             let mut consumed = entry_height;
             let mut received = entry_height;
             let mut last = entry_height;
             let mut times = 0;
             let debug_id = crdt.read().unwrap().debug_id();
             let mut pending_retransmits = false;
+            let mut leaders = LeaderTracker::default();
+            let mut sequencer = BlobSequencer::new(entry_height);
+            let mut repair_peers = RepairPeers::default();
             trace!("{:x}: RECV_WINDOW started", debug_id);
             loop {
                 if let Err(e) = recv_window(
@@ -656,6 +1059,9 @@ pub fn window(
                     &s,
                     &retransmit,
                     &mut pending_retransmits,
+                    &mut leaders,
+                    &mut sequencer,
+                    &mut repair_peers,
                 ) {
                     match e {
                         Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
@@ -667,7 +1073,15 @@ pub fn window(
                     }
                 }
                 let _ = repair_window(
-                    debug_id, &window, &crdt, &recycler, &mut last, &mut times, consumed, received,
+                    debug_id,
+                    &window,
+                    &crdt,
+                    &recycler,
+                    &mut repair_peers,
+                    &mut last,
+                    &mut times,
+                    consumed,
+                    received,
                 );
             }
         })
@@ -676,9 +1090,16 @@ pub fn window(
 
 #[cfg(test)]
 mod test {
+    use choose_gossip_peer_strategy::ChooseGossipPeerStrategy;
     use crdt::{Crdt, TestNode};
+    use entry::Entry;
+    use hash::Hash;
+    use ledger::TICKS_PER_SLOT;
     use logger;
-    use packet::{Blob, BlobRecycler, Packet, PacketRecycler, Packets, PACKET_DATA_SIZE};
+    use packet::{
+        Blob, BlobRecycler, Packet, PacketRecycler, Packets, SharedBlobs, PACKET_DATA_SIZE,
+    };
+    use rand::{thread_rng, Rng};
     use std::collections::VecDeque;
     use std::io;
     use std::io::Write;
@@ -686,10 +1107,14 @@ mod test {
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::mpsc::channel;
     use std::sync::{Arc, RwLock};
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use streamer::{blob_receiver, receiver, responder, BlobReceiver, PacketReceiver};
+    use signature::{Keypair, KeypairUtil};
+    use transaction::Transaction;
     use window::{
-        blob_idx_in_window, calculate_highest_lost_blob_index, default_window, window, WINDOW_SIZE,
+        blob_idx_in_window, blob_slot, calculate_highest_lost_blob_index, default_window,
+        find_next_missing, new_window_from_entries, window, BlobSequencer, LeaderTracker,
+        RepairPeerStats, RepairPeers, REPAIR_TIMEOUT_MS, WINDOW_SIZE,
     };
 
     fn get_msgs(r: PacketReceiver, num: &mut usize) {
@@ -1040,4 +1465,219 @@ mod test {
         assert_eq!(wrap_blob_idx_in_window(0, 91, 90, 100), (true, 100));
         assert_eq!(wrap_blob_idx_in_window(0, 101, 90, 100), (true, 101));
     }
+
+    #[test]
+    pub fn find_next_missing_test() {
+        let me = TestNode::new_localhost();
+        let peer = TestNode::new_localhost();
+        let mut crdt = Crdt::new(me.data).unwrap();
+        crdt.insert(&peer.data);
+        let crdt = Arc::new(RwLock::new(crdt));
+        let recycler = BlobRecycler::default();
+        let window = default_window();
+
+        // blobs 1 and 3 arrived, everything else up to 10 is missing
+        for ix in &[1, 3] {
+            let blob = recycler.allocate();
+            {
+                let mut b = blob.write().unwrap();
+                b.set_index(*ix).unwrap();
+                b.set_slot(0).unwrap();
+            }
+            window.write().unwrap()[*ix as usize].data = Some(blob);
+        }
+        let mut repair_peers = RepairPeers::default();
+        let request = |ix| {
+            crdt.read()
+                .unwrap()
+                .window_index_request(&RepairPeers::default(), 0, ix)
+                .unwrap()
+        };
+
+        // the holes nearest `consumed` are asked for first, within the budget
+        let reqs = find_next_missing(&window, &crdt, &recycler, &mut repair_peers, 0, 10, 3);
+        let expected: Vec<_> = [0, 2, 4].iter().map(|ix| request(*ix)).collect();
+        assert_eq!(reqs.unwrap(), expected);
+        assert_eq!(repair_peers.outstanding.len(), 3);
+
+        let reqs = find_next_missing(&window, &crdt, &recycler, &mut repair_peers, 0, 10, 100);
+        assert_eq!(reqs.unwrap().len(), 8);
+        assert_eq!(repair_peers.outstanding.len(), 8);
+        assert!(
+            find_next_missing(&window, &crdt, &recycler, &mut repair_peers, 10, 10, 100).is_err()
+        );
+    }
+
+    #[test]
+    pub fn repair_peers_test() {
+        let addr = "127.0.0.1:1234".parse().unwrap();
+        let mut repair_peers = RepairPeers::default();
+        let now = Instant::now();
+        repair_peers.requested(addr, 1, now);
+        repair_peers.requested(addr, 2, now);
+
+        // only answers to outstanding requests count
+        repair_peers.received(&[1, 3], now + Duration::from_millis(100));
+        let stats = repair_peers.stats(&addr);
+        assert!(stats.latency_ms < RepairPeerStats::default().latency_ms);
+        assert_eq!(repair_peers.outstanding.len(), 1);
+
+        repair_peers.expire(now + Duration::from_millis(REPAIR_TIMEOUT_MS - 1));
+        assert_eq!(repair_peers.stats(&addr), stats);
+        repair_peers.expire(now + Duration::from_millis(REPAIR_TIMEOUT_MS));
+        assert!(repair_peers.stats(&addr).response_rate < stats.response_rate);
+        assert!(repair_peers.outstanding.is_empty());
+    }
+
+    #[test]
+    pub fn repair_peers_choose_peer_test() {
+        let lossy = TestNode::new_localhost().data;
+        let responsive = TestNode::new_localhost().data;
+        let mut repair_peers = RepairPeers::default();
+        let now = Instant::now();
+        for ix in 0..20 {
+            repair_peers.requested(lossy.contact_info.ncp, ix, now);
+            repair_peers.requested(responsive.contact_info.ncp, 100 + ix, now);
+            repair_peers.received(&[100 + ix], now + Duration::from_millis(10));
+        }
+        repair_peers.expire(now + Duration::from_millis(REPAIR_TIMEOUT_MS));
+
+        // the responsive peer is preferred, but the lossy one is still tried
+        let mut chosen_lossy = 0;
+        for _ in 0..1000 {
+            let peer = repair_peers.choose_peer(vec![&lossy, &responsive]).unwrap();
+            if peer.id == lossy.id {
+                chosen_lossy += 1;
+            }
+        }
+        assert!(chosen_lossy > 0);
+        assert!(chosen_lossy < 200);
+        assert!(repair_peers.choose_peer(vec![]).is_err());
+    }
+
+    #[test]
+    pub fn leader_tracker_test() {
+        let old_leader = Keypair::new().pubkey();
+        let new_leader = Keypair::new().pubkey();
+        let stranger = Keypair::new().pubkey();
+        let mut leaders = LeaderTracker::default();
+        assert!(!leaders.is_leader(old_leader));
+
+        leaders.update(Some(old_leader));
+        assert_eq!(leaders.current(), Some(old_leader));
+        assert!(leaders.is_leader(old_leader));
+        assert!(!leaders.is_leader(new_leader));
+
+        // an unknown leader keeps the last one
+        leaders.update(None);
+        assert_eq!(leaders.current(), Some(old_leader));
+
+        // the prior leader's blobs are still accepted during the transition
+        leaders.update(Some(new_leader));
+        assert_eq!(leaders.current(), Some(new_leader));
+        assert!(leaders.is_leader(new_leader));
+        assert!(leaders.is_leader(old_leader));
+        assert!(!leaders.is_leader(stranger));
+    }
+
+    #[test]
+    pub fn window_slots_test() {
+        let node = TestNode::new_localhost();
+        let recycler = BlobRecycler::default();
+        let keypair = Keypair::new();
+        let tx = Transaction::new(&keypair, keypair.pubkey(), 1, Hash::default());
+
+        // a full slot of ticks, then a transaction and a tick in the next slot
+        let mut entries: Vec<_> = (0..TICKS_PER_SLOT)
+            .map(|_| Entry::new_tick(0, &Hash::default()))
+            .collect();
+        entries.push(Entry::new(&Hash::default(), 0, vec![tx], false));
+        entries.push(Entry::new_tick(0, &Hash::default()));
+
+        // the ledger had a slot of ticks before these entries
+        let entry_height = 100;
+        let tick_height = 2 * TICKS_PER_SLOT + 1;
+        let window = new_window_from_entries(
+            &entries,
+            entry_height,
+            tick_height,
+            &node.data,
+            &recycler,
+        );
+        let window = window.read().unwrap();
+        let first = entry_height - entries.len() as u64;
+        for ix in first..first + TICKS_PER_SLOT {
+            assert_eq!(blob_slot(&window, ix), Some(1));
+        }
+        assert_eq!(blob_slot(&window, first + TICKS_PER_SLOT), Some(2));
+        assert_eq!(blob_slot(&window, entry_height - 1), Some(2));
+        assert_eq!(blob_slot(&window, entry_height), None);
+    }
+
+    fn indexed_blobs(recycler: &BlobRecycler, indexes: &[u64]) -> SharedBlobs {
+        indexes
+            .iter()
+            .map(|ix| {
+                let blob = recycler.allocate();
+                blob.write().unwrap().set_index(*ix).unwrap();
+                blob
+            })
+            .collect()
+    }
+
+    fn blob_indexes(blobs: &SharedBlobs) -> Vec<u64> {
+        blobs
+            .iter()
+            .map(|blob| blob.read().unwrap().get_index().unwrap())
+            .collect()
+    }
+
+    #[test]
+    pub fn blob_sequencer_test() {
+        let recycler = BlobRecycler::default();
+        let mut sequencer = BlobSequencer::new(10);
+
+        // blobs from before the start are dropped
+        let out = sequencer.sequence(indexed_blobs(&recycler, &[8, 9]), &recycler);
+        assert!(out.is_empty());
+        assert_eq!(sequencer.next(), 10);
+
+        // blobs past a gap wait for it, duplicates of waiting blobs are dropped
+        let out = sequencer.sequence(indexed_blobs(&recycler, &[12, 11, 12]), &recycler);
+        assert!(out.is_empty());
+        assert_eq!(sequencer.pending(), 2);
+
+        // filling the gap releases everything in order
+        let out = sequencer.sequence(indexed_blobs(&recycler, &[10, 13]), &recycler);
+        assert_eq!(blob_indexes(&out), vec![10, 11, 12, 13]);
+        assert_eq!(sequencer.next(), 14);
+        assert_eq!(sequencer.pending(), 0);
+
+        // blobs already handed on are dropped
+        let out = sequencer.sequence(indexed_blobs(&recycler, &[11, 14]), &recycler);
+        assert_eq!(blob_indexes(&out), vec![14]);
+
+        // blobs further ahead than the window are dropped
+        let out = sequencer.sequence(indexed_blobs(&recycler, &[15 + WINDOW_SIZE]), &recycler);
+        assert!(out.is_empty());
+        assert_eq!(sequencer.pending(), 0);
+    }
+
+    #[test]
+    pub fn blob_sequencer_shuffled_test() {
+        let recycler = BlobRecycler::default();
+        let mut sequencer = BlobSequencer::new(0);
+
+        // every index twice, in random order and random batches, as heavy
+        // repair might deliver them
+        let mut indexes: Vec<u64> = (0..1000).chain(0..1000).collect();
+        thread_rng().shuffle(&mut indexes);
+        let mut out = vec![];
+        for batch in indexes.chunks(37) {
+            let sequenced = sequencer.sequence(indexed_blobs(&recycler, batch), &recycler);
+            out.extend(blob_indexes(&sequenced));
+        }
+        assert_eq!(out, (0..1000).collect::<Vec<_>>());
+        assert_eq!(sequencer.pending(), 0);
+    }
 }
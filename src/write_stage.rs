@@ -2,14 +2,20 @@
 //! writes entries to the given writer, which is typically a file or
 //! stdout, and then sends the Entry to its output channel.
 
-use bank::Bank;
+use bank::{Bank, ACCOUNTS_SAVE_INTERVAL};
+use block_cost::BlockCostTracker;
 use counter::Counter;
+use cpu_time;
 use crdt::Crdt;
 use entry::Entry;
-use ledger::{Block, LedgerWriter};
+use hash::Hash;
+use identity_lease::IdentityLease;
+use latency::StageLatency;
+use ledger::{Block, FileLedgerWriter, LedgerWriter, StoragePaths};
 use log::Level;
 use packet::BlobRecycler;
-use result::{Error, Result};
+use record_stage::{CommitLock, EntryBatch};
+use result::{Error, Result, StartupError};
 use service::Service;
 use signature::Keypair;
 use std::collections::VecDeque;
@@ -18,38 +24,136 @@ use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
 use std::sync::{Arc, RwLock};
 use std::thread::{self, Builder, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use streamer::{responder, BlobReceiver, BlobSender};
 use vote_stage::send_leader_vote;
 use voting::entries_to_votes;
 
+/// Saves a leader's accounts every `ACCOUNTS_SAVE_INTERVAL` entries written
+/// to its ledger.
+pub struct AccountsSaver {
+    /// The turn taken to stop entries being recorded while saving.
+    commit_lock: CommitLock,
+    entry_height: u64,
+    last_entry_id: Hash,
+    next_save: u64,
+}
+
+impl AccountsSaver {
+    /// A saver for a ledger of `entry_height` entries, the last of which is
+    /// `last_entry_id`.
+    pub fn new(commit_lock: CommitLock, entry_height: u64, last_entry_id: Hash) -> Self {
+        AccountsSaver {
+            commit_lock,
+            entry_height,
+            last_entry_id,
+            next_save: entry_height + ACCOUNTS_SAVE_INTERVAL,
+        }
+    }
+
+    fn wrote(&mut self, entries: &[Entry]) {
+        if let Some(entry) = entries.last() {
+            self.entry_height += entries.len() as u64;
+            self.last_entry_id = entry.id;
+        }
+    }
+
+    fn save(&mut self, bank: &Bank) -> Result<()> {
+        self.next_save = self.entry_height + ACCOUNTS_SAVE_INTERVAL;
+        bank.save_accounts(self.entry_height, &self.last_entry_id)?;
+        Ok(())
+    }
+}
+
 pub struct WriteStage {
     thread_hdls: Vec<JoinHandle<()>>,
 }
 
 impl WriteStage {
     /// Process any Entry items that have been published by the RecordStage.
-    /// continuosly broadcast blobs of entries out
+    /// continuosly broadcast blobs of entries out, unless this node is a
+    /// standby without the identity lease. Once it's time to save the bank's
+    /// accounts, they're saved as soon as the turn to record entries is free.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn write_and_send_entries(
+        lease: &IdentityLease,
+        crdt: &Arc<RwLock<Crdt>>,
+        bank: &Arc<Bank>,
+        ledger_writer: &mut LedgerWriter,
+        blob_sender: &BlobSender,
+        blob_recycler: &BlobRecycler,
+        entry_receiver: &Receiver<EntryBatch>,
+        latency: &mut StageLatency,
+        block_costs: &mut BlockCostTracker,
+        saver: &mut AccountsSaver,
+    ) -> Result<()> {
+        let batch = entry_receiver.recv_timeout(Duration::new(1, 0))?;
+        Self::write_and_send_batch(
+            batch,
+            lease,
+            crdt,
+            bank,
+            ledger_writer,
+            blob_sender,
+            blob_recycler,
+            latency,
+            block_costs,
+            saver,
+        )?;
+        if saver.entry_height < saver.next_save {
+            return Ok(());
+        }
+        let commit_lock = saver.commit_lock.clone();
+        if let Some(_turn) = commit_lock.try_turn() {
+            // everything the bank holds is recorded already, so once what's
+            // queued is written, the ledger matches the bank
+            while let Ok(batch) = entry_receiver.try_recv() {
+                Self::write_and_send_batch(
+                    batch,
+                    lease,
+                    crdt,
+                    bank,
+                    ledger_writer,
+                    blob_sender,
+                    blob_recycler,
+                    latency,
+                    block_costs,
+                    saver,
+                )?;
+            }
+            saver.save(bank)?;
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    fn write_and_send_batch(
+        batch: EntryBatch,
+        lease: &IdentityLease,
         crdt: &Arc<RwLock<Crdt>>,
         bank: &Arc<Bank>,
         ledger_writer: &mut LedgerWriter,
         blob_sender: &BlobSender,
         blob_recycler: &BlobRecycler,
-        entry_receiver: &Receiver<Vec<Entry>>,
+        latency: &mut StageLatency,
+        block_costs: &mut BlockCostTracker,
+        saver: &mut AccountsSaver,
     ) -> Result<()> {
-        let entries = entry_receiver.recv_timeout(Duration::new(1, 0))?;
+        let (entries, recv_time) = batch;
+        let now = Instant::now();
+        latency.record_since_recv(recv_time);
 
         let votes = entries_to_votes(&entries);
         crdt.write().unwrap().insert_votes(&votes);
 
-        ledger_writer.write_entries(entries.clone())?;
+        ledger_writer.append_entries(&entries)?;
+        saver.wrote(&entries);
 
-        for entry in entries.clone() {
-            if !entry.has_more {
-                bank.register_entry_id(&entry.id);
-            }
+        let slot_costs = block_costs.record(&entries);
+        for cost in &slot_costs {
+            cost.submit();
         }
+        bank.record_slot_costs(&slot_costs);
 
         //TODO(anatoly): real stake based voting needs to change this
         //leader simply votes if the current set of validators have voted
@@ -59,26 +163,69 @@ impl WriteStage {
         let mut blobs = VecDeque::new();
         entries.to_blobs(blob_recycler, &mut blobs);
 
+        if !lease.is_active() {
+            inc_new_counter_info!("write_stage-standby_blobs", blobs.len());
+            while let Some(blob) = blobs.pop_front() {
+                blob_recycler.recycle(blob);
+            }
+        }
+
         if !blobs.is_empty() {
             inc_new_counter_info!("write_stage-recv_vote", votes.len());
             inc_new_counter_info!("write_stage-broadcast_blobs", blobs.len());
             trace!("broadcasting {}", blobs.len());
             blob_sender.send(blobs)?;
         }
+        latency.process.record(&now.elapsed());
         Ok(())
     }
 
-    /// Create a new WriteStage for writing and broadcasting entries.
+    /// Create a new WriteStage for writing and broadcasting entries after the
+    /// `entry_height` already in the ledger, taking turns on `commit_lock` to
+    /// save the bank's accounts. Fails if the ledger can't be recovered for
+    /// writing.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     pub fn new(
         keypair: Keypair,
+        lease: Arc<IdentityLease>,
+        bank: Arc<Bank>,
+        crdt: Arc<RwLock<Crdt>>,
+        blob_recycler: BlobRecycler,
+        storage: &StoragePaths,
+        entry_receiver: Receiver<EntryBatch>,
+        commit_lock: CommitLock,
+        entry_height: u64,
+    ) -> Result<(Self, BlobReceiver)> {
+        let ledger_writer = FileLedgerWriter::recover(&storage.ledger)
+            .map_err(|err| StartupError::BadLedger(storage.ledger.clone(), err.to_string()))?;
+        Self::new_with_ledger_writer(
+            keypair,
+            lease,
+            bank,
+            crdt,
+            blob_recycler,
+            Box::new(ledger_writer),
+            entry_receiver,
+            commit_lock,
+            entry_height,
+        )
+    }
+
+    /// Same as `new`, but writes the entries to `ledger_writer`.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    pub fn new_with_ledger_writer(
+        keypair: Keypair,
+        lease: Arc<IdentityLease>,
         bank: Arc<Bank>,
         crdt: Arc<RwLock<Crdt>>,
         blob_recycler: BlobRecycler,
-        ledger_path: &str,
-        entry_receiver: Receiver<Vec<Entry>>,
-    ) -> (Self, BlobReceiver) {
+        mut ledger_writer: Box<LedgerWriter>,
+        entry_receiver: Receiver<EntryBatch>,
+        commit_lock: CommitLock,
+        entry_height: u64,
+    ) -> Result<(Self, BlobReceiver)> {
         let (vote_blob_sender, vote_blob_receiver) = channel();
-        let send = UdpSocket::bind("0.0.0.0:0").expect("bind");
+        let send = UdpSocket::bind("0.0.0.0:0")?;
         let t_responder = responder(
             "write_stage_vote_sender",
             send,
@@ -86,22 +233,30 @@ impl WriteStage {
             vote_blob_receiver,
         );
         let (blob_sender, blob_receiver) = channel();
-        let mut ledger_writer = LedgerWriter::recover(ledger_path).unwrap();
 
         let thread_hdl = Builder::new()
             .name("solana-writer".to_string())
             .spawn(move || {
+                cpu_time::register_thread("write_stage");
                 let mut last_vote = 0;
                 let mut last_valid_validator_timestamp = 0;
                 let debug_id = crdt.read().unwrap().debug_id();
+                let mut latency = StageLatency::new("write_stage-latency");
+                let mut block_costs = BlockCostTracker::new(bank.tick_height());
+                // the ledger ends at the bank's last entry when the stage starts
+                let mut saver = AccountsSaver::new(commit_lock, entry_height, bank.last_id());
                 loop {
                     if let Err(e) = Self::write_and_send_entries(
+                        &lease,
                         &crdt,
                         &bank,
-                        &mut ledger_writer,
+                        &mut *ledger_writer,
                         &blob_sender,
                         &blob_recycler,
                         &entry_receiver,
+                        &mut latency,
+                        &mut block_costs,
+                        &mut saver,
                     ) {
                         match e {
                             Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
@@ -115,6 +270,9 @@ impl WriteStage {
                             }
                         }
                     };
+                    if !lease.is_active() {
+                        continue;
+                    }
                     if let Err(e) = send_leader_vote(
                         debug_id,
                         &keypair,
@@ -133,7 +291,7 @@ impl WriteStage {
             .unwrap();
 
         let thread_hdls = vec![t_responder, thread_hdl];
-        (WriteStage { thread_hdls }, blob_receiver)
+        Ok((WriteStage { thread_hdls }, blob_receiver))
     }
 }
 
@@ -148,3 +306,89 @@ impl Service for WriteStage {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use accounts_db::AccountsDb;
+    use crdt::TestNode;
+    use entry::next_entry;
+    use ledger::MemLedger;
+    use mint::Mint;
+    use signature::KeypairUtil;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_write_entries_to_ledger() {
+        let mint = Mint::new(10);
+        let bank = Arc::new(Bank::new(&mint));
+        let crdt = Arc::new(RwLock::new(
+            Crdt::new(TestNode::new_localhost().data).unwrap(),
+        ));
+        let ledger = MemLedger::default();
+        let (entry_sender, entry_receiver) = channel();
+        let (write_stage, blob_receiver) = WriteStage::new_with_ledger_writer(
+            Keypair::new(),
+            Arc::new(IdentityLease::new(true)),
+            bank.clone(),
+            crdt,
+            BlobRecycler::default(),
+            Box::new(ledger.clone()),
+            entry_receiver,
+            CommitLock::unrecorded(),
+            0,
+        )
+        .unwrap();
+
+        let tick = next_entry(&bank.poh_domain().seed(&mint.last_id()), 1, vec![]);
+        entry_sender.send((vec![tick.clone()], None)).unwrap();
+        let blobs = blob_receiver.recv_timeout(Duration::new(5, 0)).unwrap();
+        assert_eq!(blobs.len(), 1);
+        // the entries are in the ledger before they're broadcast
+        assert_eq!(ledger.entries(), vec![tick]);
+
+        drop(entry_sender);
+        write_stage.join().unwrap();
+    }
+
+    #[test]
+    fn test_save_accounts_every_interval() {
+        let out_dir = env::var("OUT_DIR").unwrap_or_else(|_| "target".to_string());
+        let path = format!("{}/tmp-write-stage-{}", out_dir, Keypair::new().pubkey());
+        let mint = Mint::new(10);
+        let bank = Arc::new(Bank::new(&mint));
+        bank.use_accounts_db(AccountsDb::open(&path).unwrap())
+            .unwrap();
+        let crdt = Arc::new(RwLock::new(
+            Crdt::new(TestNode::new_localhost().data).unwrap(),
+        ));
+        let (entry_sender, entry_receiver) = channel();
+        let (write_stage, blob_receiver) = WriteStage::new_with_ledger_writer(
+            Keypair::new(),
+            Arc::new(IdentityLease::new(true)),
+            bank.clone(),
+            crdt,
+            BlobRecycler::default(),
+            Box::new(MemLedger::default()),
+            entry_receiver,
+            CommitLock::new(),
+            ACCOUNTS_SAVE_INTERVAL - 1,
+        )
+        .unwrap();
+
+        let tick = next_entry(&bank.poh_domain().seed(&mint.last_id()), 1, vec![]);
+        entry_sender.send((vec![tick.clone()], None)).unwrap();
+        blob_receiver.recv_timeout(Duration::new(5, 0)).unwrap();
+        drop(entry_sender);
+        write_stage.join().unwrap();
+
+        let saved = Bank::default();
+        let resume = saved
+            .use_accounts_db(AccountsDb::open(&path).unwrap())
+            .unwrap();
+        assert_eq!(resume, Some((ACCOUNTS_SAVE_INTERVAL, tick.id)));
+        assert_eq!(saved.get_balance(&mint.pubkey()), 10);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
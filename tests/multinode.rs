@@ -6,10 +6,10 @@ extern crate serde_json;
 extern crate solana;
 
 use solana::crdt::{Crdt, NodeInfo, TestNode};
-use solana::entry::Entry;
+use solana::entry::{Entry, PohDomain};
 use solana::fullnode::Fullnode;
 use solana::hash::Hash;
-use solana::ledger::LedgerWriter;
+use solana::ledger::FileLedgerWriter;
 use solana::logger;
 use solana::mint::Mint;
 use solana::ncp::Ncp;
@@ -87,7 +87,7 @@ fn genesis(name: &str, num: i64) -> (Mint, String) {
     let mint = Mint::new(num);
 
     let path = tmp_ledger_path(name);
-    let mut writer = LedgerWriter::open(&path, true).unwrap();
+    let mut writer = FileLedgerWriter::open(&path, true).unwrap();
 
     writer.write_entries(mint.create_entries()).unwrap();
 
@@ -110,11 +110,14 @@ fn tmp_copy_ledger(from: &str, name: &str) -> String {
     tostr
 }
 
-fn make_tiny_test_entries(start_hash: Hash, num: usize) -> Vec<Entry> {
+fn make_tiny_test_entries(start_hash: Hash, domain: &PohDomain, num: usize) -> Vec<Entry> {
     let mut id = start_hash;
-    let mut num_hashes = 0;
     (0..num)
-        .map(|_| Entry::new_mut(&mut id, &mut num_hashes, vec![], false))
+        .map(|_| {
+            let entry = Entry::new(&domain.seed(&id), 0, vec![], false);
+            id = entry.id;
+            entry
+        })
         .collect()
 }
 
@@ -139,13 +142,17 @@ fn test_multi_node_ledger_window() -> result::Result<()> {
     // write a bunch more ledger into leader's ledger, this should populate his window
     // and force him to respond to repair from the ledger window
     {
-        let entries = make_tiny_test_entries(alice.last_id(), WINDOW_SIZE as usize * 2);
-        let mut writer = LedgerWriter::open(&leader_ledger_path, false).unwrap();
+        let entries = make_tiny_test_entries(
+            alice.last_id(),
+            &alice.poh_domain(),
+            WINDOW_SIZE as usize * 2,
+        );
+        let mut writer = FileLedgerWriter::open(&leader_ledger_path, false).unwrap();
 
         writer.write_entries(entries).unwrap();
     }
 
-    let leader = Fullnode::new(leader, true, &leader_ledger_path, leader_keypair, None);
+    let leader = Fullnode::new(leader, true, &leader_ledger_path, leader_keypair, None).unwrap();
 
     // Send leader some tokens to vote
     let leader_balance =
@@ -163,7 +170,8 @@ fn test_multi_node_ledger_window() -> result::Result<()> {
         &zero_ledger_path,
         keypair,
         Some(leader_data.contact_info.ncp),
-    );
+    )
+    .unwrap();
 
     // contains the leader and new node
     info!("converging....");
@@ -218,7 +226,7 @@ fn test_multi_node_validator_catchup_from_zero() -> result::Result<()> {
     );
     ledger_paths.push(zero_ledger_path.clone());
 
-    let server = Fullnode::new(leader, true, &leader_ledger_path, leader_keypair, None);
+    let server = Fullnode::new(leader, true, &leader_ledger_path, leader_keypair, None).unwrap();
 
     // Send leader some tokens to vote
     let leader_balance =
@@ -241,7 +249,8 @@ fn test_multi_node_validator_catchup_from_zero() -> result::Result<()> {
             &ledger_path,
             keypair,
             Some(leader_data.contact_info.ncp),
-        );
+        )
+        .unwrap();
         nodes.push(val);
     }
     let servers = converge(&leader_data, N + 1);
@@ -276,7 +285,8 @@ fn test_multi_node_validator_catchup_from_zero() -> result::Result<()> {
         &zero_ledger_path,
         keypair,
         Some(leader_data.contact_info.ncp),
-    );
+    )
+    .unwrap();
     nodes.push(val);
     //contains the leader and new node
     let servers = converge(&leader_data, N + 2);
@@ -335,7 +345,7 @@ fn test_multi_node_basic() {
 
     let (alice, leader_ledger_path) = genesis("multi_node_basic", 10_000);
     ledger_paths.push(leader_ledger_path.clone());
-    let server = Fullnode::new(leader, true, &leader_ledger_path, leader_keypair, None);
+    let server = Fullnode::new(leader, true, &leader_ledger_path, leader_keypair, None).unwrap();
 
     // Send leader some tokens to vote
     let leader_balance =
@@ -354,7 +364,8 @@ fn test_multi_node_basic() {
             &ledger_path,
             keypair,
             Some(leader_data.contact_info.ncp),
-        );
+        )
+        .unwrap();
         nodes.push(val);
     }
     let servers = converge(&leader_data, N + 1);
@@ -396,7 +407,8 @@ fn test_boot_validator_from_file() -> result::Result<()> {
     ledger_paths.push(leader_ledger_path.clone());
 
     let leader_data = leader.data.clone();
-    let leader_fullnode = Fullnode::new(leader, true, &leader_ledger_path, leader_keypair, None);
+    let leader_fullnode =
+        Fullnode::new(leader, true, &leader_ledger_path, leader_keypair, None).unwrap();
     let leader_balance =
         send_tx_and_retry_get_balance(&leader_data, &alice, &bob_pubkey, Some(500)).unwrap();
     assert_eq!(leader_balance, 500);
@@ -415,7 +427,8 @@ fn test_boot_validator_from_file() -> result::Result<()> {
         &ledger_path,
         keypair,
         Some(leader_data.contact_info.ncp),
-    );
+    )
+    .unwrap();
     let mut client = mk_client(&validator_data);
     let getbal = retry_get_balance(&mut client, &bob_pubkey, Some(leader_balance));
     assert!(getbal == Some(leader_balance));
@@ -433,7 +446,7 @@ fn create_leader(ledger_path: &str) -> (NodeInfo, Fullnode) {
     let leader_keypair = Keypair::new();
     let leader = TestNode::new_localhost_with_pubkey(leader_keypair.pubkey());
     let leader_data = leader.data.clone();
-    let leader_fullnode = Fullnode::new(leader, true, &ledger_path, leader_keypair, None);
+    let leader_fullnode = Fullnode::new(leader, true, &ledger_path, leader_keypair, None).unwrap();
     (leader_data, leader_fullnode)
 }
 
@@ -484,7 +497,8 @@ fn test_leader_restart_validator_start_from_old_ledger() -> result::Result<()> {
         &stale_ledger_path,
         keypair,
         Some(leader_data.contact_info.ncp),
-    );
+    )
+    .unwrap();
 
     // trigger broadcast, validator should catch up from leader, whose window contains
     //   the entries missing from the stale ledger
@@ -539,7 +553,8 @@ fn test_multi_node_dynamic_network() {
     let leader_data = leader.data.clone();
 
     let server =
-        Fullnode::new_without_sigverify(leader, true, &leader_ledger_path, leader_keypair, None);
+        Fullnode::new_without_sigverify(leader, true, &leader_ledger_path, leader_keypair, None)
+            .unwrap();
 
     // Send leader some tokens to vote
     let leader_balance = send_tx_and_retry_get_balance(
@@ -613,7 +628,8 @@ fn test_multi_node_dynamic_network() {
                         &ledger_path,
                         keypair,
                         Some(leader_data.contact_info.ncp),
-                    );
+                    )
+                    .unwrap();
                     (rd, val)
                 })
                 .unwrap()